@@ -0,0 +1,18 @@
+use std::path::Path;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use marked_space::bench::parse_space;
+
+fn example_space(c: &mut Criterion) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("example/team");
+    c.bench_function("parse example/team", |b| {
+        b.iter(|| parse_space(&dir).unwrap())
+    });
+    let space = parse_space(&dir).unwrap();
+    c.bench_function("render example/team", |b| {
+        b.iter(|| space.render().unwrap())
+    });
+}
+
+criterion_group!(benches, example_space);
+criterion_main!(benches);
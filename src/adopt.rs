@@ -0,0 +1,350 @@
+//! The `adopt` subcommand: takes over pages that were made by hand before the space moved to
+//! markdown. Unmanaged pages are matched to the files with the same title (or the files a mapping
+//! file lists them for), and recorded in the page map, so the next sync updates them instead of
+//! creating a second page next to them.
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    io::{self, BufRead, IsTerminal, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::anyhow;
+use saphyr::Yaml;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    confluence_page::{ConfluenceNode, ConfluenceNodeType},
+    console::{print_info, print_status, Status},
+    markdown_page::MarkdownPage,
+    markdown_space::MarkdownSpace,
+    mentions::CachedUsers,
+    page_map::PageMap,
+    responses::ContentStatus,
+    sync::{load_space, LoadedSpace},
+    title_policy::TitlePolicy,
+    Args, Result,
+};
+
+/// A file and the page to take over for it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Adoption {
+    pub source: String,
+    pub id: String,
+    pub title: String,
+}
+
+/// Current pages that weren't synced by marked-space, other than the homepage, by id.
+fn unmanaged_pages<'a>(
+    nodes: &'a [ConfluenceNode],
+    homepage_id: &str,
+    page_map: &PageMap,
+) -> BTreeMap<&'a str, &'a ConfluenceNode> {
+    let mapped: HashSet<&str> = page_map
+        .pages
+        .values()
+        .map(|entry| entry.id.as_str())
+        .collect();
+    nodes
+        .iter()
+        .filter(|node| match &node.data {
+            ConfluenceNodeType::Page(page_data) => {
+                page_data.status == ContentStatus::Current
+                    && !page_data.is_managed()
+                    && node.id != homepage_id
+                    && !mapped.contains(node.id.as_str())
+            }
+            _ => false,
+        })
+        .map(|node| (node.id.as_str(), node))
+        .collect()
+}
+
+/// Files that could adopt a page: not in the page map yet, and not `index.md` when it is the
+/// homepage.
+fn unmapped_files<'a>(
+    markdown_pages: &'a [MarkdownPage],
+    page_map: &PageMap,
+    homepage_locked: bool,
+) -> impl Iterator<Item = (String, &'a MarkdownPage)> {
+    let mapped: HashSet<String> = page_map.pages.keys().cloned().collect();
+    markdown_pages
+        .iter()
+        .map(|page| (page.source.replace('\\', "/"), page))
+        .filter(move |(source, _)| {
+            !mapped.contains(source) && (homepage_locked || source != "index.md")
+        })
+}
+
+/// The unmanaged pages with the title of a file that doesn't have a page yet.
+pub fn match_by_title(
+    markdown_pages: &[MarkdownPage],
+    nodes: &[ConfluenceNode],
+    homepage_id: &str,
+    page_map: &PageMap,
+    title_policy: &TitlePolicy,
+    homepage_locked: bool,
+) -> Vec<Adoption> {
+    let by_title: BTreeMap<String, &ConfluenceNode> = unmanaged_pages(nodes, homepage_id, page_map)
+        .into_values()
+        .map(|node| (title_policy.normalize(&node.title), node))
+        .collect();
+    unmapped_files(markdown_pages, page_map, homepage_locked)
+        .filter_map(|(source, page)| {
+            by_title
+                .get(&title_policy.normalize(&page.title))
+                .map(|node| Adoption {
+                    source,
+                    id: node.id.clone(),
+                    title: node.title.clone(),
+                })
+        })
+        .collect()
+}
+
+/// The adoptions of a YAML mapping file of files (relative to the space directory) to page ids,
+/// which have to be unmanaged pages of the space.
+pub fn parse_mapping(
+    yaml: &str,
+    markdown_pages: &[MarkdownPage],
+    nodes: &[ConfluenceNode],
+    homepage_id: &str,
+    page_map: &PageMap,
+    homepage_locked: bool,
+) -> Result<Vec<Adoption>> {
+    let docs = Yaml::load_from_str(yaml)?;
+    let Some(Yaml::Hash(mapping)) = docs.first() else {
+        return Err(anyhow!(
+            "The mapping should be a map of files to page ids, e.g. \"guides/setup.md: 123456\""
+        ));
+    };
+    let pages = unmanaged_pages(nodes, homepage_id, page_map);
+    let files: HashSet<String> = unmapped_files(markdown_pages, page_map, homepage_locked)
+        .map(|(source, _)| source)
+        .collect();
+    mapping
+        .iter()
+        .map(|(source, id)| {
+            let source = source
+                .as_str()
+                .ok_or(anyhow!("Files in the mapping should be strings"))?
+                .trim_start_matches("./")
+                .replace('\\', "/");
+            let id = match id {
+                Yaml::Integer(id) => id.to_string(),
+                Yaml::String(id) => id.clone(),
+                _ => return Err(anyhow!("The page id of {} should be a number", source)),
+            };
+            if !files.contains(&source) {
+                return Err(anyhow!(
+                    "{} isn't a page of the space, or already has a page in Confluence",
+                    source
+                ));
+            }
+            let node = pages.get(id.as_str()).ok_or(anyhow!(
+                "Page {} for {} isn't a page of the space that marked-space doesn't manage yet",
+                id,
+                source
+            ))?;
+            Ok(Adoption {
+                source,
+                id,
+                title: node.title.clone(),
+            })
+        })
+        .collect()
+}
+
+fn confirm(adoption: &Adoption) -> Result<bool> {
+    print!(
+        "Adopt \"{}\" ({}) for {}? [y/N] ",
+        adoption.title, adoption.id, adoption.source
+    );
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+pub fn adopt(
+    confluence_client: ConfluenceClient,
+    markdown_space: &mut MarkdownSpace,
+    args: Args,
+    mapping: Option<&Path>,
+    yes: bool,
+) -> Result<()> {
+    if mapping.is_none() && !yes && !io::stdin().is_terminal() {
+        return Err(anyhow!(
+            "Adopting pages by title asks for confirmation, pass --yes or --mapping when not running in a terminal"
+        ));
+    }
+    let homepage_locked = markdown_space.config.homepage.locked;
+    let title_policy = markdown_space.config.titles.clone();
+    let LoadedSpace {
+        markdown_pages,
+        space,
+        ..
+    } = load_space(
+        &confluence_client,
+        markdown_space,
+        &args,
+        Arc::new(CachedUsers::new(confluence_client.clone())),
+    )?;
+    // with a managed label the sync only reads the labelled pages, read all of them here
+    let nodes: Vec<ConfluenceNode> = ConfluenceNode::get_space_pages(&confluence_client, &space)?
+        .iter()
+        .map(ConfluenceNode::new_from_page_bulk)
+        .collect();
+    let mut page_map = PageMap::load(&confluence_client, &space.homepage_id)?;
+
+    let adoptions = match mapping {
+        Some(path) => parse_mapping(
+            &fs::read_to_string(path)
+                .map_err(|err| anyhow!("Failed to read {}: {}", path.display(), err))?,
+            &markdown_pages,
+            &nodes,
+            &space.homepage_id,
+            &page_map,
+            homepage_locked,
+        )?,
+        None => match_by_title(
+            &markdown_pages,
+            &nodes,
+            &space.homepage_id,
+            &page_map,
+            &title_policy,
+            homepage_locked,
+        ),
+    };
+    if adoptions.is_empty() {
+        print_info("No pages to adopt");
+        return Ok(());
+    }
+    let mut adopted = 0;
+    for adoption in &adoptions {
+        if mapping.is_none() && !yes && !confirm(adoption)? {
+            continue;
+        }
+        // without a checksum the next sync compares the body and updates the page if it differs
+        page_map.insert(&adoption.source, &adoption.id, None);
+        print_status(
+            Status::Adopted,
+            &format!(
+                "page \"{}\" ({}) for {}",
+                adoption.title, adoption.id, adoption.source
+            ),
+        );
+        adopted += 1;
+    }
+    if adopted > 0 {
+        page_map.save(&confluence_client, &space.homepage_id)?;
+        print_info(&format!(
+            "Adopted {} page(s), the next sync updates them from their files",
+            adopted
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        confluence_page::ConfluencePageData, error::TestResult, page_map::PageMapEntry,
+        responses::Version, test_helpers::markdown_page_from_str,
+    };
+
+    use super::*;
+
+    fn page(id: &str, title: &str, message: &str) -> ConfluenceNode {
+        let version = Version {
+            message: String::from(message),
+            number: 1,
+        };
+        ConfluenceNode {
+            id: String::from(id),
+            title: String::from(title),
+            parent_id: Some(String::from("999")),
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                path: ConfluencePageData::extract_path(&version),
+                version,
+                status: ContentStatus::Current,
+            }),
+        }
+    }
+
+    #[test]
+    fn it_matches_unmanaged_pages_by_title() -> TestResult {
+        let markdown_pages = vec![
+            markdown_page_from_str("index.md", "# Home\n")?,
+            markdown_page_from_str("setup.md", "# Setup Guide\n")?,
+            markdown_page_from_str("faq.md", "# FAQ\n")?,
+            markdown_page_from_str("synced.md", "# Synced\n")?,
+            markdown_page_from_str("mapped.md", "# Mapped\n")?,
+        ];
+        let nodes = vec![
+            page("999", "Home", ""),
+            page("1", "Setup Guide", "Edited by hand"),
+            page(
+                "2",
+                "Synced",
+                "updated by markedspace: source=synced.md; checksum=ABC",
+            ),
+            page("3", "Mapped", ""),
+            page("4", "Notes", ""),
+        ];
+        let mut page_map = PageMap::default();
+        page_map.pages.insert(
+            String::from("mapped.md"),
+            PageMapEntry {
+                id: String::from("3"),
+                checksum: None,
+                failed: false,
+            },
+        );
+
+        assert_eq!(
+            match_by_title(
+                &markdown_pages,
+                &nodes,
+                "999",
+                &page_map,
+                &TitlePolicy::default(),
+                false
+            ),
+            vec![Adoption {
+                source: String::from("setup.md"),
+                id: String::from("1"),
+                title: String::from("Setup Guide"),
+            }]
+        );
+
+        assert_eq!(
+            parse_mapping(
+                "faq.md: 4\n",
+                &markdown_pages,
+                &nodes,
+                "999",
+                &page_map,
+                false
+            )?,
+            vec![Adoption {
+                source: String::from("faq.md"),
+                id: String::from("4"),
+                title: String::from("Notes"),
+            }]
+        );
+        let err = parse_mapping(
+            "faq.md: 2\n",
+            &markdown_pages,
+            &nodes,
+            "999",
+            &page_map,
+            false,
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", err).contains("doesn't manage yet"));
+
+        Ok(())
+    }
+}
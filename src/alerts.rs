@@ -81,13 +81,11 @@ pub(crate) fn render_basic_alert(
 
 #[cfg(test)]
 mod test {
-    use comrak::{nodes::AstNode, Arena};
 
     use crate::{error::TestResult, link_generator::LinkGenerator, markdown_page::page_from_str};
 
     #[test]
     fn it_renders_note() -> TestResult {
-        let arena = Arena::<AstNode>::new();
         let markdown_content = r###"# compulsory title
 
 > [!note] My Title
@@ -101,7 +99,7 @@ mod test {
 <p>Something else after the code</p>
 </ac:rich-text-body></ac:structured-macro>"###;
 
-        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let page = page_from_str("page.md", markdown_content)?;
         let rendered_page = page.render(&LinkGenerator::default_test())?;
 
         assert_eq!(rendered_page.content.trim(), expected_rendered_content);
@@ -111,7 +109,6 @@ mod test {
 
     #[test]
     fn it_renders_default_title() -> TestResult {
-        let arena = Arena::<AstNode>::new();
         let markdown_content = r###"# compulsory title
 
 > [!note]
@@ -125,7 +122,7 @@ mod test {
 <p>Something else after the code</p>
 </ac:rich-text-body></ac:structured-macro>"###;
 
-        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let page = page_from_str("page.md", markdown_content)?;
         let rendered_page = page.render(&LinkGenerator::default_test())?;
 
         assert_eq!(rendered_page.content.trim(), expected_rendered_content);
@@ -137,7 +134,6 @@ mod test {
     fn it_renders_expand_alert() -> TestResult {
         // note: the [expand] prefix on the title is a bit of a hack; it would be better to maybe
         // fork Comrak and allow Alert parsing to accept non-standard alert types.
-        let arena = Arena::<AstNode>::new();
         let markdown_content = r###"# compulsory title
 
 > [!note][expand] My Title
@@ -152,7 +148,7 @@ mod test {
 <p>Something else after the code</p>
 </ac:rich-text-body></ac:structured-macro>"###;
 
-        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let page = page_from_str("page.md", markdown_content)?;
         let rendered_page = page.render(&LinkGenerator::default_test())?;
 
         assert_eq!(rendered_page.content.trim(), expected_rendered_content);
@@ -164,7 +160,6 @@ mod test {
     fn it_renders_expand_alert_with_no_title() -> TestResult {
         // note: the [expand] prefix on the title is a bit of a hack; it would be better to maybe
         // fork Comrak and allow Alert parsing to accept non-standard alert types.
-        let arena = Arena::<AstNode>::new();
         let markdown_content = r###"# compulsory title
 
 > [!note][expand]
@@ -179,7 +174,7 @@ mod test {
 <p>Something else after the code</p>
 </ac:rich-text-body></ac:structured-macro>"###;
 
-        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let page = page_from_str("page.md", markdown_content)?;
         let rendered_page = page.render(&LinkGenerator::default_test())?;
 
         assert_eq!(rendered_page.content.trim(), expected_rendered_content);
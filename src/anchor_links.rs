@@ -0,0 +1,151 @@
+//! Checks that the anchors of links to pages (`other.md#some-anchor`) go to a heading of that
+//! page, with the anchors the renderer gives headings, so a typo in an anchor is caught before a
+//! reader follows the link to the top of the page.
+//!
+//! Anchors are compared after slugifying them like headings, so the anchors Confluence gives
+//! headings itself (`#Sub-Page-Section`) count as well.
+use std::path::Path;
+
+use crate::{heading_anchors::heading_anchor, markdown_page::MarkdownPage};
+
+/// A link whose anchor isn't a heading of the page it goes to.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BrokenAnchor {
+    /// The index of the page with the link.
+    pub page: usize,
+    pub link: String,
+    /// The anchor of the page that's closest to the one in the link.
+    pub suggestion: Option<String>,
+}
+
+/// The number of single character edits to turn `a` into `b`.
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn suggest<'a>(anchor: &str, anchors: impl Iterator<Item = &'a String>) -> Option<String> {
+    let max_distance = (anchor.chars().count() / 3).max(2);
+    anchors
+        .map(|candidate| (edit_distance(anchor, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate.clone())
+}
+
+/// The pages whose headings a link to `path` can go to: the page of the file, the sections it was
+/// split into, or the page it was merged into.
+fn target_pages<'p>(
+    markdown_pages: &'p [MarkdownPage],
+    page: &MarkdownPage,
+    path: &str,
+) -> Vec<&'p MarkdownPage> {
+    let section_prefix = format!("{}#", path);
+    let targets: Vec<&MarkdownPage> = markdown_pages
+        .iter()
+        .filter(|target| {
+            target.source == path
+                || target.source.starts_with(&section_prefix)
+                || target.merged.iter().any(|merged| merged.source == path)
+        })
+        .collect();
+    let page_dir = Path::new(&page.source)
+        .parent()
+        .map(|dir| dir.to_string_lossy().replace('\\', "/"));
+    match markdown_pages
+        .iter()
+        .find(|target| target.source == page.source)
+    {
+        // `#anchor` on its own links to the page itself, which resolves to its directory
+        Some(same_page) if targets.is_empty() && page_dir.as_deref() == Some(path) => {
+            vec![same_page]
+        }
+        _ => targets,
+    }
+}
+
+pub fn find_broken_anchors(markdown_pages: &[MarkdownPage]) -> Vec<BrokenAnchor> {
+    let mut broken = Vec::new();
+    for (index, page) in markdown_pages.iter().enumerate() {
+        for local_link in &page.local_links {
+            let Some(anchor) = &local_link.anchor else {
+                continue;
+            };
+            let path = local_link.path.to_string_lossy().replace('\\', "/");
+            let section = format!("{}#{}", path, anchor);
+            if markdown_pages.iter().any(|target| target.source == section) {
+                continue;
+            }
+            let targets = target_pages(markdown_pages, page, &path);
+            let slug = heading_anchor(anchor);
+            // links to other files, like attachments, aren't checked
+            if targets.is_empty()
+                || targets
+                    .iter()
+                    .any(|target| target.anchors.contains(anchor) || target.anchors.contains(&slug))
+            {
+                continue;
+            }
+            broken.push(BrokenAnchor {
+                page: index,
+                link: local_link.to_string(),
+                suggestion: suggest(
+                    &slug,
+                    targets.iter().flat_map(|target| target.anchors.iter()),
+                ),
+            });
+        }
+    }
+    broken
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{error::TestResult, test_helpers::markdown_page_from_str};
+
+    use super::*;
+
+    #[test]
+    fn it_finds_anchors_without_a_heading() -> TestResult {
+        let pages = vec![
+            markdown_page_from_str(
+                "index.md",
+                "# Home\n\n[install](guide/setup.md#instal) [usage](guide/setup.md#usage) [top](#home) [here](#welcome)\n\n## Welcome\n",
+            )?,
+            markdown_page_from_str(
+                "guide/setup.md",
+                "# Setup\n\n## Install\n\n## Usage\n\n[back](../index.md#Welcome)\n",
+            )?,
+        ];
+
+        let broken = find_broken_anchors(&pages);
+
+        assert_eq!(
+            broken,
+            vec![
+                BrokenAnchor {
+                    page: 0,
+                    link: String::from("guide/setup.md#instal"),
+                    suggestion: Some(String::from("install")),
+                },
+                BrokenAnchor {
+                    page: 0,
+                    link: String::from("#home"),
+                    suggestion: None,
+                },
+            ]
+        );
+
+        Ok(())
+    }
+}
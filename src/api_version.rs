@@ -0,0 +1,125 @@
+//! The versions of the Confluence REST API that marked-space knows about, and how it talks to them.
+//!
+//! Most calls go to the versioned API (`/wiki/api/v2`), the rest to the older `/wiki/rest/api`,
+//! which isn't versioned. The responses are read leniently (fields marked-space doesn't use are
+//! ignored, missing optional fields get defaults and unknown statuses are kept as unknown), so
+//! that a small change to the API doesn't break every sync at once. When Atlassian ships a new
+//! version before marked-space supports it, `--api-version` points the client at it.
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::anyhow;
+
+use crate::{console::print_warning, error::Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Support {
+    /// Tested against, used unless `--api-version` says otherwise.
+    Default,
+    /// Known not to work, e.g. because endpoints marked-space uses were removed.
+    Unsupported,
+}
+
+/// A version of the API in the compatibility matrix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compatibility {
+    pub version: ApiVersion,
+    pub support: Support,
+    pub notes: &'static str,
+}
+
+/// What marked-space knows about each version of the API.
+pub const COMPATIBILITY: &[Compatibility] = &[
+    Compatibility {
+        version: ApiVersion(1),
+        support: Support::Unsupported,
+        notes: "has no v2 endpoints for pages, folders, properties and descendants",
+    },
+    Compatibility {
+        version: ApiVersion(2),
+        support: Support::Default,
+        notes: "pages, folders, attachments, properties and descendants",
+    },
+];
+
+/// The version of the versioned API (`/wiki/api/v<N>`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApiVersion(pub u8);
+
+impl Default for ApiVersion {
+    fn default() -> Self {
+        COMPATIBILITY
+            .iter()
+            .find(|compatibility| compatibility.support == Support::Default)
+            .map(|compatibility| compatibility.version)
+            .unwrap_or(ApiVersion(2))
+    }
+}
+
+impl Display for ApiVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+impl FromStr for ApiVersion {
+    type Err = anyhow::Error;
+
+    /// Either `2` or `v2`.
+    fn from_str(s: &str) -> Result<Self> {
+        s.trim_start_matches('v')
+            .parse::<u8>()
+            .map(ApiVersion)
+            .map_err(|_| anyhow!("Expected an API version like v2, got \"{}\"", s))
+    }
+}
+
+impl ApiVersion {
+    /// The path of the API relative to the host.
+    pub fn base_path(&self) -> String {
+        format!("wiki/api/v{}", self.0)
+    }
+
+    pub fn compatibility(&self) -> Option<&'static Compatibility> {
+        COMPATIBILITY
+            .iter()
+            .find(|compatibility| compatibility.version == *self)
+    }
+
+    /// Fails for versions that are known not to work, warns about the ones that aren't known yet.
+    pub fn check(&self) -> Result<()> {
+        match self.compatibility() {
+            Some(Compatibility {
+                support: Support::Unsupported,
+                notes,
+                ..
+            }) => Err(anyhow!("API {} isn't supported: {}", self, notes)),
+            Some(_) => Ok(()),
+            None => {
+                print_warning(&format!(
+                    "API {} hasn't been tested with marked-space, responses are read leniently",
+                    self
+                ));
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_checks_versions_against_the_matrix() -> crate::error::TestResult {
+        assert_eq!(ApiVersion::default(), ApiVersion(2));
+        assert_eq!("v3".parse::<ApiVersion>()?, ApiVersion(3));
+        assert_eq!(ApiVersion(3).base_path(), "wiki/api/v3");
+        assert!("latest".parse::<ApiVersion>().is_err());
+
+        assert!(ApiVersion(2).check().is_ok());
+        assert!(ApiVersion(3).check().is_ok());
+        assert!(ApiVersion(1).check().is_err());
+
+        Ok(())
+    }
+}
@@ -1,13 +1,151 @@
 use std::path::Path;
 
+use anyhow::anyhow;
+use saphyr::Yaml;
+use serde_json::json;
+
 use crate::{
     confluence_client::ConfluenceClient,
-    confluence_page::{ConfluenceNode, ConfluenceNodeType},
+    confluence_page::{ConfluenceNode, ConfluenceNodeType, ConfluencePageData},
     console::{print_status, Status},
+    error::{ConfluenceError, Result},
     link_generator::LinkGenerator,
-    responses::ContentStatus,
+    responses::{BodySingle, ContentStatus, PageSingleWithBody},
+    space_config::parse_bool,
 };
 
+/// What happens to orphaned pages, from the `orphans` key of `_space.yml`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct OrphanPolicy {
+    /// Title of the page to move orphans under, which is created when it doesn't exist.
+    pub parent: Option<String>,
+    /// Label added to orphans moved under the parent.
+    pub label: Option<String>,
+    /// Put a note at the top of orphans moved under the parent.
+    pub banner: bool,
+    /// Archive orphans with the archive feature of Confluence.
+    pub archive: bool,
+}
+
+impl Default for OrphanPolicy {
+    fn default() -> Self {
+        OrphanPolicy {
+            parent: None,
+            label: None,
+            banner: false,
+            archive: true,
+        }
+    }
+}
+
+impl OrphanPolicy {
+    pub fn from_yaml(yaml: &Yaml) -> Result<Self> {
+        let parse_string = |key: &str| match &yaml[key] {
+            Yaml::BadValue | Yaml::Null => Ok(None),
+            Yaml::String(value) => Ok(Some(value.clone())),
+            _ => Err(anyhow!(
+                "Failed to parse \"orphans.{}\" key (should be a string)",
+                key
+            )),
+        };
+        match yaml {
+            Yaml::BadValue | Yaml::Null => Ok(OrphanPolicy::default()),
+            Yaml::Hash(_) => {
+                let parent = parse_string("parent")?;
+                let archive = match &yaml["archive"] {
+                    Yaml::BadValue | Yaml::Null => parent.is_none(),
+                    archive => parse_bool(archive, "orphans.archive")?,
+                };
+                if parent.is_none() && !archive {
+                    return Err(anyhow!(
+                        "Failed to parse \"orphans\" key (orphans need a parent when they're not archived)"
+                    ));
+                }
+                Ok(OrphanPolicy {
+                    parent,
+                    label: parse_string("label")?,
+                    banner: parse_bool(&yaml["banner"], "orphans.banner")?,
+                    archive,
+                })
+            }
+            _ => Err(anyhow!(
+                "Failed to parse \"orphans\" key (should be a map with parent, label, banner and/or archive)"
+            )),
+        }
+    }
+}
+
+static ORPHAN_BANNER: &str = "<ac:structured-macro ac:name=\"note\"><ac:rich-text-body><p>This page is no longer published by marked-space, its source file was removed.</p></ac:rich-text-body></ac:structured-macro>";
+
+/// Moves an orphan under the archive parent, labelling it and adding the banner when configured.
+/// The checksum in the version message no longer matches any content, so the page is updated
+/// again when its source returns.
+pub(crate) fn move_under_parent(
+    node: &ConfluenceNode,
+    parent_id: &str,
+    policy: &OrphanPolicy,
+    confluence_client: &ConfluenceClient,
+) -> Result<()> {
+    print_status(
+        Status::Archived,
+        &format!(
+            "moved orphaned \"{}\" under \"{}\"",
+            node.title,
+            policy.parent.as_deref().unwrap_or_default()
+        ),
+    );
+    let response = confluence_client.move_page_relative(&node.id, "append", parent_id)?;
+    if !response.status().is_success() {
+        return Err(ConfluenceError::failed_request(response));
+    }
+    if let Some(label) = &policy.label {
+        let response = confluence_client
+            .set_page_labels(&node.id, vec![json!({"prefix": "", "name": label})])?;
+        if !response.status().is_success() {
+            return Err(ConfluenceError::failed_request(response));
+        }
+    }
+    if policy.banner {
+        let response = confluence_client.get_page_with_body(&node.id)?;
+        if !response.status().is_success() {
+            return Err(ConfluenceError::failed_request(response));
+        }
+        let page: PageSingleWithBody = response.json()?;
+        let BodySingle::Storage(body) = page.body else {
+            return Err(anyhow!("Page \"{}\" has no storage body", node.title));
+        };
+        let source = node
+            .page_data()
+            .and_then(|page_data| page_data.path.as_ref())
+            .map(|path| path.to_string_lossy().replace('\\', "/"))
+            .unwrap_or_default();
+        let response = confluence_client.update_page(
+            &node.id,
+            json!({
+                "id": node.id,
+                "status": "current",
+                "title": page.title,
+                "body": {
+                    "representation": "storage",
+                    "value": format!("{}{}", ORPHAN_BANNER, body.value)
+                },
+                "version": {
+                    "message": format!(
+                        "{} source={}; checksum=orphaned",
+                        ConfluencePageData::version_message_prefix(),
+                        source
+                    ),
+                    "number": page.version.number + 1
+                },
+            }),
+        )?;
+        if !response.status().is_success() {
+            return Err(ConfluenceError::failed_request(response));
+        }
+    }
+    Ok(())
+}
+
 pub(crate) fn should_archive(node: &ConfluenceNode, link_generator: &LinkGenerator) -> bool {
     match &node.data {
         ConfluenceNodeType::Page(p) => {
@@ -87,11 +225,17 @@ pub(crate) fn archive(
 
 #[cfg(test)]
 mod tests {
-    use comrak::{nodes::AstNode, Arena};
+    use std::path::Path;
+
+    use mockito::Matcher;
+    use saphyr::Yaml;
+    use serde_json::json;
 
     use crate::{
-        archive::{should_archive, should_unarchive},
+        archive::{should_archive, should_unarchive, OrphanPolicy},
+        confluence_client::ConfluenceClient,
         confluence_page::{ConfluenceNode, ConfluenceNodeType, ConfluencePageData},
+        confluence_space::ConfluenceSpace,
         error::TestResult,
         link_generator::LinkGenerator,
         responses::{ContentStatus, Version},
@@ -163,13 +307,11 @@ mod tests {
 
     #[test]
     fn it_unarchives_non_orphans() -> TestResult {
-        let arena = Arena::<AstNode>::new();
         let mut link_generator = test_link_generator();
         let page = nonorphan(ContentStatus::Archived);
         link_generator.register_markdown_page(&markdown_page_from_str(
             "test.md",
             &format!("# {} \n", page.title),
-            &arena,
         )?)?;
 
         assert!(!link_generator.is_orphaned(&page, page.page_data().unwrap()));
@@ -180,13 +322,11 @@ mod tests {
 
     #[test]
     fn it_does_not_archive_nonorphans() -> TestResult {
-        let arena = Arena::<AstNode>::new();
         let mut link_generator = test_link_generator();
         let node = nonorphan(ContentStatus::Current);
         link_generator.register_markdown_page(&markdown_page_from_str(
             "test.md",
             &format!("# {} \n", node.title),
-            &arena,
         )?)?;
 
         assert!(!link_generator.is_orphaned(&node, node.page_data().unwrap()));
@@ -211,6 +351,159 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn it_reads_the_orphan_policy() -> TestResult {
+        let parse = |s: &str| -> crate::error::Result<OrphanPolicy> {
+            OrphanPolicy::from_yaml(&Yaml::load_from_str(s)?[0])
+        };
+
+        assert_eq!(parse("~")?, OrphanPolicy::default());
+        assert_eq!(
+            parse("parent: Attic\nlabel: archived\nbanner: true\n")?,
+            OrphanPolicy {
+                parent: Some(String::from("Attic")),
+                label: Some(String::from("archived")),
+                banner: true,
+                archive: false,
+            }
+        );
+        assert!(parse("parent: Attic\narchive: true\n")?.archive);
+        assert!(parse("archive: false\n").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_moves_orphans_under_the_archive_parent() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let create = server
+            .mock("POST", "/wiki/api/v2/pages")
+            .match_body(Matcher::PartialJson(
+                json!({"title": "Attic", "parentId": "999"}),
+            ))
+            .with_status(200)
+            .with_body(
+                json!({"id": "50", "title": "Attic", "version": {"number": 1, "message": ""}})
+                    .to_string(),
+            )
+            .create();
+        let moved = server
+            .mock("PUT", "/wiki/rest/api/content/2/move/append/50")
+            .with_status(200)
+            .create();
+        let labelled = server
+            .mock("POST", "/wiki/rest/api/content/2/label")
+            .match_body(Matcher::Json(json!([{"prefix": "", "name": "archived"}])))
+            .with_status(200)
+            .create();
+        let archived = server
+            .mock("POST", "/cgraphql")
+            .match_query(Matcher::Any)
+            .expect(0)
+            .create();
+        let mut space = ConfluenceSpace::new_test("1", "999", vec![orphan(ContentStatus::Current)]);
+        let policy = OrphanPolicy {
+            parent: Some(String::from("Attic")),
+            label: Some(String::from("archived")),
+            banner: false,
+            archive: false,
+        };
+
+        space.archive_orphans(&test_link_generator(), Path::new("."), &client, &policy)?;
+        // the orphan is under the parent now, so it's left alone the next time
+        space.archive_orphans(&test_link_generator(), Path::new("."), &client, &policy)?;
+
+        create.assert();
+        moved.assert();
+        labelled.assert();
+        archived.assert();
+        assert_eq!(
+            space.get_existing_node("2").unwrap().parent_id,
+            Some(String::from("50"))
+        );
+
+        Ok(())
+    }
+
+    fn synced(id: &str, title: &str, source: &str, status: ContentStatus) -> ConfluenceNode {
+        ConfluenceNode {
+            id: String::from(id),
+            title: String::from(title),
+            parent_id: Some(String::from("999")),
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: Version {
+                    message: format!("updated by markedspace: source={}; checksum=X", source),
+                    number: 1,
+                },
+                path: Some(std::path::PathBuf::from(source)),
+                status,
+            }),
+        }
+    }
+
+    #[test]
+    fn it_restores_archived_pages_whose_source_returns() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let unarchived = server
+            .mock("POST", "/cgraphql")
+            .match_query(Matcher::Any)
+            .match_body(Matcher::PartialJson(json!({
+                "operationName": "UnarchivePagesMutation",
+                "variables": {"pageIDs": ["2"]}
+            })))
+            .with_status(200)
+            .with_body("{}")
+            .create();
+        let mut link_generator = test_link_generator();
+        link_generator
+            .register_markdown_page(&markdown_page_from_str("guide.md", "# Guide (Revised)\n")?)?;
+        // the file came back with a new title
+        let mut space = ConfluenceSpace::new_test(
+            "1",
+            "999",
+            vec![synced("2", "Guide", "guide.md", ContentStatus::Archived)],
+        );
+        space.link_pages(&mut link_generator)?;
+
+        space.restore_archived_pages(&link_generator, &client)?;
+
+        unarchived.assert();
+        assert!(link_generator.get_nodes_to_create().is_empty());
+        assert!(matches!(
+            space
+                .get_existing_node("2")
+                .unwrap()
+                .page_data()
+                .unwrap()
+                .status,
+            ContentStatus::Current
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_prefers_current_pages_over_archived_duplicates() -> TestResult {
+        let mut link_generator = test_link_generator();
+        link_generator.register_markdown_page(&markdown_page_from_str("guide.md", "# Guide\n")?)?;
+        let archived = synced("2", "Guide", "guide.md", ContentStatus::Archived);
+        let current = synced("3", "Guide v2", "guide.md", ContentStatus::Current);
+        let mut space =
+            ConfluenceSpace::new_test("1", "999", vec![archived.clone(), current.clone()]);
+
+        space.link_pages(&mut link_generator)?;
+
+        assert_eq!(
+            link_generator.get_file_id(std::path::Path::new("guide.md")),
+            Some(String::from("3"))
+        );
+        assert!(!should_unarchive(&archived, &link_generator));
+
+        Ok(())
+    }
+
     #[test]
     fn it_does_not_archive_unmanaged() {
         assert!(!should_archive(
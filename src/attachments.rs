@@ -1,35 +1,66 @@
 use crate::{
-    checksum::sha256_digest,
+    checksum::ChecksumAlgorithm,
     confluence_paginator::ConfluencePaginator,
     console::{print_error, Status},
-    error::Result,
+    error::{ApiResponse, ConfluenceError, Result},
     responses::{Attachment, Content},
     sync_operation::SyncOperation,
+    sync_stats::record_attachment,
 };
 use std::{
-    collections::HashMap,
-    fs::File,
-    io::{self, BufReader, Write},
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{self, Cursor, Write},
     path::{Path, PathBuf},
 };
 
 use anyhow::Context;
 use comrak::nodes::NodeLink;
+use data_encoding::HEXLOWER;
+use once_cell::sync::Lazy;
 use regex::Regex;
 use reqwest::blocking::multipart::Part;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     confluence_client::ConfluenceClient,
-    confluence_storage_renderer::{escape_href, WriteWithLast},
+    confluence_storage_renderer::{escape, escape_href, WriteWithLast},
+    diagnostics::Diagnostic,
     link_generator::LinkGenerator,
-    responses::MultiEntityResult,
+    qr_codes::{qr_code_svg, QR_CODE_SCHEME},
 };
 
-#[derive(Debug, PartialEq)]
+/// How attachments are named on their page, the `attachment_names` key of `_space.yml`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum AttachmentNames {
+    /// The link with `/` replaced by `_`, `images/diagram.png` becomes `images_diagram.png`.
+    #[default]
+    Path,
+    /// The file name with a digest of the file, `diagram-3f2a9c1e.png`, so different files never
+    /// get the same name and the same file is attached once.
+    Content,
+}
+
+impl AttachmentNames {
+    pub fn from_str(s: Option<&str>) -> Result<AttachmentNames> {
+        match s {
+            None | Some("path") => Ok(AttachmentNames::Path),
+            Some("content") => Ok(AttachmentNames::Content),
+            Some(other) => Err(ConfluenceError::generic_error(format!(
+                "Invalid attachment names \"{}\" (should be path or content)",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct ImageAttachment {
     pub url: String,   // how this was specified in the markdown
     pub path: PathBuf, // the full path to the file
     pub name: String,  // a simple name
+    /// Page path (relative to the space) or URL to generate a QR code for, instead of a file.
+    pub qr_target: Option<String>,
 }
 
 impl ImageAttachment {
@@ -41,22 +72,124 @@ impl ImageAttachment {
             path,
             url: String::from(url),
             name: link_to_name(url),
+            qr_target: None,
+        }
+    }
+
+    pub fn qr_code(url: &str, target: &str) -> Self {
+        ImageAttachment {
+            path: PathBuf::default(),
+            url: String::from(url),
+            name: link_to_name(url),
+            qr_target: Some(String::from(target)),
+        }
+    }
+
+    /// Names the attachment after the content of its file with [`AttachmentNames::Content`].
+    /// Files that can't be read keep their name, parsing the space reports them as missing.
+    pub fn named(mut self, names: AttachmentNames) -> Self {
+        if names == AttachmentNames::Content && self.qr_target.is_none() {
+            if let Ok(content) = fs::read(&self.path) {
+                self.name = content_name(&self.url, &content);
+            }
+        }
+        self
+    }
+
+    /// The content to upload, generating the QR code from the current URL of its target.
+    pub(crate) fn content(&self, link_generator: &LinkGenerator) -> Result<Vec<u8>> {
+        match &self.qr_target {
+            Some(target) if target.contains("://") => Ok(qr_code_svg(target)?.into_bytes()),
+            Some(target) => {
+                let url = link_generator
+                    .get_file_url(Path::new(target))
+                    .ok_or_else(|| {
+                        ConfluenceError::generic_error(format!(
+                            "Can't make a QR code for {}, it has no page yet",
+                            target
+                        ))
+                    })?;
+                Ok(qr_code_svg(&url)?.into_bytes())
+            }
+            None => Ok(fs::read(&self.path)
+                .with_context(|| format!("Opening attachment for {}", self.name))?),
         }
     }
 }
 
+/// An attachment of the page itself in storage format, the ones of other pages have a `<ri:page>`
+/// inside.
+static ATTACHMENT_MARKUP: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"<ri:attachment\s[^>]*?ri:filename="([^"]+)"[^>]*?/>"#).unwrap());
+
+/// The filenames of the attachments of the page in storage format, like the markup macros and
+/// templates put in a page.
+pub fn attachment_filenames(storage: &str) -> impl Iterator<Item = &str> {
+    ATTACHMENT_MARKUP
+        .captures_iter(storage)
+        .filter_map(|captures| captures.get(1))
+        .map(|filename| filename.as_str())
+}
+
 fn link_to_name(url: &str) -> String {
+    if let Some(target) = url.strip_prefix(QR_CODE_SCHEME) {
+        let re = Regex::new(r"[/\\:?&=#]").unwrap();
+        return format!("qr_{}.svg", re.replace_all(target, "_"));
+    }
     let re = Regex::new(r"[/\\]").unwrap();
     re.replace_all(url, "_").into()
 }
 
-pub fn render_link_enter(nl: &NodeLink, output: &mut WriteWithLast) -> io::Result<()> {
+fn content_name(url: &str, content: &[u8]) -> String {
+    let file = Path::new(url);
+    let digest = ring::digest::digest(&ring::digest::SHA256, content);
+    let digest = HEXLOWER.encode(&digest.as_ref()[..4]);
+    let stem = file.file_stem().map_or(String::from("attachment"), |stem| {
+        stem.to_string_lossy().to_string()
+    });
+    match file.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, digest, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, digest),
+    }
+}
+
+/// Drops the attachments of a page that are linked more than once, and returns the errors for
+/// different files that would get the same name.
+pub fn dedup_attachments(attachments: &mut Vec<ImageAttachment>) -> Vec<Diagnostic> {
+    let mut errors = Vec::default();
+    let mut kept = Vec::<ImageAttachment>::with_capacity(attachments.len());
+    for attachment in attachments.drain(..) {
+        if kept.iter().any(|other| other.url == attachment.url) {
+            continue;
+        }
+        if let Some(other) = kept
+            .iter()
+            .find(|other| other.name == attachment.name && other.path != attachment.path)
+        {
+            errors.push(Diagnostic::new(format!(
+                "attachments {} and {} would both be named {}, rename one of the files or set attachment_names: content in _space.yml",
+                other.url, attachment.url, attachment.name
+            )));
+            continue;
+        }
+        kept.push(attachment);
+    }
+    *attachments = kept;
+    errors
+}
+
+pub fn render_link_enter(nl: &NodeLink, alt: &str, output: &mut WriteWithLast) -> io::Result<()> {
     output.write_all(br#"<ac:image ac:align="center""#)?;
     if !nl.title.is_empty() {
         output.write_all(format!(" ac:title=\"{}\"", nl.title).as_bytes())?;
     }
+    if !alt.is_empty() {
+        output.write_all(b" ac:alt=\"")?;
+        escape(output, alt.as_bytes())?;
+        output.write_all(b"\"")?;
+    }
     output.write_all(b">")?;
-    if nl.url.contains("://") {
+    if nl.url.contains("://") && !nl.url.starts_with(QR_CODE_SCHEME) {
         output.write_all(b"<ri:url ri:value=\"")?;
         escape_href(output, nl.url.as_bytes())?;
     } else {
@@ -81,16 +214,19 @@ pub fn sync_page_attachments(
     page_source: &str,
     attachments: &[ImageAttachment],
     link_generator: &mut LinkGenerator,
+    checksum: ChecksumAlgorithm,
 ) -> Result<()> {
-    let existing_attachments: MultiEntityResult<Attachment> = confluence_client
-        .get_attachments(page_id)?
-        .error_for_status()?
-        .json()?;
+    // every page of the listing, or attachments under an old name past the first are left behind
+    let response = confluence_client.get_attachments(page_id)?.api_result()?;
+    let existing_attachments: Vec<Attachment> =
+        ConfluencePaginator::<Attachment>::new(confluence_client)
+            .start(response)?
+            .collect::<Result<_>>()?;
 
     let mut hashes = HashMap::<String, String>::new();
-    let mut remove_titles_to_id = HashMap::<String, String>::new();
+    let mut remove_titles_to_id = BTreeMap::<String, String>::new();
     let mut title_to_fileid = HashMap::<String, String>::new();
-    for existing_attachment in existing_attachments.results.iter() {
+    for existing_attachment in existing_attachments.iter() {
         if existing_attachment.comment.starts_with("hash:") {
             hashes.insert(
                 existing_attachment.title.clone(),
@@ -117,44 +253,50 @@ pub fn sync_page_attachments(
         remove_titles_to_id.remove(&attachment_name);
 
         let op = SyncOperation::start(format!("[{}] attachment", attachment.path.display()), true);
-        let input = File::open(&attachment.path)
-            .with_context(|| format!("Opening attachment for {}", attachment_name))?;
-        let reader = BufReader::new(input);
-        let hashstring = sha256_digest(reader)?;
+        let content = attachment.content(link_generator)?;
+        let hashstring = checksum.digest(Cursor::new(&content))?;
         if hashes.contains_key(&attachment_name)
             && hashstring == *hashes.get(&attachment_name).unwrap()
         {
             // still add the existing attachment to lookup for covers
             let id = title_to_fileid[&attachment_name].clone();
             link_generator.register_attachment_id(page_source, &attachment.url, &id);
+            record_attachment(&Status::Skipped, 0);
             op.end(Status::Skipped);
-            return Ok(());
+            continue;
         }
 
-        let file_part = Part::file(&attachment.path)?.file_name(attachment.name.clone());
+        let size = content.len();
+        let file_part = if attachment.qr_target.is_some() {
+            Part::bytes(content).mime_str("image/svg+xml")?
+        } else {
+            Part::file(&attachment.path)?
+        }
+        .file_name(attachment.name.clone());
 
         let response =
             confluence_client.create_or_update_attachment(page_id, file_part, &hashstring)?;
 
         if !response.status().is_success() {
-            // Handle non-2xx responses (e.g., 400 Bad Request)
-            let status = response.status();
-            let error_body = response.text()?;
             print_error(&format!(
-                "error updating attachment: Status: {}, Body: {}",
-                status, error_body
+                "error updating attachment {}: {}",
+                attachment.name,
+                ConfluenceError::failed_request(response)
             ));
         } else {
             let results: Vec<Content> = ConfluencePaginator::<Content>::new(confluence_client)
                 .start(response)?
-                .filter_map(|f| f.ok())
-                .collect();
+                .collect::<Result<_>>()?;
 
             assert_eq!(results.len(), 1);
             assert_eq!(results[0].title, attachment_name);
             let id = results[0].extensions["fileId"].as_str().unwrap();
             // add new attachment to lookup
             link_generator.register_attachment_id(page_source, &attachment.url, id);
+            // the same file linked in another way has the same name and content
+            hashes.insert(attachment_name.clone(), hashstring.clone());
+            title_to_fileid.insert(attachment_name, id.to_string());
+            record_attachment(&Status::Updated, size);
         }
 
         op.end(Status::Updated);
@@ -166,6 +308,7 @@ pub fn sync_page_attachments(
             let op = SyncOperation::start(format!("[{}] attachment", title), false);
             let result = confluence_client.remove_attachment(id);
             if result.is_ok() {
+                record_attachment(&Status::Deleted, 0);
                 op.end(Status::Deleted);
             } else {
                 op.end(Status::Error);
@@ -181,9 +324,16 @@ pub fn sync_page_attachments(
 mod test {
     use std::{io::Cursor, path::PathBuf};
 
+    use assert_fs::{
+        fixture::{FileWriteStr, PathChild},
+        TempDir,
+    };
     use comrak::nodes::NodeLink;
 
-    use crate::{confluence_storage_renderer::WriteWithLast, error::TestResult};
+    use crate::{
+        confluence_storage_renderer::WriteWithLast, error::TestResult, markdown_page::MarkdownPage,
+        markdown_space::MarkdownSpace, template_renderer::TemplateRenderer,
+    };
 
     use super::*;
 
@@ -196,7 +346,7 @@ mod test {
 
         let mut cursor = Cursor::new(vec![0; 15]);
         let mut output = WriteWithLast::from_write(&mut cursor);
-        render_link_enter(&nl, &mut output)?;
+        render_link_enter(&nl, "", &mut output)?;
         render_link_leave(&nl, &mut output)?;
 
         assert_eq!(String::from_utf8(cursor.into_inner()).unwrap(),
@@ -215,7 +365,7 @@ mod test {
 
         let mut cursor = Cursor::new(vec![0; 15]);
         let mut output = WriteWithLast::from_write(&mut cursor);
-        render_link_enter(&nl, &mut output)?;
+        render_link_enter(&nl, "", &mut output)?;
         render_link_leave(&nl, &mut output)?;
 
         assert_eq!(String::from_utf8(cursor.into_inner()).unwrap(),
@@ -225,6 +375,25 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn it_renders_alt_text() -> TestResult {
+        let nl = NodeLink {
+            url: String::from("image.png"),
+            title: String::new(),
+        };
+
+        let mut cursor = Cursor::new(vec![0; 15]);
+        let mut output = WriteWithLast::from_write(&mut cursor);
+        render_link_enter(&nl, "A \"quoted\" <diagram>", &mut output)?;
+        render_link_leave(&nl, &mut output)?;
+
+        assert_eq!(String::from_utf8(cursor.into_inner()).unwrap(),
+            "<ac:image ac:align=\"center\" ac:alt=\"A &quot;quoted&quot; &lt;diagram&gt;\"><ri:attachment ri:filename=\"image.png\"/></ac:image>"
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn it_renders_image_link_in_subdirectories() {
         // Cannot upload files with names that contain slashes... confluence will strip the
@@ -248,4 +417,201 @@ mod test {
 
         Ok(())
     }
+
+    /// Parses a space with two files named diagram.png, the files stay around for rendering.
+    fn parse_space(space_config: &str, index: &str) -> Result<(TempDir, Vec<MarkdownPage>)> {
+        let temp = TempDir::new()?;
+        temp.child("test/_space.yml").write_str(space_config)?;
+        temp.child("test/index.md").write_str(index)?;
+        temp.child("test/a/diagram.png").write_str("old")?;
+        temp.child("test/b/diagram.png").write_str("new")?;
+        temp.child("test/a_diagram.png").write_str("newer")?;
+        let pages = MarkdownSpace::from_directory(temp.child("test").path())?
+            .parse(&TemplateRenderer::default()?, None)?;
+        Ok((temp, pages))
+    }
+
+    #[test]
+    fn it_names_attachments_by_content() -> TestResult {
+        let (_temp, pages) = parse_space(
+            "attachment_names: content\n",
+            "# Home\n\n![Old](a/diagram.png)\n![New](b/diagram.png)\n![Old again](a/diagram.png)\n",
+        )?;
+        let names: Vec<&str> = pages[0]
+            .attachments
+            .iter()
+            .map(|attachment| attachment.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["diagram-cba06b57.png", "diagram-11507a0e.png"]);
+
+        let rendered = pages[0].render(&LinkGenerator::default_test())?.content;
+        assert!(
+            rendered.contains("ri:filename=\"diagram-cba06b57.png\"")
+                && rendered.contains("ri:filename=\"diagram-11507a0e.png\""),
+            "{}",
+            rendered
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_attachments_with_the_same_name() {
+        let result = parse_space(
+            "attachment_names: path\n",
+            "# Home\n\n![Old](a/diagram.png)\n![New](a_diagram.png)\n",
+        );
+        let error = format!("{:#}", result.err().unwrap());
+        assert!(
+            error.contains("would both be named a_diagram.png"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn it_attaches_files_shown_by_templates() -> TestResult {
+        let (_temp, pages) = parse_space(
+            "",
+            "# Home\n\n{% set name = \"a_diagram\" %}<ac:image><ri:attachment ri:filename=\"{{ name }}.png\"/></ac:image>\n",
+        )?;
+        assert_eq!(pages[0].attachments.len(), 1);
+        assert_eq!(pages[0].attachments[0].name, "a_diagram.png");
+        pages[0].render(&LinkGenerator::default_test())?;
+
+        let (_temp, pages) = parse_space(
+            "",
+            "# Home\n\n<ac:image><ri:attachment ri:filename=\"missing.png\"/></ac:image>\n",
+        )?;
+        let error = format!(
+            "{:#}",
+            pages[0]
+                .render(&LinkGenerator::default_test())
+                .err()
+                .unwrap()
+        );
+        assert!(error.contains("missing.png"), "{}", error);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_uploads_the_attachments_after_an_unchanged_one() -> TestResult {
+        let temp = TempDir::new()?;
+        temp.child("a.png").write_str("unchanged")?;
+        temp.child("b.png").write_str("new")?;
+        let attachments = [
+            ImageAttachment::new("a.png", temp.path()),
+            ImageAttachment::new("b.png", temp.path()),
+        ];
+        let checksum = ChecksumAlgorithm::default();
+        let unchanged = checksum.digest(Cursor::new("unchanged"))?;
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        server
+            .mock("GET", "/wiki/api/v2/pages/42/attachments")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(format!(
+                r#"{{"results": [
+                    {{"id": "att1", "title": "a.png", "comment": "hash:{}", "fileId": "file1"}},
+                    {{"id": "att3", "title": "old.png", "comment": "hash:old", "fileId": "file3"}}
+                ], "_links": {{}}}}"#,
+                unchanged
+            ))
+            .create();
+        let upload = server
+            .mock("PUT", "/wiki/rest/api/content/42/child/attachment")
+            .match_body(mockito::Matcher::Regex(String::from("b.png")))
+            .with_status(200)
+            .with_body(
+                r#"{"results": [{"id": "att2", "type": "attachment", "title": "b.png", "extensions": {"fileId": "file2"}}], "_links": {}}"#,
+            )
+            .expect(1)
+            .create();
+        let delete = server
+            .mock("DELETE", "/wiki/api/v2/attachments/att3")
+            .with_status(204)
+            .expect(1)
+            .create();
+        let mut link_generator = LinkGenerator::default_test();
+
+        // skipping a.png used to end the sync of the attachments of the page
+        sync_page_attachments(
+            &client,
+            "42",
+            "page.md",
+            &attachments,
+            &mut link_generator,
+            checksum,
+        )?;
+
+        upload.assert();
+        delete.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn it_removes_the_attachments_of_the_old_names_from_every_page_of_the_listing() -> TestResult {
+        let temp = TempDir::new()?;
+        temp.child("docs/diagram.png").write_str("diagram")?;
+        let attachment =
+            ImageAttachment::new("docs/diagram.png", temp.path()).named(AttachmentNames::Content);
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        server
+            .mock("GET", "/wiki/api/v2/pages/42/attachments")
+            .match_query(mockito::Matcher::Missing)
+            .with_status(200)
+            .with_body(
+                r#"{"results": [
+                    {"id": "att1", "title": "other.png", "comment": "hash:x", "fileId": "file1"}
+                ], "_links": {"next": "/wiki/api/v2/pages/42/attachments?cursor=2"}}"#,
+            )
+            .create();
+        server
+            .mock("GET", "/wiki/api/v2/pages/42/attachments")
+            .match_query(mockito::Matcher::UrlEncoded("cursor".into(), "2".into()))
+            .with_status(200)
+            .with_body(
+                r#"{"results": [
+                    {"id": "att2", "title": "docs_diagram.png", "comment": "hash:y", "fileId": "file2"}
+                ], "_links": {}}"#,
+            )
+            .create();
+        let upload = server
+            .mock("PUT", "/wiki/rest/api/content/42/child/attachment")
+            .with_status(200)
+            .with_body(format!(
+                r#"{{"results": [{{"id": "att3", "type": "attachment", "title": "{}", "extensions": {{"fileId": "file3"}}}}], "_links": {{}}}}"#,
+                attachment.name
+            ))
+            .expect(1)
+            .create();
+        let deletes = ["att1", "att2"].map(|id| {
+            server
+                .mock(
+                    "DELETE",
+                    format!("/wiki/api/v2/attachments/{}", id).as_str(),
+                )
+                .with_status(204)
+                .expect(1)
+                .create()
+        });
+
+        sync_page_attachments(
+            &client,
+            "42",
+            "page.md",
+            &[attachment],
+            &mut LinkGenerator::default_test(),
+            ChecksumAlgorithm::default(),
+        )?;
+
+        upload.assert();
+        for delete in deletes {
+            delete.assert();
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,335 @@
+//! An append-only log of every change made in Confluence, written with `--audit-log` as one JSON
+//! object per line, so there's a record of who changed what and when through automation.
+//!
+//! The client records every call that changes something, including the ones that failed: pages,
+//! attachments, properties, labels, restrictions, comments and watchers, and the space's details,
+//! permissions and templates. A run that fails still finishes its log.
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    process,
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, Context};
+use reqwest::blocking::{multipart::Part, Response};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    checksum::sha256_digest,
+    confluence_client::ConfluenceClient,
+    console::{print_warning, Status},
+    error::{ConfluenceError, Result},
+    http_cache::buffer,
+    sync_operation::SyncOperation,
+};
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct AuditEntry {
+    /// When the response came back, in UTC.
+    pub time: String,
+    /// The run that made the change, to roll back the changes of a sync with `rollback --to`.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub run: String,
+    /// The API_USER the change was made with.
+    pub user: String,
+    pub operation: &'static str,
+    /// The space of a change to the space rather than to one of its pages.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub space_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_id: Option<String>,
+    /// The attachment, property, label, watcher, permission or template that changed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_id: Option<String>,
+    /// The title of the page, the file name of the attachment or the key of the property.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_before: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_after: Option<u64>,
+    pub status: u16,
+}
+
+impl AuditEntry {
+    pub fn new(operation: &'static str) -> Self {
+        AuditEntry {
+            operation,
+            ..Default::default()
+        }
+    }
+
+    pub fn page(mut self, page_id: &str) -> Self {
+        self.page_id = Some(String::from(page_id));
+        self
+    }
+
+    pub fn space(mut self, space_key: &str) -> Self {
+        self.space_key = Some(String::from(space_key));
+        self
+    }
+
+    pub fn target(mut self, target_id: &str) -> Self {
+        self.target_id = Some(String::from(target_id));
+        self
+    }
+
+    pub fn name(mut self, name: Option<&str>) -> Self {
+        self.name = name.map(String::from);
+        self
+    }
+
+    /// The version a change created, and the one before it unless the change created the content.
+    pub fn version(mut self, version_after: Option<u64>) -> Self {
+        self.version_after = version_after;
+        self.version_before = version_after
+            .filter(|after| *after > 1)
+            .map(|after| after - 1);
+        self
+    }
+
+    /// Fills in what only the response knows, like the id of a created page or the version of an
+    /// attachment (the first result of responses with a list).
+    fn complete(mut self, body: &Value) -> Self {
+        let content = match &body["results"] {
+            Value::Array(results) => results.first().unwrap_or(&Value::Null),
+            _ => body,
+        };
+        match (&self.page_id, content["id"].as_str()) {
+            // what changed in a space isn't a page
+            (None, id) if self.space_key.is_some() && self.target_id.is_none() => {
+                self.target_id = id.map(String::from)
+            }
+            (None, _) if self.space_key.is_some() => (),
+            (None, id) => self.page_id = id.map(String::from),
+            (Some(page_id), Some(id)) if page_id != id && self.target_id.is_none() => {
+                self.target_id = Some(String::from(id))
+            }
+            _ => (),
+        }
+        if self.name.is_none() {
+            self.name = content["title"].as_str().map(String::from);
+        }
+        if self.version_after.is_none() {
+            self = self.version(content["version"]["number"].as_u64());
+        }
+        self
+    }
+}
+
+/// The body of a response as JSON, and an equivalent response for the caller to read.
+fn read_back(response: Response) -> reqwest::Result<(Value, Response)> {
+    let (bytes, response) = buffer(response)?;
+    Ok((serde_json::from_slice(&bytes).unwrap_or_default(), response))
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    run: String,
+    file: Mutex<File>,
+    failed: Mutex<bool>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> Result<AuditLog> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+        Ok(AuditLog {
+            path: path.to_path_buf(),
+            run: format!(
+                "{}-{}",
+                chrono::Utc::now().format("%Y%m%dT%H%M%SZ"),
+                process::id()
+            ),
+            file: Mutex::new(file),
+            failed: Mutex::new(false),
+        })
+    }
+
+    /// The id of this run in the log.
+    pub fn run(&self) -> &str {
+        &self.run
+    }
+
+    /// Records a change with the outcome of its request. Requests that never got a response
+    /// didn't change anything and aren't recorded.
+    pub fn record(
+        &self,
+        user: &str,
+        entry: AuditEntry,
+        result: reqwest::Result<Response>,
+    ) -> reqwest::Result<Response> {
+        let (body, response) = read_back(result?)?;
+        let entry = AuditEntry {
+            time: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            run: self.run.clone(),
+            user: String::from(user),
+            status: response.status().as_u16(),
+            ..entry.complete(&body)
+        };
+        if let Err(err) = self.append(&entry) {
+            print_warning(&format!(
+                "failed to write to audit log {}: {}",
+                self.path.display(),
+                err
+            ));
+            *self.failed.lock().unwrap() = true;
+        }
+        Ok(response)
+    }
+
+    fn append(&self, entry: &AuditEntry) -> Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let mut file = self.file.lock().unwrap();
+        // written and flushed one line at a time, so the log is complete up to a crash
+        file.write_all(line.as_bytes())?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Attaches the log to the audit page, if there is one. Fails when any of the changes couldn't
+    /// be written to the log.
+    pub fn finish_and_upload(
+        &self,
+        confluence_client: &ConfluenceClient,
+        audit_page: Option<&str>,
+    ) -> Result<()> {
+        if let Some(page_id) = audit_page {
+            let op = SyncOperation::start(format!("[{}] audit log", self.path.display()), false);
+            let hash = sha256_digest(File::open(&self.path)?)?;
+            let filename = self
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or(String::from("audit.jsonl"));
+            let response = confluence_client.create_or_update_attachment(
+                page_id,
+                Part::file(&self.path)?.file_name(filename),
+                &hash,
+            )?;
+            if !response.status().is_success() {
+                return Err(ConfluenceError::failed_request(response));
+            }
+            op.end(Status::Updated);
+        }
+        if *self.failed.lock().unwrap() {
+            return Err(anyhow!(
+                "Some changes are missing from the audit log {}",
+                self.path.display()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc};
+
+    use assert_fs::fixture::PathChild;
+    use serde_json::json;
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn it_records_changes() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let path = temp.child("audit.jsonl");
+        let mut server = mockito::Server::new();
+        server
+            .mock("PUT", "/wiki/api/v2/pages/42")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id": "42", "title": "Home", "version": {"number": 7}}"#)
+            .create();
+        server
+            .mock("POST", "/wiki/api/v2/pages")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id": "43", "title": "New", "version": {"number": 1}}"#)
+            .create();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port())
+            .with_audit_log(Arc::new(AuditLog::open(path.path())?));
+
+        let response = client.update_page(
+            &String::from("42"),
+            json!({"id": "42", "title": "Home", "version": {"number": 7}}),
+        )?;
+        // the response can still be read after the log read it
+        assert_eq!(response.json::<Value>()?["id"], "42");
+        client.create_page(json!({"title": "New"}))?;
+
+        let log = fs::read_to_string(path.path())?;
+        let entries = log
+            .lines()
+            .map(serde_json::from_str::<Value>)
+            .collect::<serde_json::Result<Vec<_>>>()?;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["operation"], "update_page");
+        assert_eq!(entries[0]["page_id"], "42");
+        assert_eq!(entries[0]["version_before"], 6);
+        assert_eq!(entries[0]["version_after"], 7);
+        assert_eq!(entries[0]["status"], 200);
+        assert_eq!(entries[0]["run"], entries[1]["run"]);
+        assert_eq!(entries[1]["page_id"], "43");
+        assert_eq!(entries[1]["name"], "New");
+        assert!(entries[1].get("version_before").is_none());
+        assert!(entries[1]["time"].as_str().unwrap().ends_with('Z'));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_records_changes_to_labels_and_the_space() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let path = temp.child("audit.jsonl");
+        let mut server = mockito::Server::new();
+        server
+            .mock("POST", "/wiki/rest/api/content/42/label")
+            .with_status(200)
+            .with_body(r#"{"results": [{"prefix": "global", "name": "runbook", "id": "7"}]}"#)
+            .create();
+        server
+            .mock("POST", "/wiki/rest/api/space/TEAM/permission")
+            .with_status(200)
+            .with_body(r#"{"id": 12}"#)
+            .create();
+        server
+            .mock("DELETE", "/wiki/rest/api/template/3")
+            .with_status(404)
+            .create();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port())
+            .with_audit_log(Arc::new(AuditLog::open(path.path())?));
+
+        client.set_page_labels("42", vec![json!({"prefix": "global", "name": "runbook"})])?;
+        client.add_space_permission("TEAM", json!({"operation": {"key": "read"}}))?;
+        client.remove_content_template("3")?;
+
+        let log = fs::read_to_string(path.path())?;
+        let entries = log
+            .lines()
+            .map(serde_json::from_str::<Value>)
+            .collect::<serde_json::Result<Vec<_>>>()?;
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0]["operation"], "set_page_labels");
+        assert_eq!(entries[0]["page_id"], "42");
+        assert_eq!(entries[0]["target_id"], "7");
+        assert_eq!(entries[1]["operation"], "add_space_permission");
+        assert_eq!(entries[1]["space_key"], "TEAM");
+        assert!(entries[1].get("page_id").is_none());
+        assert_eq!(entries[2]["operation"], "remove_content_template");
+        assert_eq!(entries[2]["target_id"], "3");
+        assert_eq!(entries[2]["status"], 404);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+//! The parsing and rendering the criterion benchmarks in `benches/` measure. Benchmarks can only
+//! use the public API of the crate, so this module is public, but hidden from the docs: it's
+//! there for the benchmarks, not a stable API for other crates.
+use std::path::Path;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    console::set_quiet,
+    markdown_space::MarkdownSpace,
+    sync::{load_space_offline, LoadedSpace},
+    Args, Result,
+};
+
+/// A space read and parsed without Confluence, like `test-templates` does.
+pub struct ParsedSpace(LoadedSpace);
+
+/// Reads, templates and parses every page of the space in `dir`.
+pub fn parse_space(dir: &Path) -> Result<ParsedSpace> {
+    set_quiet(true);
+    let mut markdown_space = MarkdownSpace::from_directory(dir)?;
+    let args = Args {
+        space: vec![dir.to_string_lossy().into_owned()],
+        ..Args::default()
+    };
+    load_space_offline(
+        &ConfluenceClient::new("example.atlassian.net"),
+        &mut markdown_space,
+        &args,
+    )
+    .map(ParsedSpace)
+}
+
+impl ParsedSpace {
+    /// Renders the pages to storage format one after the other, returning how many bytes that is.
+    pub fn render(&self) -> Result<usize> {
+        let mut size = 0;
+        for markdown_page in &self.0.markdown_pages {
+            if !markdown_page.is_folder() {
+                size += markdown_page.render(&self.0.link_generator)?.content.len();
+            }
+        }
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn it_parses_and_renders_a_space() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("test/index.md")
+            .write_str("# Home\n\nWelcome\n")?;
+        temp.child("test/guide.md")
+            .write_str("# Guide\n\nSee [home](index.md)\n")?;
+
+        let space = parse_space(temp.child("test").path())?;
+        assert_eq!(space.0.markdown_pages.len(), 2);
+        let size = space
+            .0
+            .markdown_pages
+            .iter()
+            .map(|page| {
+                page.render(&space.0.link_generator)
+                    .map(|page| page.content.len())
+            })
+            .sum::<Result<usize>>()?;
+        assert!(size > 0);
+        assert_eq!(space.render()?, size);
+
+        Ok(())
+    }
+}
@@ -1,6 +1,8 @@
 use std::collections::HashMap;
 
-use crate::error::Result;
+use chrono::NaiveDate;
+
+use crate::{error::Result, macro_registry::MacroRegistry, qr_codes::qr};
 use tera::Tera;
 
 fn hello_world(
@@ -71,6 +73,122 @@ format!(r#"<ac:structured-macro ac:name="contentbylabel" ac:schema-version="4" d
     )
 }
 
+/// A string or number argument, escaped to go into a macro parameter.
+fn parameter(
+    args: &HashMap<String, serde_json::Value>,
+    name: &str,
+) -> std::result::Result<Option<String>, tera::Error> {
+    match args.get(name) {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(serde_json::Value::String(s)) => Ok(Some(tera::escape_html(s))),
+        Some(serde_json::Value::Number(n)) => Ok(Some(n.to_string())),
+        Some(_) => Err(tera::Error::msg(format!(
+            "{} needs to be a string or number",
+            name
+        ))),
+    }
+}
+
+fn recently_updated(
+    args: &HashMap<String, serde_json::Value>,
+) -> std::result::Result<serde_json::Value, tera::Error> {
+    let spaces = match args.get("spaces") {
+        None => String::from("@self"),
+        Some(serde_json::Value::String(s)) => tera::escape_html(s),
+        Some(serde_json::Value::Array(a)) if !a.is_empty() => a
+            .iter()
+            .map(|space| space.as_str().map(tera::escape_html))
+            .collect::<Option<Vec<String>>>()
+            .ok_or("spaces needs to be a string or array of strings")?
+            .join(","),
+        _ => Err(tera::Error::msg(
+            "spaces needs to be a string or non-empty array of strings",
+        ))?,
+    };
+    let max = parameter(args, "max")?.unwrap_or(String::from("10"));
+    Ok(serde_json::to_value(format!(
+        r#"<ac:structured-macro ac:name="recently-updated" ac:schema-version="1"><ac:parameter ac:name="spaces">{}</ac:parameter><ac:parameter ac:name="max">{}</ac:parameter></ac:structured-macro>"#,
+        spaces, max
+    ))
+    .unwrap())
+}
+
+fn contributors(
+    args: &HashMap<String, serde_json::Value>,
+) -> std::result::Result<serde_json::Value, tera::Error> {
+    let mut parameters = String::default();
+    // order is edits, name or update (the last one to change the page first)
+    for name in ["order", "limit"] {
+        if let Some(value) = parameter(args, name)? {
+            parameters.push_str(&format!(
+                r#"<ac:parameter ac:name="{}">{}</ac:parameter>"#,
+                name, value
+            ));
+        }
+    }
+    Ok(serde_json::to_value(format!(
+        r#"<ac:structured-macro ac:name="contributors" ac:schema-version="1">{}</ac:structured-macro>"#,
+        parameters
+    ))
+    .unwrap())
+}
+
+/// The Analytics macro, which only Confluence Cloud Premium and Enterprise have.
+fn page_views(
+    _args: &HashMap<String, serde_json::Value>,
+) -> std::result::Result<serde_json::Value, tera::Error> {
+    Ok(
+        serde_json::to_value(
+            r#"<ac:structured-macro ac:name="analytics" ac:schema-version="1" />"#,
+        )
+        .unwrap(),
+    )
+}
+
+/// A date lozenge, `value` is a date like 2024-09-01.
+fn date(
+    args: &HashMap<String, serde_json::Value>,
+) -> std::result::Result<serde_json::Value, tera::Error> {
+    let value = args
+        .get("value")
+        .and_then(serde_json::Value::as_str)
+        .ok_or("Missing required argument 'value'")?;
+    let date = NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").map_err(|_| {
+        tera::Error::msg(format!(
+            "value needs to be a date like 2024-09-01, not \"{}\"",
+            value
+        ))
+    })?;
+    Ok(serde_json::to_value(format!(
+        r#"<time datetime="{}" />"#,
+        date.format("%Y-%m-%d")
+    ))
+    .unwrap())
+}
+
+/// The Team Calendars macro, `id` is the id of a calendar or a list of them.
+fn calendar(
+    args: &HashMap<String, serde_json::Value>,
+) -> std::result::Result<serde_json::Value, tera::Error> {
+    let ids = match args.get("id") {
+        Some(serde_json::Value::String(id)) => tera::escape_html(id),
+        Some(serde_json::Value::Array(ids)) if !ids.is_empty() => ids
+            .iter()
+            .map(|id| id.as_str().map(tera::escape_html))
+            .collect::<Option<Vec<String>>>()
+            .ok_or("id needs to be a string or array of strings")?
+            .join(","),
+        _ => Err(tera::Error::msg(
+            "Missing required argument 'id' (a calendar id or a list of them)",
+        ))?,
+    };
+    Ok(serde_json::to_value(format!(
+        r#"<ac:structured-macro ac:name="calendar" ac:schema-version="1"><ac:parameter ac:name="id">{}</ac:parameter></ac:structured-macro>"#,
+        ids
+    ))
+    .unwrap())
+}
+
 const PROPERTIES_TABLE: &str = r###"{% macro properties(metadata) -%}
 <ac:structured-macro ac:name="details" ac:schema-version="1" data-layout="default" ac:local-id="779bc5f9-b8c3-41df-bccc-1840efc20a80" ac:macro-id="4008e080-6218-49a8-82f8-1387005d53d2"><ac:rich-text-body >
 <table><tbody>
@@ -90,11 +208,17 @@ const PROPERTIES_TABLE: &str = r###"{% macro properties(metadata) -%}
 {%- endmacro %}
 "###;
 
-pub(crate) fn add_builtins(tera: &mut Tera) -> Result<()> {
-    tera.register_function("hello_world", hello_world);
-    tera.register_function("toc", toc);
-    tera.register_function("children", children);
-    tera.register_function("labellist", labellist);
+pub(crate) fn add_builtins(tera: &mut Tera, registry: &mut MacroRegistry) -> Result<()> {
+    registry.register("hello_world", hello_world);
+    registry.register("toc", toc);
+    registry.register("children", children);
+    registry.register("labellist", labellist);
+    registry.register("qr", qr);
+    registry.register("recently_updated", recently_updated);
+    registry.register("contributors", contributors);
+    registry.register("page_views", page_views);
+    registry.register("date", date);
+    registry.register("calendar", calendar);
     tera.add_raw_template("_tera/builtins", PROPERTIES_TABLE)?;
 
     Ok(())
@@ -104,7 +228,6 @@ pub(crate) fn add_builtins(tera: &mut Tera) -> Result<()> {
 mod test {
     use std::collections::HashMap;
 
-    use comrak::{nodes::AstNode, Arena};
     use scraper::{Html, Selector};
 
     use crate::{
@@ -116,8 +239,7 @@ mod test {
     };
 
     fn test_render(markdown_content: &str) -> Result<RenderedPage> {
-        let arena = Arena::<AstNode>::new();
-        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let page = page_from_str("page.md", markdown_content)?;
         page.render(&LinkGenerator::default_test())
     }
 
@@ -146,9 +268,8 @@ mod test {
 
     #[test]
     fn it_renders_predefined_functions() -> TestResult {
-        let arena = Arena::<AstNode>::new();
         let markdown_content = "# compulsory title\n{{hello_world()}}";
-        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let page = page_from_str("page.md", markdown_content)?;
 
         let rendered_page = page.render(&LinkGenerator::default_test())?;
 
@@ -158,10 +279,9 @@ mod test {
 
     #[test]
     fn it_renders_builtins() -> TestResult {
-        let arena = Arena::<AstNode>::new();
         let markdown_content = "# compulsory title\n{{hello_world(name=\"world!\")}}";
 
-        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let page = page_from_str("page.md", markdown_content)?;
         let rendered_page = page.render(&LinkGenerator::default_test())?;
 
         assert_eq!(rendered_page.content.trim(), "<p><em>hello world!</em></p>");
@@ -190,6 +310,46 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn it_renders_dashboard_macros() -> TestResult {
+        let rendered_page = test_render(
+            "# compulsory title\n{{ recently_updated(spaces=[\"TEAM\", \"OPS\"], max=5) }}\n\n{{ contributors(order=\"update\", limit=1) }}\n\n{{ recently_updated() }}",
+        )?;
+
+        assert_eq!(
+            rendered_page.content.trim(),
+            concat!(
+                r#"<p><ac:structured-macro ac:name="recently-updated" ac:schema-version="1"><ac:parameter ac:name="spaces">TEAM,OPS</ac:parameter><ac:parameter ac:name="max">5</ac:parameter></ac:structured-macro></p>"#,
+                "\n",
+                r#"<p><ac:structured-macro ac:name="contributors" ac:schema-version="1"><ac:parameter ac:name="order">update</ac:parameter><ac:parameter ac:name="limit">1</ac:parameter></ac:structured-macro></p>"#,
+                "\n",
+                r#"<p><ac:structured-macro ac:name="recently-updated" ac:schema-version="1"><ac:parameter ac:name="spaces">@self</ac:parameter><ac:parameter ac:name="max">10</ac:parameter></ac:structured-macro></p>"#
+            )
+        );
+        assert!(test_render("# compulsory title\n{{ recently_updated(spaces=[]) }}").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_renders_date_lozenges_and_calendars() -> TestResult {
+        let rendered_page = test_render(
+            "# compulsory title\nShips on {{ date(value=\"2024-09-01\") }}.\n\n{{ calendar(id=[\"abc\", \"def\"]) }}",
+        )?;
+
+        assert_eq!(
+            rendered_page.content.trim(),
+            concat!(
+                r#"<p>Ships on <time datetime="2024-09-01" />.</p>"#,
+                "\n",
+                r#"<p><ac:structured-macro ac:name="calendar" ac:schema-version="1"><ac:parameter ac:name="id">abc,def</ac:parameter></ac:structured-macro></p>"#
+            )
+        );
+        assert!(test_render("# compulsory title\n{{ date(value=\"1 September\") }}").is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn properties_report_defaults_to_current_space() -> TestResult {
         let rendered_page =
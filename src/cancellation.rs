@@ -0,0 +1,103 @@
+//! Stopping a sync with Ctrl-C without losing track of what it did.
+//!
+//! The first Ctrl-C lets the request in flight finish (it's bounded by `--timeout`), then the sync
+//! stops before the next page, saves the page map, writes the reports and lists the pages it
+//! didn't get to. Those are synced by the next run. A second Ctrl-C quits immediately.
+//!
+//! Only sync and watch stop between pages, so the handler is only installed for them. The other
+//! commands keep the default of quitting on the first Ctrl-C.
+use std::{
+    process,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    console::{print_status, print_warning, Status},
+    error::{ConfluenceError, Result},
+    markdown_page::MarkdownPage,
+};
+
+/// Whether Ctrl-C was pressed.
+struct Flag(AtomicBool);
+
+impl Flag {
+    const fn new() -> Self {
+        Flag(AtomicBool::new(false))
+    }
+
+    /// Sets the flag, and returns whether it was set already.
+    fn cancel(&self) -> bool {
+        self.0.swap(true, Ordering::SeqCst)
+    }
+
+    fn is_set(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    #[cfg(test)]
+    fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst)
+    }
+}
+
+static CANCELLED: Flag = Flag::new();
+
+/// The exit code of a process stopped by Ctrl-C.
+const INTERRUPTED: i32 = 130;
+
+pub fn install_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if CANCELLED.cancel() {
+            process::exit(INTERRUPTED);
+        }
+        print_warning("Stopping after the current request, press Ctrl-C again to quit right away");
+    })?;
+    Ok(())
+}
+
+pub fn is_cancelled() -> bool {
+    CANCELLED.is_set()
+}
+
+/// Fails when the sync was cancelled, for the steps before any page is synced.
+pub fn check() -> Result<()> {
+    if is_cancelled() {
+        return Err(ConfluenceError::generic_error("Cancelled"));
+    }
+    Ok(())
+}
+
+/// Lists the pages a cancelled sync didn't get to.
+pub fn print_remaining(synced: usize, remaining: &[MarkdownPage]) {
+    print_warning(&format!(
+        "Cancelled after syncing {} of {} pages, not synced:",
+        synced,
+        synced + remaining.len()
+    ));
+    for markdown_page in remaining {
+        print_status(
+            Status::Skipped,
+            &format!("[{}] \"{}\"", markdown_page.source, markdown_page.title),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_quits_on_the_second_cancel() {
+        // a flag of its own, the global one would stop the syncs of other tests
+        let flag = Flag::new();
+        assert!(!flag.is_set());
+
+        assert!(!flag.cancel(), "the first Ctrl-C only stops the sync");
+        assert!(flag.is_set());
+        assert!(flag.cancel(), "the second Ctrl-C quits");
+
+        flag.reset();
+        assert!(!flag.is_set());
+        assert!(!flag.cancel());
+    }
+}
@@ -0,0 +1,145 @@
+use std::{path::Path, process::Command};
+
+use tera::escape_html;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    console::{print_status, Status},
+    error::{ConfluenceError, Result},
+};
+
+/// What git knows about the last change to a markdown file.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct GitChange {
+    author: String,
+    subject: String,
+    stats: Option<String>,
+}
+
+fn parse_git_log(output: &str) -> Option<GitChange> {
+    let mut lines = output.lines();
+    let (author, subject) = lines.next()?.split_once('\x1f')?;
+    let stats = lines
+        .map(str::trim)
+        .find(|line| line.starts_with(|c: char| c.is_ascii_digit()))
+        .map(String::from);
+    Some(GitChange {
+        author: String::from(author),
+        subject: String::from(subject),
+        stats,
+    })
+}
+
+fn last_git_change(space_dir: &Path, source: &str) -> Option<GitChange> {
+    let output = Command::new("git")
+        .current_dir(space_dir)
+        .args([
+            "log",
+            "-1",
+            "--shortstat",
+            "--format=%an%x1f%s",
+            "--",
+            source,
+        ])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_git_log(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn comment_body(source: &str, change: Option<GitChange>) -> String {
+    let mut body = format!(
+        "<p>Updated by marked-space from <code>{}</code>.</p>",
+        escape_html(source)
+    );
+    if let Some(change) = change {
+        body += &format!(
+            "<p><strong>Commit:</strong> {}</p><p><strong>Author:</strong> {}</p>",
+            escape_html(&change.subject),
+            escape_html(&change.author)
+        );
+        if let Some(stats) = change.stats {
+            body += &format!("<p><strong>Changes:</strong> {}</p>", escape_html(&stats));
+        }
+    }
+    body
+}
+
+/// Posts a footer comment on the page summarizing the last git commit to its source file, so
+/// watchers get some context along with the version notification.
+pub fn post_change_comment(
+    confluence_client: &ConfluenceClient,
+    page_id: &str,
+    space_dir: &Path,
+    source: &str,
+) -> Result<()> {
+    let body = comment_body(source, last_git_change(space_dir, source));
+    let response = confluence_client.create_footer_comment(page_id, &body)?;
+    if !response.status().is_success() {
+        return Err(ConfluenceError::failed_request(response));
+    }
+    print_status(Status::Created, "change comment");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_git_log_output() {
+        let change = parse_git_log(
+            "Jane Doe\x1fFix the <runbook>\n\n 1 file changed, 3 insertions(+), 1 deletion(-)\n",
+        );
+
+        assert_eq!(
+            change,
+            Some(GitChange {
+                author: String::from("Jane Doe"),
+                subject: String::from("Fix the <runbook>"),
+                stats: Some(String::from(
+                    "1 file changed, 3 insertions(+), 1 deletion(-)"
+                )),
+            })
+        );
+        assert_eq!(parse_git_log(""), None);
+    }
+
+    #[test]
+    fn it_escapes_the_comment_body() {
+        let body = comment_body(
+            "runbooks/db.md",
+            Some(GitChange {
+                author: String::from("Jane Doe"),
+                subject: String::from("Fix the <runbook>"),
+                stats: None,
+            }),
+        );
+
+        assert_eq!(
+            body,
+            "<p>Updated by marked-space from <code>runbooks&#x2F;db.md</code>.</p><p><strong>Commit:</strong> Fix the &lt;runbook&gt;</p><p><strong>Author:</strong> Jane Doe</p>"
+        );
+    }
+
+    #[test]
+    fn it_posts_a_footer_comment() -> crate::error::TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let mock = server
+            .mock("POST", "/wiki/api/v2/footer-comments")
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"pageId": "42", "body": {"representation": "storage"}}"#.into(),
+            ))
+            .with_status(200)
+            .create();
+
+        let temp = assert_fs::TempDir::new()?;
+        post_change_comment(&client, "42", temp.path(), "page.md")?;
+
+        mock.assert();
+        Ok(())
+    }
+}
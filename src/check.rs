@@ -0,0 +1,261 @@
+//! The `check` subcommand: everything that would fail or warn in a sync, found without Confluence
+//! and without credentials, so pull requests can be gated on it. Every page is parsed and
+//! rendered, with made-up ids like `test-templates`, and the problems of all pages are reported at
+//! once: pages that don't parse, duplicate titles, links to files and attachments that don't
+//! exist, and the warnings of the pages (unknown front matter keys, long titles, ...).
+//!
+//! Pages a sync holds back until their `publish_after` time are listed too. With `--json` only the
+//! summary is printed, as JSON:
+//!
+//! ```json
+//! {"space": "TEAM", "pages": 12, "errors": 1, "warnings": 1, "problems": [
+//!   {"severity": "error", "code": "broken-link", "file": "guides/setup.md", "message": "..."},
+//!   {"severity": "warning", "code": "MS001", "file": "index.md", "message": "..."}
+//! ], "scheduled": [{"file": "release-notes.md", "publish_after": "2024-07-01T09:00:00Z"}]}
+//! ```
+use anyhow::anyhow;
+use chrono::SecondsFormat;
+use serde::Serialize;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    console::{print_info, print_status, Status},
+    error::{ConfluenceError, Result},
+    markdown_page::MarkdownPage,
+    markdown_space::MarkdownSpace,
+    parallel::map,
+    sync::load_space_offline_with_errors,
+    Args,
+};
+
+#[derive(Serialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Problem {
+    pub severity: Severity,
+    /// What kind of problem it is, the code of a warning (e.g. `MS001`) or the kind of error.
+    pub code: String,
+    /// The file with the problem, relative to the space, when it's known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    pub message: String,
+}
+
+impl Problem {
+    fn from_error(err: &anyhow::Error) -> Problem {
+        let (code, file) = match err.downcast_ref::<ConfluenceError>() {
+            Some(ConfluenceError::DuplicateTitle { file, .. }) => ("duplicate-title", Some(file)),
+            Some(ConfluenceError::MissingFileLink { source_file, .. }) => {
+                ("broken-link", Some(source_file))
+            }
+            Some(ConfluenceError::MissingAttachmentLink { source_file, .. }) => {
+                ("missing-attachment", Some(source_file))
+            }
+            _ => ("parse-error", None),
+        };
+        Problem {
+            severity: Severity::Error,
+            code: String::from(code),
+            file: file.map(|file| file.replace('\\', "/")),
+            message: format!("{:#}", err),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct CheckSummary {
+    pub space: String,
+    /// The pages that parsed.
+    pub pages: usize,
+    pub errors: usize,
+    pub warnings: usize,
+    pub problems: Vec<Problem>,
+    /// The pages a sync holds back until their `publish_after` time.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub scheduled: Vec<ScheduledPage>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct ScheduledPage {
+    pub file: String,
+    /// In UTC, like `2024-07-01T09:00:00Z`.
+    pub publish_after: String,
+}
+
+/// Checks the space, collecting the problems of every page instead of stopping at the first one.
+pub fn check_space(
+    confluence_client: &ConfluenceClient,
+    markdown_space: &mut MarkdownSpace,
+    args: &Args,
+) -> Result<CheckSummary> {
+    let mut errors = Vec::default();
+    let loaded_space =
+        load_space_offline_with_errors(confluence_client, markdown_space, args, Some(&mut errors))?;
+    let mut problems: Vec<Problem> = errors.iter().map(Problem::from_error).collect();
+
+    let pages: Vec<&MarkdownPage> = loaded_space
+        .markdown_pages
+        .iter()
+        .chain(loaded_space.confluence_templates.iter())
+        .collect();
+    for page in &pages {
+        problems.extend(page.warnings.iter().map(|warning| Problem {
+            severity: Severity::Warning,
+            code: String::from(warning.code.code()),
+            file: Some(page.source.replace('\\', "/")),
+            message: warning.message.clone(),
+        }));
+    }
+    problems.extend(loaded_space.warnings.iter().map(|warning| Problem {
+        severity: Severity::Warning,
+        code: String::from(warning.code.code()),
+        file: None,
+        message: warning.message.clone(),
+    }));
+    let renderable: Vec<&MarkdownPage> = pages
+        .iter()
+        .copied()
+        .filter(|page| !page.is_folder())
+        .collect();
+    let results = map(&renderable, |page| {
+        page.render(&loaded_space.link_generator)
+    });
+    for (page, result) in renderable.iter().zip(results) {
+        if let Err(err) = result {
+            problems.push(Problem {
+                severity: Severity::Error,
+                code: String::from("render-error"),
+                file: Some(page.source.replace('\\', "/")),
+                message: format!("{:#}", err),
+            });
+        }
+    }
+
+    let errors = problems
+        .iter()
+        .filter(|problem| problem.severity == Severity::Error)
+        .count();
+    let scheduled = loaded_space
+        .markdown_pages
+        .iter()
+        .filter_map(|page| {
+            Some(ScheduledPage {
+                file: page.source.replace('\\', "/"),
+                publish_after: loaded_space
+                    .schedule
+                    .publish_after(&page.source)?
+                    .to_rfc3339_opts(SecondsFormat::Secs, true),
+            })
+        })
+        .collect();
+    Ok(CheckSummary {
+        space: markdown_space.key.clone(),
+        pages: loaded_space.markdown_pages.len(),
+        errors,
+        warnings: problems.len() - errors,
+        problems,
+        scheduled,
+    })
+}
+
+pub fn check(
+    confluence_client: ConfluenceClient,
+    markdown_space: &mut MarkdownSpace,
+    args: Args,
+    json: bool,
+) -> Result<()> {
+    let summary = check_space(&confluence_client, markdown_space, &args)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+    } else {
+        // the warnings were printed while parsing
+        for problem in &summary.problems {
+            if problem.severity == Severity::Error {
+                print_status(Status::Error, &problem.message);
+            }
+        }
+        for page in &summary.scheduled {
+            print_status(
+                Status::Scheduled,
+                &format!("[{}] until {}", page.file, page.publish_after),
+            );
+        }
+        print_info(&format!(
+            "Checked {} page(s): {} error(s), {} warning(s)",
+            summary.pages, summary.errors, summary.warnings
+        ));
+    }
+    if summary.errors > 0 || (args.strict && summary.warnings > 0) {
+        return Err(anyhow!(
+            "Check failed with {} error(s) and {} warning(s)",
+            summary.errors,
+            summary.warnings
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::fixture::{FileWriteStr, PathChild, PathCreateDir};
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn it_reports_the_problems_of_every_page() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("test/index.md")
+            .write_str("# Home\n\nSee [setup](setup.md).\n")?;
+        temp.child("test/guide.md")
+            .write_str("# Guide\n\n![Diagram](diagram.png)\n")?;
+        temp.child("test/faq.md")
+            .write_str("---\nownr: jane\n---\n# FAQ\n")?;
+        temp.child("test/guides/index.md").write_str("# FAQ\n")?;
+        temp.child("test/launch.md")
+            .write_str("---\npublish_after: 2099-01-01T09:00Z\n---\n# Launch\n")?;
+        temp.child("test/drafts").create_dir_all()?;
+        let mut markdown_space = MarkdownSpace::from_directory(temp.child("test").path())?;
+        let client = ConfluenceClient::new("example.atlassian.net");
+
+        let summary = check_space(&client, &mut markdown_space, &Args::default())?;
+
+        let problems: Vec<(Severity, &str, Option<&str>)> = summary
+            .problems
+            .iter()
+            .map(|problem| {
+                (
+                    problem.severity,
+                    problem.code.as_str(),
+                    problem.file.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            problems,
+            vec![
+                (Severity::Error, "missing-attachment", Some("guide.md")),
+                (Severity::Error, "duplicate-title", Some("guides/index.md")),
+                (Severity::Error, "broken-link", Some("index.md")),
+                (Severity::Warning, "MS001", Some("faq.md")),
+                (Severity::Warning, "MS021", None),
+            ]
+        );
+        assert_eq!((summary.pages, summary.errors, summary.warnings), (2, 3, 2));
+        assert_eq!(
+            serde_json::to_value(&summary.scheduled)?,
+            serde_json::json!([{"file": "launch.md", "publish_after": "2099-01-01T09:00:00Z"}])
+        );
+        assert_eq!(
+            serde_json::to_value(&summary.problems[3])?["severity"],
+            "warning"
+        );
+        Ok(())
+    }
+}
@@ -1,20 +1,287 @@
 use data_encoding::HEXUPPER;
 use ring::digest::{Context, SHA256};
 
-use crate::Result;
+use crate::{error::ConfluenceError, storage_format::normalize, Result};
 use std::io::Read;
 
-pub fn sha256_digest<R: Read>(mut reader: R) -> Result<String> {
-    let mut context = Context::new(&SHA256);
-    let mut buffer = [0; 1024];
+/// A hash function checksums of pages and attachments are computed with.
+pub trait Checksum {
+    fn update(&mut self, data: &[u8]);
+    fn finish(self: Box<Self>) -> String;
+}
+
+struct Sha256(Context);
+
+impl Checksum for Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finish(self: Box<Self>) -> String {
+        HEXUPPER.encode(self.0.finish().as_ref())
+    }
+}
+
+const PRIME64_1: u64 = 0x9E3779B185EBCA87;
+const PRIME64_2: u64 = 0xC2B2AE3D27D4EB4F;
+const PRIME64_3: u64 = 0x165667B19E3779F9;
+const PRIME64_4: u64 = 0x85EBCA77C2B2AE63;
+const PRIME64_5: u64 = 0x27D4EB2F165667C5;
+
+/// XXH64 with a seed of 0, much faster than sha256 and good enough to detect changes.
+#[derive(Default)]
+struct Xxh64 {
+    accumulators: Option<[u64; 4]>,
+    buffer: Vec<u8>,
+    length: u64,
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap_or_default())
+}
+
+fn xxh64_round(accumulator: u64, input: u64) -> u64 {
+    accumulator
+        .wrapping_add(input.wrapping_mul(PRIME64_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME64_1)
+}
+
+fn xxh64_merge(hash: u64, accumulator: u64) -> u64 {
+    (hash ^ xxh64_round(0, accumulator))
+        .wrapping_mul(PRIME64_1)
+        .wrapping_add(PRIME64_4)
+}
+
+impl Checksum for Xxh64 {
+    fn update(&mut self, data: &[u8]) {
+        self.length += data.len() as u64;
+        self.buffer.extend_from_slice(data);
+        let stripes = self.buffer.len() / 32;
+        if stripes == 0 {
+            return;
+        }
+        let accumulators = self.accumulators.get_or_insert([
+            PRIME64_1.wrapping_add(PRIME64_2),
+            PRIME64_2,
+            0,
+            0u64.wrapping_sub(PRIME64_1),
+        ]);
+        for stripe in self.buffer[..stripes * 32].chunks_exact(32) {
+            for (accumulator, lane) in accumulators.iter_mut().zip(stripe.chunks_exact(8)) {
+                *accumulator = xxh64_round(*accumulator, read_u64(lane));
+            }
+        }
+        self.buffer.drain(..stripes * 32);
+    }
+
+    fn finish(self: Box<Self>) -> String {
+        let mut hash = match self.accumulators {
+            Some([a, b, c, d]) => {
+                let hash = a
+                    .rotate_left(1)
+                    .wrapping_add(b.rotate_left(7))
+                    .wrapping_add(c.rotate_left(12))
+                    .wrapping_add(d.rotate_left(18));
+                [a, b, c, d].into_iter().fold(hash, xxh64_merge)
+            }
+            None => PRIME64_5,
+        }
+        .wrapping_add(self.length);
+
+        let mut rest = self.buffer.as_slice();
+        while rest.len() >= 8 {
+            hash = (hash ^ xxh64_round(0, read_u64(rest)))
+                .rotate_left(27)
+                .wrapping_mul(PRIME64_1)
+                .wrapping_add(PRIME64_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            let word = u32::from_le_bytes(rest[..4].try_into().unwrap_or_default()) as u64;
+            hash = (hash ^ word.wrapping_mul(PRIME64_1))
+                .rotate_left(23)
+                .wrapping_mul(PRIME64_2)
+                .wrapping_add(PRIME64_3);
+            rest = &rest[4..];
+        }
+        for byte in rest {
+            hash = (hash ^ (*byte as u64).wrapping_mul(PRIME64_5))
+                .rotate_left(11)
+                .wrapping_mul(PRIME64_1);
+        }
+
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(PRIME64_2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(PRIME64_3);
+        hash ^= hash >> 32;
+        format!("{:016X}", hash)
+    }
+}
+
+/// The checksum algorithm of a space, set with the `checksum` key of `_space.yml`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum ChecksumAlgorithm {
+    /// Checksums without a marker are sha256, which is what older versions stored.
+    #[default]
+    Sha256,
+    Xxh64,
+}
+
+impl ChecksumAlgorithm {
+    pub fn from_str(s: Option<&str>) -> Result<ChecksumAlgorithm> {
+        match s {
+            None | Some("sha256") => Ok(ChecksumAlgorithm::Sha256),
+            Some("xxh64") => Ok(ChecksumAlgorithm::Xxh64),
+            Some(other) => Err(ConfluenceError::generic_error(format!(
+                "Invalid checksum \"{}\" (should be sha256 or xxh64)",
+                other
+            ))),
+        }
+    }
 
-    loop {
-        let count = reader.read(&mut buffer)?;
-        if count == 0 {
-            break;
+    fn hasher(&self) -> Box<dyn Checksum> {
+        match self {
+            ChecksumAlgorithm::Sha256 => Box::new(Sha256(Context::new(&SHA256))),
+            ChecksumAlgorithm::Xxh64 => Box::<Xxh64>::default(),
         }
-        context.update(&buffer[..count]);
     }
 
-    Ok(HEXUPPER.encode(context.finish().as_ref()))
+    /// Prefix of the stored value, so checksums of different algorithms never match.
+    fn marker(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "",
+            ChecksumAlgorithm::Xxh64 => "xxh64:",
+        }
+    }
+
+    pub fn digest<R: Read>(&self, mut reader: R) -> Result<String> {
+        let mut hasher = self.hasher();
+        let mut buffer = [0; 1024];
+
+        loop {
+            let count = reader.read(&mut buffer)?;
+            if count == 0 {
+                break;
+            }
+            hasher.update(&buffer[..count]);
+        }
+
+        Ok(format!("{}{}", self.marker(), hasher.finish()))
+    }
+
+    /// The checksum of rendered storage format, after normalizing it so that only changes to the
+    /// content count.
+    pub fn page_checksum(&self, storage: &str) -> Result<String> {
+        self.digest(normalize(storage).as_bytes())
+    }
+
+    /// What older versions stored as the checksum of a page: sha256 of the storage format as is.
+    pub fn legacy_page_checksum(&self, storage: &str) -> Result<Option<String>> {
+        match self {
+            ChecksumAlgorithm::Sha256 => Ok(Some(self.digest(storage.as_bytes())?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+pub fn sha256_digest<R: Read>(reader: R) -> Result<String> {
+    ChecksumAlgorithm::Sha256.digest(reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn it_keeps_sha256_checksums_unmarked() -> TestResult {
+        assert_eq!(
+            sha256_digest("abc".as_bytes())?,
+            "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_computes_xxh64() -> TestResult {
+        let xxh64 = ChecksumAlgorithm::Xxh64;
+
+        assert_eq!(xxh64.digest("".as_bytes())?, "xxh64:EF46DB3751D8E999");
+        assert_eq!(xxh64.digest("abc".as_bytes())?, "xxh64:44BC2CF5AD770999");
+        assert_eq!(
+            xxh64.digest("Nobody inspects the spammish repetition".as_bytes())?,
+            "xxh64:FBCEA83C8A378BF1"
+        );
+        // the sanity check of the reference implementation (cli/xsum_sanity_check.c) hashes
+        // prefixes of a generated buffer
+        let mut byte_gen: u64 = 2654435761;
+        let sanity_buffer: Vec<u8> = (0..222)
+            .map(|_| {
+                let byte = (byte_gen >> 56) as u8;
+                byte_gen = byte_gen.wrapping_mul(11400714785074694797);
+                byte
+            })
+            .collect();
+        for (length, expected) in [
+            (0, "EF46DB3751D8E999"),
+            (1, "E934A84ADB052768"),
+            (14, "8282DCC4994E35C8"),
+            (222, "B641AE8CB691C174"),
+        ] {
+            assert_eq!(
+                xxh64.digest(&sanity_buffer[..length])?,
+                format!("xxh64:{}", expected)
+            );
+        }
+        // the same across reads split at any point
+        let long = "0123456789".repeat(200);
+        let mut hasher = xxh64.hasher();
+        for chunk in long.as_bytes().chunks(7) {
+            hasher.update(chunk);
+        }
+        assert_eq!(
+            format!("xxh64:{}", hasher.finish()),
+            xxh64.digest(long.as_bytes())?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_checksums_normalized_pages() -> TestResult {
+        let sha256 = ChecksumAlgorithm::Sha256;
+        let page = r#"<p><a title="t" href="x">link</a></p>"#;
+
+        assert_eq!(
+            sha256.page_checksum(page)?,
+            sha256.page_checksum(
+                r#"<p>
+  <a href="x" title="t">link</a>
+</p>"#
+            )?
+        );
+        assert_eq!(
+            sha256.legacy_page_checksum(page)?,
+            Some(sha256_digest(page.as_bytes())?)
+        );
+        assert_eq!(ChecksumAlgorithm::Xxh64.legacy_page_checksum(page)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_checksums_whitespace_between_inline_elements() -> TestResult {
+        let sha256 = ChecksumAlgorithm::Sha256;
+
+        assert_ne!(
+            sha256.page_checksum("<p><strong>a</strong> <em>b</em></p>")?,
+            sha256.page_checksum("<p><strong>a</strong><em>b</em></p>")?
+        );
+
+        Ok(())
+    }
 }
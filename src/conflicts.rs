@@ -0,0 +1,225 @@
+//! Pages that were edited in Confluence since marked-space synced them. The sync would overwrite
+//! the edit, so `--on-conflict` decides whether it does that quietly, with a warning, or not at all.
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::anyhow;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    confluence_page::{ConfluenceNode, ConfluencePageData},
+    error::{ApiResponse, Result},
+    page_map::PageMapEntry,
+    page_titles::has_temporary_title,
+    responses::Version,
+    warnings::{Warning, WarningCode, WarningPolicy},
+};
+
+/// What to do with a page that was edited in Confluence since the last sync.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Leave the page as it is and fail it, so the edit can be moved to the markdown first.
+    Fail,
+    /// Overwrite the edit, and warn about it.
+    #[default]
+    Warn,
+    /// Overwrite the edit.
+    Overwrite,
+}
+
+impl Display for ConflictPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConflictPolicy::Fail => write!(f, "fail"),
+            ConflictPolicy::Warn => write!(f, "warn"),
+            ConflictPolicy::Overwrite => write!(f, "overwrite"),
+        }
+    }
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fail" => Ok(ConflictPolicy::Fail),
+            "warn" => Ok(ConflictPolicy::Warn),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            _ => Err(anyhow!("Expected fail, warn or overwrite, got \"{}\"", s)),
+        }
+    }
+}
+
+/// Whether the page was edited in Confluence since the last sync: the page map has the checksum
+/// of a sync, but the latest version wasn't written by marked-space. Pages that were never synced
+/// (made by hand, or adopted) aren't conflicts, the sync takes them over.
+pub fn is_conflict(page_data: &ConfluencePageData, previous: Option<&PageMapEntry>) -> bool {
+    previous.is_some_and(|entry| entry.checksum.is_some()) && !page_data.is_managed()
+}
+
+/// Applies the policy to a page, failing when the page mustn't be overwritten.
+/// Whether the latest version of the page is a temporary rename (see [`crate::page_titles`]) of a
+/// version marked-space wrote. The title of a page can't be changed with a version message, so a
+/// page renamed by a sync that failed before updating it would look edited in Confluence.
+pub fn is_temporary_rename(
+    confluence_client: &ConfluenceClient,
+    node: &ConfluenceNode,
+) -> Result<bool> {
+    let Some(page_data) = node.page_data() else {
+        return Ok(false);
+    };
+    if page_data.is_managed() || !has_temporary_title(node) || page_data.version.number < 2 {
+        return Ok(false);
+    }
+    let renamed = confluence_client
+        .get_page_version(&node.id, page_data.version.number - 1)?
+        .api_result()?
+        .json::<Version>()?;
+    Ok(renamed
+        .message
+        .starts_with(ConfluencePageData::version_message_prefix()))
+}
+
+pub fn check_conflict(
+    policy: ConflictPolicy,
+    warnings: &WarningPolicy,
+    page_data: &ConfluencePageData,
+    previous: Option<&PageMapEntry>,
+    source: &str,
+    title: &str,
+) -> Result<()> {
+    if !is_conflict(page_data, previous) {
+        return Ok(());
+    }
+    match policy {
+        ConflictPolicy::Fail => Err(anyhow!(
+            "\"{}\" was edited in Confluence since the last sync (version {}), not overwriting it. \
+             Copy the edit to the markdown, or pass --on-conflict overwrite",
+            title,
+            page_data.version.number
+        )),
+        ConflictPolicy::Warn => warnings.report(Warning::new(
+            WarningCode::OverwrittenEdit,
+            format!(
+                "[{}] \"{}\" was edited in Confluence since the last sync (version {}), \
+                 overwriting the edit",
+                source, title, page_data.version.number
+            ),
+        )),
+        ConflictPolicy::Overwrite => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        confluence_page::ConfluenceNodeType,
+        error::TestResult,
+        responses::{ContentStatus, Version},
+    };
+
+    use super::*;
+
+    fn page_data(message: &str) -> ConfluencePageData {
+        ConfluencePageData {
+            version: Version {
+                message: String::from(message),
+                number: 7,
+            },
+            path: None,
+            status: ContentStatus::Current,
+        }
+    }
+
+    fn synced(checksum: Option<&str>) -> PageMapEntry {
+        PageMapEntry {
+            id: String::from("42"),
+            checksum: checksum.map(String::from),
+            failed: false,
+        }
+    }
+
+    #[test]
+    fn it_detects_pages_edited_in_confluence() {
+        let edited = page_data("Fixed a typo");
+        let synced_version = page_data("updated by markedspace: source=guide.md; checksum=ABC");
+
+        assert!(is_conflict(&edited, Some(&synced(Some("ABC")))));
+        assert!(!is_conflict(&synced_version, Some(&synced(Some("ABC")))));
+        // adopted, or never synced
+        assert!(!is_conflict(&edited, Some(&synced(None))));
+        assert!(!is_conflict(&edited, None));
+    }
+
+    #[test]
+    fn it_applies_the_policy() {
+        let edited = page_data("");
+        let previous = synced(Some("ABC"));
+        let mut warnings = WarningPolicy::default();
+        let check = |policy, warnings: &WarningPolicy| {
+            check_conflict(
+                policy,
+                warnings,
+                &edited,
+                Some(&previous),
+                "guide.md",
+                "Guide",
+            )
+        };
+
+        assert_eq!(
+            check(ConflictPolicy::Fail, &warnings)
+                .unwrap_err()
+                .to_string(),
+            "\"Guide\" was edited in Confluence since the last sync (version 7), not overwriting \
+             it. Copy the edit to the markdown, or pass --on-conflict overwrite"
+        );
+        assert!(check(ConflictPolicy::Warn, &warnings).is_ok());
+        assert!(check(ConflictPolicy::Overwrite, &warnings).is_ok());
+        warnings.strict = true;
+        assert!(check(ConflictPolicy::Warn, &warnings).is_err());
+        warnings.disabled.insert(WarningCode::OverwrittenEdit);
+        assert!(check(ConflictPolicy::Warn, &warnings).is_ok());
+        assert_eq!(
+            "overwrite".parse::<ConflictPolicy>().unwrap(),
+            ConflictPolicy::Overwrite
+        );
+        assert!("ignore".parse::<ConflictPolicy>().is_err());
+    }
+
+    #[test]
+    fn it_takes_a_temporary_rename_for_marked_space() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let renamed = |id: &str, title: &str| ConfluenceNode {
+            id: String::from(id),
+            title: String::from(title),
+            parent_id: Some(String::from("1")),
+            data: ConfluenceNodeType::Page(page_data("")),
+        };
+        server
+            .mock("GET", "/wiki/rest/api/content/42/version/6")
+            .with_status(200)
+            .with_body(r#"{"number": 6, "message": "updated by markedspace: source=guide.md; checksum=ABC"}"#)
+            .create();
+        server
+            .mock("GET", "/wiki/rest/api/content/43/version/6")
+            .with_status(200)
+            .with_body(r#"{"number": 6, "message": "Fixed a typo"}"#)
+            .create();
+
+        // the sync renamed the page, and failed before updating it
+        assert!(is_temporary_rename(
+            &client,
+            &renamed("42", "Guide (renaming 42)")
+        )?);
+        // edited in Confluence before the rename
+        assert!(!is_temporary_rename(
+            &client,
+            &renamed("43", "FAQ (renaming 43)")
+        )?);
+        // edited in Confluence, without asking what came before
+        assert!(!is_temporary_rename(&client, &renamed("44", "Setup"))?);
+
+        Ok(())
+    }
+}
@@ -1,8 +1,18 @@
 #![allow(dead_code)]
 
-use reqwest::blocking::multipart::{Form, Part};
+use reqwest::blocking::{
+    multipart::{Form, Part},
+    Body, RequestBuilder,
+};
 use serde_json::{json, Value};
-use std::env;
+use std::{env, sync::Arc, time::Duration};
+
+use crate::{
+    api_version::ApiVersion,
+    audit_log::{AuditEntry, AuditLog},
+    http_cache::{buffer, HttpCache},
+    sync_stats::{record_request, record_response},
+};
 
 #[derive(Clone)]
 pub struct ConfluenceClient {
@@ -11,18 +21,52 @@ pub struct ConfluenceClient {
     api_token: String,
     pub hostname: String,
     insecure: bool,
+    audit_log: Option<Arc<AuditLog>>,
+    /// The number of results to ask for per request when reading lists.
+    pub page_size: Option<u16>,
+    api_version: ApiVersion,
+    http_cache: Arc<HttpCache>,
 }
 
 pub type Result = anyhow::Result<reqwest::blocking::Response, reqwest::Error>;
 
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Sends requests like `send`, counting them for the summary at the end of a sync.
+trait SendCounted {
+    fn send_counted(self) -> Result;
+}
+
+impl SendCounted for RequestBuilder {
+    fn send_counted(self) -> Result {
+        let (client, request) = self.build_split();
+        let request = request?;
+        record_request(
+            request
+                .body()
+                .and_then(Body::as_bytes)
+                .map_or(0, <[u8]>::len),
+        );
+        // compressed and chunked responses have no Content-Length, the body is read to count it
+        let (body, response) = buffer(client.execute(request)?)?;
+        record_response(body.len());
+        Ok(response)
+    }
+}
+
 impl ConfluenceClient {
     pub fn new(hostname: &str) -> ConfluenceClient {
         ConfluenceClient {
             api_user: env::var("API_USER").unwrap_or_default(),
             api_token: env::var("API_TOKEN").unwrap_or_default(),
-            client: reqwest::blocking::Client::new(),
+            client: Self::http_client(DEFAULT_CONNECT_TIMEOUT, DEFAULT_TIMEOUT).unwrap_or_default(),
             hostname: String::from(hostname),
             insecure: false,
+            audit_log: None,
+            page_size: None,
+            api_version: ApiVersion::default(),
+            http_cache: Arc::default(),
         }
     }
 
@@ -34,9 +78,81 @@ impl ConfluenceClient {
             client: reqwest::blocking::Client::new(),
             hostname: String::from(hostname),
             insecure: true,
+            audit_log: None,
+            page_size: None,
+            api_version: ApiVersion::default(),
+            http_cache: Arc::default(),
         }
     }
 
+    fn http_client(
+        connect_timeout: Duration,
+        timeout: Duration,
+    ) -> reqwest::Result<reqwest::blocking::Client> {
+        reqwest::blocking::Client::builder()
+            .connect_timeout(connect_timeout)
+            .timeout(timeout)
+            .build()
+    }
+
+    /// Gives up on connecting after `connect_timeout`, and on requests that take longer than
+    /// `timeout` altogether (including uploads), so a hung request doesn't stall a sync.
+    pub fn with_timeouts(
+        mut self,
+        connect_timeout: Duration,
+        timeout: Duration,
+    ) -> reqwest::Result<ConfluenceClient> {
+        self.client = Self::http_client(connect_timeout, timeout)?;
+        Ok(self)
+    }
+
+    /// Records every change the client makes in the audit log.
+    pub fn with_audit_log(mut self, audit_log: Arc<AuditLog>) -> ConfluenceClient {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    /// Asks for `page_size` results per request when reading lists, instead of Confluence's default.
+    pub fn with_page_size(mut self, page_size: Option<u16>) -> ConfluenceClient {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Talks to another version of the versioned API than the default one.
+    pub fn with_api_version(mut self, api_version: ApiVersion) -> ConfluenceClient {
+        self.api_version = api_version;
+        self
+    }
+
+    /// The `limit` query of list requests, empty for Confluence's default.
+    fn page_size_query(&self) -> Vec<(&'static str, String)> {
+        self.page_size
+            .map(|limit| vec![("limit", limit.to_string())])
+            .unwrap_or_default()
+    }
+
+    /// Keeps the responses to GET requests in `http_cache` instead of only for the run.
+    pub fn with_http_cache(mut self, http_cache: Arc<HttpCache>) -> ConfluenceClient {
+        self.http_cache = http_cache;
+        self
+    }
+
+    fn send_conditionally(&self, request: reqwest::blocking::RequestBuilder) -> Result {
+        self.http_cache.send(&self.api_user, request)
+    }
+
+    fn audited(&self, entry: AuditEntry, result: Result) -> Result {
+        match &self.audit_log {
+            Some(audit_log) => audit_log.record(&self.api_user, entry, result),
+            None => result,
+        }
+    }
+
+    /// Confluence Cloud sites are all under atlassian.net, Server and Data Center are self-hosted.
+    pub fn is_cloud(&self) -> bool {
+        self.hostname.ends_with(".atlassian.net")
+    }
+
     fn rest_api(&self, p: &str) -> String {
         format!(
             "{}://{}/wiki/rest/api/{}",
@@ -48,9 +164,10 @@ impl ConfluenceClient {
 
     fn rest_api_v2(&self, p: &str) -> String {
         format!(
-            "{}://{}/wiki/api/v2/{}",
+            "{}://{}/{}/{}",
             if self.insecure { "http" } else { "https" },
             self.hostname,
+            self.api_version.base_path(),
             p
         )
     }
@@ -64,96 +181,157 @@ impl ConfluenceClient {
     }
 
     pub fn get_space_by_key(&self, space_key: &str) -> Result {
-        let url = format!("https://{}/wiki/api/v2/spaces", self.hostname);
-        self.client
-            .get(url)
-            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
-            .header("Accept", "application/json")
-            .query(&[("keys", space_key)])
-            .send()
+        let url = self.rest_api_v2("spaces");
+        self.send_conditionally(
+            self.client
+                .get(url)
+                .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+                .header("Accept", "application/json")
+                .query(&[("keys", space_key)]),
+        )
     }
 
     pub fn create_page(&self, body_json: Value) -> Result {
-        let url = format!("https://{}/wiki/api/v2/pages", self.hostname);
-        self.client
+        let url = self.rest_api_v2("pages");
+        let result = self
+            .client
             .post(url)
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
             .json(&body_json)
-            .send()
+            .send_counted();
+        self.audited(
+            AuditEntry::new("create_page").name(body_json["title"].as_str()),
+            result,
+        )
+    }
+
+    /// The current pages of the space with this title, read fresh rather than from the cache.
+    pub(crate) fn get_pages_by_title(&self, space_id: &str, title: &str) -> Result {
+        let url = self.rest_api_v2("pages");
+
+        self.client
+            .get(url)
+            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+            .query(&[
+                ("space-id", space_id),
+                ("title", title),
+                ("status", "current"),
+            ])
+            .header("Accept", "application/json")
+            .send_counted()
     }
 
     pub(crate) fn create_folder(&self, body_json: Value) -> Result {
         let url = self.rest_api_v2("folders");
-        self.client
+        let result = self
+            .client
             .post(url)
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
             .json(&body_json)
-            .send()
+            .send_counted();
+        self.audited(
+            AuditEntry::new("create_folder").name(body_json["title"].as_str()),
+            result,
+        )
     }
 
     pub fn get(&self, url: &reqwest::Url) -> Result {
-        self.client
-            .get(url.clone())
-            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
-            .header("Accept", "application/json")
-            .send()
+        self.send_conditionally(
+            self.client
+                .get(url.clone())
+                .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+                .header("Accept", "application/json"),
+        )
     }
 
     pub fn get_all_pages_in_space(&self, space_id: &str) -> Result {
-        let url = format!(
-            "https://{}/wiki/api/v2/spaces/{}/pages",
-            self.hostname, space_id
-        );
-
-        self.client
-            .get(url)
-            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
-            .header("Accept", "application/json")
-            .send()
+        let url = self.rest_api_v2(&format!("spaces/{}/pages", space_id));
+
+        self.send_conditionally(
+            self.client
+                .get(url)
+                .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+                .query(&self.page_size_query())
+                .header("Accept", "application/json"),
+        )
     }
 
     pub fn get_all_pages_from_homepage(&self, homepage_id: &str) -> Result {
         let url = self.rest_api_v2(&format!("pages/{}/descendants", homepage_id));
 
+        self.send_conditionally(
+            self.client
+                .get(url)
+                .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+                .query(&[("limit", "1")])
+                .header("Accept", "application/json"),
+        )
+    }
+
+    pub(crate) fn get_label(&self, name: &str) -> Result {
+        let url = self.rest_api("label");
+
         self.client
             .get(url)
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
-            .query(&[("limit", "1")])
+            .query(&[("name", name)])
             .header("Accept", "application/json")
-            .send()
+            .send_counted()
+    }
+
+    pub(crate) fn get_pages_with_label(&self, label_id: &str, space_id: &str) -> Result {
+        let url = self.rest_api_v2(&format!("labels/{}/pages", label_id));
+
+        self.send_conditionally(
+            self.client
+                .get(url)
+                .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+                .query(&[("space-id", space_id)])
+                .query(&self.page_size_query())
+                .header("Accept", "application/json"),
+        )
     }
 
     pub(crate) fn get_folder_descendants(&self, page_id: String) -> Result {
         let url = self.rest_api_v2(&format!("folders/{}/descendants", page_id));
 
-        self.client
-            .get(url)
-            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
-            .query(&[("depth", "1")])
-            .header("Accept", "application/json")
-            .send()
+        self.send_conditionally(
+            self.client
+                .get(url)
+                .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+                .query(&[("depth", "1")])
+                .header("Accept", "application/json"),
+        )
     }
 
     pub(crate) fn get_page_descendants(&self, page_id: String) -> Result {
         let url = self.rest_api_v2(&format!("pages/{}/descendants", page_id));
 
-        self.client
-            .get(url)
-            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
-            .query(&[("depth", "1")])
-            .header("Accept", "application/json")
-            .send()
+        self.send_conditionally(
+            self.client
+                .get(url)
+                .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+                .query(&[("depth", "1")])
+                .header("Accept", "application/json"),
+        )
     }
 
     pub fn update_page(&self, page_id: &String, payload: Value) -> Result {
-        let url = format!("https://{}/wiki/api/v2/pages/{}", self.hostname, page_id);
-        // let url = format!("https://{}/wiki/api/content/{}", self.hostname, page_id);
-        self.client
+        let url = self.rest_api_v2(&format!("pages/{}", page_id));
+        let result = self
+            .client
             .put(url)
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
             .header("Accept", "application/json")
             .json(&payload)
-            .send()
+            .send_counted();
+        self.audited(
+            AuditEntry::new("update_page")
+                .page(page_id)
+                .name(payload["title"].as_str())
+                .version(payload["version"]["number"].as_u64()),
+            result,
+        )
     }
 
     pub fn create_or_update_attachment(
@@ -162,147 +340,238 @@ impl ConfluenceClient {
         file_part: Part,
         hash: &String,
     ) -> Result {
-        let url = format!(
-            "https://{}/wiki/rest/api/content/{}/child/attachment",
-            self.hostname, content_id
-        );
+        let url = self.rest_api(&format!("content/{}/child/attachment", content_id));
         let form = Form::new()
             .text("minorEdit", "true")
             .text("comment", format!("hash:{}", hash))
             .part("file", file_part);
 
-        self.client
+        let result = self
+            .client
             .put(url)
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
             .header("Accept", "application/json")
             .header("X-Atlassian-Token", "nocheck")
             .multipart(form)
-            .send()
+            .send_counted();
+        self.audited(
+            AuditEntry::new("update_attachment").page(content_id),
+            result,
+        )
+    }
+
+    pub(crate) fn get_page(&self, page_id: &str) -> Result {
+        let url = self.rest_api_v2(&format!("pages/{}", page_id));
+
+        self.send_conditionally(
+            self.client
+                .get(url)
+                .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+                .header("Accept", "application/json"),
+        )
+    }
+
+    /// The pages with these ids, as many at once as there are ids (up to 250).
+    pub(crate) fn get_pages_by_id(&self, page_ids: &[&str]) -> Result {
+        let url = self.rest_api_v2("pages");
+
+        self.send_conditionally(
+            self.client
+                .get(url)
+                .query(&[
+                    ("id", page_ids.join(",")),
+                    ("limit", page_ids.len().to_string()),
+                ])
+                .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+                .header("Accept", "application/json"),
+        )
+    }
+
+    pub(crate) fn get_page_with_body(&self, page_id: &str) -> Result {
+        let url = self.rest_api_v2(&format!("pages/{}", page_id));
+
+        self.send_conditionally(
+            self.client
+                .get(url)
+                .query(&[("body-format", "storage")])
+                .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+                .header("Accept", "application/json"),
+        )
     }
 
     pub fn get_attachments(&self, page_id: &str) -> Result {
+        let url = self.rest_api_v2(&format!("pages/{}/attachments", page_id));
+
+        self.send_conditionally(
+            self.client
+                .get(url)
+                .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+                .query(&self.page_size_query())
+                .header("Accept", "application/json"),
+        )
+    }
+
+    /// The file of an attachment, by the download link relative to the wiki base.
+    pub(crate) fn download_attachment(&self, download_link: &str) -> Result {
         let url = format!(
-            "https://{}/wiki/api/v2/pages/{}/attachments",
-            self.hostname, page_id
+            "{}://{}/wiki{}",
+            if self.insecure { "http" } else { "https" },
+            self.hostname,
+            download_link
         );
 
         self.client
             .get(url)
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
-            .header("Accept", "application/json")
-            .send()
+            .send_counted()
     }
 
     pub(crate) fn remove_attachment(&self, id: &str) -> Result {
-        let url = format!("https://{}/wiki/api/v2/attachments/{}", self.hostname, id);
+        let url = self.rest_api_v2(&format!("attachments/{}", id));
 
-        self.client
+        let result = self
+            .client
             .delete(url)
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
             .header("Accept", "application/json")
-            .send()
+            .send_counted();
+        self.audited(AuditEntry::new("remove_attachment").target(id), result)
     }
 
     pub(crate) fn get_page_labels(&self, page_id: &str) -> Result {
-        let url = format!(
-            "https://{}/wiki/rest/api/content/{}/label",
-            self.hostname, page_id
-        );
+        let url = self.rest_api(&format!("content/{}/label", page_id));
+
+        self.send_conditionally(
+            self.client
+                .get(url)
+                .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+                .header("Accept", "application/json")
+                .header("X-Atlassian-Token", "no-check"),
+        )
+    }
+
+    /// The pages of a space with a label, found with a single CQL search.
+    pub(crate) fn get_labelled_pages(&self, space_key: &str, label: &str) -> Result {
+        let url = self.rest_api("content/search");
 
         self.client
             .get(url)
+            .query(&[
+                (
+                    "cql",
+                    format!(
+                        "type = page and space = \"{}\" and label = \"{}\"",
+                        space_key, label
+                    ),
+                ),
+                ("limit", String::from("100")),
+            ])
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
             .header("Accept", "application/json")
             .header("X-Atlassian-Token", "no-check")
-            .send()
+            .send_counted()
     }
 
     pub(crate) fn set_page_labels(&self, page_id: &str, body: Vec<Value>) -> Result {
-        let url = format!(
-            "https://{}/wiki/rest/api/content/{}/label",
-            self.hostname, page_id
-        );
+        let url = self.rest_api(&format!("content/{}/label", page_id));
 
-        self.client
+        let result = self
+            .client
             .post(url)
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
             .json(&body)
             .header("Accept", "application/json")
             .header("X-Atlassian-Token", "no-check")
-            .send()
+            .send_counted();
+        self.audited(AuditEntry::new("set_page_labels").page(page_id), result)
     }
 
     pub(crate) fn remove_label(&self, page_id: &str, label: &crate::responses::Label) -> Result {
-        let url = format!(
-            "https://{}/wiki/rest/api/content/{}/label",
-            self.hostname, page_id
-        );
+        let url = self.rest_api(&format!("content/{}/label", page_id));
 
-        self.client
+        let result = self
+            .client
             .delete(url)
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
             .query(&[("name", label.name.clone())])
             .header("Accept", "application/json")
             .header("X-Atlassian-Token", "no-check")
-            .send()
+            .send_counted();
+        self.audited(
+            AuditEntry::new("remove_label")
+                .page(page_id)
+                .name(Some(&label.name)),
+            result,
+        )
     }
 
     pub(crate) fn get_properties(&self, page_id: &str) -> Result {
-        let url = format!(
-            "https://{}/wiki/api/v2/pages/{}/properties",
-            self.hostname, page_id
-        );
-
-        self.client
-            .get(url)
-            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
-            .header("Accept", "application/json")
-            .header("X-Atlassian-Token", "no-check")
-            .send()
+        let url = self.rest_api_v2(&format!("pages/{}/properties", page_id));
+
+        self.send_conditionally(
+            self.client
+                .get(url)
+                .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+                .header("Accept", "application/json")
+                .header("X-Atlassian-Token", "no-check"),
+        )
     }
 
     pub(crate) fn create_property(&self, page_id: &str, value: Value) -> Result {
-        let url = format!(
-            "https://{}/wiki/api/v2/pages/{}/properties",
-            self.hostname, page_id
-        );
+        let url = self.rest_api_v2(&format!("pages/{}/properties", page_id));
 
-        self.client
+        let result = self
+            .client
             .post(url)
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
             .header("Accept", "application/json")
             .header("X-Atlassian-Token", "no-check")
             .json(&value)
-            .send()
+            .send_counted();
+        self.audited(
+            AuditEntry::new("create_property")
+                .page(page_id)
+                .name(value["key"].as_str()),
+            result,
+        )
     }
 
     pub(crate) fn set_property(&self, page_id: &str, property_id: &str, value: Value) -> Result {
-        let url = format!(
-            "https://{}/wiki/api/v2/pages/{}/properties/{}",
-            self.hostname, page_id, property_id
-        );
+        let url = self.rest_api_v2(&format!("pages/{}/properties/{}", page_id, property_id));
 
-        self.client
+        let result = self
+            .client
             .put(url)
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
             .header("Accept", "application/json")
             .header("X-Atlassian-Token", "no-check")
             .json(&value)
-            .send()
+            .send_counted();
+        self.audited(
+            AuditEntry::new("update_property")
+                .page(page_id)
+                .target(property_id)
+                .name(value["key"].as_str()),
+            result,
+        )
     }
 
     pub(crate) fn delete_property(&self, page_id: &str, property_id: &str) -> Result {
-        let url = format!(
-            "https://{}/wiki/api/v2/pages/{}/properties/{}",
-            self.hostname, page_id, property_id
-        );
+        let url = self.rest_api_v2(&format!("pages/{}/properties/{}", page_id, property_id));
 
-        self.client
+        let result = self
+            .client
             .delete(url)
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
             .header("Accept", "application/json")
             .header("X-Atlassian-Token", "no-check")
-            .send()
+            .send_counted();
+        self.audited(
+            AuditEntry::new("delete_property")
+                .page(page_id)
+                .target(property_id),
+            result,
+        )
     }
 
     pub(crate) fn search_users(&self, public_name: &str) -> Result {
@@ -313,12 +582,12 @@ impl ConfluenceClient {
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
             .header("Accept", "application/json")
             .header("X-Atlassian-Token", "no-check")
-            .send()
+            .send_counted()
     }
 
     pub(crate) fn archive_page(&self, id: &str, note: &str) -> Result {
         let url = self.graphql_api();
-        self.client
+        let result = self.client
             .post(url)
             .query(&[("q", "ArchivePagesMutation")])
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
@@ -333,12 +602,13 @@ impl ConfluenceClient {
                 },
                 "query": "mutation ArchivePagesMutation($input: [BulkArchivePagesInput]!) {\narchivePages(input: $input) {\n    taskId\n    status\n    __typename\n  }\n}\n"
             }))
-            .send()
+            .send_counted();
+        self.audited(AuditEntry::new("archive_page").page(id), result)
     }
 
     pub(crate) fn unarchive_page(&self, id: &str) -> Result {
         let url = self.graphql_api();
-        self.client
+        let result = self.client
             .post(url)
             .query(&[("q", "ArchivePagesMutation")])
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
@@ -352,12 +622,13 @@ impl ConfluenceClient {
                 },
                 "query": "mutation UnarchivePagesMutation($pageIDs: [Long!]!, $includeChildren: [Boolean!]!, $parentPageId: Long) {\n  bulkUnarchivePages(\n    pageIDs: $pageIDs\n    includeChildren: $includeChildren\n    parentPageId: $parentPageId\n  ) {\n    taskId\n    status\n    __typename\n  }\n}\n"
             }))
-            .send()
+            .send_counted();
+        self.audited(AuditEntry::new("unarchive_page").page(id), result)
     }
 
     pub(crate) fn move_page(&self, page_id: &str, parent_id: &str) -> Result {
         let url = self.graphql_api();
-        self.client
+        let result = self.client
             .post(url)
             .query(&[("q", "useMovePageHandlerMovePageAppendMutation")])
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
@@ -371,18 +642,21 @@ impl ConfluenceClient {
                     },
                     "query": "mutation useMovePageHandlerMovePageAppendMutation($pageId: ID!, $parentId: ID!) {\n  movePageAppend(input: {pageId: $pageId, parentId: $parentId}) {\n    page {\n      id\n      links {\n        webui\n        editui\n        __typename\n      }\n      __typename\n    }\n    __typename\n  }\n}\n"
                 }))
-            .send()
+            .send_counted();
+        self.audited(AuditEntry::new("move_page").page(page_id), result)
     }
 
     pub(crate) fn set_restrictions(&self, id: &str, body: Value) -> Result {
         let url = self.rest_api(&format!("content/{}/restriction", id));
-        self.client
+        let result = self
+            .client
             .put(url)
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
             .header("Accept", "application/json")
             .header("X-Atlassian-Token", "no-check")
             .json(&body)
-            .send()
+            .send_counted();
+        self.audited(AuditEntry::new("set_restrictions").page(id), result)
     }
 
     pub(crate) fn current_user(&self) -> Result {
@@ -392,17 +666,70 @@ impl ConfluenceClient {
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
             .header("Accept", "application/json")
             .header("X-Atlassian-Token", "no-check")
-            .send()
+            .send_counted()
+    }
+
+    pub(crate) fn create_footer_comment(&self, page_id: &str, storage: &str) -> Result {
+        let url = self.rest_api_v2("footer-comments");
+        let result = self
+            .client
+            .post(url)
+            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+            .header("Accept", "application/json")
+            .header("X-Atlassian-Token", "no-check")
+            .json(&json!({
+                "pageId": page_id,
+                "body": {
+                    "representation": "storage",
+                    "value": storage
+                }
+            }))
+            .send_counted();
+        self.audited(
+            AuditEntry::new("create_footer_comment").page(page_id),
+            result,
+        )
+    }
+
+    pub(crate) fn get_content_watch_status(&self, id: &str, account_id: &str) -> Result {
+        let url = self.rest_api(&format!("user/watch/content/{}", id));
+        self.client
+            .get(url)
+            .query(&[("accountId", account_id)])
+            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+            .header("Accept", "application/json")
+            .header("X-Atlassian-Token", "no-check")
+            .send_counted()
+    }
+
+    pub(crate) fn add_content_watcher(&self, id: &str, account_id: &str) -> Result {
+        let url = self.rest_api(&format!("user/watch/content/{}", id));
+        let result = self
+            .client
+            .post(url)
+            .query(&[("accountId", account_id)])
+            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+            .header("Accept", "application/json")
+            .header("X-Atlassian-Token", "no-check")
+            .send_counted();
+        self.audited(
+            AuditEntry::new("add_content_watcher")
+                .page(id)
+                .target(account_id),
+            result,
+        )
     }
 
     pub(crate) fn delete_restrictions(&self, id: &str) -> Result {
         let url = self.rest_api(&format!("content/{}/restriction", id));
-        self.client
+        let result = self
+            .client
             .delete(url)
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
             .header("Accept", "application/json")
             .header("X-Atlassian-Token", "no-check")
-            .send()
+            .send_counted();
+        self.audited(AuditEntry::new("delete_restrictions").page(id), result)
     }
 
     pub(crate) fn get_restrictions_by_operation(&self, id: &str) -> Result {
@@ -412,7 +739,154 @@ impl ConfluenceClient {
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
             .header("Accept", "application/json")
             .header("X-Atlassian-Token", "no-check")
-            .send()
+            .send_counted()
+    }
+
+    pub(crate) fn get_space_permissions(&self, space_id: &str) -> Result {
+        let url = self.rest_api_v2(&format!("spaces/{}/permissions", space_id));
+        self.client
+            .get(url)
+            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+            .header("Accept", "application/json")
+            .send_counted()
+    }
+
+    pub(crate) fn get_group_by_name(&self, name: &str) -> Result {
+        let url = self.rest_api("group/by-name");
+        self.client
+            .get(url)
+            .query(&[("name", name)])
+            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+            .header("Accept", "application/json")
+            .send_counted()
+    }
+
+    pub(crate) fn add_space_permission(&self, space_key: &str, body: Value) -> Result {
+        let url = self.rest_api(&format!("space/{}/permission", space_key));
+        let result = self
+            .client
+            .post(url)
+            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+            .header("Accept", "application/json")
+            .header("X-Atlassian-Token", "no-check")
+            .json(&body)
+            .send_counted();
+        self.audited(
+            AuditEntry::new("add_space_permission").space(space_key),
+            result,
+        )
+    }
+
+    pub(crate) fn remove_space_permission(&self, space_key: &str, permission_id: &str) -> Result {
+        let url = self.rest_api(&format!("space/{}/permission/{}", space_key, permission_id));
+        let result = self
+            .client
+            .delete(url)
+            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+            .header("Accept", "application/json")
+            .header("X-Atlassian-Token", "no-check")
+            .send_counted();
+        self.audited(
+            AuditEntry::new("remove_space_permission")
+                .space(space_key)
+                .target(permission_id),
+            result,
+        )
+    }
+
+    pub(crate) fn get_space_details(&self, space_key: &str) -> Result {
+        let url = self.rest_api(&format!("space/{}", space_key));
+        self.client
+            .get(url)
+            .query(&[("expand", "description.plain,icon")])
+            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+            .header("Accept", "application/json")
+            .send_counted()
+    }
+
+    pub(crate) fn update_space(&self, space_key: &str, body: Value) -> Result {
+        let url = self.rest_api(&format!("space/{}", space_key));
+        let result = self
+            .client
+            .put(url)
+            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+            .header("Accept", "application/json")
+            .header("X-Atlassian-Token", "no-check")
+            .json(&body)
+            .send_counted();
+        self.audited(AuditEntry::new("update_space").space(space_key), result)
+    }
+
+    pub(crate) fn get_content_attachments(&self, content_id: &str, filename: &str) -> Result {
+        let url = self.rest_api(&format!("content/{}/child/attachment", content_id));
+        self.client
+            .get(url)
+            .query(&[("filename", filename)])
+            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+            .header("Accept", "application/json")
+            .send_counted()
+    }
+
+    pub(crate) fn get_content_templates(&self, space_key: &str) -> Result {
+        let url = self.rest_api("template/page");
+        self.client
+            .get(url)
+            .query(&[("spaceKey", space_key)])
+            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+            .header("Accept", "application/json")
+            .send_counted()
+    }
+
+    pub(crate) fn create_content_template(&self, body: Value) -> Result {
+        let url = self.rest_api("template");
+        let result = self
+            .client
+            .post(url)
+            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+            .header("Accept", "application/json")
+            .header("X-Atlassian-Token", "no-check")
+            .json(&body)
+            .send_counted();
+        self.audited(
+            AuditEntry::new("create_content_template")
+                .space(body["space"]["key"].as_str().unwrap_or_default())
+                .name(body["name"].as_str()),
+            result,
+        )
+    }
+
+    pub(crate) fn update_content_template(&self, body: Value) -> Result {
+        let url = self.rest_api("template");
+        let result = self
+            .client
+            .put(url)
+            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+            .header("Accept", "application/json")
+            .header("X-Atlassian-Token", "no-check")
+            .json(&body)
+            .send_counted();
+        self.audited(
+            AuditEntry::new("update_content_template")
+                .space(body["space"]["key"].as_str().unwrap_or_default())
+                .target(body["templateId"].as_str().unwrap_or_default())
+                .name(body["name"].as_str()),
+            result,
+        )
+    }
+
+    pub(crate) fn remove_content_template(&self, template_id: &str) -> Result {
+        let url = self.rest_api(&format!("template/{}", template_id));
+        let result = self
+            .client
+            .delete(url)
+            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+            .header("Accept", "application/json")
+            .header("X-Atlassian-Token", "no-check")
+            .send_counted();
+        self.audited(
+            AuditEntry::new("remove_content_template").target(template_id),
+            result,
+        )
     }
 
     pub(crate) fn move_page_relative(
@@ -425,11 +899,71 @@ impl ConfluenceClient {
             "content/{}/move/{}/{}",
             page_id, position, target_id
         ));
-        self.client
+        let result = self
+            .client
             .put(url)
             .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
             .header("Accept", "application/json")
             .header("X-Atlassian-Token", "no-check")
-            .send()
+            .send_counted();
+        self.audited(AuditEntry::new("move_page").page(page_id), result)
+    }
+
+    /// Changes only the title of a page, which adds a version to it.
+    pub(crate) fn update_page_title(&self, page_id: &str, title: &str) -> Result {
+        let url = self.rest_api_v2(&format!("pages/{}/title", page_id));
+        let result = self
+            .client
+            .put(url)
+            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+            .header("Accept", "application/json")
+            .json(&json!({"status": "current", "title": title}))
+            .send_counted();
+        self.audited(
+            AuditEntry::new("update_page_title")
+                .page(page_id)
+                .name(Some(title)),
+            result,
+        )
+    }
+
+    /// One version of a page, with its message.
+    pub(crate) fn get_page_version(&self, page_id: &str, number: i32) -> Result {
+        let url = self.rest_api(&format!("content/{}/version/{}", page_id, number));
+
+        self.send_conditionally(
+            self.client
+                .get(url)
+                .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+                .header("Accept", "application/json"),
+        )
+    }
+
+    /// Makes an earlier version of a page its current version, as a new version with `message`.
+    pub(crate) fn restore_page_version(
+        &self,
+        page_id: &str,
+        version: u64,
+        message: &str,
+    ) -> Result {
+        let url = self.rest_api(&format!("content/{}/version", page_id));
+        let result = self
+            .client
+            .post(url)
+            .basic_auth(self.api_user.clone(), Some(self.api_token.clone()))
+            .header("Accept", "application/json")
+            .json(&json!({
+                "operationKey": "restore",
+                "params": {
+                    "versionNumber": version,
+                    "message": message,
+                    "restoreTitle": true,
+                }
+            }))
+            .send_counted();
+        self.audited(
+            AuditEntry::new("restore_page_version").page(page_id),
+            result,
+        )
     }
 }
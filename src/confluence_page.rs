@@ -1,12 +1,18 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::path::PathBuf;
 use std::str::FromStr;
 
+use reqwest::StatusCode;
+
 use crate::confluence_paginator::ConfluencePaginator;
 use crate::confluence_space::ConfluenceSpace;
+use crate::console::print_info;
 use crate::{confluence_client::ConfluenceClient, responses};
 
-use crate::error::Result;
+use crate::error::{ApiResponse, Result};
+
+/// How often reading all pages of a space resumes after a failed request.
+const READ_RETRIES: usize = 3;
 
 #[derive(Debug, Clone)]
 pub enum ConfluenceNodeType {
@@ -27,36 +33,115 @@ impl ConfluenceNode {
         confluence_client: &ConfluenceClient,
         space: &ConfluenceSpace,
     ) -> Result<Vec<Self>> {
-        let response = confluence_client
-            .get_all_pages_in_space(&space.id)?
-            .error_for_status()?;
-
-        let mut page_iter =
-            ConfluencePaginator::<responses::PageBulkWithoutBody>::new(confluence_client);
-
-        let pages = page_iter
-            .start(response)?
-            .filter_map(|f| f.ok())
-            .map(|bulk_page| Self::new_from_page_bulk(&bulk_page));
-
+        // folders can't be listed by label, every folder under the homepage is read instead
         let folder_response = confluence_client
             .get_all_pages_from_homepage(&space.homepage_id)?
-            .error_for_status()?;
+            .api_result()?;
 
         let mut folder_iter = ConfluencePaginator::<responses::Descendant>::new(confluence_client);
 
-        let folders = folder_iter
+        let folders: Vec<Self> = folder_iter
             .start(folder_response)?
-            .filter_map(|d| d.ok())
+            .collect_resuming(READ_RETRIES)?
+            .into_iter()
             .filter(|d| d._type == "folder")
-            .map(|d| Self::folder_from_descendant(&d));
+            .map(|d| Self::folder_from_descendant(&d))
+            .collect();
+
+        let labelled_pages = match &space.managed_label {
+            Some(label) => Self::get_labelled_pages(confluence_client, space, label, &folders)?,
+            None => None,
+        };
+        let pages = match labelled_pages {
+            Some(pages) => pages,
+            None => Self::get_space_pages(confluence_client, space)?,
+        }
+        .into_iter()
+        .map(|bulk_page| Self::new_from_page_bulk(&bulk_page));
 
         let result: Vec<Self> = pages.chain(folders).collect();
 
         Ok(result)
     }
 
-    fn new_from_page_bulk(bulk_page: &responses::PageBulkWithoutBody) -> Self {
+    /// Every page of the space, whether it is managed or not.
+    pub(crate) fn get_space_pages(
+        confluence_client: &ConfluenceClient,
+        space: &ConfluenceSpace,
+    ) -> Result<Vec<responses::PageBulkWithoutBody>> {
+        let response = confluence_client
+            .get_all_pages_in_space(&space.id)?
+            .api_result()?;
+        // the full space can be thousands of pages, don't start over when a page fails
+        ConfluencePaginator::<responses::PageBulkWithoutBody>::new(confluence_client)
+            .start(response)?
+            .collect_resuming(READ_RETRIES)
+    }
+
+    /// The pages with the managed label, plus the homepage and the pages in the page map or
+    /// `_page_ids.yml` that don't have the label yet (e.g. when it was only just configured). None
+    /// when no page of the space has the label, as then all pages have to be read. The `folders`
+    /// are read anyway, so the ones in the page map aren't read as pages.
+    fn get_labelled_pages(
+        confluence_client: &ConfluenceClient,
+        space: &ConfluenceSpace,
+        label: &str,
+        folders: &[Self],
+    ) -> Result<Option<Vec<responses::PageBulkWithoutBody>>> {
+        let response = confluence_client.get_label(label)?;
+        if response.status() == StatusCode::NOT_FOUND {
+            print_info(&format!(
+                "No pages have the {} label yet, reading all pages of the space",
+                label
+            ));
+            return Ok(None);
+        }
+        let label_json: serde_json::Value = response.api_result()?.json()?;
+        let Some(label_id) = label_json["label"]["id"].as_str() else {
+            return Ok(None);
+        };
+        let response = confluence_client
+            .get_pages_with_label(label_id, &space.id)?
+            .api_result()?;
+        let mut pages: Vec<responses::PageBulkWithoutBody> =
+            ConfluencePaginator::<responses::PageBulkWithoutBody>::new(confluence_client)
+                .start(response)?
+                .collect::<Result<_>>()?;
+        // the label exists as soon as a page of any space has it
+        if pages.is_empty() {
+            print_info(&format!(
+                "No pages of the space have the {} label yet, reading all pages of the space",
+                label
+            ));
+            return Ok(None);
+        }
+
+        let mut missing_ids: BTreeSet<String> = space
+            .page_map
+            .pages
+            .values()
+            .map(|entry| entry.id.clone())
+            .collect();
+        missing_ids.extend(space.page_ids.values().cloned());
+        missing_ids.insert(space.homepage_id.clone());
+        for id in pages
+            .iter()
+            .map(|page| &page.id)
+            .chain(folders.iter().map(|folder| &folder.id))
+        {
+            missing_ids.remove(id);
+        }
+        for id in missing_ids {
+            let response = confluence_client.get_page(&id)?;
+            // deleted pages in the page map
+            if response.status().is_success() {
+                pages.push(response.json()?);
+            }
+        }
+        Ok(Some(pages))
+    }
+
+    pub(crate) fn new_from_page_bulk(bulk_page: &responses::PageBulkWithoutBody) -> Self {
         Self {
             id: bulk_page.id.clone(),
             parent_id: bulk_page.parent_id.clone(),
@@ -73,16 +158,14 @@ impl ConfluenceNode {
     pub(crate) fn archive(&self, confluence_client: &ConfluenceClient) -> anyhow::Result<()> {
         let response = confluence_client
             .archive_page(&self.id, "Orphaned")?
-            .error_for_status()?;
+            .api_result()?;
 
         let _body: serde_json::Value = response.json()?;
         Ok(())
     }
 
     pub(crate) fn unarchive(&self, confluence_client: &ConfluenceClient) -> anyhow::Result<()> {
-        let response = confluence_client
-            .unarchive_page(&self.id)?
-            .error_for_status()?;
+        let response = confluence_client.unarchive_page(&self.id)?.api_result()?;
 
         let _body: serde_json::Value = response.json()?;
         Ok(())
@@ -120,26 +203,27 @@ impl ConfluencePageData {
         "updated by markedspace:"
     }
 
-    pub fn extract_path(version: &responses::Version) -> Option<PathBuf> {
-        if let Some(data) = version
+    fn version_message_value(version: &responses::Version, key: &str) -> Option<String> {
+        let data = version
             .message
-            .strip_prefix(ConfluencePageData::version_message_prefix())
-        {
-            let kvs: HashMap<&str, &str> = data
-                .split(';')
-                .map(|kv| {
-                    let (key, value) = kv.split_once('=').unwrap();
-                    (key.trim(), value.trim())
-                })
-                .collect();
-            if let Some(path) = kvs.get("source") {
-                PathBuf::from_str(path).ok()
-            } else {
-                None
-            }
-        } else {
-            None
-        }
+            .strip_prefix(ConfluencePageData::version_message_prefix())?;
+        let kvs: HashMap<&str, &str> = data
+            .split(';')
+            .map(|kv| {
+                let (key, value) = kv.split_once('=').unwrap();
+                (key.trim(), value.trim())
+            })
+            .collect();
+        kvs.get(key).map(|value| String::from(*value))
+    }
+
+    pub fn extract_path(version: &responses::Version) -> Option<PathBuf> {
+        Self::version_message_value(version, "source")
+            .and_then(|path| PathBuf::from_str(&path).ok())
+    }
+
+    pub fn extract_checksum(version: &responses::Version) -> Option<String> {
+        Self::version_message_value(version, "checksum")
     }
 
     pub(crate) fn is_managed(&self) -> bool {
@@ -184,4 +268,107 @@ mod test {
         let path = result.unwrap();
         assert_eq!(path.as_os_str().to_str().unwrap(), "FILE");
     }
+
+    #[test]
+    fn it_extracts_checksums() {
+        let version = responses::Version {
+            message: ConfluencePageData::version_message_prefix().to_owned()
+                + " source=FILE; checksum=CHECKSUM",
+            number: 27,
+        };
+        assert_eq!(
+            ConfluencePageData::extract_checksum(&version),
+            Some(String::from("CHECKSUM"))
+        );
+    }
+
+    #[test]
+    fn it_reads_only_labelled_pages() -> crate::error::TestResult {
+        let mut server = mockito::Server::new();
+        let client =
+            ConfluenceClient::new_insecure(&server.host_with_port()).with_page_size(Some(50));
+        let page = |id: &str| {
+            format!(
+                r#"{{"id": "{}", "parentId": "1", "title": "Page {}", "status": "current", "version": {{"number": 2, "message": ""}}}}"#,
+                id, id
+            )
+        };
+        server
+            .mock("GET", "/wiki/rest/api/label")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "name".into(),
+                "managed".into(),
+            ))
+            .with_body(r#"{"label": {"id": "77", "name": "managed"}}"#)
+            .create();
+        let labelled = server
+            .mock("GET", "/wiki/api/v2/labels/77/pages")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("space-id".into(), "S".into()),
+                mockito::Matcher::UrlEncoded("limit".into(), "50".into()),
+            ]))
+            .with_body(format!(r#"{{"results": [{}], "_links": {{}}}}"#, page("2")))
+            .create();
+        let folder_as_page = server
+            .mock("GET", "/wiki/api/v2/pages/4")
+            .expect(0)
+            .create();
+        let folder = ConfluenceNode {
+            id: String::from("4"),
+            title: String::from("Team"),
+            parent_id: Some(String::from("1")),
+            data: ConfluenceNodeType::Folder(ConfluenceFolder {}),
+        };
+        server
+            .mock("GET", "/wiki/api/v2/pages/1")
+            .with_body(page("1"))
+            .create();
+        server
+            .mock("GET", "/wiki/api/v2/pages/3")
+            .with_body(page("3"))
+            .create();
+        let mut space = ConfluenceSpace::new_test("S", "1", Vec::default());
+        // read with the pages, see ConfluenceSpace::read_all_pages
+        for (source, id) in [("guide.md", "2"), ("old.md", "3"), ("team", "4")] {
+            space.page_map.insert(source, id, None);
+        }
+
+        let pages =
+            ConfluenceNode::get_labelled_pages(&client, &space, "managed", &[folder])?.unwrap();
+
+        labelled.assert();
+        folder_as_page.assert();
+        assert_eq!(
+            pages
+                .iter()
+                .map(|page| page.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["2", "1", "3"]
+        );
+
+        server
+            .mock("GET", "/wiki/rest/api/label")
+            .match_query(mockito::Matcher::UrlEncoded("name".into(), "unused".into()))
+            .with_status(404)
+            .create();
+        assert!(ConfluenceNode::get_labelled_pages(&client, &space, "unused", &[])?.is_none());
+
+        // the label is only on pages of other spaces
+        server
+            .mock("GET", "/wiki/rest/api/label")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "name".into(),
+                "elsewhere".into(),
+            ))
+            .with_body(r#"{"label": {"id": "78", "name": "elsewhere"}}"#)
+            .create();
+        server
+            .mock("GET", "/wiki/api/v2/labels/78/pages")
+            .match_query(mockito::Matcher::Any)
+            .with_body(r#"{"results": [], "_links": {}}"#)
+            .create();
+        assert!(ConfluenceNode::get_labelled_pages(&client, &space, "elsewhere", &[])?.is_none());
+
+        Ok(())
+    }
 }
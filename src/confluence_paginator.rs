@@ -1,15 +1,43 @@
+//! Reads the results of list endpoints page by page, following the `next` links.
+//!
+//! The paginator is an iterator that only fetches the next page once the results of the current
+//! one are consumed, so a caller that stops early doesn't read the rest. When reading a page
+//! fails, the iterator yields the error once and stops; the URL of the page that failed is kept as
+//! a checkpoint to resume from, so the results already read don't have to be read again.
 use std::collections::VecDeque;
 
+use anyhow::Context;
 use serde_json::from_str;
 
 use crate::confluence_client::ConfluenceClient;
-use crate::error::Result;
+use crate::console::print_warning;
+use crate::error::{ApiResponse, Result};
 use crate::responses;
 
 pub struct ConfluencePaginator<'a, T> {
     client: &'a ConfluenceClient,
     current_page: VecDeque<T>,
     next_url: Option<reqwest::Url>,
+    /// Overrides the `limit` of the next pages.
+    page_size: Option<u16>,
+    /// Results read so far, for the diagnostics of failures.
+    read: usize,
+    failed: bool,
+}
+
+/// Sets the `limit` query parameter of a URL.
+fn with_limit(url: &reqwest::Url, limit: u16) -> reqwest::Url {
+    let mut url = url.clone();
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| key != "limit")
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .append_pair("limit", &limit.to_string());
+    url
 }
 
 impl<'a, T> ConfluencePaginator<'a, T>
@@ -21,11 +49,45 @@ where
             client,
             current_page: VecDeque::default(),
             next_url: None,
+            page_size: client.page_size,
+            read: 0,
+            failed: false,
         }
     }
 
     pub fn start(&mut self, response: reqwest::blocking::Response) -> Result<&mut Self> {
-        self.parse_response(response.error_for_status()?)
+        self.parse_response(response.api_result()?)
+    }
+
+    /// The URL of the next page to read, which is the page that failed after a failure.
+    pub fn checkpoint(&self) -> Option<&reqwest::Url> {
+        self.next_url.as_ref()
+    }
+
+    /// Continues reading from a checkpoint, e.g. after a failure.
+    pub fn resume(&mut self, checkpoint: reqwest::Url) -> &mut Self {
+        self.next_url = Some(checkpoint);
+        self.failed = false;
+        self
+    }
+
+    /// Reads all results, resuming from the checkpoint when reading a page fails, up to `retries`
+    /// times.
+    pub fn collect_resuming(&mut self, retries: usize) -> Result<Vec<T>> {
+        let mut results = Vec::new();
+        let mut failures = 0;
+        while let Some(result) = self.next() {
+            match (result, self.checkpoint().cloned()) {
+                (Ok(item), _) => results.push(item),
+                (Err(err), Some(checkpoint)) if failures < retries => {
+                    failures += 1;
+                    print_warning(&format!("{:#}, resuming", err));
+                    self.resume(checkpoint);
+                }
+                (Err(err), _) => return Err(err),
+            }
+        }
+        Ok(results)
     }
 
     fn parse_response(&mut self, response: reqwest::blocking::Response) -> Result<&mut Self> {
@@ -37,14 +99,19 @@ where
         self.next_url = existing_page
             .links
             .and_then(|l| l.next)
-            .and_then(|n| current_url.join(&n).ok());
+            .and_then(|n| current_url.join(&n).ok())
+            .map(|url| match self.page_size {
+                Some(limit) => with_limit(&url, limit),
+                None => url,
+            });
+        self.read += existing_page.results.len();
         self.current_page = VecDeque::from_iter(existing_page.results.iter().cloned());
         Ok(self)
     }
 
     fn get_next_page(&mut self) -> Result<&mut Self> {
         if let Some(next_url) = &self.next_url {
-            let response = self.client.get(next_url)?.error_for_status()?;
+            let response = self.client.get(next_url)?.api_result()?;
             self.parse_response(response)
         } else {
             Err(anyhow::anyhow!("No next url!"))
@@ -59,9 +126,20 @@ where
     type Item = Result<T>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
         if self.current_page.is_empty() && self.next_url.is_some() {
             if let Err(err) = self.get_next_page() {
-                return Some(Err(err));
+                self.failed = true;
+                let url = self.next_url.as_ref().map(|url| url.to_string());
+                return Some(Err(err).with_context(|| {
+                    format!(
+                        "Failed to read more results after {} from {}",
+                        self.read,
+                        url.unwrap_or_default()
+                    )
+                }));
             }
         }
         self.current_page.pop_front().map(Ok)
@@ -75,7 +153,7 @@ mod test {
 
     use crate::{
         confluence_client,
-        error::TestResult,
+        error::{Result, TestResult},
         responses::{self, Descendant},
     };
 
@@ -120,8 +198,7 @@ mod test {
             ConfluencePaginator::new(&client);
         let descendants = descendants_iter
             .start(response)?
-            .filter_map(|d| d.ok())
-            .collect::<Vec<Descendant>>();
+            .collect::<Result<Vec<Descendant>>>()?;
 
         assert!(!descendants.is_empty());
 
@@ -200,9 +277,7 @@ mod test {
             ConfluencePaginator::new(&client);
         let descendants = descendants_iter
             .start(response)?
-            .filter_map(|d| d.ok())
-            .inspect(|d| println!("{}", d.title))
-            .collect::<Vec<Descendant>>();
+            .collect::<Result<Vec<Descendant>>>()?;
 
         assert!(!descendants.is_empty());
 
@@ -211,4 +286,74 @@ mod test {
 
         Ok(())
     }
+
+    fn descendant(id: &str, next: Option<&str>) -> String {
+        json!({
+            "results": [
+                {
+                    "id": id,
+                    "status": "current",
+                    "title": id,
+                    "parentId": "1",
+                    "depth": 1,
+                    "childPosition": 0,
+                    "type": "page"
+                },
+            ],
+            "_links": next.map(|next| json!({"next": next})).unwrap_or(json!({}))
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn it_resumes_from_the_checkpoint() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = confluence_client::ConfluenceClient::new_insecure(&server.host_with_port())
+            .with_page_size(Some(1));
+        server
+            .mock("GET", "/wiki/api/v2/pages/1/descendants")
+            .match_query(Matcher::UrlEncoded("limit".into(), "1".into()))
+            .with_body(descendant(
+                "2",
+                Some("/wiki/api/v2/pages/1/descendants?cursor=a&limit=25"),
+            ))
+            .create();
+        let failing = server
+            .mock("GET", "/wiki/api/v2/pages/1/descendants")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("cursor".into(), "a".into()),
+                Matcher::UrlEncoded("limit".into(), "1".into()),
+            ]))
+            .with_status(500)
+            .expect(1)
+            .create();
+
+        let response = client.get_all_pages_from_homepage("1")?;
+        let mut paginator = ConfluencePaginator::<Descendant>::new(&client);
+        paginator.start(response)?;
+
+        assert_eq!(paginator.next().unwrap()?.id, "2");
+        let err = paginator.next().unwrap().unwrap_err();
+        assert!(format!("{:#}", err).starts_with("Failed to read more results after 1 from "));
+        // stops after the failure instead of retrying
+        assert!(paginator.next().is_none());
+        failing.assert();
+
+        let checkpoint = paginator.checkpoint().cloned().unwrap();
+        failing.remove();
+        server
+            .mock("GET", "/wiki/api/v2/pages/1/descendants")
+            .match_query(Matcher::UrlEncoded("cursor".into(), "a".into()))
+            .with_body(descendant("3", None))
+            .create();
+        let rest = paginator
+            .resume(checkpoint)
+            .collect::<Result<Vec<Descendant>>>()?;
+
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].id, "3");
+        assert!(paginator.checkpoint().is_none());
+
+        Ok(())
+    }
 }
@@ -1,25 +1,62 @@
-use std::path::Path;
+use std::{collections::BTreeMap, path::Path};
 
 use anyhow::Result;
 use serde_json::json;
 
-use crate::archive::{archive, should_archive, should_unarchive, unarchive};
+use crate::archive::{
+    archive, move_under_parent, should_archive, should_unarchive, unarchive, OrphanPolicy,
+};
 use crate::confluence_client::ConfluenceClient;
 use crate::confluence_page::{ConfluenceNode, ConfluenceNodeType, ConfluencePageData};
 use crate::console::{print_status, Status};
-use crate::error::{self, ConfluenceError};
+use crate::error::{self, ApiResponse, ConfluenceError};
 use crate::link_generator::LinkGenerator;
+use crate::page_ids::PAGE_IDS_FILE;
+use crate::page_map::PageMap;
 
-use crate::responses::{self, ContentStatus, PageSingleWithoutBody, Version};
+use crate::responses::{
+    self, ContentStatus, MultiEntityResult, PageBulkWithoutBody, PageSingleWithoutBody, Version,
+};
+use crate::sync::is_in_scope;
 use crate::sync_operation::SyncOperation;
+use crate::sync_stats::{record_created_page, record_page, PageState};
+use crate::warnings::{Warning, WarningCode};
+
+/// Whether the page was synced from a file in the scope of `--only`. Pages without a file are
+/// only in the scope of the whole space.
+fn in_scope(scope: &[String], node: &ConfluenceNode) -> bool {
+    scope.is_empty()
+        || node
+            .page_data()
+            .and_then(|page_data| page_data.path.as_deref())
+            .is_some_and(|path| is_in_scope(scope, &path.to_string_lossy()))
+}
 
 #[derive(Debug)]
 pub struct ConfluenceSpace {
     pub id: String,
     pub homepage_id: String,
+    /// Only the pages with this label are read, see `managed_label` in the space config.
+    pub managed_label: Option<String>,
+    /// The pages `_page_ids.yml` lists for files, by file.
+    pub page_ids: BTreeMap<String, String>,
+    /// The files and directories of `--only`: orphans are only archived, and archived pages only
+    /// restored, when they were synced from them.
+    pub scope: Vec<String>,
+    /// The pages of the files at the last sync, read with the pages.
+    pub page_map: PageMap,
     nodes: Vec<ConfluenceNode>,
 }
 
+/// The version message of the blank pages created before they're synced, which tells them apart
+/// from a page someone else created with the same title.
+fn created_message() -> String {
+    format!(
+        "{} created=blank",
+        ConfluencePageData::version_message_prefix()
+    )
+}
+
 impl ConfluenceSpace {
     pub fn get(confluence_client: &ConfluenceClient, space_key: &str) -> Result<ConfluenceSpace> {
         let resp = confluence_client.get_space_by_key(space_key)?;
@@ -41,53 +78,213 @@ impl ConfluenceSpace {
         Ok(ConfluenceSpace {
             id: parsed_space.id,
             homepage_id: parsed_space.homepage_id,
+            managed_label: None,
+            page_ids: BTreeMap::new(),
+            scope: Vec::new(),
+            page_map: PageMap::default(),
             nodes: Vec::default(),
         })
     }
 
+    /// Reads the pages of the space and the page map, which a sync takes over to update it.
     pub fn read_all_pages(&mut self, confluence_client: &ConfluenceClient) -> Result<()> {
+        // the pages with the managed label are read together with the pages of the page map
+        if self.managed_label.is_some() {
+            self.page_map = PageMap::load(confluence_client, &self.homepage_id)?;
+            self.nodes = ConfluenceNode::get_all(confluence_client, self)?;
+            return Ok(());
+        }
         self.nodes = ConfluenceNode::get_all(confluence_client, self)?;
+        // a missing homepage is reported by the caller
+        if self.nodes.iter().any(|node| node.id == self.homepage_id) {
+            self.page_map = PageMap::load(confluence_client, &self.homepage_id)?;
+        }
         Ok(())
     }
 
-    pub fn link_pages(&mut self, link_generator: &mut LinkGenerator) {
+    pub fn link_pages(&mut self, link_generator: &mut LinkGenerator) -> Result<()> {
+        for (filename, id) in &self.page_ids {
+            let problem = if link_generator.get_file_title(Path::new(filename)).is_none() {
+                format!(
+                    "{} in {} isn't a page of the space",
+                    filename, PAGE_IDS_FILE
+                )
+            } else if !self.nodes.iter().any(|node| node.id == *id) {
+                format!(
+                    "Page {} of {} in {} isn't in the space, matching the file by title",
+                    id, filename, PAGE_IDS_FILE
+                )
+            } else {
+                continue;
+            };
+            link_generator
+                .warnings()
+                .report(Warning::new(WarningCode::StalePageId, problem))?;
+        }
+        link_generator.register_page_ids(&self.page_ids, &self.nodes);
+        link_generator.register_page_map(&self.page_map.pages, &self.nodes);
+        link_generator.register_synced_sources(&self.nodes);
         self.nodes.iter().for_each(|confluence_page| {
             link_generator.register_confluence_node(confluence_page);
         });
+        Ok(())
     }
 
+    /// Unarchives the archived pages whose file is back, so they're updated instead of a new page
+    /// being created for the file.
     pub(crate) fn restore_archived_pages(
-        &self,
+        &mut self,
         link_generator: &LinkGenerator,
         confluence_client: &ConfluenceClient,
     ) -> anyhow::Result<()> {
-        let _errors = self
+        for node in self
             .nodes
-            .iter()
-            .filter(|p| should_unarchive(p, link_generator))
-            .filter_map(|p| unarchive(p, confluence_client).err())
-            .collect::<Vec<anyhow::Error>>();
+            .iter_mut()
+            .filter(|p| should_unarchive(p, link_generator) && in_scope(&self.scope, p))
+        {
+            if unarchive(node, confluence_client).is_ok() {
+                if let ConfluenceNodeType::Page(page_data) = &mut node.data {
+                    page_data.status = ContentStatus::Current;
+                }
+            }
+        }
 
         Ok(())
     }
 
     pub(crate) fn archive_orphans(
-        &self,
+        &mut self,
         link_generator: &LinkGenerator,
         space_dir: &Path,
         confluence_client: &ConfluenceClient,
+        policy: &OrphanPolicy,
     ) -> error::Result<()> {
-        // let orphaned_pages = self.get_orphans(link_generator);
-        let _errors = self
-            .nodes
-            .iter()
-            .filter(|p| should_archive(p, link_generator))
-            .filter_map(|p| archive(p, space_dir, confluence_client).err())
-            .collect::<Vec<anyhow::Error>>();
+        if let Some(parent_title) = &policy.parent {
+            let parent_id = self.archive_parent_id(parent_title);
+            let orphans: Vec<ConfluenceNode> = self
+                .nodes
+                .iter()
+                .filter(|p| should_archive(p, link_generator) && in_scope(&self.scope, p))
+                .filter(|p| parent_id.is_none() || p.parent_id != parent_id)
+                .cloned()
+                .collect();
+            if !orphans.is_empty() {
+                let parent_id = match parent_id {
+                    Some(parent_id) => parent_id,
+                    None => self.create_archive_parent(parent_title, confluence_client)?,
+                };
+                for orphan in &orphans {
+                    move_under_parent(orphan, &parent_id, policy, confluence_client)?;
+                    self.set_parent(&orphan.id, &parent_id);
+                }
+            }
+        }
+        if policy.archive {
+            // let orphaned_pages = self.get_orphans(link_generator);
+            let _errors = self
+                .nodes
+                .iter()
+                .filter(|p| should_archive(p, link_generator) && in_scope(&self.scope, p))
+                .filter_map(|p| archive(p, space_dir, confluence_client).err())
+                .collect::<Vec<anyhow::Error>>();
+        }
 
         Ok(())
     }
 
+    fn archive_parent_id(&self, title: &str) -> Option<String> {
+        self.nodes
+            .iter()
+            .find(|node| {
+                node.title == title
+                    && node
+                        .page_data()
+                        .is_some_and(|p| !matches!(p.status, ContentStatus::Archived))
+            })
+            .map(|node| node.id.clone())
+    }
+
+    fn create_archive_parent(
+        &mut self,
+        title: &str,
+        confluence_client: &ConfluenceClient,
+    ) -> Result<String> {
+        let op = SyncOperation::start(format!("Creating archive page \"{}\"", title), true);
+        let resp = confluence_client.create_page(json!({
+            "spaceId": self.id,
+            "status": "current",
+            "title": title,
+            "parentId": self.homepage_id.clone(),
+            "body": {
+                "representation": "storage",
+                "value": "<p>Pages that are no longer published by marked-space.</p>"
+            },
+        }))?;
+        if !resp.status().is_success() {
+            op.end(Status::Error);
+            return Err(ConfluenceError::failed_request(resp));
+        }
+        let page: PageSingleWithoutBody = resp.json()?;
+        self.add_node(ConfluenceNode {
+            id: page.id.clone(),
+            title: String::from(title),
+            parent_id: Some(self.homepage_id.clone()),
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: page.version,
+                path: None,
+                status: ContentStatus::Current,
+            }),
+        });
+        op.end(Status::Created);
+        Ok(page.id)
+    }
+
+    /// A space without any pages, for rendering without Confluence.
+    pub fn offline() -> Self {
+        ConfluenceSpace {
+            id: String::default(),
+            homepage_id: String::from("0"),
+            managed_label: None,
+            page_ids: BTreeMap::new(),
+            scope: Vec::new(),
+            page_map: PageMap::default(),
+            nodes: Vec::default(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn new_test(id: &str, homepage_id: &str, nodes: Vec<ConfluenceNode>) -> Self {
+        ConfluenceSpace {
+            id: String::from(id),
+            homepage_id: String::from(homepage_id),
+            managed_label: None,
+            page_ids: BTreeMap::new(),
+            scope: Vec::new(),
+            page_map: PageMap::default(),
+            nodes,
+        }
+    }
+
+    pub fn nodes(&self) -> &[ConfluenceNode] {
+        &self.nodes
+    }
+
+    pub(crate) fn set_parent(&mut self, node_id: &str, parent_id: &str) {
+        if let Some(node) = self.nodes.iter_mut().find(|node| node.id == node_id) {
+            node.parent_id = Some(String::from(parent_id));
+        }
+    }
+
+    /// Records a title change made outside of a full page update, which adds a version to pages.
+    pub(crate) fn set_title(&mut self, node_id: &str, title: &str) {
+        if let Some(node) = self.nodes.iter_mut().find(|node| node.id == node_id) {
+            node.title = String::from(title);
+            if let ConfluenceNodeType::Page(page_data) = &mut node.data {
+                page_data.version.number += 1;
+            }
+        }
+    }
+
     pub fn get_existing_node(&self, node_id: &str) -> Option<ConfluenceNode> {
         self.nodes.iter().find(|node| node.id == node_id).cloned()
     }
@@ -96,12 +293,21 @@ impl ConfluenceSpace {
         self.nodes.push(from);
     }
 
+    /// Creates the pages and folders of the files that don't have one yet, leaving out the files
+    /// this sync doesn't select, e.g. the ones outside of `--only`.
     pub fn create_initial_nodes(
         &mut self,
         link_generator: &mut LinkGenerator,
         confluence_client: &ConfluenceClient,
+        is_selected: impl Fn(&str) -> bool,
     ) -> Result<()> {
         for title in link_generator.get_nodes_to_create() {
+            if !link_generator
+                .get_title_file(&title)
+                .is_some_and(&is_selected)
+            {
+                continue;
+            }
             if link_generator.is_folder(&title) {
                 self.create_folder(title, confluence_client, link_generator)?;
             } else {
@@ -118,19 +324,46 @@ impl ConfluenceSpace {
         link_generator: &mut LinkGenerator,
     ) -> Result<(), anyhow::Error> {
         let op = SyncOperation::start(format!("Creating new page \"{}\"", title), true);
-        let resp = confluence_client.create_page(json!({
+        let body = json!({
             "spaceId": self.id,
             "status": "current",
             "title": title,
             "parentId": self.homepage_id.clone(),
-        }))?;
-        if !resp.status().is_success() {
-            op.end(Status::Error);
-            return Err(ConfluenceError::failed_request(resp));
-        }
-        let page: PageSingleWithoutBody = resp.json()?;
+            "version": {"message": created_message()},
+        });
+        let id = match confluence_client.create_page(body.clone()) {
+            Ok(resp) if resp.status().is_success() => resp.json::<PageSingleWithoutBody>()?.id,
+            // a request that timed out or failed at a proxy may still have created the page
+            result => match self.find_created_page(&title, confluence_client) {
+                Ok(Some(id)) => id,
+                Err(lookup_err) => {
+                    op.end(Status::Error);
+                    let create_err = match result {
+                        Ok(resp) => ConfluenceError::failed_request(resp),
+                        Err(err) => err.into(),
+                    };
+                    return Err(create_err.context(format!(
+                        "Failed to check whether the page was created anyway: {:#}",
+                        lookup_err
+                    )));
+                }
+                Ok(None) => {
+                    // nothing was created, so trying again after a network error can't make a
+                    // duplicate
+                    let resp = match result {
+                        Ok(resp) => resp,
+                        Err(_) => confluence_client.create_page(body)?,
+                    };
+                    if !resp.status().is_success() {
+                        op.end(Status::Error);
+                        return Err(ConfluenceError::failed_request(resp));
+                    }
+                    resp.json::<PageSingleWithoutBody>()?.id
+                }
+            },
+        };
         let existing_page = ConfluenceNode {
-            id: page.id,
+            id,
             title: title.clone(),
             parent_id: Some(self.homepage_id.clone()),
             data: ConfluenceNodeType::Page(ConfluencePageData {
@@ -142,12 +375,36 @@ impl ConfluenceSpace {
                 status: ContentStatus::Current,
             }),
         };
+        record_created_page(&existing_page.id);
         link_generator.register_confluence_node(&existing_page);
         self.add_node(existing_page);
         op.end(Status::Created);
         Ok(())
     }
 
+    /// The page a failed create request made anyway: a page with the title right under the
+    /// homepage, where new pages are created, with the version message of a new page and that
+    /// nobody edited since.
+    fn find_created_page(
+        &self,
+        title: &str,
+        confluence_client: &ConfluenceClient,
+    ) -> Result<Option<String>> {
+        let pages: MultiEntityResult<PageBulkWithoutBody> = confluence_client
+            .get_pages_by_title(&self.id, title)?
+            .api_result()?
+            .json()?;
+        Ok(pages
+            .results
+            .into_iter()
+            .find(|page| {
+                page.parent_id.as_ref() == Some(&self.homepage_id)
+                    && page.version.number == 1
+                    && page.version.message == created_message()
+            })
+            .map(|page| page.id))
+    }
+
     fn create_folder(
         &self,
         title: String,
@@ -160,9 +417,91 @@ impl ConfluenceSpace {
                 "title": title,
                 "parent_id": self.homepage_id.clone()
             }))?
-            .error_for_status()?;
+            .api_result()?;
 
+        record_page(PageState::Created);
         print_status(Status::Created, &format!("folder \"{}\"", title));
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use mockito::Matcher;
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn it_finds_the_page_a_failed_create_request_made() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let create = server
+            .mock("POST", "/wiki/api/v2/pages")
+            .with_status(504)
+            .expect(1)
+            .create();
+        server
+            .mock("GET", "/wiki/api/v2/pages")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("space-id".into(), "1".into()),
+                Matcher::UrlEncoded("title".into(), "New".into()),
+            ]))
+            .with_status(200)
+            .with_body(
+                json!({"results": [
+                    {"id": "41", "parentId": "7", "title": "New", "status": "current", "version": {"number": 3, "message": ""}},
+                    {"id": "42", "parentId": "999", "title": "New", "status": "current", "version": {"number": 1, "message": ""}},
+                    {"id": "43", "parentId": "999", "title": "New", "status": "current", "version": {"number": 1, "message": "updated by markedspace: created=blank"}}
+                ]})
+                .to_string(),
+            )
+            .create();
+        let mut space = ConfluenceSpace::new_test("1", "999", Vec::default());
+
+        space.create_page(
+            String::from("New"),
+            &client,
+            &mut LinkGenerator::default_test(),
+        )?;
+
+        create.assert();
+        assert_eq!(space.nodes()[0].id, "43");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_fails_with_the_create_error_when_the_created_page_cannot_be_looked_up() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        server
+            .mock("POST", "/wiki/api/v2/pages")
+            .with_status(504)
+            .with_body("Gateway Timeout")
+            .create();
+        server
+            .mock("GET", "/wiki/api/v2/pages")
+            .match_query(Matcher::Any)
+            .with_status(500)
+            .create();
+        let mut space = ConfluenceSpace::new_test("1", "999", Vec::default());
+
+        let err = space
+            .create_page(
+                String::from("New"),
+                &client,
+                &mut LinkGenerator::default_test(),
+            )
+            .unwrap_err();
+
+        assert!(
+            format!("{:#}", err).starts_with("Failed to check whether the page was created anyway")
+        );
+        assert!(format!("{:?}", err).contains("504"));
+        assert!(space.nodes().is_empty());
+
+        Ok(())
+    }
+}
@@ -13,6 +13,7 @@ use once_cell::sync::Lazy;
 
 use crate::alerts::{render_basic_alert, render_expand};
 use crate::attachments::{render_link_enter, render_link_leave};
+use crate::helpers::collect_text;
 use crate::link_generator::LinkGenerator;
 
 #[rustfmt::skip]
@@ -93,6 +94,8 @@ pub struct ConfluenceStorageRenderer<'o> {
     link_generator: &'o LinkGenerator,
     next_task_id: u32,
     pub source: PathBuf,
+    /// Set while entering a node whose children shouldn't be rendered.
+    skip_children: bool,
 }
 
 #[rustfmt::skip]
@@ -312,6 +315,7 @@ impl<'o> ConfluenceStorageRenderer<'o> {
             link_generator,
             next_task_id: 1,
             source: PathBuf::from(source),
+            skip_children: false,
         }
     }
 
@@ -364,6 +368,10 @@ impl<'o> ConfluenceStorageRenderer<'o> {
                         self.format_node(node, true)?
                     };
 
+                    if self.skip_children {
+                        self.skip_children = false;
+                        continue;
+                    }
                     for ch in node.reverse_children() {
                         stack.push((ch, new_plain, Phase::Pre));
                     }
@@ -645,15 +653,20 @@ impl<'o> ConfluenceStorageRenderer<'o> {
                 let link_generator = self.link_generator;
                 if entering {
                     let no_children = node.first_child().is_none();
-                    link_generator.enter(nl, self, no_children)?;
+                    self.skip_children = link_generator.enter(nl, self, no_children)?;
                 } else {
                     link_generator.exit(nl, self)?;
                 }
             }
             NodeValue::Image(ref nl) => {
                 if entering {
-                    render_link_enter(nl, self.output)?;
-                    return Ok(true);
+                    // the alt text goes into an attribute instead of the image body
+                    let mut alt = Vec::with_capacity(20);
+                    for n in node.children() {
+                        collect_text(n, &mut alt);
+                    }
+                    render_link_enter(nl, &String::from_utf8_lossy(&alt), self.output)?;
+                    self.skip_children = true;
                 } else {
                     render_link_leave(nl, self.output)?;
                 }
@@ -0,0 +1,227 @@
+//! Publishing the markdown files in `_confluence_templates` as space templates, which people
+//! creating pages by hand in Confluence can start from.
+//!
+//! Like pages, templates are rendered to storage format and links to other pages resolve. The
+//! description of a template records its source and checksum, so templates are only updated when
+//! they change and templates whose source was removed get deleted.
+use serde_json::json;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    confluence_page::ConfluencePageData,
+    confluence_paginator::ConfluencePaginator,
+    console::Status,
+    error::{ConfluenceError, Result},
+    link_generator::LinkGenerator,
+    markdown_page::MarkdownPage,
+    responses::{ContentTemplate, Version},
+    sync_operation::SyncOperation,
+};
+
+pub static CONFLUENCE_TEMPLATES_DIR: &str = "_confluence_templates";
+
+/// The description of a template is kept in the same format as the version message of pages.
+fn template_version(template: &ContentTemplate) -> Version {
+    Version {
+        message: template.description.clone(),
+        number: 0,
+    }
+}
+
+fn template_source(template: &ContentTemplate) -> Option<String> {
+    ConfluencePageData::extract_path(&template_version(template))
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+fn template_checksum(template: &ContentTemplate) -> Option<String> {
+    ConfluencePageData::extract_checksum(&template_version(template))
+}
+
+pub fn sync_confluence_templates(
+    confluence_client: &ConfluenceClient,
+    space_key: &str,
+    templates: &[MarkdownPage],
+    link_generator: &LinkGenerator,
+) -> Result<()> {
+    let mut existing = ConfluencePaginator::<ContentTemplate>::new(confluence_client)
+        .start(confluence_client.get_content_templates(space_key)?)?
+        .collect::<Result<Vec<ContentTemplate>>>()?;
+
+    for template in templates {
+        let rendered = template.render(link_generator)?;
+        let op = SyncOperation::start(
+            format!("[{}] template \"{}\"", rendered.source, rendered.title),
+            true,
+        );
+        let description = rendered.version_message();
+        let position = existing
+            .iter()
+            .position(|t| template_source(t).as_deref() == Some(rendered.source.as_str()))
+            .or_else(|| existing.iter().position(|t| t.name == rendered.title));
+        let mut body = json!({
+            "name": rendered.title,
+            "templateType": "page",
+            "description": description,
+            "space": { "key": space_key },
+            "body": {
+                "storage": {
+                    "value": rendered.content,
+                    "representation": "storage",
+                }
+            },
+        });
+
+        let response = match position.map(|position| existing.remove(position)) {
+            Some(current)
+                if current.name == rendered.title
+                    && template_checksum(&current)
+                        .is_some_and(|checksum| rendered.has_checksum(&checksum)) =>
+            {
+                op.end(Status::Skipped);
+                continue;
+            }
+            Some(current) => {
+                body["templateId"] = json!(current.template_id);
+                confluence_client.update_content_template(body)?
+            }
+            None => confluence_client.create_content_template(body)?,
+        };
+        if !response.status().is_success() {
+            op.end(Status::Error);
+            return Err(ConfluenceError::failed_request(response));
+        }
+        op.end(if position.is_some() {
+            Status::Updated
+        } else {
+            Status::Created
+        });
+    }
+
+    // remaining templates published by marked-space no longer have a source
+    for orphan in existing.iter().filter(|t| template_source(t).is_some()) {
+        let op = SyncOperation::start(format!("template \"{}\"", orphan.name), true);
+        let response = confluence_client.remove_content_template(&orphan.template_id)?;
+        if !response.status().is_success() {
+            op.end(Status::Error);
+            return Err(ConfluenceError::failed_request(response));
+        }
+        op.end(Status::Deleted);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Matcher;
+
+    use crate::{error::TestResult, test_helpers::markdown_page_from_str};
+
+    use super::*;
+
+    #[test]
+    fn it_publishes_templates() -> TestResult {
+        let templates = vec![
+            markdown_page_from_str(
+                "_confluence_templates/runbook.md",
+                "# Runbook\n\n## Rollback\n",
+            )?,
+            markdown_page_from_str(
+                "_confluence_templates/adr.md",
+                "# Decision Record\n\n## Context\n",
+            )?,
+        ];
+        let link_generator = LinkGenerator::default_test();
+        let adr = templates[1].render(&link_generator)?;
+
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        server
+            .mock("GET", "/wiki/rest/api/template/page")
+            .match_query(Matcher::UrlEncoded("spaceKey".into(), "TEST".into()))
+            .with_status(200)
+            .with_body(
+                json!({
+                    "results": [
+                        {"templateId": "1", "name": "Decision Record", "description": adr.version_message()},
+                        {"templateId": "2", "name": "Postmortem", "description": "updated by markedspace: source=_confluence_templates/postmortem.md; checksum=ABC"},
+                        {"templateId": "3", "name": "Meeting Notes", "description": "Made by hand"}
+                    ],
+                    "_links": {}
+                })
+                .to_string(),
+            )
+            .create();
+        let create = server
+            .mock("POST", "/wiki/rest/api/template")
+            .match_body(Matcher::PartialJson(json!({
+                "name": "Runbook",
+                "templateType": "page",
+                "space": {"key": "TEST"}
+            })))
+            .with_status(200)
+            .create();
+        let update = server
+            .mock("PUT", "/wiki/rest/api/template")
+            .expect(0)
+            .create();
+        let delete = server
+            .mock("DELETE", "/wiki/rest/api/template/2")
+            .with_status(204)
+            .create();
+        let delete_by_hand = server
+            .mock("DELETE", "/wiki/rest/api/template/3")
+            .expect(0)
+            .create();
+
+        sync_confluence_templates(&client, "TEST", &templates, &link_generator)?;
+
+        create.assert();
+        update.assert();
+        delete.assert();
+        delete_by_hand.assert();
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_deletes_the_templates_when_their_files_are_gone() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        server
+            .mock("GET", "/wiki/rest/api/template/page")
+            .match_query(Matcher::UrlEncoded("spaceKey".into(), "TEST".into()))
+            .with_status(200)
+            .with_body(
+                json!({
+                    "results": [
+                        {"templateId": "1", "name": "Runbook", "description": "updated by markedspace: source=_confluence_templates/runbook.md; checksum=ABC"},
+                        {"templateId": "2", "name": "Postmortem", "description": "updated by markedspace: source=_confluence_templates/postmortem.md; checksum=DEF"},
+                        {"templateId": "3", "name": "Meeting Notes", "description": "Made by hand"}
+                    ],
+                    "_links": {}
+                })
+                .to_string(),
+            )
+            .create();
+        let delete = server
+            .mock(
+                "DELETE",
+                Matcher::Regex(r"^/wiki/rest/api/template/[12]$".into()),
+            )
+            .with_status(204)
+            .expect(2)
+            .create();
+        let delete_by_hand = server
+            .mock("DELETE", "/wiki/rest/api/template/3")
+            .expect(0)
+            .create();
+
+        sync_confluence_templates(&client, "TEST", &[], &LinkGenerator::default_test())?;
+
+        delete.assert();
+        delete_by_hand.assert();
+
+        Ok(())
+    }
+}
@@ -1,7 +1,44 @@
+use std::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
 use owo_colors::{OwoColorize, Stream::Stdout, Style};
 
+use crate::sync_stats::record_warning;
+
 const PADDING: usize = 9;
 
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Silences everything but errors, for commands that write machine readable output to stdout.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+thread_local! {
+    static PREFIX: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Starts every line printed from the current thread with the space key, so the progress of
+/// spaces that sync at the same time can be told apart.
+pub fn set_space_prefix(space_key: &str) {
+    PREFIX.with(|prefix| *prefix.borrow_mut() = format!("[{}] ", space_key));
+}
+
+pub(crate) fn prefix() -> String {
+    PREFIX.with(|prefix| prefix.borrow().clone())
+}
+
+/// Gives the current thread the prefix of the thread that started it.
+pub(crate) fn inherit_prefix(inherited: String) {
+    PREFIX.with(|prefix| *prefix.borrow_mut() = inherited);
+}
+
 pub enum Status {
     Updated,
     Skipped,
@@ -11,23 +48,35 @@ pub enum Status {
     Archived,
     Unarchived,
     Reordered,
+    Scheduled,
+    Adopted,
+    Restored,
 }
 
 pub fn print_warning(warning_str: &str) {
+    record_warning();
+    if is_quiet() {
+        return;
+    }
     println!(
-        "{}: {}",
+        "{}{}: {}",
+        prefix(),
         "warning".if_supports_color(Stdout, |s| s.bright_yellow()),
         warning_str
     );
 }
 
 pub fn print_info(info_str: &str) {
-    println!("{}{}", " ".repeat(PADDING), info_str);
+    if is_quiet() {
+        return;
+    }
+    println!("{}{}{}", prefix(), " ".repeat(PADDING), info_str);
 }
 
 pub fn print_error(info_str: &str) {
     println!(
-        "{}: {}",
+        "{}{}: {}",
+        prefix(),
         "  error".if_supports_color(Stdout, |s| s.bright_red()),
         info_str
     );
@@ -43,9 +92,16 @@ pub fn print_status(status: Status, status_str: &str) {
         Status::Archived => ("archived", Style::new().blue()),
         Status::Unarchived => ("unarchived", Style::new().blue()),
         Status::Reordered => ("reordered", Style::new().cyan()),
+        Status::Scheduled => ("scheduled", Style::new().magenta()),
+        Status::Adopted => ("adopted", Style::new().green()),
+        Status::Restored => ("restored", Style::new().yellow()),
     };
+    if is_quiet() {
+        return;
+    }
     println!(
-        "{}: {}",
+        "{}{}: {}",
+        prefix(),
         label.if_supports_color(Stdout, |s| s.style(style)),
         status_str
     );
@@ -0,0 +1,203 @@
+//! Terms that must never be published in a space, like customer names or internal codenames.
+//!
+//! The `policy` key of `_space.yml` points at a YAML file (relative to the space directory, so
+//! several spaces can share one) with the blocked terms and patterns:
+//!
+//! ```yaml
+//! blocked_terms:
+//!   - Acme Corp
+//! blocked_patterns:
+//!   internal codename: '\bProject (Falcon|Osprey)\b'
+//! ```
+//!
+//! Terms match whole words regardless of case. Every page is checked before anything is
+//! published, and with `--check`.
+use std::{collections::BTreeSet, fmt::Display, fs, path::Path};
+
+use anyhow::{anyhow, Context};
+use regex::{Regex, RegexBuilder};
+use saphyr::Yaml;
+
+use crate::{
+    console::print_error, error::Result, markdown_page::MarkdownPage,
+    space_config::parse_string_map,
+};
+
+#[derive(Debug, Default)]
+pub struct ContentPolicy {
+    /// What is reported for a match, e.g. `blocked term "Acme Corp"`, to the regex.
+    rules: Vec<(String, Regex)>,
+}
+
+impl ContentPolicy {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read policy file {}", path.display()))?;
+        Self::from_yaml_str(&content)
+            .with_context(|| format!("Failed to parse policy file {}", path.display()))
+    }
+
+    fn from_yaml_str(s: &str) -> Result<Self> {
+        let docs = Yaml::load_from_str(s).context("Failed to parse policy as YAML")?;
+        let Some(yaml) = docs.first() else {
+            return Ok(ContentPolicy::default());
+        };
+        let terms = match &yaml["blocked_terms"] {
+            Yaml::BadValue | Yaml::Null => Vec::default(),
+            Yaml::Array(terms) => terms
+                .iter()
+                .map(|term| {
+                    term.as_str().map(String::from).ok_or(anyhow!(
+                        "Failed to parse \"blocked_terms\" key (should be a list of strings)"
+                    ))
+                })
+                .collect::<Result<_>>()?,
+            _ => {
+                return Err(anyhow!(
+                    "Failed to parse \"blocked_terms\" key (should be a list of strings)"
+                ))
+            }
+        };
+        let mut rules = terms
+            .iter()
+            .map(|term| {
+                let regex = RegexBuilder::new(&format!(r"\b{}\b", regex::escape(term.trim())))
+                    .case_insensitive(true)
+                    .build()?;
+                Ok((format!("blocked term \"{}\"", term.trim()), regex))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for (name, pattern) in parse_string_map(&yaml["blocked_patterns"], "blocked_patterns")? {
+            let regex = Regex::new(&pattern)
+                .map_err(|err| anyhow!("Invalid pattern for \"{}\": {}", name, err))?;
+            rules.push((name, regex));
+        }
+        Ok(ContentPolicy { rules })
+    }
+
+    fn check(&self, file: &str, content: &str) -> Vec<PolicyViolation> {
+        content
+            .lines()
+            .enumerate()
+            .flat_map(|(index, line)| {
+                self.rules.iter().flat_map(move |(rule, regex)| {
+                    regex.find_iter(line).map(move |found| PolicyViolation {
+                        file: String::from(file),
+                        line: index + 1,
+                        column: line[..found.start()].chars().count() + 1,
+                        rule: rule.clone(),
+                    })
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PolicyViolation {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub rule: String,
+}
+
+impl Display for PolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.rule
+        )
+    }
+}
+
+/// Fails when a page contains something the policy file of the space blocks.
+pub fn check_content_policy(
+    pages: &[&MarkdownPage],
+    space_dir: &Path,
+    policy_path: &Path,
+) -> Result<()> {
+    let policy = ContentPolicy::load(&space_dir.join(policy_path))?;
+    let mut violations = Vec::default();
+    let files: BTreeSet<&str> = pages.iter().flat_map(|page| page.source_files()).collect();
+    for file in files {
+        let content = fs::read_to_string(space_dir.join(file))?;
+        violations.extend(policy.check(file, &content));
+    }
+    if violations.is_empty() {
+        return Ok(());
+    }
+    for violation in &violations {
+        print_error(&violation.to_string());
+    }
+    Err(anyhow!(
+        "{} content policy violation(s) found, not publishing",
+        violations.len()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+
+    use crate::{error::TestResult, test_helpers::markdown_page_from_str};
+
+    use super::*;
+
+    static POLICY: &str = r#"
+blocked_terms:
+  - Acme Corp
+blocked_patterns:
+  internal codename: '\bProject (Falcon|Osprey)\b'
+"#;
+
+    #[test]
+    fn it_reports_blocked_terms_and_patterns() -> TestResult {
+        let policy = ContentPolicy::from_yaml_str(POLICY)?;
+
+        assert_eq!(
+            policy
+                .check(
+                    "release.md",
+                    "# Release\n\nBuilt for ACME corp as part of Project Falcon.\nAcmeCorporation\n"
+                )
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "release.md:3:11: blocked term \"Acme Corp\"",
+                "release.md:3:32: internal codename"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_errors_on_invalid_policies() {
+        assert!(ContentPolicy::from_yaml_str("blocked_terms: Acme").is_err());
+        assert!(ContentPolicy::from_yaml_str("blocked_patterns:\n  broken: '(['").is_err());
+    }
+
+    #[test]
+    fn it_checks_the_pages_of_a_space() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("policies/customer.yml").write_str(POLICY)?;
+        let content = "# Overview\n\nOur biggest customer is Acme Corp.\n";
+        temp.child("space/overview.md").write_str(content)?;
+        let page = markdown_page_from_str("overview.md", content)?;
+
+        assert_eq!(
+            check_content_policy(
+                &[&page],
+                temp.child("space").path(),
+                Path::new("../policies/customer.yml")
+            )
+            .unwrap_err()
+            .to_string(),
+            "1 content policy violation(s) found, not publishing"
+        );
+
+        Ok(())
+    }
+}
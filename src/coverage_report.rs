@@ -0,0 +1,242 @@
+//! The `report coverage` subcommand: every page of the Confluence space against the pages
+//! marked-space manages, listing the pages made by hand, so teams can decide what to import into
+//! the markdown and what to archive.
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    confluence_page::{ConfluenceNode, ConfluenceNodeType},
+    console::set_quiet,
+    link_generator::LinkGenerator,
+    markdown_space::MarkdownSpace,
+    mentions::CachedUsers,
+    responses::ContentStatus,
+    sync::{check_homepage_exists, load_space, LoadedSpace},
+    Args, Result,
+};
+
+/// A page in Confluence, with the titles of its ancestors to find it by.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CoveragePage {
+    pub id: String,
+    /// The titles from the homepage down to the page.
+    pub path: Vec<String>,
+    /// The file it was synced from, for managed pages.
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CoverageReport {
+    /// The current pages in the space.
+    pub total: usize,
+    pub managed: usize,
+    /// Made or last edited by hand, not from a markdown file.
+    pub unmanaged: Vec<CoveragePage>,
+    /// Synced before, but their file is gone.
+    pub orphaned: Vec<CoveragePage>,
+}
+
+impl CoverageReport {
+    /// `nodes` are all pages of the space, not only the ones the sync reads.
+    pub fn collect(
+        nodes: &[ConfluenceNode],
+        homepage_id: &str,
+        link_generator: &LinkGenerator,
+    ) -> Self {
+        let by_id: HashMap<&str, &ConfluenceNode> =
+            nodes.iter().map(|node| (node.id.as_str(), node)).collect();
+        let path = |node: &ConfluenceNode| {
+            let mut path = vec![node.title.clone()];
+            let mut parent_id = node.parent_id.as_deref();
+            // a page can't be its own ancestor, but don't trust the response with that
+            while let Some(parent) = parent_id.and_then(|id| by_id.get(id)) {
+                if path.len() > nodes.len() {
+                    break;
+                }
+                path.insert(0, parent.title.clone());
+                parent_id = parent.parent_id.as_deref();
+            }
+            path
+        };
+
+        let mut report = CoverageReport::default();
+        for node in nodes {
+            let ConfluenceNodeType::Page(page_data) = &node.data else {
+                continue;
+            };
+            if page_data.status != ContentStatus::Current {
+                continue;
+            }
+            report.total += 1;
+            let page = CoveragePage {
+                id: node.id.clone(),
+                path: path(node),
+                source: page_data
+                    .path
+                    .as_ref()
+                    .map(|path| path.to_string_lossy().to_string()),
+            };
+            if link_generator.is_orphaned(node, page_data) {
+                report.orphaned.push(page);
+            } else if page_data.is_managed() || node.id == homepage_id {
+                // the homepage is index.md, even before the first sync
+                report.managed += 1;
+            } else {
+                report.unmanaged.push(page);
+            }
+        }
+        report.unmanaged.sort_by(|a, b| a.path.cmp(&b.path));
+        report.orphaned.sort_by(|a, b| a.path.cmp(&b.path));
+        report
+    }
+
+    pub fn render(&self) -> String {
+        let percentage = match self.total {
+            0 => 100,
+            total => self.managed * 100 / total,
+        };
+        let mut output = format!(
+            "{} of {} pages are managed by marked-space ({}%)\n",
+            self.managed, self.total, percentage
+        );
+        if !self.unmanaged.is_empty() {
+            output += &format!("Not managed ({}):\n", self.unmanaged.len());
+            for page in &self.unmanaged {
+                output += &format!("  {} ({})\n", page.path.join(" / "), page.id);
+            }
+        }
+        if !self.orphaned.is_empty() {
+            output += &format!("Orphaned, their file is gone ({}):\n", self.orphaned.len());
+            for page in &self.orphaned {
+                output += &format!(
+                    "  {} ({}) {}\n",
+                    page.path.join(" / "),
+                    page.id,
+                    page.source.as_deref().unwrap_or_default()
+                );
+            }
+        }
+        output
+    }
+}
+
+pub fn report_coverage(
+    confluence_client: ConfluenceClient,
+    markdown_space: &mut MarkdownSpace,
+    args: Args,
+) -> Result<()> {
+    set_quiet(true);
+    let space_key = markdown_space.key.clone();
+    let LoadedSpace {
+        mut space,
+        mut link_generator,
+        ..
+    } = load_space(
+        &confluence_client,
+        markdown_space,
+        &args,
+        Arc::new(CachedUsers::new(confluence_client.clone())),
+    )?;
+
+    space.read_all_pages(&confluence_client)?;
+    check_homepage_exists(&space, &space_key)?;
+    space.link_pages(&mut link_generator)?;
+
+    // with a managed label the sync only reads the labelled pages, the hand-made ones are the point
+    let nodes: Vec<ConfluenceNode> = match space.managed_label {
+        Some(_) => ConfluenceNode::get_space_pages(&confluence_client, &space)?
+            .iter()
+            .map(ConfluenceNode::new_from_page_bulk)
+            .collect(),
+        None => space.nodes().to_vec(),
+    };
+    print!(
+        "{}",
+        CoverageReport::collect(&nodes, &space.homepage_id, &link_generator).render()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{
+        confluence_page::ConfluencePageData,
+        confluence_space::ConfluenceSpace,
+        error::TestResult,
+        responses::{ContentStatus, Version},
+        test_helpers::markdown_page_from_str,
+    };
+
+    use super::*;
+
+    fn page(id: &str, title: &str, parent: Option<&str>, message: &str) -> ConfluenceNode {
+        let version = Version {
+            message: String::from(message),
+            number: 1,
+        };
+        ConfluenceNode {
+            id: String::from(id),
+            title: String::from(title),
+            parent_id: parent.map(String::from),
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                path: ConfluencePageData::extract_path(&version),
+                version,
+                status: ContentStatus::Current,
+            }),
+        }
+    }
+
+    #[test]
+    fn it_lists_pages_not_managed_by_marked_space() -> TestResult {
+        let pages = vec![
+            markdown_page_from_str("index.md", "# Home\n")?,
+            markdown_page_from_str("guides.md", "# Guides\n")?,
+        ];
+        let mut link_generator = LinkGenerator::default_test();
+        for page in &pages {
+            link_generator.register_markdown_page(page)?;
+        }
+        let space = ConfluenceSpace::new_test(
+            "1",
+            "999",
+            vec![
+                page("999", "Home", None, "edited by hand"),
+                page(
+                    "10",
+                    "Guides",
+                    Some("999"),
+                    "updated by markedspace: source=guides.md; checksum=1",
+                ),
+                page(
+                    "11",
+                    "Old Page",
+                    Some("999"),
+                    "updated by markedspace: source=old.md; checksum=1",
+                ),
+                page("12", "Retro notes", Some("10"), "edited by hand"),
+                page("13", "Meeting notes", Some("999"), ""),
+            ],
+        );
+        for node in space.nodes() {
+            link_generator.register_confluence_node(node);
+        }
+
+        let report = CoverageReport::collect(space.nodes(), "999", &link_generator);
+
+        assert_eq!(report.total, 5);
+        assert_eq!(report.managed, 2);
+        assert_eq!(
+            report.render(),
+            "2 of 5 pages are managed by marked-space (40%)
+Not managed (2):
+  Home / Guides / Retro notes (12)
+  Home / Meeting notes (13)
+Orphaned, their file is gone (1):
+  Home / Old Page (11) old.md
+"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+//! Errors in markdown files that point at where in the file they are.
+//!
+//! Positions come from the source positions comrak keeps for every node, shifted by the lines of
+//! the front matter so they match the file. A diagnostic is shown with the offending line and the
+//! span underlined, like the compiler does:
+//!
+//! ```text
+//! Failed to parse local link: ./foo.md
+//!   --> guides/setup.md:12:5
+//!    |
+//! 12 | See [the other page](./foo.md) for details
+//!    |     ^^^^^^^^^^^^^^^^^^^^^^^^^^ this link
+//! ```
+use std::fmt::Display;
+
+use comrak::nodes::Sourcepos;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Location {
+    /// Line in the file, starting at 1.
+    pub line: usize,
+    /// Column of the first character of the span, starting at 1.
+    pub column: usize,
+    /// The text of the line.
+    pub text: String,
+    /// Number of characters underlined.
+    pub length: usize,
+    pub label: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub location: Option<Location>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>) -> Self {
+        Diagnostic {
+            message: message.into(),
+            location: None,
+        }
+    }
+
+    /// A diagnostic for the node at `sourcepos`, in the markdown `content` which starts at line
+    /// `body_line + 1` of the file.
+    pub fn at(
+        message: impl Into<String>,
+        sourcepos: Sourcepos,
+        content: &str,
+        body_line: usize,
+        label: impl Into<String>,
+    ) -> Self {
+        Diagnostic {
+            message: message.into(),
+            location: Self::locate(sourcepos, content, body_line, label.into()),
+        }
+    }
+
+    fn locate(
+        sourcepos: Sourcepos,
+        content: &str,
+        body_line: usize,
+        label: String,
+    ) -> Option<Location> {
+        if sourcepos.start.line == 0 {
+            return None;
+        }
+        let text = content.lines().nth(sourcepos.start.line - 1)?.to_string();
+        let line_length = text.chars().count();
+        let column = sourcepos.start.column.max(1);
+        let end = if sourcepos.end.line == sourcepos.start.line {
+            sourcepos.end.column.min(line_length)
+        } else {
+            line_length
+        };
+        Some(Location {
+            line: sourcepos.start.line + body_line,
+            column,
+            length: (end + 1).saturating_sub(column).max(1),
+            text,
+            label,
+        })
+    }
+
+    /// The diagnostic with the file and the offending line, for printing.
+    pub fn render(&self, filename: &str) -> String {
+        let Some(location) = &self.location else {
+            return self.message.clone();
+        };
+        let gutter = " ".repeat(location.line.to_string().len());
+        format!(
+            "{message}\n{gutter}--> {filename}:{line}:{column}\n{gutter} |\n{line} | {text}\n{gutter} | {padding}{underline} {label}",
+            message = self.message,
+            line = location.line,
+            column = location.column,
+            text = location.text,
+            padding = " ".repeat(location.column - 1),
+            underline = "^".repeat(location.length),
+            label = location.label,
+        )
+    }
+}
+
+/// All the diagnostics of a file.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Diagnostics {
+    pub filename: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rendered: Vec<String> = self
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.render(&self.filename))
+            .collect();
+        if self.diagnostics.iter().all(|d| d.location.is_none()) {
+            f.write_str(&rendered.join(", "))
+        } else {
+            f.write_str(&rendered.join("\n"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use comrak::{nodes::NodeValue, parse_document, Arena, Options};
+
+    use super::*;
+
+    #[test]
+    fn it_points_at_the_node() {
+        let content = "# Title\n\nSee [the other page](./foo.md) for details\n";
+        let arena = Arena::new();
+        let root = parse_document(&arena, content, &Options::default());
+        let link = root
+            .descendants()
+            .find(|node| matches!(node.data.borrow().value, NodeValue::Link(_)))
+            .unwrap();
+
+        let diagnostic = Diagnostic::at(
+            "Failed to parse local link: ./foo.md",
+            link.data.borrow().sourcepos,
+            content,
+            4,
+            "this link",
+        );
+
+        assert_eq!(
+            diagnostic.render("guides/setup.md"),
+            "Failed to parse local link: ./foo.md
+ --> guides/setup.md:7:5
+  |
+7 | See [the other page](./foo.md) for details
+  |     ^^^^^^^^^^^^^^^^^^^^^^^^^^ this link"
+        );
+    }
+
+    #[test]
+    fn it_keeps_diagnostics_without_location_on_one_line() {
+        let diagnostics = Diagnostics {
+            filename: String::from("page.md"),
+            diagnostics: vec![Diagnostic::new("one"), Diagnostic::new("two")],
+        };
+
+        assert_eq!(diagnostics.to_string(), "one, two");
+    }
+}
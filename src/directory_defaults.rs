@@ -0,0 +1,220 @@
+//! Front matter defaults for the pages in a directory.
+//!
+//! A `_defaults.md` file with only front matter sets values for every page in its directory and
+//! the directories below it, unless a page (or a `_defaults.md` closer to it) sets its own. It is
+//! never published as a page. For now only `emoji` and `publish: false` are inherited, and `labels`
+//! are added to the labels of the pages. Labels can use the [`PathVariables`] of the page, like
+//! `{{ path.top_directory }}`.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use tera::Tera;
+
+use crate::{error::Result, frontmatter::FrontMatter, path_variables::PathVariables};
+
+pub static DEFAULTS_FILENAME: &str = "_defaults.md";
+
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct DirectoryDefaults {
+    /// Directory to the emoji of the pages in it.
+    emojis: BTreeMap<PathBuf, String>,
+    /// Directories with `publish: false`.
+    unpublished: BTreeSet<PathBuf>,
+    /// Directory to the labels of the pages in it, which can be templates.
+    labels: BTreeMap<PathBuf, Vec<String>>,
+}
+
+fn render_label(label: &str, path_variables: &PathVariables) -> Result<String> {
+    if !label.contains("{{") && !label.contains("{%") {
+        return Ok(label.to_string());
+    }
+    let mut context = tera::Context::new();
+    context.insert("path", path_variables);
+    Tera::one_off(label, &context, false)
+        .map(|label| label.trim().to_string())
+        .map_err(|err| anyhow!("Invalid label template \"{}\": {:#}", label, err))
+}
+
+impl DirectoryDefaults {
+    pub fn is_defaults_file(path: &Path) -> bool {
+        path.file_name()
+            .is_some_and(|filename| filename == DEFAULTS_FILENAME)
+    }
+
+    /// Reads the defaults file at `path`.
+    pub fn load(&mut self, path: &Path) -> Result<()> {
+        let file = File::open(path)?;
+        let (fm, _content) = FrontMatter::from_reader(&mut io::BufReader::new(file))
+            .with_context(|| path.display().to_string())?;
+        if let Some(dir) = path.parent() {
+            if !fm.publish {
+                self.unpublished.insert(PathBuf::from(dir));
+            }
+            if !fm.emoji.is_empty() {
+                self.emojis.insert(PathBuf::from(dir), fm.emoji);
+            }
+            if !fm.labels.is_empty() {
+                // fail on broken templates when the file is read rather than in the first page
+                for label in &fm.labels {
+                    render_label(label, &PathVariables::default())
+                        .with_context(|| path.display().to_string())?;
+                }
+                self.labels.insert(PathBuf::from(dir), fm.labels);
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills in what the front matter of the page at `page_path` doesn't set.
+    pub fn apply(
+        &self,
+        page_path: &Path,
+        path_variables: &PathVariables,
+        fm: &mut FrontMatter,
+    ) -> Result<()> {
+        if fm.emoji.is_empty() {
+            if let Some(emoji) = page_path
+                .ancestors()
+                .skip(1)
+                .find_map(|dir| self.emojis.get(dir))
+            {
+                fm.emoji = emoji.clone();
+            }
+        }
+        if page_path
+            .ancestors()
+            .skip(1)
+            .any(|dir| self.unpublished.contains(dir))
+        {
+            fm.publish = false;
+        }
+        let dirs: Vec<&Path> = page_path.ancestors().skip(1).collect();
+        for labels in dirs.iter().rev().filter_map(|dir| self.labels.get(*dir)) {
+            for label in labels {
+                let label = render_label(label, path_variables)?;
+                if !label.is_empty() && !fm.labels.contains(&label) {
+                    fm.labels.push(label);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn it_inherits_the_closest_emoji() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("runbooks/_defaults.md")
+            .write_str("---\nemoji: fire\n---\n")?;
+        temp.child("runbooks/db/_defaults.md")
+            .write_str("---\nemoji: floppy_disk\n---\n")?;
+        let mut defaults = DirectoryDefaults::default();
+        defaults.load(temp.child("runbooks/_defaults.md").path())?;
+        defaults.load(temp.child("runbooks/db/_defaults.md").path())?;
+
+        let mut fm = FrontMatter::default();
+        defaults.apply(
+            temp.child("runbooks/restart.md").path(),
+            &PathVariables::default(),
+            &mut fm,
+        )?;
+        assert_eq!(fm.emoji, "fire");
+
+        let mut fm = FrontMatter::default();
+        defaults.apply(
+            temp.child("runbooks/db/backup/restore.md").path(),
+            &PathVariables::default(),
+            &mut fm,
+        )?;
+        assert_eq!(fm.emoji, "floppy_disk");
+
+        let (mut fm, _content) = FrontMatter::from_str("---\nemoji: rocket\n---\n# Deploy\n")?;
+        defaults.apply(
+            temp.child("runbooks/deploy.md").path(),
+            &PathVariables::default(),
+            &mut fm,
+        )?;
+        assert_eq!(fm.emoji, "rocket");
+
+        let mut fm = FrontMatter::default();
+        defaults.apply(
+            temp.child("index.md").path(),
+            &PathVariables::default(),
+            &mut fm,
+        )?;
+        assert_eq!(fm.emoji, "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_unpublishes_directories() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("internal/_defaults.md")
+            .write_str("---\npublish: false\n---\n")?;
+        let mut defaults = DirectoryDefaults::default();
+        defaults.load(temp.child("internal/_defaults.md").path())?;
+
+        let mut fm = FrontMatter::default();
+        defaults.apply(
+            temp.child("internal/oncall/rota.md").path(),
+            &PathVariables::default(),
+            &mut fm,
+        )?;
+        assert!(!fm.publish);
+
+        let mut fm = FrontMatter::default();
+        defaults.apply(
+            temp.child("guide.md").path(),
+            &PathVariables::default(),
+            &mut fm,
+        )?;
+        assert!(fm.publish);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_adds_labels_with_path_variables() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("_defaults.md")
+            .write_str("---\nlabels: [\"{{ path.top_directory | lower }}\"]\n---\n")?;
+        temp.child("Runbooks/_defaults.md")
+            .write_str("---\nlabels: [runbook]\n---\n")?;
+        let mut defaults = DirectoryDefaults::default();
+        defaults.load(temp.child("_defaults.md").path())?;
+        defaults.load(temp.child("Runbooks/_defaults.md").path())?;
+
+        let (mut fm, _content) = FrontMatter::from_str("---\nlabels: [db]\n---\n# Restore\n")?;
+        defaults.apply(
+            temp.child("Runbooks/db/restore.md").path(),
+            &PathVariables::from_source("Runbooks/db/restore.md"),
+            &mut fm,
+        )?;
+        assert_eq!(fm.labels, vec!["db", "runbooks", "runbook"]);
+
+        // no empty label for pages at the top
+        let mut fm = FrontMatter::default();
+        defaults.apply(
+            temp.child("index.md").path(),
+            &PathVariables::from_source("index.md"),
+            &mut fm,
+        )?;
+        assert!(fm.labels.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+//! `--dry-run`: what a sync would change, without changing anything. The rendered pages are
+//! compared with their bodies in Confluence and printed as a unified diff, after normalizing both
+//! sides (see `storage_format`) so only changes to the content show up. New pages are diffed
+//! against nothing, and the moves and archives the sync would make are listed.
+//!
+//! With `--dry-run-html`, the changed pages are also written as HTML, the page in Confluence next
+//! to the page after the sync (rendered like `preview` does), for reviewers of a pull request to
+//! look at in a CI artifact.
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use similar::TextDiff;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    console::print_info,
+    error::{ApiResponse, Result},
+    link_generator::LinkGenerator,
+    markdown_page::{MarkdownPage, RenderedPage},
+    page_tree::NodeStatus,
+    preview::{encode_path, escape_html, storage_to_html, STYLE},
+    responses::{BodySingle, PageSingleWithBody},
+    space_status::StatusEntry,
+    storage_format::normalize,
+};
+
+/// Normalized storage format with every tag on a line of its own, so the diff is readable.
+fn storage_lines(storage: &str) -> String {
+    let mut lines = normalize(storage).replace("><", ">\n<");
+    lines.push('\n');
+    lines
+}
+
+/// The unified diff of a page, `None` when it would stay the same. `current` is the title and
+/// storage format of the page in Confluence, `None` for a page that would be created.
+pub fn page_diff(rendered_page: &RenderedPage, current: Option<(&str, &str)>) -> Option<String> {
+    let (title, body) = current.unwrap_or_default();
+    let (old, new) = (storage_lines(body), storage_lines(&rendered_page.content));
+    if current.is_some() && title == rendered_page.title && old == new {
+        return None;
+    }
+    let source = rendered_page.source.replace('\\', "/");
+    let old_header = match current {
+        Some(_) => format!("a/{} \"{}\"", source, title),
+        None => String::from("/dev/null"),
+    };
+    let new_header = format!("b/{} \"{}\"", source, rendered_page.title);
+    let diff = TextDiff::from_lines(&old, &new);
+    Some(
+        diff.unified_diff()
+            .context_radius(3)
+            .header(&old_header, &new_header)
+            .to_string(),
+    )
+}
+
+fn current_page(confluence_client: &ConfluenceClient, page_id: &str) -> Result<(String, String)> {
+    let page: PageSingleWithBody = confluence_client
+        .get_page_with_body(page_id)?
+        .api_result()?
+        .json()?;
+    let body = match page.body {
+        BodySingle::Storage(body) => body.value,
+        _ => String::default(),
+    };
+    Ok((page.title, body))
+}
+
+static BUNDLE_STYLE: &str = "body{max-width:none}.sides{display:flex;gap:2em}\
+.sides>div{flex:1;min-width:0}.sides h2{color:#5e6c84;border-bottom:1px solid #dfe1e6}\
+.new{color:#00875a}.archived{color:#de350b}";
+
+/// The HTML files of a dry run: `index.html` listing the changed pages, a page per changed file at
+/// `pages/<source>.html`, and the attachments of those files at `attachments/<source>/<name>`.
+pub struct HtmlBundle {
+    dir: PathBuf,
+    /// The changed pages by source, with their title and whether they'd be created.
+    pages: BTreeMap<String, (String, bool)>,
+}
+
+impl HtmlBundle {
+    pub fn create(dir: &Path) -> Result<HtmlBundle> {
+        fs::create_dir_all(dir)?;
+        Ok(HtmlBundle {
+            dir: dir.to_path_buf(),
+            pages: BTreeMap::default(),
+        })
+    }
+
+    /// The storage format as HTML for a file of the bundle at `depth` directories below its root.
+    /// Links to other pages keep pointing at Confluence, as unchanged pages aren't in the bundle.
+    fn html(storage: &str, source: &str, depth: usize) -> String {
+        let root = "../".repeat(depth);
+        storage_to_html(storage, source, &BTreeMap::default())
+            .replace("=\"/attachments/", &format!("=\"{}attachments/", root))
+    }
+
+    pub fn add_page(
+        &mut self,
+        rendered_page: &RenderedPage,
+        current: Option<(&str, &str)>,
+        diff: &str,
+        markdown_page: Option<&MarkdownPage>,
+        link_generator: &LinkGenerator,
+    ) -> Result<()> {
+        let source = rendered_page.source.replace('\\', "/");
+        let depth = source.matches('/').count() + 1;
+        let before = match current {
+            Some((title, body)) => format!(
+                "<h1>{}</h1>{}",
+                escape_html(title),
+                Self::html(body, &source, depth)
+            ),
+            None => String::from("<p class=\"new\">A new page</p>"),
+        };
+        let after = format!(
+            "<h1>{}</h1>{}",
+            escape_html(&rendered_page.title),
+            Self::html(&rendered_page.content, &source, depth)
+        );
+        let html = format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title><style>{}{}</style></head><body><p><a href=\"{}index.html\">All changes</a> <code>{}</code></p><div class=\"sides\"><div><h2>In Confluence</h2>{}</div><div><h2>After the sync</h2>{}</div></div><details><summary>Diff of the storage format</summary><pre>{}</pre></details></body></html>\n",
+            escape_html(&rendered_page.title),
+            STYLE,
+            BUNDLE_STYLE,
+            "../".repeat(depth),
+            escape_html(&source),
+            before,
+            after,
+            escape_html(diff)
+        );
+        let path = self.dir.join("pages").join(format!("{}.html", source));
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, html)?;
+
+        for attachment in markdown_page.map_or(&[][..], |page| &page.attachments) {
+            let path = self
+                .dir
+                .join("attachments")
+                .join(&source)
+                .join(&attachment.name);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, attachment.content(link_generator)?)?;
+        }
+        self.pages
+            .insert(source, (rendered_page.title.clone(), current.is_none()));
+        Ok(())
+    }
+
+    /// Writes `index.html`, with the pages that would be archived.
+    pub fn finish(self, orphans: &[&StatusEntry]) -> Result<()> {
+        let mut body = String::from("<ul>");
+        for (source, (title, created)) in &self.pages {
+            body += &format!(
+                "<li><a href=\"pages/{}.html\">{}</a> <code>{}</code>{}</li>",
+                encode_path(source),
+                escape_html(title),
+                escape_html(source),
+                if *created {
+                    " <span class=\"new\">new</span>"
+                } else {
+                    ""
+                }
+            );
+        }
+        for orphan in orphans {
+            body += &format!(
+                "<li>{} <code>{}</code> <span class=\"archived\">archived</span></li>",
+                escape_html(&orphan.title),
+                escape_html(&orphan.source)
+            );
+        }
+        body += "</ul>";
+        fs::write(
+            self.dir.join("index.html"),
+            format!(
+                "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Dry run</title><style>{}{}</style></head><body><h1>Dry run: {} changed page(s)</h1>{}</body></html>\n",
+                STYLE,
+                BUNDLE_STYLE,
+                self.pages.len() + orphans.len(),
+                body
+            ),
+        )?;
+        print_info(&format!(
+            "Wrote the changed pages to {}",
+            self.dir.join("index.html").display()
+        ));
+        Ok(())
+    }
+}
+
+/// Prints the diff of every page the sync would create or update, and the pages it would archive.
+/// With `html_dir`, the changes are also written there as HTML.
+pub fn print_dry_run(
+    rendered_pages: &[RenderedPage],
+    markdown_pages: &[MarkdownPage],
+    status: &[StatusEntry],
+    link_generator: &LinkGenerator,
+    confluence_client: &ConfluenceClient,
+    html_dir: Option<&Path>,
+) -> Result<()> {
+    let mut bundle = html_dir.map(HtmlBundle::create).transpose()?;
+    let (mut created, mut updated) = (0, 0);
+    for rendered_page in rendered_pages {
+        let id = link_generator.get_page_id(Path::new(&rendered_page.source));
+        // only pages that are in Confluence have an id, nothing is created in a dry run
+        let current = match id {
+            Some(id) => Some(current_page(confluence_client, &id)?),
+            None => None,
+        };
+        let current = current
+            .as_ref()
+            .map(|(title, body)| (title.as_str(), body.as_str()));
+        if let Some(diff) = page_diff(rendered_page, current) {
+            match current {
+                Some(_) => updated += 1,
+                None => created += 1,
+            }
+            print!("{}", diff);
+            if let Some(bundle) = &mut bundle {
+                let markdown_page = markdown_pages
+                    .iter()
+                    .find(|page| page.source == rendered_page.source);
+                bundle.add_page(rendered_page, current, &diff, markdown_page, link_generator)?;
+            }
+        }
+    }
+    let orphans: Vec<&StatusEntry> = status
+        .iter()
+        .filter(|entry| entry.status == NodeStatus::Orphan)
+        .collect();
+    for orphan in &orphans {
+        println!("archive {} \"{}\"", orphan.source, orphan.title);
+    }
+    if let Some(bundle) = bundle {
+        bundle.finish(&orphans)?;
+    }
+    println!(
+        "Dry run: {} page(s) would be created, {} updated and {} archived, nothing was changed",
+        created,
+        updated,
+        orphans.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::TestResult, test_helpers::markdown_page_from_str};
+
+    use super::*;
+
+    #[test]
+    fn it_diffs_the_normalized_storage_format() -> TestResult {
+        let rendered_page = markdown_page_from_str("guide.md", "# Guide\n\nNew text\n\nSame\n")?
+            .render(&LinkGenerator::default_test())?;
+
+        let current = "<p>Old text</p>\n<p ac:local-id=\"1\">Same</p>";
+
+        assert_eq!(
+            page_diff(&rendered_page, Some(("Guide", current))),
+            Some(String::from(
+                "--- a/guide.md \"Guide\"\n+++ b/guide.md \"Guide\"\n@@ -1,2 +1,2 @@\n-<p>Old text</p>\n+<p>New text</p>\n <p>Same</p>\n"
+            ))
+        );
+        assert_eq!(
+            page_diff(
+                &rendered_page,
+                Some(("Guide", "<p>New text</p><p>Same</p>"))
+            ),
+            None
+        );
+        assert!(page_diff(&rendered_page, None)
+            .unwrap()
+            .starts_with("--- /dev/null\n+++ b/guide.md \"Guide\"\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_the_changes_as_html() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let link_generator = LinkGenerator::default_test();
+        let changed = markdown_page_from_str("guides/setup.md", "# Setup\n\nNew text\n")?
+            .render(&link_generator)?;
+        let created = markdown_page_from_str("faq.md", "# FAQ\n")?.render(&link_generator)?;
+        let current = Some((
+            "Setup",
+            "<p><ac:image><ri:attachment ri:filename=\"a.png\"/></ac:image></p>",
+        ));
+
+        let mut bundle = HtmlBundle::create(temp.path())?;
+        bundle.add_page(&changed, current, "-old\n+new\n", None, &link_generator)?;
+        bundle.add_page(&created, None, "+new\n", None, &link_generator)?;
+        let orphan = StatusEntry {
+            status: NodeStatus::Orphan,
+            source: String::from("old.md"),
+            title: String::from("Old"),
+            publish_after: None,
+        };
+        bundle.finish(&[&orphan])?;
+
+        let page = fs::read_to_string(temp.path().join("pages/guides/setup.md.html"))?;
+        assert!(page.contains("<a href=\"../../index.html\">All changes</a>"));
+        assert!(page.contains("<img src=\"../../attachments/guides/setup.md/a.png\""));
+        assert!(page.contains("<p>New text</p>"));
+        let new_page = fs::read_to_string(temp.path().join("pages/faq.md.html"))?;
+        assert!(new_page.contains("A new page"));
+        let index = fs::read_to_string(temp.path().join("index.html"))?;
+        assert!(index.contains("<h1>Dry run: 3 changed page(s)</h1>"));
+        assert!(index.contains("<a href=\"pages/guides/setup.md.html\">Setup</a>"));
+        assert!(index.contains("Old <code>old.md</code>"));
+
+        Ok(())
+    }
+}
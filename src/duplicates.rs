@@ -0,0 +1,320 @@
+//! Detection of near-duplicate paragraphs and code blocks across pages, which are candidates for
+//! an excerpt or a shared template instead of copies that drift apart.
+//!
+//! Blocks are compared by the MinHash of their word shingles, which estimates how many word
+//! sequences two blocks have in common without comparing every pair of blocks word by word. Only
+//! blocks that share a band of their signature (locality-sensitive hashing) are compared at all,
+//! so the work grows with the number of blocks rather than the number of pairs.
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeSet, HashMap, HashSet},
+    hash::{Hash, Hasher},
+};
+
+use anyhow::anyhow;
+use saphyr::Yaml;
+
+use crate::error::Result;
+
+const SHINGLE_SIZE: usize = 3;
+const NUM_HASHES: u64 = 64;
+
+/// Configured with `lint.near_duplicates` in `_space.yml`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DuplicatePolicy {
+    /// Estimated percentage of shingles two blocks need to have in common.
+    pub threshold: u32,
+    /// Shorter blocks are ignored, short sentences repeat all the time.
+    pub min_words: usize,
+}
+
+impl Default for DuplicatePolicy {
+    fn default() -> Self {
+        DuplicatePolicy {
+            threshold: 80,
+            min_words: 20,
+        }
+    }
+}
+
+impl DuplicatePolicy {
+    pub fn from_yaml(yaml: &Yaml) -> Result<Option<Self>> {
+        let default = DuplicatePolicy::default();
+        match yaml {
+            Yaml::BadValue | Yaml::Null | Yaml::Boolean(false) => Ok(None),
+            Yaml::Boolean(true) => Ok(Some(default)),
+            Yaml::Hash(_) => {
+                let threshold = match &yaml["threshold"] {
+                    Yaml::BadValue | Yaml::Null => default.threshold,
+                    Yaml::Integer(n) if (1..=100).contains(n) => *n as u32,
+                    _ => {
+                        return Err(anyhow!(
+                            "Failed to parse \"lint.near_duplicates.threshold\" key (should be a percentage)"
+                        ))
+                    }
+                };
+                let min_words = match &yaml["min_words"] {
+                    Yaml::BadValue | Yaml::Null => default.min_words,
+                    Yaml::Integer(n) if *n > 0 => *n as usize,
+                    _ => {
+                        return Err(anyhow!(
+                            "Failed to parse \"lint.near_duplicates.min_words\" key (should be a positive number)"
+                        ))
+                    }
+                };
+                Ok(Some(DuplicatePolicy {
+                    threshold,
+                    min_words,
+                }))
+            }
+            _ => Err(anyhow!(
+                "Failed to parse \"lint.near_duplicates\" key (should be true/false or a map)"
+            )),
+        }
+    }
+}
+
+struct Block {
+    page: usize,
+    excerpt: String,
+    signature: Vec<u64>,
+}
+
+fn signature(words: &[String]) -> Vec<u64> {
+    let shingles: Vec<u64> = words
+        .windows(SHINGLE_SIZE)
+        .map(|shingle| {
+            let mut hasher = DefaultHasher::new();
+            shingle.hash(&mut hasher);
+            hasher.finish()
+        })
+        .collect();
+    (0..NUM_HASHES)
+        .map(|seed| {
+            shingles
+                .iter()
+                .map(|shingle| {
+                    let mut hasher = DefaultHasher::new();
+                    seed.hash(&mut hasher);
+                    shingle.hash(&mut hasher);
+                    hasher.finish()
+                })
+                .min()
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// How many hashes of the signature make a band. Blocks are candidates when all the hashes of one
+/// band are the same, longer bands let fewer pairs through, so they're only used for the high
+/// thresholds that a pair needs to pass anyway.
+fn rows_per_band(threshold: u32) -> usize {
+    match threshold {
+        90.. => 8,
+        70..=89 => 4,
+        40..=69 => 2,
+        _ => 1,
+    }
+}
+
+/// The pairs of blocks of different pages (the later one first) that have a band in common.
+fn candidate_pairs(blocks: &[Block], threshold: u32) -> BTreeSet<(usize, usize)> {
+    let rows = rows_per_band(threshold);
+    let mut buckets = HashMap::<(usize, &[u64]), Vec<usize>>::default();
+    for (index, block) in blocks.iter().enumerate() {
+        for (band, hashes) in block.signature.chunks(rows).enumerate() {
+            buckets.entry((band, hashes)).or_default().push(index);
+        }
+    }
+    let mut pairs = BTreeSet::default();
+    for bucket in buckets.values() {
+        for (i, &earlier) in bucket.iter().enumerate() {
+            for &later in &bucket[i + 1..] {
+                if blocks[earlier].page != blocks[later].page {
+                    pairs.insert((later, earlier));
+                }
+            }
+        }
+    }
+    pairs
+}
+
+/// The estimated percentage of shingles in common.
+fn similarity(a: &[u64], b: &[u64]) -> u32 {
+    let same = a.iter().zip(b).filter(|(a, b)| a == b).count();
+    (same * 100 / a.len()) as u32
+}
+
+/// A block of one page that closely matches a block of an earlier page.
+#[derive(Debug, PartialEq)]
+pub struct NearDuplicate {
+    pub page: usize,
+    pub other_page: usize,
+    pub excerpt: String,
+    pub similarity: u32,
+}
+
+/// Finds near-duplicate blocks, given the text blocks of each page. Each block is reported once per
+/// other page it matches, on the page that comes last.
+pub fn find_near_duplicates(pages: &[Vec<String>], policy: &DuplicatePolicy) -> Vec<NearDuplicate> {
+    let blocks: Vec<Block> = pages
+        .iter()
+        .enumerate()
+        .flat_map(|(page, blocks)| {
+            blocks.iter().filter_map(move |text| {
+                let words: Vec<String> = text
+                    .split_whitespace()
+                    .map(|word| {
+                        word.trim_matches(|c: char| !c.is_alphanumeric())
+                            .to_lowercase()
+                    })
+                    .filter(|word| !word.is_empty())
+                    .collect();
+                if words.len() < policy.min_words.max(SHINGLE_SIZE) {
+                    return None;
+                }
+                Some(Block {
+                    page,
+                    excerpt: words
+                        .iter()
+                        .take(8)
+                        .cloned()
+                        .collect::<Vec<String>>()
+                        .join(" "),
+                    signature: signature(&words),
+                })
+            })
+        })
+        .collect();
+
+    let mut duplicates = Vec::<NearDuplicate>::default();
+    // the blocks that were reported for a page already
+    let mut reported = HashSet::<(usize, usize)>::default();
+    for (i, j) in candidate_pairs(&blocks, policy.threshold) {
+        let (block, earlier) = (&blocks[i], &blocks[j]);
+        if reported.contains(&(i, earlier.page)) {
+            continue;
+        }
+        let similarity = similarity(&block.signature, &earlier.signature);
+        if similarity >= policy.threshold {
+            reported.insert((i, earlier.page));
+            duplicates.push(NearDuplicate {
+                page: block.page,
+                other_page: earlier.page,
+                excerpt: block.excerpt.clone(),
+                similarity,
+            });
+        }
+    }
+    duplicates
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::TestResult;
+
+    use super::*;
+
+    static SETUP: &str = "Install the toolchain with rustup, then clone the repository and run make setup to install the git hooks and the local database used by the integration tests";
+
+    #[test]
+    fn it_finds_near_duplicates_across_pages() {
+        let drifted = SETUP.replace("make setup", "make bootstrap");
+        let pages = vec![
+            vec![String::from(SETUP), String::from("Something else entirely.")],
+            vec![String::from("An unrelated paragraph about deploying on a Friday afternoon and why that is a bad idea for everyone involved in it")],
+            vec![drifted],
+        ];
+
+        let duplicates = find_near_duplicates(
+            &pages,
+            &DuplicatePolicy {
+                threshold: 60,
+                min_words: 10,
+            },
+        );
+
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].page, 2);
+        assert_eq!(duplicates[0].other_page, 0);
+        assert_eq!(
+            duplicates[0].excerpt,
+            "install the toolchain with rustup then clone the"
+        );
+        assert!(duplicates[0].similarity >= 60 && duplicates[0].similarity < 100);
+    }
+
+    #[test]
+    fn it_ignores_short_blocks_and_repeats_within_a_page() {
+        let pages = vec![
+            vec![String::from(SETUP), String::from(SETUP)],
+            vec![
+                String::from("See the setup guide."),
+                String::from("See the setup guide."),
+            ],
+        ];
+
+        assert!(find_near_duplicates(&pages, &DuplicatePolicy::default()).is_empty());
+    }
+
+    #[test]
+    fn it_only_compares_blocks_with_a_band_in_common() {
+        let mut pages: Vec<Vec<String>> = (0..100)
+            .map(|page| {
+                vec![(0..25)
+                    .map(|word| format!("page{}word{}", page, word))
+                    .collect::<Vec<String>>()
+                    .join(" ")]
+            })
+            .collect();
+        pages[40].push(String::from(SETUP));
+        pages[70].push(String::from(SETUP));
+        pages[70].push(SETUP.replace("rustup", "the installer"));
+        let policy = DuplicatePolicy::default();
+        let blocks: Vec<Block> = pages
+            .iter()
+            .enumerate()
+            .flat_map(|(page, blocks)| {
+                blocks.iter().map(move |text| {
+                    let words: Vec<String> =
+                        text.split_whitespace().map(str::to_lowercase).collect();
+                    Block {
+                        page,
+                        excerpt: String::default(),
+                        signature: signature(&words),
+                    }
+                })
+            })
+            .collect();
+
+        // the block of page 40 and the two of page 70
+        assert_eq!(
+            candidate_pairs(&blocks, policy.threshold),
+            BTreeSet::from([(72, 41), (73, 41)])
+        );
+        let duplicates = find_near_duplicates(&pages, &policy);
+        assert_eq!(
+            duplicates
+                .iter()
+                .map(|duplicate| (duplicate.page, duplicate.other_page, duplicate.similarity))
+                .collect::<Vec<_>>(),
+            vec![(70, 40, 100)]
+        );
+    }
+
+    #[test]
+    fn it_parses_the_policy() -> TestResult {
+        let parse = |s: &str| DuplicatePolicy::from_yaml(&Yaml::load_from_str(s).unwrap()[0]);
+
+        assert_eq!(parse("true")?, Some(DuplicatePolicy::default()));
+        assert_eq!(
+            parse("threshold: 90\nmin_words: 30")?,
+            Some(DuplicatePolicy {
+                threshold: 90,
+                min_words: 30
+            })
+        );
+        assert!(parse("threshold: 0.9").is_err());
+
+        Ok(())
+    }
+}
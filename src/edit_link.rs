@@ -0,0 +1,112 @@
+//! A panel at the bottom of every page linking to its file in the git forge, so Confluence
+//! readers know where to propose changes. Set with the `edit_link` key of `_space.yml`:
+//!
+//! ```yaml
+//! edit_link:
+//!   url: https://github.com/acme/docs/edit/{{ branch }}/docs/{{ source }}
+//!   branch: main
+//!   text: Edit this page on GitHub
+//! ```
+//!
+//! `url` is a tera template that gets `branch` (default `main`) and `source`, the path of the
+//! file relative to the space directory.
+use anyhow::anyhow;
+use saphyr::Yaml;
+use tera::{Context, Tera};
+
+use crate::{
+    confluence_storage_renderer::{escape, escape_href},
+    error::Result,
+};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct EditLink {
+    pub url: String,
+    pub branch: String,
+    pub text: String,
+}
+
+impl EditLink {
+    pub fn from_yaml(yaml: &Yaml) -> Result<Option<Self>> {
+        let string = |key: &str, default: &str| match &yaml[key] {
+            Yaml::BadValue | Yaml::Null => Ok(String::from(default)),
+            Yaml::String(value) if !value.trim().is_empty() => Ok(value.trim().to_string()),
+            _ => Err(anyhow!(
+                "Failed to parse \"edit_link.{}\" key (should be a string)",
+                key
+            )),
+        };
+        let edit_link = match yaml {
+            Yaml::BadValue | Yaml::Null => return Ok(None),
+            Yaml::Hash(_) if !yaml["url"].is_badvalue() => EditLink {
+                url: string("url", "")?,
+                branch: string("branch", "main")?,
+                text: string("text", "Edit this page")?,
+            },
+            _ => {
+                return Err(anyhow!(
+                "Failed to parse \"edit_link\" key (should be a map with url, branch and/or text)"
+            ))
+            }
+        };
+        // fail on a broken template when the config is read rather than in the first page
+        edit_link.href("index.md")?;
+        Ok(Some(edit_link))
+    }
+
+    /// Where the file of `source` is in the forge.
+    pub(crate) fn href(&self, source: &str) -> Result<String> {
+        let mut context = Context::new();
+        context.insert("branch", &self.branch);
+        context.insert("source", &source.replace('\\', "/"));
+        Tera::one_off(&self.url, &context, false)
+            .map_err(|err| anyhow!("Invalid template for edit_link.url: {:#}", err))
+    }
+
+    /// The panel linking to `source`.
+    pub fn markup(&self, source: &str) -> Result<String> {
+        let mut href = Vec::default();
+        escape_href(&mut href, self.href(source)?.as_bytes())?;
+        let mut text = Vec::default();
+        escape(&mut text, self.text.as_bytes())?;
+        Ok(format!(
+            "<ac:structured-macro ac:name=\"info\"><ac:rich-text-body><p><a href=\"{}\">{}</a></p></ac:rich-text-body></ac:structured-macro>",
+            String::from_utf8_lossy(&href),
+            String::from_utf8_lossy(&text)
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::TestResult, link_generator::LinkGenerator, space_config::SpaceConfig,
+        test_helpers::markdown_page_from_str_with_config,
+    };
+
+    #[test]
+    fn it_links_pages_to_their_source() -> TestResult {
+        let space_config = SpaceConfig::from_yaml_str(
+            "edit_link:\n  url: https://github.com/acme/docs/edit/{{ branch }}/docs/{{ source }}?plain=1&x=y\n",
+        )?;
+        let page = markdown_page_from_str_with_config(
+            "runbooks/restore.md",
+            "# Restore\n\nSteps\n",
+            &space_config,
+        )?;
+
+        assert_eq!(
+            page.render(&LinkGenerator::default_test())?.content,
+            "<p>Steps</p>\n<ac:structured-macro ac:name=\"info\"><ac:rich-text-body><p><a href=\"https://github.com/acme/docs/edit/main/docs/runbooks/restore.md?plain=1&amp;x=y\">Edit this page</a></p></ac:rich-text-body></ac:structured-macro>\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_needs_a_url() {
+        let result = SpaceConfig::from_yaml_str("edit_link:\n  branch: main\n");
+
+        assert!(format!("{:#}", result.unwrap_err()).contains("edit_link"));
+    }
+}
@@ -1,6 +1,8 @@
 use reqwest::{blocking::Response, StatusCode};
 use thiserror::Error;
 
+use crate::diagnostics::{Diagnostic, Diagnostics};
+
 #[derive(Error, Debug)]
 pub enum ConfluenceError {
     #[error("{0}")]
@@ -12,8 +14,33 @@ pub enum ConfluenceError {
         body_content: String,
     },
 
-    #[error("Failed to parse {filename}: {errors}")]
-    ParsingError { filename: String, errors: String },
+    #[error("Unauthorized. Check your API_USER/API_TOKEN and try again.")]
+    Unauthorized,
+
+    #[error("Not allowed: {message}")]
+    PermissionDenied { message: String },
+
+    #[error("Not found: {message}")]
+    NotFound { message: String },
+
+    #[error("{message}")]
+    TitleAlreadyExists { message: String },
+
+    /// The content changed in Confluence since it was read.
+    #[error("Version conflict: {message}")]
+    VersionConflict { message: String },
+
+    #[error("Rate limited by Confluence: {message}")]
+    RateLimited { message: String },
+
+    #[error("Failed to parse {filename}: {diagnostics}")]
+    ParsingError {
+        filename: String,
+        diagnostics: Diagnostics,
+    },
+
+    #[error("{diagnostics}")]
+    TemplateError { diagnostics: Diagnostics },
 
     #[error("Duplicate title '{title}' in [{file}]")]
     DuplicateTitle { title: String, file: String },
@@ -38,36 +65,130 @@ impl ConfluenceError {
 
     pub fn failed_request(response: Response) -> anyhow::Error {
         let status = response.status();
-        let body_content = match status {
-            StatusCode::UNAUTHORIZED => {
-                String::from("Unauthorized. Check your API_USER/API_TOKEN and try again.")
-            }
-            _ => {
-                let json: serde_json::Value = match response.json() {
-                    Ok(j) => j,
-                    Err(_) => todo!(),
-                };
-                json["errors"][0]["title"].to_string()
+        let body = response.text().unwrap_or_default();
+        Self::from_error_body(status, &body).into()
+    }
+
+    /// Turns an error response into the error it describes, from the messages in the body when
+    /// Confluence sent any.
+    pub fn from_error_body(status: StatusCode, body: &str) -> ConfluenceError {
+        let message = error_messages(body)
+            .unwrap_or_else(|| String::from(status.canonical_reason().unwrap_or("Unknown error")));
+        match status {
+            StatusCode::UNAUTHORIZED => ConfluenceError::Unauthorized,
+            StatusCode::FORBIDDEN => ConfluenceError::PermissionDenied { message },
+            StatusCode::NOT_FOUND => ConfluenceError::NotFound { message },
+            StatusCode::CONFLICT => ConfluenceError::VersionConflict { message },
+            StatusCode::TOO_MANY_REQUESTS => ConfluenceError::RateLimited { message },
+            StatusCode::BAD_REQUEST if message.contains("already exists") => {
+                ConfluenceError::TitleAlreadyExists { message }
             }
-        };
-        ConfluenceError::FailedRequest {
-            status,
-            body_content,
+            _ => ConfluenceError::FailedRequest {
+                status,
+                body_content: message,
+            },
         }
-        .into()
     }
 
-    pub fn parsing_errors(filename: impl Into<String>, errors: Vec<String>) -> anyhow::Error {
-        let errors = errors.join(", ");
+    pub fn parsing_errors(filename: impl Into<String>, errors: Vec<Diagnostic>) -> anyhow::Error {
+        let filename = filename.into();
         ConfluenceError::ParsingError {
-            filename: filename.into(),
-            errors,
+            filename: filename.clone(),
+            diagnostics: Diagnostics {
+                filename,
+                diagnostics: errors,
+            },
+        }
+        .into()
+    }
+
+    pub fn template_error(filename: impl Into<String>, error: Diagnostic) -> anyhow::Error {
+        ConfluenceError::TemplateError {
+            diagnostics: Diagnostics {
+                filename: filename.into(),
+                diagnostics: vec![error],
+            },
         }
         .into()
     }
 }
 
+/// The messages of an error body: `errors[].title` and `detail` of the v2 API, `message` of the
+/// v1 API.
+fn error_messages(body: &str) -> Option<String> {
+    let json: serde_json::Value = serde_json::from_str(body).ok()?;
+    let messages: Vec<String> = match json["errors"].as_array() {
+        Some(errors) => errors
+            .iter()
+            .filter_map(|error| {
+                let title = error["title"].as_str().filter(|title| !title.is_empty());
+                let detail = error["detail"].as_str().filter(|detail| !detail.is_empty());
+                match (title, detail) {
+                    (Some(title), Some(detail)) => Some(format!("{} ({})", title, detail)),
+                    (Some(message), None) | (None, Some(message)) => Some(String::from(message)),
+                    (None, None) => None,
+                }
+            })
+            .collect(),
+        None => json["message"]
+            .as_str()
+            .map(String::from)
+            .into_iter()
+            .collect(),
+    };
+    Some(messages.join("; ")).filter(|messages| !messages.is_empty())
+}
+
+/// Fails with the error the response describes, like `error_for_status` but with Confluence's
+/// message instead of just the status.
+pub trait ApiResponse: Sized {
+    fn api_result(self) -> Result<Self>;
+}
+
+impl ApiResponse for Response {
+    fn api_result(self) -> Result<Self> {
+        if self.status().is_client_error() || self.status().is_server_error() {
+            Err(ConfluenceError::failed_request(self))
+        } else {
+            Ok(self)
+        }
+    }
+}
+
 pub type Result<T> = anyhow::Result<T>;
 
 #[cfg(test)]
 pub type TestResult = Result<()>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reads_confluence_error_messages() {
+        let error = ConfluenceError::from_error_body(
+            StatusCode::BAD_REQUEST,
+            r#"{"errors":[{"status":400,"code":"INVALID_REQUEST_PARAMETER","title":"A page with this title already exists: A page already exists with the same TITLE in this space","detail":null}]}"#,
+        );
+        assert!(matches!(error, ConfluenceError::TitleAlreadyExists { .. }));
+        assert_eq!(
+            error.to_string(),
+            "A page with this title already exists: A page already exists with the same TITLE in this space"
+        );
+
+        let error = ConfluenceError::from_error_body(
+            StatusCode::CONFLICT,
+            r#"{"statusCode":409,"message":"Version must be incremented on update. Current Version is: 5"}"#,
+        );
+        assert_eq!(
+            error.to_string(),
+            "Version conflict: Version must be incremented on update. Current Version is: 5"
+        );
+
+        let error = ConfluenceError::from_error_body(StatusCode::BAD_GATEWAY, "<html>");
+        assert_eq!(
+            error.to_string(),
+            "Failed request 502 Bad Gateway: Bad Gateway"
+        );
+    }
+}
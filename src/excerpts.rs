@@ -0,0 +1,176 @@
+//! The `confluence_excerpt(page_id=...)` shortcode, which embeds the excerpt of a page that isn't
+//! published from markdown, e.g. an announcement maintained by another team.
+//!
+//! The page is fetched while the markdown is rendered, so the shortcode only works when that's
+//! allowed with `--fetch-excerpts`. Every page is fetched once per run.
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::anyhow;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    error::{ApiResponse, Result},
+    responses::{BodySingle, PageSingleWithBody},
+};
+
+/// The body of the excerpt macro named `name` (or the first one) in storage format.
+fn find_excerpt(storage: &str, name: Option<&str>) -> Option<String> {
+    static EXCERPT: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r#"(?s)<ac:structured-macro[^>]*ac:name="excerpt"[^>]*>(.*?)<ac:rich-text-body>(.*?)</ac:rich-text-body>"#)
+            .unwrap()
+    });
+    static NAME: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"<ac:parameter ac:name="name">([^<]*)</ac:parameter>"#).unwrap());
+
+    EXCERPT
+        .captures_iter(storage)
+        .find(|captures| match name {
+            Some(name) => NAME
+                .captures(&captures[1])
+                .is_some_and(|parameter| parameter[1].trim() == name),
+            None => true,
+        })
+        .map(|captures| captures[2].trim().to_string())
+}
+
+pub struct CachedExcerpts {
+    /// `None` when fetching pages isn't allowed.
+    client: Option<ConfluenceClient>,
+    bodies: RwLock<HashMap<String, Arc<String>>>,
+}
+
+impl CachedExcerpts {
+    pub fn new(client: ConfluenceClient, allowed: bool) -> CachedExcerpts {
+        CachedExcerpts {
+            client: allowed.then_some(client),
+            bodies: RwLock::new(HashMap::new()),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn disabled() -> CachedExcerpts {
+        CachedExcerpts {
+            client: None,
+            bodies: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn body(&self, page_id: &str) -> Result<Arc<String>> {
+        if let Some(body) = self.bodies.read().unwrap().get(page_id) {
+            return Ok(body.clone());
+        }
+        let client = self.client.as_ref().ok_or(anyhow!(
+            "confluence_excerpt() fetches pages from Confluence, run with --fetch-excerpts to allow it"
+        ))?;
+        let page: PageSingleWithBody = client.get_page_with_body(page_id)?.api_result()?.json()?;
+        let body = match page.body {
+            BodySingle::Storage(body) => Arc::new(body.value),
+            _ => return Err(anyhow!("Page {} has no storage format body", page_id)),
+        };
+        self.bodies
+            .write()
+            .unwrap()
+            .insert(String::from(page_id), body.clone());
+        Ok(body)
+    }
+
+    pub fn excerpt(&self, page_id: &str, name: Option<&str>) -> Result<String> {
+        let body = self.body(page_id)?;
+        find_excerpt(&body, name).ok_or(match name {
+            Some(name) => anyhow!("Page {} has no excerpt named \"{}\"", page_id, name),
+            None => anyhow!("Page {} has no excerpt", page_id),
+        })
+    }
+}
+
+impl tera::Function for CachedExcerpts {
+    fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+        let page_id = match args.get("page_id") {
+            Some(tera::Value::String(id)) => id.clone(),
+            Some(tera::Value::Number(id)) => id.to_string(),
+            _ => return Err(tera::Error::msg("Missing 'page_id'")),
+        };
+        let name = match args.get("name") {
+            None => None,
+            Some(tera::Value::String(name)) => Some(name.as_str()),
+            Some(_) => return Err(tera::Error::msg("name must be a string")),
+        };
+        self.excerpt(&page_id, name)
+            .map(tera::Value::from)
+            .map_err(|err| tera::Error::msg(err.to_string()))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    static PAGE: &str = r#"<p>Intro</p><ac:structured-macro ac:name="excerpt" ac:schema-version="1"><ac:parameter ac:name="name">short</ac:parameter><ac:rich-text-body><p>Short</p></ac:rich-text-body></ac:structured-macro><ac:structured-macro ac:name="excerpt" ac:schema-version="1"><ac:parameter ac:name="name">long</ac:parameter><ac:rich-text-body>
+<p>Long</p>
+</ac:rich-text-body></ac:structured-macro>"#;
+
+    #[test]
+    fn it_finds_excerpts_by_name() {
+        assert_eq!(find_excerpt(PAGE, None), Some(String::from("<p>Short</p>")));
+        assert_eq!(
+            find_excerpt(PAGE, Some("long")),
+            Some(String::from("<p>Long</p>"))
+        );
+        assert_eq!(find_excerpt(PAGE, Some("other")), None);
+        assert_eq!(find_excerpt("<p>Intro</p>", None), None);
+    }
+
+    #[test]
+    fn it_fetches_each_page_once() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let page = server
+            .mock("GET", "/wiki/api/v2/pages/42")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "body-format".into(),
+                "storage".into(),
+            ))
+            .with_status(200)
+            .with_body(
+                json!({
+                    "id": "42",
+                    "title": "Announcements",
+                    "version": {"number": 3, "message": ""},
+                    "body": {"storage": {"representation": "storage", "value": PAGE}}
+                })
+                .to_string(),
+            )
+            .expect(1)
+            .create();
+        let excerpts = CachedExcerpts::new(client, true);
+
+        assert_eq!(excerpts.excerpt("42", None)?, "<p>Short</p>");
+        assert_eq!(excerpts.excerpt("42", Some("long"))?, "<p>Long</p>");
+        page.assert();
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_needs_to_be_allowed() {
+        let excerpts = CachedExcerpts::new(ConfluenceClient::new("host.example.com"), false);
+
+        assert_eq!(
+            excerpts.excerpt("42", None).unwrap_err().to_string(),
+            "confluence_excerpt() fetches pages from Confluence, run with --fetch-excerpts to allow it"
+        );
+    }
+}
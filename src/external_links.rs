@@ -0,0 +1,432 @@
+//! Tidying up links to other sites before they are published, the `external_links` key of
+//! `_space.yml`:
+//!
+//! ```yaml
+//! external_links:
+//!   strip_parameters: [utm_*, fbclid]
+//!   internal_hosts: [wiki.example.com]
+//!   rewrite:
+//!     staging:
+//!       internal-git.example.com: git-staging.example.com
+//!     production:
+//!       internal-git.example.com: github.example.com
+//! ```
+//!
+//! Query parameters matching `strip_parameters` (globs) are removed, so tracking parameters
+//! copied along with a link don't end up in the space. Links to `internal_hosts` are normalized:
+//! https, a lowercase host and no default port. The `rewrite` map of the publish profile picked
+//! with `--profile` replaces hosts (or URL prefixes, for keys with a scheme), so the same markdown
+//! can point at different systems from the staging and the production space. Every link that was
+//! changed is listed in the report of the sync.
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::anyhow;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use reqwest::Url;
+use saphyr::Yaml;
+use serde::Serialize;
+
+use crate::{
+    console::print_info,
+    error::Result,
+    frontmatter::parse_string_list,
+    space_config::{parse_bool, parse_string_map},
+};
+
+#[derive(Debug, Clone)]
+pub struct ExternalLinks {
+    strip_parameters: Vec<String>,
+    parameter_globs: GlobSet,
+    internal_hosts: BTreeSet<String>,
+    /// Publish profile to the hosts or URL prefixes to replace and their replacements.
+    rewrite: BTreeMap<String, BTreeMap<String, String>>,
+    /// The rewrites of the profile in use, see [`ExternalLinks::for_profile`].
+    active_rewrites: BTreeMap<String, String>,
+    /// Links to pages of the space are resolved to the page they point at.
+    resolve_confluence_links: bool,
+}
+
+impl Default for ExternalLinks {
+    fn default() -> Self {
+        ExternalLinks {
+            strip_parameters: Vec::default(),
+            parameter_globs: GlobSet::empty(),
+            internal_hosts: BTreeSet::default(),
+            rewrite: BTreeMap::default(),
+            active_rewrites: BTreeMap::default(),
+            resolve_confluence_links: false,
+        }
+    }
+}
+
+impl PartialEq for ExternalLinks {
+    fn eq(&self, other: &Self) -> bool {
+        self.strip_parameters == other.strip_parameters
+            && self.internal_hosts == other.internal_hosts
+            && self.rewrite == other.rewrite
+            && self.active_rewrites == other.active_rewrites
+            && self.resolve_confluence_links == other.resolve_confluence_links
+    }
+}
+
+impl Eq for ExternalLinks {}
+
+/// A link that was published with another URL than the one in the markdown.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize)]
+pub struct RewrittenLink {
+    pub source: String,
+    pub from: String,
+    pub to: String,
+}
+
+impl ExternalLinks {
+    pub fn from_yaml(yaml: &Yaml) -> Result<Self> {
+        match yaml {
+            Yaml::BadValue | Yaml::Null => Ok(ExternalLinks::default()),
+            Yaml::Hash(_) => {
+                let strip_parameters = parse_string_list(
+                    &yaml["strip_parameters"],
+                    "external_links.strip_parameters",
+                )?;
+                let mut builder = GlobSetBuilder::new();
+                for pattern in strip_parameters.iter() {
+                    builder.add(Glob::new(pattern).map_err(|e| {
+                        anyhow!(
+                            "Failed to parse \"external_links.strip_parameters\" glob \"{}\": {}",
+                            pattern,
+                            e
+                        )
+                    })?);
+                }
+                let internal_hosts =
+                    parse_string_list(&yaml["internal_hosts"], "external_links.internal_hosts")?
+                        .into_iter()
+                        .map(|host| host.to_lowercase())
+                        .collect();
+                let rewrite = match &yaml["rewrite"] {
+                    Yaml::BadValue | Yaml::Null => BTreeMap::default(),
+                    Yaml::Hash(profiles) => profiles
+                        .iter()
+                        .map(|(profile, rewrites)| {
+                            let profile = profile.as_str().ok_or(anyhow!(
+                                "Failed to parse \"external_links.rewrite\" key (should be a map of profile names)"
+                            ))?;
+                            let key = format!("external_links.rewrite.{}", profile);
+                            let rewrites = parse_string_map(rewrites, &key)?
+                                .into_iter()
+                                // hosts are compared lowercase, like parsing leaves them
+                                .map(|(from, to)| {
+                                    if from.contains("://") {
+                                        (from, to)
+                                    } else {
+                                        (from.to_lowercase(), to)
+                                    }
+                                })
+                                .collect();
+                            Ok((String::from(profile), rewrites))
+                        })
+                        .collect::<Result<_>>()?,
+                    _ => {
+                        return Err(anyhow!(
+                            "Failed to parse \"external_links.rewrite\" key (should be a map of profile names)"
+                        ))
+                    }
+                };
+                Ok(ExternalLinks {
+                    strip_parameters,
+                    parameter_globs: builder.build()?,
+                    internal_hosts,
+                    rewrite,
+                    active_rewrites: BTreeMap::default(),
+                    resolve_confluence_links: parse_bool(
+                        &yaml["resolve_confluence_links"],
+                        "external_links.resolve_confluence_links",
+                    )?,
+                })
+            }
+            _ => Err(anyhow!(
+                "Failed to parse \"external_links\" key (should be a map with strip_parameters, internal_hosts, rewrite and/or resolve_confluence_links)"
+            )),
+        }
+    }
+
+    /// The same rules, with the rewrites of the publish profile `profile` (`--profile`).
+    pub fn for_profile(&self, profile: Option<&str>) -> Result<ExternalLinks> {
+        let active_rewrites = match profile {
+            None => BTreeMap::default(),
+            // without any rewrites there is nothing to pick
+            Some(_) if self.rewrite.is_empty() => BTreeMap::default(),
+            Some(profile) => self
+                .rewrite
+                .get(profile)
+                .ok_or(anyhow!(
+                    "No external_links.rewrite for profile \"{}\" in the space config (has {})",
+                    profile,
+                    self.rewrite
+                        .keys()
+                        .map(String::as_str)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))?
+                .clone(),
+        };
+        Ok(ExternalLinks {
+            active_rewrites,
+            ..self.clone()
+        })
+    }
+
+    /// Whether links to pages of the space are resolved, see [`confluence_link`].
+    pub fn resolves_confluence_links(&self) -> bool {
+        self.resolve_confluence_links
+    }
+
+    /// The URL to publish instead of `url`, if it needs tidying up.
+    pub fn rewrite(&self, url: &str) -> Option<String> {
+        let prefix = self
+            .active_rewrites
+            .iter()
+            .find(|(from, _)| from.contains("://") && url.starts_with(from.as_str()));
+        let mut changed = prefix.is_some();
+        let mut parsed = match prefix {
+            Some((from, to)) => Url::parse(&format!("{}{}", to, &url[from.len()..])).ok()?,
+            None => Url::parse(url).ok()?,
+        };
+        if let Some(to) = parsed
+            .host_str()
+            .and_then(|host| self.active_rewrites.get(host))
+            .cloned()
+        {
+            parsed.set_host(Some(&to)).ok()?;
+            changed = true;
+        }
+
+        if parsed.query().is_some() && !self.strip_parameters.is_empty() {
+            let pairs: Vec<(String, String)> = parsed
+                .query_pairs()
+                .map(|(name, value)| (name.into_owned(), value.into_owned()))
+                .collect();
+            let kept: Vec<&(String, String)> = pairs
+                .iter()
+                .filter(|(name, _)| !self.parameter_globs.is_match(name))
+                .collect();
+            if kept.len() < pairs.len() {
+                changed = true;
+                if kept.is_empty() {
+                    parsed.set_query(None);
+                } else {
+                    parsed.query_pairs_mut().clear().extend_pairs(kept);
+                }
+            }
+        }
+
+        let internal = parsed
+            .host_str()
+            .is_some_and(|host| self.internal_hosts.contains(host));
+        if internal && parsed.scheme() == "http" {
+            // both schemes are special, so this can't fail
+            let _ = parsed.set_scheme("https");
+        }
+        // parsing lowercases the host and drops the default port already
+        changed |= internal && parsed.as_str() != url;
+
+        changed.then(|| parsed.to_string())
+    }
+}
+
+/// A link into the Confluence site the space is published to.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConfluenceLink {
+    pub page_id: String,
+    /// Whether the link names the space, which `/x/` short links and `viewpage.action` don't.
+    pub in_space: bool,
+    pub fragment: Option<String>,
+}
+
+/// The id of the page behind a `/x/` short link: the little-endian bytes of the id in base64,
+/// with `-` for `/`, `_` for `+` and the trailing zeroes left out.
+fn short_link_page_id(code: &str) -> Option<String> {
+    if code.is_empty() || code.len() > 11 {
+        return None;
+    }
+    let mut base64: String = code
+        .chars()
+        .map(|c| match c {
+            '-' => '/',
+            '_' => '+',
+            c => c,
+        })
+        .collect();
+    while base64.len() < 11 {
+        base64.push('A');
+    }
+    let bytes: [u8; 8] = data_encoding::BASE64_NOPAD
+        .decode(base64.as_bytes())
+        .ok()?
+        .try_into()
+        .ok()?;
+    match u64::from_le_bytes(bytes) {
+        0 => None,
+        id => Some(id.to_string()),
+    }
+}
+
+/// The page a link on `host` points at: `/wiki/spaces/{space_key}/pages/{id}`, with or without
+/// the title after it, `/wiki/pages/viewpage.action?pageId={id}` or a `/wiki/x/` short link.
+pub fn confluence_link(url: &str, host: &str, space_key: &str) -> Option<ConfluenceLink> {
+    let parsed = Url::parse(url).ok()?;
+    let link_host = match parsed.port() {
+        Some(port) => format!("{}:{}", parsed.host_str()?, port),
+        None => parsed.host_str()?.to_string(),
+    };
+    if !link_host.eq_ignore_ascii_case(host) {
+        return None;
+    }
+    let segments: Vec<&str> = parsed.path_segments()?.collect();
+    let (page_id, in_space) = match segments.as_slice() {
+        ["wiki", "spaces", key, "pages", id, ..] if *key == space_key => (String::from(*id), true),
+        ["wiki", "pages", "viewpage.action"] => (
+            parsed
+                .query_pairs()
+                .find(|(name, _)| name == "pageId")?
+                .1
+                .into_owned(),
+            false,
+        ),
+        ["wiki", "x", code] => (short_link_page_id(code)?, false),
+        _ => return None,
+    };
+    if page_id.is_empty() || !page_id.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(ConfluenceLink {
+        page_id,
+        in_space,
+        fragment: parsed.fragment().map(String::from),
+    })
+}
+
+/// Lists the links that were changed, so the markdown can be fixed at the source.
+pub fn print_rewritten_links(rewritten_links: &[RewrittenLink]) {
+    if rewritten_links.is_empty() {
+        return;
+    }
+    print_info(&format!(
+        "Rewrote {} external link(s):",
+        rewritten_links.len()
+    ));
+    for link in rewritten_links {
+        print_info(&format!("  [{}] {} -> {}", link.source, link.from, link.to));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_strips_parameters_and_normalizes_internal_links() -> crate::error::TestResult {
+        let docs = Yaml::load_from_str(
+            "strip_parameters: [utm_*, fbclid]\ninternal_hosts: [Wiki.example.com]\n",
+        )?;
+        let external_links = ExternalLinks::from_yaml(&docs[0])?;
+
+        assert_eq!(
+            external_links
+                .rewrite("https://blog.example.org/post?id=7&utm_source=slack&utm_medium=chat#top")
+                .as_deref(),
+            Some("https://blog.example.org/post?id=7#top")
+        );
+        assert_eq!(
+            external_links
+                .rewrite("https://blog.example.org/post?fbclid=abc")
+                .as_deref(),
+            Some("https://blog.example.org/post")
+        );
+        assert_eq!(
+            external_links
+                .rewrite("http://WIKI.example.com:80/display/TEAM")
+                .as_deref(),
+            Some("https://wiki.example.com/display/TEAM")
+        );
+        assert_eq!(external_links.rewrite("https://WIKI.example.org"), None);
+        assert_eq!(
+            external_links.rewrite("https://blog.example.org/post?id=7"),
+            None
+        );
+        assert_eq!(external_links.rewrite("mailto:someone@example.com"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rewrites_links_for_the_profile() -> crate::error::TestResult {
+        let docs = Yaml::load_from_str(
+            "rewrite:\n  staging:\n    Internal-Git.example.com: git-staging.example.com\n  production:\n    internal-git.example.com: github.example.com\n    https://jira.example.com/browse/: https://example.atlassian.net/browse/\n",
+        )?;
+        let external_links = ExternalLinks::from_yaml(&docs[0])?;
+        let url = "https://internal-git.example.com/team/api/pulls?state=open";
+
+        assert_eq!(external_links.for_profile(None)?.rewrite(url), None);
+        assert_eq!(
+            external_links
+                .for_profile(Some("staging"))?
+                .rewrite(url)
+                .as_deref(),
+            Some("https://git-staging.example.com/team/api/pulls?state=open")
+        );
+        let production = external_links.for_profile(Some("production"))?;
+        assert_eq!(
+            production.rewrite(url).as_deref(),
+            Some("https://github.example.com/team/api/pulls?state=open")
+        );
+        assert_eq!(
+            production
+                .rewrite("https://jira.example.com/browse/TEAM-42")
+                .as_deref(),
+            Some("https://example.atlassian.net/browse/TEAM-42")
+        );
+        assert!(external_links.for_profile(Some("qa")).is_err());
+        assert_eq!(
+            ExternalLinks::default().for_profile(Some("qa"))?,
+            ExternalLinks::default()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_the_page_of_confluence_links() {
+        let link = |url| {
+            confluence_link(url, "example.atlassian.net", "TEST").map(|link| {
+                (
+                    link.page_id,
+                    link.in_space,
+                    link.fragment.unwrap_or_default(),
+                )
+            })
+        };
+
+        assert_eq!(
+            link("https://example.atlassian.net/wiki/spaces/TEST/pages/123/Old+Title#Usage"),
+            Some((String::from("123"), true, String::from("Usage")))
+        );
+        assert_eq!(
+            link("https://Example.atlassian.net/wiki/pages/viewpage.action?pageId=456"),
+            Some((String::from("456"), false, String::default()))
+        );
+        assert_eq!(
+            link("https://example.atlassian.net/wiki/x/KgA"),
+            Some((String::from("42"), false, String::default()))
+        );
+        assert_eq!(
+            link("https://example.atlassian.net/wiki/x/LoB1"),
+            Some((String::from("7700526"), false, String::default()))
+        );
+        assert_eq!(
+            link("https://example.atlassian.net/wiki/spaces/OTHER/pages/123"),
+            None
+        );
+        assert_eq!(link("https://other.example.com/wiki/x/KgA"), None);
+    }
+}
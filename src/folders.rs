@@ -4,10 +4,9 @@ use crate::{
     confluence_client::ConfluenceClient,
     confluence_space::ConfluenceSpace,
     console::{print_status, Status::Updated},
-    error::Result,
+    error::{ApiResponse, Result},
     link_generator::LinkGenerator,
     markdown_page::MarkdownPage,
-    parent::get_parent_file,
 };
 
 pub fn sync_folder(
@@ -20,9 +19,7 @@ pub fn sync_folder(
         .get_file_id(&PathBuf::from(&markdown_page.source))
         .expect("error: All pages should have been created already.");
 
-    let parent_id = get_parent_file(&PathBuf::from(&markdown_page.source))
-        .and_then(|f| link_generator.get_file_id(&f))
-        .or(Some(space.homepage_id.clone()));
+    let parent_id = link_generator.get_parent_id(&PathBuf::from(&markdown_page.source));
 
     let existing_folder = space
         .get_existing_node(&page_id)
@@ -32,10 +29,10 @@ pub fn sync_folder(
         return Err(anyhow::anyhow!("{} is a page and cannot be converted to a folder at this time. Please remove the page to create the folder.", existing_folder.title));
     }
 
-    if existing_folder.parent_id != parent_id {
+    if let Some(parent_id) = parent_id.filter(|p| existing_folder.parent_id.as_ref() != Some(p)) {
         confluence_client
-            .move_page(&page_id, &parent_id.unwrap())?
-            .error_for_status()?;
+            .move_page(&page_id, &parent_id)?
+            .api_result()?;
 
         print_status(
             Updated,
@@ -48,7 +45,6 @@ pub fn sync_folder(
 
 #[cfg(test)]
 mod test {
-    use comrak::{nodes::AstNode, Arena};
 
     use crate::{
         confluence_page::{ConfluenceFolder, ConfluenceNode, ConfluenceNodeType},
@@ -64,11 +60,9 @@ mod test {
         let new_title = String::from("New Title");
         let new_source = String::from("new-test.md");
 
-        let arena = Arena::<AstNode>::new();
         link_generator.register_markdown_page(&markdown_page_from_str(
             &new_source,
             &format!("---\nfolder: true\n---\n# {} \n", new_title),
-            &arena,
         )?)?;
 
         let pages_to_create = link_generator.get_nodes_to_create();
@@ -86,11 +80,9 @@ mod test {
         let existing_title = String::from("Existing Title");
         let existing_source = String::from("existing-test.md");
 
-        let arena = Arena::<AstNode>::new();
         link_generator.register_markdown_page(&markdown_page_from_str(
             &existing_source,
             &format!("---\nfolder: true\n---\n# {} \n", existing_title),
-            &arena,
         )?)?;
 
         link_generator.register_confluence_node(&ConfluenceNode {
@@ -0,0 +1,611 @@
+//! Front matter in TOML (between `+++` lines) and JSON (an object at the top of the file), as
+//! Hugo and Zola write it, turned into the YAML the rest of the front matter parsing reads.
+//!
+//! The TOML parser covers what front matter uses: key/value pairs with dotted and quoted keys,
+//! strings, numbers, booleans, dates, arrays, inline tables, `[tables]` and `[[arrays of
+//! tables]]`. Dates are kept as strings, like YAML does. A table defined twice and an inline
+//! table spread over several lines are errors, as TOML has them.
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use saphyr::{Hash, Yaml};
+use serde_json::Value;
+
+use crate::Result;
+
+pub fn json_to_yaml(json: &str) -> Result<Yaml> {
+    let value: Value = serde_json::from_str(json)?;
+    if !value.is_object() {
+        return Err(anyhow!("Expected JSON object for front matter"));
+    }
+    Ok(json_value_to_yaml(value))
+}
+
+fn json_value_to_yaml(value: Value) -> Yaml {
+    match value {
+        Value::Null => Yaml::Null,
+        Value::Bool(b) => Yaml::Boolean(b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Yaml::Integer(i),
+            None => Yaml::Real(n.to_string()),
+        },
+        Value::String(s) => Yaml::String(s),
+        Value::Array(values) => Yaml::Array(values.into_iter().map(json_value_to_yaml).collect()),
+        Value::Object(map) => Yaml::Hash(
+            map.into_iter()
+                .map(|(key, value)| (Yaml::String(key), json_value_to_yaml(value)))
+                .collect(),
+        ),
+    }
+}
+
+pub fn toml_to_yaml(toml: &str) -> Result<Yaml> {
+    let mut parser = TomlParser {
+        chars: toml.chars().collect(),
+        pos: 0,
+        line: 1,
+    };
+    parser
+        .document()
+        .map_err(|err| anyhow!("line {}: {}", parser.line, err))
+}
+
+struct TomlParser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+/// The table at `path` under `root`, made when it isn't there yet. An array of tables stands for
+/// its last table.
+fn table_at<'t>(root: &'t mut Hash, path: &[String]) -> Result<&'t mut Hash> {
+    let mut table = root;
+    for key in path {
+        let entry = table
+            .entry(Yaml::String(key.clone()))
+            .or_insert_with(|| Yaml::Hash(Hash::new()));
+        table = match entry {
+            Yaml::Hash(hash) => hash,
+            Yaml::Array(array) => match array.last_mut() {
+                Some(Yaml::Hash(hash)) => hash,
+                _ => return Err(anyhow!("{} is not a table", key)),
+            },
+            _ => return Err(anyhow!("{} is not a table", key)),
+        };
+    }
+    Ok(table)
+}
+
+fn insert(table: &mut Hash, key: &str, value: Yaml) -> Result<()> {
+    let key = Yaml::String(key.to_string());
+    if table.contains_key(&key) {
+        return Err(anyhow!("{} is defined twice", key.as_str().unwrap()));
+    }
+    table.insert(key, value);
+    Ok(())
+}
+
+impl TomlParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_str(&self, s: &str) -> bool {
+        s.chars()
+            .enumerate()
+            .all(|(offset, c)| self.chars.get(self.pos + offset) == Some(&c))
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(anyhow!("expected {} but found {}", expected, c)),
+            None => Err(anyhow!("expected {} but the front matter ended", expected)),
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.next();
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.next();
+            }
+        }
+    }
+
+    /// Skips whitespace, newlines and comments, as between the items of an array.
+    fn skip_blank(&mut self) {
+        loop {
+            self.skip_spaces();
+            self.skip_comment();
+            match self.peek() {
+                Some('\n' | '\r') => {
+                    self.next();
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<()> {
+        self.skip_spaces();
+        self.skip_comment();
+        match self.peek() {
+            None | Some('\n') => Ok(()),
+            Some('\r') if self.chars.get(self.pos + 1) == Some(&'\n') => Ok(()),
+            Some(c) => Err(anyhow!("expected the end of the line but found {}", c)),
+        }
+    }
+
+    fn document(&mut self) -> Result<Yaml> {
+        let mut root = Hash::new();
+        let mut current = Vec::<String>::default();
+        // `[tables]` can only be defined once, the tables of every `[[array]]` item start over
+        let mut defined = HashSet::<Vec<String>>::default();
+        loop {
+            self.skip_blank();
+            match self.peek() {
+                None => break,
+                Some('[') => {
+                    self.next();
+                    let array = self.peek() == Some('[');
+                    if array {
+                        self.next();
+                    }
+                    self.skip_spaces();
+                    let path = self.key()?;
+                    self.skip_spaces();
+                    self.expect(']')?;
+                    if array {
+                        self.expect(']')?;
+                        defined.retain(|table| !table.starts_with(&path));
+                        let (last, parent) = path.split_last().unwrap();
+                        let entry = table_at(&mut root, parent)?
+                            .entry(Yaml::String(last.clone()))
+                            .or_insert_with(|| Yaml::Array(Vec::default()));
+                        match entry {
+                            Yaml::Array(tables) => tables.push(Yaml::Hash(Hash::new())),
+                            _ => return Err(anyhow!("{} is not an array of tables", last)),
+                        }
+                    } else {
+                        if !defined.insert(path.clone()) {
+                            return Err(anyhow!("[{}] is defined twice", path.join(".")));
+                        }
+                        table_at(&mut root, &path)?;
+                    }
+                    self.end_of_line()?;
+                    current = path;
+                }
+                Some(_) => {
+                    let (key, value) = self.key_value()?;
+                    let (last, parent) = key.split_last().unwrap();
+                    let path: Vec<String> = current.iter().chain(parent).cloned().collect();
+                    insert(table_at(&mut root, &path)?, last, value)?;
+                    self.end_of_line()?;
+                }
+            }
+        }
+        Ok(Yaml::Hash(root))
+    }
+
+    fn key_value(&mut self) -> Result<(Vec<String>, Yaml)> {
+        let key = self.key()?;
+        self.skip_spaces();
+        self.expect('=')?;
+        self.skip_spaces();
+        Ok((key, self.value()?))
+    }
+
+    /// A dotted key, `a."b.c".d` is `["a", "b.c", "d"]`.
+    fn key(&mut self) -> Result<Vec<String>> {
+        let mut parts = Vec::default();
+        loop {
+            self.skip_spaces();
+            let part = match self.peek() {
+                Some('"') => self.basic_string()?,
+                Some('\'') => self.literal_string()?,
+                _ => {
+                    let start = self.pos;
+                    while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                    {
+                        self.next();
+                    }
+                    if self.pos == start {
+                        return Err(match self.peek() {
+                            Some(c) => anyhow!("expected a key but found {}", c),
+                            None => anyhow!("expected a key but the front matter ended"),
+                        });
+                    }
+                    self.chars[start..self.pos].iter().collect()
+                }
+            };
+            parts.push(part);
+            self.skip_spaces();
+            if self.peek() != Some('.') {
+                return Ok(parts);
+            }
+            self.next();
+        }
+    }
+
+    fn value(&mut self) -> Result<Yaml> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Yaml::String),
+            Some('\'') => self.literal_string().map(Yaml::String),
+            Some('[') => self.array(),
+            Some('{') => self.inline_table(),
+            Some(_) => self.scalar(),
+            None => Err(anyhow!("expected a value but the front matter ended")),
+        }
+    }
+
+    fn array(&mut self) -> Result<Yaml> {
+        self.expect('[')?;
+        let mut values = Vec::default();
+        loop {
+            self.skip_blank();
+            if self.peek() == Some(']') {
+                self.next();
+                return Ok(Yaml::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip_blank();
+            match self.next() {
+                Some(',') => {}
+                Some(']') => return Ok(Yaml::Array(values)),
+                Some(c) => return Err(anyhow!("expected , or ] in array but found {}", c)),
+                None => return Err(anyhow!("array is never closed")),
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Yaml> {
+        self.expect('{')?;
+        let mut table = Hash::new();
+        self.skip_spaces();
+        if self.peek() == Some('}') {
+            self.next();
+            return Ok(Yaml::Hash(table));
+        }
+        loop {
+            self.skip_spaces();
+            if matches!(self.peek(), Some('\n' | '\r')) {
+                return Err(anyhow!("inline tables must be on a single line"));
+            }
+            let (key, value) = self.key_value()?;
+            let (last, parent) = key.split_last().unwrap();
+            insert(table_at(&mut table, parent)?, last, value)?;
+            self.skip_spaces();
+            match self.next() {
+                Some(',') => {}
+                Some('}') => return Ok(Yaml::Hash(table)),
+                Some(c) => return Err(anyhow!("expected , or }} in inline table but found {}", c)),
+                None => return Err(anyhow!("inline table is never closed")),
+            }
+        }
+    }
+
+    /// A number, boolean or date, up to the next separator.
+    fn scalar(&mut self) -> Result<Yaml> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            // dates can have a space between the date and the time
+            let date_time_space = c == ' '
+                && self.pos > start
+                && self.chars[self.pos - 1].is_ascii_digit()
+                && self
+                    .chars
+                    .get(self.pos + 1)
+                    .is_some_and(char::is_ascii_digit)
+                && self.chars[start..self.pos].contains(&'-');
+            if matches!(c, ',' | ']' | '}' | '#' | '\n' | '\r' | '\t')
+                || (c == ' ' && !date_time_space)
+            {
+                break;
+            }
+            self.next();
+        }
+        let token: String = self.chars[start..self.pos].iter().collect();
+        match token.as_str() {
+            "true" => return Ok(Yaml::Boolean(true)),
+            "false" => return Ok(Yaml::Boolean(false)),
+            "inf" | "+inf" | "-inf" | "nan" | "+nan" | "-nan" => return Ok(Yaml::Real(token)),
+            _ => {}
+        }
+        let number = token.replace('_', "");
+        if let Some(hex) = number.strip_prefix("0x") {
+            return i64::from_str_radix(hex, 16)
+                .map(Yaml::Integer)
+                .map_err(|_| anyhow!("invalid number {}", token));
+        }
+        if let Ok(integer) = number.parse::<i64>() {
+            return Ok(Yaml::Integer(integer));
+        }
+        if number.parse::<f64>().is_ok() && !number.starts_with('.') && !number.ends_with('.') {
+            return Ok(Yaml::Real(number));
+        }
+        let is_date = token.len() >= 8
+            && token
+                .chars()
+                .all(|c| c.is_ascii_digit() || "-:T t.Z+z".contains(c))
+            && token.chars().filter(char::is_ascii_digit).count() >= 4;
+        if is_date {
+            return Ok(Yaml::String(token));
+        }
+        Err(match token.is_empty() {
+            true => anyhow!("expected a value"),
+            false => anyhow!("invalid value {} (strings need quotes)", token),
+        })
+    }
+
+    fn basic_string(&mut self) -> Result<String> {
+        let multiline = self.peek_str("\"\"\"");
+        if multiline {
+            self.pos += 3;
+            // a newline right after the opening quotes isn't part of the string
+            self.trim_newline();
+        } else {
+            self.next();
+        }
+        let mut string = String::default();
+        loop {
+            if multiline && self.peek_str("\"\"\"") {
+                self.pos += 3;
+                // up to two quotes right before the closing ones are part of the string
+                while self.peek() == Some('"') && !self.peek_str("\"\"\"") {
+                    string.push('"');
+                    self.next();
+                }
+                return Ok(string);
+            }
+            match self.next() {
+                Some('"') if !multiline => return Ok(string),
+                Some('\\') => {
+                    if multiline && matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+                        // a backslash at the end of a line trims the whitespace after it
+                        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+                            self.next();
+                        }
+                        continue;
+                    }
+                    string.push(self.escape()?);
+                }
+                Some('\n') if !multiline => return Err(anyhow!("string is never closed")),
+                Some(c) => string.push(c),
+                None => return Err(anyhow!("string is never closed")),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char> {
+        Ok(match self.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some(unicode @ ('u' | 'U')) => {
+                let length = if unicode == 'u' { 4 } else { 8 };
+                let hex: String = (0..length).filter_map(|_| self.next()).collect();
+                u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or(anyhow!("invalid unicode escape \\{}{}", unicode, hex))?
+            }
+            Some(c) => return Err(anyhow!("invalid escape \\{}", c)),
+            None => return Err(anyhow!("string is never closed")),
+        })
+    }
+
+    fn literal_string(&mut self) -> Result<String> {
+        let multiline = self.peek_str("'''");
+        if multiline {
+            self.pos += 3;
+            self.trim_newline();
+        } else {
+            self.next();
+        }
+        let mut string = String::default();
+        loop {
+            if multiline && self.peek_str("'''") {
+                self.pos += 3;
+                while self.peek() == Some('\'') && !self.peek_str("'''") {
+                    string.push('\'');
+                    self.next();
+                }
+                return Ok(string);
+            }
+            match self.next() {
+                Some('\'') if !multiline => return Ok(string),
+                Some('\n') if !multiline => return Err(anyhow!("string is never closed")),
+                Some(c) => string.push(c),
+                None => return Err(anyhow!("string is never closed")),
+            }
+        }
+    }
+
+    fn trim_newline(&mut self) {
+        if self.peek_str("\r\n") {
+            self.pos += 1;
+        }
+        if self.peek() == Some('\n') {
+            self.next();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn it_reads_toml() -> TestResult {
+        let yaml = toml_to_yaml(
+            r#"
+title = "Deploying" # comment
+labels = [
+  "runbook",
+  'ops', # trailing comma
+]
+draft = false
+weight = 1_000
+publish_after = 2024-09-01T09:00:00Z
+"quoted key" = """
+two
+lines"""
+
+[metadata]
+owner.team = "payments"
+limits = { cpu = 0.5, memory = "1Gi" }
+
+[[metadata.steps]]
+name = "build"
+
+[[metadata.steps]]
+name = "ship"
+"#,
+        )?;
+
+        assert_eq!(yaml["title"].as_str(), Some("Deploying"));
+        assert_eq!(yaml["labels"][1].as_str(), Some("ops"));
+        assert_eq!(yaml["labels"].as_vec().map(Vec::len), Some(2));
+        assert_eq!(yaml["draft"].as_bool(), Some(false));
+        assert_eq!(yaml["weight"].as_i64(), Some(1000));
+        assert_eq!(yaml["publish_after"].as_str(), Some("2024-09-01T09:00:00Z"));
+        assert_eq!(yaml["quoted key"].as_str(), Some("two\nlines"));
+        assert_eq!(yaml["metadata"]["owner"]["team"].as_str(), Some("payments"));
+        assert_eq!(yaml["metadata"]["limits"]["cpu"].as_f64(), Some(0.5));
+        assert_eq!(yaml["metadata"]["steps"][1]["name"].as_str(), Some("ship"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_invalid_toml_with_its_line() {
+        let error = toml_to_yaml("title = \"Title\"\nlabels = runbook\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 2: invalid value runbook (strings need quotes)"
+        );
+
+        let error = toml_to_yaml("emoji = \"fire\"\nemoji = \"rocket\"\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: emoji is defined twice");
+
+        let error = toml_to_yaml("[a]\nb = 1\n\n[a]\nc = 2\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 4: [a] is defined twice");
+
+        let error = toml_to_yaml("a = 1\n[a.b]\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: a is not a table");
+
+        let error = toml_to_yaml("a = [1, 2\nb = 3\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 2: expected , or ] in array but found b"
+        );
+
+        let error = toml_to_yaml("a = { b = 1,\n c = 2 }\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 1: inline tables must be on a single line"
+        );
+    }
+
+    #[test]
+    fn it_reads_nested_tables() -> TestResult {
+        let yaml = toml_to_yaml(
+            r#"
+[a.b.c]
+d = 1
+
+[a]
+e = 2
+
+[a.b]
+f = { g = { h = 3 } }
+
+[[steps]]
+name = "build"
+[steps.env]
+target = "release"
+
+[[steps]]
+name = "ship"
+[steps.env]
+target = "prod"
+"#,
+        )?;
+
+        assert_eq!(yaml["a"]["b"]["c"]["d"].as_i64(), Some(1));
+        assert_eq!(yaml["a"]["e"].as_i64(), Some(2));
+        assert_eq!(yaml["a"]["b"]["f"]["g"]["h"].as_i64(), Some(3));
+        assert_eq!(yaml["steps"][0]["env"]["target"].as_str(), Some("release"));
+        assert_eq!(yaml["steps"][1]["env"]["target"].as_str(), Some("prod"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_nested_arrays() -> TestResult {
+        let yaml = toml_to_yaml(
+            r#"
+matrix = [[1, 2], ["a", 'b'], []]
+owners = [
+  { name = "Ada", teams = ["core", "docs"] }, # a comment
+  { name = "Linus" },
+]
+"#,
+        )?;
+
+        assert_eq!(yaml["matrix"][0][1].as_i64(), Some(2));
+        assert_eq!(yaml["matrix"][1][1].as_str(), Some("b"));
+        assert_eq!(yaml["matrix"][2].as_vec().map(Vec::len), Some(0));
+        assert_eq!(yaml["owners"][0]["teams"][1].as_str(), Some("docs"));
+        assert_eq!(yaml["owners"][1]["name"].as_str(), Some("Linus"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_quoted_keys() -> TestResult {
+        let yaml = toml_to_yaml(
+            r#"
+"with space" = 1
+'literal "quotes"' = 2
+site."docs.example.com".path = "/runbooks"
+"escapedA" = 3
+
+["quoted table".'sub.table']
+key = 4
+"#,
+        )?;
+
+        assert_eq!(yaml["with space"].as_i64(), Some(1));
+        assert_eq!(yaml["literal \"quotes\""].as_i64(), Some(2));
+        assert_eq!(
+            yaml["site"]["docs.example.com"]["path"].as_str(),
+            Some("/runbooks")
+        );
+        assert_eq!(yaml["escapedA"].as_i64(), Some(3));
+        assert_eq!(yaml["quoted table"]["sub.table"]["key"].as_i64(), Some(4));
+
+        Ok(())
+    }
+}
@@ -1,6 +1,14 @@
 use saphyr::Yaml;
 
-use crate::{page_covers::Cover, sort::Sort, Result};
+use crate::{
+    front_matter_formats::{json_to_yaml, toml_to_yaml},
+    page_covers::Cover,
+    page_split::parse_split,
+    publish_schedule::parse_publish_after,
+    sort::Sort,
+    Result,
+};
+use chrono::{DateTime, Utc};
 use std::{
     collections::HashSet,
     io::{self, BufRead},
@@ -25,11 +33,40 @@ pub struct FrontMatter {
     pub imports: Vec<String>,
     pub folder: bool,
     pub sort: Sort,
+    pub watchers: Vec<String>,
+    pub owners: Vec<String>,
+    /// The `type` key, e.g. runbook.
+    pub page_type: Option<String>,
+    /// Render the page with tera, `template: false` keeps `{{` and `{%` as they are.
+    pub template: bool,
+    /// Don't create or update the page before this time.
+    pub publish_after: Option<DateTime<Utc>>,
+    /// Publish every `##` section as a child page, `split: h2`.
+    pub split: bool,
+    /// Files (relative to the page) whose content is appended to the page, in this order.
+    pub merge: Vec<String>,
+    /// `publish: false` keeps the page (and the pages below it) out of Confluence.
+    pub publish: bool,
+    /// `snippet: true` files aren't published, other pages include them.
+    pub snippet: bool,
+    /// CSS for the whole page, published with the `styles.page` template of the space.
+    pub style: Option<String>,
+    /// Number of lines before the markdown (the front matter), to point at lines in the file.
+    pub body_line: usize,
+}
+
+/// The language of the front matter, told apart by how it starts: `---` for YAML, `+++` for TOML
+/// and `{` for a JSON object.
+#[derive(Clone, Copy)]
+enum FrontMatterFormat {
+    Yaml,
+    Toml,
+    Json,
 }
 
 enum FrontMatterParseState {
     Before,
-    Inside,
+    Inside(FrontMatterFormat),
     After,
 }
 
@@ -44,6 +81,17 @@ impl Default for FrontMatter {
             folder: false,
             sort: Sort::Unsorted,
             cover: None,
+            watchers: Vec::default(),
+            owners: Vec::default(),
+            page_type: None,
+            template: true,
+            publish_after: None,
+            split: false,
+            merge: Vec::default(),
+            publish: true,
+            snippet: false,
+            style: None,
+            body_line: 0,
         }
     }
 }
@@ -59,25 +107,56 @@ impl FrontMatter {
         let mut front_matter_str = String::new();
         let mut content_str = String::new();
         let mut state = FrontMatterParseState::Before;
+        let mut format = FrontMatterFormat::Yaml;
+        let mut body_line = 0;
         let lines = reader.lines();
-        for line in lines.map_while(io::Result::ok) {
+        for (index, line) in lines.map_while(io::Result::ok).enumerate() {
             match state {
                 FrontMatterParseState::Before => {
                     let trimmed_line = line.trim();
                     if trimmed_line == "---" {
-                        state = FrontMatterParseState::Inside;
+                        state = FrontMatterParseState::Inside(FrontMatterFormat::Yaml);
+                    } else if trimmed_line == "+++" {
+                        format = FrontMatterFormat::Toml;
+                        state = FrontMatterParseState::Inside(format);
+                    } else if trimmed_line == "{" || trimmed_line.starts_with("{\"") {
+                        // `{{` and `{%` are templates, a JSON object starts with a key
+                        format = FrontMatterFormat::Json;
+                        front_matter_str.push_str(&line);
+                        front_matter_str += "\n";
+                        state = match is_complete_json(&front_matter_str) {
+                            true => {
+                                body_line = index + 1;
+                                FrontMatterParseState::After
+                            }
+                            false => FrontMatterParseState::Inside(format),
+                        };
                     } else if !trimmed_line.is_empty() {
                         // found non frontmatter marker, assuming no front matter
                         state = FrontMatterParseState::After;
+                        body_line = index;
                         content_str.push_str(&line);
                         content_str += "\n";
                     } else {
                         // whitespace before front matter
                     }
                 }
-                FrontMatterParseState::Inside => {
-                    if line.starts_with("---") {
+                FrontMatterParseState::Inside(FrontMatterFormat::Json) => {
+                    front_matter_str.push_str(&line);
+                    front_matter_str += "\n";
+                    if is_complete_json(&front_matter_str) {
+                        state = FrontMatterParseState::After;
+                        body_line = index + 1;
+                    }
+                }
+                FrontMatterParseState::Inside(format) => {
+                    let closing = match format {
+                        FrontMatterFormat::Toml => "+++",
+                        _ => "---",
+                    };
+                    if line.starts_with(closing) {
                         state = FrontMatterParseState::After;
+                        body_line = index + 1;
                     } else {
                         front_matter_str.push_str(&line);
                         front_matter_str += "\n";
@@ -90,10 +169,23 @@ impl FrontMatter {
             }
         }
 
-        let yaml_fm_docs = Yaml::load_from_str(&front_matter_str)
-            .context("Failed to parse front matter as YAML")?;
+        let yaml_fm_docs =
+            match format {
+                FrontMatterFormat::Yaml => Yaml::load_from_str(&front_matter_str)
+                    .context("Failed to parse front matter as YAML")?,
+                FrontMatterFormat::Toml => vec![toml_to_yaml(&front_matter_str)
+                    .context("Failed to parse front matter as TOML")?],
+                FrontMatterFormat::Json => vec![json_to_yaml(&front_matter_str)
+                    .context("Failed to parse front matter as JSON")?],
+            };
         if yaml_fm_docs.is_empty() {
-            return Ok((FrontMatter::default(), content_str));
+            return Ok((
+                FrontMatter {
+                    body_line,
+                    ..Default::default()
+                },
+                content_str,
+            ));
         }
         let yaml_fm = &yaml_fm_docs[0];
         if !yaml_fm.is_hash() {
@@ -103,8 +195,24 @@ impl FrontMatter {
             .into());
         }
 
-        static VALID_TOP_LEVEL_KEYS: [&str; 7] = [
-            "emoji", "labels", "metadata", "imports", "folder", "sort", "cover",
+        static VALID_TOP_LEVEL_KEYS: [&str; 17] = [
+            "emoji",
+            "labels",
+            "metadata",
+            "imports",
+            "folder",
+            "sort",
+            "cover",
+            "watchers",
+            "owners",
+            "type",
+            "template",
+            "publish_after",
+            "split",
+            "merge",
+            "publish",
+            "snippet",
+            "style",
         ];
         let string_keys: HashSet<&str> = yaml_fm
             .as_hash()
@@ -138,6 +246,9 @@ impl FrontMatter {
             })
             .unwrap_or_default();
 
+        let watchers = parse_string_list(&yaml_fm["watchers"], "watchers")?;
+        let owners = parse_string_list(&yaml_fm["owners"], "owners")?;
+
         let folder = yaml_fm["folder"]
             .borrowed_or(&Yaml::Boolean(false))
             .as_bool()
@@ -145,12 +256,59 @@ impl FrontMatter {
                 "Failed to parse \"folder\" key (should be true/false)"
             ))?;
 
+        let template = yaml_fm["template"]
+            .borrowed_or(&Yaml::Boolean(true))
+            .as_bool()
+            .ok_or(anyhow::anyhow!(
+                "Failed to parse \"template\" key (should be true/false)"
+            ))?;
+
+        let publish = yaml_fm["publish"]
+            .borrowed_or(&Yaml::Boolean(true))
+            .as_bool()
+            .ok_or(anyhow::anyhow!(
+                "Failed to parse \"publish\" key (should be true/false)"
+            ))?;
+
+        let snippet = yaml_fm["snippet"]
+            .borrowed_or(&Yaml::Boolean(false))
+            .as_bool()
+            .ok_or(anyhow::anyhow!(
+                "Failed to parse \"snippet\" key (should be true/false)"
+            ))?;
+
         let emoji = String::from(yaml_fm["emoji"].as_str().unwrap_or_default());
 
+        let page_type = match &yaml_fm["type"] {
+            Yaml::BadValue | Yaml::Null => None,
+            Yaml::String(page_type) => Some(page_type.clone()),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Failed to parse \"type\" key (should be a string)"
+                ))
+            }
+        };
+
+        let style = match &yaml_fm["style"] {
+            Yaml::BadValue | Yaml::Null => None,
+            Yaml::String(style) => Some(style.clone()),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Failed to parse \"style\" key (should be a string of CSS)"
+                ))
+            }
+        };
+
         let sort = Sort::from_str(yaml_fm["sort"].as_str())?;
 
         let cover = Cover::from_yaml(&yaml_fm["cover"])?;
 
+        let publish_after = parse_publish_after(&yaml_fm["publish_after"])?;
+
+        let split = parse_split(yaml_fm["split"].as_str())?;
+
+        let merge = parse_string_list(&yaml_fm["merge"], "merge")?;
+
         Ok((
             FrontMatter {
                 labels,
@@ -161,12 +319,72 @@ impl FrontMatter {
                 folder,
                 sort,
                 cover,
+                watchers,
+                owners,
+                page_type,
+                template,
+                publish_after,
+                split,
+                merge,
+                publish,
+                snippet,
+                style,
+                body_line,
             },
             content_str,
         ))
     }
 }
 
+impl FrontMatter {
+    /// Whether a field is set, `metadata.some.path` looks up a nested metadata value.
+    pub fn has_field(&self, field: &str) -> bool {
+        match field {
+            "labels" => !self.labels.is_empty(),
+            "emoji" => !self.emoji.is_empty(),
+            "cover" => self.cover.is_some(),
+            "imports" => !self.imports.is_empty(),
+            "watchers" => !self.watchers.is_empty(),
+            "owners" => !self.owners.is_empty(),
+            "metadata" => !matches!(self.metadata, Yaml::Null | Yaml::BadValue),
+            _ => match field.strip_prefix("metadata.") {
+                Some(path) => !matches!(
+                    path.split('.').fold(&self.metadata, |yaml, key| &yaml[key]),
+                    Yaml::Null | Yaml::BadValue
+                ),
+                None => false,
+            },
+        }
+    }
+}
+
+/// People are listed by account ID or by anything the Confluence user search matches (public name
+/// or email).
+/// Whether the JSON front matter read so far is a whole object, a syntax error is only reported
+/// once the file ends without one.
+fn is_complete_json(json: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(json).is_ok()
+}
+
+pub(crate) fn parse_string_list(yaml: &Yaml, key: &str) -> Result<Vec<String>> {
+    match yaml {
+        Yaml::BadValue | Yaml::Null => Ok(Vec::default()),
+        Yaml::Array(people) => people
+            .iter()
+            .map(|person| {
+                person.as_str().map(String::from).ok_or(anyhow::anyhow!(
+                    "Failed to parse \"{}\" key (should be a list of strings)",
+                    key
+                ))
+            })
+            .collect(),
+        _ => Err(anyhow::anyhow!(
+            "Failed to parse \"{}\" key (should be a list of strings)",
+            key
+        )),
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -267,6 +485,102 @@ gah = bar
         assert!(fm_result.is_ok());
 
         let (fm, _content) = fm_result.unwrap();
+        assert_eq!(
+            fm,
+            FrontMatter {
+                body_line: 3,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn it_reads_watchers_and_owners() -> TestResult {
+        let (fm, _content) = FrontMatter::from_str(
+            "---\nwatchers:\n- jane@example.com\n- 557058:048d7c01-8b68-440d-964f-07ce58d92aeb\nowners:\n- John Doe\n---\n# title",
+        )?;
+
+        assert_eq!(
+            fm.watchers,
+            vec![
+                "jane@example.com",
+                "557058:048d7c01-8b68-440d-964f-07ce58d92aeb"
+            ]
+        );
+        assert_eq!(fm.owners, vec!["John Doe"]);
+        assert!(fm.unknown_keys.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_the_page_type() -> TestResult {
+        let (fm, _content) = FrontMatter::from_str("---\ntype: runbook\n---\n# title")?;
+
+        assert_eq!(fm.page_type, Some(String::from("runbook")));
+        assert!(fm.unknown_keys.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_the_template_flag() -> TestResult {
+        let (fm, _content) = FrontMatter::from_str("---\ntemplate: false\n---\n# title")?;
+
+        assert!(!fm.template);
+        assert!(FrontMatter::from_str("---\n---\n# title")?.0.template);
+        assert!(FrontMatter::from_str("---\ntemplate: maybe\n---\n# title").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_checks_if_fields_are_set() -> TestResult {
+        let (fm, _content) = FrontMatter::from_str(FRONT_MATTER_MD)?;
+
+        assert!(fm.has_field("labels"));
+        assert!(!fm.has_field("owners"));
+        assert!(fm.has_field("metadata.some.arbitrary"));
+        assert!(!fm.has_field("metadata.some.other"));
+        assert!(!fm.has_field("unknown"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_errors_if_watchers_are_not_a_list() {
+        let fm_result = FrontMatter::from_str("---\nwatchers: jane@example.com\n---\n# title");
+
+        assert_eq!(
+            fm_result.err().unwrap().to_string(),
+            "Failed to parse \"watchers\" key (should be a list of strings)"
+        );
+    }
+
+    #[test]
+    fn it_reads_toml_and_json_frontmatter() -> TestResult {
+        let (fm, content) = FrontMatter::from_str(
+            "+++\nlabels = [\"foo\", \"bar\"]\nemoji = \"heart_eyes\"\n\n[metadata.some]\narbitrary = \"value\"\n+++\n# title\n",
+        )?;
+        assert_eq!(fm.labels, vec!["foo", "bar"]);
+        assert_eq!(fm.emoji, "heart_eyes");
+        assert_eq!(fm.metadata["some"]["arbitrary"].as_str(), Some("value"));
+        assert_eq!(fm.body_line, 7);
+        assert_eq!(content, "# title\n");
+
+        let (fm, content) = FrontMatter::from_str(
+            "{\n  \"labels\": [\"foo\"],\n  \"metadata\": {\"some\": {\"arbitrary\": \"value\"}}\n}\n# title\n",
+        )?;
+        assert_eq!(fm.labels, vec!["foo"]);
+        assert_eq!(fm.metadata["some"]["arbitrary"].as_str(), Some("value"));
+        assert_eq!(fm.body_line, 4);
+        assert_eq!(content, "# title\n");
+
+        // templates at the top of a page aren't JSON
+        let (fm, content) = FrontMatter::from_str("{% import 'macros.md' as m %}\n# title\n")?;
         assert_eq!(fm, FrontMatter::default());
+        assert_eq!(content, "{% import 'macros.md' as m %}\n# title\n");
+
+        Ok(())
     }
 }
@@ -0,0 +1,117 @@
+//! Anchors for the headings in a page, following the GitHub convention that markdown previews
+//! use: lowercase, spaces become dashes, punctuation is dropped and repeated headings get a `-1`,
+//! `-2`, ... suffix.
+use std::collections::HashMap;
+
+use crate::warnings::{Warning, WarningCode};
+
+pub fn heading_anchor(text: &str) -> String {
+    text.trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// A heading whose anchor was already taken by an earlier heading in the same page.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AnchorCollision {
+    pub heading: String,
+    /// The anchor the earlier heading has.
+    pub taken: String,
+    /// The suffixed anchor this heading gets when disambiguating.
+    pub anchor: String,
+}
+
+impl AnchorCollision {
+    pub fn warning(&self) -> Warning {
+        Warning::new(
+            WarningCode::DuplicateHeading,
+            format!(
+                "duplicate heading \"{}\": links to #{} will only reach the first one",
+                self.heading, self.taken
+            ),
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct HeadingAnchors {
+    counts: HashMap<String, usize>,
+}
+
+impl HeadingAnchors {
+    /// Registers the next heading of the page, returning the collision if the anchor was already
+    /// used.
+    pub fn add(&mut self, text: &str) -> Option<AnchorCollision> {
+        let taken = heading_anchor(text);
+        let count = self.counts.entry(taken.clone()).or_default();
+        *count += 1;
+        if *count == 1 {
+            return None;
+        }
+        let mut suffix = *count - 1;
+        let mut anchor = format!("{}-{}", taken, suffix);
+        // a heading may already be literally called "Overview 1"
+        while self.counts.contains_key(&anchor) {
+            suffix += 1;
+            anchor = format!("{}-{}", taken, suffix);
+        }
+        self.counts.insert(anchor.clone(), 1);
+        Some(AnchorCollision {
+            heading: String::from(text.trim()),
+            taken,
+            anchor,
+        })
+    }
+
+    pub fn anchor_macro(anchor: &str) -> String {
+        format!(
+            "<ac:structured-macro ac:name=\"anchor\" ac:schema-version=\"1\"><ac:parameter ac:name=\"\">{}</ac:parameter></ac:structured-macro>",
+            anchor
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_makes_github_style_anchors() {
+        assert_eq!(heading_anchor("Getting Started!"), "getting-started");
+        assert_eq!(heading_anchor("What's new in v1.2?"), "whats-new-in-v12");
+        assert_eq!(
+            heading_anchor("snake_case and-dashes"),
+            "snake_case-and-dashes"
+        );
+    }
+
+    #[test]
+    fn it_detects_collisions() {
+        let mut anchors = HeadingAnchors::default();
+
+        assert_eq!(anchors.add("Overview"), None);
+        assert_eq!(anchors.add("Details"), None);
+        let collision = anchors.add("overview").unwrap();
+        assert_eq!(collision.anchor, "overview-1");
+        assert_eq!(
+            collision.warning().to_string(),
+            "duplicate heading \"overview\": links to #overview will only reach the first one [MS002]"
+        );
+        assert_eq!(anchors.add("Overview").unwrap().anchor, "overview-2");
+    }
+
+    #[test]
+    fn it_skips_suffixes_used_by_other_headings() {
+        let mut anchors = HeadingAnchors::default();
+
+        assert_eq!(anchors.add("Overview 1"), None);
+        assert_eq!(anchors.add("Overview"), None);
+        assert_eq!(anchors.add("Overview").unwrap().anchor, "overview-2");
+    }
+}
@@ -0,0 +1,344 @@
+//! Conditional requests for reading pages, attachments and properties from Confluence.
+//!
+//! Responses with an `ETag` or `Last-Modified` header are kept, and asking for the same URL again
+//! sends `If-None-Match` / `If-Modified-Since`. When Confluence answers `304 Not Modified`, the
+//! kept body is used instead of downloading it again. The cache lives for a run, or across runs in
+//! the directory given with `--http-cache`.
+//!
+//! A run keeps at most `MAX_MEMORY` bytes of bodies in memory, dropping the oldest first. The
+//! directory holds the bodies as Confluence sent them, in plain text, so it and its files are only
+//! readable by the user running marked-space.
+use std::{
+    collections::{HashMap, VecDeque},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use data_encoding::HEXLOWER;
+use reqwest::{
+    blocking::{Body, RequestBuilder, Response},
+    header::{HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    ResponseBuilderExt, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Result,
+    sync_stats::{record_request, record_response},
+};
+
+/// The most bytes of bodies kept in memory.
+const MAX_MEMORY: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedResponse {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: String,
+}
+
+/// Reads the body of a response and hands back an equivalent response, so the caller can still
+/// read it.
+pub(crate) fn buffer(response: Response) -> reqwest::Result<(Vec<u8>, Response)> {
+    let status = response.status();
+    let url = response.url().clone();
+    let headers = response.headers().clone();
+    let bytes = response.bytes()?.to_vec();
+    Ok((bytes.clone(), replay(status, url, headers, bytes)))
+}
+
+fn replay(
+    status: StatusCode,
+    url: reqwest::Url,
+    headers: reqwest::header::HeaderMap,
+    body: Vec<u8>,
+) -> Response {
+    let mut builder = http::Response::builder().status(status).url(url);
+    if let Some(builder_headers) = builder.headers_mut() {
+        builder_headers.extend(headers);
+    }
+    builder.body(body).unwrap().into()
+}
+
+fn header(response: &Response, name: reqwest::header::HeaderName) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+}
+
+/// Creates a directory only the current user can read.
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+/// Writes a file only the current user can read.
+fn write_private(path: &Path, content: &str) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    io::Write::write_all(&mut options.open(path)?, content.as_bytes())
+}
+
+/// The responses kept in memory, in the order they were stored.
+#[derive(Default)]
+struct Entries {
+    responses: HashMap<String, CachedResponse>,
+    order: VecDeque<String>,
+    /// Bytes of all bodies together.
+    size: usize,
+}
+
+impl Entries {
+    fn insert(&mut self, key: String, cached: CachedResponse) {
+        if let Some(replaced) = self.responses.remove(&key) {
+            self.size -= replaced.body.len();
+            self.order.retain(|k| *k != key);
+        }
+        self.size += cached.body.len();
+        self.order.push_back(key.clone());
+        self.responses.insert(key, cached);
+        while self.size > MAX_MEMORY {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(dropped) = self.responses.remove(&oldest) {
+                self.size -= dropped.body.len();
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct HttpCache {
+    /// Where the responses are kept across runs.
+    dir: Option<PathBuf>,
+    entries: Mutex<Entries>,
+}
+
+impl HttpCache {
+    pub fn persistent(dir: &Path) -> Result<HttpCache> {
+        create_private_dir(dir)
+            .with_context(|| format!("Failed to create HTTP cache {}", dir.display()))?;
+        Ok(HttpCache {
+            dir: Some(dir.to_path_buf()),
+            ..Default::default()
+        })
+    }
+
+    /// Responses depend on who asks, as they only include what the user is allowed to see.
+    fn key(user: &str, url: &reqwest::Url) -> String {
+        let digest = ring::digest::digest(
+            &ring::digest::SHA256,
+            format!("{} {}", user, url).as_bytes(),
+        );
+        HEXLOWER.encode(digest.as_ref())
+    }
+
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        self.dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.json", key)))
+    }
+
+    fn lookup(&self, key: &str) -> Option<CachedResponse> {
+        if let Some(cached) = self.entries.lock().unwrap().responses.get(key) {
+            return Some(cached.clone());
+        }
+        let content = fs::read_to_string(self.path(key)?).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn store(&self, key: String, cached: CachedResponse) {
+        if let Some(path) = self.path(&key) {
+            // a cache that can't be written only costs a download next time
+            if let Ok(content) = serde_json::to_string(&cached) {
+                let _ = write_private(&path, &content);
+            }
+        }
+        self.entries.lock().unwrap().insert(key, cached);
+    }
+
+    /// Sends a GET request, conditionally when the response to it was kept before.
+    pub fn send(&self, user: &str, request: RequestBuilder) -> reqwest::Result<Response> {
+        let (client, request) = request.build_split();
+        let mut request = request?;
+        let key = Self::key(user, request.url());
+        let cached = self.lookup(&key);
+        if let Some(cached) = &cached {
+            let headers = request.headers_mut();
+            for (name, value) in [
+                (IF_NONE_MATCH, &cached.etag),
+                (IF_MODIFIED_SINCE, &cached.last_modified),
+            ] {
+                if let Some(value) = value.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+                    headers.insert(name, value);
+                }
+            }
+        }
+
+        record_request(
+            request
+                .body()
+                .and_then(Body::as_bytes)
+                .map_or(0, <[u8]>::len),
+        );
+        let (body, response) = buffer(client.execute(request)?)?;
+        record_response(body.len());
+        match (response.status(), cached) {
+            (StatusCode::NOT_MODIFIED, Some(cached)) => Ok(replay(
+                StatusCode::OK,
+                response.url().clone(),
+                response.headers().clone(),
+                cached.body.into_bytes(),
+            )),
+            (status, _) if status.is_success() => {
+                let (etag, last_modified) =
+                    (header(&response, ETAG), header(&response, LAST_MODIFIED));
+                if etag.is_none() && last_modified.is_none() {
+                    return Ok(response);
+                }
+                if let Ok(body) = String::from_utf8(body) {
+                    self.store(
+                        key,
+                        CachedResponse {
+                            etag,
+                            last_modified,
+                            body,
+                        },
+                    );
+                }
+                Ok(response)
+            }
+            _ => Ok(response),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use mockito::Matcher;
+
+    use crate::{confluence_client::ConfluenceClient, error::TestResult};
+
+    use super::*;
+
+    #[test]
+    fn it_reuses_bodies_that_were_not_modified() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let mut server = mockito::Server::new();
+        let first = server
+            .mock("GET", "/wiki/api/v2/pages/42")
+            .match_header("if-none-match", Matcher::Missing)
+            .with_header("etag", "\"v7\"")
+            .with_body(r#"{"id": "42", "title": "Home", "version": {"number": 7}}"#)
+            .expect(1)
+            .create();
+        let not_modified = server
+            .mock("GET", "/wiki/api/v2/pages/42")
+            .match_header("if-none-match", "\"v7\"")
+            .with_status(304)
+            .expect(2)
+            .create();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port())
+            .with_http_cache(Arc::new(HttpCache::persistent(temp.path())?));
+
+        let body = client.get_page("42")?.text()?;
+        assert_eq!(client.get_page("42")?.text()?, body);
+        // a new run reads what the last one kept
+        let client = ConfluenceClient::new_insecure(&server.host_with_port())
+            .with_http_cache(Arc::new(HttpCache::persistent(temp.path())?));
+        let response = client.get_page("42")?;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text()?, body);
+
+        first.assert();
+        not_modified.assert();
+
+        Ok(())
+    }
+
+    fn cached(body: &str) -> CachedResponse {
+        CachedResponse {
+            etag: Some(String::from("\"v1\"")),
+            last_modified: None,
+            body: String::from(body),
+        }
+    }
+
+    #[test]
+    fn it_drops_the_oldest_responses_over_the_memory_limit() {
+        let mut entries = Entries::default();
+        let half = "x".repeat(MAX_MEMORY / 2);
+        entries.insert(String::from("a"), cached(&half));
+        entries.insert(String::from("b"), cached(&half));
+        entries.insert(String::from("a"), cached(&half));
+        assert_eq!(entries.size, MAX_MEMORY);
+
+        entries.insert(String::from("c"), cached("y"));
+        assert!(!entries.responses.contains_key("b"));
+        assert!(entries.responses.contains_key("a") && entries.responses.contains_key("c"));
+        assert_eq!(entries.size, MAX_MEMORY / 2 + 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_keeps_responses_only_readable_by_the_user() -> TestResult {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = assert_fs::TempDir::new()?;
+        let dir = temp.path().join("cache");
+        let cache = HttpCache::persistent(&dir)?;
+        cache.store(String::from("key"), cached("{}"));
+
+        let mode = |path: &Path| -> io::Result<u32> {
+            Ok(fs::metadata(path)?.permissions().mode() & 0o777)
+        };
+        assert_eq!(mode(&dir)?, 0o700);
+        assert_eq!(mode(&dir.join("key.json"))?, 0o600);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_counts_the_bytes_of_responses_without_a_content_length() -> TestResult {
+        let mut server = mockito::Server::new();
+        server
+            .mock("GET", "/wiki/api/v2/pages/42")
+            .with_chunked_body(|w| w.write_all(b"{\"id\": \"42\"}"))
+            .create();
+        server
+            .mock("GET", "/wiki/download/attachments/42/a.png")
+            .with_chunked_body(|w| w.write_all(&[0; 2048]))
+            .create();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        crate::sync_stats::take();
+
+        assert_eq!(client.get_page("42")?.text()?, "{\"id\": \"42\"}");
+        assert_eq!(
+            client
+                .download_attachment("/download/attachments/42/a.png")?
+                .bytes()?
+                .len(),
+            2048
+        );
+        assert_eq!(crate::sync_stats::take().bytes_received, 12 + 2048);
+
+        Ok(())
+    }
+}
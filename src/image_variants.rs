@@ -0,0 +1,124 @@
+//! Theme variants of images, declared with an attribute block right after the image:
+//! `![Architecture](diagram.png){dark=diagram-dark.png}`.
+//!
+//! Confluence storage format can't switch images with the theme, so the dark variant is attached
+//! as well and shown in an expand macro below the paragraph of the light one.
+use std::io::{self, Write};
+
+use comrak::nodes::NodeLink;
+
+use crate::{
+    attachments::{render_link_enter, render_link_leave},
+    confluence_storage_renderer::WriteWithLast,
+};
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImageVariants {
+    pub dark: Option<String>,
+    pub unknown_keys: Vec<String>,
+}
+
+/// Parses the attribute block at the start of the text following an image, returning the
+/// variants and the length of the block.
+pub fn parse_image_variants(text: &str) -> Option<(ImageVariants, usize)> {
+    let block = text.strip_prefix('{')?;
+    let end = block.find('}')?;
+    let mut variants = ImageVariants::default();
+    for attribute in block[..end].split_whitespace() {
+        let (key, value) = attribute.split_once('=')?;
+        match key {
+            "dark" => variants.dark = Some(String::from(value.trim_matches('"'))),
+            _ => variants.unknown_keys.push(String::from(key)),
+        }
+    }
+    Some((variants, end + 2))
+}
+
+pub fn dark_variant_markup(url: &str, alt: &str) -> io::Result<String> {
+    let nl = NodeLink {
+        url: String::from(url),
+        title: String::default(),
+    };
+    let mut markup = Vec::<u8>::default();
+    let mut output = WriteWithLast::from_write(&mut markup);
+    output.write_all(b"<ac:structured-macro ac:name=\"expand\"><ac:parameter ac:name=\"title\">Dark mode version</ac:parameter><ac:rich-text-body>")?;
+    render_link_enter(&nl, alt, &mut output)?;
+    render_link_leave(&nl, &mut output)?;
+    output.write_all(b"</ac:rich-text-body></ac:structured-macro>")?;
+    Ok(String::from_utf8_lossy(&markup).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{error::TestResult, link_generator::LinkGenerator, markdown_page::page_from_str};
+
+    use super::*;
+
+    #[test]
+    fn it_parses_variants() {
+        assert_eq!(
+            parse_image_variants("{dark=diagram-dark.png} and more"),
+            Some((
+                ImageVariants {
+                    dark: Some(String::from("diagram-dark.png")),
+                    unknown_keys: Vec::default(),
+                },
+                23
+            ))
+        );
+        assert_eq!(
+            parse_image_variants("{contrast=high}")
+                .unwrap()
+                .0
+                .unknown_keys,
+            vec!["contrast"]
+        );
+        assert_eq!(parse_image_variants("not a block"), None);
+        assert_eq!(parse_image_variants("{unclosed=x"), None);
+    }
+
+    #[test]
+    fn it_attaches_and_renders_dark_variants() -> TestResult {
+        let page = page_from_str(
+            "page.md",
+            "# Title\n\n![Architecture](diagram.png){dark=diagram-dark.png}\n",
+        )?;
+
+        assert_eq!(
+            page.attachments
+                .iter()
+                .map(|attachment| attachment.url.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["diagram.png", "diagram-dark.png"]
+        );
+        assert_eq!(
+            page.render(&LinkGenerator::default_test())?.content,
+            "<p><ac:image ac:align=\"center\" ac:alt=\"Architecture\"><ri:attachment ri:filename=\"diagram.png\"/></ac:image></p>\n<ac:structured-macro ac:name=\"expand\"><ac:parameter ac:name=\"title\">Dark mode version</ac:parameter><ac:rich-text-body><ac:image ac:align=\"center\" ac:alt=\"Architecture (dark mode)\"><ri:attachment ri:filename=\"diagram-dark.png\"/></ac:image></ac:rich-text-body></ac:structured-macro>\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_the_order_of_the_dark_variants_of_a_paragraph() -> TestResult {
+        let page = page_from_str(
+            "page.md",
+            "# Title\n\n![One](one.png){dark=one-dark.png} ![Two](two.png){dark=two-dark.png}\n\nAfter\n",
+        )?;
+
+        let content = page.render(&LinkGenerator::default_test())?.content;
+
+        let positions: Vec<usize> = ["two.png", "</p>", "one-dark.png", "two-dark.png", "After"]
+            .iter()
+            .map(|text| content.find(text).unwrap())
+            .collect();
+        assert!(
+            positions.windows(2).all(|pair| pair[0] < pair[1]),
+            "{}",
+            content
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+//! The `report labels` subcommand: how often each label and emoji is used by the pages of the
+//! space, with the labels that look like misspellings of each other (`runbook` and `run-book`),
+//! so doc owners can keep the taxonomy clean.
+use std::{collections::BTreeMap, sync::Arc};
+
+use crate::{
+    anchor_links::edit_distance,
+    confluence_client::ConfluenceClient,
+    console::set_quiet,
+    markdown_page::MarkdownPage,
+    markdown_space::MarkdownSpace,
+    mentions::CachedUsers,
+    page_emojis::find_emoji,
+    sync::{load_space, LoadedSpace},
+    Args, Result,
+};
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct LabelReport {
+    pub pages: usize,
+    /// Label to the number of pages with it.
+    pub labels: BTreeMap<String, usize>,
+    /// Emoji (as in the front matter) to the number of pages with it.
+    pub emojis: BTreeMap<String, usize>,
+    /// Pairs of labels that are probably meant to be the same, the less used one first.
+    pub similar: Vec<(String, String)>,
+}
+
+/// The label without separators and case, `Run-Book` and `run_book` become `runbook`.
+fn squash(label: &str) -> String {
+    label
+        .chars()
+        .filter(|c| !matches!(c, '-' | '_' | '.' | ' '))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn is_similar(a: &str, b: &str) -> bool {
+    let (a, b) = (squash(a), squash(b));
+    // short labels are one typo away from many others
+    a == b || (a.chars().count().min(b.chars().count()) >= 5 && edit_distance(&a, &b) <= 1)
+}
+
+impl LabelReport {
+    pub fn collect(markdown_pages: &[MarkdownPage]) -> Self {
+        let mut report = LabelReport::default();
+        for markdown_page in markdown_pages.iter().filter(|page| !page.is_folder()) {
+            report.pages += 1;
+            for label in &markdown_page.front_matter.labels {
+                *report.labels.entry(label.clone()).or_default() += 1;
+            }
+            let emoji = markdown_page.front_matter.emoji.trim();
+            if !emoji.is_empty() {
+                *report.emojis.entry(emoji.to_string()).or_default() += 1;
+            }
+        }
+        let labels: Vec<(&String, &usize)> = report.labels.iter().collect();
+        for (index, (label, count)) in labels.iter().enumerate() {
+            for (other, other_count) in &labels[index + 1..] {
+                if is_similar(label, other) {
+                    let pair = if count <= other_count {
+                        ((*label).clone(), (*other).clone())
+                    } else {
+                        ((*other).clone(), (*label).clone())
+                    };
+                    report.similar.push(pair);
+                }
+            }
+        }
+        report
+    }
+
+    pub fn render(&self) -> String {
+        let width = self
+            .labels
+            .keys()
+            .chain(self.emojis.keys())
+            .map(|name| name.chars().count())
+            .max()
+            .unwrap_or_default();
+        let mut output = format!("Labels ({} pages):\n", self.pages);
+        let mut by_count: Vec<(&String, &usize)> = self.labels.iter().collect();
+        by_count.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        for (label, count) in by_count {
+            output += &format!("  {:width$}  {:>4}", label, count, width = width);
+            if let Some((_, other)) = self.similar.iter().find(|(less, _)| less == label) {
+                output += &format!("  similar to {}", other);
+            }
+            output += "\n";
+        }
+        output += "Emoji:\n";
+        let mut by_count: Vec<(&String, &usize)> = self.emojis.iter().collect();
+        by_count.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        for (emoji, count) in by_count {
+            let glyph = find_emoji(emoji).map(|found| found.as_str()).unwrap_or("?");
+            output += &format!(
+                "  {} {:width$}  {:>4}\n",
+                glyph,
+                emoji,
+                count,
+                width = width
+            );
+        }
+        output
+    }
+}
+
+pub fn report_labels(
+    confluence_client: ConfluenceClient,
+    markdown_space: &mut MarkdownSpace,
+    args: Args,
+) -> Result<()> {
+    set_quiet(true);
+    let LoadedSpace { markdown_pages, .. } = load_space(
+        &confluence_client,
+        markdown_space,
+        &args,
+        Arc::new(CachedUsers::new(confluence_client.clone())),
+    )?;
+    print!("{}", LabelReport::collect(&markdown_pages).render());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{error::TestResult, test_helpers::markdown_page_from_str};
+
+    use super::*;
+
+    #[test]
+    fn it_counts_labels_and_finds_similar_ones() -> TestResult {
+        let pages = vec![
+            markdown_page_from_str(
+                "a.md",
+                "---\nlabels: [runbook, payments]\nemoji: fire\n---\n# A\n",
+            )?,
+            markdown_page_from_str(
+                "b.md",
+                "---\nlabels: [runbook, api]\nemoji: fire\n---\n# B\n",
+            )?,
+            markdown_page_from_str("c.md", "---\nlabels: [run-book, apis, payment]\n---\n# C\n")?,
+        ];
+
+        let report = LabelReport::collect(&pages);
+
+        assert_eq!(report.pages, 3);
+        assert_eq!(report.labels.get("runbook"), Some(&2));
+        assert_eq!(report.emojis.get("fire"), Some(&2));
+        assert_eq!(
+            report.similar,
+            vec![
+                (String::from("payment"), String::from("payments")),
+                (String::from("run-book"), String::from("runbook")),
+            ]
+        );
+        assert_eq!(
+            report.render(),
+            "Labels (3 pages):
+  runbook      2
+  api          1
+  apis         1
+  payment      1  similar to payments
+  payments     1
+  run-book     1  similar to runbook
+Emoji:
+  🔥 fire         2
+"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,583 @@
+//! marked-space publishes a directory of markdown files as a Confluence space, see the README
+//! and `marked-space --help` for the command line, which is what [`run`] runs.
+//!
+//! Other tools can use the crate as a library for what's exported here:
+//!
+//! - [`resolve_space_links`] finds the published page of every markdown file of a space, to link
+//!   to the docs without knowing the ids of their pages (the library side of `resolve-links`).
+//!
+//! The rest of the crate is internal to the command line.
+use std::env;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+
+use confluence_client::{ConfluenceClient, DEFAULT_CONNECT_TIMEOUT, DEFAULT_TIMEOUT};
+use dotenvy::dotenv;
+use markdown_space::MarkdownSpace;
+
+mod adopt;
+mod alerts;
+mod anchor_links;
+mod api_version;
+mod archive;
+mod attachments;
+mod audit_log;
+#[doc(hidden)]
+pub mod bench;
+mod builtins;
+mod cancellation;
+mod change_comments;
+mod check;
+mod checksum;
+mod conflicts;
+mod confluence_client;
+mod confluence_page;
+mod confluence_paginator;
+mod confluence_space;
+mod confluence_storage_renderer;
+mod confluence_templates;
+mod console;
+mod content_policy;
+mod coverage_report;
+mod diagnostics;
+mod directory_defaults;
+mod dry_run;
+mod duplicates;
+mod edit_link;
+mod error;
+mod excerpts;
+mod external_links;
+mod folders;
+mod front_matter_formats;
+mod frontmatter;
+mod heading_anchors;
+mod helpers;
+mod http_cache;
+mod image_variants;
+mod imports;
+mod label_report;
+mod link_definitions;
+mod link_generator;
+mod lint_rules;
+mod local_link;
+mod macro_registry;
+mod markdown_page;
+mod markdown_space;
+mod mentions;
+mod multi_space;
+mod page_covers;
+mod page_emojis;
+mod page_ids;
+mod page_limits;
+mod page_map;
+mod page_merge;
+mod page_moves;
+mod page_properties;
+mod page_split;
+mod page_styles;
+mod page_titles;
+mod page_tree;
+mod page_types;
+mod parallel;
+mod parent;
+mod parse_cache;
+mod path_variables;
+mod placeholder_pages;
+mod preview;
+mod provenance;
+mod publish_schedule;
+mod pull;
+mod qr_codes;
+mod render_profile;
+mod report;
+mod resolve_links;
+mod responses;
+mod restrictions;
+mod rollback;
+mod search_index;
+mod secrets;
+mod snippets;
+mod sort;
+mod space_config;
+mod space_details;
+mod space_permissions;
+mod space_status;
+mod storage_format;
+mod sync;
+mod sync_lock;
+mod sync_operation;
+mod sync_stats;
+mod team_pages;
+mod template_errors;
+mod template_escaping;
+mod template_renderer;
+mod template_tests;
+#[cfg(test)]
+mod test_helpers;
+mod title_policy;
+mod warnings;
+mod watch;
+mod watchers;
+
+use crate::adopt::adopt;
+use crate::api_version::ApiVersion;
+use crate::audit_log::AuditLog;
+use crate::cancellation::install_handler;
+use crate::check::check;
+use crate::conflicts::ConflictPolicy;
+use crate::console::{print_error, print_info, set_quiet};
+use crate::coverage_report::report_coverage;
+use crate::error::{ConfluenceError, Result};
+use crate::http_cache::HttpCache;
+use crate::label_report::report_labels;
+use crate::mentions::CachedUsers;
+use crate::multi_space::sync_spaces;
+use crate::page_ids::write_page_ids;
+use crate::page_tree::tree;
+use crate::parallel::set_jobs;
+use crate::preview::preview;
+use crate::provenance::verify;
+use crate::pull::pull;
+use crate::resolve_links::resolve_links;
+use crate::rollback::rollback;
+use crate::space_permissions::permissions;
+use crate::space_status::status;
+use crate::sync::sync_space;
+use crate::template_tests::test_templates;
+use crate::watch::watch;
+
+pub use crate::link_generator::ResolvedLink;
+pub use crate::resolve_links::resolve_space_links;
+
+fn check_environment_vars() -> Result<()> {
+    match (env::var("API_USER"), env::var("API_TOKEN")) {
+        (Err(_), Err(_)) => Err(ConfluenceError::generic_error(
+            "Missing API_USER and API_TOKEN",
+        )),
+        (Err(_), Ok(_)) => Err(ConfluenceError::generic_error("Missing API_USER")),
+        (Ok(_), Err(_)) => Err(ConfluenceError::generic_error("Missing API_TOKEN")),
+        (Ok(_), Ok(_)) => Ok(()),
+    }
+}
+
+#[derive(Parser, Debug, Default, Clone)]
+#[command(author, version, about, long_about = None)]
+pub(crate) struct Args {
+    /// Path to the space to update. Can be repeated to sync several spaces at the same time
+    #[arg(short, long, required = true)]
+    space: Vec<String>,
+
+    /// Write intermediate output to this directory
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// The host to connect to. Can also be specified in $CONFLUENCE_HOST
+    #[arg(long)]
+    host: Option<String>,
+
+    /// Set the user identified by the token to the sole editor of pages. Default is to make the
+    /// space editable to anyone who has access to the space.
+    #[arg(long)]
+    single_editor: bool,
+
+    /// Set the user identified by the token to the sole editor of pages. Default is to make the
+    /// space editable to anyone who has access to the space.
+    #[arg(long)]
+    check: bool,
+
+    /// Print a diff of the pages the sync would create or update, and the pages it would move
+    /// and archive, without changing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// With --dry-run, also write the pages that would change to this directory as HTML, the page
+    /// in Confluence next to the page after the sync, e.g. to review as an artifact of a CI job
+    #[arg(long, value_name = "DIR", requires = "dry_run")]
+    dry_run_html: Option<PathBuf>,
+
+    /// Treat warnings (e.g. images without alt text) as errors
+    #[arg(long)]
+    strict: bool,
+
+    /// Write a JSON report of the synced pages, including their short links, to this file
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    /// Write a markdown table of short links to the synced pages to this file
+    #[arg(long)]
+    links: Option<PathBuf>,
+
+    /// Write the titles, URLs, headings and text of all pages as JSON to this file, for feeding
+    /// an external search index
+    #[arg(long)]
+    search_index: Option<PathBuf>,
+
+    /// Compare the body of pages in Confluence with the rendered pages instead of trusting the
+    /// checksums in their version messages, and don't update pages whose body is the same
+    #[arg(long)]
+    compare_remote: bool,
+
+    /// Allow the confluence_excerpt() shortcode to fetch pages from Confluence while rendering
+    #[arg(long)]
+    fetch_excerpts: bool,
+
+    /// Publish although the secret scanner found a likely secret, by the fingerprint it printed.
+    /// Can be repeated
+    #[arg(long, value_name = "FINGERPRINT")]
+    allow_secret: Vec<String>,
+
+    /// Take over the lock of another sync of the space, e.g. of a sync that was killed
+    #[arg(long)]
+    force_unlock: bool,
+
+    /// What to do with pages that were edited in Confluence since the last sync: fail them, warn
+    /// and overwrite them, or overwrite them
+    #[arg(long, value_name = "POLICY", default_value_t)]
+    on_conflict: ConflictPolicy,
+
+    /// Only sync the pages that failed to render or sync in the last run
+    #[arg(long)]
+    retry_failed: bool,
+
+    /// Leave the page of this file (relative to the space) as it is in Confluence, e.g. while
+    /// it's broken. Can be repeated
+    #[arg(long, value_name = "PATH")]
+    skip: Vec<String>,
+
+    /// Only sync the pages of this file or directory (relative to the space), and only archive
+    /// the orphans that were synced from it. Can be repeated
+    #[arg(long, value_name = "PATH")]
+    only: Vec<String>,
+
+    /// Append a JSON line for every page, attachment and property the run changes to this file
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+
+    /// Attach the audit log to this page (by id) after the run
+    #[arg(long, value_name = "PAGE_ID", requires = "audit_log")]
+    audit_page: Option<String>,
+
+    /// Number of results to ask Confluence for per request when reading lists of pages
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..=250))]
+    page_size: Option<u16>,
+
+    /// Seconds to wait for a connection to Confluence [default: 10]
+    #[arg(long, value_name = "SECONDS")]
+    connect_timeout: Option<u64>,
+
+    /// Seconds a request to Confluence may take, including uploads [default: 120]
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+
+    /// Keep the responses of Confluence in this directory, so the next run only downloads what
+    /// changed
+    #[arg(long, value_name = "DIR")]
+    http_cache: Option<PathBuf>,
+
+    /// Keep the templated and parsed pages in this directory, so the next run only parses the
+    /// files that changed
+    #[arg(long, value_name = "DIR")]
+    parse_cache: Option<PathBuf>,
+
+    /// Version of the Confluence REST API to use, e.g. v2
+    #[arg(long, value_name = "VERSION")]
+    api_version: Option<ApiVersion>,
+
+    /// Publish profile, picks the `external_links.rewrite` map of the space config, e.g. staging
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Print how long reading the front matter, templating, parsing, rendering and checksumming
+    /// took for every page, slowest first
+    #[arg(long)]
+    profile_render: bool,
+
+    /// Number of threads for templating, parsing and rendering pages [default: one per core]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+
+    /// Keep running after the sync, and sync the pages whose files change (and the pages linking
+    /// to them) right away
+    #[arg(long)]
+    watch: bool,
+
+    /// The files `--watch` saw change, to sync only their pages and the pages linking to them
+    #[arg(skip)]
+    changed: Option<Vec<String>>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub(crate) enum Command {
+    /// Print a JSON map of markdown files to the URLs of their published pages
+    ResolveLinks,
+    /// Compare the space permissions with the groups and roles in _space.yml
+    Permissions {
+        /// Change the space permissions to match _space.yml
+        #[arg(long)]
+        apply: bool,
+    },
+    /// List the pages that are new, modified, moved or orphaned, without changing anything
+    Status,
+    /// Check that every page synced by marked-space has a provenance that matches its content
+    Verify,
+    /// Print the page hierarchy with the id and sync status of every page
+    Tree {
+        /// Also print the hierarchy of the pages in Confluence
+        #[arg(long)]
+        remote: bool,
+        /// Also list the pages that differ between the two
+        #[arg(long)]
+        diff: bool,
+    },
+    /// Parse and render every page without Confluence, and report all the errors and warnings of
+    /// the space, e.g. to gate pull requests. Fails on errors, and with --strict on warnings
+    Check {
+        /// Print only a summary of the problems, as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Render every page without Confluence and check the assertions in _template_tests.yml
+    TestTemplates,
+    /// Serve the pages as they would be published on a local web server, without Confluence
+    Preview {
+        /// Port to listen on, on localhost
+        #[arg(long, default_value_t = 8000)]
+        port: u16,
+    },
+    /// Write the pages of the Confluence space to the space directory as markdown, to start
+    /// managing an existing space with marked-space
+    #[command(alias = "export")]
+    Pull {
+        /// Overwrite the files of a space directory that isn't empty
+        #[arg(long)]
+        force: bool,
+    },
+    /// Take over pages made by hand that have the title of a file, so the sync updates them
+    /// instead of creating new pages next to them
+    Adopt {
+        /// YAML file mapping files (relative to the space) to the ids of the pages to adopt,
+        /// instead of matching titles
+        #[arg(long, value_name = "FILE")]
+        mapping: Option<PathBuf>,
+        /// Adopt every page with a matching title without asking
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Restore the pages a sync changed to the versions they had before it, using the audit log
+    Rollback {
+        /// The sync to roll back, as printed when it started and in the "run" of the audit log
+        #[arg(long, value_name = "SYNC_ID")]
+        to: String,
+        /// Also roll back pages that were changed after the sync
+        #[arg(long)]
+        force: bool,
+    },
+    /// Write the ids of the pages the files are synced to to _page_ids.yml, which then decides
+    /// the page of every file it lists
+    PageIds,
+    /// Summarize the content of the space
+    Report {
+        #[command(subcommand)]
+        report: Report,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub(crate) enum Report {
+    /// Count how often each label and emoji is used, and list labels that look like typos of
+    /// each other
+    Labels,
+    /// Compare all pages of the Confluence space to the managed ones, and list the pages made by
+    /// hand
+    Coverage,
+}
+
+/// Runs the command line, what the binary does.
+pub fn run() -> Result<ExitCode> {
+    load_dotenv_if_exists();
+
+    let args = Args::parse();
+    set_jobs(args.jobs.map_or(0, usize::from));
+
+    if let Some(Command::Check { .. } | Command::TestTemplates | Command::Preview { .. }) =
+        args.command
+    {
+        // renders without Confluence, so it needs neither credentials nor a host
+        let host = args
+            .host
+            .clone()
+            .or(env::var("CONFLUENCE_HOST").ok())
+            .unwrap_or(String::from("example.atlassian.net"));
+        return Ok(exit_code(run_space_command(
+            ConfluenceClient::new(&host),
+            args,
+        )));
+    }
+
+    check_environment_vars()?;
+
+    let host = match (args.host.clone(), env::var("CONFLUENCE_HOST").ok()) {
+        (Some(host), _) => host,
+        (_, Some(envvar)) => envvar,
+        _ => {
+            eprintln!("Couldn't determine host from either --host or $CONFLUENCE_HOST");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    let audit_log = match &args.audit_log {
+        Some(path) => {
+            let audit_log = AuditLog::open(path)?;
+            print_info(&format!(
+                "Recording changes in {} as sync {}",
+                path.display(),
+                audit_log.run()
+            ));
+            Some(Arc::new(audit_log))
+        }
+        None => None,
+    };
+    let audit_page = args.audit_page.clone();
+    let api_version = args.api_version.unwrap_or_default();
+    api_version.check()?;
+    let mut confluence_client = ConfluenceClient::new(host.as_str())
+        .with_page_size(args.page_size)
+        .with_api_version(api_version)
+        .with_timeouts(
+            args.connect_timeout
+                .map_or(DEFAULT_CONNECT_TIMEOUT, Duration::from_secs),
+            args.timeout.map_or(DEFAULT_TIMEOUT, Duration::from_secs),
+        )?;
+    // the other commands don't stop between requests, Ctrl-C quits them right away
+    if args.command.is_none() && (args.watch || !(args.check || args.dry_run)) {
+        install_handler()?;
+    }
+    if let Some(audit_log) = &audit_log {
+        confluence_client = confluence_client.with_audit_log(audit_log.clone());
+    }
+    if let Some(dir) = &args.http_cache {
+        confluence_client =
+            confluence_client.with_http_cache(Arc::new(HttpCache::persistent(dir)?));
+    }
+
+    let result = run_space_command(confluence_client.clone(), args);
+    // also when the command failed, which is when the log is needed most
+    let finished = match &audit_log {
+        Some(audit_log) => audit_log.finish_and_upload(&confluence_client, audit_page.as_deref()),
+        None => Ok(()),
+    };
+    let result = match (result, finished) {
+        (Err(err), Err(finish_err)) => {
+            print_error(&format!("{:#}", finish_err));
+            Err(err)
+        }
+        (result, finished) => result.and(finished),
+    };
+    Ok(exit_code(result))
+}
+
+fn exit_code(result: Result<()>) -> ExitCode {
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:#}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run_space_command(confluence_client: ConfluenceClient, args: Args) -> Result<()> {
+    if args.space.len() > 1 {
+        if args.watch || !args.only.is_empty() {
+            return Err(ConfluenceError::generic_error(
+                "--watch and --only work on a single space, pass --space once",
+            ));
+        }
+        match args.command {
+            None => sync_spaces(confluence_client, args),
+            Some(_) => Err(ConfluenceError::generic_error(
+                "Subcommands work on a single space, pass --space once",
+            )),
+        }
+    } else {
+        run_single_space(confluence_client, args)
+    }
+}
+
+fn run_single_space(confluence_client: ConfluenceClient, args: Args) -> Result<()> {
+    let dir = PathBuf::from(&args.space[0]);
+    if let Some(Command::Pull { force }) = args.command {
+        // the directory is what gets written, it doesn't have to be a space yet
+        return pull(confluence_client, &dir, force);
+    }
+    if let Some(Command::Rollback { to, force }) = &args.command {
+        let Some(audit_log) = &args.audit_log else {
+            return Err(ConfluenceError::generic_error(
+                "Pass the --audit-log of the sync to roll back",
+            ));
+        };
+        return rollback(confluence_client, audit_log, to, *force);
+    }
+    if let Some(Command::Check { json: true }) = args.command {
+        // nothing but the summary on stdout, also while reading the directory
+        set_quiet(true);
+    }
+    let mut markdown_space = MarkdownSpace::from_directory(&dir)?;
+    match args.command.clone() {
+        Some(Command::ResolveLinks) => resolve_links(confluence_client, &mut markdown_space, args),
+        Some(Command::Status) => status(confluence_client, &mut markdown_space, args),
+        Some(Command::Tree { remote, diff }) => {
+            tree(confluence_client, &mut markdown_space, args, remote, diff)
+        }
+        Some(Command::Verify) => verify(confluence_client, &markdown_space),
+        Some(Command::Permissions { apply }) => {
+            permissions(confluence_client, &markdown_space, apply)
+        }
+        Some(Command::Check { json }) => check(confluence_client, &mut markdown_space, args, json),
+        Some(Command::TestTemplates) => {
+            test_templates(confluence_client, &mut markdown_space, args)
+        }
+        Some(Command::Preview { port }) => preview(confluence_client, &dir, args, port),
+        // handled before reading the directory
+        Some(Command::Pull { .. }) | Some(Command::Rollback { .. }) => unreachable!(),
+        Some(Command::Adopt { mapping, yes }) => adopt(
+            confluence_client,
+            &mut markdown_space,
+            args,
+            mapping.as_deref(),
+            yes,
+        ),
+        Some(Command::PageIds) => write_page_ids(confluence_client, &mut markdown_space, args),
+        Some(Command::Report {
+            report: Report::Labels,
+        }) => report_labels(confluence_client, &mut markdown_space, args),
+        Some(Command::Report {
+            report: Report::Coverage,
+        }) => report_coverage(confluence_client, &mut markdown_space, args),
+        None if args.watch => watch(confluence_client, &dir, args),
+        None => {
+            let users = Arc::new(CachedUsers::new(confluence_client.clone()));
+            sync_space(confluence_client, &mut markdown_space, args.clone(), users)?.write(&args)
+        }
+    }
+}
+
+fn load_dotenv_if_exists() {
+    if let Err(e) = dotenv() {
+        match e {
+            dotenvy::Error::Io(io_err) => {
+                match io_err.kind() {
+                    std::io::ErrorKind::NotFound => (), // do nothing
+                    _ => eprintln!("Failure loading .env: {}", io_err),
+                }
+            }
+            _ => eprintln!("Failure loading .env: {}", e),
+        }
+    }
+}
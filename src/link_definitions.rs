@@ -0,0 +1,248 @@
+//! Reference definitions shared by every page of a space, so `[Confluence REST API][cc-rest]`
+//! works in any page without repeating `[cc-rest]: https://...` at the bottom of each.
+//!
+//! The definitions live in `_definitions.md` at the root of the space, or in `links.md` when that
+//! has nothing but definitions in it (otherwise it's a page like any other). A definition in a
+//! page takes precedence over a shared one with the same label.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Context};
+use comrak::{BrokenLinkCallback, BrokenLinkReference, ResolvedReference};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::error::Result;
+
+pub static LINK_DEFINITIONS_FILENAME: &str = "_definitions.md";
+/// Only used for definitions when it doesn't have anything else in it.
+pub static LINKS_FILENAME: &str = "links.md";
+
+static DEFINITION: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"^ {0,3}\[([^\]]+)\]:\s*(<[^>]*>|\S+)(?:\s+(?:"([^"]*)"|'([^']*)'|\(([^)]*)\)))?\s*$"#,
+    )
+    .unwrap()
+});
+
+/// Labels match regardless of case and whitespace, like they do in CommonMark.
+fn normalize(label: &str) -> String {
+    label
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LinkDefinition {
+    /// The label as it was written in the file.
+    pub label: String,
+    pub url: String,
+    pub title: String,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct LinkDefinitions {
+    /// The file the definitions were read from, relative to the space directory.
+    pub source: Option<String>,
+    /// Normalized label to definition.
+    definitions: BTreeMap<String, LinkDefinition>,
+}
+
+impl LinkDefinitions {
+    /// The file with the shared definitions of the space in `dir`, if it has one.
+    pub fn file(dir: &Path) -> Result<Option<PathBuf>> {
+        let definitions = dir.join(LINK_DEFINITIONS_FILENAME);
+        let links = dir.join(LINKS_FILENAME);
+        let links_has_definitions = links.exists() && {
+            let content = fs::read_to_string(&links)?;
+            let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+            lines.clone().next().is_some() && lines.all(|line| DEFINITION.is_match(line))
+        };
+        match (definitions.exists(), links_has_definitions) {
+            (true, true) => Err(anyhow!(
+                "Both {} and {} have link definitions, keep them in one of the two",
+                LINK_DEFINITIONS_FILENAME,
+                LINKS_FILENAME
+            )),
+            (true, false) => Ok(Some(definitions)),
+            (false, true) => Ok(Some(links)),
+            (false, false) => Ok(None),
+        }
+    }
+
+    pub fn from_directory(dir: &Path) -> Result<Self> {
+        let Some(path) = Self::file(dir)? else {
+            return Ok(LinkDefinitions::default());
+        };
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read link definitions {}", path.display()))?;
+        let mut link_definitions = Self::parse(&content);
+        link_definitions.source = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string());
+        Ok(link_definitions)
+    }
+
+    /// Reads the `[label]: url "title"` lines, the rest of the file is left for the reader.
+    pub fn parse(content: &str) -> Self {
+        let mut definitions = BTreeMap::default();
+        for captures in content.lines().filter_map(|line| DEFINITION.captures(line)) {
+            let label = captures[1].trim().to_string();
+            let url = captures[2]
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string();
+            let title = (3..=5)
+                .find_map(|group| captures.get(group))
+                .map(|title| title.as_str().to_string())
+                .unwrap_or_default();
+            // the first definition of a label wins, like in a page
+            definitions
+                .entry(normalize(&label))
+                .or_insert(LinkDefinition { label, url, title });
+        }
+        LinkDefinitions {
+            source: None,
+            definitions,
+        }
+    }
+
+    pub fn get(&self, label: &str) -> Option<&LinkDefinition> {
+        self.definitions.get(&normalize(label))
+    }
+
+    /// The definitions no page uses, `used` being the normalized labels pages used.
+    pub fn unused<'s>(&'s self, used: &BTreeSet<String>) -> Vec<&'s LinkDefinition> {
+        self.definitions
+            .iter()
+            .filter(|(label, _)| !used.contains(*label))
+            .map(|(_, definition)| definition)
+            .collect()
+    }
+}
+
+/// Resolves the references a page doesn't define itself while it's parsed, keeping track of
+/// which shared definitions it used and which references it has no definition for.
+#[derive(Debug)]
+pub struct ReferenceResolver {
+    definitions: Arc<LinkDefinitions>,
+    used: Mutex<BTreeSet<String>>,
+    unresolved: Mutex<BTreeSet<String>>,
+}
+
+impl ReferenceResolver {
+    pub fn new(definitions: &Arc<LinkDefinitions>) -> Self {
+        ReferenceResolver {
+            definitions: definitions.clone(),
+            used: Mutex::default(),
+            unresolved: Mutex::default(),
+        }
+    }
+
+    /// The normalized labels of the shared definitions the page used.
+    pub fn used(&self) -> BTreeSet<String> {
+        self.used.lock().unwrap().clone()
+    }
+
+    /// The labels of `[text][label]` and `[label][]` references without a definition in `content`.
+    ///
+    /// Text in brackets on its own (`[label]`) could be a reference as well, but is more often just
+    /// text, so it isn't reported.
+    pub fn undefined(&self, content: &str) -> Vec<String> {
+        self.unresolved
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|label| {
+                content.contains(&format!("][{}]", label))
+                    || content.contains(&format!("[{}][]", label))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+impl BrokenLinkCallback for ReferenceResolver {
+    fn resolve(&self, reference: BrokenLinkReference) -> Option<ResolvedReference> {
+        match self.definitions.get(reference.original) {
+            Some(definition) => {
+                self.used
+                    .lock()
+                    .unwrap()
+                    .insert(normalize(reference.original));
+                Some(ResolvedReference {
+                    url: definition.url.clone(),
+                    title: definition.title.clone(),
+                })
+            }
+            None => {
+                self.unresolved
+                    .lock()
+                    .unwrap()
+                    .insert(reference.original.to_string());
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reads_definitions() {
+        let definitions = LinkDefinitions::parse(
+            "# Links\n\n[cc-rest]: https://developer.atlassian.com/cloud/confluence/rest/v2/ \"REST API\"\n[Home Page]: <../index.md>\n[cc-rest]: https://example.com\n\nNot a [definition]: here\n",
+        );
+
+        assert_eq!(
+            definitions.get("CC-REST"),
+            Some(&LinkDefinition {
+                label: String::from("cc-rest"),
+                url: String::from("https://developer.atlassian.com/cloud/confluence/rest/v2/"),
+                title: String::from("REST API"),
+            })
+        );
+        assert_eq!(
+            definitions.get("home   page").map(|d| d.url.as_str()),
+            Some("../index.md")
+        );
+        assert_eq!(definitions.get("definition"), None);
+        assert_eq!(
+            definitions
+                .unused(&BTreeSet::from([String::from("cc-rest")]))
+                .iter()
+                .map(|d| d.label.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Home Page"]
+        );
+    }
+
+    #[test]
+    fn it_uses_links_md_only_when_it_has_only_definitions() -> crate::error::TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        fs::write(temp.path().join(LINKS_FILENAME), "# Links\n\nA page.\n")?;
+        assert_eq!(LinkDefinitions::file(temp.path())?, None);
+
+        fs::write(
+            temp.path().join(LINKS_FILENAME),
+            "[jira]: https://example.atlassian.net\n",
+        )?;
+        assert_eq!(
+            LinkDefinitions::file(temp.path())?,
+            Some(temp.path().join(LINKS_FILENAME))
+        );
+
+        fs::write(temp.path().join(LINK_DEFINITIONS_FILENAME), "[a]: b\n")?;
+        assert!(LinkDefinitions::file(temp.path()).is_err());
+
+        Ok(())
+    }
+}
@@ -1,32 +1,101 @@
 use path_clean::PathClean;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     io::{self, Write},
     path::{Path, PathBuf},
+    sync::Mutex,
 };
 
 use comrak::nodes::NodeLink;
+use serde::Serialize;
 
 use crate::{
+    archive::should_unarchive,
     confluence_page::{ConfluenceNode, ConfluenceNodeType, ConfluencePageData},
-    confluence_storage_renderer::ConfluenceStorageRenderer,
+    confluence_storage_renderer::{escape, ConfluenceStorageRenderer},
     console::print_warning,
     error::{ConfluenceError, Result},
+    external_links::{confluence_link, ExternalLinks, RewrittenLink},
     local_link::LocalLink,
     markdown_page::MarkdownPage,
+    page_map::PageMapEntry,
+    parent::get_flattened_parent_file,
+    responses::ContentStatus,
+    title_policy::TitlePolicy,
+    warnings::{Warning, WarningCode, WarningPolicy},
 };
 
+/// What to do with the text of links to local pages.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum LinkText {
+    /// Use the page title when the link text is empty.
+    #[default]
+    WhenEmpty,
+    /// Always replace the link text with the current page title.
+    Title,
+    /// Keep the link text, and show the page title as a tooltip.
+    Tooltip,
+}
+
+impl LinkText {
+    pub fn from_str(s: Option<&str>) -> Result<LinkText> {
+        match s {
+            None | Some("when_empty") => Ok(LinkText::WhenEmpty),
+            Some("title") => Ok(LinkText::Title),
+            Some("tooltip") => Ok(LinkText::Tooltip),
+            Some(other) => Err(ConfluenceError::generic_error(format!(
+                "Invalid link text \"{}\" (should be when_empty, title or tooltip)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Where a markdown page is published, see [`crate::resolve_space_links`].
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct ResolvedLink {
+    pub title: String,
+    /// `None` until the page has been created in Confluence.
+    pub url: Option<String>,
+}
+
 #[derive(Debug)]
 pub struct LinkGenerator {
     host: String,
     space_key: String,
     homepage_id: String,
+    homepage_locked: bool,
+    /// The title of the locked homepage, which `index.md` can't have.
+    locked_homepage_title: Option<String>,
+    title_policy: TitlePolicy,
+    link_text: LinkText,
     filename_to_id: HashMap<String, String>,
     filename_to_title: HashMap<String, String>,
     title_to_file: HashMap<String, String>,
     title_to_id: HashMap<String, String>,
     folders: HashSet<String>,
+    /// Files and pages linked through the page map or the source in the version message, which
+    /// title matching doesn't override.
+    mapped_files: HashSet<String>,
+    mapped_ids: HashSet<String>,
+    /// Files that aren't published yet (see `publish_after`), their pages aren't created.
+    scheduled_files: HashSet<String>,
+    /// Files merged into another page (see `merge`), to that page and the anchor of the section.
+    merged_files: HashMap<String, (String, String)>,
+    /// Files with `snippet: true`, which are included into pages but have none of their own.
+    snippet_files: HashSet<String>,
     page_attachment_pair_to_id: HashMap<(String, String), String>,
+    /// Pages that are archived or trashed in Confluence, by id, with their status.
+    inactive_ids: HashMap<String, ContentStatus>,
+    /// Fail on warnings about links instead of printing them (`--strict`), unless they're disabled.
+    warnings: WarningPolicy,
+    /// The warnings turned off by pages, by file.
+    page_disabled_warnings: HashMap<String, BTreeSet<WarningCode>>,
+    /// Pages are put no deeper than this under the homepage, see `max_depth` in the space config.
+    max_depth: Option<usize>,
+    external_links: ExternalLinks,
+    /// The external links that were published with another URL, as pages are rendered.
+    rewritten_links: Mutex<BTreeSet<RewrittenLink>>,
 }
 
 impl LinkGenerator {
@@ -35,12 +104,27 @@ impl LinkGenerator {
             host: host.to_string(),
             space_key: space_key.to_string(),
             homepage_id: homepage_id.into(),
+            homepage_locked: false,
+            locked_homepage_title: None,
+            title_policy: TitlePolicy::default(),
+            link_text: LinkText::default(),
             filename_to_id: HashMap::default(),
             filename_to_title: HashMap::default(),
             title_to_file: HashMap::default(),
             title_to_id: HashMap::default(),
             folders: HashSet::default(),
+            mapped_files: HashSet::default(),
+            mapped_ids: HashSet::default(),
+            scheduled_files: HashSet::default(),
+            merged_files: HashMap::default(),
+            snippet_files: HashSet::default(),
             page_attachment_pair_to_id: HashMap::default(),
+            inactive_ids: HashMap::default(),
+            warnings: WarningPolicy::default(),
+            page_disabled_warnings: HashMap::default(),
+            max_depth: None,
+            external_links: ExternalLinks::default(),
+            rewritten_links: Mutex::default(),
         }
     }
 
@@ -49,8 +133,112 @@ impl LinkGenerator {
         Self::new("example.atlassian.net", "TEST", "999")
     }
 
+    /// Leaves the homepage alone: `index.md` gets a page of its own under the homepage, and
+    /// becomes the parent of the other top level pages.
+    pub fn lock_homepage(&mut self) {
+        self.homepage_locked = true;
+    }
+
+    /// Fails when `index.md` would get a page under the locked homepage with the title of the
+    /// homepage, which Confluence doesn't allow twice in a space.
+    pub fn check_locked_homepage(&self) -> Result<()> {
+        let index_title = self.filename_to_title.get("index.md");
+        match &self.locked_homepage_title {
+            Some(title) if index_title == Some(title) => {
+                Err(ConfluenceError::generic_error(format!(
+                "index.md has the title of the locked homepage (\"{}\"), set homepage.title in \
+                 _space.yml to give its page another title",
+                title
+            )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Titles are looked up after normalizing them with the policy, so remote titles that only
+    /// differ in whitespace or quotes still match their markdown page.
+    pub fn set_title_policy(&mut self, title_policy: &TitlePolicy) {
+        self.title_policy = title_policy.clone();
+    }
+
+    pub fn set_link_text(&mut self, link_text: LinkText) {
+        self.link_text = link_text;
+    }
+
+    /// Links that would be warned about fail the render instead (`--strict`).
+    pub fn set_strict(&mut self, strict: bool) {
+        self.warnings.strict = strict;
+    }
+
+    /// The codes turned off for the whole space, see `warnings.disable` in the space config.
+    pub fn set_disabled_warnings(&mut self, disabled: &BTreeSet<WarningCode>) {
+        self.warnings.disabled = disabled.clone();
+    }
+
+    pub fn warnings(&self) -> &WarningPolicy {
+        &self.warnings
+    }
+
+    /// Prints a warning about a link in `source`, or fails with `--strict`.
+    fn warn(&self, source: &str, warning: Warning) -> io::Result<()> {
+        if self
+            .page_disabled_warnings
+            .get(source)
+            .is_some_and(|disabled| disabled.contains(&warning.code))
+        {
+            return Ok(());
+        }
+        self.warnings
+            .report(warning)
+            .map_err(|err| io::Error::other(err.to_string()))
+    }
+
+    /// Puts pages deeper than `max_depth` under the closest page above them that isn't.
+    pub fn set_max_depth(&mut self, max_depth: Option<usize>) {
+        self.max_depth = max_depth;
+    }
+
+    /// Strips tracking parameters from and normalizes the external links of rendered pages.
+    pub fn set_external_links(&mut self, external_links: &ExternalLinks) {
+        self.external_links = external_links.clone();
+    }
+
+    /// The external links that were published with another URL, sorted by page.
+    pub fn rewritten_links(&self) -> Vec<RewrittenLink> {
+        self.rewritten_links
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Remembers which pages aren't current, so links to them can be reported. Register them once
+    /// the archived pages of files that are back are restored, those are current by then (or will
+    /// be, when checking).
+    pub fn register_inactive_pages(&mut self, confluence_nodes: &[ConfluenceNode]) {
+        self.inactive_ids = confluence_nodes
+            .iter()
+            .filter_map(|node| match node.page_data() {
+                Some(page_data)
+                    if !matches!(page_data.status, ContentStatus::Current)
+                        && !should_unarchive(node, self) =>
+                {
+                    Some((node.id.clone(), page_data.status.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+    }
+
+    /// The status of the page a file links to when it isn't current, e.g. when it's archived.
+    pub fn inactive_status(&self, filename: &Path) -> Option<&ContentStatus> {
+        self.get_page_id(filename)
+            .and_then(|id| self.inactive_ids.get(&id))
+    }
+
     pub fn register_markdown_page(&mut self, markdown_page: &MarkdownPage) -> Result<()> {
-        let title = markdown_page.title.to_owned();
+        let title = self.title_policy.normalize(&markdown_page.title);
         let filename = markdown_page.source.replace('\\', "/");
         if self.title_to_file.contains_key(&title) {
             return Err(ConfluenceError::DuplicateTitle {
@@ -67,16 +255,141 @@ impl LinkGenerator {
 
         self.filename_to_title
             .insert(filename.clone(), title.clone());
+        if !markdown_page.disabled_warnings.is_empty() {
+            self.page_disabled_warnings
+                .insert(filename.clone(), markdown_page.disabled_warnings.clone());
+        }
+
+        for merged in &markdown_page.merged {
+            self.merged_files.insert(
+                merged.source.clone(),
+                (filename.clone(), merged.anchor.clone()),
+            );
+        }
 
         Ok(())
     }
 
+    /// Keeps the page of a file from being created until it can be published.
+    pub fn register_scheduled_file(&mut self, filename: &Path) {
+        if let Ok(filename) = Self::path_to_string(filename) {
+            self.scheduled_files.insert(filename);
+        }
+    }
+
+    pub fn register_snippet_file(&mut self, filename: &Path) {
+        if let Ok(filename) = Self::path_to_string(filename) {
+            self.snippet_files.insert(filename);
+        }
+    }
+
+    fn is_snippet_file(&self, filename: &Path) -> bool {
+        Self::path_to_string(filename).is_ok_and(|filename| self.snippet_files.contains(&filename))
+    }
+
+    /// Links files to the pages `_page_ids.yml` lists for them. Register them before the page map,
+    /// as the committed file takes precedence over everything else.
+    pub fn register_page_ids(
+        &mut self,
+        page_ids: &BTreeMap<String, String>,
+        confluence_nodes: &[ConfluenceNode],
+    ) {
+        for (filename, id) in page_ids {
+            if self.filename_to_title.contains_key(filename)
+                && confluence_nodes.iter().any(|node| node.id == *id)
+                && !self.mapped_files.contains(filename)
+                && !self.mapped_ids.contains(id)
+            {
+                self.filename_to_id.insert(filename.clone(), id.clone());
+                self.mapped_files.insert(filename.clone());
+                self.mapped_ids.insert(id.clone());
+            }
+        }
+    }
+
+    /// Links files to the pages they were synced to before, according to the page map. Register
+    /// the map before the Confluence nodes, as it takes precedence over matching titles.
+    pub fn register_page_map(
+        &mut self,
+        pages: &BTreeMap<String, PageMapEntry>,
+        confluence_nodes: &[ConfluenceNode],
+    ) {
+        for (filename, entry) in pages {
+            if self.filename_to_title.contains_key(filename)
+                && confluence_nodes.iter().any(|node| node.id == entry.id)
+                && !self.mapped_files.contains(filename)
+                && !self.mapped_ids.contains(&entry.id)
+            {
+                self.filename_to_id
+                    .insert(filename.clone(), entry.id.clone());
+                self.mapped_files.insert(filename.clone());
+                self.mapped_ids.insert(entry.id.clone());
+            }
+        }
+    }
+
+    /// Links files to the pages that were last synced from them, according to the source in the
+    /// version message. This comes before matching titles, so a page keeps its file when the title
+    /// in the markdown changes, even to the title of another page. Archived pages only get the
+    /// file when there's no current page synced from it, to be restored instead of duplicated.
+    pub fn register_synced_sources(&mut self, confluence_nodes: &[ConfluenceNode]) {
+        let (archived, current): (Vec<&ConfluenceNode>, Vec<&ConfluenceNode>) =
+            confluence_nodes.iter().partition(|node| {
+                node.page_data()
+                    .is_some_and(|page_data| matches!(page_data.status, ContentStatus::Archived))
+            });
+        for node in current.into_iter().chain(archived) {
+            if node.id == self.homepage_id || self.mapped_ids.contains(&node.id) {
+                continue;
+            }
+            let Some(Ok(filename)) = node
+                .page_data()
+                .and_then(|page_data| page_data.path.as_deref())
+                .map(Self::path_to_string)
+            else {
+                continue;
+            };
+            if self.filename_to_title.contains_key(&filename)
+                && !self.mapped_files.contains(&filename)
+            {
+                self.filename_to_id
+                    .insert(filename.clone(), node.id.clone());
+                self.mapped_files.insert(filename);
+                self.mapped_ids.insert(node.id.clone());
+            }
+        }
+    }
+
+    /// Makes up an id for every markdown page, so links render like they would once the pages
+    /// exist, without asking Confluence.
+    pub fn register_offline_ids(&mut self) {
+        let mut pages: Vec<(String, String)> = self
+            .filename_to_title
+            .iter()
+            .map(|(filename, title)| (filename.clone(), title.clone()))
+            .collect();
+        // numbered in file order, so the ids are the same every time
+        pages.sort();
+        for (index, (filename, title)) in pages.into_iter().enumerate() {
+            let id = (index + 1).to_string();
+            self.title_to_id.insert(title, id.clone());
+            self.filename_to_id.insert(filename, id);
+        }
+    }
+
     pub fn register_confluence_node(&mut self, confluence_node: &ConfluenceNode) {
-        let title = confluence_node.title.clone();
+        let title = self.title_policy.normalize(&confluence_node.title);
         let id = confluence_node.id.clone();
         let homepage_id = self.homepage_id.clone();
+        if self.homepage_locked && id == homepage_id {
+            self.locked_homepage_title = Some(title.clone());
+            self.title_to_id.insert(title, id);
+            return;
+        }
         if let Some(filename) = self.title_to_file.get(&title) {
-            self.filename_to_id.insert(filename.clone(), id.clone());
+            if !self.mapped_files.contains(filename) && !self.mapped_ids.contains(&id) {
+                self.filename_to_id.insert(filename.clone(), id.clone());
+            }
         }
         self.title_to_id.insert(title.clone(), id.clone());
         if id == homepage_id {
@@ -112,7 +425,8 @@ impl LinkGenerator {
     }
 
     pub fn has_title(&self, title: &str) -> bool {
-        self.title_to_file.contains_key(title)
+        self.title_to_file
+            .contains_key(&self.title_policy.normalize(title))
     }
 
     pub fn get_file_id(&self, filename: &Path) -> Option<String> {
@@ -122,6 +436,25 @@ impl LinkGenerator {
             .cloned()
     }
 
+    /// The file of the page a page belongs under, after flattening the tree to `max_depth`.
+    pub fn get_parent_file(&self, page_path: &Path) -> Option<PathBuf> {
+        get_flattened_parent_file(page_path, self.max_depth)
+    }
+
+    /// The id of the Confluence node a page belongs under, which is `None` only for the homepage.
+    pub fn get_parent_id(&self, page_path: &Path) -> Option<String> {
+        let is_homepage = Self::path_to_string(page_path).is_ok_and(|p| p == "index.md");
+        if is_homepage && !self.homepage_locked {
+            return None;
+        }
+        let parent_id = match self.get_parent_file(page_path) {
+            Some(parent_file) => self.get_file_id(&parent_file),
+            None if self.homepage_locked && !is_homepage => self.get_file_id(Path::new("index.md")),
+            None => None,
+        };
+        parent_id.or(Some(self.homepage_id.clone()))
+    }
+
     fn id_to_url(&self, id: &str) -> String {
         format!(
             "https://{}/wiki/spaces/{}/pages/{}",
@@ -129,41 +462,160 @@ impl LinkGenerator {
         )
     }
 
-    fn get_file_url(&self, filename: &Path) -> Option<String> {
-        if filename == PathBuf::from("index.md") {
-            return Some(self.id_to_url(&self.homepage_id));
-        }
-        if let Ok(s) = Self::path_to_string(filename) {
-            self.filename_to_id.get(&s).map(|id| self.id_to_url(id))
-            // .or_else(|| self.version_path_to_id.get(&s).map(|id| self.id_to_url(id)))
-        } else {
-            None
+    /// The id of the published page for a markdown file, relative to the space directory.
+    pub fn get_page_id(&self, filename: &Path) -> Option<String> {
+        if filename == Path::new("index.md") && !self.homepage_locked {
+            return Some(self.homepage_id.clone());
         }
+        self.get_file_id(filename)
     }
 
-    fn get_file_title(&self, path: &Path) -> Option<String> {
-        let s = Self::path_to_string(path).unwrap();
-        self.filename_to_title.get(&s).cloned()
+    /// The URL of the published page for a markdown file, relative to the space directory.
+    pub fn get_file_url(&self, filename: &Path) -> Option<String> {
+        self.get_page_id(filename).map(|id| self.id_to_url(&id))
     }
 
+    /// The (normalized) title of the page for a markdown file, relative to the space directory.
+    pub fn get_file_title(&self, path: &Path) -> Option<String> {
+        Self::path_to_string(path)
+            .ok()
+            .and_then(|s| self.filename_to_title.get(&s).cloned())
+    }
+
+    /// Every registered markdown page, keyed by its path relative to the space directory (with
+    /// forward slashes).
+    pub fn resolved_links(&self) -> BTreeMap<String, ResolvedLink> {
+        self.filename_to_title
+            .iter()
+            .map(|(filename, title)| {
+                (
+                    filename.clone(),
+                    ResolvedLink {
+                        title: title.clone(),
+                        url: self.get_file_url(Path::new(filename)),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// The stable URL of the page of the space an absolute Confluence link points at (with
+    /// `resolve_confluence_links`), when that isn't the link already. Links to pages that are gone
+    /// or archived are left alone with a warning, or fail the render with `--strict`.
+    fn resolve_confluence_link(&self, url: &str, source: &str) -> io::Result<Option<String>> {
+        if !self.external_links.resolves_confluence_links() {
+            return Ok(None);
+        }
+        let Some(link) = confluence_link(url, &self.host, &self.space_key) else {
+            return Ok(None);
+        };
+        let known = link.page_id == self.homepage_id
+            || self.title_to_id.values().any(|id| *id == link.page_id)
+            || self.filename_to_id.values().any(|id| *id == link.page_id);
+        let problem = match self.inactive_ids.get(&link.page_id) {
+            Some(status) => Some(format!("is {} in Confluence", status)),
+            // short links can point at other spaces of the site, which aren't known here
+            None if !known && link.in_space => Some(String::from("isn't in the space anymore")),
+            None if !known => return Ok(None),
+            None => None,
+        };
+        if let Some(problem) = problem {
+            self.warn(
+                source,
+                Warning::new(
+                    WarningCode::InactiveLink,
+                    format!(
+                        "Confluence link {} in {} points at a page that {}",
+                        url, source, problem
+                    ),
+                ),
+            )?;
+            return Ok(None);
+        }
+        let mut resolved = self.id_to_url(&link.page_id);
+        if let Some(fragment) = link.fragment {
+            resolved.push('#');
+            resolved.push_str(&fragment);
+        }
+        Ok((resolved != url).then_some(resolved))
+    }
+
+    /// Writes the start of a link, returning true if the link text has already been written and
+    /// the children of the link shouldn't be rendered.
     pub fn enter(
         &self,
         nl: &NodeLink,
         confluence_formatter: &mut ConfluenceStorageRenderer,
         no_children: bool,
-    ) -> io::Result<()> {
-        if nl.url.contains("://") {
+    ) -> io::Result<bool> {
+        if nl.url.contains("://") || nl.url.starts_with("mailto:") {
+            let source = confluence_formatter
+                .source
+                .to_string_lossy()
+                .replace('\\', "/");
+            let tidied = self.external_links.rewrite(&nl.url);
+            let resolved =
+                self.resolve_confluence_link(tidied.as_deref().unwrap_or(&nl.url), &source)?;
+            let url = match resolved.or(tidied) {
+                Some(rewritten) => {
+                    self.rewritten_links.lock().unwrap().insert(RewrittenLink {
+                        source,
+                        from: nl.url.clone(),
+                        to: rewritten.clone(),
+                    });
+                    rewritten
+                }
+                None => nl.url.clone(),
+            };
             confluence_formatter.output.write_all(b"<a href=\"")?;
-            confluence_formatter.output.write_all(nl.url.as_bytes())?;
+            confluence_formatter.output.write_all(url.as_bytes())?;
             confluence_formatter.output.write_all(b"\">")?;
-            return Ok(());
+            return Ok(false);
         }
 
-        let local_link = relative_local_link(nl, confluence_formatter);
+        let mut local_link = relative_local_link(nl, confluence_formatter);
+        if let Some(anchor) = &local_link.anchor {
+            // sections of split pages have their own page
+            let section = PathBuf::from(format!("{}#{}", local_link.path.display(), anchor));
+            if self.get_file_title(&section).is_some() {
+                local_link = LocalLink {
+                    path: section,
+                    anchor: None,
+                };
+            }
+        }
+        if let Some((filename, anchor)) = Self::path_to_string(&local_link.path)
+            .ok()
+            .and_then(|path| self.merged_files.get(&path))
+        {
+            local_link = LocalLink {
+                path: PathBuf::from(filename),
+                anchor: local_link.anchor.or(Some(anchor.clone())),
+            };
+        }
         confluence_formatter.output.write_all(b"<a href=\"")?;
 
         let mut link_empty = true;
 
+        let source = confluence_formatter
+            .source
+            .to_string_lossy()
+            .replace('\\', "/");
+        if let Some(status) = self.inactive_status(&local_link.path) {
+            self.warn(
+                &source,
+                Warning::new(
+                    WarningCode::InactiveLink,
+                    format!(
+                        "file link {} in {} points at a page that is {} in Confluence",
+                        &local_link.path.display(),
+                        &confluence_formatter.source.display(),
+                        status
+                    ),
+                ),
+            )?;
+        }
+
         if let Some(url) = self.get_file_url(&local_link.path) {
             link_empty = false;
             confluence_formatter.output.write_all(url.as_bytes())?;
@@ -175,23 +627,48 @@ impl LinkGenerator {
             confluence_formatter.output.write_all(anchor.as_bytes())?;
         }
 
-        if link_empty {
-            print_warning(&format!(
-                "file link {} in {} couldn't be resolved",
-                &local_link.path.display(),
-                &confluence_formatter.source.display(),
-            ));
+        if link_empty && self.is_snippet_file(&local_link.path) {
+            self.warn(
+                &source,
+                Warning::new(
+                    WarningCode::SnippetLink,
+                    format!(
+                        "file link {} in {} points at a snippet, which isn't published (include it instead)",
+                        &local_link.path.display(),
+                        &confluence_formatter.source.display(),
+                    ),
+                ),
+            )?;
+        } else if link_empty {
+            self.warn(
+                &source,
+                Warning::new(
+                    WarningCode::UnresolvedLink,
+                    format!(
+                        "file link {} in {} couldn't be resolved",
+                        &local_link.path.display(),
+                        &confluence_formatter.source.display(),
+                    ),
+                ),
+            )?;
+        }
+
+        let title = self.get_file_title(&local_link.path);
+        if let (LinkText::Tooltip, Some(title)) = (self.link_text, &title) {
+            confluence_formatter.output.write_all(b"\" title=\"")?;
+            escape(confluence_formatter.output, title.as_bytes())?;
         }
 
         confluence_formatter.output.write_all(b"\">")?;
 
-        if no_children {
-            confluence_formatter
-                .output
-                .write_all(self.get_file_title(&local_link.path).unwrap().as_bytes())?;
+        if no_children || self.link_text == LinkText::Title {
+            if let Some(title) = title {
+                escape(confluence_formatter.output, title.as_bytes())?;
+                return Ok(true);
+            }
         }
 
-        Ok(())
+        Ok(false)
     }
 
     pub fn exit(
@@ -205,14 +682,19 @@ impl LinkGenerator {
     }
 
     pub fn is_folder(&self, title: &str) -> bool {
-        self.folders.contains(title)
+        self.folders.contains(&self.title_policy.normalize(title))
+    }
+
+    /// The file of a title returned by `get_nodes_to_create`.
+    pub fn get_title_file(&self, title: &str) -> Option<&str> {
+        self.title_to_file.get(title).map(String::as_str)
     }
 
     pub fn get_nodes_to_create(&self) -> Vec<String> {
         self.title_to_file
             .iter()
             .filter_map(|(title, file)| {
-                if !self.filename_to_id.contains_key(file) {
+                if !self.filename_to_id.contains_key(file) && !self.scheduled_files.contains(file) {
                     Some(title.clone())
                 } else {
                     None
@@ -222,11 +704,19 @@ impl LinkGenerator {
     }
 
     pub fn is_orphaned(&self, node: &ConfluenceNode, confluence_page: &ConfluencePageData) -> bool {
+        // another page synced from the same file got it, this one is a duplicate
+        let is_duplicate = confluence_page
+            .path
+            .as_deref()
+            .and_then(|path| Self::path_to_string(path).ok())
+            .is_some_and(|path| self.mapped_files.contains(&path));
         confluence_page
             .version
             .message
             .starts_with(ConfluencePageData::version_message_prefix())
-            && !self.has_title(node.title.as_str())
+            && (is_duplicate || !self.has_title(node.title.as_str()))
+            && !self.mapped_ids.contains(&node.id)
+            && node.id != self.homepage_id
     }
 
     pub fn attachment_id(&self, _relative_path: &str, _page: &MarkdownPage) -> Option<String> {
@@ -258,18 +748,21 @@ fn relative_local_link(
 
 #[cfg(test)]
 mod test {
-    use std::path::PathBuf;
-
-    use comrak::{nodes::AstNode, Arena};
+    use std::{
+        collections::BTreeSet,
+        path::{Path, PathBuf},
+    };
 
     use crate::{
         confluence_page::{ConfluenceNode, ConfluenceNodeType, ConfluencePageData},
         error::TestResult,
         responses::{self, ContentStatus, Version},
+        space_config::SpaceConfig,
         test_helpers::markdown_page_from_str,
+        warnings::WarningCode,
     };
 
-    use super::LinkGenerator;
+    use super::{LinkGenerator, ResolvedLink, TitlePolicy};
 
     #[test]
     fn it_returns_homepage_link_for_root_index_md() -> TestResult {
@@ -283,6 +776,187 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn it_keeps_pages_when_titles_are_swapped() -> TestResult {
+        let mut link_generator = LinkGenerator::default_test();
+        for (source, content) in [("a.md", "# Usage\n"), ("b.md", "# Setup\n")] {
+            link_generator.register_markdown_page(&markdown_page_from_str(source, content)?)?;
+        }
+        let page = |id: &str, title: &str, source: &str| ConfluenceNode {
+            id: String::from(id),
+            title: String::from(title),
+            parent_id: Some(String::from("999")),
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: Version {
+                    message: format!("updated by markedspace: source={}; checksum=X", source),
+                    number: 1,
+                },
+                path: Some(PathBuf::from(source)),
+                status: ContentStatus::Current,
+            }),
+        };
+        let mut space = crate::confluence_space::ConfluenceSpace::new_test(
+            "1",
+            "999",
+            vec![page("2", "Setup", "a.md"), page("3", "Usage", "b.md")],
+        );
+
+        space.link_pages(&mut link_generator)?;
+
+        assert_eq!(
+            link_generator.get_file_id(&PathBuf::from("a.md")),
+            Some(String::from("2"))
+        );
+        assert_eq!(
+            link_generator.get_file_id(&PathBuf::from("b.md")),
+            Some(String::from("3"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_prefers_the_page_map_over_titles() -> TestResult {
+        let mut link_generator = LinkGenerator::default_test();
+        link_generator.register_markdown_page(&markdown_page_from_str(
+            "guide.md",
+            "# New Title
+",
+        )?)?;
+        let page = |id: &str, title: &str| ConfluenceNode {
+            id: String::from(id),
+            title: String::from(title),
+            parent_id: Some(String::from("999")),
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: Version {
+                    message: String::from("updated by markedspace: source=guide.md; checksum=X"),
+                    number: 1,
+                },
+                path: Some(PathBuf::from("guide.md")),
+                status: ContentStatus::Current,
+            }),
+        };
+        // the page of guide.md was retitled, and an unrelated page has the new title
+        let nodes = vec![page("42", "Old Title"), page("43", "New Title")];
+        let pages = std::collections::BTreeMap::from([(
+            String::from("guide.md"),
+            crate::page_map::PageMapEntry {
+                id: String::from("42"),
+                checksum: None,
+                failed: false,
+            },
+        )]);
+
+        // every command reading the space links its pages with the page map
+        let mut space =
+            crate::confluence_space::ConfluenceSpace::new_test("1", "999", nodes.clone());
+        space.page_map.pages = pages;
+        space.link_pages(&mut link_generator)?;
+
+        assert_eq!(
+            link_generator.get_file_id(&PathBuf::from("guide.md")),
+            Some(String::from("42"))
+        );
+        if let ConfluenceNodeType::Page(page_data) = &nodes[0].data {
+            assert!(!link_generator.is_orphaned(&nodes[0], page_data));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_prefers_the_page_ids_file_over_the_page_map() -> TestResult {
+        let mut link_generator = LinkGenerator::default_test();
+        link_generator.register_markdown_page(&markdown_page_from_str("guide.md", "# Guide\n")?)?;
+        let page = |id: &str| ConfluenceNode {
+            id: String::from(id),
+            title: String::from("Guide"),
+            parent_id: Some(String::from("999")),
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: Version {
+                    message: String::default(),
+                    number: 1,
+                },
+                path: None,
+                status: ContentStatus::Current,
+            }),
+        };
+        let nodes = vec![page("42"), page("43")];
+        let page_map = std::collections::BTreeMap::from([(
+            String::from("guide.md"),
+            crate::page_map::PageMapEntry {
+                id: String::from("42"),
+                checksum: None,
+                failed: false,
+            },
+        )]);
+        let page_ids =
+            std::collections::BTreeMap::from([(String::from("guide.md"), String::from("43"))]);
+
+        link_generator.register_page_ids(&page_ids, &nodes);
+        link_generator.register_page_map(&page_map, &nodes);
+        for node in &nodes {
+            link_generator.register_confluence_node(node);
+        }
+
+        assert_eq!(
+            link_generator.get_file_id(&PathBuf::from("guide.md")),
+            Some(String::from("43"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_resolves_links_of_all_pages() -> TestResult {
+        let mut link_generator = LinkGenerator::default_test();
+        for (source, content) in [
+            ("index.md", "# Home\n"),
+            ("docs/published.md", "# Published\n"),
+            ("docs/new.md", "# New\n"),
+        ] {
+            link_generator.register_markdown_page(&markdown_page_from_str(source, content)?)?;
+        }
+        link_generator.register_confluence_node(&ConfluenceNode {
+            id: "42".to_string(),
+            title: "Published".to_string(),
+            parent_id: None,
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: responses::Version {
+                    message: String::default(),
+                    number: 1,
+                },
+                path: None,
+                status: ContentStatus::Current,
+            }),
+        });
+
+        let links = link_generator.resolved_links();
+
+        assert_eq!(
+            links.keys().collect::<Vec<&String>>(),
+            vec!["docs/new.md", "docs/published.md", "index.md"]
+        );
+        assert_eq!(
+            links["docs/published.md"],
+            ResolvedLink {
+                title: String::from("Published"),
+                url: Some(String::from(
+                    "https://example.atlassian.net/wiki/spaces/TEST/pages/42"
+                )),
+            }
+        );
+        assert_eq!(links["docs/new.md"].url, None);
+        assert_eq!(
+            links["index.md"].url,
+            Some(String::from(
+                "https://example.atlassian.net/wiki/spaces/TEST/pages/999"
+            ))
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn it_handles_retitles() -> TestResult {
         let mut link_generator = LinkGenerator::default_test();
@@ -291,11 +965,9 @@ mod test {
         let new_title = String::from("New Title");
         let source = String::from("test.md");
 
-        let arena = Arena::<AstNode>::new();
         link_generator.register_markdown_page(&markdown_page_from_str(
             &source,
             &format!("# {} \n", new_title),
-            &arena,
         )?)?;
         link_generator.register_confluence_node(&ConfluenceNode {
             id: "1".to_string(),
@@ -327,11 +999,9 @@ mod test {
         let old_source = String::from("old-test.md");
         let new_source = String::from("new-test.md");
 
-        let arena = Arena::<AstNode>::new();
         link_generator.register_markdown_page(&markdown_page_from_str(
             &new_source,
             &format!("# {} \n", title),
-            &arena,
         )?)?;
         link_generator.register_confluence_node(&ConfluenceNode {
             id: "9991".to_string(),
@@ -366,11 +1036,9 @@ mod test {
         let old_source = String::from("old-test.md");
         let new_source = String::from("new-test.md");
 
-        let arena = Arena::<AstNode>::new();
         link_generator.register_markdown_page(&markdown_page_from_str(
             &new_source,
             &format!("# {} \n", new_title),
-            &arena,
         )?)?;
         link_generator.register_confluence_node(&ConfluenceNode {
             id: "9991".to_string(),
@@ -399,11 +1067,9 @@ mod test {
         let new_title = String::from("New Title");
         let new_source = String::from("new-test.md");
 
-        let arena = Arena::<AstNode>::new();
         link_generator.register_markdown_page(&markdown_page_from_str(
             &new_source,
             &format!("# {} \n", new_title),
-            &arena,
         )?)?;
 
         let pages_to_create = link_generator.get_nodes_to_create();
@@ -414,6 +1080,30 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn it_does_not_create_scheduled_pages() -> TestResult {
+        let mut link_generator = LinkGenerator::default_test();
+
+        link_generator
+            .register_markdown_page(&markdown_page_from_str("release.md", "# Release Notes\n")?)?;
+        link_generator.register_scheduled_file(Path::new("release.md"));
+
+        assert!(link_generator.get_nodes_to_create().is_empty());
+        assert_eq!(link_generator.get_file_url(Path::new("release.md")), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_knows_snippets() {
+        let mut link_generator = LinkGenerator::default_test();
+        link_generator.register_snippet_file(Path::new("shared/support.md"));
+
+        assert!(link_generator.is_snippet_file(Path::new("shared/./support.md")));
+        assert!(!link_generator.is_snippet_file(Path::new("support.md")));
+        assert!(link_generator.get_nodes_to_create().is_empty());
+    }
+
     #[test]
     fn it_does_not_create_homepage_because_it_always_exists() -> TestResult {
         let mut link_generator = LinkGenerator::default_test();
@@ -421,11 +1111,9 @@ mod test {
         let new_title = String::from("Homepage");
         let new_source = String::from("index.md");
 
-        let arena = Arena::<AstNode>::new();
         link_generator.register_markdown_page(&markdown_page_from_str(
             &new_source,
             &format!("# {} \n", new_title),
-            &arena,
         )?)?;
 
         link_generator.register_confluence_node(&ConfluenceNode {
@@ -449,6 +1137,120 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn it_creates_index_md_under_a_locked_homepage() -> TestResult {
+        let homepage = ConfluenceNode {
+            id: "999".to_string(),
+            title: "Homepage".into(),
+            parent_id: None,
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: responses::Version {
+                    message: "Default created".into(),
+                    number: 1,
+                },
+                path: None,
+                status: ContentStatus::Current,
+            }),
+        };
+
+        // index.md can't get a second page titled like the homepage
+        let mut colliding = LinkGenerator::default_test();
+        colliding.lock_homepage();
+        colliding.register_markdown_page(&markdown_page_from_str("index.md", "# Homepage\n")?)?;
+        colliding.register_confluence_node(&homepage);
+        assert_eq!(
+            colliding.check_locked_homepage().unwrap_err().to_string(),
+            "index.md has the title of the locked homepage (\"Homepage\"), set homepage.title in \
+             _space.yml to give its page another title"
+        );
+
+        // with homepage.title, index.md gets a title of its own
+        let mut link_generator = LinkGenerator::default_test();
+        link_generator.lock_homepage();
+        link_generator
+            .register_markdown_page(&markdown_page_from_str("index.md", "# Team Handbook\n")?)?;
+        link_generator.register_markdown_page(&markdown_page_from_str("other.md", "# Other\n")?)?;
+        link_generator.register_confluence_node(&homepage);
+        link_generator.check_locked_homepage()?;
+
+        let mut pages_to_create = link_generator.get_nodes_to_create();
+        pages_to_create.sort();
+        assert_eq!(pages_to_create, vec!["Other", "Team Handbook"]);
+
+        link_generator.register_confluence_node(&ConfluenceNode {
+            id: "1".to_string(),
+            title: "Team Handbook".into(),
+            parent_id: Some("999".into()),
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: responses::Version {
+                    message: String::default(),
+                    number: 1,
+                },
+                path: None,
+                status: ContentStatus::Current,
+            }),
+        });
+
+        assert_eq!(
+            link_generator.get_parent_id(&PathBuf::from("index.md")),
+            Some(String::from("999"))
+        );
+        assert_eq!(
+            link_generator.get_parent_id(&PathBuf::from("other.md")),
+            Some(String::from("1"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_uses_the_homepage_as_the_top_level_parent() {
+        let link_generator = LinkGenerator::default_test();
+
+        assert_eq!(
+            link_generator.get_parent_id(&PathBuf::from("index.md")),
+            None
+        );
+        assert_eq!(
+            link_generator.get_parent_id(&PathBuf::from("other.md")),
+            Some(String::from("999"))
+        );
+    }
+
+    #[test]
+    fn it_matches_remote_titles_after_normalizing() -> TestResult {
+        let mut link_generator = LinkGenerator::default_test();
+        link_generator.set_title_policy(&TitlePolicy {
+            straighten_quotes: true,
+            ..TitlePolicy::default()
+        });
+
+        link_generator
+            .register_markdown_page(&markdown_page_from_str("page.md", "# Don't  panic \n")?)?;
+
+        link_generator.register_confluence_node(&ConfluenceNode {
+            id: "1".to_string(),
+            title: "Don\u{2019}t panic ".into(),
+            parent_id: Some("999".into()),
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: responses::Version {
+                    message: String::default(),
+                    number: 1,
+                },
+                path: None,
+                status: ContentStatus::Current,
+            }),
+        });
+
+        assert!(link_generator.get_nodes_to_create().is_empty());
+        assert_eq!(
+            link_generator.get_file_id(&PathBuf::from("page.md")),
+            Some(String::from("1"))
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn it_identifies_orphans() {
         let orphaned_confluence_page = ConfluenceNode {
@@ -471,4 +1273,140 @@ mod test {
             orphaned_confluence_page.page_data().unwrap()
         ));
     }
+
+    #[test]
+    fn it_reports_links_to_archived_pages() -> TestResult {
+        let mut link_generator = LinkGenerator::default_test();
+        let page = markdown_page_from_str("index.md", "# Home\n\n[old](old.md)\n")?;
+        link_generator.register_markdown_page(&page)?;
+        link_generator.register_markdown_page(&markdown_page_from_str("old.md", "# Old\n")?)?;
+        // archived by hand, so it isn't restored
+        let nodes = vec![ConfluenceNode {
+            id: "42".to_string(),
+            title: "Old".to_string(),
+            parent_id: None,
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: Version {
+                    message: String::default(),
+                    number: 3,
+                },
+                path: None,
+                status: ContentStatus::Archived,
+            }),
+        }];
+        for node in &nodes {
+            link_generator.register_confluence_node(node);
+        }
+        link_generator.register_inactive_pages(&nodes);
+
+        assert_eq!(
+            link_generator.inactive_status(Path::new("old.md")),
+            Some(&ContentStatus::Archived)
+        );
+        assert!(link_generator
+            .inactive_status(Path::new("index.md"))
+            .is_none());
+        assert!(page.render(&link_generator).is_ok());
+        link_generator.set_strict(true);
+        let err = page.render(&link_generator).unwrap_err();
+        assert!(format!("{:#}", err).contains("points at a page that is archived in Confluence"));
+        link_generator.set_disabled_warnings(&BTreeSet::from([WarningCode::InactiveLink]));
+        assert!(page.render(&link_generator).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_resolves_links_to_pages_of_the_space() -> TestResult {
+        let mut link_generator = LinkGenerator::default_test();
+        link_generator.set_external_links(
+            &SpaceConfig::from_yaml_str("external_links:\n  resolve_confluence_links: true")?
+                .external_links,
+        );
+        let page = markdown_page_from_str(
+            "index.md",
+            "# Home\n\n[short](https://example.atlassian.net/wiki/x/KgA) [long](https://example.atlassian.net/wiki/spaces/TEST/pages/42/Old+Title#Usage) [gone](https://example.atlassian.net/wiki/spaces/TEST/pages/7)\n",
+        )?;
+        link_generator.register_markdown_page(&page)?;
+        link_generator.register_confluence_node(&ConfluenceNode {
+            id: "42".to_string(),
+            title: "Current Title".to_string(),
+            parent_id: None,
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: Version {
+                    message: String::default(),
+                    number: 3,
+                },
+                path: None,
+                status: ContentStatus::Current,
+            }),
+        });
+
+        assert_eq!(
+            page.render(&link_generator)?.content,
+            "<p><a href=\"https://example.atlassian.net/wiki/spaces/TEST/pages/42\">short</a> <a href=\"https://example.atlassian.net/wiki/spaces/TEST/pages/42#Usage\">long</a> <a href=\"https://example.atlassian.net/wiki/spaces/TEST/pages/7\">gone</a></p>\n"
+        );
+        assert_eq!(link_generator.rewritten_links().len(), 2);
+        link_generator.set_strict(true);
+        let err = page.render(&link_generator).unwrap_err();
+        assert!(format!("{:#}", err).contains("points at a page that isn't in the space anymore"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_resolves_confluence_links_to_the_pages_of_files() -> TestResult {
+        let mut link_generator = LinkGenerator::default_test();
+        link_generator.set_external_links(
+            &SpaceConfig::from_yaml_str("external_links:\n  resolve_confluence_links: true")?
+                .external_links,
+        );
+        let page = markdown_page_from_str(
+            "index.md",
+            "# Home\n\n[guide](https://example.atlassian.net/wiki/spaces/TEST/pages/43/Old+Guide) [elsewhere](https://example.atlassian.net/wiki/x/AQ)\n",
+        )?;
+        link_generator.register_markdown_page(&page)?;
+        link_generator.register_markdown_page(&markdown_page_from_str("guide.md", "# Guide\n")?)?;
+        // the page of guide.md has the title of before, the page map knows it's the file's
+        let nodes = vec![ConfluenceNode {
+            id: "43".to_string(),
+            title: "Old Guide".to_string(),
+            parent_id: None,
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: Version {
+                    message: String::default(),
+                    number: 3,
+                },
+                path: None,
+                status: ContentStatus::Current,
+            }),
+        }];
+        link_generator.register_page_map(
+            &std::collections::BTreeMap::from([(
+                String::from("guide.md"),
+                crate::page_map::PageMapEntry {
+                    id: String::from("43"),
+                    checksum: None,
+                    failed: false,
+                },
+            )]),
+            &nodes,
+        );
+        // a short link to a page that isn't one of the space could be of another space
+        link_generator.set_strict(true);
+
+        assert_eq!(
+            page.render(&link_generator)?.content,
+            "<p><a href=\"https://example.atlassian.net/wiki/spaces/TEST/pages/43\">guide</a> <a href=\"https://example.atlassian.net/wiki/x/AQ\">elsewhere</a></p>\n"
+        );
+        assert_eq!(
+            link_generator.get_file_url(Path::new("guide.md")),
+            Some(String::from(
+                "https://example.atlassian.net/wiki/spaces/TEST/pages/43"
+            ))
+        );
+        assert_eq!(link_generator.rewritten_links().len(), 1);
+
+        Ok(())
+    }
 }
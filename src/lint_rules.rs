@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+
+use anyhow::anyhow;
+use comrak::nodes::{AstNode, NodeValue};
+use saphyr::Yaml;
+
+use crate::{
+    duplicates::DuplicatePolicy,
+    error::Result,
+    helpers::collect_text,
+    space_config::parse_bool,
+    warnings::{Warning, WarningCode},
+};
+
+/// Structure rules checked for every page, configured with the `lint` key in `_space.yml`.
+///
+/// Violations are reported as warnings, so `--strict` turns them into errors.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct LintRules {
+    pub max_heading_depth: Option<usize>,
+    /// Warn when a heading is more than one level deeper than the one before it.
+    pub no_skipped_heading_levels: bool,
+    /// Maximum number of lines of markdown (after the front matter).
+    pub max_page_lines: Option<usize>,
+    /// Page type (the `type` front matter key) to the headings those pages must have.
+    pub required_sections: BTreeMap<String, Vec<String>>,
+    /// Warn about paragraphs and code blocks that (nearly) repeat one from another page.
+    pub near_duplicates: Option<DuplicatePolicy>,
+}
+
+pub(crate) fn parse_positive_number(yaml: &Yaml, key: &str) -> Result<Option<usize>> {
+    match yaml {
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        Yaml::Integer(n) if *n > 0 => Ok(Some(*n as usize)),
+        _ => Err(anyhow!(
+            "Failed to parse \"{}\" key (should be a positive number)",
+            key
+        )),
+    }
+}
+
+fn parse_required_sections(yaml: &Yaml) -> Result<BTreeMap<String, Vec<String>>> {
+    let error = || {
+        anyhow!(
+            "Failed to parse \"lint.required_sections\" key (should be a map of page types to lists of headings)"
+        )
+    };
+    match yaml {
+        Yaml::BadValue | Yaml::Null => Ok(BTreeMap::default()),
+        Yaml::Hash(hash) => hash
+            .iter()
+            .map(|(page_type, sections)| {
+                let page_type = page_type.as_str().ok_or_else(error)?;
+                let sections = sections
+                    .as_vec()
+                    .ok_or_else(error)?
+                    .iter()
+                    .map(|section| section.as_str().map(String::from).ok_or_else(error))
+                    .collect::<Result<Vec<String>>>()?;
+                Ok((String::from(page_type), sections))
+            })
+            .collect(),
+        _ => Err(error()),
+    }
+}
+
+impl LintRules {
+    pub fn from_yaml(yaml: &Yaml) -> Result<Self> {
+        match yaml {
+            Yaml::BadValue | Yaml::Null => Ok(LintRules::default()),
+            Yaml::Hash(_) => Ok(LintRules {
+                max_heading_depth: parse_positive_number(
+                    &yaml["max_heading_depth"],
+                    "lint.max_heading_depth",
+                )?,
+                no_skipped_heading_levels: parse_bool(
+                    &yaml["no_skipped_heading_levels"],
+                    "lint.no_skipped_heading_levels",
+                )?,
+                max_page_lines: parse_positive_number(
+                    &yaml["max_page_lines"],
+                    "lint.max_page_lines",
+                )?,
+                required_sections: parse_required_sections(&yaml["required_sections"])?,
+                near_duplicates: DuplicatePolicy::from_yaml(&yaml["near_duplicates"])?,
+            }),
+            _ => Err(anyhow!("Failed to parse \"lint\" key (should be a map)")),
+        }
+    }
+
+    /// Checks the body of a page, i.e. without the title heading, returning the warnings.
+    pub fn check<'a>(
+        &self,
+        root: &'a AstNode<'a>,
+        content: &str,
+        page_type: Option<&str>,
+    ) -> Vec<Warning> {
+        let mut warnings = Vec::<Warning>::default();
+
+        if let Some(max_page_lines) = self.max_page_lines {
+            let lines = content.lines().count();
+            if lines > max_page_lines {
+                warnings.push(Warning::new(
+                    WarningCode::LongPage,
+                    format!(
+                        "page is {} lines long, more than the maximum of {}",
+                        lines, max_page_lines
+                    ),
+                ));
+            }
+        }
+
+        // the title is always the level 1 heading
+        let mut previous_level = 1;
+        let mut headings = Vec::<String>::default();
+        for node in root.descendants() {
+            let level = match node.data.borrow().value {
+                NodeValue::Heading(heading) => heading.level as usize,
+                _ => continue,
+            };
+            let mut text = Vec::with_capacity(20);
+            collect_text(node, &mut text);
+            let text = String::from_utf8_lossy(&text).trim().to_string();
+
+            if let Some(max_heading_depth) = self.max_heading_depth {
+                if level > max_heading_depth {
+                    warnings.push(Warning::new(
+                        WarningCode::DeepHeading,
+                        format!(
+                            "heading \"{}\" is level {}, deeper than the maximum of {}",
+                            text, level, max_heading_depth
+                        ),
+                    ));
+                }
+            }
+            if self.no_skipped_heading_levels && level > previous_level + 1 {
+                warnings.push(Warning::new(
+                    WarningCode::SkippedHeadingLevel,
+                    format!(
+                        "heading \"{}\" skips from level {} to level {}",
+                        text, previous_level, level
+                    ),
+                ));
+            }
+            previous_level = level;
+            headings.push(text);
+        }
+
+        if let Some(page_type) = page_type {
+            for section in self.required_sections.get(page_type).into_iter().flatten() {
+                if !headings
+                    .iter()
+                    .any(|heading| heading.eq_ignore_ascii_case(section.trim()))
+                {
+                    warnings.push(Warning::new(
+                        WarningCode::MissingRequiredSection,
+                        format!(
+                            "{} page is missing required section \"{}\"",
+                            page_type, section
+                        ),
+                    ));
+                }
+            }
+        }
+
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use comrak::{parse_document, Arena, Options};
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    fn rules_from_str(s: &str) -> Result<LintRules> {
+        LintRules::from_yaml(&Yaml::load_from_str(s)?[0])
+    }
+
+    fn check(rules: &LintRules, content: &str, page_type: Option<&str>) -> Vec<String> {
+        let arena = Arena::new();
+        let root = parse_document(&arena, content, &Options::default());
+        rules
+            .check(root, content, page_type)
+            .into_iter()
+            .map(|warning| warning.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn it_checks_nothing_by_default() {
+        let rules = LintRules::default();
+
+        assert!(check(&rules, "#### Deep\n\n###### Deeper\n", None).is_empty());
+    }
+
+    #[test]
+    fn it_checks_heading_levels() -> TestResult {
+        let rules = rules_from_str("max_heading_depth: 3\nno_skipped_heading_levels: true")?;
+
+        assert_eq!(
+            check(&rules, "## Overview\n#### Details\n### Steps\n", None),
+            vec![
+                "heading \"Details\" is level 4, deeper than the maximum of 3 [MS009]",
+                "heading \"Details\" skips from level 2 to level 4 [MS010]"
+            ]
+        );
+        assert_eq!(
+            check(&rules, "### Overview\n", None),
+            vec!["heading \"Overview\" skips from level 1 to level 3 [MS010]"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_checks_page_length() -> TestResult {
+        let rules = rules_from_str("max_page_lines: 2")?;
+
+        assert!(check(&rules, "one\ntwo\n", None).is_empty());
+        assert_eq!(
+            check(&rules, "one\ntwo\nthree\n", None),
+            vec!["page is 3 lines long, more than the maximum of 2 [MS008]"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_checks_required_sections() -> TestResult {
+        let rules = rules_from_str("required_sections:\n  runbook: [Rollback, Escalation]")?;
+        let content = "## Steps\n\n## rollback\n";
+
+        assert_eq!(
+            check(&rules, content, Some("runbook")),
+            vec!["runbook page is missing required section \"Escalation\" [MS011]"]
+        );
+        assert!(check(&rules, content, Some("adr")).is_empty());
+        assert!(check(&rules, content, None).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_errors_on_invalid_rules() {
+        assert_eq!(
+            rules_from_str("max_heading_depth: deep")
+                .unwrap_err()
+                .to_string(),
+            "Failed to parse \"lint.max_heading_depth\" key (should be a positive number)"
+        );
+        assert_eq!(
+            rules_from_str("required_sections:\n  runbook: Rollback")
+                .unwrap_err()
+                .to_string(),
+            "Failed to parse \"lint.required_sections\" key (should be a map of page types to lists of headings)"
+        );
+    }
+}
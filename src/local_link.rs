@@ -5,7 +5,9 @@ use std::{
     str::FromStr,
 };
 
-#[derive(Debug, PartialEq)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct LocalLink {
     pub path: PathBuf,
     pub anchor: Option<String>,
@@ -0,0 +1,194 @@
+//! Registry for the `{{ name(...) }}` shortcodes available in markdown pages.
+//!
+//! Builtins register plain tera functions, while spaces can map their own shortcodes to
+//! storage format templates in `_space.yml`, which allows emitting any Confluence macro without
+//! changes to marked-space.
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::anyhow;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tera::{Tera, Value};
+
+use crate::{
+    error::Result,
+    warnings::{Warning, WarningCode},
+};
+
+#[derive(Default)]
+pub(crate) struct MacroRegistry {
+    macros: BTreeMap<String, Box<dyn tera::Function>>,
+    /// Shortcodes of the space that override a builtin.
+    pub warnings: Vec<Warning>,
+}
+
+struct RegisteredMacro(Box<dyn tera::Function>);
+
+impl tera::Function for RegisteredMacro {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        self.0.call(args)
+    }
+
+    fn is_safe(&self) -> bool {
+        self.0.is_safe()
+    }
+}
+
+/// A shortcode defined by a storage format template, with the shortcode arguments available as
+/// variables.
+struct StorageTemplateMacro {
+    name: String,
+    tera: Tera,
+}
+
+impl StorageTemplateMacro {
+    fn new(name: &str, template: &str) -> Result<Self> {
+        let mut tera = Tera::default();
+        tera.autoescape_on(vec![]);
+        tera.add_raw_template(name, template)
+            .map_err(|err| anyhow!("Invalid template for macro '{}': {:#}", name, err))?;
+        Ok(StorageTemplateMacro {
+            name: String::from(name),
+            tera,
+        })
+    }
+}
+
+impl tera::Function for StorageTemplateMacro {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let mut context = tera::Context::new();
+        for (key, value) in args {
+            context.insert(key, value);
+        }
+        self.tera.render(&self.name, &context).map(Value::from)
+    }
+}
+
+fn is_valid_macro_name(name: &str) -> bool {
+    static NAME: Lazy<Regex> = Lazy::new(|| Regex::new("^[A-Za-z_][A-Za-z0-9_]*$").unwrap());
+    NAME.is_match(name)
+}
+
+impl MacroRegistry {
+    pub fn register<F: tera::Function + 'static>(&mut self, name: &str, function: F) {
+        self.macros.insert(String::from(name), Box::new(function));
+    }
+
+    pub fn register_template(&mut self, name: &str, template: &str) -> Result<()> {
+        if !is_valid_macro_name(name) {
+            return Err(anyhow!(
+                "Invalid macro name '{}': can only be letters, numbers and underscores",
+                name
+            ));
+        }
+        if self.contains(name) {
+            self.warnings.push(Warning::new(
+                WarningCode::MacroOverride,
+                format!("macro '{}' overrides a builtin", name),
+            ));
+        }
+        self.register(name, StorageTemplateMacro::new(name, template)?);
+        Ok(())
+    }
+
+    pub fn register_templates(&mut self, templates: &BTreeMap<String, String>) -> Result<()> {
+        for (name, template) in templates {
+            self.register_template(name, template)?;
+        }
+        Ok(())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.macros.contains_key(name)
+    }
+
+    pub fn install(self, tera: &mut Tera) {
+        for (name, function) in self.macros {
+            tera.register_function(&name, RegisteredMacro(function));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use tera::Tera;
+
+    use crate::{builtins::add_builtins, error::TestResult, warnings::WarningCode};
+
+    use super::MacroRegistry;
+
+    fn render(registry: MacroRegistry, template: &str) -> tera::Result<String> {
+        let mut tera = Tera::default();
+        registry.install(&mut tera);
+        tera.render_str(template, &tera::Context::new())
+    }
+
+    #[test]
+    fn it_renders_storage_template_macros() -> TestResult {
+        let mut registry = MacroRegistry::default();
+        registry.register_template(
+            "status",
+            r#"<ac:structured-macro ac:name="status"><ac:parameter ac:name="title">{{ title }}</ac:parameter></ac:structured-macro>"#,
+        )?;
+
+        let result = render(registry, "{{ status(title=\"<done>\") }}")?;
+
+        assert_eq!(
+            result,
+            r#"<ac:structured-macro ac:name="status"><ac:parameter ac:name="title"><done></ac:parameter></ac:structured-macro>"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_supports_optional_arguments() -> TestResult {
+        let mut registry = MacroRegistry::default();
+        registry.register_template(
+            "greet",
+            "hello{% if name is defined %} {{ name }}{% endif %}",
+        )?;
+
+        assert_eq!(render(registry, "{{ greet() }}")?, "hello");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_invalid_names() {
+        let mut registry = MacroRegistry::default();
+        let result = registry.register_template("not-valid", "");
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn it_rejects_invalid_templates() {
+        let mut registry = MacroRegistry::default();
+        let result = registry.register_templates(&BTreeMap::from([(
+            String::from("broken"),
+            String::from("{{ unclosed"),
+        )]));
+
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("Invalid template for macro 'broken'"));
+    }
+
+    #[test]
+    fn it_overrides_builtins() -> TestResult {
+        let mut registry = MacroRegistry::default();
+        add_builtins(&mut Tera::default(), &mut registry)?;
+        assert!(registry.contains("toc"));
+        registry.register_template("toc", "custom")?;
+
+        assert_eq!(registry.warnings[0].code, WarningCode::MacroOverride);
+        assert_eq!(render(registry, "{{ toc() }}")?, "custom");
+
+        Ok(())
+    }
+}
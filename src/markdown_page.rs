@@ -1,31 +1,106 @@
 use std::{
+    collections::BTreeSet,
     fs::File,
     io::{self},
     path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
 };
 
 use crate::{
-    attachments::ImageAttachment, checksum::sha256_digest, confluence_page::ConfluencePageData,
-    confluence_storage_renderer::render_confluence_storage, frontmatter::FrontMatter,
-    helpers::collect_text, link_generator::LinkGenerator, local_link::LocalLink,
-    parent::get_parent_file, template_renderer::TemplateRenderer,
+    attachments::{attachment_filenames, dedup_attachments, AttachmentNames, ImageAttachment},
+    checksum::ChecksumAlgorithm,
+    confluence_page::ConfluencePageData,
+    confluence_storage_renderer::render_confluence_storage,
+    diagnostics::Diagnostic,
+    directory_defaults::DirectoryDefaults,
+    edit_link::EditLink,
+    frontmatter::FrontMatter,
+    heading_anchors::{heading_anchor, HeadingAnchors},
+    helpers::collect_text,
+    image_variants::{dark_variant_markup, parse_image_variants},
+    link_definitions::ReferenceResolver,
+    link_generator::LinkGenerator,
+    local_link::LocalLink,
+    page_emojis::{find_emoji, EmojiTitle},
+    page_merge::{demote_headings, merged_source, MergedFile},
+    page_split::{index_markdown, section_markdown, split_sections},
+    page_styles::apply_styles,
+    page_types::check_page_type,
+    parse_cache::CachedPage,
+    path_variables::PathVariables,
+    placeholder_pages::placeholder_content,
+    qr_codes::QR_CODE_SCHEME,
+    render_profile::{timed, PhaseTimings},
+    space_config::SpaceConfig,
+    template_renderer::TemplateRenderer,
+    title_policy::TitlePolicy,
+    warnings::{inline_disabled_warnings, Warning, WarningCode},
 };
 use anyhow::Context;
 use comrak::{
-    nodes::{AstNode, NodeValue},
+    nodes::{AstNode, NodeHtmlBlock, NodeValue},
     parse_document, Arena, Options,
 };
 
 use crate::{error::ConfluenceError, Result};
 
-pub struct MarkdownPage<'a> {
+pub struct MarkdownPage {
     pub title: String,
     pub source: String,
-    root: &'a AstNode<'a>,
+    /// The file the page is parsed from.
+    path: PathBuf,
+    /// The markdown after templating. The AST is parsed from it again whenever it's needed, in an
+    /// arena of its own, so a space never has more than one page's AST in memory.
+    markdown: String,
+    space_config: Arc<SpaceConfig>,
     pub attachments: Vec<ImageAttachment>,
     pub local_links: Vec<LocalLink>,
     pub front_matter: FrontMatter,
-    pub warnings: Vec<String>,
+    pub warnings: Vec<Warning>,
+    /// Warnings turned off in `_space.yml` or by a comment in the page.
+    pub disabled_warnings: BTreeSet<WarningCode>,
+    pub emoji_title: EmojiTitle,
+    pub checksum: ChecksumAlgorithm,
+    /// Files appended to the page with `merge`.
+    pub merged: Vec<MergedFile>,
+    /// The anchors links can go to: the headings after the title, and the repeated ones that got
+    /// an anchor of their own.
+    pub anchors: BTreeSet<String>,
+    /// The (normalized) labels of the shared link definitions the page uses.
+    pub references: BTreeSet<String>,
+    /// How long reading and parsing the page took, for `--profile-render`.
+    pub timings: PhaseTimings,
+}
+
+/// What the markdown of a page is parsed for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ParseMode {
+    /// Finding out about the page when it's read, with every check and warning.
+    Page,
+    /// Only the AST, prepared for rendering, for a page that was already read.
+    Ast,
+}
+
+/// The headings and text of a page, for the search index.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PageText {
+    /// The text of the headings, without the title.
+    pub headings: Vec<String>,
+    /// The text of the paragraphs and code blocks.
+    pub blocks: Vec<String>,
+}
+
+/// What parsing the markdown of a page found out about it, with the AST it parsed.
+struct ParsedMarkdown<'a> {
+    root: &'a AstNode<'a>,
+    title: String,
+    attachments: Vec<ImageAttachment>,
+    local_links: Vec<LocalLink>,
+    warnings: Vec<Warning>,
+    disabled_warnings: BTreeSet<WarningCode>,
+    anchors: BTreeSet<String>,
+    references: BTreeSet<String>,
 }
 
 pub fn remove_prefix(prefix: &Path, page_path: &Path) -> Result<String> {
@@ -45,46 +120,291 @@ pub fn remove_prefix(prefix: &Path, page_path: &Path) -> Result<String> {
         .replace('\\', "/"))
 }
 
-impl<'a> MarkdownPage<'a> {
+impl MarkdownPage {
     pub fn from_file(
         // markdown_space: &MarkdownSpace,
         space_dir: &Path,
         markdown_page: &Path,
-        arena: &'a Arena<AstNode<'a>>,
         template_renderer: &mut TemplateRenderer,
-    ) -> Result<MarkdownPage<'a>> {
+        space_config: &Arc<SpaceConfig>,
+        defaults: &DirectoryDefaults,
+    ) -> Result<MarkdownPage> {
+        let (source_string, fm, content, merged, timings) =
+            Self::read_file(space_dir, markdown_page, template_renderer, defaults)?;
+        let mut page =
+            Self::parse_markdown(source_string, markdown_page, content, fm, space_config)?;
+        page.merged = merged;
+        page.timings = PhaseTimings {
+            parse: page.timings.parse,
+            ..timings
+        };
+        Ok(page)
+    }
+
+    /// The page of the file, followed by the pages of its sections when it has `split: h2`, or
+    /// is too big with `limits.oversized: split`.
+    pub fn from_file_split(
+        space_dir: &Path,
+        markdown_page: &Path,
+        template_renderer: &mut TemplateRenderer,
+        space_config: &Arc<SpaceConfig>,
+        defaults: &DirectoryDefaults,
+    ) -> Result<Vec<MarkdownPage>> {
+        let (source_string, fm, content, merged, timings) =
+            Self::read_file(space_dir, markdown_page, template_renderer, defaults)?;
+        if !fm.split && !space_config.limits.splits(&content) {
+            let mut page =
+                Self::parse_markdown(source_string, markdown_page, content, fm, space_config)?;
+            page.merged = merged;
+            page.timings = PhaseTimings {
+                parse: page.timings.parse,
+                ..timings
+            };
+            return Ok(vec![page]);
+        }
+        let file_name = markdown_page
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let (intro, sections) = split_sections(&content);
+        let section_front_matters: Vec<FrontMatter> = sections
+            .iter()
+            .map(|_| Self::section_front_matter(&fm))
+            .collect();
+        let mut page = Self::parse_markdown(
+            source_string.clone(),
+            markdown_page,
+            index_markdown(&intro, &file_name, &sections),
+            fm,
+            space_config,
+        )?;
+        page.merged = merged;
+        // the sections only have their own parse time, the file is read once
+        page.timings = PhaseTimings {
+            parse: page.timings.parse,
+            ..timings
+        };
+        let mut pages = Vec::with_capacity(sections.len() + 1);
+        for (index, (section, fm)) in sections.iter().zip(section_front_matters).enumerate() {
+            pages.push(Self::parse_markdown(
+                format!("{}#{}", source_string, section.anchor),
+                markdown_page,
+                section_markdown(&page.title, &file_name, &sections, index),
+                fm,
+                space_config,
+            )?);
+        }
+        pages.insert(0, page);
+        Ok(pages)
+    }
+
+    /// The front matter of the sections of a split page, which share the labels and emoji of the
+    /// file.
+    fn section_front_matter(fm: &FrontMatter) -> FrontMatter {
+        FrontMatter {
+            labels: fm.labels.clone(),
+            emoji: fm.emoji.clone(),
+            body_line: fm.body_line,
+            ..Default::default()
+        }
+    }
+
+    /// The pages of a file as the parse cache kept them, with the front matter read again.
+    pub(crate) fn from_cached(
+        space_dir: &Path,
+        markdown_page: &Path,
+        cached_pages: Vec<CachedPage>,
+        space_config: &Arc<SpaceConfig>,
+        defaults: &DirectoryDefaults,
+    ) -> Result<Vec<MarkdownPage>> {
+        let mut timings = PhaseTimings::default();
+        let source_string = remove_prefix(space_dir, markdown_page)?;
+        let (mut fm, _content) = timed(&mut timings.front_matter, || {
+            let file = File::open(markdown_page)?;
+            let mut reader = io::BufReader::new(file);
+            FrontMatter::from_reader(&mut reader).with_context(|| source_string.clone())
+        })?;
+        defaults.apply(
+            markdown_page,
+            &PathVariables::from_source(&source_string),
+            &mut fm,
+        )?;
+        let mut front_matters: Vec<FrontMatter> = cached_pages
+            .iter()
+            .skip(1)
+            .map(|_| Self::section_front_matter(&fm))
+            .collect();
+        front_matters.insert(0, fm);
+        Ok(cached_pages
+            .into_iter()
+            .zip(front_matters)
+            .enumerate()
+            .map(|(index, (cached, fm))| MarkdownPage {
+                title: cached.title,
+                source: cached.source,
+                path: markdown_page.to_path_buf(),
+                markdown: cached.markdown,
+                space_config: space_config.clone(),
+                // named again, the files can change without the page changing
+                attachments: cached
+                    .attachments
+                    .into_iter()
+                    .map(|attachment| attachment.named(space_config.attachment_names))
+                    .collect(),
+                local_links: cached.local_links,
+                front_matter: fm,
+                warnings: cached.warnings,
+                disabled_warnings: cached.disabled_warnings.into_iter().collect(),
+                emoji_title: space_config.emoji_title,
+                checksum: space_config.checksum,
+                merged: cached.merged,
+                anchors: cached.anchors.into_iter().collect(),
+                references: cached.references.into_iter().collect(),
+                timings: match index {
+                    0 => timings,
+                    _ => PhaseTimings::default(),
+                },
+            })
+            .collect())
+    }
+
+    /// What the parse cache keeps of the page.
+    pub(crate) fn to_cached(&self) -> CachedPage {
+        CachedPage {
+            title: self.title.clone(),
+            source: self.source.clone(),
+            markdown: self.markdown.clone(),
+            attachments: self.attachments.clone(),
+            local_links: self.local_links.clone(),
+            warnings: self.warnings.clone(),
+            disabled_warnings: self.disabled_warnings.iter().copied().collect(),
+            merged: self.merged.clone(),
+            anchors: self.anchors.iter().cloned().collect(),
+            references: self.references.iter().cloned().collect(),
+        }
+    }
+
+    /// The source relative to the space directory, front matter and rendered markdown of a file,
+    /// with the files it merges appended, and how long reading and templating them took.
+    fn read_file(
+        space_dir: &Path,
+        markdown_page: &Path,
+        template_renderer: &mut TemplateRenderer,
+        defaults: &DirectoryDefaults,
+    ) -> Result<(String, FrontMatter, String, Vec<MergedFile>, PhaseTimings)> {
+        let mut timings = PhaseTimings::default();
         let source_string = remove_prefix(space_dir, markdown_page)?;
-        // let markdown_page = space_dir.join(source);
-        let file = File::open(markdown_page)?;
-        let mut reader = io::BufReader::new(file);
-        let (fm, original_content) =
-            FrontMatter::from_reader(&mut reader).with_context(|| source_string.clone())?;
+        let (mut fm, original_content) = timed(&mut timings.front_matter, || {
+            let file = File::open(markdown_page)?;
+            let mut reader = io::BufReader::new(file);
+            FrontMatter::from_reader(&mut reader).with_context(|| source_string.clone())
+        })?;
+        defaults.apply(
+            markdown_page,
+            &PathVariables::from_source(&source_string),
+            &mut fm,
+        )?;
 
-        let content = template_renderer
-            .render_template_str(&source_string, &original_content, &fm)
-            .context(format!("Loading markdown from file {}", source_string))?;
-        Self::parse_markdown(arena, source_string, markdown_page, &content, fm)
+        let mut content = timed(&mut timings.template, || {
+            template_renderer.render_template_str(&source_string, &original_content, &fm)
+        })
+        .context(format!("Loading markdown from file {}", source_string))?;
+
+        let mut merged = Vec::with_capacity(fm.merge.len());
+        for file in &fm.merge {
+            let source = merged_source(&source_string, file);
+            if Path::new(&source).parent() != Path::new(&source_string).parent() {
+                // links and images in the file are resolved relative to the page
+                return Err(ConfluenceError::generic_error(format!(
+                    "Failed to merge {} into {}: merged files must be in the same directory",
+                    source, source_string
+                )));
+            }
+            let (merged_fm, merged_content) = timed(&mut timings.front_matter, || {
+                let file = File::open(space_dir.join(&source)).with_context(|| {
+                    format!("Failed to merge {} into {}", source, source_string)
+                })?;
+                let mut reader = io::BufReader::new(file);
+                FrontMatter::from_reader(&mut reader).with_context(|| source.clone())
+            })?;
+            let merged_content = timed(&mut timings.template, || {
+                template_renderer.render_template_str(&source, &merged_content, &merged_fm)
+            })
+            .context(format!("Loading markdown from file {}", source))?;
+            let (markdown, anchor) = demote_headings(&merged_content);
+            let anchor = anchor.ok_or_else(|| {
+                ConfluenceError::generic_error(format!(
+                    "Failed to merge {} into {}: it has no heading",
+                    source, source_string
+                ))
+            })?;
+            content.push('\n');
+            content.push_str(&markdown);
+            merged.push(MergedFile { source, anchor });
+        }
+        Ok((source_string, fm, content, merged, timings))
+    }
+
+    /// The file the page is published from, relative to the space directory, which is the source
+    /// without the section of a split page.
+    pub fn source_file(&self) -> &str {
+        self.source
+            .split_once('#')
+            .map_or(self.source.as_str(), |(file, _section)| file)
+    }
+
+    /// The markdown of the page after templating, with what it includes.
+    pub fn markdown(&self) -> &str {
+        &self.markdown
+    }
+
+    /// The files the page is published from: its own and those it merges.
+    pub fn source_files(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.source_file())
+            .chain(self.merged.iter().map(|merged| merged.source.as_str()))
     }
 
     #[cfg(test)]
     pub fn from_str(
         markdown_page: &Path,
         content: &str,
-        arena: &'a Arena<AstNode<'a>>,
         source: String,
         template_renderer: &mut TemplateRenderer,
-    ) -> Result<MarkdownPage<'a>> {
+    ) -> Result<MarkdownPage> {
+        Self::from_str_with_config(
+            markdown_page,
+            content,
+            source,
+            template_renderer,
+            &SpaceConfig::default(),
+        )
+    }
+
+    #[cfg(test)]
+    pub(crate) fn from_str_with_config(
+        markdown_page: &Path,
+        content: &str,
+        source: String,
+        template_renderer: &mut TemplateRenderer,
+        space_config: &SpaceConfig,
+    ) -> Result<MarkdownPage> {
         let (fm, original_content) = FrontMatter::from_str(content)?;
         let content = template_renderer
             .render_template_str(source.as_str(), &original_content, &fm)
             .context(format!("Failed to render markdown from file {}", source))?;
-        Self::parse_markdown(arena, source, markdown_page, &content, fm)
+        Self::parse_markdown(
+            source,
+            markdown_page,
+            content,
+            fm,
+            &Arc::new(space_config.clone()),
+        )
     }
 
-    fn options() -> Options<'a> {
+    fn options<'o>() -> Options<'o> {
         let mut options = Options::default();
         options.render.unsafe_ = true;
-        // options.extension.autolink = true;
+        options.extension.autolink = true;
         options.extension.table = true;
         options.extension.tasklist = true;
         options.extension.strikethrough = true;
@@ -96,18 +416,69 @@ impl<'a> MarkdownPage<'a> {
     }
 
     fn parse_markdown(
-        arena: &'a Arena<AstNode<'a>>,
         source: String,
         markdown_page: &Path,
-        content: &str,
+        content: String,
         fm: FrontMatter,
-    ) -> Result<MarkdownPage<'a>> {
+        space_config: &Arc<SpaceConfig>,
+    ) -> Result<MarkdownPage> {
+        let started = Instant::now();
+        // the AST is only needed to find out about the page, rendering parses it again
+        let arena = Arena::new();
+        let parsed = Self::parse_ast(
+            &arena,
+            &source,
+            markdown_page,
+            &content,
+            &fm,
+            space_config,
+            ParseMode::Page,
+        )?;
+        Ok(MarkdownPage {
+            title: parsed.title,
+            source,
+            path: markdown_page.to_path_buf(),
+            markdown: content,
+            space_config: space_config.clone(),
+            attachments: parsed.attachments,
+            local_links: parsed.local_links,
+            warnings: parsed.warnings,
+            disabled_warnings: parsed.disabled_warnings,
+            emoji_title: space_config.emoji_title,
+            front_matter: fm,
+            checksum: space_config.checksum,
+            merged: Vec::default(),
+            anchors: parsed.anchors,
+            references: parsed.references,
+            timings: PhaseTimings {
+                parse: started.elapsed(),
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Parses the markdown of a page into `arena` and prepares the AST for rendering. With
+    /// [`ParseMode::Page`] the page is checked too, the lint rules, types and references only run
+    /// then.
+    fn parse_ast<'a>(
+        arena: &'a Arena<AstNode<'a>>,
+        source: &str,
+        markdown_page: &Path,
+        content: &str,
+        fm: &FrontMatter,
+        space_config: &SpaceConfig,
+        mode: ParseMode,
+    ) -> Result<ParsedMarkdown<'a>> {
+        let checked = mode == ParseMode::Page;
         let parent = markdown_page.parent().unwrap();
-        let root: &AstNode<'_> = parse_document(arena, content, &Self::options());
+        let resolver = Arc::new(ReferenceResolver::new(&space_config.link_definitions));
+        let mut options = Self::options();
+        options.parse.broken_link_callback = Some(resolver.clone());
+        let root: &AstNode<'_> = parse_document(arena, content, &options);
 
-        fn iter_nodes<'a, F>(node: &'a AstNode<'a>, f: &mut F)
+        fn iter_nodes<'n, F>(node: &'n AstNode<'n>, f: &mut F)
         where
-            F: FnMut(&'a AstNode<'a>),
+            F: FnMut(&'n AstNode<'n>),
         {
             f(node);
             for c in node.children() {
@@ -115,103 +486,360 @@ impl<'a> MarkdownPage<'a> {
             }
         }
 
-        let mut errors = Vec::<String>::default();
-        let mut warnings = Vec::<String>::default();
-        if !fm.unknown_keys.is_empty() {
-            warnings.push(format!(
-                "Unknown top level front matter keys: {}",
-                fm.unknown_keys.join(", "),
+        let body_line = fm.body_line;
+        let merging = !fm.merge.is_empty();
+        let mut errors = Vec::<Diagnostic>::default();
+        let mut warnings = Vec::<Warning>::default();
+        if checked && !fm.unknown_keys.is_empty() {
+            warnings.push(Warning::new(
+                WarningCode::UnknownFrontMatterKey,
+                format!(
+                    "Unknown top level front matter keys: {}",
+                    fm.unknown_keys.join(", "),
+                ),
+            ));
+        }
+        if checked && !fm.emoji.trim().is_empty() && find_emoji(&fm.emoji).is_none() {
+            warnings.push(Warning::new(
+                WarningCode::UnknownEmoji,
+                format!("Unknown emoji '{}'", fm.emoji.trim()),
             ));
         }
 
         let mut attachments = Vec::<ImageAttachment>::default();
         if let Some(cover) = &fm.cover {
             if MarkdownPage::is_local_link(&cover.source) {
-                attachments.push(ImageAttachment::new(&cover.source, parent));
+                attachments.push(
+                    ImageAttachment::new(&cover.source, parent)
+                        .named(space_config.attachment_names),
+                );
             }
         }
         let mut local_links = Vec::<LocalLink>::default();
         let mut first_heading: Option<&AstNode> = None;
-        iter_nodes(root, &mut |node| match &mut node.data.borrow_mut().value {
-            NodeValue::Heading(_heading) => {
-                if first_heading.is_none() {
-                    first_heading = Some(node);
-                } else {
-                    let mut text_content = Vec::with_capacity(20);
+        let mut heading_anchors = HeadingAnchors::default();
+        let mut anchors = BTreeSet::<String>::default();
+        let mut disambiguated_headings = Vec::<(&AstNode, String)>::default();
+        let mut dark_variants = Vec::<(&AstNode, String, String)>::default();
+        let lenient = space_config.titles.lenient;
+        // only a level 1 heading is the title, pages without one get theirs from the file name
+        let h1_title = lenient || space_config.titles.from_filename;
+        let mut demoted_headings = 0;
+        iter_nodes(root, &mut |node| {
+            let sourcepos = node.data.borrow().sourcepos;
+            match &mut node.data.borrow_mut().value {
+                NodeValue::Heading(heading) => {
+                    if first_heading.is_none() && (!h1_title || heading.level == 1) {
+                        first_heading = Some(node);
+                    } else {
+                        if lenient && heading.level == 1 {
+                            heading.level = 2;
+                            demoted_headings += 1;
+                        }
+                        let mut text_content = Vec::with_capacity(20);
+                        for n in node.children() {
+                            collect_text(n, &mut text_content);
+                        }
+                        let text = String::from_utf8_lossy(&text_content);
+                        if let Some(collision) = heading_anchors.add(&text) {
+                            if space_config.disambiguate_headings {
+                                anchors.insert(collision.anchor.clone());
+                                disambiguated_headings.push((node, collision.anchor));
+                            } else {
+                                warnings.push(collision.warning());
+                            }
+                        } else {
+                            anchors.insert(heading_anchor(&text));
+                            if merging && heading.level == 2 {
+                                // merged files become `##` sections, which links to them need
+                                // anchors for
+                                disambiguated_headings.push((node, heading_anchor(&text)));
+                            }
+                        }
+                    }
+                }
+                NodeValue::Image(image) => {
+                    let mut alt = Vec::with_capacity(20);
                     for n in node.children() {
-                        collect_text(n, &mut text_content);
+                        collect_text(n, &mut alt);
+                    }
+                    if alt.iter().all(u8::is_ascii_whitespace) {
+                        warnings.push(Warning::new(
+                            WarningCode::MissingAltText,
+                            format!("image {} has no alt text", image.url),
+                        ));
+                    }
+                    if let Some(target) = image.url.strip_prefix(QR_CODE_SCHEME) {
+                        if target.contains("://") {
+                            attachments.push(ImageAttachment::qr_code(&image.url, target));
+                        } else if let Ok(local_link) =
+                            LocalLink::from_str(target, PathBuf::from(source).parent().unwrap())
+                        {
+                            attachments.push(ImageAttachment::qr_code(
+                                &image.url,
+                                &local_link.to_string(),
+                            ));
+                            local_links.push(local_link);
+                        } else {
+                            errors.push(Diagnostic::at(
+                                format!("Failed to parse QR code target: {}", target),
+                                sourcepos,
+                                content,
+                                body_line,
+                                "this image",
+                            ));
+                        }
+                    } else if MarkdownPage::is_local_link(&image.url) {
+                        let attachment = ImageAttachment::new(&image.url, parent)
+                            .named(space_config.attachment_names);
+                        if space_config.attachment_names == AttachmentNames::Content {
+                            // the renderer names the attachment after the url
+                            image.url = attachment.name.clone();
+                        }
+                        attachments.push(attachment);
+                    }
+                    if let Some(sibling) = node.next_sibling() {
+                        if let NodeValue::Text(ref mut text) = sibling.data.borrow_mut().value {
+                            if let Some((variants, length)) = parse_image_variants(text) {
+                                text.drain(..length);
+                                for key in variants.unknown_keys {
+                                    warnings.push(Warning::new(
+                                        WarningCode::UnknownImageVariant,
+                                        format!(
+                                            "unknown image variant \"{}\" for {}",
+                                            key, image.url
+                                        ),
+                                    ));
+                                }
+                                if let Some(mut dark) = variants.dark {
+                                    if MarkdownPage::is_local_link(&dark) {
+                                        let attachment = ImageAttachment::new(&dark, parent)
+                                            .named(space_config.attachment_names);
+                                        if space_config.attachment_names == AttachmentNames::Content
+                                        {
+                                            dark = attachment.name.clone();
+                                        }
+                                        attachments.push(attachment);
+                                    }
+                                    dark_variants.push((
+                                        node,
+                                        dark,
+                                        format!("{} (dark mode)", String::from_utf8_lossy(&alt)),
+                                    ));
+                                }
+                            }
+                        }
                     }
                 }
-            }
-            NodeValue::Image(image) => {
-                if MarkdownPage::is_local_link(&image.url) {
-                    attachments.push(ImageAttachment::new(&image.url, parent));
+                NodeValue::HtmlBlock(NodeHtmlBlock { literal, .. })
+                | NodeValue::HtmlInline(literal)
+                | NodeValue::Text(literal)
+                    if checked =>
+                {
+                    // macros and templates can show files next to the page too, storage format
+                    // tags end up as text as they aren't HTML
+                    for filename in attachment_filenames(literal) {
+                        if parent.join(filename).is_file() {
+                            attachments.push(ImageAttachment::new(filename, parent));
+                        }
+                    }
                 }
-            }
-            NodeValue::Link(node_link) => {
-                if !(node_link.url.starts_with("http://")
-                    || node_link.url.starts_with("https://")
-                    || node_link.url.starts_with("ac:"))
+                NodeValue::Link(node_link)
+                    if !(node_link.url.starts_with("http://")
+                        || node_link.url.starts_with("https://")
+                        || node_link.url.starts_with("mailto:")
+                        || node_link.url.starts_with("ac:")) =>
                 {
-                    if let Ok(local_link) = LocalLink::from_str(
-                        &node_link.url,
-                        PathBuf::from(source.as_str()).parent().unwrap(),
-                    ) {
+                    if let Ok(local_link) =
+                        LocalLink::from_str(&node_link.url, PathBuf::from(source).parent().unwrap())
+                    {
                         local_links.push(local_link);
                     } else {
-                        errors.push(format!("Failed to parse local link: {}", node_link.url));
+                        errors.push(Diagnostic::at(
+                            format!("Failed to parse local link: {}", node_link.url),
+                            sourcepos,
+                            content,
+                            body_line,
+                            "this link",
+                        ));
                     }
                 }
+                _ => (),
             }
-            _ => (),
         });
 
+        errors.extend(dedup_attachments(&mut attachments));
+
+        // the expand macro is a block, it goes after the paragraph of the image (in reverse, so
+        // the ones of the same paragraph keep their order)
+        for (image, dark, alt) in dark_variants.into_iter().rev() {
+            let markup = dark_variant_markup(&dark, &alt)?;
+            match image
+                .ancestors()
+                .find(|node| matches!(node.data.borrow().value, NodeValue::Paragraph))
+            {
+                Some(paragraph) => paragraph.insert_after(arena.alloc(AstNode::from(
+                    NodeValue::HtmlBlock(NodeHtmlBlock {
+                        block_type: 0,
+                        literal: markup,
+                    }),
+                ))),
+                None => {
+                    image.insert_after(arena.alloc(AstNode::from(NodeValue::HtmlInline(markup))))
+                }
+            }
+        }
+
+        errors.extend(apply_styles(
+            arena,
+            root,
+            fm.style.as_deref(),
+            &space_config.styles,
+            content,
+            body_line,
+        )?);
+        if let Some(edit_link) = &space_config.edit_link {
+            root.append(
+                arena.alloc(AstNode::from(NodeValue::HtmlBlock(NodeHtmlBlock {
+                    block_type: 0,
+                    literal: edit_link.markup(source)?,
+                }))),
+            );
+        }
+
+        for (heading, anchor) in disambiguated_headings {
+            heading.prepend(arena.alloc(AstNode::from(NodeValue::HtmlInline(
+                HeadingAnchors::anchor_macro(&anchor),
+            ))));
+        }
+
         let mut title = String::default();
+        let filename_title = TitlePolicy::title_from_filename(source);
 
         if let Some(heading_node) = first_heading {
             if let NodeValue::Heading(heading) = heading_node.data.borrow().value {
                 if heading.level != 1 {
-                    errors.push(format!(
-                        "first heading in file should be level 1, instead was level {}",
-                        heading.level
+                    errors.push(Diagnostic::at(
+                        format!(
+                            "first heading in file should be level 1, instead was level {}",
+                            heading.level
+                        ),
+                        heading_node.data.borrow().sourcepos,
+                        content,
+                        body_line,
+                        "this heading",
                     ));
                 }
             }
             let mut output = Vec::default();
             collect_text(heading_node, &mut output);
-            title = String::from_utf8(output)?;
+            let mut heading_text = String::from_utf8(output)?;
+            if space_config.emoji_title == EmojiTitle::Prefix {
+                if let Some(emoji) = find_emoji(&fm.emoji) {
+                    heading_text = format!("{} {}", emoji.as_str(), heading_text.trim());
+                }
+            }
+            if let Some(warning) = space_config
+                .titles
+                .warning(&heading_text)
+                .filter(|_| checked)
+            {
+                warnings.push(warning);
+            }
+            title = space_config.titles.normalize(&heading_text);
 
-            // TODO: it's still allocated tho...
             heading_node.detach();
+        } else if h1_title && !filename_title.is_empty() {
+            title = space_config.titles.normalize(&filename_title);
+            if !space_config.titles.from_filename {
+                warnings.push(Warning::new(
+                    WarningCode::LenientHeadings,
+                    format!(
+                        "no level 1 heading, the title is the file name \"{}\"",
+                        title
+                    ),
+                ));
+            }
         } else {
-            errors.push(String::from("missing first heading for title"));
+            errors.push(Diagnostic::new("missing first heading for title"));
+        }
+        if demoted_headings > 0 {
+            warnings.push(Warning::new(
+                WarningCode::LenientHeadings,
+                format!(
+                    "{} more level 1 heading(s) after the title, demoted to level 2",
+                    demoted_headings
+                ),
+            ));
+        }
+
+        let mut disabled_warnings = BTreeSet::default();
+        if checked {
+            warnings.extend(resolver.undefined(content).into_iter().map(|label| {
+                Warning::new(
+                    WarningCode::UndefinedReference,
+                    format!(
+                        "reference [{}] isn't defined in the page or the space",
+                        label
+                    ),
+                )
+            }));
+            warnings.extend(check_page_type(&space_config.types, fm));
+            warnings.extend(
+                space_config
+                    .lint
+                    .check(root, content, fm.page_type.as_deref()),
+            );
+
+            disabled_warnings = inline_disabled_warnings(root);
+            disabled_warnings.extend(space_config.disabled_warnings.iter().copied());
+            warnings.retain(|warning| !disabled_warnings.contains(&warning.code));
         }
 
         if errors.is_empty() {
-            Ok(MarkdownPage {
-                title,
-                source,
+            Ok(ParsedMarkdown {
                 root,
+                title,
                 attachments,
                 local_links,
                 warnings,
-                front_matter: fm,
+                disabled_warnings,
+                anchors,
+                references: resolver.used(),
             })
         } else {
-            Err(ConfluenceError::parsing_errors(source, errors))
+            Err(ConfluenceError::parsing_errors(source.to_string(), errors))
         }
     }
 
+    /// Runs `f` on the AST of the page, which is parsed again (without the checks of reading the
+    /// page) and dropped right after.
+    fn with_ast<R>(&self, f: impl for<'a> FnOnce(&'a AstNode<'a>) -> R) -> Result<R> {
+        let arena = Arena::new();
+        let parsed = Self::parse_ast(
+            &arena,
+            &self.source,
+            &self.path,
+            &self.markdown,
+            &self.front_matter,
+            &self.space_config,
+            ParseMode::Ast,
+        )?;
+        Ok(f(parsed.root))
+    }
+
     fn to_html_string(&self, link_generator: &LinkGenerator) -> Result<String> {
         let mut html = vec![];
-        render_confluence_storage(
-            self.root,
-            &Self::options(),
-            &mut html,
-            link_generator,
-            &PathBuf::from(self.source.clone()),
-        )
-        .unwrap();
+        self.with_ast(|root| {
+            render_confluence_storage(
+                root,
+                &Self::options(),
+                &mut html,
+                link_generator,
+                &PathBuf::from(self.source.clone()),
+            )
+        })?
+        .with_context(|| format!("Failed to render {}", self.source))?;
 
         match String::from_utf8(html) {
             Ok(content) => Ok(content),
@@ -220,12 +848,53 @@ impl<'a> MarkdownPage<'a> {
     }
 
     pub fn render(&self, link_generator: &LinkGenerator) -> Result<RenderedPage> {
-        let rendered_html = self.to_html_string(link_generator)?.clone();
-        let content = rendered_html;
+        let mut timings = self.timings;
+        let content = timed(&mut timings.render, || self.to_html_string(link_generator))?;
+        let missing: BTreeSet<&str> = attachment_filenames(&content)
+            .filter(|filename| {
+                !self
+                    .attachments
+                    .iter()
+                    .any(|attachment| attachment.name == *filename)
+            })
+            .collect();
+        if !missing.is_empty() {
+            return Err(ConfluenceError::generic_error(format!(
+                "{} shows attachments that aren't files next to it: {}",
+                self.source,
+                missing.into_iter().collect::<Vec<_>>().join(", ")
+            )));
+        }
+        self.rendered(link_generator, content, timings)
+    }
+
+    /// A page standing in for this one when it fails to render with `error`, see
+    /// `render_errors` in the space config.
+    pub fn render_placeholder(
+        &self,
+        link_generator: &LinkGenerator,
+        error: &anyhow::Error,
+        edit_link: Option<&EditLink>,
+    ) -> Result<RenderedPage> {
+        let content = placeholder_content(&self.source, error, edit_link)?;
+        self.rendered(link_generator, content, self.timings)
+    }
+
+    fn rendered(
+        &self,
+        link_generator: &LinkGenerator,
+        content: String,
+        mut timings: PhaseTimings,
+    ) -> Result<RenderedPage> {
         let title = self.title.clone();
         let page_path = PathBuf::from(self.source.clone());
-        let parent = get_parent_file(&page_path).and_then(|f| link_generator.get_file_id(&f));
-        let checksum = sha256_digest(content.as_bytes())?;
+        let parent = link_generator.get_parent_id(&page_path);
+        let (checksum, legacy_checksum) = timed(&mut timings.checksum, || {
+            Ok::<_, anyhow::Error>((
+                self.checksum.page_checksum(&content)?,
+                self.checksum.legacy_page_checksum(&content)?,
+            ))
+        })?;
 
         Ok(RenderedPage {
             title,
@@ -233,6 +902,8 @@ impl<'a> MarkdownPage<'a> {
             source: self.source.clone(),
             parent,
             checksum,
+            legacy_checksum,
+            timings,
         })
     }
 
@@ -240,6 +911,34 @@ impl<'a> MarkdownPage<'a> {
         self.front_matter.folder
     }
 
+    /// The headings and the text of the paragraphs and code blocks of the page.
+    pub fn text(&self) -> Result<PageText> {
+        self.with_ast(|root| {
+            let mut text = PageText::default();
+            for node in root.descendants() {
+                match &node.data.borrow().value {
+                    NodeValue::Heading(_) => {
+                        let mut heading = Vec::default();
+                        collect_text(node, &mut heading);
+                        text.headings
+                            .push(String::from_utf8_lossy(&heading).trim().to_string());
+                    }
+                    NodeValue::Paragraph => {
+                        let mut block = Vec::default();
+                        collect_text(node, &mut block);
+                        text.blocks
+                            .push(String::from_utf8_lossy(&block).into_owned());
+                    }
+                    NodeValue::CodeBlock(code_block) => {
+                        text.blocks.push(code_block.literal.clone());
+                    }
+                    _ => (),
+                }
+            }
+            text
+        })
+    }
+
     pub fn is_local_link(link: &str) -> bool {
         !link.starts_with("http")
     }
@@ -252,33 +951,45 @@ pub struct RenderedPage {
     pub source: String,
     pub parent: Option<String>,
     pub checksum: String,
+    /// The checksum older versions would have stored for this content, which still counts as up
+    /// to date.
+    pub legacy_checksum: Option<String>,
+    pub timings: PhaseTimings,
 }
 
 impl RenderedPage {
-    pub fn is_home_page(&self) -> bool {
-        self.source == "index.md"
-    }
-
-    pub fn version_message(&self) -> String {
+    fn version_message_with(&self, checksum: &str) -> String {
         format!(
             "{} source={}; checksum={}",
             ConfluencePageData::version_message_prefix(),
             self.source.replace('\\', "/"), // needs to be platform independent
-            self.checksum
+            checksum
         )
     }
+
+    pub fn version_message(&self) -> String {
+        self.version_message_with(&self.checksum)
+    }
+
+    /// Whether a version message records this content, by its current or legacy checksum.
+    pub fn is_version_message(&self, message: &str) -> bool {
+        message == self.version_message()
+            || self
+                .legacy_checksum
+                .as_ref()
+                .is_some_and(|checksum| message == self.version_message_with(checksum))
+    }
+
+    pub fn has_checksum(&self, checksum: &str) -> bool {
+        checksum == self.checksum || self.legacy_checksum.as_deref() == Some(checksum)
+    }
 }
 
 #[cfg(test)]
-pub fn page_from_str<'a>(
-    filename: &str,
-    content: &str,
-    arena: &'a Arena<AstNode<'a>>,
-) -> crate::error::Result<MarkdownPage<'a>> {
+pub fn page_from_str(filename: &str, content: &str) -> crate::error::Result<MarkdownPage> {
     MarkdownPage::from_str(
         &PathBuf::from(filename),
         content,
-        arena,
         filename.to_string(),
         &mut TemplateRenderer::default()?,
     )
@@ -290,19 +1001,19 @@ mod tests {
 
     use std::path::PathBuf;
 
-    use comrak::{nodes::AstNode, Arena};
-
     use crate::confluence_page::{ConfluenceNode, ConfluenceNodeType, ConfluencePageData};
     use crate::error::TestResult;
-    use crate::link_generator::LinkGenerator;
+    use crate::external_links::RewrittenLink;
+    use crate::link_definitions::LinkDefinitions;
+    use crate::link_generator::{LinkGenerator, LinkText};
     use crate::markdown_page::LocalLink;
+    use crate::page_emojis::parse_emoji;
     use crate::responses::{ContentStatus, Version};
 
     #[test]
     fn it_get_first_heading_as_title() -> TestResult {
-        let arena = Arena::<AstNode>::new();
         let markdown_content = &String::from("# My Page Title\n\nMy page content");
-        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let page = page_from_str("page.md", markdown_content)?;
 
         assert_eq!(page.title, "My Page Title");
 
@@ -311,9 +1022,8 @@ mod tests {
 
     #[test]
     fn it_removes_title_heading_and_renders_content() -> TestResult {
-        let arena = Arena::<AstNode>::new();
         let markdown_content = &String::from("# My Page Title\n\nMy page content");
-        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let page = page_from_str("page.md", markdown_content)?;
 
         let content = page.to_html_string(&LinkGenerator::default_test())?;
 
@@ -325,9 +1035,8 @@ mod tests {
 
     #[test]
     fn it_errors_if_no_heading() -> TestResult {
-        let arena = Arena::<AstNode>::new();
         let markdown_content = &String::from("My page content");
-        let page = page_from_str("page.md", markdown_content, &arena);
+        let page = page_from_str("page.md", markdown_content);
 
         assert!(page.is_err());
         assert_eq!(
@@ -340,28 +1049,91 @@ mod tests {
 
     #[test]
     fn it_fails_if_first_non_frontmatter_element_is_not_h1() -> TestResult {
-        let arena = Arena::<AstNode>::new();
-        let markdown_content = &String::from("## First Heading Needs to be H1");
-        let page = page_from_str("page.md", markdown_content, &arena);
+        let markdown_content =
+            &String::from("---\nlabels: [a]\n---\n## First Heading Needs to be H1");
+        let page = page_from_str("page.md", markdown_content);
 
         assert!(page.is_err());
         assert_eq!(
             page.err().unwrap().to_string(),
-            "Failed to parse page.md: first heading in file should be level 1, instead was level 2"
+            "Failed to parse page.md: first heading in file should be level 1, instead was level 2
+ --> page.md:4:1
+  |
+4 | ## First Heading Needs to be H1
+  | ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^ this heading"
         );
 
         Ok(())
     }
 
+    #[test]
+    fn it_takes_the_title_leniently() -> TestResult {
+        let space_config = SpaceConfig::from_yaml_str("titles:\n  lenient: true\n")?;
+
+        let page = crate::test_helpers::markdown_page_from_str_with_config(
+            "page.md",
+            "## Intro\n\n# Title\n\nText\n\n# Imported\n",
+            &space_config,
+        )?;
+        assert_eq!(page.title, "Title");
+        assert_eq!(
+            page.render(&LinkGenerator::default_test())?.content,
+            "<h2>Intro</h2>\n<p>Text</p>\n<h2>Imported</h2>\n"
+        );
+        assert_eq!(page.warnings[0].code, WarningCode::LenientHeadings);
+
+        let page = crate::test_helpers::markdown_page_from_str_with_config(
+            "runbooks/restore-db.md",
+            "## Steps\n",
+            &space_config,
+        )?;
+        assert_eq!(page.title, "Restore Db");
+        assert_eq!(page.warnings[0].code, WarningCode::LenientHeadings);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_takes_the_title_from_the_file_name() -> TestResult {
+        let space_config = SpaceConfig::from_yaml_str("titles:\n  from_filename: true\n")?;
+
+        let page = crate::test_helpers::markdown_page_from_str_with_config(
+            "releases/release_notes-2024.md",
+            "## 1.0.0\n\nFirst release\n",
+            &space_config,
+        )?;
+        assert_eq!(page.title, "Release Notes 2024");
+        assert!(page.warnings.is_empty());
+        assert_eq!(
+            page.render(&LinkGenerator::default_test())?.content,
+            "<h2>1.0.0</h2>\n<p>First release</p>\n"
+        );
+
+        let page = crate::test_helpers::markdown_page_from_str_with_config(
+            "runbooks/index.md",
+            "Text\n",
+            &space_config,
+        )?;
+        assert_eq!(page.title, "Runbooks");
+
+        let page = crate::test_helpers::markdown_page_from_str_with_config(
+            "changelog.md",
+            "# What Changed\n",
+            &space_config,
+        )?;
+        assert_eq!(page.title, "What Changed");
+
+        Ok(())
+    }
+
     #[test]
     fn it_parses_file_links_with_anchors() -> TestResult {
-        let arena = Arena::<AstNode>::new();
         let link_filename = PathBuf::from("some-page.md");
         let markdown_content = &format!(
             "# My Page Title\n\nMy page content: [link text]({}#some-anchor)",
             link_filename.display()
         );
-        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let page = page_from_str("page.md", markdown_content)?;
         assert_eq!(
             page.local_links,
             vec![LocalLink {
@@ -394,17 +1166,15 @@ mod tests {
         let link_filename = PathBuf::from("hello-world.md");
         let link_text = String::from("");
         let link_url = String::from("https://example.atlassian.net/wiki/spaces/TEST/pages/47");
-        let arena = Arena::<AstNode>::new();
         let markdown_content = &format!(
             "# My Page Title\n\nMy page content: [{}]({})",
             link_text,
             link_filename.display()
         );
-        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let page = page_from_str("page.md", markdown_content)?;
         let linked_page = page_from_str(
             link_filename.as_os_str().to_str().unwrap(),
             "# A Linked Page\n",
-            &arena,
         )?;
 
         let mut link_generator = LinkGenerator::default_test();
@@ -427,17 +1197,15 @@ mod tests {
         let link_filename = PathBuf::from("hello-world.md");
         let link_text = String::from("Link text");
         let link_url = String::from("https://example.atlassian.net/wiki/spaces/TEST/pages/47");
-        let arena = Arena::<AstNode>::new();
         let markdown_content = &format!(
             "# My Page Title\n\nMy page content: [{}](./hello-world.md)",
             link_text,
             // link_filename.display()
         );
-        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let page = page_from_str("page.md", markdown_content)?;
         let linked_page = page_from_str(
             link_filename.as_os_str().to_str().unwrap(),
             "# A Linked Page\n",
-            &arena,
         )?;
 
         let mut link_generator = LinkGenerator::default_test();
@@ -456,9 +1224,109 @@ mod tests {
     }
 
     #[test]
-    fn it_translates_relative_file_links() -> TestResult {
-        let arena = Arena::<AstNode>::new();
+    fn it_links_anchors_to_the_pages_of_sections() -> TestResult {
+        let page = page_from_str(
+            "page.md",
+            "# My Page Title\n\nSee [the data model](./api.md#data-model) and [errors](./api.md#errors)",
+        )?;
+        let api_page = page_from_str("api.md", "# API\n")?;
+        let section_page = page_from_str("api.md#data-model", "# API: Data Model\n")?;
+
+        let mut link_generator = LinkGenerator::default_test();
+        link_generator.register_markdown_page(&page)?;
+        link_generator.register_markdown_page(&api_page)?;
+        link_generator.register_markdown_page(&section_page)?;
+        link_generator.register_confluence_node(&dummy_confluence_page("API", "47"));
+        link_generator.register_confluence_node(&dummy_confluence_page("API: Data Model", "48"));
+
+        let content = page.to_html_string(&link_generator)?;
+        assert!(content.contains(
+            "<a href=\"https://example.atlassian.net/wiki/spaces/TEST/pages/48\">the data model</a>"
+        ));
+        assert!(content.contains(
+            "<a href=\"https://example.atlassian.net/wiki/spaces/TEST/pages/47#errors\">errors</a>"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_links_merged_files_to_their_section() -> TestResult {
+        let page = page_from_str("page.md", "# My Page Title\n\nSee [install](./install.md)")?;
+        let mut guide_page = page_from_str(
+            "guide.md",
+            "---\nmerge: [install.md]\n---\n# Guide\n\n## Install\n",
+        )?;
+        guide_page.merged = vec![MergedFile {
+            source: String::from("install.md"),
+            anchor: String::from("install"),
+        }];
+
+        let mut link_generator = LinkGenerator::default_test();
+        link_generator.register_markdown_page(&page)?;
+        link_generator.register_markdown_page(&guide_page)?;
+        link_generator.register_confluence_node(&dummy_confluence_page("Guide", "47"));
+
+        assert!(page.to_html_string(&link_generator)?.contains(
+            "<a href=\"https://example.atlassian.net/wiki/spaces/TEST/pages/47#install\">install</a>"
+        ));
+        assert!(guide_page
+            .to_html_string(&link_generator)?
+            .contains(&HeadingAnchors::anchor_macro("install")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_replaces_link_text_with_the_page_title() -> TestResult {
+        let page = page_from_str(
+            "page.md",
+            "# My Page Title\n\nSee [the *old* title](hello-world.md) and [](hello-world.md#usage)",
+        )?;
+        let linked_page = page_from_str("hello-world.md", "# Fish & Chips\n")?;
+
+        let mut link_generator = LinkGenerator::default_test();
+        link_generator.set_link_text(LinkText::Title);
+        link_generator.register_markdown_page(&page)?;
+        link_generator.register_markdown_page(&linked_page)?;
+        link_generator.register_confluence_node(&dummy_confluence_page("Fish & Chips", "47"));
+
+        let content = page.to_html_string(&link_generator)?;
+
+        assert_eq!(
+            content,
+            "<p>See <a href=\"https://example.atlassian.net/wiki/spaces/TEST/pages/47\">Fish &amp; Chips</a> and <a href=\"https://example.atlassian.net/wiki/spaces/TEST/pages/47#usage\">Fish &amp; Chips</a></p>\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_adds_the_page_title_as_a_tooltip() -> TestResult {
+        let page = page_from_str(
+            "page.md",
+            "# My Page Title\n\nSee [this page](hello-world.md)",
+        )?;
+        let linked_page = page_from_str("hello-world.md", "# A Linked Page\n")?;
 
+        let mut link_generator = LinkGenerator::default_test();
+        link_generator.set_link_text(LinkText::Tooltip);
+        link_generator.register_markdown_page(&page)?;
+        link_generator.register_markdown_page(&linked_page)?;
+        link_generator.register_confluence_node(&dummy_confluence_page("A Linked Page", "47"));
+
+        let content = page.to_html_string(&link_generator)?;
+
+        assert_eq!(
+            content,
+            "<p>See <a href=\"https://example.atlassian.net/wiki/spaces/TEST/pages/47\" title=\"A Linked Page\">this page</a></p>\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_translates_relative_file_links() -> TestResult {
         let link_filename = PathBuf::from("hello-world.md");
         let link_text = String::from("Link text");
         let link_url = String::from("https://example.atlassian.net/wiki/spaces/TEST/pages/47");
@@ -467,11 +1335,10 @@ mod tests {
             // relative paths
             link_text,
         );
-        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let page = page_from_str("page.md", markdown_content)?;
         let linked_page = page_from_str(
             link_filename.as_os_str().to_str().unwrap(),
             "# A Linked Page\n",
-            &arena,
         )?;
 
         let mut link_generator = LinkGenerator::default_test();
@@ -492,8 +1359,7 @@ mod tests {
     #[test]
     fn it_renders_local_file_as_attached_image() -> TestResult {
         let content = "# My Page Title\n\nMy page content: ![myimage](myimage.png)";
-        let arena = Arena::<AstNode>::new();
-        let page = page_from_str("page.md", content, &arena)?;
+        let page = page_from_str("page.md", content)?;
 
         assert_eq!(page.attachments.len(), 1);
 
@@ -514,8 +1380,7 @@ mod tests {
             "# My Page Title\n\nMy page content: ![myimage]({})",
             image_url
         );
-        let arena = Arena::<AstNode>::new();
-        let page = page_from_str("page.md", markdown_content.as_str(), &arena)?;
+        let page = page_from_str("page.md", markdown_content.as_str())?;
 
         assert_eq!(page.attachments.len(), 0); // should not view the external link as an attachment
         let html_content = page.to_html_string(&LinkGenerator::default_test())?;
@@ -524,7 +1389,7 @@ mod tests {
 
         assert!(html_content.contains(
             format!(
-                r#"<ac:image ac:align="center"><ri:url ri:value="{}"/>myimage</ac:image>"#,
+                r#"<ac:image ac:align="center" ac:alt="myimage"><ri:url ri:value="{}"/></ac:image>"#,
                 image_url
             )
             .as_str()
@@ -540,8 +1405,7 @@ mod tests {
             "# My Page Title\n\nExternal Link: [example]({})",
             external_url
         );
-        let arena = Arena::<AstNode>::new();
-        let page = page_from_str("page.md", markdown_content.as_str(), &arena)?;
+        let page = page_from_str("page.md", markdown_content.as_str())?;
         let html_content = page.to_html_string(&LinkGenerator::default_test())?;
 
         println!("Got content: {:#}", html_content);
@@ -555,9 +1419,8 @@ mod tests {
 
     #[test]
     fn it_renders_templates() -> TestResult {
-        let arena = Arena::<AstNode>::new();
         let markdown_content = "# compulsory title\n{{filename}}";
-        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let page = page_from_str("page.md", markdown_content)?;
 
         let rendered_page = page.render(&LinkGenerator::default_test())?;
 
@@ -568,7 +1431,6 @@ mod tests {
 
     #[test]
     fn it_parses_yaml_frontmatter() -> TestResult {
-        let arena = Arena::<AstNode>::new();
         let markdown_content = r##"---
 labels: 
 - foo
@@ -577,7 +1439,7 @@ labels:
 # compulsory title
 "##;
 
-        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let page = page_from_str("page.md", markdown_content)?;
 
         let expected_labels = vec!["foo", "bar"];
 
@@ -588,7 +1450,6 @@ labels:
 
     #[test]
     fn it_warns_about_unknown_keys() -> TestResult {
-        let arena = Arena::<AstNode>::new();
         let markdown_content = r##"---
 unknown_top_level_key: "foo"
 page_emoji: "and typos"
@@ -596,11 +1457,267 @@ page_emoji: "and typos"
 # compulsory title
 "##;
 
-        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let page = page_from_str("page.md", markdown_content)?;
+
+        assert_eq!(
+            page.warnings,
+            vec![Warning::new(
+                WarningCode::UnknownFrontMatterKey,
+                "Unknown top level front matter keys: page_emoji, unknown_top_level_key"
+            )]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_warns_about_repeated_headings() -> TestResult {
+        let markdown_content = "# Title\n## Overview\n## Details\n### Overview\n";
+
+        let page = page_from_str("page.md", markdown_content)?;
 
         assert_eq!(
             page.warnings,
-            vec!["Unknown top level front matter keys: page_emoji, unknown_top_level_key"]
+            vec![Warning::new(
+                WarningCode::DuplicateHeading,
+                "duplicate heading \"Overview\": links to #overview will only reach the first one"
+            )]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_warns_about_images_without_alt_text() -> TestResult {
+        let markdown_content =
+            "# Title\n![](diagram.png)\n![ ](photo.png)\n![A photo](other.png)\n";
+
+        let page = page_from_str("page.md", markdown_content)?;
+
+        assert_eq!(
+            page.warnings,
+            vec![
+                Warning::new(
+                    WarningCode::MissingAltText,
+                    "image diagram.png has no alt text"
+                ),
+                Warning::new(
+                    WarningCode::MissingAltText,
+                    "image photo.png has no alt text"
+                )
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_applies_lint_rules() -> TestResult {
+        let space_config = SpaceConfig::from_yaml_str(
+            "lint:\n  no_skipped_heading_levels: true\n  required_sections:\n    runbook: [Rollback]\n",
+        )?;
+        let (fm, content) =
+            FrontMatter::from_str("---\ntype: runbook\n---\n# Restart the database\n### Steps\n")?;
+
+        let page = MarkdownPage::parse_markdown(
+            String::from("page.md"),
+            &PathBuf::from("page.md"),
+            content,
+            fm,
+            &Arc::new(space_config),
+        )?;
+
+        assert_eq!(
+            page.warnings
+                .iter()
+                .map(Warning::to_string)
+                .collect::<Vec<String>>(),
+            vec![
+                "heading \"Steps\" skips from level 1 to level 3 [MS010]",
+                "runbook page is missing required section \"Rollback\" [MS011]"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_suppresses_disabled_warnings() -> TestResult {
+        let space_config = SpaceConfig::from_yaml_str(
+            "lint:\n  no_skipped_heading_levels: true\nwarnings:\n  disable: [MS010]\n",
+        )?;
+        let (fm, content) = FrontMatter::from_str(
+            "---\nowner: me\n---\n# Title\n<!-- marked-space: disable MS003 -->\n### Steps\n![](diagram.png)\n",
+        )?;
+
+        let page = MarkdownPage::parse_markdown(
+            String::from("page.md"),
+            &PathBuf::from("page.md"),
+            content,
+            fm,
+            &Arc::new(space_config),
+        )?;
+
+        assert_eq!(
+            page.warnings
+                .iter()
+                .map(|warning| warning.code)
+                .collect::<Vec<WarningCode>>(),
+            vec![WarningCode::UnknownFrontMatterKey]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_prefixes_the_title_instead_of_setting_the_property() -> TestResult {
+        let space_config = SpaceConfig::from_yaml_str("emoji_title: prefix")?;
+        let (fm, content) = FrontMatter::from_str("---\nemoji: fire\n---\n# Incidents\n")?;
+
+        let page = MarkdownPage::parse_markdown(
+            String::from("incidents.md"),
+            &PathBuf::from("incidents.md"),
+            content,
+            fm,
+            &Arc::new(space_config),
+        )?;
+
+        assert_eq!(page.title, "\u{1F525} Incidents");
+        assert_eq!(parse_emoji(&page), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_adds_anchors_to_repeated_headings() -> TestResult {
+        let space_config = SpaceConfig {
+            disambiguate_headings: true,
+            ..SpaceConfig::default()
+        };
+
+        let page = MarkdownPage::parse_markdown(
+            String::from("page.md"),
+            &PathBuf::from("page.md"),
+            String::from("# Title\n## Overview\n## Overview\n"),
+            FrontMatter::default(),
+            &Arc::new(space_config),
+        )?;
+        let rendered_page = page.render(&LinkGenerator::default_test())?;
+
+        assert!(page.warnings.is_empty());
+        assert_eq!(
+            rendered_page.content,
+            "<h2>Overview</h2>\n<h2><ac:structured-macro ac:name=\"anchor\" ac:schema-version=\"1\"><ac:parameter ac:name=\"\">overview-1</ac:parameter></ac:structured-macro>Overview</h2>\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_renders_and_reads_the_text_of_the_ast_of_a_full_parse() -> TestResult {
+        let space_config = Arc::new(SpaceConfig {
+            disambiguate_headings: true,
+            ..SpaceConfig::default()
+        });
+        let markdown = "# Title\n\n## Overview\n\n![](diagram.png){dark=diagram-dark.png}\n\n## Overview\n\n[undefined]\n";
+        let page = MarkdownPage::parse_markdown(
+            String::from("page.md"),
+            &PathBuf::from("page.md"),
+            String::from(markdown),
+            FrontMatter::default(),
+            &space_config,
+        )?;
+        let link_generator = LinkGenerator::default_test();
+
+        let arena = Arena::new();
+        let parsed = MarkdownPage::parse_ast(
+            &arena,
+            "page.md",
+            &PathBuf::from("page.md"),
+            markdown,
+            &FrontMatter::default(),
+            &space_config,
+            ParseMode::Page,
+        )?;
+        let mut html = vec![];
+        render_confluence_storage(
+            parsed.root,
+            &MarkdownPage::options(),
+            &mut html,
+            &link_generator,
+            &PathBuf::from("page.md"),
+        )?;
+
+        assert!(!page.warnings.is_empty());
+        assert_eq!(
+            page.render(&link_generator)?.content,
+            String::from_utf8(html)?
+        );
+        assert_eq!(page.text()?.headings, vec!["Overview", "Overview"]);
+        assert_eq!(page.text()?.blocks, vec!["", "[undefined]"]);
+        Ok(())
+    }
+
+    #[test]
+    fn it_autolinks_and_tidies_up_external_links() -> TestResult {
+        let page = page_from_str(
+            "page.md",
+            "# Title\n\nSee https://blog.example.org/post?id=7&utm_source=slack or mail team@example.com\n",
+        )?;
+        let mut link_generator = LinkGenerator::default_test();
+        link_generator.set_external_links(
+            &SpaceConfig::from_yaml_str("external_links:\n  strip_parameters: [utm_*]")?
+                .external_links,
+        );
+
+        let rendered_page = page.render(&link_generator)?;
+
+        assert!(page.local_links.is_empty());
+        assert_eq!(
+            rendered_page.content,
+            "<p>See <a href=\"https://blog.example.org/post?id=7\">https://blog.example.org/post?id=7&utm_source=slack</a> or mail <a href=\"mailto:team@example.com\">team@example.com</a></p>\n"
+        );
+        assert_eq!(
+            link_generator.rewritten_links(),
+            vec![RewrittenLink {
+                source: String::from("page.md"),
+                from: String::from("https://blog.example.org/post?id=7&utm_source=slack"),
+                to: String::from("https://blog.example.org/post?id=7"),
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_resolves_shared_link_definitions() -> TestResult {
+        let space_config = SpaceConfig {
+            link_definitions: Arc::new(LinkDefinitions::parse(
+                "[cc-rest]: https://developer.atlassian.com/cloud/confluence/rest/v2/\n[unused]: https://example.com\n",
+            )),
+            ..SpaceConfig::default()
+        };
+
+        let page = MarkdownPage::parse_markdown(
+            String::from("page.md"),
+            &PathBuf::from("page.md"),
+            String::from("# Title\n\nSee the [Confluence REST API][CC-REST], [this][local] and [that][typo].\n\n[local]: https://example.com/local\n"),
+            FrontMatter::default(),
+            &Arc::new(space_config),
+        )?;
+        let rendered_page = page.render(&LinkGenerator::default_test())?;
+
+        assert_eq!(
+            rendered_page.content,
+            "<p>See the <a href=\"https://developer.atlassian.com/cloud/confluence/rest/v2/\">Confluence REST API</a>, <a href=\"https://example.com/local\">this</a> and [that][typo].</p>\n"
+        );
+        assert_eq!(page.references, BTreeSet::from([String::from("cc-rest")]));
+        assert_eq!(
+            page.warnings,
+            vec![Warning::new(
+                WarningCode::UndefinedReference,
+                "reference [typo] isn't defined in the page or the space"
+            )]
         );
 
         Ok(())
@@ -608,7 +1725,6 @@ page_emoji: "and typos"
 
     #[test]
     fn it_fails_if_front_matter_is_invalid_yaml() -> TestResult {
-        let arena = Arena::<AstNode>::new();
         let markdown_content = r##"---
 [some section]
 foo=bar
@@ -616,7 +1732,7 @@ foo=bar
 # compulsory title
 "##;
 
-        let result = page_from_str("page.md", markdown_content, &arena);
+        let result = page_from_str("page.md", markdown_content);
 
         assert!(result.is_err());
 
@@ -633,7 +1749,6 @@ foo=bar
 
     #[test]
     fn it_allows_metadata() -> TestResult {
-        let arena = Arena::<AstNode>::new();
         let markdown_content = r##"---
 metadata:
     some:
@@ -642,7 +1757,7 @@ metadata:
 # compulsory title
 "##;
 
-        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let page = page_from_str("page.md", markdown_content)?;
 
         assert_eq!(
             page.front_matter.metadata["some"]["arbitrary"].as_str(),
@@ -654,7 +1769,6 @@ metadata:
 
     #[test]
     fn it_has_metadata_as_variables() -> TestResult {
-        let arena = Arena::<AstNode>::new();
         let markdown_content = r##"---
 metadata:
     some:
@@ -663,7 +1777,7 @@ metadata:
 # compulsory title
 {{ metadata(path="some.arbitrary") }}
 "##;
-        let page = page_from_str("page.md", markdown_content, &arena)?;
+        let page = page_from_str("page.md", markdown_content)?;
 
         let rendered_page = page.render(&LinkGenerator::default_test())?;
 
@@ -1,51 +1,153 @@
 use clap::builder::OsStr;
-use comrak::{nodes::AstNode, Arena};
 use regex::Regex;
 use walkdir::WalkDir;
 
 use crate::{
+    anchor_links::find_broken_anchors,
+    confluence_templates::CONFLUENCE_TEMPLATES_DIR,
     console::{print_info, print_warning},
+    directory_defaults::DirectoryDefaults,
+    duplicates::find_near_duplicates,
     error::{ConfluenceError, Result},
-    markdown_page::MarkdownPage,
+    frontmatter::FrontMatter,
+    link_definitions::LinkDefinitions,
+    markdown_page::{remove_prefix, MarkdownPage},
+    page_merge::merged_source,
+    parallel::map_with,
+    parent::flattened_parent_files,
+    parse_cache::ParseCache,
+    space_config::SpaceConfig,
     template_renderer::TemplateRenderer,
+    warnings::{report, Warning, WarningCode},
 };
 use std::{
-    collections::HashSet,
+    collections::{BTreeSet, HashMap, HashSet},
+    fs::File,
+    io,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
-fn is_valid_space_key(space_key: &str) -> bool {
+/// Prefixes the titles of pages that are put higher up the tree than their file (see
+/// `max_depth` in the space config) with the titles of the pages they skip, so the title still
+/// says where the page belongs, e.g. `Databases / Postgres / Restore`.
+fn prefix_flattened_titles(markdown_pages: &mut [Result<MarkdownPage>], max_depth: usize) {
+    let titles: HashMap<String, String> = markdown_pages
+        .iter()
+        .flatten()
+        .map(|page| (page.source.replace('\\', "/"), page.title.clone()))
+        .collect();
+    for markdown_page in markdown_pages.iter_mut().flatten() {
+        let skipped = flattened_parent_files(Path::new(&markdown_page.source), max_depth);
+        if skipped.is_empty() {
+            continue;
+        }
+        let mut parts: Vec<String> = skipped
+            .iter()
+            .rev()
+            .map(|parent| {
+                let parent = parent.to_string_lossy().replace('\\', "/");
+                titles.get(&parent).cloned().unwrap_or_else(|| {
+                    // a directory without an index.md
+                    Path::new(&parent)
+                        .parent()
+                        .and_then(Path::file_name)
+                        .map(|name| name.to_string_lossy().to_string())
+                        .unwrap_or(parent)
+                })
+            })
+            .collect();
+        parts.push(markdown_page.title.clone());
+        markdown_page.title = parts.join(" / ");
+    }
+}
+
+pub(crate) fn is_valid_space_key(space_key: &str) -> bool {
     Regex::new("^[A-Za-z0-9]+$").unwrap().is_match(space_key)
 }
 
-pub struct MarkdownSpace<'a> {
+/// The markdown files that aren't pages themselves: the files merged into another page, and
+/// snippets. Files with broken front matter are skipped here, parsing them reports the error.
+fn non_page_files(dir: &Path, markdown_pages: &[PathBuf]) -> (HashSet<PathBuf>, Vec<PathBuf>) {
+    let mut merged = HashSet::default();
+    let mut snippets = Vec::default();
+    for path in markdown_pages {
+        let Some(fm) = File::open(path)
+            .ok()
+            .and_then(|file| FrontMatter::from_reader(&mut io::BufReader::new(file)).ok())
+            .map(|(fm, _content)| fm)
+        else {
+            continue;
+        };
+        if fm.snippet {
+            snippets.push(path.clone());
+        }
+        if let Ok(source) = remove_prefix(dir, path) {
+            merged.extend(
+                fm.merge
+                    .iter()
+                    .map(|file| dir.join(merged_source(&source, file))),
+            );
+        }
+    }
+    (merged, snippets)
+}
+
+pub struct MarkdownSpace {
     pub key: String,
-    pub arena: Arena<AstNode<'a>>,
     pub markdown_pages: Vec<PathBuf>,
+    /// Markdown files in `_confluence_templates`, published as space templates instead of pages.
+    pub confluence_templates: Vec<PathBuf>,
+    /// Markdown files with `snippet: true`, included into pages instead of published.
+    pub snippets: Vec<PathBuf>,
     pub dir: PathBuf,
+    pub config: SpaceConfig,
+    pub defaults: DirectoryDefaults,
+    /// Warnings about the space rather than one of its pages, found while reading it.
+    pub warnings: Vec<Warning>,
 }
 
-impl<'a> MarkdownSpace<'a> {
+/// Fails with every error of the files that didn't parse, if there are any.
+pub(crate) fn check_parse_errors(parse_errors: Vec<anyhow::Error>) -> Result<()> {
+    if parse_errors.is_empty() {
+        return Ok(());
+    }
+    let error_string: String = parse_errors
+        .iter()
+        .map(|e| format!("{:#}", e))
+        .collect::<Vec<String>>()
+        .join("\n  ");
+    Err(ConfluenceError::generic_error(format!(
+        "{} Error(s) parsing space:\n  {}",
+        parse_errors.len(),
+        &error_string
+    )))
+}
+
+impl MarkdownSpace {
     #[cfg(test)]
     pub fn default(key: &str, dir: &Path) -> Self {
         MarkdownSpace {
             markdown_pages: Vec::default(),
+            confluence_templates: Vec::default(),
+            snippets: Vec::default(),
             key: String::from(key),
             dir: PathBuf::from(dir),
-            arena: Arena::new(),
+            config: SpaceConfig::default(),
+            defaults: DirectoryDefaults::default(),
+            warnings: Vec::default(),
         }
     }
 
     #[cfg(test)]
     pub fn page_from_str(
-        &'a self,
+        &self,
         filename: &str,
         content: &str,
-    ) -> crate::error::Result<MarkdownPage<'a>> {
+    ) -> crate::error::Result<MarkdownPage> {
         MarkdownPage::from_str(
             &PathBuf::from(filename),
             content,
-            &self.arena,
             filename.to_string(),
             &mut TemplateRenderer::default()?,
         )
@@ -65,29 +167,74 @@ impl<'a> MarkdownSpace<'a> {
             dir.display()
         ));
         let mut markdown_pages = Vec::<PathBuf>::default();
-        for entry in WalkDir::new(dir) {
+        let mut confluence_templates = Vec::<PathBuf>::default();
+        let mut defaults = DirectoryDefaults::default();
+        let mut missing_index = Vec::<PathBuf>::default();
+        let link_definitions_file = LinkDefinitions::file(dir)?;
+        // sorted, so pages are synced and reported in the same order on every machine
+        for entry in WalkDir::new(dir).sort_by_file_name() {
             let entry = entry?;
             if entry.path().starts_with(dir.join("_tera")) {
                 continue;
             }
+            if entry.path().starts_with(dir.join(CONFLUENCE_TEMPLATES_DIR)) {
+                if entry.path().extension() == Some(&OsStr::from("md")) {
+                    confluence_templates.push(entry.into_path());
+                }
+                continue;
+            }
             if entry.path().is_dir() {
                 if !entry.path().join("index.md").exists() {
-                    print_warning(&format!(
-                        "directory {} is missing index.md",
-                        entry.path().display()
-                    ));
+                    missing_index.push(entry.into_path());
                 }
+            } else if Some(entry.path()) == link_definitions_file.as_deref() {
+                continue;
+            } else if DirectoryDefaults::is_defaults_file(entry.path()) {
+                defaults.load(entry.path())?;
             } else if entry.path().extension() == Some(&OsStr::from("md")) {
                 markdown_pages.push(entry.into_path());
             }
         }
+        let (merged, snippets) = non_page_files(dir, &markdown_pages);
+        markdown_pages.retain(|path| !merged.contains(path) && !snippets.contains(path));
         let key = String::from(dir.file_stem().unwrap().to_str().unwrap());
         if dir.exists() {
+            let mut config = SpaceConfig::from_directory(dir)?;
+            config.link_definitions = Arc::new(LinkDefinitions::from_directory(dir)?);
+            let mut warnings = Vec::default();
+            // the directories are found before the config that can disable the warning is read
+            for directory in missing_index {
+                report(
+                    Warning::new(
+                        WarningCode::MissingIndex,
+                        format!("directory {} is missing index.md", directory.display()),
+                    ),
+                    &config.disabled_warnings,
+                    &mut warnings,
+                );
+            }
+            if !config.unknown_keys.is_empty() {
+                report(
+                    Warning::new(
+                        WarningCode::UnknownSpaceConfigKey,
+                        format!(
+                            "Unknown top level space config keys: {}",
+                            config.unknown_keys.join(", ")
+                        ),
+                    ),
+                    &config.disabled_warnings,
+                    &mut warnings,
+                );
+            }
             Ok(MarkdownSpace {
                 markdown_pages,
+                confluence_templates,
+                snippets,
                 key,
                 dir: PathBuf::from(dir),
-                arena: Arena::new(),
+                config,
+                defaults,
+                warnings,
             })
         } else {
             Err(crate::error::ConfluenceError::generic_error(
@@ -114,24 +261,75 @@ impl<'a> MarkdownSpace<'a> {
     }
 
     pub(crate) fn parse(
-        &'a mut self,
-        template_renderer: &mut TemplateRenderer,
-    ) -> Result<Vec<MarkdownPage<'a>>> {
+        &self,
+        template_renderer: &TemplateRenderer,
+        parse_cache: Option<&ParseCache>,
+    ) -> Result<Vec<MarkdownPage>> {
         let mut parse_errors = Vec::<anyhow::Error>::default();
+        let markdown_pages =
+            self.parse_collecting(template_renderer, parse_cache, &mut parse_errors)?;
+        check_parse_errors(parse_errors)?;
+        Ok(markdown_pages)
+    }
+
+    /// Like [`MarkdownSpace::parse`], but the files that fail to parse (or link to files that
+    /// don't exist) are left out and their errors added to `parse_errors`, so every error of the
+    /// space can be reported at once.
+    pub(crate) fn parse_collecting(
+        &self,
+        template_renderer: &TemplateRenderer,
+        parse_cache: Option<&ParseCache>,
+        parse_errors: &mut Vec<anyhow::Error>,
+    ) -> Result<Vec<MarkdownPage>> {
+        let space_config = Arc::new(self.config.clone());
         let mut titles: HashSet<String> = HashSet::default();
-        let markdown_pages: Vec<MarkdownPage> = self
-            .markdown_pages
-            .iter()
-            .map(|markdown_page_path| {
-                let markdown_page = MarkdownPage::from_file(
+        // templating and parsing the files is independent, checking the pages against each other
+        // happens afterwards, in the order of the files
+        let parsed_files = map_with(
+            &self.markdown_pages,
+            || template_renderer.clone(),
+            |template_renderer, markdown_page_path| match parse_cache
+                .and_then(|cache| cache.lookup(markdown_page_path))
+            {
+                Some(cached_pages) => MarkdownPage::from_cached(
+                    &self.dir,
+                    markdown_page_path,
+                    cached_pages,
+                    &space_config,
+                    &self.defaults,
+                ),
+                None => MarkdownPage::from_file_split(
                     &self.dir,
                     markdown_page_path,
-                    &self.arena,
                     template_renderer,
-                )?;
+                    &space_config,
+                    &self.defaults,
+                )
+                .and_then(|markdown_pages| {
+                    if let Some(cache) = parse_cache {
+                        cache.store(markdown_page_path, &markdown_pages)?;
+                    }
+                    Ok(markdown_pages)
+                }),
+            },
+        );
+        let mut parsed_pages: Vec<Result<MarkdownPage>> = parsed_files
+            .into_iter()
+            .flat_map(|parsed| match parsed {
+                Ok(markdown_pages) => markdown_pages.into_iter().map(Ok).collect(),
+                Err(err) => vec![Err(err)],
+            })
+            .collect();
+        if let Some(max_depth) = self.config.max_depth {
+            prefix_flattened_titles(&mut parsed_pages, max_depth);
+        }
+        let markdown_pages: Vec<MarkdownPage> = parsed_pages
+            .into_iter()
+            .map(|markdown_page: Result<MarkdownPage>| {
+                let markdown_page = markdown_page?;
 
                 for warning in markdown_page.warnings.iter() {
-                    print_warning(warning);
+                    print_warning(&warning.to_string());
                 }
                 let title = markdown_page.title.to_owned();
                 let filename = markdown_page.source.replace('\\', "/");
@@ -173,7 +371,7 @@ impl<'a> MarkdownSpace<'a> {
                     .attachments
                     .iter()
                     .filter_map(|attachment| {
-                        if !attachment.path.exists() {
+                        if attachment.qr_target.is_none() && !attachment.path.exists() {
                             Some(self.space_relative_path_string(&attachment.path).unwrap())
                         } else {
                             None
@@ -194,21 +392,113 @@ impl<'a> MarkdownSpace<'a> {
             .filter_map(|r| r.map_err(|e| parse_errors.push(e)).ok())
             .collect();
 
-        if !parse_errors.is_empty() {
-            let error_string: String = parse_errors
+        let mut markdown_pages = markdown_pages;
+        if let Some(policy) = &self.config.lint.near_duplicates {
+            let text_blocks: Vec<Vec<String>> = markdown_pages
                 .iter()
-                .map(|e| format!("{:#}", e))
-                .collect::<Vec<String>>()
-                .join("\n  ");
-            return Err(ConfluenceError::generic_error(format!(
-                "{} Error(s) parsing space:\n  {}",
-                parse_errors.len(),
-                &error_string
-            )));
+                .map(|markdown_page| Ok(markdown_page.text()?.blocks))
+                .collect::<Result<_>>()?;
+            for duplicate in find_near_duplicates(&text_blocks, policy) {
+                let warning = Warning::new(
+                    WarningCode::NearDuplicate,
+                    format!(
+                        "\"{}...\" in {} is {}% similar to a block in {}, consider an excerpt or template",
+                        duplicate.excerpt,
+                        markdown_pages[duplicate.page].source,
+                        duplicate.similarity,
+                        markdown_pages[duplicate.other_page].source
+                    ),
+                );
+                let page = &mut markdown_pages[duplicate.page];
+                if !page.disabled_warnings.contains(&warning.code) {
+                    print_warning(&warning.to_string());
+                    page.warnings.push(warning);
+                }
+            }
+        }
+
+        for broken_anchor in find_broken_anchors(&markdown_pages) {
+            let warning = Warning::new(
+                WarningCode::BrokenAnchor,
+                match &broken_anchor.suggestion {
+                    Some(suggestion) => format!(
+                        "link {} in {} goes to no heading, did you mean #{}?",
+                        broken_anchor.link, markdown_pages[broken_anchor.page].source, suggestion
+                    ),
+                    None => format!(
+                        "link {} in {} goes to no heading",
+                        broken_anchor.link, markdown_pages[broken_anchor.page].source
+                    ),
+                },
+            );
+            let page = &mut markdown_pages[broken_anchor.page];
+            if !page.disabled_warnings.contains(&warning.code) {
+                print_warning(&warning.to_string());
+                page.warnings.push(warning);
+            }
+        }
+
+        let used: BTreeSet<String> = markdown_pages
+            .iter()
+            .flat_map(|markdown_page| markdown_page.references.iter().cloned())
+            .collect();
+        if !self
+            .config
+            .disabled_warnings
+            .contains(&WarningCode::UnusedReference)
+        {
+            for definition in self.config.link_definitions.unused(&used) {
+                print_warning(
+                    &Warning::new(
+                        WarningCode::UnusedReference,
+                        format!(
+                            "link definition [{}] in {} isn't used by any page",
+                            definition.label,
+                            self.config
+                                .link_definitions
+                                .source
+                                .as_deref()
+                                .unwrap_or_default()
+                        ),
+                    )
+                    .to_string(),
+                );
+            }
         }
 
         Ok(markdown_pages)
     }
+
+    /// Parses the markdown files in `_confluence_templates`, which are rendered like pages but
+    /// can't have attachments.
+    pub(crate) fn parse_confluence_templates(
+        &self,
+        template_renderer: &mut TemplateRenderer,
+    ) -> Result<Vec<MarkdownPage>> {
+        let space_config = Arc::new(self.config.clone());
+        self.confluence_templates
+            .iter()
+            .map(|path| {
+                let markdown_page = MarkdownPage::from_file(
+                    &self.dir,
+                    path,
+                    template_renderer,
+                    &space_config,
+                    &self.defaults,
+                )?;
+                for warning in markdown_page.warnings.iter() {
+                    print_warning(&warning.to_string());
+                }
+                if !markdown_page.attachments.is_empty() {
+                    return Err(ConfluenceError::generic_error(format!(
+                        "{} links to images, which Confluence templates can't have",
+                        markdown_page.source
+                    )));
+                }
+                Ok(markdown_page)
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -246,6 +536,191 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn it_lists_markdown_files_in_a_stable_order() -> Result {
+        let temp = assert_fs::TempDir::new().unwrap();
+        for file in [
+            "test/zebra.md",
+            "test/guides/index.md",
+            "test/index.md",
+            "test/api.md",
+        ] {
+            temp.child(file).write_str("# Title\n")?;
+        }
+        let space = MarkdownSpace::from_directory(temp.child("test").path())?;
+
+        let files: Vec<String> = space
+            .markdown_pages
+            .iter()
+            .map(|path| space.space_relative_path_string(path))
+            .collect::<std::result::Result<_, _>>()?;
+        assert_eq!(
+            files,
+            vec!["api.md", "guides/index.md", "index.md", "zebra.md"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_directory_defaults_instead_of_pages() -> Result {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("test/index.md").write_str("# Home\n")?;
+        temp.child("test/runbooks/index.md")
+            .write_str("# Runbooks\n")?;
+        temp.child("test/runbooks/_defaults.md")
+            .write_str("---\nemoji: fire\n---\n")?;
+        let space = MarkdownSpace::from_directory(temp.child("test").path())?;
+
+        assert_eq!(space.markdown_pages.len(), 2);
+        let pages = space.parse(&TemplateRenderer::default()?, None)?;
+        let emojis: Vec<&str> = pages
+            .iter()
+            .map(|page| page.front_matter.emoji.as_str())
+            .collect();
+        assert!(emojis.contains(&"fire") && emojis.contains(&""));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_splits_pages_into_sections() -> Result {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("test/api.md").write_str(
+            "---\nsplit: h2\nlabels: [api]\n---\n# API\n\nIntro\n\n## Overview\n\nText\n\n## Data Model\n\nTables\n",
+        )?;
+        let space = MarkdownSpace::from_directory(temp.child("test").path())?;
+
+        let pages = space.parse(&TemplateRenderer::default()?, None)?;
+        assert_eq!(
+            pages
+                .iter()
+                .map(|page| (page.source.as_str(), page.title.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("api.md", "API"),
+                ("api.md#overview", "API: Overview"),
+                ("api.md#data-model", "API: Data Model")
+            ]
+        );
+        assert_eq!(pages[2].source_file(), "api.md");
+        assert_eq!(pages[2].front_matter.labels, vec!["api"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_splits_oversized_pages_into_sections() -> Result {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("test/_space.yml")
+            .write_str("limits:\n  max_body_size: 40\n  oversized: split\n")?;
+        temp.child("test/big.md")
+            .write_str("# Big\n\n## Overview\n\nText\n\n## Details\n\nMore text\n")?;
+        temp.child("test/small.md")
+            .write_str("# Small\n\n## Overview\n")?;
+        let space = MarkdownSpace::from_directory(temp.child("test").path())?;
+
+        let pages = space.parse(&TemplateRenderer::default()?, None)?;
+        let mut sources = pages
+            .iter()
+            .map(|page| page.source.as_str())
+            .collect::<Vec<_>>();
+        sources.sort();
+        assert_eq!(
+            sources,
+            vec!["big.md", "big.md#details", "big.md#overview", "small.md"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_prefixes_the_titles_of_flattened_pages() -> Result {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("test/_space.yml").write_str("max_depth: 2\n")?;
+        temp.child("test/db/index.md").write_str("# Databases\n")?;
+        temp.child("test/db/postgres/index.md")
+            .write_str("# Postgres\n")?;
+        temp.child("test/db/postgres/restore.md")
+            .write_str("# Restore\n")?;
+        temp.child("test/db/postgres/replicas/failover.md")
+            .write_str("# Failover\n")?;
+        let space = MarkdownSpace::from_directory(temp.child("test").path())?;
+
+        let pages = space.parse(&TemplateRenderer::default()?, None)?;
+        assert_eq!(
+            pages
+                .iter()
+                .map(|page| (page.source.as_str(), page.title.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("db/index.md", "Databases"),
+                ("db/postgres/index.md", "Postgres"),
+                (
+                    "db/postgres/replicas/failover.md",
+                    "Postgres / replicas / Failover"
+                ),
+                ("db/postgres/restore.md", "Postgres / Restore"),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_merges_files_into_one_page() -> Result {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("test/index.md").write_str("# Home\n")?;
+        temp.child("test/guide/index.md")
+            .write_str("---\nmerge: [install.md, ./usage.md]\n---\n# Guide\n")?;
+        temp.child("test/guide/install.md")
+            .write_str("---\nlabels: [ignored]\n---\n# Install\n\n## Requirements\n")?;
+        temp.child("test/guide/usage.md")
+            .write_str("# Usage\n\nSee [install](install.md)\n")?;
+        let space = MarkdownSpace::from_directory(temp.child("test").path())?;
+
+        assert_eq!(space.markdown_pages.len(), 2);
+        let pages = space.parse(&TemplateRenderer::default()?, None)?;
+        let guide = pages.iter().find(|page| page.title == "Guide").unwrap();
+        assert_eq!(
+            guide
+                .merged
+                .iter()
+                .map(|merged| (merged.source.as_str(), merged.anchor.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("guide/install.md", "install"), ("guide/usage.md", "usage")]
+        );
+        assert_eq!(
+            guide.source_files().collect::<Vec<_>>(),
+            vec!["guide/index.md", "guide/install.md", "guide/usage.md"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_confluence_templates_apart_from_pages() -> Result {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("test/index.md").touch().unwrap();
+        temp.child("test/_confluence_templates/runbook.md")
+            .touch()
+            .unwrap();
+        let space = MarkdownSpace::from_directory(temp.child("test").path())?;
+
+        assert_eq!(
+            space.markdown_pages,
+            vec![temp.child("test/index.md").to_path_buf()]
+        );
+        assert_eq!(
+            space.confluence_templates,
+            vec![temp
+                .child("test/_confluence_templates/runbook.md")
+                .to_path_buf()]
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn it_uses_the_basename_of_current_directory_if_not_full_path() -> Result {
         let temp = assert_fs::TempDir::new().unwrap();
@@ -346,10 +821,10 @@ mod tests {
         Ok(())
     }
 
-    fn parse_default<'a>(
-        space: &'a mut MarkdownSpace<'a>,
-    ) -> anyhow::Result<Vec<MarkdownPage<'a>>, anyhow::Error> {
-        space.parse(&mut TemplateRenderer::default()?)
+    fn parse_default(
+        space: &mut MarkdownSpace,
+    ) -> anyhow::Result<Vec<MarkdownPage>, anyhow::Error> {
+        space.parse(&TemplateRenderer::default()?, None)
     }
 
     #[test]
@@ -402,7 +877,7 @@ mod tests {
             .find(|x| x.title == "Page 1")
             .ok_or(anyhow!("No page"))?;
 
-        assert_eq!(page.warnings, Vec::<String>::default());
+        assert!(page.warnings.is_empty());
 
         assert_eq!(
             page.attachments,
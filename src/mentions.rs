@@ -1,41 +1,60 @@
-use std::{collections::HashMap, sync::RwLock};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+};
 
 use crate::{
-    confluence_client::ConfluenceClient, confluence_paginator::ConfluencePaginator,
-    console::print_warning, error::Result, responses,
+    confluence_client::ConfluenceClient,
+    confluence_paginator::ConfluencePaginator,
+    error::{ApiResponse, Result},
+    responses,
+    warnings::{Warning, WarningCode},
 };
 
 fn get_user(client: &ConfluenceClient, public_name: &str) -> Result<Option<responses::User>> {
-    let response = client.search_users(public_name)?.error_for_status()?;
+    let response = client.search_users(public_name)?.api_result()?;
     let mut results: Vec<responses::User> =
         ConfluencePaginator::<responses::SearchResult>::new(client)
             .start(response)?
-            .filter_map(|f| f.ok())
-            .map(|search_result_page| search_result_page.user)
-            .collect();
+            .map(|search_result_page| search_result_page.map(|result| result.user))
+            .collect::<Result<_>>()?;
     Ok(results.pop())
 }
 
-pub struct CachedMentions {
+/// User lookups by public name, cached for the duration of a sync so that mentions, watchers and
+/// owners only search for each person once.
+pub struct CachedUsers {
     client: ConfluenceClient,
+    /// Doesn't look anyone up, everyone is unknown.
+    offline: bool,
     cache: RwLock<HashMap<String, Option<String>>>,
+    /// Warnings about people that weren't found, until the caller takes them.
+    warnings: Mutex<Vec<Warning>>,
 }
 
-impl CachedMentions {
-    pub fn new(client: ConfluenceClient) -> CachedMentions {
+impl CachedUsers {
+    pub fn new(client: ConfluenceClient) -> CachedUsers {
         Self {
             client,
+            offline: false,
             cache: RwLock::new(HashMap::new()),
+            warnings: Mutex::default(),
         }
     }
 
-    fn format_as_user_link(&self, account_id: &str) -> tera::Value {
-        serde_json::to_value(format!(
-            // trailing space prevents the tag being recognized as a markdown link
-            "<ac:link ><ri:user ri:account-id=\"{}\"/></ac:link>",
-            account_id
-        ))
-        .unwrap()
+    /// For rendering without Confluence: mentions and avatars keep the name or email they were
+    /// given, without a warning.
+    pub fn offline(client: ConfluenceClient) -> CachedUsers {
+        Self {
+            offline: true,
+            ..Self::new(client)
+        }
+    }
+
+    /// The warnings about people that weren't found since the last call, once for each person.
+    /// They're left to the caller, which knows the warnings the space disabled.
+    pub fn take_warnings(&self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings.lock().unwrap())
     }
 
     fn read_cache(&self, public_name: &str) -> Option<Option<String>> {
@@ -46,28 +65,54 @@ impl CachedMentions {
             .map(|optional_account_id| optional_account_id.to_owned())
     }
 
-    fn account_id(&self, public_name: &str) -> tera::Result<Option<String>> {
-        if let Some(optional_account_id) = self.read_cache(public_name) {
+    pub fn account_id(&self, public_name: &str) -> Result<Option<String>> {
+        if self.offline {
+            Ok(None)
+        } else if let Some(optional_account_id) = self.read_cache(public_name) {
             Ok(optional_account_id.to_owned())
         } else {
             let mut write_cache = self.cache.write().unwrap();
-            match get_user(&self.client, public_name) {
-                Ok(Some(user)) => {
+            // another thread may have looked the user up while this one waited for the lock
+            if let Some(optional_account_id) = write_cache.get(public_name) {
+                return Ok(optional_account_id.to_owned());
+            }
+            match get_user(&self.client, public_name)? {
+                Some(user) => {
                     write_cache.insert(public_name.to_owned(), Some(user.account_id.clone()));
                     Ok(Some(user.account_id))
                 }
-                Ok(None) => {
+                None => {
                     write_cache.insert(String::from(public_name), None);
-                    print_warning(&format!("Unknown user \"{}\"", public_name));
+                    self.warnings.lock().unwrap().push(Warning::new(
+                        WarningCode::UnknownUser,
+                        format!("Unknown user \"{}\"", public_name),
+                    ));
                     Ok(None)
                 }
-
-                Err(err) => Err(tera::Error::msg(err.to_string())),
             }
         }
     }
 }
 
+pub struct CachedMentions {
+    users: Arc<CachedUsers>,
+}
+
+impl CachedMentions {
+    pub fn new(users: Arc<CachedUsers>) -> CachedMentions {
+        Self { users }
+    }
+
+    fn format_as_user_link(&self, account_id: &str) -> tera::Value {
+        serde_json::to_value(format!(
+            // trailing space prevents the tag being recognized as a markdown link
+            "<ac:link ><ri:user ri:account-id=\"{}\"/></ac:link>",
+            account_id
+        ))
+        .unwrap()
+    }
+}
+
 impl tera::Function for CachedMentions {
     fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
         let public_name = args.get("public_name").ok_or("Missing 'public_name'")?;
@@ -77,7 +122,11 @@ impl tera::Function for CachedMentions {
             _ => Err(tera::Error::msg("public_name must be a string")),
         }?;
 
-        match self.account_id(public_name_str)? {
+        match self
+            .users
+            .account_id(public_name_str)
+            .map_err(|err| tera::Error::msg(err.to_string()))?
+        {
             Some(account_id) => Ok(self.format_as_user_link(&account_id)),
             None => Ok(public_name.to_owned()),
         }
@@ -190,6 +239,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn it_shares_lookups_with_avatars() -> TestResult {
+        let mut server = mockito::Server::new();
+        let host = server.host_with_port();
+
+        let mock = mock_user_search(&mut server, "john.doe@example.com", TEST_USER);
+        let client = confluence_client::ConfluenceClient::new_insecure(&host);
+
+        let mut template_renderer = TemplateRenderer::default_with_client(&client)?;
+
+        let result = template_renderer.render_template_str(
+            "test.md",
+            "{{ mention(public_name=\"john.doe@example.com\") }}{{ avatar(email=\"john.doe@example.com\") }}",
+            &FrontMatter::default(),
+        )?;
+
+        mock.assert();
+
+        assert!(result.ends_with(&crate::team_pages::profile_picture("some-atlassian-uuid")));
+
+        Ok(())
+    }
+
     #[test]
     fn it_errors_if_public_name_not_a_string() -> TestResult {
         let server = mockito::Server::new();
@@ -0,0 +1,112 @@
+//! Syncing several spaces at once, when `--space` is given more than once.
+//!
+//! Every space syncs on its own thread with its own progress (each line starts with the space
+//! key), sharing the client and the user lookups. A space that fails doesn't stop the others, and
+//! the reports of all spaces are combined.
+use std::{path::Path, sync::Arc, thread};
+
+use anyhow::anyhow;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    console::{print_error, set_space_prefix},
+    markdown_space::MarkdownSpace,
+    mentions::CachedUsers,
+    sync::{sync_space, SyncOutputs},
+    Args, Result,
+};
+
+fn sync_one(
+    confluence_client: ConfluenceClient,
+    dir: &Path,
+    args: Args,
+    users: Arc<CachedUsers>,
+) -> Result<SyncOutputs> {
+    let mut markdown_space = MarkdownSpace::from_directory(dir)?;
+    set_space_prefix(&markdown_space.key);
+    sync_space(confluence_client, &mut markdown_space, args, users)
+}
+
+pub fn sync_spaces(confluence_client: ConfluenceClient, args: Args) -> Result<()> {
+    let users = Arc::new(CachedUsers::new(confluence_client.clone()));
+    let results: Vec<(&String, Result<SyncOutputs>)> = thread::scope(|scope| {
+        let handles: Vec<_> = args
+            .space
+            .iter()
+            .map(|dir| {
+                let (confluence_client, args, users) =
+                    (confluence_client.clone(), args.clone(), users.clone());
+                (
+                    dir,
+                    scope.spawn(move || sync_one(confluence_client, Path::new(dir), args, users)),
+                )
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|(dir, handle)| {
+                let result = handle
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow!("Syncing the space panicked")));
+                (dir, result)
+            })
+            .collect()
+    });
+
+    let mut outputs = Vec::new();
+    let mut failed = Vec::new();
+    for (dir, result) in results {
+        match result {
+            Ok(space_outputs) => outputs.push(space_outputs),
+            Err(err) => {
+                print_error(&format!("{}: {:#}", dir, err));
+                failed.push(dir.as_str());
+            }
+        }
+    }
+    SyncOutputs::merge(outputs).write(&args)?;
+    if !failed.is_empty() {
+        return Err(anyhow!(
+            "{} of {} spaces failed to sync: {}",
+            failed.len(),
+            args.space.len(),
+            failed.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn it_reports_every_space_that_failed() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("team/index.md").write_str("No title")?;
+        temp.child("ops/index.md").write_str("No title either")?;
+        let args = Args {
+            space: vec![
+                temp.child("team").path().to_string_lossy().to_string(),
+                temp.child("ops").path().to_string_lossy().to_string(),
+            ],
+            ..Default::default()
+        };
+
+        let result = sync_spaces(ConfluenceClient::new("host.example.com"), args);
+
+        let error = result.unwrap_err().to_string();
+        assert!(
+            error.starts_with("2 of 2 spaces failed to sync"),
+            "{}",
+            error
+        );
+        assert!(error.contains("team") && error.contains("ops"));
+
+        Ok(())
+    }
+}
@@ -47,7 +47,7 @@ impl Cover {
 
 // todo: "value": "{\"id\":\"a27ac7fd-5b79-4185-b5a2-c32afe0e84c6\",\"position\":50}",
 // "value": "{\"id\":\"https://images.unsplash.com/photo-1541701494587-cb58502866ab?crop=entropy&cs=srgb&fm=jpg&ixid=M3wyMDQ0MDF8MHwxfHNlYXJjaHwzfHxjb2xvcnxlbnwwfDB8fHwxNzQ4Mjk2MDg0fDA&ixlib=rb-4.1.0&q=85\",\"position\":50}",
-pub fn parse_cover(page: &MarkdownPage<'_>, link_generator: &LinkGenerator) -> serde_json::Value {
+pub fn parse_cover(page: &MarkdownPage, link_generator: &LinkGenerator) -> serde_json::Value {
     if let Some(cover) = &page.front_matter.cover {
         let result = if MarkdownPage::is_local_link(&cover.source) {
             json!({"id": link_generator.attachment_id(&cover.source, page), "position":cover.position})
@@ -65,9 +65,8 @@ pub fn parse_cover(page: &MarkdownPage<'_>, link_generator: &LinkGenerator) -> s
 mod test {
     use assert_fs::fixture::{FileWriteStr, PathChild};
     use saphyr::Yaml;
-    use std::str::FromStr;
+    use std::{str::FromStr, sync::Arc};
 
-    use comrak::{nodes::AstNode, Arena};
     use serde_json::json;
 
     use crate::{
@@ -75,6 +74,7 @@ mod test {
         error::TestResult,
         link_generator::LinkGenerator,
         markdown_space::MarkdownSpace,
+        mentions::CachedUsers,
         page_properties::{get_property_updates, COVER_PICTURE_ID_PUBLISHED_PROP},
         responses::ContentProperty,
         sync::sync_space,
@@ -110,8 +110,7 @@ cover:
 
     #[test]
     fn it_adds_cover_from_http() -> TestResult {
-        let arena = Arena::<AstNode>::new();
-        let page = markdown_page_from_str("test.md", PAGE_WITH_COVER_HTTP, &arena)?;
+        let page = markdown_page_from_str("test.md", PAGE_WITH_COVER_HTTP)?;
 
         assert!(page.attachments.is_empty());
 
@@ -130,8 +129,7 @@ cover:
 
     #[test]
     fn it_adds_cover_from_local_file() -> TestResult {
-        let arena = Arena::<AstNode>::new();
-        let page = markdown_page_from_str("test.md", PAGE_WITH_COVER_LOCAL_FILE, &arena)?;
+        let page = markdown_page_from_str("test.md", PAGE_WITH_COVER_LOCAL_FILE)?;
 
         assert_eq!(page.attachments.len(), 1, "Should add cover as attachment");
 
@@ -159,7 +157,12 @@ cover:
 
         let confluence_client = ConfluenceClient::new("host.example.com");
         let mut space = MarkdownSpace::from_directory(temp.child("test").path())?;
-        let sync_result = sync_space(confluence_client, &mut space, Args::default());
+        let sync_result = sync_space(
+            confluence_client.clone(),
+            &mut space,
+            Args::default(),
+            Arc::new(CachedUsers::new(confluence_client)),
+        );
 
         assert!(sync_result.is_err());
 
@@ -179,8 +182,7 @@ cover:
 
     #[test]
     fn it_supports_position() -> TestResult {
-        let arena = Arena::<AstNode>::new();
-        let page = markdown_page_from_str("test.md", PAGE_WITH_POSITION, &arena)?;
+        let page = markdown_page_from_str("test.md", PAGE_WITH_POSITION)?;
 
         let mut link_generator = LinkGenerator::default_test();
         link_generator.register_attachment_id(&page.source, "image.png", "some_other_id");
@@ -1,17 +1,72 @@
-use crate::{console::print_warning, markdown_page::MarkdownPage};
+use emojis::Emoji;
+
+use crate::{error::ConfluenceError, markdown_page::MarkdownPage, Result};
+
+/// How the page emoji is shown, set with the `emoji_title` key of `_space.yml`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum EmojiTitle {
+    /// Set the emoji title property, which Confluence Cloud shows in front of the title.
+    #[default]
+    Property,
+    /// Put the emoji in front of the title text, for Confluence Server/Data Center which don't
+    /// have the property.
+    Prefix,
+    /// The property on Confluence Cloud, the prefix on other instances.
+    Auto,
+}
+
+impl EmojiTitle {
+    pub fn from_str(s: Option<&str>) -> Result<EmojiTitle> {
+        match s {
+            None | Some("property") => Ok(EmojiTitle::Property),
+            Some("prefix") => Ok(EmojiTitle::Prefix),
+            Some("auto") => Ok(EmojiTitle::Auto),
+            Some(other) => Err(ConfluenceError::generic_error(format!(
+                "Invalid emoji title \"{}\" (should be property, prefix or auto)",
+                other
+            ))),
+        }
+    }
+
+    /// Picks property or prefix for `auto`, depending on the instance.
+    pub fn resolve(self, is_cloud: bool) -> EmojiTitle {
+        match self {
+            EmojiTitle::Auto if is_cloud => EmojiTitle::Property,
+            EmojiTitle::Auto => EmojiTitle::Prefix,
+            other => other,
+        }
+    }
+}
+
+static VARIATION_SELECTOR: char = '\u{fe0f}';
+
+/// The value Confluence stores for an emoji: the codepoints of the whole sequence (skin tones,
+/// flags, joined emojis) in hex, separated by dashes, e.g. `1f44d-1f3fd`. Variation selectors
+/// are left out, so emojis of a single codepoint are stored as they always were.
+fn property_value(emoji: &Emoji) -> String {
+    emoji
+        .as_str()
+        .chars()
+        .filter(|c| *c != VARIATION_SELECTOR)
+        .map(|c| format!("{:x}", c as u32))
+        .collect::<Vec<String>>()
+        .join("-")
+}
+
+/// An emoji given as a short code (`heart_eyes`) or as the emoji itself.
+pub(crate) fn find_emoji(emoji_string: &str) -> Option<&'static Emoji> {
+    let emoji_string = emoji_string.trim();
+    emojis::get_by_shortcode(emoji_string).or_else(|| emojis::get(emoji_string))
+}
 
+/// The value of the emoji title property of the page, `None` when it shouldn't be set.
 pub(crate) fn parse_emoji(page: &MarkdownPage) -> Option<String> {
-    let emoji_string = &page.front_matter.emoji;
-    if emoji_string.is_empty() {
+    let emoji_string = page.front_matter.emoji.trim();
+    if emoji_string.is_empty() || page.emoji_title == EmojiTitle::Prefix {
         None
-    } else if let Some(emoji) = emojis::get_by_shortcode(emoji_string) {
-        Some(format!(
-            "{:x}",
-            emoji.as_str().chars().next().unwrap() as u32
-        ))
     } else {
-        print_warning(&format!("Unknown short code '{}'", &emoji_string));
-        None
+        // unknown emojis are warned about when the page is parsed
+        find_emoji(emoji_string).map(property_value)
     }
 }
 
@@ -20,61 +75,102 @@ mod tests {
 
     use std::str::FromStr;
 
-    use comrak::{nodes::AstNode, Arena};
     use serde_json::json;
 
     use crate::{
+        error::TestResult,
         link_generator::LinkGenerator,
         markdown_page::page_from_str,
         page_properties::{get_property_updates, EMOJI_TITLE_PUBLISHED_PROP},
         responses::{ContentProperty, Version},
+        warnings::WarningCode,
     };
 
     use super::*;
 
-    fn without_emoji_md<'a>(
-        arena: &'a Arena<comrak::arena_tree::Node<'a, std::cell::RefCell<comrak::nodes::Ast>>>,
-    ) -> MarkdownPage<'a> {
+    fn without_emoji_md() -> MarkdownPage {
         let markdown_content = r##"---
 ---
 # Test Heading
 "##;
-        page_from_str("without_emoji.md", markdown_content, arena).unwrap()
+        page_from_str("without_emoji.md", markdown_content).unwrap()
     }
 
-    fn heart_eyes_md<'a>(
-        arena: &'a Arena<comrak::arena_tree::Node<'a, std::cell::RefCell<comrak::nodes::Ast>>>,
-    ) -> MarkdownPage<'a> {
+    fn heart_eyes_md() -> MarkdownPage {
         let markdown_content = r##"---
 emoji:  heart_eyes
 ---
 # Test Heading
 "##;
-        page_from_str("heart_eyes.md", markdown_content, arena).unwrap()
+        page_from_str("heart_eyes.md", markdown_content).unwrap()
     }
 
     #[test]
     fn it_reads_emoji_in_front_matter() {
-        let arena = Arena::<AstNode>::new();
         let markdown_content = r##"---
 emoji:  heart_eyes
 ---
 # Test Heading
 "##;
-        let page = page_from_str("test.md", markdown_content, &arena).unwrap();
+        let page = page_from_str("test.md", markdown_content).unwrap();
         let emoji = parse_emoji(&page);
 
         assert_eq!(emoji, Some(String::from_str("1f60d").unwrap()));
     }
 
+    #[test]
+    fn it_reads_unicode_emoji_in_front_matter() {
+        let markdown_content = "---\nemoji: \"\u{1F525}\"\n---\n# Test Heading\n";
+        let page = page_from_str("test.md", markdown_content).unwrap();
+        let emoji = parse_emoji(&page);
+
+        assert_eq!(emoji, Some(String::from("1f525")));
+    }
+
+    #[test]
+    fn it_keeps_every_codepoint_of_a_sequence() {
+        for (emoji, expected) in [
+            ("\u{1F44D}\u{1F3FD}", "1f44d-1f3fd"),
+            ("\u{1F1F3}\u{1F1F1}", "1f1f3-1f1f1"),
+            ("\u{1F469}\u{200D}\u{1F4BB}", "1f469-200d-1f4bb"),
+            ("\u{2764}\u{FE0F}", "2764"),
+            ("rainbow_flag", "1f3f3-200d-1f308"),
+        ] {
+            let markdown_content = format!("---\nemoji: \"{}\"\n---\n# Test Heading\n", emoji);
+            let page = page_from_str("test.md", &markdown_content).unwrap();
+
+            assert_eq!(parse_emoji(&page), Some(String::from(expected)));
+        }
+    }
+
+    #[test]
+    fn it_ignores_sequences_that_are_not_emoji() {
+        let markdown_content = "---\nemoji: \"\u{1F469}\u{1F4BB}\"\n---\n# Test Heading\n";
+        let page = page_from_str("test.md", markdown_content).unwrap();
+
+        assert_eq!(parse_emoji(&page), None);
+        assert_eq!(page.warnings[0].code, WarningCode::UnknownEmoji);
+    }
+
+    #[test]
+    fn it_picks_the_property_on_cloud() -> TestResult {
+        let auto = EmojiTitle::from_str(Some("auto"))?;
+
+        assert_eq!(auto.resolve(true), EmojiTitle::Property);
+        assert_eq!(auto.resolve(false), EmojiTitle::Prefix);
+        assert_eq!(EmojiTitle::Property.resolve(false), EmojiTitle::Property);
+        assert!(EmojiTitle::from_str(Some("title")).is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn it_allows_absent_emojis_in_front_matter() {
-        let arena = Arena::<AstNode>::new();
         let markdown_content = r##"---
 ---
 # Test Heading
 "##;
-        let page = page_from_str("test.md", markdown_content, &arena).unwrap();
+        let page = page_from_str("test.md", markdown_content).unwrap();
         let emoji = parse_emoji(&page);
 
         assert_eq!(emoji, None);
@@ -82,13 +178,12 @@ emoji:  heart_eyes
 
     #[test]
     fn it_fails_if_front_matter_emoji_string_is_not_a_valid_shortcode() {
-        let arena = Arena::<AstNode>::new();
         let markdown_content = r##"---
 emoji:  not_a_short_code
 ---
 # Test Heading
 "##;
-        let page = page_from_str("test.md", markdown_content, &arena).unwrap();
+        let page = page_from_str("test.md", markdown_content).unwrap();
         let emoji = parse_emoji(&page);
 
         assert_eq!(emoji, None);
@@ -99,8 +194,7 @@ emoji:  not_a_short_code
         // given a confluence page without emojis
         // when I add the emoji field to the front matter
         // then it adds it as a property to the confluence page
-        let arena = Arena::<AstNode>::new();
-        let page = heart_eyes_md(&arena);
+        let page = heart_eyes_md();
 
         let existing_properties: Vec<ContentProperty> = Vec::new();
 
@@ -125,11 +219,10 @@ emoji:  not_a_short_code
         // given a confluence page without emojis
         // when I add the emoji field to the front matter
         // then it adds it as a property to the confluence page
-        let arena = Arena::<AstNode>::new();
         let markdown_content = r##"
 # Test Heading
 "##;
-        let page = page_from_str("test.md", markdown_content, &arena).unwrap();
+        let page = page_from_str("test.md", markdown_content).unwrap();
 
         let existing_properties: Vec<ContentProperty> = vec![ContentProperty {
             id: String::from("123456"),
@@ -159,8 +252,7 @@ emoji:  not_a_short_code
 
     #[test]
     fn it_updates_existing_emoji() {
-        let arena = Arena::<AstNode>::new();
-        let page = heart_eyes_md(&arena);
+        let page = heart_eyes_md();
 
         let existing_properties: Vec<ContentProperty> = vec![ContentProperty {
             id: String::from("123456"),
@@ -190,8 +282,7 @@ emoji:  not_a_short_code
 
     #[test]
     fn it_skips_existing_emoji_updates_when_no_change() {
-        let arena = Arena::<AstNode>::new();
-        let page = heart_eyes_md(&arena);
+        let page = heart_eyes_md();
 
         let existing_properties: Vec<ContentProperty> = vec![ContentProperty {
             id: String::from("123456"),
@@ -213,8 +304,7 @@ emoji:  not_a_short_code
 
     #[test]
     fn it_skips_absent_emoji_updates_when_no_change() {
-        let arena = Arena::<AstNode>::new();
-        let page = without_emoji_md(&arena);
+        let page = without_emoji_md();
 
         let existing_properties: Vec<ContentProperty> = Vec::new();
 
@@ -0,0 +1,199 @@
+//! The ids of the pages of the files, committed to the space directory in `_page_ids.yml`. When
+//! the file exists it's the first thing used to find the page of a file, before the page map and
+//! matching titles, so every machine syncs a file to the same page and changes to the mapping show
+//! up in review. The `page-ids` subcommand writes it from what the space is synced to now.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::anyhow;
+use saphyr::Yaml;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    console::{print_info, print_status, Status},
+    markdown_space::MarkdownSpace,
+    mentions::CachedUsers,
+    sync::{check_homepage_exists, load_space, LoadedSpace},
+    Args, Result,
+};
+
+pub static PAGE_IDS_FILE: &str = "_page_ids.yml";
+
+/// Parses the mapping of files (relative to the space) to page ids.
+pub fn parse(yaml: &str) -> Result<BTreeMap<String, String>> {
+    let docs = Yaml::load_from_str(yaml)?;
+    let mapping = match docs.first() {
+        Some(Yaml::Hash(mapping)) => mapping,
+        None | Some(Yaml::Null) => return Ok(BTreeMap::new()),
+        _ => {
+            return Err(anyhow!(
+                "{} should be a map of files to page ids, e.g. \"guides/setup.md: 123456\"",
+                PAGE_IDS_FILE
+            ))
+        }
+    };
+    let mut page_ids = BTreeMap::new();
+    let mut ids = BTreeSet::new();
+    for (source, id) in mapping {
+        let source = source
+            .as_str()
+            .ok_or(anyhow!("Files in {} should be strings", PAGE_IDS_FILE))?
+            .trim_start_matches("./")
+            .replace('\\', "/");
+        let id = match id {
+            Yaml::Integer(id) => id.to_string(),
+            Yaml::String(id) => id.clone(),
+            _ => {
+                return Err(anyhow!(
+                    "The page id of {} in {} should be a number",
+                    source,
+                    PAGE_IDS_FILE
+                ))
+            }
+        };
+        if !ids.insert(id.clone()) {
+            return Err(anyhow!(
+                "Page {} is listed for more than one file in {}",
+                id,
+                PAGE_IDS_FILE
+            ));
+        }
+        page_ids.insert(source, id);
+    }
+    Ok(page_ids)
+}
+
+/// Reads `_page_ids.yml` of the space, empty when there is none.
+pub fn read(dir: &Path) -> Result<BTreeMap<String, String>> {
+    let path = dir.join(PAGE_IDS_FILE);
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let yaml = fs::read_to_string(&path)?;
+    parse(&yaml).map_err(|err| anyhow!("{}: {:#}", path.display(), err))
+}
+
+/// Writes the mapping one file per line, sorted, so changes show up as small diffs.
+pub fn render(page_ids: &BTreeMap<String, String>) -> String {
+    let mut yaml = String::from(
+        "# The pages the files of this space are synced to, written by `marked-space page-ids`\n",
+    );
+    for (source, id) in page_ids {
+        let plain = source
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/._-".contains(c));
+        let key = if plain {
+            source.clone()
+        } else {
+            serde_json::to_string(source).unwrap()
+        };
+        yaml.push_str(&format!("{}: {}\n", key, id));
+    }
+    yaml
+}
+
+/// Writes `_page_ids.yml` with the pages the files are synced to now: the pages of the page map,
+/// or else of the version messages and the titles. Files without a page yet are left out.
+pub fn write_page_ids(
+    confluence_client: ConfluenceClient,
+    markdown_space: &mut MarkdownSpace,
+    args: Args,
+) -> Result<()> {
+    let space_key = markdown_space.key.clone();
+    let LoadedSpace {
+        markdown_pages,
+        mut space,
+        mut link_generator,
+        ..
+    } = load_space(
+        &confluence_client,
+        markdown_space,
+        &args,
+        Arc::new(CachedUsers::new(confluence_client.clone())),
+    )?;
+    space.read_all_pages(&confluence_client)?;
+    check_homepage_exists(&space, &space_key)?;
+    space.link_pages(&mut link_generator)?;
+
+    let page_ids: BTreeMap<String, String> = markdown_pages
+        .iter()
+        .filter_map(|markdown_page| {
+            link_generator
+                .get_file_id(Path::new(&markdown_page.source))
+                .map(|id| (markdown_page.source.replace('\\', "/"), id))
+        })
+        .collect();
+    let unmapped = markdown_pages.len() - page_ids.len();
+
+    let path = markdown_space.dir.join(PAGE_IDS_FILE);
+    let yaml = render(&page_ids);
+    if fs::read_to_string(&path).ok().as_deref() == Some(yaml.as_str()) {
+        print_info(&format!("{} is up to date", path.display()));
+    } else {
+        let status = if path.exists() {
+            Status::Updated
+        } else {
+            Status::Created
+        };
+        fs::write(&path, yaml)?;
+        print_status(
+            status,
+            &format!("{} with {} page(s)", path.display(), page_ids.len()),
+        );
+    }
+    if unmapped > 0 {
+        print_info(&format!(
+            "{} file(s) have no page yet, run again after the next sync to add them",
+            unmapped
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reads_and_writes_the_page_ids() -> Result<()> {
+        let page_ids = BTreeMap::from([
+            (String::from("index.md"), String::from("999")),
+            (String::from("guides/set up.md"), String::from("42")),
+            (
+                String::from("runbooks/restore.md#Backups"),
+                String::from("43"),
+            ),
+        ]);
+        let yaml = render(&page_ids);
+
+        assert_eq!(
+            yaml.lines().skip(1).collect::<Vec<_>>(),
+            vec![
+                "\"guides/set up.md\": 42",
+                "index.md: 999",
+                "\"runbooks/restore.md#Backups\": 43",
+            ]
+        );
+        assert_eq!(parse(&yaml)?, page_ids);
+        assert_eq!(
+            parse("./guides\\setup.md: '42'\n")?,
+            BTreeMap::from([(String::from("guides/setup.md"), String::from("42"))])
+        );
+        assert_eq!(parse("")?, BTreeMap::new());
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_a_page_listed_twice() {
+        let err = parse("a.md: 42\nb.md: 42\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Page 42 is listed for more than one file in _page_ids.yml"
+        );
+        assert!(parse("- a.md").is_err());
+    }
+}
@@ -0,0 +1,304 @@
+//! Checking rendered pages against the limits of Confluence before they are uploaded.
+//!
+//! Pages that are too big fail at upload with an error that doesn't say much, so the size of the
+//! body, the number of macros and the total size of the attachments of every page are checked
+//! first. The limits are set with the `limits` key of `_space.yml`. With `oversized: split`,
+//! files with more markdown than the body may take are split into a page per `##` section (see
+//! page_split) before they are rendered.
+use std::fs;
+
+use anyhow::anyhow;
+use saphyr::Yaml;
+
+use crate::{
+    console::print_warning,
+    error::Result,
+    lint_rules::parse_positive_number,
+    markdown_page::{MarkdownPage, RenderedPage},
+    page_split::split_sections,
+    warnings::{Warning, WarningCode},
+};
+
+const KB: u64 = 1024;
+const MB: u64 = 1024 * KB;
+
+/// What happens to pages over a limit.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Oversized {
+    #[default]
+    Warn,
+    /// Stop the sync before the page is uploaded.
+    Error,
+    /// Split files over the body size on `##` headings, as with `split: h2`, and warn about the
+    /// pages that are still over a limit.
+    Split,
+}
+
+impl Oversized {
+    fn from_str(s: Option<&str>) -> Result<Self> {
+        match s {
+            None | Some("warn") => Ok(Oversized::Warn),
+            Some("error") => Ok(Oversized::Error),
+            Some("split") => Ok(Oversized::Split),
+            Some(other) => Err(anyhow!(
+                "Invalid value \"{}\" for \"limits.oversized\" (should be warn, error or split)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PageLimits {
+    /// Bytes of storage format.
+    pub max_body_size: u64,
+    pub max_macros: usize,
+    /// Bytes of all attachments of a page together.
+    pub max_attachment_size: u64,
+    pub oversized: Oversized,
+}
+
+impl Default for PageLimits {
+    fn default() -> Self {
+        PageLimits {
+            max_body_size: 5 * MB,
+            max_macros: 1000,
+            max_attachment_size: 100 * MB,
+            oversized: Oversized::Warn,
+        }
+    }
+}
+
+/// A number of bytes, like `5242880`, `512KB` or `5MB`.
+fn parse_size(yaml: &Yaml, key: &str) -> Result<Option<u64>> {
+    let error = || {
+        anyhow!(
+            "Failed to parse \"{}\" key (should be a size like 5MB or 512KB)",
+            key
+        )
+    };
+    match yaml {
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        Yaml::Integer(n) if *n > 0 => Ok(Some(*n as u64)),
+        Yaml::String(s) => {
+            let s = s.trim().to_uppercase();
+            let (number, unit) = match s.strip_suffix("MB").or(s.strip_suffix('M')) {
+                Some(number) => (number, MB),
+                None => match s.strip_suffix("KB").or(s.strip_suffix('K')) {
+                    Some(number) => (number, KB),
+                    None => (s.strip_suffix('B').unwrap_or(&s), 1),
+                },
+            };
+            number
+                .trim()
+                .parse::<u64>()
+                .ok()
+                .filter(|n| *n > 0)
+                .map(|n| Some(n * unit))
+                .ok_or_else(error)
+        }
+        _ => Err(error()),
+    }
+}
+
+pub(crate) fn format_size(bytes: u64) -> String {
+    if bytes >= MB {
+        format!("{:.1}MB", bytes as f64 / MB as f64)
+    } else if bytes >= KB {
+        format!("{:.1}KB", bytes as f64 / KB as f64)
+    } else {
+        format!("{} bytes", bytes)
+    }
+}
+
+impl PageLimits {
+    pub fn from_yaml(yaml: &Yaml) -> Result<Self> {
+        let default = PageLimits::default();
+        match yaml {
+            Yaml::BadValue | Yaml::Null => Ok(default),
+            Yaml::Hash(_) => Ok(PageLimits {
+                max_body_size: parse_size(&yaml["max_body_size"], "limits.max_body_size")?
+                    .unwrap_or(default.max_body_size),
+                max_macros: parse_positive_number(&yaml["max_macros"], "limits.max_macros")?
+                    .unwrap_or(default.max_macros),
+                max_attachment_size: parse_size(
+                    &yaml["max_attachment_size"],
+                    "limits.max_attachment_size",
+                )?
+                .unwrap_or(default.max_attachment_size),
+                oversized: Oversized::from_str(yaml["oversized"].as_str())?,
+            }),
+            _ => Err(anyhow!("Failed to parse \"limits\" key (should be a map)")),
+        }
+    }
+
+    /// Whether the markdown of a file is split into a page per section because of its size. The
+    /// storage format is at least as big as the markdown, so a file over the body size would
+    /// render to a page over it.
+    pub fn splits(&self, content: &str) -> bool {
+        self.oversized == Oversized::Split
+            && content.len() as u64 > self.max_body_size
+            && !split_sections(content).1.is_empty()
+    }
+
+    fn warnings(&self, page: &MarkdownPage, rendered_page: &RenderedPage) -> Vec<Warning> {
+        let mut warnings = Vec::default();
+        let body_size = rendered_page.content.len() as u64;
+        if body_size > self.max_body_size {
+            warnings.push(Warning::new(
+                WarningCode::OversizedPage,
+                format!(
+                    "[{}] body is {}, over the limit of {}",
+                    page.source,
+                    format_size(body_size),
+                    format_size(self.max_body_size)
+                ),
+            ));
+        }
+        let macros = rendered_page
+            .content
+            .matches("<ac:structured-macro")
+            .count();
+        if macros > self.max_macros {
+            warnings.push(Warning::new(
+                WarningCode::OversizedPage,
+                format!(
+                    "[{}] has {} macros, over the limit of {}",
+                    page.source, macros, self.max_macros
+                ),
+            ));
+        }
+        let attachment_size: u64 = page
+            .attachments
+            .iter()
+            .filter(|attachment| attachment.qr_target.is_none())
+            .filter_map(|attachment| fs::metadata(&attachment.path).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        if attachment_size > self.max_attachment_size {
+            warnings.push(Warning::new(
+                WarningCode::OversizedPage,
+                format!(
+                    "[{}] has {} of attachments, over the limit of {}",
+                    page.source,
+                    format_size(attachment_size),
+                    format_size(self.max_attachment_size)
+                ),
+            ));
+        }
+        warnings.retain(|warning| !page.disabled_warnings.contains(&warning.code));
+        warnings
+    }
+
+    /// Reports a rendered page that is over a limit, failing when `oversized: error` is set.
+    pub fn check(&self, page: &MarkdownPage, rendered_page: &RenderedPage) -> Result<()> {
+        let warnings = self.warnings(page, rendered_page);
+        if warnings.is_empty() {
+            return Ok(());
+        }
+        match self.oversized {
+            Oversized::Warn | Oversized::Split => {
+                for warning in warnings {
+                    print_warning(&warning.to_string());
+                }
+                Ok(())
+            }
+            Oversized::Error => Err(anyhow!(
+                "{}",
+                warnings
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::TestResult, link_generator::LinkGenerator, test_helpers::markdown_page_from_str,
+    };
+
+    use super::*;
+
+    #[test]
+    fn it_parses_limits() -> TestResult {
+        let yaml = &Yaml::load_from_str(
+            "max_body_size: 512KB\nmax_macros: 20\nmax_attachment_size: 10485760\noversized: error",
+        )?[0];
+
+        assert_eq!(
+            PageLimits::from_yaml(yaml)?,
+            PageLimits {
+                max_body_size: 512 * KB,
+                max_macros: 20,
+                max_attachment_size: 10 * MB,
+                oversized: Oversized::Error,
+            }
+        );
+        assert_eq!(PageLimits::from_yaml(&Yaml::Null)?, PageLimits::default());
+
+        let yaml = &Yaml::load_from_str("max_body_size: lots")?[0];
+        assert!(PageLimits::from_yaml(yaml).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_pages_over_the_limits() -> TestResult {
+        let page = markdown_page_from_str(
+            "big.md",
+            "# Big\n\n> [!NOTE]\n> one\n\n> [!NOTE]\n> two\n\nSome text that takes up space.\n",
+        )?;
+        let rendered_page = page.render(&LinkGenerator::default_test())?;
+        let limits = PageLimits {
+            max_body_size: 100,
+            max_macros: 1,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            limits
+                .warnings(&page, &rendered_page)
+                .iter()
+                .map(|warning| warning.to_string().split(',').next().unwrap().to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                format!(
+                    "[big.md] body is {}",
+                    format_size(rendered_page.content.len() as u64)
+                ),
+                String::from("[big.md] has 2 macros")
+            ]
+        );
+        assert!(limits.check(&page, &rendered_page).is_ok());
+        assert!(PageLimits {
+            oversized: Oversized::Error,
+            ..limits
+        }
+        .check(&page, &rendered_page)
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_splits_oversized_files_with_sections() {
+        let limits = PageLimits {
+            max_body_size: 20,
+            oversized: Oversized::Split,
+            ..Default::default()
+        };
+
+        assert!(limits.splits("# Big\n\n## One\n\nText\n\n## Two\n\nText\n"));
+        assert!(!limits.splits("# Small\n\n## One\n"));
+        assert!(!limits.splits("# Big\n\nWithout any sections at all\n"));
+        assert!(!PageLimits {
+            oversized: Oversized::Warn,
+            ..limits
+        }
+        .splits("# Big\n\n## One\n\nText\n\n## Two\n\nText\n"));
+    }
+}
@@ -0,0 +1,228 @@
+//! The map of markdown files to the pages they were synced to, kept in a content property of the
+//! homepage. It's the first thing used to find the page of a file, before matching titles, so
+//! retitled pages keep their page instead of being archived and recreated.
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    console::{print_status, Status},
+    error::{ApiResponse, ConfluenceError, Result},
+    responses::{ContentProperty, MultiEntityResult},
+};
+
+pub static PAGE_MAP_PROP: &str = "marked-space-page-map";
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct PageMapEntry {
+    pub id: String,
+    /// Checksum of the content at the last sync, folders have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// The page failed to render or sync in the last run, `--retry-failed` only syncs these.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub failed: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct PageMap {
+    /// Source path (with forward slashes) to page.
+    pub pages: BTreeMap<String, PageMapEntry>,
+    property: Option<ContentProperty>,
+}
+
+impl PageMap {
+    pub fn load(confluence_client: &ConfluenceClient, homepage_id: &str) -> Result<Self> {
+        let properties: MultiEntityResult<ContentProperty> = confluence_client
+            .get_properties(homepage_id)?
+            .api_result()?
+            .json()?;
+        let property = properties
+            .results
+            .into_iter()
+            .find(|property| property.key == PAGE_MAP_PROP);
+        let pages = match &property {
+            // starting over would match files by title again, and overwrite the map
+            Some(property) => serde_json::from_value(property.value.clone()).map_err(|err| {
+                ConfluenceError::generic_error(format!(
+                    "The {} property of the homepage isn't a valid page map ({}), fix or delete \
+                     it to match the files to pages by title again",
+                    PAGE_MAP_PROP, err
+                ))
+            })?,
+            None => BTreeMap::default(),
+        };
+        Ok(PageMap { pages, property })
+    }
+
+    /// Starts over with the pages of this sync, keeping the loaded property to update it.
+    pub fn clear(&mut self) {
+        self.pages.clear();
+    }
+
+    pub fn insert(&mut self, source: &str, id: &str, checksum: Option<String>) {
+        self.pages.insert(
+            source.replace('\\', "/"),
+            PageMapEntry {
+                id: String::from(id),
+                checksum,
+                failed: false,
+            },
+        );
+    }
+
+    /// Records that the page failed, keeping the checksum of the last sync of the same page, so a
+    /// page that failed because it was edited in Confluence is still known to be edited next time.
+    pub fn mark_failed(&mut self, source: &str, id: &str, previous: Option<&PageMapEntry>) {
+        self.pages.insert(
+            source.replace('\\', "/"),
+            PageMapEntry {
+                id: String::from(id),
+                checksum: previous
+                    .filter(|entry| entry.id == id)
+                    .and_then(|entry| entry.checksum.clone()),
+                failed: true,
+            },
+        );
+    }
+
+    /// Keeps what the last run recorded about a page that isn't synced this run.
+    pub fn keep(&mut self, source: &str, id: &str, previous: Option<&PageMapEntry>) {
+        self.pages.insert(
+            source.replace('\\', "/"),
+            PageMapEntry {
+                id: String::from(id),
+                checksum: previous.and_then(|entry| entry.checksum.clone()),
+                failed: previous.is_some_and(|entry| entry.failed),
+            },
+        );
+    }
+
+    /// Writes the map in one go at the end of a sync. The version number of the property makes
+    /// Confluence reject the update if another sync wrote the map in the meantime.
+    pub fn save(&self, confluence_client: &ConfluenceClient, homepage_id: &str) -> Result<()> {
+        let value = json!(self.pages);
+        let response = match &self.property {
+            Some(property) if property.value == value => return Ok(()),
+            Some(property) => confluence_client.set_property(
+                homepage_id,
+                &property.id,
+                json!({
+                    "key": PAGE_MAP_PROP,
+                    "value": value,
+                    "version": {
+                        "message": property.version.message,
+                        "number": property.version.number + 1,
+                    }
+                }),
+            )?,
+            None => confluence_client
+                .create_property(homepage_id, json!({"key": PAGE_MAP_PROP, "value": value}))?,
+        };
+        if !response.status().is_success() {
+            return Err(ConfluenceError::failed_request(response));
+        }
+        print_status(Status::Updated, &format!("property {}", PAGE_MAP_PROP));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Matcher;
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn it_fails_on_a_corrupt_map() {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        server
+            .mock("GET", "/wiki/api/v2/pages/999/properties")
+            .with_status(200)
+            .with_body(
+                json!({"results": [
+                    {"id": "7", "key": PAGE_MAP_PROP, "value": {"guide.md": "42"},
+                     "version": {"number": 3, "message": ""}}
+                ]})
+                .to_string(),
+            )
+            .create();
+
+        let err = PageMap::load(&client, "999").unwrap_err();
+        assert!(err.to_string().starts_with(
+            "The marked-space-page-map property of the homepage isn't a valid page map"
+        ));
+    }
+
+    #[test]
+    fn it_loads_and_updates_the_map() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        server
+            .mock("GET", "/wiki/api/v2/pages/999/properties")
+            .with_status(200)
+            .with_body(
+                json!({"results": [
+                    {"id": "5", "key": "emoji-title-published", "value": "1f600", "version": {"number": 1, "message": ""}},
+                    {"id": "6", "key": PAGE_MAP_PROP, "value": {"guide.md": {"id": "42", "checksum": "OLD"}}, "version": {"number": 3, "message": ""}}
+                ]})
+                .to_string(),
+            )
+            .create();
+        let update = server
+            .mock("PUT", "/wiki/api/v2/pages/999/properties/6")
+            .match_body(Matcher::PartialJson(json!({
+                "value": {"guide.md": {"id": "42", "checksum": "NEW"}, "folder.md": {"id": "43"}, "broken.md": {"id": "44", "failed": true}},
+                "version": {"number": 4}
+            })))
+            .with_status(200)
+            .create();
+
+        let mut page_map = PageMap::load(&client, "999")?;
+        assert_eq!(
+            page_map.pages.get("guide.md"),
+            Some(&PageMapEntry {
+                id: String::from("42"),
+                checksum: Some(String::from("OLD")),
+                failed: false,
+            })
+        );
+
+        page_map.clear();
+        page_map.insert("guide.md", "42", Some(String::from("NEW")));
+        page_map.insert("folder.md", "43", None);
+        page_map.mark_failed("broken.md", "44", None);
+        page_map.save(&client, "999")?;
+
+        update.assert();
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_creates_the_property_once() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let create = server
+            .mock("POST", "/wiki/api/v2/pages/999/properties")
+            .match_body(Matcher::Json(json!({
+                "key": PAGE_MAP_PROP,
+                "value": {"index.md": {"id": "999", "checksum": "ABC"}}
+            })))
+            .with_status(200)
+            .create();
+
+        let mut page_map = PageMap::default();
+        page_map.insert("index.md", "999", Some(String::from("ABC")));
+        page_map.save(&client, "999")?;
+
+        create.assert();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+//! Publishing several markdown files as one page, with `merge` in the front matter of the page.
+//!
+//! The files listed in `merge` (relative to the page) are appended to the page in that order and
+//! aren't published on their own. Their headings move one level down, so the title of every file
+//! becomes a `##` section of the page. Links to a merged file go to the anchor of its section.
+use std::path::Path;
+
+use path_clean::PathClean;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    heading_anchors::heading_anchor,
+    page_split::heading_text,
+    template_escaping::{closes, opening_fence},
+};
+
+/// A file that is published as a section of another page.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct MergedFile {
+    /// Relative to the space directory.
+    pub source: String,
+    /// The anchor of the section the file became.
+    pub anchor: String,
+}
+
+/// The path of a file in `merge`, relative to the space directory like the source of the page.
+pub fn merged_source(page_source: &str, file: &str) -> String {
+    Path::new(page_source)
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(file)
+        .clean()
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+fn heading_level(line: &str) -> Option<usize> {
+    let level = line.trim_start().chars().take_while(|c| *c == '#').count();
+    heading_text(line, level).map(|_| level)
+}
+
+/// The markdown of a merged file with its headings one level down (outside of code blocks), and
+/// the anchor of its first heading.
+pub fn demote_headings(content: &str) -> (String, Option<String>) {
+    let mut markdown = String::with_capacity(content.len() + 16);
+    let mut anchor = None;
+    let mut fence: Option<&str> = None;
+    for line in content.split_inclusive('\n') {
+        match fence {
+            Some(open) => {
+                if closes(line, open) {
+                    fence = None;
+                }
+            }
+            None => {
+                fence = opening_fence(line);
+                if let Some(level @ 1..=5) = heading_level(line) {
+                    if anchor.is_none() {
+                        anchor = heading_text(line, level).map(heading_anchor);
+                    }
+                    let indent = line.len() - line.trim_start().len();
+                    markdown.push_str(&line[..indent]);
+                    markdown.push('#');
+                    markdown.push_str(&line[indent..]);
+                    continue;
+                }
+            }
+        }
+        markdown.push_str(line);
+    }
+    (markdown, anchor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_moves_headings_down() {
+        let (markdown, anchor) = demote_headings(
+            "# Getting Started\n\nText\n\n## Install\n\n```markdown\n# Not a heading\n```\n\n###### Deepest\n#hashtag\n",
+        );
+
+        assert_eq!(
+            markdown,
+            "## Getting Started\n\nText\n\n### Install\n\n```markdown\n# Not a heading\n```\n\n###### Deepest\n#hashtag\n"
+        );
+        assert_eq!(anchor, Some(String::from("getting-started")));
+    }
+
+    #[test]
+    fn it_resolves_merged_files_next_to_the_page() {
+        assert_eq!(
+            merged_source("guide/index.md", "install.md"),
+            "guide/install.md"
+        );
+        assert_eq!(
+            merged_source("guide/index.md", "../shared/faq.md"),
+            "shared/faq.md"
+        );
+        assert_eq!(merged_source("index.md", "./intro.md"), "intro.md");
+    }
+}
@@ -0,0 +1,327 @@
+//! Planning the re-parenting of pages when the directory structure changes.
+//!
+//! Moving a page under one of its own descendants fails, so when a restructure moves many pages
+//! the moves have to happen in the right order. The plan is worked out up front on a copy of the
+//! page tree and executed with the move endpoint before the content of the pages is synced.
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::anyhow;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    confluence_page::ConfluenceNode,
+    confluence_space::ConfluenceSpace,
+    console::{print_info, Status},
+    error::{ConfluenceError, Result},
+    sync_operation::SyncOperation,
+};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PageMove {
+    pub id: String,
+    pub title: String,
+    pub from: Option<String>,
+    pub to: String,
+}
+
+/// The pages of a space as id to (title, parent id).
+type Tree = BTreeMap<String, (String, Option<String>)>;
+
+#[derive(Debug, Default)]
+pub struct MovePlan {
+    /// In the order they have to be executed in.
+    pub moves: Vec<PageMove>,
+    before: Tree,
+    after: Tree,
+}
+
+fn is_descendant(tree: &Tree, id: &str, ancestor: &str) -> bool {
+    let mut seen = HashSet::<&str>::default();
+    let mut current = tree.get(id).and_then(|(_, parent)| parent.as_deref());
+    while let Some(parent) = current {
+        if parent == ancestor {
+            return true;
+        }
+        if !seen.insert(parent) {
+            return false;
+        }
+        current = tree.get(parent).and_then(|(_, parent)| parent.as_deref());
+    }
+    false
+}
+
+/// Plans moving pages to the parents in `desired` (page id to parent id), failing if the desired
+/// parents form a cycle.
+pub fn plan_moves(
+    nodes: &[ConfluenceNode],
+    desired: &BTreeMap<String, String>,
+) -> Result<MovePlan> {
+    let before: Tree = nodes
+        .iter()
+        .map(|node| {
+            (
+                node.id.clone(),
+                (node.title.clone(), node.parent_id.clone()),
+            )
+        })
+        .collect();
+    let mut after = before.clone();
+    for (id, parent) in desired {
+        if let Some((_, current)) = after.get_mut(id) {
+            *current = Some(parent.clone());
+        }
+    }
+    if let Some((id, (title, _))) = after.iter().find(|(id, _)| is_descendant(&after, id, id)) {
+        return Err(anyhow!(
+            "Can't move page \"{}\" ({}), it would end up under itself",
+            title,
+            id
+        ));
+    }
+
+    let mut pending: Vec<PageMove> = desired
+        .iter()
+        .filter_map(|(id, parent)| {
+            let (title, current) = before.get(id)?;
+            (current.as_ref() != Some(parent)).then(|| PageMove {
+                id: id.clone(),
+                title: title.clone(),
+                from: current.clone(),
+                to: parent.clone(),
+            })
+        })
+        .collect();
+
+    // a page can't be moved under its own descendants, so those have to move out first
+    let mut tree = before.clone();
+    let mut moves = Vec::<PageMove>::default();
+    while !pending.is_empty() {
+        let Some(next) = pending
+            .iter()
+            .position(|m| m.to != m.id && !is_descendant(&tree, &m.to, &m.id))
+        else {
+            return Err(anyhow!(
+                "Can't find an order to move pages {} in",
+                pending
+                    .iter()
+                    .map(|m| format!("\"{}\"", m.title))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ));
+        };
+        let page_move = pending.remove(next);
+        if let Some((_, parent)) = tree.get_mut(&page_move.id) {
+            *parent = Some(page_move.to.clone());
+        }
+        moves.push(page_move);
+    }
+
+    Ok(MovePlan {
+        moves,
+        before,
+        after,
+    })
+}
+
+fn render_tree(tree: &Tree, root: &str) -> String {
+    fn render(tree: &Tree, id: &str, depth: usize, output: &mut String) {
+        let Some((title, _)) = tree.get(id) else {
+            return;
+        };
+        output.push_str(&format!("{}- {}\n", "  ".repeat(depth), title));
+        let mut children: Vec<(&String, &String)> = tree
+            .iter()
+            .filter(|(_, (_, parent))| parent.as_deref() == Some(id))
+            .map(|(id, (title, _))| (title, id))
+            .collect();
+        children.sort();
+        for (_, child) in children {
+            render(tree, child, depth + 1, output);
+        }
+    }
+    let mut output = String::default();
+    render(tree, root, 0, &mut output);
+    output
+}
+
+impl MovePlan {
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+    /// The page tree under `root` before and after the moves.
+    pub fn trees(&self, root: &str) -> (String, String) {
+        (
+            render_tree(&self.before, root),
+            render_tree(&self.after, root),
+        )
+    }
+
+    fn title(&self, id: &str) -> String {
+        self.before
+            .get(id)
+            .map(|(title, _)| title.clone())
+            .unwrap_or(id.to_string())
+    }
+
+    /// Prints the moves and the tree before and after them, for `--check`.
+    pub fn print(&self, root: &str) {
+        if self.is_empty() {
+            return;
+        }
+        print_info("Planned page moves:");
+        for page_move in &self.moves {
+            print_info(&format!(
+                "  \"{}\" from \"{}\" to \"{}\"",
+                page_move.title,
+                page_move
+                    .from
+                    .as_deref()
+                    .map(|from| self.title(from))
+                    .unwrap_or_default(),
+                self.title(&page_move.to)
+            ));
+        }
+        let (before, after) = self.trees(root);
+        print_info("Before:");
+        before
+            .lines()
+            .for_each(|line| print_info(&format!("  {}", line)));
+        print_info("After:");
+        after
+            .lines()
+            .for_each(|line| print_info(&format!("  {}", line)));
+    }
+
+    pub fn execute(
+        &self,
+        confluence_client: &ConfluenceClient,
+        space: &mut ConfluenceSpace,
+    ) -> Result<()> {
+        for page_move in &self.moves {
+            let op = SyncOperation::start(
+                format!(
+                    "[{}] moved under \"{}\"",
+                    page_move.title,
+                    self.title(&page_move.to)
+                ),
+                true,
+            );
+            let response =
+                confluence_client.move_page_relative(&page_move.id, "append", &page_move.to)?;
+            if !response.status().is_success() {
+                op.end(Status::Error);
+                return Err(ConfluenceError::failed_request(response));
+            }
+            space.set_parent(&page_move.id, &page_move.to);
+            op.end(Status::Updated);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        confluence_page::{ConfluenceFolder, ConfluenceNodeType},
+        error::TestResult,
+    };
+
+    use super::*;
+
+    fn node(id: &str, title: &str, parent: Option<&str>) -> ConfluenceNode {
+        ConfluenceNode {
+            id: String::from(id),
+            title: String::from(title),
+            parent_id: parent.map(String::from),
+            data: ConfluenceNodeType::Folder(ConfluenceFolder {}),
+        }
+    }
+
+    fn desired(moves: &[(&str, &str)]) -> BTreeMap<String, String> {
+        moves
+            .iter()
+            .map(|(id, parent)| (String::from(*id), String::from(*parent)))
+            .collect()
+    }
+
+    #[test]
+    fn it_plans_nothing_when_parents_match() -> TestResult {
+        let nodes = vec![node("1", "Home", None), node("2", "Guides", Some("1"))];
+
+        assert!(plan_moves(&nodes, &desired(&[("2", "1")]))?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_moves_descendants_out_first() -> TestResult {
+        // Home > Guides > Setup becomes Home > Setup > Guides
+        let nodes = vec![
+            node("1", "Home", None),
+            node("2", "Guides", Some("1")),
+            node("3", "Setup", Some("2")),
+        ];
+
+        let plan = plan_moves(&nodes, &desired(&[("2", "3"), ("3", "1")]))?;
+
+        assert_eq!(
+            plan.moves
+                .iter()
+                .map(|m| (m.id.as_str(), m.to.as_str()))
+                .collect::<Vec<(&str, &str)>>(),
+            vec![("3", "1"), ("2", "3")]
+        );
+        assert_eq!(
+            plan.trees("1"),
+            (
+                String::from("- Home\n  - Guides\n    - Setup\n"),
+                String::from("- Home\n  - Setup\n    - Guides\n")
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_detects_cycles() {
+        let nodes = vec![
+            node("1", "Home", None),
+            node("2", "Guides", Some("1")),
+            node("3", "Setup", Some("1")),
+        ];
+
+        assert!(plan_moves(&nodes, &desired(&[("2", "3"), ("3", "2")])).is_err());
+    }
+
+    #[test]
+    fn it_executes_moves_with_the_move_endpoint() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let first = server
+            .mock("PUT", "/wiki/rest/api/content/3/move/append/1")
+            .with_status(200)
+            .create();
+        let second = server
+            .mock("PUT", "/wiki/rest/api/content/2/move/append/3")
+            .with_status(200)
+            .create();
+        let nodes = vec![
+            node("1", "Home", None),
+            node("2", "Guides", Some("1")),
+            node("3", "Setup", Some("2")),
+        ];
+        let mut space = ConfluenceSpace::new_test("99", "1", nodes.clone());
+
+        plan_moves(&nodes, &desired(&[("2", "3"), ("3", "1")]))?.execute(&client, &mut space)?;
+
+        first.assert();
+        second.assert();
+        assert_eq!(
+            space.get_existing_node("2").unwrap().parent_id,
+            Some(String::from("3"))
+        );
+
+        Ok(())
+    }
+}
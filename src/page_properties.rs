@@ -1,11 +1,12 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet};
 
 use serde_json::json;
 
 use crate::console::{print_status, Status};
-use crate::error::Result;
+use crate::error::{ApiResponse, Result};
 use crate::page_covers::parse_cover;
 use crate::page_emojis::parse_emoji;
+use crate::provenance::{provenance_update, PageProvenance};
 use crate::responses::{self, ContentProperty, MultiEntityResult};
 use crate::{
     confluence_client::ConfluenceClient, link_generator::LinkGenerator, markdown_page::MarkdownPage,
@@ -17,8 +18,8 @@ pub static COVER_PICTURE_ID_PUBLISHED_PROP: &str = "cover-picture-id-published";
 fn get_page_property_values(
     page: &MarkdownPage,
     link_generator: &LinkGenerator,
-) -> HashMap<String, serde_json::Value> {
-    let mut result = HashMap::new();
+) -> BTreeMap<String, serde_json::Value> {
+    let mut result = BTreeMap::new();
     result.insert(
         String::from(EMOJI_TITLE_PUBLISHED_PROP),
         json!(parse_emoji(page)),
@@ -33,14 +34,14 @@ fn get_page_property_values(
 }
 
 pub fn get_property_updates(
-    page: &MarkdownPage<'_>,
+    page: &MarkdownPage,
     existing_properties: &[ContentProperty],
     link_generator: &LinkGenerator,
 ) -> Vec<ContentProperty> {
     let mut result = Vec::new();
 
     let page_properties = get_page_property_values(page, link_generator);
-    let mut page_property_keys: HashSet<String> = page_properties.keys().cloned().collect();
+    let mut page_property_keys: BTreeSet<String> = page_properties.keys().cloned().collect();
 
     for prop in existing_properties {
         if let Some(new_value) = page_properties.get(&prop.key) {
@@ -79,13 +80,17 @@ pub fn sync_page_properties(
     page: &MarkdownPage,
     page_id: &str,
     link_generator: &LinkGenerator,
+    provenance: Option<&PageProvenance>,
 ) -> Result<()> {
     let prop_json = confluence_client
         .get_properties(page_id)?
-        .error_for_status()?
+        .api_result()?
         .json::<MultiEntityResult<responses::ContentProperty>>()?;
 
-    let property_updates = get_property_updates(page, &prop_json.results, link_generator);
+    let mut property_updates = get_property_updates(page, &prop_json.results, link_generator);
+    property_updates.extend(
+        provenance.and_then(|provenance| provenance_update(&prop_json.results, provenance)),
+    );
 
     for property_update in property_updates.iter() {
         let update_response = if property_update.value.is_null() {
@@ -122,7 +127,7 @@ pub fn sync_page_properties(
             )
         };
 
-        update_response?.error_for_status()?;
+        update_response?.api_result()?;
     }
 
     Ok(())
@@ -0,0 +1,198 @@
+//! Splitting long markdown files into a page per section, with `split: h2` in the front matter.
+//!
+//! What comes before the first `##` heading stays the page of the file, followed by a list of the
+//! sections. Every section becomes a child page titled "<page title>: <heading>", with its
+//! headings one level up and links to the previous and next section and back to the page. The
+//! source of a section page is the file and the anchor of the heading, e.g.
+//! `specs/api.md#data-model`, so its title and source stay the same as long as the heading does,
+//! and links to the anchor reach the section page.
+use anyhow::anyhow;
+
+use crate::{
+    error::Result,
+    heading_anchors::{heading_anchor, HeadingAnchors},
+    template_escaping::{closes, opening_fence},
+};
+
+/// The `split` front matter key, only `h2` for now.
+pub fn parse_split(s: Option<&str>) -> Result<bool> {
+    match s.map(str::trim) {
+        None => Ok(false),
+        Some("h2") => Ok(true),
+        Some(other) => Err(anyhow!(
+            "Failed to parse \"split\" key (should be h2, not \"{}\")",
+            other
+        )),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Section {
+    pub heading: String,
+    pub anchor: String,
+    /// The markdown after the heading, with its headings one level up.
+    pub body: String,
+}
+
+/// The text of an ATX heading of `level`, e.g. "Overview" for `## Overview ##`.
+pub(crate) fn heading_text(line: &str, level: usize) -> Option<&str> {
+    let trimmed = line.trim_start();
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let rest = trimmed.strip_prefix(&"#".repeat(level))?;
+    if !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        return None;
+    }
+    Some(rest.trim().trim_end_matches('#').trim_end())
+}
+
+/// Moves a heading deeper than `##` one level up, so `###` becomes `##`.
+fn promote_heading(line: &str) -> Option<String> {
+    let trimmed = line.trim_start();
+    let level = trimmed.chars().take_while(|c| *c == '#').count();
+    if (3..=6).contains(&level) && heading_text(line, level).is_some() {
+        Some(trimmed[1..].to_string())
+    } else {
+        None
+    }
+}
+
+/// The markdown before the first `##` heading, and the sections starting at every `##` heading
+/// outside of code blocks.
+pub fn split_sections(content: &str) -> (String, Vec<Section>) {
+    let mut intro = String::new();
+    let mut sections: Vec<Section> = Vec::default();
+    let mut anchors = HeadingAnchors::default();
+    let mut fence: Option<&str> = None;
+    for line in content.split_inclusive('\n') {
+        let heading = match fence {
+            Some(open) => {
+                if closes(line, open) {
+                    fence = None;
+                }
+                None
+            }
+            None => {
+                fence = opening_fence(line);
+                heading_text(line, 2)
+            }
+        };
+        if let Some(heading) = heading {
+            let anchor = match anchors.add(heading) {
+                Some(collision) => collision.anchor,
+                None => heading_anchor(heading),
+            };
+            sections.push(Section {
+                heading: String::from(heading),
+                anchor,
+                body: String::new(),
+            });
+            continue;
+        }
+        match sections.last_mut() {
+            Some(section) => match (fence, promote_heading(line)) {
+                (None, Some(promoted)) => section.body.push_str(&promoted),
+                _ => section.body.push_str(line),
+            },
+            None => intro.push_str(line),
+        }
+    }
+    (intro, sections)
+}
+
+/// The markdown of the page of the file: the intro and a list of links to the sections.
+pub fn index_markdown(intro: &str, file_name: &str, sections: &[Section]) -> String {
+    let mut markdown = String::from(intro.trim_end());
+    markdown.push_str("\n\n");
+    for section in sections {
+        markdown.push_str(&format!(
+            "- [{}]({}#{})\n",
+            section.heading, file_name, section.anchor
+        ));
+    }
+    markdown
+}
+
+/// The markdown of the page of the section at `index`, with navigation links above and below.
+pub fn section_markdown(
+    title: &str,
+    file_name: &str,
+    sections: &[Section],
+    index: usize,
+) -> String {
+    let section = &sections[index];
+    let mut links = Vec::default();
+    if let Some(previous) = index.checked_sub(1).map(|i| &sections[i]) {
+        links.push(format!(
+            "[← {}]({}#{})",
+            previous.heading, file_name, previous.anchor
+        ));
+    }
+    links.push(format!("[↑ {}]({})", title, file_name));
+    if let Some(next) = sections.get(index + 1) {
+        links.push(format!(
+            "[{} →]({}#{})",
+            next.heading, file_name, next.anchor
+        ));
+    }
+    let navigation = links.join(" | ");
+    format!(
+        "# {}: {}\n\n{}\n\n{}\n\n{}\n",
+        title,
+        section.heading,
+        navigation,
+        section.body.trim(),
+        navigation
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SPEC: &str = "# API\n\nThe API.\n\n## Overview\n\nIntro\n\n### Goals\n\n```markdown\n## Not a section\n### Not promoted\n```\n\n## Data Model\n\nTables\n\n## Overview\n\nAgain\n";
+
+    #[test]
+    fn it_splits_on_second_level_headings() {
+        let (intro, sections) = split_sections(SPEC);
+
+        assert_eq!(intro, "# API\n\nThe API.\n\n");
+        assert_eq!(
+            sections
+                .iter()
+                .map(|section| (section.heading.as_str(), section.anchor.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("Overview", "overview"),
+                ("Data Model", "data-model"),
+                ("Overview", "overview-1")
+            ]
+        );
+        assert_eq!(
+            sections[0].body,
+            "\nIntro\n\n## Goals\n\n```markdown\n## Not a section\n### Not promoted\n```\n\n"
+        );
+    }
+
+    #[test]
+    fn it_links_sections() {
+        let (intro, sections) = split_sections(SPEC);
+
+        assert_eq!(
+            index_markdown(&intro, "api.md", &sections),
+            "# API\n\nThe API.\n\n- [Overview](api.md#overview)\n- [Data Model](api.md#data-model)\n- [Overview](api.md#overview-1)\n"
+        );
+        assert_eq!(
+            section_markdown("API", "api.md", &sections, 1),
+            "# API: Data Model\n\n[← Overview](api.md#overview) | [↑ API](api.md) | [Overview →](api.md#overview-1)\n\nTables\n\n[← Overview](api.md#overview) | [↑ API](api.md) | [Overview →](api.md#overview-1)\n"
+        );
+    }
+
+    #[test]
+    fn it_only_splits_on_h2() {
+        assert!(parse_split(Some("h2")).unwrap());
+        assert!(!parse_split(None).unwrap());
+        assert!(parse_split(Some("h3")).is_err());
+    }
+}
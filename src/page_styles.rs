@@ -0,0 +1,321 @@
+//! Minimal styling for branded spaces, with whatever styling app the Confluence instance has.
+//!
+//! Confluence storage format has no CSS, so styles go through a macro of a styling app (e.g. the
+//! Adaptavist Content Formatting macros). Which macro differs per instance, so the `styles` key of
+//! `_space.yml` maps the two ways to style a page to storage format templates:
+//!
+//! ```yaml
+//! styles:
+//!   page: '<ac:structured-macro ac:name="style"><ac:plain-text-body><![CDATA[{{ css }}]]></ac:plain-text-body></ac:structured-macro>'
+//!   block: '<ac:structured-macro ac:name="div"><ac:parameter ac:name="style">{{ style }}</ac:parameter><ac:rich-text-body>{{ body }}</ac:rich-text-body></ac:structured-macro>'
+//! ```
+//!
+//! `page` is put at the top of pages with the `style` front matter key, with its value as `css`.
+//! `block` wraps the markdown between a `::: style <css>` line and a `:::` line, with the CSS as
+//! `style` and the rendered markdown as `body`. Without a `block` template, the paragraphs and
+//! headings of such a block get inline `<span style>` instead, which Confluence keeps for simple
+//! styles like colors.
+use anyhow::anyhow;
+use comrak::{
+    nodes::{AstNode, NodeHtmlBlock, NodeValue},
+    Arena,
+};
+use saphyr::Yaml;
+use tera::{Context, Tera};
+
+use crate::{
+    confluence_storage_renderer::escape, diagnostics::Diagnostic, error::Result,
+    helpers::collect_text,
+};
+
+/// Stands in for the body of a block, to split the `block` template into what goes before and
+/// after it.
+const BODY_MARKER: &str = "\u{1}body\u{1}";
+
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct StyleTemplates {
+    /// Storage format for the `style` front matter key, gets `css`.
+    pub page: Option<String>,
+    /// Storage format for `::: style` blocks, gets `style` and `body`.
+    pub block: Option<String>,
+}
+
+fn render(name: &str, template: &str, context: &Context) -> Result<String> {
+    Tera::one_off(template, context, false)
+        .map_err(|err| anyhow!("Invalid template for styles.{}: {:#}", name, err))
+}
+
+impl StyleTemplates {
+    pub fn from_yaml(yaml: &Yaml) -> Result<Self> {
+        let error = |key: &str| {
+            anyhow!(
+                "Failed to parse \"styles.{}\" key (should be a storage format template)",
+                key
+            )
+        };
+        let templates = match yaml {
+            Yaml::BadValue | Yaml::Null => return Ok(StyleTemplates::default()),
+            Yaml::Hash(_) => {
+                let template = |key: &str| match &yaml[key] {
+                    Yaml::BadValue | Yaml::Null => Ok(None),
+                    Yaml::String(template) => Ok(Some(template.clone())),
+                    _ => Err(error(key)),
+                };
+                StyleTemplates {
+                    page: template("page")?,
+                    block: template("block")?,
+                }
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Failed to parse \"styles\" key (should be a map with page and/or block)"
+                ))
+            }
+        };
+        // fail on broken templates when the config is read rather than in the first styled page
+        templates.page_markup("")?;
+        templates.block_markup("")?;
+        Ok(templates)
+    }
+
+    fn page_markup(&self, css: &str) -> Result<Option<String>> {
+        let Some(template) = &self.page else {
+            return Ok(None);
+        };
+        let mut context = Context::new();
+        context.insert("css", css);
+        render("page", template, &context).map(Some)
+    }
+
+    /// The markup before and after the body of a `::: style` block.
+    fn block_markup(&self, style: &str) -> Result<Option<(String, String)>> {
+        let Some(template) = &self.block else {
+            return Ok(None);
+        };
+        let mut context = Context::new();
+        context.insert("style", &escape_css(style));
+        context.insert("body", BODY_MARKER);
+        let markup = render("block", template, &context)?;
+        let (before, after) = markup
+            .split_once(BODY_MARKER)
+            .ok_or(anyhow!("The styles.block template has no {{{{ body }}}}"))?;
+        Ok(Some((before.to_string(), after.to_string())))
+    }
+}
+
+fn html_block<'a>(arena: &'a Arena<AstNode<'a>>, literal: String) -> &'a AstNode<'a> {
+    arena.alloc(AstNode::from(NodeValue::HtmlBlock(NodeHtmlBlock {
+        block_type: 0,
+        literal,
+    })))
+}
+
+fn html_inline<'a>(arena: &'a Arena<AstNode<'a>>, literal: String) -> &'a AstNode<'a> {
+    arena.alloc(AstNode::from(NodeValue::HtmlInline(literal)))
+}
+
+enum Fence {
+    Open(String),
+    Close,
+}
+
+/// A paragraph that's nothing but `::: style <css>` or `:::`.
+fn fence<'a>(node: &'a AstNode<'a>) -> Option<Fence> {
+    if !matches!(node.data.borrow().value, NodeValue::Paragraph) {
+        return None;
+    }
+    let mut text = Vec::default();
+    collect_text(node, &mut text);
+    let text = String::from_utf8_lossy(&text);
+    let rest = text.trim().strip_prefix(":::")?.trim();
+    if rest.is_empty() {
+        return Some(Fence::Close);
+    }
+    let css = rest.strip_prefix("style")?;
+    css.starts_with(char::is_whitespace)
+        .then(|| Fence::Open(css.trim().to_string()))
+}
+
+/// CSS as text of storage format, so a `<` or `&` in it can't end the attribute or add markup.
+fn escape_css(css: &str) -> String {
+    let mut escaped = Vec::with_capacity(css.len());
+    escape(&mut escaped, css.as_bytes()).expect("writing to a Vec doesn't fail");
+    String::from_utf8_lossy(&escaped).to_string()
+}
+
+/// Wraps the inline content of the paragraphs and headings in `node` in a styled span.
+fn style_spans<'a>(arena: &'a Arena<AstNode<'a>>, node: &'a AstNode<'a>, css: &str) {
+    let targets: Vec<&AstNode> = node
+        .descendants()
+        .filter(|node| {
+            matches!(
+                node.data.borrow().value,
+                NodeValue::Paragraph | NodeValue::Heading(_)
+            )
+        })
+        .collect();
+    for target in targets {
+        target.prepend(html_inline(
+            arena,
+            format!("<span style=\"{}\">", escape_css(css)),
+        ));
+        target.append(html_inline(arena, String::from("</span>")));
+    }
+}
+
+/// Applies the `style` front matter key and the `::: style` blocks of a page, returning the
+/// problems with them.
+pub fn apply_styles<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    root: &'a AstNode<'a>,
+    page_css: Option<&str>,
+    templates: &StyleTemplates,
+    content: &str,
+    body_line: usize,
+) -> Result<Vec<Diagnostic>> {
+    let mut diagnostics = Vec::default();
+    let at = |message: &str, node: &'a AstNode<'a>, label: &str| {
+        Diagnostic::at(
+            message,
+            node.data.borrow().sourcepos,
+            content,
+            body_line,
+            label,
+        )
+    };
+
+    let containers: Vec<&AstNode> = root
+        .descendants()
+        .filter(|node| node.children().any(|child| fence(child).is_some()))
+        .collect();
+    for container in containers {
+        let mut open: Option<(&AstNode, String)> = None;
+        for node in container.children().collect::<Vec<_>>() {
+            match (fence(node), &open) {
+                (Some(Fence::Open(_)), Some(_)) => diagnostics.push(at(
+                    "::: style blocks can't be nested, close the one before with :::",
+                    node,
+                    "this block",
+                )),
+                (Some(Fence::Open(css)), None) => open = Some((node, css)),
+                (Some(Fence::Close), Some((start, css))) => {
+                    match templates.block_markup(css)? {
+                        Some((before, after)) => {
+                            start.insert_after(html_block(arena, before));
+                            node.insert_after(html_block(arena, after));
+                        }
+                        None => {
+                            let mut styled = start.next_sibling();
+                            while let Some(sibling) = styled.filter(|s| !s.same_node(node)) {
+                                style_spans(arena, sibling, css);
+                                styled = sibling.next_sibling();
+                            }
+                        }
+                    }
+                    start.detach();
+                    node.detach();
+                    open = None;
+                }
+                (Some(Fence::Close), None) => diagnostics.push(at(
+                    "::: without a ::: style block to close",
+                    node,
+                    "this line",
+                )),
+                (None, _) => {}
+            }
+        }
+        if let Some((start, _)) = open {
+            diagnostics.push(at(
+                "::: style block is never closed, end it with a ::: line",
+                start,
+                "this block",
+            ));
+        }
+    }
+
+    if let Some(css) = page_css {
+        match templates.page_markup(css)? {
+            Some(markup) => root.prepend(html_block(arena, markup)),
+            None => diagnostics.push(Diagnostic::new(
+                "the style front matter key needs a styles.page template in _space.yml",
+            )),
+        }
+    }
+
+    Ok(diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::TestResult, link_generator::LinkGenerator, space_config::SpaceConfig,
+        test_helpers::markdown_page_from_str_with_config,
+    };
+
+    #[test]
+    fn it_styles_pages_and_blocks_with_the_configured_macros() -> TestResult {
+        let space_config = SpaceConfig::from_yaml_str(
+            r#"styles:
+  page: '<ac:structured-macro ac:name="style"><ac:plain-text-body><![CDATA[{{ css }}]]></ac:plain-text-body></ac:structured-macro>'
+  block: '<ac:structured-macro ac:name="div"><ac:parameter ac:name="style">{{ style }}</ac:parameter><ac:rich-text-body>{{ body }}</ac:rich-text-body></ac:structured-macro>'
+"#,
+        )?;
+        let page = markdown_page_from_str_with_config(
+            "page.md",
+            "---\nstyle: 'h2 { color: #0052CC; }'\n---\n# Title\n\n::: style border: 1px solid\n\nBoxed **text**.\n\n:::\n",
+            &space_config,
+        )?;
+
+        assert_eq!(
+            page.render(&LinkGenerator::default_test())?.content,
+            "<ac:structured-macro ac:name=\"style\"><ac:plain-text-body><![CDATA[h2 { color: #0052CC; }]]></ac:plain-text-body></ac:structured-macro>\n<ac:structured-macro ac:name=\"div\"><ac:parameter ac:name=\"style\">border: 1px solid</ac:parameter><ac:rich-text-body>\n<p>Boxed <strong>text</strong>.</p>\n</ac:rich-text-body></ac:structured-macro>\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_falls_back_to_styled_spans() -> TestResult {
+        let page = markdown_page_from_str_with_config(
+            "page.md",
+            "# Title\n\n::: style color: red\n\n## Careful\n\n- Hot\n\n:::\n\nNormal\n",
+            &SpaceConfig::default(),
+        )?;
+
+        assert_eq!(
+            page.render(&LinkGenerator::default_test())?.content,
+            "<h2><span style=\"color: red\">Careful</span></h2>\n<ul>\n<li><span style=\"color: red\">Hot</span></li>\n</ul>\n<p>Normal</p>\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_escapes_the_css_of_styled_spans() -> TestResult {
+        let page = markdown_page_from_str_with_config(
+            "page.md",
+            "# Title\n\n::: style color: red\">&lt;b&gt;&x\n\nHot\n\n:::\n",
+            &SpaceConfig::default(),
+        )?;
+
+        assert_eq!(
+            page.render(&LinkGenerator::default_test())?.content,
+            "<p><span style=\"color: red&quot;&gt;&lt;b&gt;&amp;x\">Hot</span></p>\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_unclosed_blocks_and_unconfigured_page_styles() {
+        let result = markdown_page_from_str_with_config(
+            "page.md",
+            "---\nstyle: 'p { margin: 0; }'\n---\n# Title\n\n::: style color: red\n\nText\n",
+            &SpaceConfig::default(),
+        );
+
+        let error = format!("{:#}", result.err().unwrap());
+        assert!(error.contains("never closed"), "{}", error);
+        assert!(error.contains("styles.page"), "{}", error);
+    }
+}
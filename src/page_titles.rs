@@ -0,0 +1,184 @@
+//! Retitling pages whose title was changed in the markdown.
+//!
+//! Titles are unique within a space, so when pages trade titles (or one page takes the old title
+//! of another) updating them one by one fails on the first of them. Those pages are first renamed
+//! to a temporary title, which frees up the titles before the content of the pages is synced.
+use std::collections::BTreeMap;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    confluence_page::ConfluenceNode,
+    confluence_space::ConfluenceSpace,
+    console::Status,
+    error::{ConfluenceError, Result},
+    sync_operation::SyncOperation,
+};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Rename {
+    pub id: String,
+    pub from: String,
+    pub to: String,
+}
+
+fn same_title(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
+/// The temporary renames needed before pages can get the titles in `desired` (page id to title):
+/// every retitled page whose current title is wanted by another page.
+pub fn plan_temporary_renames(
+    nodes: &[ConfluenceNode],
+    desired: &BTreeMap<String, String>,
+) -> Vec<Rename> {
+    let retitled: Vec<&ConfluenceNode> = nodes
+        .iter()
+        .filter(|node| {
+            desired
+                .get(&node.id)
+                .is_some_and(|title| title != &node.title)
+        })
+        .collect();
+    retitled
+        .iter()
+        .filter(|node| {
+            desired
+                .iter()
+                .any(|(id, title)| id != &node.id && same_title(title, &node.title))
+        })
+        .map(|node| Rename {
+            id: node.id.clone(),
+            from: node.title.clone(),
+            to: temporary_title(&node.title, &node.id),
+        })
+        .collect()
+}
+
+fn temporary_title(title: &str, id: &str) -> String {
+    format!("{} (renaming {})", title, id)
+}
+
+/// Whether the page still has the temporary title of a rename, because the sync that renamed it
+/// stopped before the page got its new title.
+pub fn has_temporary_title(node: &ConfluenceNode) -> bool {
+    node.title.ends_with(&temporary_title("", &node.id))
+}
+
+pub fn execute_renames(
+    confluence_client: &ConfluenceClient,
+    space: &mut ConfluenceSpace,
+    renames: &[Rename],
+) -> Result<()> {
+    for rename in renames {
+        let op = SyncOperation::start(
+            format!("[{}] temporarily renamed to \"{}\"", rename.from, rename.to),
+            true,
+        );
+        let response = confluence_client.update_page_title(&rename.id, &rename.to)?;
+        if !response.status().is_success() {
+            op.end(Status::Error);
+            return Err(ConfluenceError::failed_request(response));
+        }
+        space.set_title(&rename.id, &rename.to);
+        op.end(Status::Updated);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use mockito::Matcher;
+    use serde_json::json;
+
+    use crate::{
+        confluence_page::{ConfluenceNodeType, ConfluencePageData},
+        error::TestResult,
+        responses::{ContentStatus, Version},
+    };
+
+    use super::*;
+
+    fn page(id: &str, title: &str) -> ConfluenceNode {
+        ConfluenceNode {
+            id: String::from(id),
+            title: String::from(title),
+            parent_id: Some(String::from("1")),
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: Version {
+                    message: String::default(),
+                    number: 3,
+                },
+                path: Some(PathBuf::from("page.md")),
+                status: ContentStatus::Current,
+            }),
+        }
+    }
+
+    fn desired(titles: &[(&str, &str)]) -> BTreeMap<String, String> {
+        titles
+            .iter()
+            .map(|(id, title)| (String::from(*id), String::from(*title)))
+            .collect()
+    }
+
+    #[test]
+    fn it_renames_nothing_without_conflicts() {
+        let nodes = vec![page("2", "Setup"), page("3", "Usage")];
+
+        assert_eq!(
+            plan_temporary_renames(&nodes, &desired(&[("2", "Installation"), ("3", "Usage")])),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn it_frees_titles_taken_by_other_pages() {
+        let nodes = vec![page("2", "Setup"), page("3", "Usage")];
+
+        assert_eq!(
+            plan_temporary_renames(&nodes, &desired(&[("2", "Usage"), ("3", "Setup")])),
+            vec![
+                Rename {
+                    id: String::from("2"),
+                    from: String::from("Setup"),
+                    to: String::from("Setup (renaming 2)")
+                },
+                Rename {
+                    id: String::from("3"),
+                    from: String::from("Usage"),
+                    to: String::from("Usage (renaming 3)")
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn it_renames_with_the_title_endpoint() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let rename = server
+            .mock("PUT", "/wiki/api/v2/pages/2/title")
+            .match_body(Matcher::Json(
+                json!({"status": "current", "title": "Setup (renaming 2)"}),
+            ))
+            .with_status(200)
+            .create();
+        let nodes = vec![page("2", "Setup"), page("3", "Getting Started")];
+        let mut space = ConfluenceSpace::new_test("99", "1", nodes.clone());
+
+        let renames = plan_temporary_renames(
+            &nodes,
+            &desired(&[("2", "Getting Started"), ("3", "Setup")]),
+        );
+        execute_renames(&client, &mut space, &renames[..1])?;
+
+        rename.assert();
+        let node = space.get_existing_node("2").unwrap();
+        assert_eq!(node.title, "Setup (renaming 2)");
+        assert_eq!(node.page_data().unwrap().version.number, 4);
+
+        Ok(())
+    }
+}
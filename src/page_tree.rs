@@ -0,0 +1,482 @@
+//! The `tree` subcommand, which prints the page hierarchy of the markdown space, and optionally
+//! the one in Confluence, with the id and sync status of every page.
+use std::{fmt::Display, path::Path, sync::Arc};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    conflicts::is_conflict,
+    confluence_client::ConfluenceClient,
+    confluence_page::{ConfluenceNode, ConfluenceNodeType, ConfluencePageData},
+    confluence_space::ConfluenceSpace,
+    console::set_quiet,
+    link_generator::LinkGenerator,
+    markdown_page::MarkdownPage,
+    markdown_space::MarkdownSpace,
+    mentions::CachedUsers,
+    publish_schedule::PublishSchedule,
+    responses::ContentStatus,
+    sync::{check_homepage_exists, load_space, LoadedSpace},
+    Args, Result,
+};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum NodeStatus {
+    /// Not created in Confluence yet.
+    New,
+    UpToDate,
+    /// The title or content will be updated.
+    Changed,
+    /// Edited in Confluence since the last sync, the next sync overwrites that.
+    RemotelyModified,
+    /// Never synced by marked-space, but matched by its title (like the homepage of a new space),
+    /// the next sync takes it over.
+    Adopted,
+    /// Will be moved to a different parent.
+    Moved,
+    /// Synced before but its file is gone, it will be archived.
+    Orphan,
+    /// Archived before and its file is back, it will be restored.
+    Restored,
+    Archived,
+    /// Not synced by marked-space.
+    Unmanaged,
+    /// Synced by marked-space from a file that still exists.
+    Synced,
+    /// Held back until its `publish_after` time.
+    Scheduled,
+    /// Never published, with `publish: false`.
+    Unpublished,
+}
+
+impl Display for NodeStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            NodeStatus::New => "new",
+            NodeStatus::UpToDate => "up to date",
+            NodeStatus::Changed => "changed",
+            NodeStatus::RemotelyModified => "changed in Confluence",
+            NodeStatus::Adopted => "adopted",
+            NodeStatus::Moved => "moved",
+            NodeStatus::Orphan => "orphan",
+            NodeStatus::Restored => "restored",
+            NodeStatus::Archived => "archived",
+            NodeStatus::Unmanaged => "unmanaged",
+            NodeStatus::Synced => "synced",
+            NodeStatus::Scheduled => "scheduled",
+            NodeStatus::Unpublished => "not published",
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct TreeNode {
+    pub title: String,
+    pub id: Option<String>,
+    pub status: NodeStatus,
+    /// The markdown file of the page, if any.
+    pub source: Option<String>,
+    /// When a scheduled page is published.
+    pub publish_after: Option<DateTime<Utc>>,
+    pub children: Vec<TreeNode>,
+}
+
+impl TreeNode {
+    fn label(&self) -> String {
+        let mut label = self.title.clone();
+        if let Some(id) = &self.id {
+            label.push_str(&format!(" ({})", id));
+        }
+        if let Some(source) = &self.source {
+            label.push_str(&format!(" {}", source));
+        }
+        match self.publish_after {
+            Some(publish_after) => label.push_str(&format!(
+                " [{} until {}]",
+                self.status,
+                publish_after.format("%Y-%m-%d %H:%M UTC")
+            )),
+            None => label.push_str(&format!(" [{}]", self.status)),
+        }
+        label
+    }
+
+    fn descendants(&self) -> Vec<&TreeNode> {
+        std::iter::once(self)
+            .chain(self.children.iter().flat_map(TreeNode::descendants))
+            .collect()
+    }
+}
+
+/// Renders trees with box drawing characters, one page per line.
+pub fn render_ascii(roots: &[TreeNode]) -> String {
+    fn render(node: &TreeNode, prefix: &str, output: &mut String) {
+        for (i, child) in node.children.iter().enumerate() {
+            let last = i == node.children.len() - 1;
+            output.push_str(&format!(
+                "{}{}{}\n",
+                prefix,
+                if last { "└── " } else { "├── " },
+                child.label()
+            ));
+            let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            render(child, &prefix, output);
+        }
+    }
+    let mut output = String::default();
+    for root in roots {
+        output.push_str(&format!("{}\n", root.label()));
+        render(root, "", &mut output);
+    }
+    output
+}
+
+/// The id of the page of a markdown file and how it differs from the page in Confluence. Pages the
+/// `schedule` holds back are scheduled or not published, whatever their page looks like.
+pub(crate) fn local_status(
+    markdown_page: &MarkdownPage,
+    link_generator: &LinkGenerator,
+    space: &ConfluenceSpace,
+    schedule: &PublishSchedule,
+) -> Result<(Option<String>, NodeStatus)> {
+    let source = Path::new(&markdown_page.source);
+    if !schedule.is_published(&markdown_page.source) {
+        return Ok((link_generator.get_page_id(source), NodeStatus::Unpublished));
+    }
+    if schedule.publish_after(&markdown_page.source).is_some() {
+        return Ok((link_generator.get_page_id(source), NodeStatus::Scheduled));
+    }
+    let Some(id) = link_generator.get_page_id(source) else {
+        return Ok((None, NodeStatus::New));
+    };
+    let Some(node) = space.get_existing_node(&id) else {
+        return Ok((Some(id), NodeStatus::New));
+    };
+    let previous = space
+        .page_map
+        .pages
+        .get(&markdown_page.source.replace('\\', "/"))
+        .filter(|entry| entry.id == id);
+    let status = if node
+        .page_data()
+        .is_some_and(|page_data| is_conflict(page_data, previous))
+    {
+        NodeStatus::RemotelyModified
+    } else if node
+        .page_data()
+        .is_some_and(|page_data| !page_data.is_managed())
+    {
+        NodeStatus::Adopted
+    } else if node
+        .page_data()
+        .is_some_and(|page_data| matches!(page_data.status, ContentStatus::Archived))
+    {
+        NodeStatus::Restored
+    } else if link_generator.get_parent_id(source) != node.parent_id {
+        NodeStatus::Moved
+    } else {
+        match node.page_data() {
+            Some(page_data) => {
+                let rendered = markdown_page.render(link_generator)?;
+                if rendered.title != node.title
+                    || !ConfluencePageData::extract_checksum(&page_data.version)
+                        .is_some_and(|checksum| rendered.has_checksum(&checksum))
+                {
+                    NodeStatus::Changed
+                } else {
+                    NodeStatus::UpToDate
+                }
+            }
+            None => NodeStatus::UpToDate,
+        }
+    };
+    Ok((Some(id), status))
+}
+
+/// The hierarchy of the markdown files, with pages at the root of the space under `index.md`.
+pub fn local_tree(
+    markdown_pages: &[MarkdownPage],
+    link_generator: &LinkGenerator,
+    space: &ConfluenceSpace,
+    schedule: &PublishSchedule,
+) -> Result<Vec<TreeNode>> {
+    fn parent_source(source: &str, link_generator: &LinkGenerator) -> Option<String> {
+        match link_generator.get_parent_file(Path::new(source)) {
+            Some(parent) => Some(parent.to_string_lossy().replace('\\', "/")),
+            None if source != "index.md" => Some(String::from("index.md")),
+            None => None,
+        }
+    }
+    fn children(
+        parent: Option<&str>,
+        markdown_pages: &[MarkdownPage],
+        link_generator: &LinkGenerator,
+        space: &ConfluenceSpace,
+        schedule: &PublishSchedule,
+    ) -> Result<Vec<TreeNode>> {
+        let has_index = markdown_pages.iter().any(|page| page.source == "index.md");
+        let mut nodes = markdown_pages
+            .iter()
+            .filter(|page| {
+                let page_parent = parent_source(&page.source, link_generator)
+                    .filter(|p| p != "index.md" || has_index);
+                page_parent.as_deref() == parent
+            })
+            .map(|page| {
+                let (id, status) = local_status(page, link_generator, space, schedule)?;
+                Ok(TreeNode {
+                    title: page.title.clone(),
+                    id,
+                    status,
+                    source: Some(page.source.replace('\\', "/")),
+                    publish_after: schedule.publish_after(&page.source),
+                    children: children(
+                        Some(&page.source),
+                        markdown_pages,
+                        link_generator,
+                        space,
+                        schedule,
+                    )?,
+                })
+            })
+            .collect::<Result<Vec<TreeNode>>>()?;
+        nodes.sort_by(|a, b| a.title.cmp(&b.title));
+        Ok(nodes)
+    }
+    children(None, markdown_pages, link_generator, space, schedule)
+}
+
+fn remote_status(node: &ConfluenceNode, link_generator: &LinkGenerator) -> NodeStatus {
+    match &node.data {
+        ConfluenceNodeType::Page(page_data) => {
+            if matches!(page_data.status, ContentStatus::Archived) {
+                NodeStatus::Archived
+            } else if !page_data.is_managed() {
+                NodeStatus::Unmanaged
+            } else if link_generator.is_orphaned(node, page_data) {
+                NodeStatus::Orphan
+            } else {
+                NodeStatus::Synced
+            }
+        }
+        ConfluenceNodeType::Folder(_) => NodeStatus::Synced,
+    }
+}
+
+/// The pages under the homepage in Confluence.
+pub fn remote_tree(space: &ConfluenceSpace, link_generator: &LinkGenerator) -> Vec<TreeNode> {
+    fn node(id: &str, space: &ConfluenceSpace, link_generator: &LinkGenerator) -> Option<TreeNode> {
+        let existing = space.get_existing_node(id)?;
+        let mut children: Vec<TreeNode> = space
+            .nodes()
+            .iter()
+            .filter(|child| child.parent_id.as_deref() == Some(id))
+            .filter_map(|child| node(&child.id, space, link_generator))
+            .collect();
+        children.sort_by(|a, b| a.title.cmp(&b.title));
+        Some(TreeNode {
+            title: existing.title.clone(),
+            id: Some(existing.id.clone()),
+            status: remote_status(&existing, link_generator),
+            source: existing
+                .page_data()
+                .and_then(|page_data| ConfluencePageData::extract_path(&page_data.version))
+                .map(|path| path.to_string_lossy().replace('\\', "/")),
+            publish_after: None,
+            children,
+        })
+    }
+    node(&space.homepage_id, space, link_generator)
+        .into_iter()
+        .collect()
+}
+
+/// One line per page that differs: `+` new, `>` moved, `~` changed, `!` changed in Confluence,
+/// `*` adopted, `^` restored from the archive and `-` to be archived.
+pub fn tree_diff(local: &[TreeNode], remote: &[TreeNode]) -> String {
+    let mut lines = Vec::<String>::default();
+    for node in local.iter().flat_map(TreeNode::descendants) {
+        let marker = match node.status {
+            NodeStatus::New => "+",
+            NodeStatus::Moved => ">",
+            NodeStatus::Changed => "~",
+            NodeStatus::RemotelyModified => "!",
+            NodeStatus::Adopted => "*",
+            NodeStatus::Restored => "^",
+            _ => continue,
+        };
+        lines.push(format!("{} {}", marker, node.label()));
+    }
+    for node in remote.iter().flat_map(TreeNode::descendants) {
+        if node.status == NodeStatus::Orphan {
+            lines.push(format!("- {}", node.label()));
+        }
+    }
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+pub fn tree(
+    confluence_client: ConfluenceClient,
+    markdown_space: &mut MarkdownSpace,
+    args: Args,
+    remote: bool,
+    diff: bool,
+) -> Result<()> {
+    set_quiet(true);
+    let space_key = markdown_space.key.clone();
+    let LoadedSpace {
+        markdown_pages,
+        mut space,
+        mut link_generator,
+        schedule,
+        ..
+    } = load_space(
+        &confluence_client,
+        markdown_space,
+        &args,
+        Arc::new(CachedUsers::new(confluence_client.clone())),
+    )?;
+
+    space.read_all_pages(&confluence_client)?;
+    check_homepage_exists(&space, &space_key)?;
+    space.link_pages(&mut link_generator)?;
+
+    let local = local_tree(&markdown_pages, &link_generator, &space, &schedule)?;
+    let remote_nodes = remote_tree(&space, &link_generator);
+    println!("Local:\n{}", render_ascii(&local));
+    if remote {
+        println!("Confluence:\n{}", render_ascii(&remote_nodes));
+    }
+    if diff {
+        println!("Differences:\n{}", tree_diff(&local, &remote_nodes));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{
+        confluence_page::ConfluencePageData,
+        error::TestResult,
+        responses::{ContentStatus, Version},
+        test_helpers::markdown_page_from_str,
+    };
+
+    use super::*;
+
+    fn page(id: &str, title: &str, parent: Option<&str>, message: &str) -> ConfluenceNode {
+        ConfluenceNode {
+            id: String::from(id),
+            title: String::from(title),
+            parent_id: parent.map(String::from),
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: Version {
+                    message: String::from(message),
+                    number: 1,
+                },
+                path: None,
+                status: ContentStatus::Current,
+            }),
+        }
+    }
+
+    #[test]
+    fn it_renders_ascii_trees() {
+        let leaf = |title: &str| TreeNode {
+            title: String::from(title),
+            id: None,
+            status: NodeStatus::New,
+            source: None,
+            publish_after: None,
+            children: Vec::default(),
+        };
+        let root = TreeNode {
+            title: String::from("Home"),
+            id: Some(String::from("1")),
+            status: NodeStatus::UpToDate,
+            source: Some(String::from("index.md")),
+            publish_after: None,
+            children: vec![
+                TreeNode {
+                    children: vec![leaf("Setup")],
+                    ..leaf("Guides")
+                },
+                leaf("FAQ"),
+            ],
+        };
+
+        assert_eq!(
+            render_ascii(&[root]),
+            "Home (1) index.md [up to date]\n├── Guides [new]\n│   └── Setup [new]\n└── FAQ [new]\n"
+        );
+    }
+
+    #[test]
+    fn it_compares_local_and_remote_trees() -> TestResult {
+        let pages = vec![
+            markdown_page_from_str("index.md", "# Home\n")?,
+            markdown_page_from_str("guides/index.md", "# Guides\n")?,
+            markdown_page_from_str("guides/setup.md", "# Setup\n")?,
+            markdown_page_from_str("faq.md", "# FAQ\n")?,
+            markdown_page_from_str(
+                "launch.md",
+                "---\npublish_after: 2099-01-01T09:00Z\n---\n# Launch\n",
+            )?,
+            markdown_page_from_str("draft.md", "---\npublish: false\n---\n# Draft\n")?,
+        ];
+        let schedule = PublishSchedule::new(&pages, Utc::now());
+        let mut link_generator = LinkGenerator::default_test();
+        for page in &pages {
+            link_generator.register_markdown_page(page)?;
+        }
+        let space = ConfluenceSpace::new_test(
+            "1",
+            "999",
+            vec![
+                page("999", "Home", None, ""),
+                page(
+                    "10",
+                    "Guides",
+                    Some("999"),
+                    "updated by markedspace: source=guides/index.md; checksum=OLD",
+                ),
+                // should be under Guides
+                page(
+                    "11",
+                    "Setup",
+                    Some("999"),
+                    "updated by markedspace: source=guides/setup.md; checksum=OLD",
+                ),
+                page(
+                    "12",
+                    "Old Page",
+                    Some("999"),
+                    "updated by markedspace: source=old.md; checksum=OLD",
+                ),
+                page("13", "Notes", Some("999"), "edited by hand"),
+            ],
+        );
+        for node in space.nodes() {
+            link_generator.register_confluence_node(node);
+        }
+
+        let local = local_tree(&pages, &link_generator, &space, &schedule)?;
+        let remote = remote_tree(&space, &link_generator);
+
+        assert_eq!(
+            render_ascii(&local),
+            "Home (999) index.md [adopted]\n├── Draft draft.md [not published]\n├── FAQ faq.md [new]\n├── Guides (10) guides/index.md [changed]\n│   └── Setup (11) guides/setup.md [moved]\n└── Launch launch.md [scheduled until 2099-01-01 09:00 UTC]\n"
+        );
+        assert_eq!(
+            render_ascii(&remote),
+            "Home (999) [unmanaged]\n├── Guides (10) guides/index.md [synced]\n├── Notes (13) [unmanaged]\n├── Old Page (12) old.md [orphan]\n└── Setup (11) guides/setup.md [synced]\n"
+        );
+        assert_eq!(
+            tree_diff(&local, &remote),
+            "* Home (999) index.md [adopted]\n+ FAQ faq.md [new]\n~ Guides (10) guides/index.md [changed]\n> Setup (11) guides/setup.md [moved]\n- Old Page (12) old.md [orphan]\n"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+
+use anyhow::anyhow;
+use saphyr::Yaml;
+
+use crate::{
+    error::Result,
+    frontmatter::FrontMatter,
+    warnings::{Warning, WarningCode},
+};
+
+/// A kind of page (runbook, adr, ...) as configured under the `types` key in `_space.yml`.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct PageType {
+    /// Front matter fields pages of this type must set, `metadata.` paths included.
+    pub required_fields: Vec<String>,
+    /// Template under `_tera` the body of the page is rendered into, as `page.body`.
+    pub template: Option<String>,
+}
+
+impl PageType {
+    fn from_yaml(yaml: &Yaml, name: &str) -> Result<Self> {
+        let required_fields = match &yaml["required_fields"] {
+            Yaml::BadValue | Yaml::Null => Vec::default(),
+            Yaml::Array(fields) => fields
+                .iter()
+                .map(|field| {
+                    field.as_str().map(String::from).ok_or(anyhow!(
+                        "Failed to parse \"types.{}.required_fields\" key (should be a list of strings)",
+                        name
+                    ))
+                })
+                .collect::<Result<Vec<String>>>()?,
+            _ => {
+                return Err(anyhow!(
+                    "Failed to parse \"types.{}.required_fields\" key (should be a list of strings)",
+                    name
+                ))
+            }
+        };
+        let template = match &yaml["template"] {
+            Yaml::BadValue | Yaml::Null => None,
+            Yaml::String(template) => Some(template.clone()),
+            _ => {
+                return Err(anyhow!(
+                    "Failed to parse \"types.{}.template\" key (should be a string)",
+                    name
+                ))
+            }
+        };
+        Ok(PageType {
+            required_fields,
+            template,
+        })
+    }
+}
+
+pub fn parse_page_types(yaml: &Yaml) -> Result<BTreeMap<String, PageType>> {
+    match yaml {
+        Yaml::BadValue | Yaml::Null => Ok(BTreeMap::default()),
+        Yaml::Hash(hash) => hash
+            .iter()
+            .map(|(name, page_type)| {
+                let name = name
+                    .as_str()
+                    .ok_or(anyhow!("Failed to parse \"types\" key (should be a map)"))?;
+                let page_type = match page_type {
+                    Yaml::Null => PageType::default(),
+                    Yaml::Hash(_) => PageType::from_yaml(page_type, name)?,
+                    _ => {
+                        return Err(anyhow!(
+                            "Failed to parse \"types.{}\" key (should be a map)",
+                            name
+                        ))
+                    }
+                };
+                Ok((String::from(name), page_type))
+            })
+            .collect(),
+        _ => Err(anyhow!("Failed to parse \"types\" key (should be a map)")),
+    }
+}
+
+/// Checks the type of a page and its required fields, returning the warnings.
+///
+/// Any type is accepted when no types are configured.
+pub fn check_page_type(types: &BTreeMap<String, PageType>, fm: &FrontMatter) -> Vec<Warning> {
+    let Some(name) = &fm.page_type else {
+        return Vec::default();
+    };
+    if types.is_empty() {
+        return Vec::default();
+    }
+    let Some(page_type) = types.get(name) else {
+        return vec![Warning::new(
+            WarningCode::UnknownPageType,
+            format!(
+                "unknown page type \"{}\" (expected one of {})",
+                name,
+                types.keys().cloned().collect::<Vec<String>>().join(", ")
+            ),
+        )];
+    };
+    page_type
+        .required_fields
+        .iter()
+        .filter(|field| !fm.has_field(field))
+        .map(|field| {
+            Warning::new(
+                WarningCode::MissingRequiredField,
+                format!("{} page is missing required field \"{}\"", name, field),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::TestResult;
+
+    use super::*;
+
+    fn types_from_str(s: &str) -> Result<BTreeMap<String, PageType>> {
+        parse_page_types(&Yaml::load_from_str(s)?[0])
+    }
+
+    #[test]
+    fn it_parses_page_types() -> TestResult {
+        let types = types_from_str(
+            "runbook:\n  required_fields: [owners, metadata.service]\n  template: runbook.md\nhowto:\n",
+        )?;
+
+        assert_eq!(
+            types.get("runbook"),
+            Some(&PageType {
+                required_fields: vec![String::from("owners"), String::from("metadata.service")],
+                template: Some(String::from("runbook.md")),
+            })
+        );
+        assert_eq!(types.get("howto"), Some(&PageType::default()));
+        assert_eq!(
+            types_from_str("runbook:\n  required_fields: owners\n")
+                .unwrap_err()
+                .to_string(),
+            "Failed to parse \"types.runbook.required_fields\" key (should be a list of strings)"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_checks_required_fields() -> TestResult {
+        let types = types_from_str("runbook:\n  required_fields: [owners, metadata.service]\n")?;
+        let (fm, _content) = FrontMatter::from_str(
+            "---\ntype: runbook\nowners: [Jane Doe]\nmetadata:\n  team: core\n---\n# title",
+        )?;
+
+        assert_eq!(
+            check_page_type(&types, &fm),
+            vec![Warning::new(
+                WarningCode::MissingRequiredField,
+                "runbook page is missing required field \"metadata.service\""
+            )]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_checks_the_type_is_known() -> TestResult {
+        let types = types_from_str("runbook:\nadr:\n")?;
+        let (fm, _content) = FrontMatter::from_str("---\ntype: guide\n---\n# title")?;
+
+        assert_eq!(
+            check_page_type(&types, &fm),
+            vec![Warning::new(
+                WarningCode::UnknownPageType,
+                "unknown page type \"guide\" (expected one of adr, runbook)"
+            )]
+        );
+        assert!(check_page_type(&BTreeMap::default(), &fm).is_empty());
+
+        Ok(())
+    }
+}
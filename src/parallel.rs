@@ -0,0 +1,109 @@
+//! Spreading the local work on the pages (templating, parsing and rendering) over the cores, on a
+//! rayon pool with `--jobs` threads.
+//!
+//! Results come back in the order of the pages, whichever thread finished first, so what's done
+//! with them afterwards (registering links, reporting errors) is the same on every run.
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    OnceLock,
+};
+
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
+
+use crate::{
+    console::{inherit_prefix, prefix},
+    sync_stats::{self, SyncStats},
+};
+
+/// 0 is a thread per core.
+static JOBS: AtomicUsize = AtomicUsize::new(0);
+
+static POOL: OnceLock<ThreadPool> = OnceLock::new();
+
+/// The number of threads for the pages, `--jobs`. Only has an effect before the first pages are
+/// mapped, the pool is made then.
+pub fn set_jobs(jobs: usize) {
+    JOBS.store(jobs, Ordering::Relaxed);
+}
+
+fn pool() -> &'static ThreadPool {
+    POOL.get_or_init(|| {
+        ThreadPoolBuilder::new()
+            .num_threads(JOBS.load(Ordering::Relaxed))
+            .thread_name(|index| format!("pages-{}", index))
+            .build()
+            .expect("Failed to start the threads for the pages")
+    })
+}
+
+/// How many pages to render at once when they're used one after the other, enough to keep every
+/// thread busy.
+pub fn render_batch_size() -> usize {
+    pool().current_num_threads() * 8
+}
+
+/// Maps the items on as many threads as there are jobs, with a `state` per thread made by `init`.
+pub fn map_with<T, S, R>(
+    items: &[T],
+    init: impl Fn() -> S + Sync + Send,
+    f: impl Fn(&mut S, &T) -> R + Sync + Send,
+) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+{
+    let pool = pool();
+    if pool.current_num_threads() <= 1 || items.len() <= 1 {
+        let mut state = init();
+        return items.iter().map(|item| f(&mut state, item)).collect();
+    }
+    let console_prefix = prefix();
+    let results = pool.install(|| {
+        items
+            .par_iter()
+            .map_init(
+                || {
+                    inherit_prefix(console_prefix.clone());
+                    init()
+                },
+                |state, item| f(state, item),
+            )
+            .collect()
+    });
+    // the requests the pages needed (excerpts, mentions) count for the sync, the threads of the
+    // pool keep them until they're taken
+    let stats: Vec<SyncStats> = pool.broadcast(|_| sync_stats::take());
+    stats.into_iter().for_each(sync_stats::merge);
+    results
+}
+
+/// Maps the items on as many threads as there are jobs.
+pub fn map<T, R>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+{
+    map_with(items, || (), |_, item| f(item))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn it_keeps_the_order_of_the_items() {
+        let items: Vec<u64> = (0..200).collect();
+        let squares = map(&items, |item| {
+            // the early items finish last
+            thread::sleep(std::time::Duration::from_micros(200 - item));
+            item * item
+        });
+
+        assert_eq!(
+            squares,
+            items.iter().map(|item| item * item).collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,6 +1,10 @@
 use std::path::{Path, PathBuf};
 
 pub fn get_parent_file(page_path: &Path) -> Option<PathBuf> {
+    // the page of a section (see page_split) belongs under the page of its file
+    if let Some((file, _anchor)) = page_path.to_str().and_then(|p| p.split_once('#')) {
+        return Some(PathBuf::from(file));
+    }
     if let Some(parent_path) = page_path.parent() {
         if parent_path == PathBuf::default() {
             // Parent is space
@@ -20,6 +24,42 @@ pub fn get_parent_file(page_path: &Path) -> Option<PathBuf> {
     }
 }
 
+/// How deep the page of `page_path` is in the tree, 1 for the pages right under the homepage.
+pub fn page_level(page_path: &Path) -> usize {
+    if let Some((file, _anchor)) = page_path.to_str().and_then(|p| p.split_once('#')) {
+        return page_level(Path::new(file)) + 1;
+    }
+    let components = page_path.components().count();
+    if page_path.file_name().is_some_and(|name| name == "index.md") {
+        components - 1
+    } else {
+        components
+    }
+}
+
+/// The files above `page_path` that are at `max_depth` or deeper, which its page skips so it
+/// doesn't end up deeper than `max_depth` (see `max_depth` in the space config), closest first.
+pub fn flattened_parent_files(page_path: &Path, max_depth: usize) -> Vec<PathBuf> {
+    let mut skipped = Vec::default();
+    let mut parent = get_parent_file(page_path);
+    while let Some(parent_file) = parent.filter(|parent| page_level(parent) >= max_depth) {
+        parent = get_parent_file(&parent_file);
+        skipped.push(parent_file);
+    }
+    skipped
+}
+
+/// Like `get_parent_file`, but skipping the parents at `max_depth` or deeper.
+pub fn get_flattened_parent_file(page_path: &Path, max_depth: Option<usize>) -> Option<PathBuf> {
+    match max_depth {
+        Some(max_depth) => match flattened_parent_files(page_path, max_depth).last() {
+            Some(skipped) => get_parent_file(skipped),
+            None => get_parent_file(page_path),
+        },
+        None => get_parent_file(page_path),
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -46,10 +86,42 @@ mod tests {
         assert_eq!(parent_file, None);
     }
 
+    #[test]
+    fn it_returns_the_file_for_sections() {
+        let parent_file = get_parent_file(&PathBuf::from("specs/api.md#data-model"));
+
+        assert_eq!(parent_file, Some(PathBuf::from("specs/api.md")));
+    }
+
     #[test]
     fn it_returns_none_parent_for_homepage() {
         let parent_file = get_parent_file(&PathBuf::from("index.md"));
 
         assert_eq!(parent_file, None);
     }
+
+    #[test]
+    fn it_flattens_parents_below_the_max_depth() {
+        let page = PathBuf::from("a/b/c/d.md");
+
+        assert_eq!(page_level(&page), 4);
+        assert_eq!(page_level(Path::new("a/b/index.md")), 2);
+        assert_eq!(page_level(Path::new("a/b.md#usage")), 3);
+        assert_eq!(
+            flattened_parent_files(&page, 2),
+            vec![
+                PathBuf::from("a/b/c/index.md"),
+                PathBuf::from("a/b/index.md")
+            ]
+        );
+        assert_eq!(
+            get_flattened_parent_file(&page, Some(2)),
+            Some(PathBuf::from("a/index.md"))
+        );
+        assert_eq!(get_flattened_parent_file(&page, Some(1)), None);
+        assert_eq!(
+            get_flattened_parent_file(Path::new("a/b/index.md"), Some(2)),
+            Some(PathBuf::from("a/index.md"))
+        );
+    }
 }
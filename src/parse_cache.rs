@@ -0,0 +1,259 @@
+//! `--parse-cache`: the templated markdown of the pages and what parsing it found, kept across
+//! runs so files that didn't change aren't templated and parsed again.
+//!
+//! Entries are keyed by the content and path of the file, the version of marked-space and a
+//! digest of the files any page can depend on: `_space.yml`, `_defaults.md`, everything in
+//! `_tera`, link definitions and snippets. Files merged into a page are checked when the entry is
+//! used. Pages that call a function whose output doesn't come from the files (`now()`,
+//! `confluence_excerpt()`, ...) are templated on every run.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::Context;
+use data_encoding::HEXLOWER;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use ring::digest::{Context as DigestContext, SHA256};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::{
+    attachments::ImageAttachment,
+    error::Result,
+    link_definitions::LinkDefinitions,
+    local_link::LocalLink,
+    markdown_page::MarkdownPage,
+    markdown_space::MarkdownSpace,
+    page_merge::MergedFile,
+    warnings::{Warning, WarningCode},
+};
+
+/// Template functions that give something else every run, or read it from Confluence.
+static VOLATILE_FUNCTION: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\b(now|get_random|get_env|confluence_excerpt|team|avatar)\s*\(").unwrap()
+});
+
+/// What the cache keeps of a page, everything but what comes from the front matter and the space
+/// config.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachedPage {
+    pub title: String,
+    pub source: String,
+    pub markdown: String,
+    pub attachments: Vec<ImageAttachment>,
+    pub local_links: Vec<LocalLink>,
+    pub warnings: Vec<Warning>,
+    pub disabled_warnings: Vec<WarningCode>,
+    pub merged: Vec<MergedFile>,
+    pub anchors: Vec<String>,
+    pub references: Vec<String>,
+}
+
+/// The pages of a file, the page followed by its sections when it's split.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedFile {
+    /// Digests of the files merged into the page, by source.
+    merged: Vec<(String, String)>,
+    pages: Vec<CachedPage>,
+}
+
+fn digest(parts: &[&[u8]]) -> String {
+    let mut context = DigestContext::new(&SHA256);
+    for part in parts {
+        // the length keeps ("ab", "c") and ("a", "bc") apart
+        context.update(&(part.len() as u64).to_le_bytes());
+        context.update(part);
+    }
+    HEXLOWER.encode(context.finish().as_ref())
+}
+
+fn uses_templates(content: &str) -> bool {
+    content.contains("{{") || content.contains("{%")
+}
+
+pub struct ParseCache {
+    dir: PathBuf,
+    space_dir: PathBuf,
+    /// Digest of the version, the space config and the files any page can depend on.
+    inputs: String,
+    /// Whether the shared templates call a volatile function, which rules out caching any page
+    /// that's templated.
+    volatile_templates: bool,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl ParseCache {
+    pub fn open(dir: &Path, markdown_space: &MarkdownSpace) -> Result<ParseCache> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create parse cache {}", dir.display()))?;
+        let space_dir = &markdown_space.dir;
+        let link_definitions = LinkDefinitions::file(space_dir)?;
+        let files: Vec<PathBuf> = WalkDir::new(space_dir)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| entry.into_path())
+            .filter(|path| {
+                path.strip_prefix(space_dir).is_ok_and(|relative| {
+                    relative
+                        .components()
+                        .any(|component| component.as_os_str().to_string_lossy().starts_with('_'))
+                }) || Some(path) == link_definitions.as_ref()
+                    || markdown_space.snippets.contains(path)
+            })
+            .collect();
+
+        let version = env!("CARGO_PKG_VERSION");
+        let emoji_title = format!("{:?}", markdown_space.config.emoji_title);
+        let mut parts: Vec<Vec<u8>> = vec![version.into(), emoji_title.into()];
+        let mut volatile_templates = false;
+        for path in &files {
+            let content = fs::read(path)?;
+            volatile_templates |= VOLATILE_FUNCTION.is_match(&String::from_utf8_lossy(&content));
+            parts.push(path.to_string_lossy().as_bytes().to_vec());
+            parts.push(content);
+        }
+        let parts: Vec<&[u8]> = parts.iter().map(Vec::as_slice).collect();
+        Ok(ParseCache {
+            dir: dir.to_path_buf(),
+            space_dir: space_dir.clone(),
+            inputs: digest(&parts),
+            volatile_templates,
+            hits: AtomicUsize::default(),
+            misses: AtomicUsize::default(),
+        })
+    }
+
+    fn path(&self, markdown_page: &Path, content: &[u8]) -> PathBuf {
+        let key = digest(&[
+            self.inputs.as_bytes(),
+            markdown_page.to_string_lossy().as_bytes(),
+            content,
+        ]);
+        self.dir.join(format!("{}.json", key))
+    }
+
+    /// The pages of the file, when it was parsed before and neither it nor what it depends on
+    /// changed since.
+    pub fn lookup(&self, markdown_page: &Path) -> Option<Vec<CachedPage>> {
+        let found = fs::read(markdown_page).ok().and_then(|content| {
+            let cached = fs::read_to_string(self.path(markdown_page, &content)).ok()?;
+            let cached: CachedFile = serde_json::from_str(&cached).ok()?;
+            for (source, merged_digest) in &cached.merged {
+                let merged_content = fs::read(self.space_dir.join(source)).ok()?;
+                if digest(&[&merged_content]) != *merged_digest {
+                    return None;
+                }
+            }
+            Some(cached.pages)
+        });
+        match &found {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        found
+    }
+
+    /// Keeps the pages parsed from the file for the next run, unless templating them depends on
+    /// more than the files.
+    pub fn store(&self, markdown_page: &Path, pages: &[MarkdownPage]) -> Result<()> {
+        let Some(page) = pages.first() else {
+            return Ok(());
+        };
+        let content = fs::read(markdown_page)?;
+        let mut contents = vec![String::from_utf8_lossy(&content).to_string()];
+        let mut merged = Vec::with_capacity(page.merged.len());
+        for merged_file in &page.merged {
+            let merged_content = fs::read(self.space_dir.join(&merged_file.source))?;
+            merged.push((merged_file.source.clone(), digest(&[&merged_content])));
+            contents.push(String::from_utf8_lossy(&merged_content).to_string());
+        }
+        let templated = page.front_matter.template
+            && (page.front_matter.page_type.is_some()
+                || contents.iter().any(|content| uses_templates(content)));
+        if contents
+            .iter()
+            .any(|content| VOLATILE_FUNCTION.is_match(content))
+            || (templated && self.volatile_templates)
+        {
+            return Ok(());
+        }
+
+        let cached = CachedFile {
+            merged,
+            pages: pages.iter().map(MarkdownPage::to_cached).collect(),
+        };
+        // a cache that can't be written only costs parsing the file again next time
+        let _ = fs::write(
+            self.path(markdown_page, &content),
+            serde_json::to_string(&cached)?,
+        );
+        Ok(())
+    }
+
+    /// How many files were read from the cache, and how many had to be parsed.
+    pub fn stats(&self) -> (usize, usize) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+
+    use crate::{error::TestResult, template_renderer::TemplateRenderer};
+
+    use super::*;
+
+    fn parse(markdown_space: &MarkdownSpace, cache: &ParseCache) -> Result<Vec<String>> {
+        Ok(markdown_space
+            .parse(&TemplateRenderer::default()?, Some(cache))?
+            .iter()
+            .map(|page| page.title.clone())
+            .collect())
+    }
+
+    #[test]
+    fn it_only_parses_files_that_changed() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let cache_dir = temp.child("cache");
+        temp.child("test/index.md")
+            .write_str("# Home\n\n{{ 1 + 1 }}\n")?;
+        temp.child("test/guide.md").write_str("# Guide\n")?;
+        temp.child("test/today.md")
+            .write_str("# Today\n\n{{ now() }}\n")?;
+        let markdown_space = MarkdownSpace::from_directory(temp.child("test").path())?;
+
+        let cache = ParseCache::open(cache_dir.path(), &markdown_space)?;
+        let titles = parse(&markdown_space, &cache)?;
+        assert_eq!(cache.stats(), (0, 3));
+
+        let cache = ParseCache::open(cache_dir.path(), &markdown_space)?;
+        assert_eq!(parse(&markdown_space, &cache)?, titles);
+        // today.md calls now(), so it's templated every run
+        assert_eq!(cache.stats(), (2, 1));
+
+        temp.child("test/guide.md").write_str("# Setup Guide\n")?;
+        let cache = ParseCache::open(cache_dir.path(), &markdown_space)?;
+        assert!(parse(&markdown_space, &cache)?.contains(&String::from("Setup Guide")));
+        assert_eq!(cache.stats(), (1, 2));
+
+        // changing the space config parses everything again
+        temp.child("test/_space.yml")
+            .write_str("disambiguate_headings: true\n")?;
+        let markdown_space = MarkdownSpace::from_directory(temp.child("test").path())?;
+        let cache = ParseCache::open(cache_dir.path(), &markdown_space)?;
+        parse(&markdown_space, &cache)?;
+        assert_eq!(cache.stats(), (0, 3));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+//! What the path of a file says about a page, for templates (as `path`) and the labels of
+//! `_defaults.md` files, so pages can e.g. be labelled with their top level directory.
+use std::path::{Component, Path};
+
+use serde::Serialize;
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize)]
+pub struct PathVariables {
+    /// The directory the file is in, empty for files at the top of the space.
+    pub directory: String,
+    /// The directory at the top of the space the file is under, empty for files at the top.
+    pub top_directory: String,
+    /// How many directories deep the file is, 0 at the top of the space.
+    pub depth: usize,
+    /// The file name without its extension.
+    pub stem: String,
+}
+
+impl PathVariables {
+    /// The variables of the file at `source`, relative to the space directory.
+    pub fn from_source(source: &str) -> Self {
+        let path = Path::new(source);
+        let directories: Vec<String> = path
+            .parent()
+            .into_iter()
+            .flat_map(Path::components)
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+                _ => None,
+            })
+            .collect();
+        PathVariables {
+            directory: directories.last().cloned().unwrap_or_default(),
+            top_directory: directories.first().cloned().unwrap_or_default(),
+            depth: directories.len(),
+            stem: path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reads_the_variables_from_the_path() {
+        assert_eq!(
+            PathVariables::from_source("runbooks/db/restore.md"),
+            PathVariables {
+                directory: String::from("db"),
+                top_directory: String::from("runbooks"),
+                depth: 2,
+                stem: String::from("restore"),
+            }
+        );
+        assert_eq!(
+            PathVariables::from_source("index.md"),
+            PathVariables {
+                stem: String::from("index"),
+                ..Default::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,99 @@
+//! Placeholders for pages that fail to render, the `render_errors: placeholder` key of
+//! `_space.yml`. The page is published with the error and a link to its source instead of being
+//! left out, so its children stay where they are and links to it keep working while the fix
+//! lands. The page still counts as failed, so the sync fails and `--retry-failed` picks it up.
+use crate::{
+    confluence_storage_renderer::{escape, escape_href},
+    edit_link::EditLink,
+    error::{ConfluenceError, Result},
+};
+
+/// What happens to pages that fail to render.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum RenderErrors {
+    /// The page isn't synced.
+    #[default]
+    Fail,
+    /// The page is replaced by a placeholder with the error, unless the sync is `--strict`.
+    Placeholder,
+}
+
+impl RenderErrors {
+    pub fn from_str(s: Option<&str>) -> Result<RenderErrors> {
+        match s {
+            None | Some("fail") => Ok(RenderErrors::Fail),
+            Some("placeholder") => Ok(RenderErrors::Placeholder),
+            Some(other) => Err(ConfluenceError::generic_error(format!(
+                "Invalid render errors \"{}\" (should be fail or placeholder)",
+                other
+            ))),
+        }
+    }
+}
+
+fn escaped(text: &str) -> Result<String> {
+    let mut output = Vec::default();
+    escape(&mut output, text.as_bytes())?;
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// The storage format of the placeholder for the page of `source` that failed with `error`.
+pub fn placeholder_content(
+    source: &str,
+    error: &anyhow::Error,
+    edit_link: Option<&EditLink>,
+) -> Result<String> {
+    let source_markup = match edit_link {
+        Some(edit_link) => {
+            let mut href = Vec::default();
+            escape_href(&mut href, edit_link.href(source)?.as_bytes())?;
+            format!(
+                "<a href=\"{}\">{}</a>",
+                String::from_utf8_lossy(&href),
+                escaped(source)?
+            )
+        }
+        None => format!("<code>{}</code>", escaped(source)?),
+    };
+    Ok(format!(
+        "<ac:structured-macro ac:name=\"warning\"><ac:rich-text-body><p>This page failed to render, it will be replaced by its content once the error in {} is fixed.</p><pre>{}</pre></ac:rich-text-body></ac:structured-macro>\n",
+        source_markup,
+        escaped(&format!("{:#}", error))?
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use crate::{
+        error::TestResult, link_generator::LinkGenerator, space_config::SpaceConfig,
+        test_helpers::markdown_page_from_str_with_config,
+    };
+
+    #[test]
+    fn it_renders_a_placeholder_with_the_error() -> TestResult {
+        let space_config = SpaceConfig::from_yaml_str(
+            "render_errors: placeholder\nedit_link:\n  url: https://git.example.com/docs/{{ source }}\n",
+        )?;
+        let page = markdown_page_from_str_with_config(
+            "runbooks/restore.md",
+            "# Restore\n\nSteps\n",
+            &space_config,
+        )?;
+
+        let placeholder = page.render_placeholder(
+            &LinkGenerator::default_test(),
+            &anyhow!("Unknown <macro>"),
+            space_config.edit_link.as_ref(),
+        )?;
+
+        assert_eq!(placeholder.title, "Restore");
+        assert_eq!(
+            placeholder.content,
+            "<ac:structured-macro ac:name=\"warning\"><ac:rich-text-body><p>This page failed to render, it will be replaced by its content once the error in <a href=\"https://git.example.com/docs/runbooks/restore.md\">runbooks/restore.md</a> is fixed.</p><pre>Unknown &lt;macro&gt;</pre></ac:rich-text-body></ac:structured-macro>\n"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,551 @@
+//! The `preview` subcommand: a small local web server showing the pages of the space as they would
+//! be published, without Confluence. The space is rendered (like `test-templates`, with made-up
+//! ids) on the first request and again on the first request after one of its files changed, so
+//! saving a file and reloading the browser shows the change. The storage format is turned into plain HTML: images and attachments are served from
+//! the space directory, macros become boxes with their name, and links between pages stay in the
+//! preview.
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+};
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    console::{print_error, print_info},
+    markdown_page::MarkdownPage,
+    markdown_space::MarkdownSpace,
+    parallel::map,
+    sync::{load_space_offline, LoadedSpace},
+    watch::{snapshot, Snapshot},
+    Args, Result,
+};
+
+static IMAGE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?s)<ac:image([^>]*)>\s*<ri:(attachment|url)\s+ri:(?:filename|value)="([^"]*)"[^>]*?/>\s*</ac:image>"#)
+        .unwrap()
+});
+static LINK: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)<ac:link[^>]*>(.*?)</ac:link>").unwrap());
+static LINK_BODY: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?s)<ac:(?:plain-text-)?link-body>(?:<!\[CDATA\[(.*?)\]\]>|(.*?))</ac:(?:plain-text-)?link-body>")
+        .unwrap()
+});
+static PAGE_URL: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"https?://[^/"]+/wiki/spaces/[^/"]+/pages/(\d+)"#).unwrap());
+static TAG: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"(?s)<!\[CDATA\[(.*?)\]\]>|<(/?)(?:ac|ri):([a-z-]+)((?:\s+[a-z:-]+="[^"]*")*)\s*(/?)>"#,
+    )
+    .unwrap()
+});
+
+static ATTRIBUTE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"([\w:.-]+)="([^"]*)""#).unwrap());
+
+fn attribute(attributes: &str, name: &str) -> Option<String> {
+    ATTRIBUTE
+        .captures_iter(attributes)
+        .find(|captures| &captures[1] == name)
+        .map(|captures| captures[2].to_string())
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Percent-encodes a path for a URL of the preview, keeping the slashes.
+pub(crate) fn encode_path(path: &str) -> String {
+    path.bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                String::from(byte as char)
+            }
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn decode_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn attachment_url(source: &str, filename: &str) -> String {
+    format!(
+        "/attachments/{}/{}",
+        encode_path(source),
+        encode_path(filename)
+    )
+}
+
+/// The HTML of a tag of the storage format: what to write for the tag, and for its closing tag.
+fn tag_html(name: &str, attributes: &str) -> (String, String) {
+    let macro_name = attribute(attributes, "ac:name").unwrap_or_default();
+    match name {
+        "structured-macro" => {
+            let label = match macro_name.as_str() {
+                // panels explain themselves by their color
+                "info" | "note" | "tip" | "warning" | "panel" | "expand" => String::default(),
+                name => format!("<div class=\"macro-name\">{}</div>", escape_html(name)),
+            };
+            (
+                format!(
+                    "<div class=\"macro macro-{}\">{}",
+                    escape_html(&macro_name),
+                    label
+                ),
+                String::from("</div>"),
+            )
+        }
+        "parameter" if macro_name == "title" => (
+            String::from("<div class=\"macro-title\">"),
+            String::from("</div>"),
+        ),
+        "parameter" => (
+            String::from("<span class=\"macro-parameter\" hidden>"),
+            String::from("</span>"),
+        ),
+        "rich-text-body" | "layout" | "layout-section" | "layout-cell" => {
+            (format!("<div class=\"{}\">", name), String::from("</div>"))
+        }
+        "plain-text-body" => (String::from("<pre><code>"), String::from("</code></pre>")),
+        "task-list" => (String::from("<ul class=\"tasks\">"), String::from("</ul>")),
+        "task" => (String::from("<li>"), String::from("</li>")),
+        "task-status" | "task-id" => (String::from("<span hidden>"), String::from("</span>")),
+        "emoticon" => (
+            format!(
+                "<span class=\"emoticon\">{}</span>",
+                attribute(attributes, "ac:emoji-fallback").unwrap_or(format!(":{}:", macro_name))
+            ),
+            String::default(),
+        ),
+        _ => (
+            format!("<span class=\"{}\">", escape_html(name)),
+            String::from("</span>"),
+        ),
+    }
+}
+
+/// Turns the storage format of the page of `source` into HTML a browser shows. `pages` are the
+/// sources of the pages by their (made-up) id, for links between pages.
+pub fn storage_to_html(storage: &str, source: &str, pages: &BTreeMap<String, String>) -> String {
+    let html = IMAGE.replace_all(storage, |captures: &Captures| {
+        let src = match &captures[2] {
+            "attachment" => attachment_url(source, &captures[3]),
+            _ => captures[3].to_string(),
+        };
+        format!(
+            "<img src=\"{}\" alt=\"{}\">",
+            src,
+            attribute(&captures[1], "ac:alt").unwrap_or_default()
+        )
+    });
+    let html = LINK.replace_all(&html, |captures: &Captures| {
+        let inner = &captures[1];
+        let body = LINK_BODY.captures(inner).map(|body| match body.get(1) {
+            Some(cdata) => escape_html(cdata.as_str()),
+            None => body[2].to_string(),
+        });
+        if let Some(account_id) = attribute(inner, "ri:account-id") {
+            return format!(
+                "<span class=\"mention\">@{}</span>",
+                body.unwrap_or(account_id)
+            );
+        }
+        if let Some(filename) = attribute(inner, "ri:filename") {
+            return format!(
+                "<a href=\"{}\">{}</a>",
+                attachment_url(source, &filename),
+                body.unwrap_or(filename)
+            );
+        }
+        let title = attribute(inner, "ri:content-title").unwrap_or_default();
+        format!("<a class=\"confluence-link\">{}</a>", body.unwrap_or(title))
+    });
+    let html = PAGE_URL.replace_all(&html, |captures: &Captures| match pages.get(&captures[1]) {
+        Some(page) => format!("/pages/{}", encode_path(page)),
+        None => captures[0].to_string(),
+    });
+
+    let mut output = String::with_capacity(html.len());
+    let mut closing: Vec<String> = Vec::default();
+    let mut last = 0;
+    for captures in TAG.captures_iter(&html) {
+        let whole = captures.get(0).unwrap();
+        output.push_str(&html[last..whole.start()]);
+        last = whole.end();
+        if let Some(cdata) = captures.get(1) {
+            output.push_str(&escape_html(cdata.as_str()));
+        } else if &captures[2] == "/" {
+            output.push_str(&closing.pop().unwrap_or_default());
+        } else {
+            let (open, close) = tag_html(&captures[3], &captures[4]);
+            output.push_str(&open);
+            if &captures[5] == "/" {
+                output.push_str(&close);
+            } else {
+                closing.push(close);
+            }
+        }
+    }
+    output.push_str(&html[last..]);
+    output
+}
+
+pub(crate) static STYLE: &str =
+    "body{font-family:sans-serif;max-width:60em;margin:2em auto;padding:0 1em;line-height:1.5}\
+img{max-width:100%}pre{background:#f4f5f7;padding:1em;overflow:auto}\
+table{border-collapse:collapse}td,th{border:1px solid #dfe1e6;padding:.3em .6em}\
+.macro{border:1px solid #dfe1e6;border-radius:3px;margin:1em 0;padding:.5em 1em}\
+.macro-info{background:#deebff}.macro-note{background:#eae6ff}.macro-tip{background:#e3fcef}\
+.macro-warning{background:#ffebe6}.macro-name,.macro-title{font-weight:bold;color:#5e6c84}\
+.mention{background:#ebecf0;border-radius:1em;padding:0 .4em}.error{color:#de350b}";
+
+fn html_page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title><style>{}</style></head><body><p><a href=\"/\">All pages</a></p><h1>{}</h1>{}</body></html>\n",
+        escape_html(title),
+        STYLE,
+        escape_html(title),
+        body
+    )
+}
+
+/// The response to a request of the preview.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn html(status: u16, html: String) -> Self {
+        Response {
+            status,
+            content_type: "text/html; charset=utf-8",
+            body: html.into_bytes(),
+        }
+    }
+
+    fn not_found(what: &str) -> Self {
+        Response::html(
+            404,
+            html_page(
+                "Not found",
+                &format!("<p>{} isn't in the space.</p>", escape_html(what)),
+            ),
+        )
+    }
+}
+
+fn content_type(filename: &str) -> &'static str {
+    match Path::new(filename)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase())
+        .as_deref()
+    {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        Some("pdf") => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// A page of the preview: its title and HTML, or why it failed to render.
+type PreviewPage = std::result::Result<(String, String), String>;
+
+/// The space as rendered from its files at one point in time.
+struct RenderedSpace {
+    key: String,
+    loaded_space: LoadedSpace,
+    /// The pages by source.
+    pages: BTreeMap<String, PreviewPage>,
+}
+
+impl RenderedSpace {
+    fn render(dir: &Path, confluence_client: &ConfluenceClient, args: &Args) -> Result<Self> {
+        let mut markdown_space = MarkdownSpace::from_directory(dir)?;
+        let loaded_space = load_space_offline(confluence_client, &mut markdown_space, args)?;
+        let link_generator = &loaded_space.link_generator;
+        let ids: BTreeMap<String, String> = loaded_space
+            .markdown_pages
+            .iter()
+            .filter_map(|page| {
+                link_generator
+                    .get_page_id(Path::new(&page.source))
+                    .map(|id| (id, page.source.replace('\\', "/")))
+            })
+            .collect();
+        let renderable: Vec<&MarkdownPage> = loaded_space
+            .markdown_pages
+            .iter()
+            .filter(|page| !page.is_folder())
+            .collect();
+        let results = map(&renderable, |markdown_page| {
+            markdown_page.render(link_generator)
+        });
+        let pages = renderable
+            .iter()
+            .zip(results)
+            .map(|(markdown_page, result)| {
+                let source = markdown_page.source.replace('\\', "/");
+                let page = result
+                    .map(|rendered_page| {
+                        let html = storage_to_html(&rendered_page.content, &source, &ids);
+                        (rendered_page.title, html)
+                    })
+                    .map_err(|err| format!("{:#}", err));
+                (source, page)
+            })
+            .collect();
+        Ok(RenderedSpace {
+            key: markdown_space.key,
+            loaded_space,
+            pages,
+        })
+    }
+}
+
+/// The preview of a space. The space is rendered on the first request, and again on a request
+/// after its files changed.
+pub struct Preview {
+    dir: PathBuf,
+    confluence_client: ConfluenceClient,
+    args: Args,
+    /// The last rendering, with the files it was rendered from.
+    rendered: Option<(Snapshot, std::result::Result<RenderedSpace, String>)>,
+}
+
+impl Preview {
+    pub fn new(dir: &Path, confluence_client: ConfluenceClient, args: Args) -> Self {
+        Preview {
+            dir: PathBuf::from(dir),
+            confluence_client,
+            args,
+            rendered: None,
+        }
+    }
+
+    fn rendered(&mut self) -> &std::result::Result<RenderedSpace, String> {
+        let snapshot = snapshot(&self.dir, &[]);
+        if self
+            .rendered
+            .as_ref()
+            .is_none_or(|(rendered_from, _)| *rendered_from != snapshot)
+        {
+            let rendered = RenderedSpace::render(&self.dir, &self.confluence_client, &self.args)
+                .map_err(|err| format!("{:#}", err));
+            self.rendered = Some((snapshot, rendered));
+        }
+        &self.rendered.as_ref().unwrap().1
+    }
+
+    /// Answers a request for `path`: the list of pages at `/`, a page at `/pages/<source>` and
+    /// its attachments at `/attachments/<source>/<name>`.
+    pub fn respond(&mut self, path: &str) -> Response {
+        let rendered_space = match self.rendered() {
+            Ok(rendered_space) => rendered_space,
+            Err(err) => return Response::html(500, html_page("Error", &error_html(err))),
+        };
+        let path = decode_path(path.split(['?', '#']).next().unwrap_or_default());
+        if path == "/" {
+            return index(rendered_space);
+        }
+        if let Some(source) = path.strip_prefix("/pages/") {
+            return match rendered_space.pages.get(source) {
+                Some(Ok((title, html))) => Response::html(200, html_page(title, html)),
+                Some(Err(err)) => Response::html(500, html_page(source, &error_html(err))),
+                None => Response::not_found(source),
+            };
+        }
+        if let Some(rest) = path.strip_prefix("/attachments/") {
+            let Some((source, name)) = rest.rsplit_once('/') else {
+                return Response::not_found(&path);
+            };
+            let loaded_space = &rendered_space.loaded_space;
+            let attachment = loaded_space
+                .markdown_pages
+                .iter()
+                .find(|page| page.source.replace('\\', "/") == source)
+                .and_then(|page| page.attachments.iter().find(|a| a.name == name));
+            return match attachment.map(|a| a.content(&loaded_space.link_generator)) {
+                Some(Ok(body)) => Response {
+                    status: 200,
+                    content_type: content_type(name),
+                    body,
+                },
+                Some(Err(err)) => {
+                    Response::html(500, html_page("Error", &error_html(&format!("{:#}", err))))
+                }
+                None => Response::not_found(&path),
+            };
+        }
+        Response::not_found(&path)
+    }
+}
+
+fn error_html(err: &str) -> String {
+    format!("<pre class=\"error\">{}</pre>", escape_html(err))
+}
+
+fn index(rendered_space: &RenderedSpace) -> Response {
+    let mut body = String::from("<ul>");
+    let mut errors = String::default();
+    for (source, page) in &rendered_space.pages {
+        match page {
+            Ok((title, _)) => {
+                body += &format!(
+                    "<li><a href=\"/pages/{}\">{}</a> <code>{}</code></li>",
+                    encode_path(source),
+                    escape_html(title),
+                    escape_html(source)
+                )
+            }
+            Err(err) => errors += &error_html(&format!("[{}] {}", source, err)),
+        }
+    }
+    body += "</ul>";
+    body += &errors;
+    Response::html(
+        200,
+        html_page(&format!("{} (preview)", rendered_space.key), &body),
+    )
+}
+
+fn handle(stream: TcpStream, preview: &mut Preview) -> Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let response = match request_line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["GET", path, ..] => preview.respond(path),
+        _ => Response::html(405, html_page("Method not allowed", "")),
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        response.status,
+        match response.status {
+            200 => "OK",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        },
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    Ok(())
+}
+
+pub fn preview(
+    confluence_client: ConfluenceClient,
+    dir: &Path,
+    args: Args,
+    port: u16,
+) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    print_info(&format!(
+        "Previewing {} at http://127.0.0.1:{}/ (stop with Ctrl+C)",
+        dir.display(),
+        port
+    ));
+    let mut preview = Preview::new(dir, confluence_client, args);
+    for stream in listener.incoming() {
+        if let Err(err) = stream
+            .map_err(anyhow::Error::from)
+            .and_then(|stream| handle(stream, &mut preview))
+        {
+            print_error(&format!("{:#}", err));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn it_turns_the_storage_format_into_html() {
+        let pages = BTreeMap::from([(String::from("2"), String::from("guides/setup.md"))]);
+        let storage = "<p><ac:image ac:align=\"center\" ac:alt=\"Diagram\"><ri:attachment ri:filename=\"assets_diagram.png\"/></ac:image> <a href=\"https://example.atlassian.net/wiki/spaces/TEST/pages/2#usage\">setup</a> <ac:link><ri:user ri:account-id=\"abc\"/></ac:link></p>\n<ac:structured-macro ac:name=\"info\"><ac:parameter ac:name=\"title\">Note</ac:parameter><ac:rich-text-body><p>Hi</p></ac:rich-text-body></ac:structured-macro>\n<ac:structured-macro ac:name=\"code\"><ac:parameter ac:name=\"language\">rust</ac:parameter><ac:plain-text-body><![CDATA[a < b]]></ac:plain-text-body></ac:structured-macro>\n";
+
+        assert_eq!(
+            storage_to_html(storage, "index.md", &pages),
+            "<p><img src=\"/attachments/index.md/assets_diagram.png\" alt=\"Diagram\"> <a href=\"/pages/guides/setup.md#usage\">setup</a> <span class=\"mention\">@abc</span></p>\n<div class=\"macro macro-info\"><div class=\"macro-title\">Note</div><div class=\"rich-text-body\"><p>Hi</p></div></div>\n<div class=\"macro macro-code\"><div class=\"macro-name\">code</div><span class=\"macro-parameter\" hidden>rust</span><pre><code>a &lt; b</code></pre></div>\n"
+        );
+    }
+
+    #[test]
+    fn it_serves_the_pages_of_the_space() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("test/index.md")
+            .write_str("# Home\n\nSee [the guide](guide.md).\n")?;
+        temp.child("test/guide.md")
+            .write_str("# Guide\n\nHello\n")?;
+        let client = ConfluenceClient::new("example.atlassian.net");
+        let mut preview = Preview::new(&temp.path().join("test"), client, Args::default());
+
+        let index = preview.respond("/");
+        assert_eq!(index.status, 200);
+        assert!(String::from_utf8(index.body)?.contains("<a href=\"/pages/guide.md\">Guide</a>"));
+
+        let home = preview.respond("/pages/index.md");
+        assert!(String::from_utf8(home.body)?.contains("<a href=\"/pages/guide.md\">the guide</a>"));
+
+        assert_eq!(preview.respond("/pages/missing.md").status, 404);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_renders_the_space_again_when_a_file_changed() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("test/index.md").write_str("# Home\n")?;
+        let client = ConfluenceClient::new("example.atlassian.net");
+        let mut preview = Preview::new(&temp.path().join("test"), client, Args::default());
+
+        assert_eq!(preview.respond("/pages/guide.md").status, 404);
+
+        temp.child("test/guide.md")
+            .write_str("# Guide\n\nHello\n")?;
+        let guide = preview.respond("/pages/guide.md");
+        assert_eq!(guide.status, 200);
+        assert!(String::from_utf8(guide.body)?.contains("<p>Hello</p>"));
+
+        Ok(())
+    }
+}
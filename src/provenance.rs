@@ -0,0 +1,408 @@
+//! Where a page came from, stored in a content property on every page when `provenance: true` is
+//! set in `_space.yml`, and checked across the space by the `verify` subcommand.
+//!
+//! The provenance names the tool version, the git commit and repository, the CI run and the
+//! checksum of the content that was published. With `PROVENANCE_KEY` in the environment it's also
+//! signed (HMAC-SHA256), so a provenance can't be written by anyone who doesn't have the key.
+//! The property is only rewritten when the content of the page changes.
+use std::{path::Path, process::Command};
+
+use data_encoding::HEXLOWER;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    confluence_page::ConfluencePageData,
+    confluence_space::ConfluenceSpace,
+    console::{print_error, print_status, print_warning, Status},
+    error::{ApiResponse, ConfluenceError, Result},
+    markdown_space::MarkdownSpace,
+    responses::{self, ContentProperty, ContentStatus, MultiEntityResult},
+    sync::check_homepage_exists,
+};
+
+pub static PROVENANCE_PROP: &str = "marked-space-provenance";
+
+/// Where a sync runs from, the same for every page it publishes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    pub commit: Option<String>,
+    pub repository: Option<String>,
+    pub run: Option<String>,
+    key: Option<String>,
+}
+
+/// The provenance of a single page, as stored in its content property.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageProvenance {
+    pub tool: String,
+    pub source: String,
+    /// The checksum of the published content, as in the version message of the page.
+    pub checksum: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+fn first_of(env: &impl Fn(&str) -> Option<String>, names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .find_map(|name| env(name).filter(|value| !value.is_empty()))
+}
+
+impl Provenance {
+    /// Reads the commit, repository and run from the environment variables of GitHub Actions,
+    /// GitLab CI or Jenkins, falling back on git for the commit and repository.
+    pub fn detect(space_dir: &Path) -> Provenance {
+        Self::detect_with(
+            |name| std::env::var(name).ok(),
+            |args| {
+                let output = Command::new("git")
+                    .arg("-C")
+                    .arg(space_dir)
+                    .args(args)
+                    .output()
+                    .ok()?;
+                output
+                    .status
+                    .success()
+                    .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+                    .filter(|value| !value.is_empty())
+            },
+        )
+    }
+
+    pub(crate) fn detect_with(
+        env: impl Fn(&str) -> Option<String>,
+        git: impl Fn(&[&str]) -> Option<String>,
+    ) -> Provenance {
+        let github = match (
+            env("GITHUB_SERVER_URL"),
+            env("GITHUB_REPOSITORY"),
+            env("GITHUB_RUN_ID"),
+        ) {
+            (Some(server), Some(repository), run_id) => Some((
+                format!("{}/{}", server, repository),
+                run_id.map(|run_id| format!("{}/{}/actions/runs/{}", server, repository, run_id)),
+            )),
+            _ => None,
+        };
+        let (github_repository, github_run) = github.unzip();
+        Provenance {
+            commit: first_of(&env, &["GITHUB_SHA", "CI_COMMIT_SHA", "GIT_COMMIT"])
+                .or_else(|| git(&["rev-parse", "HEAD"])),
+            repository: github_repository
+                .or_else(|| first_of(&env, &["CI_PROJECT_URL", "GIT_URL"]))
+                .or_else(|| git(&["config", "--get", "remote.origin.url"])),
+            run: github_run
+                .flatten()
+                .or_else(|| first_of(&env, &["CI_JOB_URL", "BUILD_URL"])),
+            key: first_of(&env, &["PROVENANCE_KEY"]),
+        }
+    }
+
+    pub fn for_page(&self, source: &str, checksum: &str) -> PageProvenance {
+        let mut page = PageProvenance {
+            tool: format!("marked-space {}", env!("CARGO_PKG_VERSION")),
+            source: source.replace('\\', "/"),
+            checksum: String::from(checksum),
+            commit: self.commit.clone(),
+            repository: self.repository.clone(),
+            run: self.run.clone(),
+            signature: None,
+        };
+        page.signature = self.key.as_deref().map(|key| page.sign(key));
+        page
+    }
+}
+
+impl PageProvenance {
+    /// Everything but the signature, one field per line.
+    fn signed_content(&self) -> String {
+        [
+            Some(self.tool.as_str()),
+            Some(self.source.as_str()),
+            Some(self.checksum.as_str()),
+            self.commit.as_deref(),
+            self.repository.as_deref(),
+            self.run.as_deref(),
+        ]
+        .map(|field| field.unwrap_or_default())
+        .join("\n")
+    }
+
+    fn sign(&self, key: &str) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+        format!(
+            "hmac-sha256:{}",
+            HEXLOWER.encode(hmac::sign(&key, self.signed_content().as_bytes()).as_ref())
+        )
+    }
+
+    fn has_valid_signature(&self, key: &str) -> bool {
+        let Some(signature) = self
+            .signature
+            .as_deref()
+            .and_then(|signature| signature.strip_prefix("hmac-sha256:"))
+            .and_then(|hex| HEXLOWER.decode(hex.as_bytes()).ok())
+        else {
+            return false;
+        };
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+        hmac::verify(&key, self.signed_content().as_bytes(), &signature).is_ok()
+    }
+}
+
+/// The provenance property to write, when the page has none yet or it's about other content.
+pub fn provenance_update(
+    existing_properties: &[ContentProperty],
+    provenance: &PageProvenance,
+) -> Option<ContentProperty> {
+    let value = serde_json::to_value(provenance).unwrap();
+    match existing_properties
+        .iter()
+        .find(|property| property.key == PROVENANCE_PROP)
+    {
+        Some(property)
+            if property.value["checksum"] == value["checksum"]
+                && property.value["signature"].is_null() == value["signature"].is_null() =>
+        {
+            None
+        }
+        Some(property) => {
+            let mut update = property.clone();
+            update.value = value;
+            update.version.number += 1;
+            Some(update)
+        }
+        None => Some(ContentProperty {
+            id: String::from(""),
+            key: String::from(PROVENANCE_PROP),
+            value,
+            version: responses::Version {
+                message: String::from(""),
+                number: 0,
+            },
+        }),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    Verified,
+    /// The page has no provenance, e.g. because it was synced before `provenance` was enabled.
+    Missing,
+    /// The content isn't what the provenance was written for, e.g. after an edit in Confluence.
+    Modified,
+    /// There's a key to check signatures with, but the provenance isn't signed with it.
+    BadSignature,
+}
+
+pub fn verify_page(
+    page_data: &ConfluencePageData,
+    property: Option<&Value>,
+    key: Option<&str>,
+) -> Verification {
+    let Some(provenance) =
+        property.and_then(|value| serde_json::from_value::<PageProvenance>(value.clone()).ok())
+    else {
+        return Verification::Missing;
+    };
+    if ConfluencePageData::extract_checksum(&page_data.version).as_ref()
+        != Some(&provenance.checksum)
+    {
+        return Verification::Modified;
+    }
+    if key.is_some_and(|key| !provenance.has_valid_signature(key)) {
+        return Verification::BadSignature;
+    }
+    Verification::Verified
+}
+
+/// The `verify` subcommand: checks the provenance of every page synced by marked-space.
+pub fn verify(confluence_client: ConfluenceClient, markdown_space: &MarkdownSpace) -> Result<()> {
+    let key = std::env::var("PROVENANCE_KEY")
+        .ok()
+        .filter(|key| !key.is_empty());
+    let mut space = ConfluenceSpace::get(&confluence_client, &markdown_space.key)?;
+    if let Some(homepage_id) = &markdown_space.config.homepage.id {
+        space.homepage_id = homepage_id.clone();
+    }
+    space.managed_label = markdown_space.config.managed_label.clone();
+    space.read_all_pages(&confluence_client)?;
+    check_homepage_exists(&space, &markdown_space.key)?;
+
+    let mut failed = 0;
+    for node in space.nodes() {
+        let Some(page_data) = node.page_data() else {
+            continue;
+        };
+        if !page_data.is_managed() || matches!(page_data.status, ContentStatus::Archived) {
+            continue;
+        }
+        let properties = confluence_client
+            .get_properties(&node.id)?
+            .api_result()?
+            .json::<MultiEntityResult<ContentProperty>>()?;
+        let property = properties
+            .results
+            .iter()
+            .find(|property| property.key == PROVENANCE_PROP)
+            .map(|property| &property.value);
+        let source = page_data
+            .path
+            .as_ref()
+            .map(|path| path.to_string_lossy().replace('\\', "/"))
+            .unwrap_or_default();
+        let message = |problem: &str| format!("[{}] \"{}\" {}", source, node.title, problem);
+        match verify_page(page_data, property, key.as_deref()) {
+            Verification::Verified => print_status(Status::Skipped, &message("verified")),
+            Verification::Missing => {
+                failed += 1;
+                print_error(&message("has no provenance"));
+            }
+            Verification::Modified => {
+                failed += 1;
+                print_error(&message("changed since it was published"));
+            }
+            Verification::BadSignature => {
+                failed += 1;
+                print_error(&message("has no valid signature"));
+            }
+        }
+    }
+    if key.is_none() {
+        print_warning("signatures weren't checked, set PROVENANCE_KEY to check them");
+    }
+    if failed > 0 {
+        return Err(ConfluenceError::generic_error(format!(
+            "{} page(s) failed verification",
+            failed
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn page_data(checksum: &str) -> ConfluencePageData {
+        ConfluencePageData {
+            version: responses::Version {
+                message: format!(
+                    "{}source=docs/index.md;checksum={}",
+                    ConfluencePageData::version_message_prefix(),
+                    checksum
+                ),
+                number: 3,
+            },
+            path: None,
+            status: ContentStatus::Current,
+        }
+    }
+
+    #[test]
+    fn it_detects_where_a_sync_runs() {
+        let provenance = Provenance::detect_with(
+            |name| match name {
+                "GITHUB_SERVER_URL" => Some(String::from("https://github.com")),
+                "GITHUB_REPOSITORY" => Some(String::from("Nr18/marked-space")),
+                "GITHUB_RUN_ID" => Some(String::from("42")),
+                "GITHUB_SHA" => Some(String::from("abc123")),
+                _ => None,
+            },
+            |_| None,
+        );
+
+        assert_eq!(provenance.commit.as_deref(), Some("abc123"));
+        assert_eq!(
+            provenance.repository.as_deref(),
+            Some("https://github.com/Nr18/marked-space")
+        );
+        assert_eq!(
+            provenance.run.as_deref(),
+            Some("https://github.com/Nr18/marked-space/actions/runs/42")
+        );
+
+        let local = Provenance::detect_with(
+            |_| None,
+            |args| (args[0] == "rev-parse").then(|| String::from("def456")),
+        );
+        assert_eq!(local.commit.as_deref(), Some("def456"));
+        assert_eq!(local.run, None);
+    }
+
+    #[test]
+    fn it_verifies_pages() {
+        let provenance = Provenance {
+            commit: Some(String::from("abc123")),
+            key: Some(String::from("secret")),
+            ..Default::default()
+        };
+        let value = serde_json::to_value(provenance.for_page("docs/index.md", "xxh64:1")).unwrap();
+
+        assert_eq!(
+            verify_page(&page_data("xxh64:1"), Some(&value), Some("secret")),
+            Verification::Verified
+        );
+        assert_eq!(
+            verify_page(&page_data("xxh64:2"), Some(&value), Some("secret")),
+            Verification::Modified
+        );
+        assert_eq!(
+            verify_page(&page_data("xxh64:1"), Some(&value), Some("other")),
+            Verification::BadSignature
+        );
+        assert_eq!(
+            verify_page(&page_data("xxh64:1"), None, None),
+            Verification::Missing
+        );
+
+        let mut forged = value.clone();
+        forged["commit"] = json!("fff000");
+        assert_eq!(
+            verify_page(&page_data("xxh64:1"), Some(&forged), Some("secret")),
+            Verification::BadSignature
+        );
+    }
+
+    #[test]
+    fn it_only_updates_provenance_of_changed_content() {
+        let provenance = Provenance::default();
+        let existing = ContentProperty {
+            id: String::from("7"),
+            key: String::from(PROVENANCE_PROP),
+            value: serde_json::to_value(provenance.for_page("index.md", "xxh64:1")).unwrap(),
+            version: responses::Version {
+                message: String::from(""),
+                number: 1,
+            },
+        };
+
+        assert_eq!(
+            provenance_update(
+                std::slice::from_ref(&existing),
+                &provenance.for_page("index.md", "xxh64:1")
+            ),
+            None
+        );
+        let update = provenance_update(
+            std::slice::from_ref(&existing),
+            &provenance.for_page("index.md", "xxh64:2"),
+        )
+        .unwrap();
+        assert_eq!(update.id, "7");
+        assert_eq!(update.version.number, 2);
+        assert!(provenance_update(&[], &provenance.for_page("index.md", "xxh64:1")).is_some());
+    }
+}
@@ -0,0 +1,222 @@
+//! Pages that are written before they may be published, with the `publish_after` front matter key.
+//!
+//! A scheduled page is parsed and rendered like any other page, so its mistakes show up before the
+//! launch, but it isn't created or updated in Confluence until that time has passed. The pages
+//! below a scheduled page wait for it.
+//!
+//! Pages with `publish: false` (or below an `index.md` or `_defaults.md` with it) are parsed and
+//! their links are checked, but they are never published.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use saphyr::Yaml;
+
+use crate::{error::Result, markdown_page::MarkdownPage, parent::get_parent_file};
+
+/// Parses a time like `2024-07-01T09:00Z`, `2024-07-01T09:00:00+02:00` or `2024-07-01`. Times
+/// without an offset are UTC.
+fn parse_time(s: &str) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Some(time.with_timezone(&Utc));
+    }
+    let without_zone = s.strip_suffix(['Z', 'z']).unwrap_or(s);
+    ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(without_zone, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .map(|time| time.and_utc())
+}
+
+/// The `publish_after` front matter key.
+pub fn parse_publish_after(yaml: &Yaml) -> Result<Option<DateTime<Utc>>> {
+    match yaml {
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        Yaml::String(s) => parse_time(s).map(Some).ok_or(anyhow!(
+            "Failed to parse \"publish_after\" key (should be a time like 2024-07-01T09:00Z)"
+        )),
+        _ => Err(anyhow!(
+            "Failed to parse \"publish_after\" key (should be a time like 2024-07-01T09:00Z)"
+        )),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PublishSchedule {
+    /// Source of every page that can't be published yet, to the time it can.
+    pending: BTreeMap<PathBuf, DateTime<Utc>>,
+    /// Source of every page that isn't published at all.
+    unpublished: BTreeSet<PathBuf>,
+}
+
+impl PublishSchedule {
+    pub fn new(markdown_pages: &[MarkdownPage], now: DateTime<Utc>) -> Self {
+        let publish_after: BTreeMap<PathBuf, DateTime<Utc>> = markdown_pages
+            .iter()
+            .filter_map(|page| {
+                Some((
+                    PathBuf::from(&page.source),
+                    page.front_matter.publish_after?,
+                ))
+            })
+            .collect();
+        let pending = markdown_pages
+            .iter()
+            .filter_map(|page| {
+                let source = PathBuf::from(&page.source);
+                let after = Self::inherited(&source, &publish_after)?;
+                (after > now).then_some((source, after))
+            })
+            .collect();
+        let unpublished_pages: BTreeSet<PathBuf> = markdown_pages
+            .iter()
+            .filter(|page| !page.front_matter.publish)
+            .map(|page| PathBuf::from(&page.source))
+            .collect();
+        let unpublished = markdown_pages
+            .iter()
+            .map(|page| PathBuf::from(&page.source))
+            .filter(|source| Self::is_below(source, &unpublished_pages))
+            .collect();
+        PublishSchedule {
+            pending,
+            unpublished,
+        }
+    }
+
+    /// Whether the page or a page above it is in `sources`.
+    fn is_below(source: &Path, sources: &BTreeSet<PathBuf>) -> bool {
+        sources.contains(source)
+            || get_parent_file(source)
+                .is_some_and(|parent| parent != source && Self::is_below(&parent, sources))
+    }
+
+    /// The latest `publish_after` of the page and the pages above it.
+    fn inherited(
+        source: &Path,
+        publish_after: &BTreeMap<PathBuf, DateTime<Utc>>,
+    ) -> Option<DateTime<Utc>> {
+        let own = publish_after.get(source).copied();
+        let parent = get_parent_file(source).and_then(|parent| {
+            (parent != source)
+                .then(|| Self::inherited(&parent, publish_after))
+                .flatten()
+        });
+        own.max(parent)
+    }
+
+    /// When the page from `source` can be published, `None` when it can be now.
+    pub fn publish_after(&self, source: &str) -> Option<DateTime<Utc>> {
+        self.pending.get(Path::new(source)).copied()
+    }
+
+    pub fn is_published(&self, source: &str) -> bool {
+        !self.unpublished.contains(Path::new(source))
+    }
+
+    /// Sources of the pages that aren't created, as they can't be published yet or at all.
+    pub fn sources(&self) -> impl Iterator<Item = &PathBuf> {
+        self.pending.keys().chain(self.unpublished.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::TestResult, test_helpers::markdown_page_from_str};
+
+    use super::*;
+
+    #[test]
+    fn it_parses_times() {
+        let expected = NaiveDate::from_ymd_opt(2024, 7, 1)
+            .and_then(|date| date.and_hms_opt(9, 0, 0))
+            .map(|time| time.and_utc());
+
+        assert_eq!(parse_time("2024-07-01T09:00Z"), expected);
+        assert_eq!(parse_time("2024-07-01T09:00:00Z"), expected);
+        assert_eq!(parse_time("2024-07-01T11:00:00+02:00"), expected);
+        assert_eq!(parse_time("2024-07-01 09:00"), expected);
+        assert_eq!(
+            parse_time("2024-07-01"),
+            NaiveDate::from_ymd_opt(2024, 7, 1)
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .map(|time| time.and_utc())
+        );
+        assert_eq!(parse_time("next week"), None);
+    }
+
+    #[test]
+    fn it_schedules_pages_and_their_children() -> TestResult {
+        let pages: Vec<MarkdownPage> = [
+            ("index.md", "# Home\n"),
+            (
+                "release/index.md",
+                "---\npublish_after: 2024-07-01T09:00Z\n---\n# Release\n",
+            ),
+            ("release/notes.md", "# Notes\n"),
+            (
+                "launched.md",
+                "---\npublish_after: 2024-01-01\n---\n# Launched\n",
+            ),
+        ]
+        .into_iter()
+        .map(|(source, content)| markdown_page_from_str(source, content))
+        .collect::<Result<_>>()?;
+        let now = parse_time("2024-06-01T00:00Z").unwrap();
+
+        let schedule = PublishSchedule::new(&pages, now);
+
+        assert_eq!(schedule.publish_after("index.md"), None);
+        assert_eq!(schedule.publish_after("launched.md"), None);
+        assert_eq!(
+            schedule.publish_after("release/index.md"),
+            parse_time("2024-07-01T09:00Z")
+        );
+        assert_eq!(
+            schedule.publish_after("release/notes.md"),
+            parse_time("2024-07-01T09:00Z")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_unpublished_directories_out() -> TestResult {
+        let pages: Vec<MarkdownPage> = [
+            ("index.md", "# Home\n"),
+            (
+                "internal/index.md",
+                "---\npublish: false\n---\n# Internal\n",
+            ),
+            ("internal/oncall.md", "# On Call\n"),
+            ("guide.md", "# Guide\n"),
+        ]
+        .into_iter()
+        .map(|(source, content)| markdown_page_from_str(source, content))
+        .collect::<Result<_>>()?;
+
+        let schedule = PublishSchedule::new(&pages, Utc::now());
+
+        assert!(schedule.is_published("index.md"));
+        assert!(schedule.is_published("guide.md"));
+        assert!(!schedule.is_published("internal/index.md"));
+        assert!(!schedule.is_published("internal/oncall.md"));
+        assert_eq!(
+            schedule.sources().collect::<Vec<_>>(),
+            vec![
+                Path::new("internal/index.md"),
+                Path::new("internal/oncall.md")
+            ]
+        );
+
+        Ok(())
+    }
+}
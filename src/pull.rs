@@ -0,0 +1,1188 @@
+//! The `pull` subcommand (or `export`): writes an existing Confluence space to a directory of
+//! markdown, to start a marked-space repository from it. Pages become files mirroring the page
+//! tree (pages with children get a directory with an `index.md`), their storage format is turned
+//! into markdown, images and attachments are downloaded next to them and links between pages
+//! become links between the files. Markup without a markdown equivalent, like most macros, is
+//! kept as storage format, which marked-space publishes as it is.
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    confluence_page::{ConfluenceNode, ConfluenceNodeType},
+    confluence_paginator::ConfluencePaginator,
+    confluence_space::ConfluenceSpace,
+    console::{print_info, print_status, print_warning, Status},
+    error::{ApiResponse, Result},
+    markdown_space::is_valid_space_key,
+    responses::{self, BodySingle, ContentStatus, MultiEntityResult, PageSingleWithBody},
+};
+
+static TOKEN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?s)<!\[CDATA\[(.*?)\]\]>|<!--.*?-->|<(/?)([A-Za-z][\w:.-]*)((?:\s+[\w:.-]+\s*=\s*(?:"[^"]*"|'[^']*'))*)\s*(/?)>"#)
+        .unwrap()
+});
+static ATTRIBUTE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"([\w:.-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+static PAGE_HREF: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^https?://([^/]+)/wiki/(?:spaces/[^/]+/pages/(\d+)[^#]*|pages/viewpage\.action\?pageId=(\d+)[^#]*)(#.*)?$")
+        .unwrap()
+});
+static TERA_DELIMITER: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{[{%#]").unwrap());
+
+/// An element or text of the storage format, with where it is in the storage format.
+#[derive(Debug)]
+enum StorageNode {
+    Element {
+        name: String,
+        attributes: Vec<(String, String)>,
+        children: Vec<StorageNode>,
+        span: (usize, usize),
+    },
+    Text(String),
+}
+
+impl StorageNode {
+    fn name(&self) -> &str {
+        match self {
+            StorageNode::Element { name, .. } => name,
+            StorageNode::Text(_) => "",
+        }
+    }
+
+    fn attribute(&self, key: &str) -> Option<&str> {
+        match self {
+            StorageNode::Element { attributes, .. } => attributes
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.as_str()),
+            StorageNode::Text(_) => None,
+        }
+    }
+
+    fn children(&self) -> &[StorageNode] {
+        match self {
+            StorageNode::Element { children, .. } => children,
+            StorageNode::Text(_) => &[],
+        }
+    }
+
+    fn child(&self, name: &str) -> Option<&StorageNode> {
+        self.children().iter().find(|child| child.name() == name)
+    }
+
+    /// The text of the node and all of its children.
+    fn text(&self) -> String {
+        match self {
+            StorageNode::Text(text) => text.clone(),
+            StorageNode::Element { children, .. } => children.iter().map(Self::text).collect(),
+        }
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    static ENTITY: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"&(#x[0-9a-fA-F]+|#[0-9]+|\w+);").unwrap());
+    ENTITY
+        .replace_all(text, |captures: &regex::Captures| {
+            let entity = &captures[1];
+            let decoded = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ => match entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                    None => entity
+                        .strip_prefix('#')
+                        .and_then(|n| n.parse().ok())
+                        .and_then(char::from_u32),
+                },
+            };
+            // other named entities work in markdown too
+            decoded.map_or(captures[0].to_string(), String::from)
+        })
+        .into_owned()
+}
+
+/// Parses the storage format, which is XHTML with Confluence elements, into a tree. Closing tags
+/// that don't match are skipped, rather than failing on a page that Confluence shows fine.
+fn parse_storage(storage: &str) -> Vec<StorageNode> {
+    struct Open {
+        name: String,
+        attributes: Vec<(String, String)>,
+        children: Vec<StorageNode>,
+        start: usize,
+    }
+    fn close(stack: &mut Vec<Open>, root: &mut Vec<StorageNode>, end: usize) {
+        let open = stack.pop().unwrap();
+        let element = StorageNode::Element {
+            name: open.name,
+            attributes: open.attributes,
+            children: open.children,
+            span: (open.start, end),
+        };
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(element),
+            None => root.push(element),
+        }
+    }
+
+    let mut root = Vec::default();
+    let mut stack: Vec<Open> = Vec::default();
+    let mut last = 0;
+    let push = |stack: &mut Vec<Open>, root: &mut Vec<StorageNode>, node: StorageNode| match stack
+        .last_mut()
+    {
+        Some(parent) => parent.children.push(node),
+        None => root.push(node),
+    };
+    for captures in TOKEN.captures_iter(storage) {
+        let token = captures.get(0).unwrap();
+        if token.start() > last {
+            let text = decode_entities(&storage[last..token.start()]);
+            push(&mut stack, &mut root, StorageNode::Text(text));
+        }
+        last = token.end();
+        if let Some(cdata) = captures.get(1) {
+            push(
+                &mut stack,
+                &mut root,
+                StorageNode::Text(cdata.as_str().to_string()),
+            );
+            continue;
+        }
+        let Some(name) = captures.get(3) else {
+            // a comment
+            continue;
+        };
+        let name = name.as_str();
+        if &captures[2] == "/" {
+            if let Some(position) = stack.iter().rposition(|open| open.name == name) {
+                while stack.len() > position + 1 {
+                    close(&mut stack, &mut root, token.start());
+                }
+                close(&mut stack, &mut root, token.end());
+            }
+            continue;
+        }
+        let attributes = ATTRIBUTE
+            .captures_iter(&captures[4])
+            .map(|attribute| {
+                let value = attribute.get(2).or(attribute.get(3)).unwrap().as_str();
+                (attribute[1].to_string(), decode_entities(value))
+            })
+            .collect();
+        let void = matches!(name, "br" | "hr" | "img" | "col");
+        if &captures[5] == "/" || void {
+            push(
+                &mut stack,
+                &mut root,
+                StorageNode::Element {
+                    name: name.to_string(),
+                    attributes,
+                    children: Vec::default(),
+                    span: (token.start(), token.end()),
+                },
+            );
+        } else {
+            stack.push(Open {
+                name: name.to_string(),
+                attributes,
+                children: Vec::default(),
+                start: token.start(),
+            });
+        }
+    }
+    if last < storage.len() {
+        let text = decode_entities(&storage[last..]);
+        push(&mut stack, &mut root, StorageNode::Text(text));
+    }
+    while !stack.is_empty() {
+        close(&mut stack, &mut root, storage.len());
+    }
+    root
+}
+
+fn is_block(name: &str) -> bool {
+    matches!(
+        name,
+        "p" | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "ul"
+            | "ol"
+            | "table"
+            | "pre"
+            | "blockquote"
+            | "hr"
+            | "div"
+            | "section"
+            | "ac:structured-macro"
+            | "ac:task-list"
+            | "ac:layout"
+            | "ac:layout-section"
+            | "ac:layout-cell"
+            | "ac:adf-extension"
+    )
+}
+
+/// Escapes the characters that would make text markdown.
+fn escape_markdown(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut escaped = String::with_capacity(text.len());
+    for (i, c) in chars.iter().enumerate() {
+        let in_word = |j: Option<usize>| {
+            j.and_then(|j| chars.get(j))
+                .is_some_and(|c| c.is_alphanumeric())
+        };
+        let escape = match c {
+            '\\' | '`' | '*' | '[' | ']' | '<' => true,
+            // snake_case reads fine without escapes
+            '_' => !(in_word(i.checked_sub(1)) && in_word(Some(i + 1))),
+            _ => false,
+        };
+        if escape {
+            escaped.push('\\');
+        }
+        escaped.push(*c);
+    }
+    escaped
+}
+
+/// Escapes what would start a block at the start of a paragraph, like a heading or a list.
+fn escape_line_start(text: String) -> String {
+    static BLOCK_START: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(#|>|[-+] |\d+[.)] )").unwrap());
+    if BLOCK_START.is_match(&text) {
+        format!("\\{}", text)
+    } else {
+        text
+    }
+}
+
+fn link_target(target: &str) -> String {
+    if target.contains([' ', '(', ')', '<', '>']) {
+        format!("<{}>", target.replace('<', "%3C").replace('>', "%3E"))
+    } else {
+        target.to_string()
+    }
+}
+
+fn fence(body: &str, language: &str) -> String {
+    let mut backticks = String::from("```");
+    while body.contains(&backticks) {
+        backticks.push('`');
+    }
+    format!(
+        "{}{}\n{}\n{}",
+        backticks,
+        language,
+        body.trim_end_matches('\n'),
+        backticks
+    )
+}
+
+/// `path` relative to the directory `from`, both relative to the space directory.
+fn relative_path(from: &Path, path: &Path) -> String {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = path.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for _ in common..from.len() {
+        relative.push("..");
+    }
+    for component in &to[common..] {
+        relative.push(component);
+    }
+    relative.to_string_lossy().replace('\\', "/")
+}
+
+/// Where the pages of the space go, for the links between them.
+#[derive(Debug, Default)]
+pub struct PagePaths {
+    pub by_id: BTreeMap<String, PathBuf>,
+    by_title: BTreeMap<String, PathBuf>,
+    host: String,
+}
+
+impl PagePaths {
+    fn slug(title: &str, id: &str) -> String {
+        let mut slug = String::default();
+        for c in title.to_lowercase().chars() {
+            if c.is_alphanumeric() {
+                slug.push(c);
+            } else if !slug.ends_with('-') {
+                slug.push('-');
+            }
+        }
+        match slug.trim_matches('-') {
+            "" => format!("page-{}", id),
+            slug => slug.to_string(),
+        }
+    }
+
+    /// The homepage is `index.md`, pages with children (and folders) get a directory with an
+    /// `index.md`, other pages a file named after their title. Pages whose parent isn't in the
+    /// space, e.g. pages at the top of the space, go under the homepage.
+    pub fn new(nodes: &[ConfluenceNode], homepage_id: &str, host: &str) -> Self {
+        let ids: HashSet<&str> = nodes.iter().map(|node| node.id.as_str()).collect();
+        let mut children: BTreeMap<&str, Vec<&ConfluenceNode>> = BTreeMap::default();
+        for node in nodes.iter().filter(|node| node.id != homepage_id) {
+            let parent = node
+                .parent_id
+                .as_deref()
+                .filter(|parent| ids.contains(parent))
+                .unwrap_or(homepage_id);
+            children.entry(parent).or_default().push(node);
+        }
+        let mut paths = PagePaths {
+            host: String::from(host),
+            ..Default::default()
+        };
+        let homepage_title = nodes
+            .iter()
+            .find(|node| node.id == homepage_id)
+            .map(|node| node.title.clone())
+            .unwrap_or_default();
+        paths.insert(homepage_id, &homepage_title, PathBuf::from("index.md"));
+        let mut queue = vec![(homepage_id, PathBuf::new())];
+        let mut visited = HashSet::from([homepage_id]);
+        while let Some((parent, dir)) = queue.pop() {
+            let mut taken = HashSet::from([String::from("index")]);
+            for node in children.get(parent).into_iter().flatten() {
+                if !visited.insert(node.id.as_str()) {
+                    continue;
+                }
+                let slug = Self::slug(&node.title, &node.id);
+                let mut name = slug.clone();
+                let mut n = 2;
+                while !taken.insert(name.clone()) {
+                    name = format!("{}-{}", slug, n);
+                    n += 1;
+                }
+                let is_folder = matches!(node.data, ConfluenceNodeType::Folder(_));
+                if is_folder || children.contains_key(node.id.as_str()) {
+                    let child_dir = dir.join(&name);
+                    paths.insert(&node.id, &node.title, child_dir.join("index.md"));
+                    queue.push((&node.id, child_dir));
+                } else {
+                    paths.insert(&node.id, &node.title, dir.join(format!("{}.md", name)));
+                }
+            }
+        }
+        paths
+    }
+
+    fn insert(&mut self, id: &str, title: &str, path: PathBuf) {
+        self.by_id.insert(String::from(id), path.clone());
+        self.by_title.insert(String::from(title), path);
+    }
+
+    /// The file a link to a page of the site points at, with its anchor.
+    fn linked_file(&self, href: &str) -> Option<(&PathBuf, String)> {
+        let captures = PAGE_HREF.captures(href)?;
+        if !captures[1].eq_ignore_ascii_case(&self.host) {
+            return None;
+        }
+        let id = captures.get(2).or(captures.get(3))?.as_str();
+        let anchor = captures.get(4).map_or("", |anchor| anchor.as_str());
+        self.by_id.get(id).map(|path| (path, anchor.to_string()))
+    }
+}
+
+/// Turns the storage format of a page into markdown.
+pub struct MarkdownConverter<'a> {
+    storage: &'a str,
+    paths: &'a PagePaths,
+    /// The file of the page.
+    path: &'a Path,
+    /// The attachments of the page that are linked, to download next to the file.
+    attachments: RefCell<BTreeSet<String>>,
+}
+
+impl<'a> MarkdownConverter<'a> {
+    pub fn new(storage: &'a str, paths: &'a PagePaths, path: &'a Path) -> Self {
+        MarkdownConverter {
+            storage,
+            paths,
+            path,
+            attachments: RefCell::default(),
+        }
+    }
+
+    fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new(""))
+    }
+
+    /// Where the attachments of the page go, relative to the space directory.
+    pub fn attachment_dir(path: &Path) -> PathBuf {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        path.parent()
+            .unwrap_or(Path::new(""))
+            .join("assets")
+            .join(stem.as_ref())
+    }
+
+    fn attachment_link(&self, filename: &str) -> String {
+        self.attachments.borrow_mut().insert(filename.to_string());
+        let path = Self::attachment_dir(self.path).join(filename);
+        link_target(&relative_path(self.dir(), &path))
+    }
+
+    fn page_link(&self, path: &Path, anchor: &str) -> String {
+        link_target(&format!("{}{}", relative_path(self.dir(), path), anchor))
+    }
+
+    /// The storage format of a node as it is, for markup markdown can't express.
+    fn raw(&self, node: &StorageNode) -> String {
+        match node {
+            StorageNode::Element {
+                span: (start, end), ..
+            } => {
+                // a blank line would end the HTML block in markdown
+                static BLANK_LINES: Lazy<Regex> = Lazy::new(|| Regex::new(r"\n\s*\n").unwrap());
+                BLANK_LINES
+                    .replace_all(&self.storage[*start..*end], "\n")
+                    .into_owned()
+            }
+            StorageNode::Text(text) => escape_markdown(text),
+        }
+    }
+
+    /// The markdown of the page, with the linked attachments.
+    pub fn convert(self) -> (String, BTreeSet<String>) {
+        let nodes = parse_storage(self.storage);
+        let markdown = self.blocks(&nodes, "\n\n");
+        (markdown, self.attachments.into_inner())
+    }
+
+    fn blocks(&self, nodes: &[StorageNode], separator: &str) -> String {
+        self.block_refs(&nodes.iter().collect::<Vec<_>>(), separator)
+    }
+
+    fn block(&self, node: &StorageNode) -> String {
+        let name = node.name();
+        match name {
+            "p" => escape_line_start(self.inline(node.children()).trim().to_string()),
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = name[1..].parse().unwrap_or(1);
+                format!(
+                    "{} {}",
+                    "#".repeat(level),
+                    self.inline(node.children()).trim()
+                )
+            }
+            "ul" | "ol" => self.list(node, name == "ol"),
+            "table" => self.table(node),
+            "pre" => fence(&node.text(), ""),
+            "blockquote" => prefix_lines(&self.blocks(node.children(), "\n\n"), "> "),
+            "hr" => String::from("---"),
+            "div" | "section" | "ac:layout" | "ac:layout-section" | "ac:layout-cell" => {
+                self.blocks(node.children(), "\n\n")
+            }
+            "ac:structured-macro" => self.structured_macro(node),
+            "ac:task-list" => self.task_list(node),
+            _ => self.raw(node),
+        }
+    }
+
+    fn list(&self, node: &StorageNode, ordered: bool) -> String {
+        let start: usize = node
+            .attribute("start")
+            .and_then(|start| start.parse().ok())
+            .unwrap_or(1);
+        node.children()
+            .iter()
+            .filter(|child| child.name() == "li")
+            .enumerate()
+            .map(|(i, item)| {
+                let marker = if ordered {
+                    format!("{}. ", start + i)
+                } else {
+                    String::from("- ")
+                };
+                let content = self.blocks(item.children(), "\n");
+                indent_item(&marker, &content)
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn task_list(&self, node: &StorageNode) -> String {
+        node.children()
+            .iter()
+            .filter(|child| child.name() == "ac:task")
+            .map(|task| {
+                let done = task
+                    .child("ac:task-status")
+                    .is_some_and(|status| status.text().trim() == "complete");
+                let body = task
+                    .child("ac:task-body")
+                    .map(|body| self.blocks(body.children(), "\n"))
+                    .unwrap_or_default();
+                indent_item(if done { "- [x] " } else { "- [ ] " }, &body)
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn table(&self, node: &StorageNode) -> String {
+        let mut rows: Vec<&StorageNode> = Vec::default();
+        for child in node.children() {
+            match child.name() {
+                "tr" => rows.push(child),
+                "thead" | "tbody" | "tfoot" => {
+                    rows.extend(child.children().iter().filter(|row| row.name() == "tr"))
+                }
+                _ => {}
+            }
+        }
+        let cells: Vec<Vec<&StorageNode>> = rows
+            .iter()
+            .map(|row| {
+                row.children()
+                    .iter()
+                    .filter(|cell| matches!(cell.name(), "td" | "th"))
+                    .collect()
+            })
+            .collect();
+        // cells with lists, tables or merged cells don't fit a markdown table
+        let complex = cells.iter().flatten().any(|cell| {
+            cell.attribute("colspan").is_some_and(|span| span != "1")
+                || cell.attribute("rowspan").is_some_and(|span| span != "1")
+                || cell
+                    .children()
+                    .iter()
+                    .any(|child| is_block(child.name()) && child.name() != "p")
+        });
+        let columns = cells.iter().map(Vec::len).max().unwrap_or(0);
+        if complex || columns == 0 {
+            return self.raw(node);
+        }
+        let render_row = |row: &Vec<&StorageNode>| {
+            let mut texts: Vec<String> = row
+                .iter()
+                .map(|cell| {
+                    self.blocks(cell.children(), "<br/>")
+                        .replace('\n', " ")
+                        .replace('|', "\\|")
+                })
+                .collect();
+            texts.resize(columns, String::default());
+            format!("| {} |", texts.join(" | "))
+        };
+        let mut lines = vec![
+            render_row(&cells[0]),
+            format!("|{}", " --- |".repeat(columns)),
+        ];
+        lines.extend(cells[1..].iter().map(render_row));
+        lines.join("\n")
+    }
+
+    fn structured_macro(&self, node: &StorageNode) -> String {
+        let parameter = |name: &str| {
+            node.children()
+                .iter()
+                .find(|child| {
+                    child.name() == "ac:parameter" && child.attribute("ac:name") == Some(name)
+                })
+                .map(|parameter| parameter.text())
+        };
+        let macro_name = node.attribute("ac:name").unwrap_or_default();
+        match macro_name {
+            "code" | "noformat" => {
+                let body = node
+                    .child("ac:plain-text-body")
+                    .map(StorageNode::text)
+                    .unwrap_or_default();
+                fence(&body, &parameter("language").unwrap_or_default())
+            }
+            "info" | "tip" | "note" | "warning" | "expand" => {
+                let Some(body) = node.child("ac:rich-text-body") else {
+                    return self.raw(node);
+                };
+                // the inverse of the panels alerts become
+                let alert = match macro_name {
+                    "info" => "NOTE",
+                    "tip" => "TIP",
+                    "note" => "WARNING",
+                    "warning" => "CAUTION",
+                    _ => "note",
+                };
+                let mut children: Vec<&StorageNode> = body.children().iter().collect();
+                let mut title = parameter("title");
+                // alerts published by marked-space start with their title in bold
+                if let Some(first) = children
+                    .iter()
+                    .position(|child| !child.text().trim().is_empty())
+                {
+                    let bold_title = match children[first].children() {
+                        [strong] if children[first].name() == "p" && strong.name() == "strong" => {
+                            Some(strong.text())
+                        }
+                        _ => None,
+                    };
+                    if let Some(bold_title) = bold_title.filter(|_| macro_name != "expand") {
+                        children.remove(first);
+                        let default = alert[..1].to_string() + &alert[1..].to_lowercase();
+                        if title.is_none() && bold_title.trim() != default {
+                            title = Some(bold_title);
+                        }
+                    }
+                }
+                let header = match (macro_name, title) {
+                    ("expand", title) => format!("[!note][expand] {}", title.unwrap_or_default()),
+                    (_, Some(title)) => format!("[!{}] {}", alert, title),
+                    (_, None) => format!("[!{}]", alert),
+                };
+                let content = self.block_refs(&children, "\n\n");
+                prefix_lines(&format!("{}\n{}", header.trim_end(), content), "> ")
+            }
+            _ => self.raw(node),
+        }
+    }
+
+    fn inline(&self, nodes: &[StorageNode]) -> String {
+        self.inline_nodes(nodes.iter())
+    }
+
+    fn inline_nodes<'n>(&self, nodes: impl Iterator<Item = &'n StorageNode>) -> String {
+        let mut text = String::default();
+        for node in nodes {
+            text += &self.inline_node(node);
+        }
+        text
+    }
+
+    fn inline_node(&self, node: &StorageNode) -> String {
+        static WHITESPACE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\s+").unwrap());
+        let wrap = |marker: &str| {
+            let content = self.inline(node.children());
+            if content.trim().is_empty() {
+                return content;
+            }
+            // markers have to touch the text they emphasize
+            let leading = &content[..content.len() - content.trim_start().len()];
+            let trailing = &content[content.trim_end().len()..];
+            format!(
+                "{}{}{}{}{}",
+                leading,
+                marker,
+                content.trim(),
+                marker,
+                trailing
+            )
+        };
+        match node {
+            StorageNode::Text(text) => escape_markdown(&WHITESPACE.replace_all(text, " ")),
+            StorageNode::Element { name, .. } => match name.as_str() {
+                "strong" | "b" => wrap("**"),
+                "em" | "i" => wrap("*"),
+                "s" | "del" => wrap("~~"),
+                "code" => {
+                    let code = node.text();
+                    let ticks = if code.contains('`') { "``" } else { "`" };
+                    format!("{}{}{}", ticks, code, ticks)
+                }
+                "br" => String::from("<br/>"),
+                "span" | "p" => self.inline(node.children()),
+                "a" => {
+                    let href = node.attribute("href").unwrap_or_default();
+                    let target = match self.paths.linked_file(href) {
+                        Some((path, anchor)) => self.page_link(path, &anchor),
+                        None => link_target(href),
+                    };
+                    format!("[{}]({})", self.inline(node.children()).trim(), target)
+                }
+                "ac:link" => self.confluence_link(node),
+                "ac:image" => self.image(node),
+                "ac:emoticon" => node
+                    .attribute("ac:emoji-fallback")
+                    .map(String::from)
+                    .unwrap_or(format!(
+                        ":{}:",
+                        node.attribute("ac:name").unwrap_or_default()
+                    )),
+                _ => self.raw(node),
+            },
+        }
+    }
+
+    fn confluence_link(&self, node: &StorageNode) -> String {
+        let body = node
+            .child("ac:plain-text-link-body")
+            .map(|body| escape_markdown(&body.text()))
+            .or(node
+                .child("ac:link-body")
+                .map(|body| self.inline(body.children())))
+            .filter(|body| !body.trim().is_empty());
+        let anchor = node
+            .attribute("ac:anchor")
+            .map_or(String::default(), |anchor| format!("#{}", anchor));
+        if let Some(page) = node.child("ri:page") {
+            let title = page.attribute("ri:content-title").unwrap_or_default();
+            let other_space = page.attribute("ri:space-key").is_some();
+            if let Some(path) = self.paths.by_title.get(title).filter(|_| !other_space) {
+                return format!(
+                    "[{}]({})",
+                    body.unwrap_or(escape_markdown(title)),
+                    self.page_link(path, &anchor)
+                );
+            }
+        } else if let Some(filename) = node
+            .child("ri:attachment")
+            .and_then(|attachment| attachment.attribute("ri:filename"))
+            .filter(|filename| is_plain_filename(filename))
+        {
+            return format!(
+                "[{}]({})",
+                body.unwrap_or(escape_markdown(filename)),
+                self.attachment_link(filename)
+            );
+        }
+        self.raw(node)
+    }
+
+    fn image(&self, node: &StorageNode) -> String {
+        let alt = escape_markdown(node.attribute("ac:alt").unwrap_or_default());
+        if let Some(url) = node
+            .child("ri:url")
+            .and_then(|url| url.attribute("ri:value"))
+        {
+            return format!("![{}]({})", alt, link_target(url));
+        }
+        match node.child("ri:attachment") {
+            // attachments of other pages stay as they are
+            Some(attachment) if attachment.child("ri:page").is_none() => {
+                match attachment
+                    .attribute("ri:filename")
+                    .filter(|filename| is_plain_filename(filename))
+                {
+                    Some(filename) => format!("![{}]({})", alt, self.attachment_link(filename)),
+                    None => self.raw(node),
+                }
+            }
+            _ => self.raw(node),
+        }
+    }
+
+    fn block_refs(&self, nodes: &[&StorageNode], separator: &str) -> String {
+        let mut blocks = Vec::default();
+        let mut inline_run: Vec<&StorageNode> = Vec::default();
+        let flush = |inline_run: &mut Vec<&StorageNode>, blocks: &mut Vec<String>| {
+            let text = self.inline_nodes(inline_run.drain(..));
+            let text = text.trim();
+            if !text.is_empty() {
+                blocks.push(escape_line_start(text.to_string()));
+            }
+        };
+        for node in nodes {
+            if is_block(node.name()) {
+                flush(&mut inline_run, &mut blocks);
+                let block = self.block(node);
+                if !block.trim().is_empty() {
+                    blocks.push(block);
+                }
+            } else {
+                inline_run.push(node);
+            }
+        }
+        flush(&mut inline_run, &mut blocks);
+        blocks.join(separator)
+    }
+}
+
+fn prefix_lines(text: &str, prefix: &str) -> String {
+    text.lines()
+        .map(|line| {
+            if line.is_empty() {
+                prefix.trim_end().to_string()
+            } else {
+                format!("{}{}", prefix, line)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// A list item: the marker before the first line, the other lines indented to line up with it.
+fn indent_item(marker: &str, content: &str) -> String {
+    let indent = " ".repeat(marker.len());
+    let mut lines = content.lines();
+    let mut item = format!("{}{}", marker, lines.next().unwrap_or_default())
+        .trim_end()
+        .to_string();
+    for line in lines {
+        item.push('\n');
+        if !line.is_empty() {
+            item.push_str(&indent);
+            item.push_str(line);
+        }
+    }
+    item
+}
+
+/// Stops tera from reading text of the page as template syntax, as every file is a template.
+fn escape_tera(markdown: &str) -> String {
+    TERA_DELIMITER
+        .replace_all(markdown, |captures: &regex::Captures| {
+            format!("{{{{ \"{}\" }}}}", &captures[0])
+        })
+        .into_owned()
+}
+
+/// The markdown file of a page: labels in the front matter, the title and the converted body.
+pub fn page_markdown(title: &str, labels: &[String], body: &str) -> String {
+    let front_matter = match labels {
+        [] => String::default(),
+        labels => format!("---\nlabels: [{}]\n---\n", labels.join(", ")),
+    };
+    let body = body.trim();
+    let body = if body.is_empty() {
+        String::default()
+    } else {
+        format!("\n{}\n", body)
+    };
+    escape_tera(&format!(
+        "{}# {}\n{}",
+        front_matter,
+        escape_markdown(title),
+        body
+    ))
+}
+
+fn page_labels(confluence_client: &ConfluenceClient, page_id: &str) -> Result<Vec<String>> {
+    let labels: MultiEntityResult<responses::Label> = confluence_client
+        .get_page_labels(page_id)?
+        .api_result()?
+        .json()?;
+    Ok(labels
+        .results
+        .into_iter()
+        .filter(|label| label.prefix.is_empty() || label.prefix == "global")
+        .map(|label| label.name)
+        .collect())
+}
+
+/// Whether an attachment name from the storage format can be written into the attachment
+/// directory as it is: a single file name, not a path that leaves the directory.
+fn is_plain_filename(filename: &str) -> bool {
+    !filename.contains(['/', '\\'])
+        && matches!(
+            Path::new(filename).components().collect::<Vec<_>>()[..],
+            [Component::Normal(_)]
+        )
+}
+
+fn download_attachments(
+    confluence_client: &ConfluenceClient,
+    page_id: &str,
+    filenames: &BTreeSet<String>,
+    dir: &Path,
+) -> Result<()> {
+    if filenames.is_empty() {
+        return Ok(());
+    }
+    let attachments: Vec<responses::Attachment> =
+        ConfluencePaginator::<responses::Attachment>::new(confluence_client)
+            .start(confluence_client.get_attachments(page_id)?)?
+            .collect::<Result<_>>()?;
+    for filename in filenames {
+        if !is_plain_filename(filename) {
+            print_warning(&format!(
+                "attachment {} of page {} isn't a plain file name, not downloading it",
+                filename, page_id
+            ));
+            continue;
+        }
+        let Some(attachment) = attachments
+            .iter()
+            .find(|attachment| &attachment.title == filename)
+        else {
+            print_warning(&format!(
+                "attachment {} of page {} wasn't found",
+                filename, page_id
+            ));
+            continue;
+        };
+        let content = confluence_client
+            .download_attachment(&attachment.download_link)?
+            .api_result()?
+            .bytes()?;
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join(filename), content)?;
+    }
+    Ok(())
+}
+
+pub fn pull(confluence_client: ConfluenceClient, dir: &Path, force: bool) -> Result<()> {
+    let space_key = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if !is_valid_space_key(&space_key) {
+        return Err(anyhow!(
+            "Invalid space directory/key '{}': can only be letters and numbers",
+            space_key
+        ));
+    }
+    if !force
+        && dir
+            .read_dir()
+            .is_ok_and(|mut entries| entries.next().is_some())
+    {
+        return Err(anyhow!(
+            "{} isn't empty, pull into a new directory or pass --force to overwrite its files",
+            dir.display()
+        ));
+    }
+    let space = ConfluenceSpace::get(&confluence_client, &space_key)?;
+    let nodes: Vec<ConfluenceNode> = ConfluenceNode::get_all(&confluence_client, &space)?
+        .into_iter()
+        .filter(|node| match &node.data {
+            ConfluenceNodeType::Page(page_data) => page_data.status == ContentStatus::Current,
+            ConfluenceNodeType::Folder(_) => true,
+        })
+        .collect();
+    let paths = PagePaths::new(&nodes, &space.homepage_id, &confluence_client.hostname);
+
+    let mut pages = 0;
+    for node in &nodes {
+        let Some(path) = paths.by_id.get(&node.id) else {
+            continue;
+        };
+        let markdown = match node.data {
+            ConfluenceNodeType::Folder(_) => format!(
+                "---\nfolder: true\n---\n{}",
+                page_markdown(&node.title, &[], "")
+            ),
+            ConfluenceNodeType::Page(_) => {
+                let page: PageSingleWithBody = confluence_client
+                    .get_page_with_body(&node.id)?
+                    .api_result()?
+                    .json()?;
+                let storage = match page.body {
+                    BodySingle::Storage(body) => body.value,
+                    _ => String::default(),
+                };
+                let (body, attachments) = MarkdownConverter::new(&storage, &paths, path).convert();
+                download_attachments(
+                    &confluence_client,
+                    &node.id,
+                    &attachments,
+                    &dir.join(MarkdownConverter::attachment_dir(path)),
+                )?;
+                page_markdown(
+                    &node.title,
+                    &page_labels(&confluence_client, &node.id)?,
+                    &body,
+                )
+            }
+        };
+        let file = dir.join(path);
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&file, markdown)?;
+        print_status(Status::Created, &path.to_string_lossy());
+        pages += 1;
+    }
+    print_info(&format!(
+        "Pulled {} page(s) of {} into {}",
+        pages,
+        space_key,
+        dir.display()
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::fixture::PathChild;
+
+    use crate::{
+        confluence_page::ConfluencePageData,
+        error::TestResult,
+        responses::{ContentStatus, Version},
+    };
+
+    use super::*;
+
+    fn page(id: &str, title: &str, parent: Option<&str>) -> ConfluenceNode {
+        ConfluenceNode {
+            id: String::from(id),
+            title: String::from(title),
+            parent_id: parent.map(String::from),
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: Version {
+                    message: String::default(),
+                    number: 1,
+                },
+                path: None,
+                status: ContentStatus::Current,
+            }),
+        }
+    }
+
+    #[test]
+    fn it_lays_out_the_page_tree_as_files() {
+        let nodes = vec![
+            page("1", "Home", None),
+            page("2", "Guides", Some("1")),
+            page("3", "Setup & Install", Some("2")),
+            page("4", "FAQ", Some("1")),
+            page("5", "Faq", Some("1")),
+            page("6", "Loose page", None),
+        ];
+        let paths = PagePaths::new(&nodes, "1", "example.atlassian.net");
+
+        let files: Vec<(&str, String)> = paths
+            .by_id
+            .iter()
+            .map(|(id, path)| (id.as_str(), path.to_string_lossy().replace('\\', "/")))
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                ("1", String::from("index.md")),
+                ("2", String::from("guides/index.md")),
+                ("3", String::from("guides/setup-install.md")),
+                ("4", String::from("faq.md")),
+                ("5", String::from("faq-2.md")),
+                ("6", String::from("loose-page.md")),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_converts_the_storage_format_to_markdown() {
+        let nodes = vec![
+            page("1", "Home", None),
+            page("2", "Guides", Some("1")),
+            page("3", "Setup", Some("2")),
+        ];
+        let paths = PagePaths::new(&nodes, "1", "example.atlassian.net");
+        let storage = concat!(
+            "<h2>Intro</h2><p>Some <strong>bold</strong>, <em>slanted</em> and <code>a_b</code> text&nbsp;with a * star.</p>",
+            "<ul><li>one<ul><li>nested</li></ul></li><li><p>two</p></li></ul>",
+            "<p>See <a href=\"https://example.atlassian.net/wiki/spaces/TEST/pages/3/Setup#usage\">setup</a>, ",
+            "<ac:link><ri:page ri:content-title=\"Guides\"/><ac:plain-text-link-body><![CDATA[the guides]]></ac:plain-text-link-body></ac:link> ",
+            "and <a href=\"https://example.com/x\">elsewhere</a>.</p>",
+            "<ac:image ac:alt=\"Diagram\"><ri:attachment ri:filename=\"diagram.png\"/></ac:image>",
+            "<table><tbody><tr><th>Name</th><th>Value</th></tr><tr><td>a|b</td><td><p>1</p></td></tr></tbody></table>",
+            "<ac:structured-macro ac:name=\"code\"><ac:parameter ac:name=\"language\">rust</ac:parameter><ac:plain-text-body><![CDATA[let x = \"{{ y }}\";]]></ac:plain-text-body></ac:structured-macro>",
+            "<ac:structured-macro ac:name=\"info\"><ac:rich-text-body>\n<p><strong>Note</strong></p><p>Read this.</p></ac:rich-text-body></ac:structured-macro>",
+            "<ac:structured-macro ac:name=\"toc\"><ac:parameter ac:name=\"maxLevel\">2</ac:parameter></ac:structured-macro>",
+        );
+
+        let (markdown, attachments) =
+            MarkdownConverter::new(storage, &paths, Path::new("guides/index.md")).convert();
+
+        assert_eq!(
+            markdown,
+            concat!(
+                "## Intro\n\n",
+                "Some **bold**, *slanted* and `a_b` text with a \\* star.\n\n",
+                "- one\n  - nested\n- two\n\n",
+                "See [setup](setup.md#usage), [the guides](index.md) and [elsewhere](https://example.com/x).\n\n",
+                "![Diagram](assets/index/diagram.png)\n\n",
+                "| Name | Value |\n| --- | --- |\n| a\\|b | 1 |\n\n",
+                "```rust\nlet x = \"{{ y }}\";\n```\n\n",
+                "> [!NOTE]\n> Read this.\n\n",
+                "<ac:structured-macro ac:name=\"toc\"><ac:parameter ac:name=\"maxLevel\">2</ac:parameter></ac:structured-macro>",
+            )
+        );
+        assert_eq!(attachments, BTreeSet::from([String::from("diagram.png")]));
+        assert_eq!(
+            page_markdown("Guides", &[String::from("howto")], &markdown)
+                .lines()
+                .take(4)
+                .collect::<Vec<_>>(),
+            vec!["---", "labels: [howto]", "---", "# Guides"]
+        );
+        assert!(page_markdown("Guides", &[], &markdown).contains("let x = \"{{ \"{{\" }} y }}\";"));
+    }
+
+    #[test]
+    fn it_leaves_attachments_that_are_paths_alone() {
+        let paths = PagePaths::new(&[page("1", "Home", None)], "1", "example.atlassian.net");
+        let storage = concat!(
+            "<ac:image ac:alt=\"Key\"><ri:attachment ri:filename=\"../../.ssh/key.png\"/></ac:image>",
+            "<ac:link><ri:attachment ri:filename=\"a/b.pdf\"/></ac:link>",
+        );
+
+        let (markdown, attachments) =
+            MarkdownConverter::new(storage, &paths, Path::new("index.md")).convert();
+
+        assert_eq!(markdown, storage);
+        assert!(attachments.is_empty());
+        assert!(is_plain_filename("diagram.png"));
+        assert!(!is_plain_filename(".."));
+        assert!(!is_plain_filename("..\\key.png"));
+    }
+
+    #[test]
+    fn it_downloads_attachments_from_every_page_of_the_listing() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        server
+            .mock("GET", "/wiki/api/v2/pages/42/attachments")
+            .match_query(mockito::Matcher::Missing)
+            .with_status(200)
+            .with_body(
+                serde_json::json!({
+                    "results": [{"id": "a1", "title": "other.png", "fileId": "f1"}],
+                    "_links": {"next": "/wiki/api/v2/pages/42/attachments?cursor=next"}
+                })
+                .to_string(),
+            )
+            .create();
+        server
+            .mock("GET", "/wiki/api/v2/pages/42/attachments")
+            .match_query(mockito::Matcher::UrlEncoded("cursor".into(), "next".into()))
+            .with_status(200)
+            .with_body(
+                serde_json::json!({"results": [{
+                    "id": "a2", "title": "diagram.png", "fileId": "f2",
+                    "downloadLink": "/download/attachments/42/diagram.png"
+                }]})
+                .to_string(),
+            )
+            .create();
+        let download = server
+            .mock("GET", "/wiki/download/attachments/42/diagram.png")
+            .with_status(200)
+            .with_body("png")
+            .expect(1)
+            .create();
+
+        download_attachments(
+            &client,
+            "42",
+            &BTreeSet::from([String::from("diagram.png")]),
+            temp.child("assets").path(),
+        )?;
+
+        download.assert();
+        assert_eq!(
+            fs::read_to_string(temp.child("assets/diagram.png").path())?,
+            "png"
+        );
+
+        Ok(())
+    }
+}
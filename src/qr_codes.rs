@@ -0,0 +1,101 @@
+//! QR codes for page URLs, e.g. for posters in the lab pointing at a runbook.
+//!
+//! The `qr()` builtin emits an image with a `qr:` url. Instead of a file, the attachment for such
+//! an image is generated when syncing from the current URL of the target, so it follows the page
+//! when it is moved or retitled.
+use std::collections::HashMap;
+
+use qrcode::{render::svg, QrCode};
+
+use crate::error::Result;
+
+pub static QR_CODE_SCHEME: &str = "qr:";
+
+/// `{{ qr(page="runbook.md") }}` for a page of the space (relative to the current page), or
+/// `{{ qr(url="https://...") }}` for any other URL.
+pub fn qr(args: &HashMap<String, serde_json::Value>) -> tera::Result<serde_json::Value> {
+    let target = match (args.get("page"), args.get("url")) {
+        (Some(serde_json::Value::String(page)), None) => page,
+        (None, Some(serde_json::Value::String(url))) => url,
+        _ => {
+            return Err(tera::Error::msg(
+                "qr needs either a 'page' or a 'url' string argument",
+            ))
+        }
+    };
+    let alt = args
+        .get("alt")
+        .and_then(|alt| alt.as_str())
+        .map(String::from)
+        .unwrap_or_else(|| format!("QR code for {}", target));
+    Ok(serde_json::Value::from(format!(
+        "![{}](<{}{}>)",
+        alt, QR_CODE_SCHEME, target
+    )))
+}
+
+pub fn qr_code_svg(url: &str) -> Result<String> {
+    Ok(QrCode::new(url.as_bytes())?
+        .render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{error::TestResult, link_generator::LinkGenerator, markdown_page::page_from_str};
+
+    use super::*;
+
+    #[test]
+    fn it_renders_a_qr_image() -> TestResult {
+        let args = HashMap::from([(
+            String::from("page"),
+            serde_json::Value::from("ops/runbook.md"),
+        )]);
+
+        assert_eq!(
+            qr(&args)?,
+            "![QR code for ops/runbook.md](<qr:ops/runbook.md>)"
+        );
+        assert!(qr(&HashMap::default()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_attaches_qr_codes_for_pages() -> TestResult {
+        let page = page_from_str(
+            "ops/index.md",
+            "# Ops\n{{ qr(page=\"runbook.md\") }}\n{{ qr(url=\"https://example.com\", alt=\"Example\") }}\n",
+        )?;
+
+        assert_eq!(
+            page.attachments
+                .iter()
+                .map(|attachment| (attachment.name.as_str(), attachment.qr_target.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("qr_runbook.md.svg", Some("ops/runbook.md")),
+                ("qr_https___example.com.svg", Some("https://example.com")),
+            ]
+        );
+        assert!(page.warnings.is_empty());
+        assert!(page
+            .render(&LinkGenerator::default_test())?
+            .content
+            .contains(r#"<ac:image ac:align="center" ac:alt="Example"><ri:attachment ri:filename="qr_https___example.com.svg"/></ac:image>"#));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_generates_svg() -> TestResult {
+        let svg = qr_code_svg("https://example.atlassian.net/wiki/x/KgA")?;
+
+        assert!(svg.contains("<svg"));
+
+        Ok(())
+    }
+}
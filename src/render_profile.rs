@@ -0,0 +1,157 @@
+//! `--profile-render`: how long each phase of turning a markdown file into storage format took,
+//! per page and for the whole space, to find the pages that make a sync slow.
+use std::{
+    ops::AddAssign,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{console::print_info, markdown_page::RenderedPage};
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct PhaseTimings {
+    /// Reading the file and its front matter, and those of the files it merges.
+    pub front_matter: Duration,
+    /// Rendering the Tera template of the file (and merged files).
+    pub template: Duration,
+    /// Parsing the markdown and checking the AST (links, headings, images, lint rules).
+    pub parse: Duration,
+    /// Writing the AST as storage format.
+    pub render: Duration,
+    pub checksum: Duration,
+}
+
+impl PhaseTimings {
+    pub fn total(&self) -> Duration {
+        self.front_matter + self.template + self.parse + self.render + self.checksum
+    }
+}
+
+impl AddAssign for PhaseTimings {
+    fn add_assign(&mut self, other: Self) {
+        self.front_matter += other.front_matter;
+        self.template += other.template;
+        self.parse += other.parse;
+        self.render += other.render;
+        self.checksum += other.checksum;
+    }
+}
+
+/// Runs `f`, adding the time it took to `duration`.
+pub fn timed<T>(duration: &mut Duration, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    *duration += started.elapsed();
+    result
+}
+
+/// The timings of the pages rendered during a run.
+#[derive(Debug, Default)]
+pub struct RenderProfile {
+    pages: Mutex<Vec<(String, PhaseTimings)>>,
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.1}", duration.as_secs_f64() * 1000.0)
+}
+
+impl RenderProfile {
+    pub fn record(&self, rendered_page: &RenderedPage) {
+        self.pages
+            .lock()
+            .unwrap()
+            .push((rendered_page.source.clone(), rendered_page.timings));
+    }
+
+    /// A table of the pages, slowest first, in milliseconds, with the totals at the bottom.
+    pub fn render(&self) -> String {
+        let mut pages = self.pages.lock().unwrap().clone();
+        pages.sort_by(|(a_source, a), (b_source, b)| {
+            b.total().cmp(&a.total()).then(a_source.cmp(b_source))
+        });
+        let mut total = PhaseTimings::default();
+        for (_, timings) in &pages {
+            total += *timings;
+        }
+        let total_label = format!("total ({} pages)", pages.len());
+        let width = pages
+            .iter()
+            .map(|(source, _)| source.chars().count())
+            .chain([total_label.len()])
+            .max()
+            .unwrap_or_default();
+        let row = |label: &str, timings: &PhaseTimings| {
+            format!(
+                "  {:width$}  {:>12}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}\n",
+                label,
+                millis(timings.front_matter),
+                millis(timings.template),
+                millis(timings.parse),
+                millis(timings.render),
+                millis(timings.checksum),
+                millis(timings.total()),
+                width = width
+            )
+        };
+        let mut output = format!(
+            "Render profile (ms), slowest first:\n  {:width$}  {:>12}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}\n",
+            "page",
+            "front matter",
+            "template",
+            "parse",
+            "render",
+            "checksum",
+            "total",
+            width = width
+        );
+        for (source, timings) in &pages {
+            output += &row(source, timings);
+        }
+        output += &row(&total_label, &total);
+        output
+    }
+
+    pub fn print(&self) {
+        for line in self.render().lines() {
+            print_info(line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rendered_page(source: &str, millis: u64) -> RenderedPage {
+        RenderedPage {
+            title: String::from(source),
+            content: String::default(),
+            source: String::from(source),
+            parent: None,
+            checksum: String::default(),
+            legacy_checksum: None,
+            timings: PhaseTimings {
+                template: Duration::from_millis(millis),
+                render: Duration::from_millis(1),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn it_lists_the_slowest_pages_first_with_totals() {
+        let profile = RenderProfile::default();
+        profile.record(&rendered_page("index.md", 2));
+        profile.record(&rendered_page("guides/big.md", 800));
+
+        assert_eq!(
+            profile.render(),
+            "Render profile (ms), slowest first:
+  page             front matter  template     parse    render  checksum     total
+  guides/big.md             0.0     800.0       0.0       1.0       0.0     801.0
+  index.md                  0.0       2.0       0.0       1.0       0.0       3.0
+  total (2 pages)           0.0     802.0       0.0       2.0       0.0     804.0
+"
+        );
+    }
+}
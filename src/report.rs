@@ -0,0 +1,250 @@
+use std::{collections::HashMap, fs::File, io::Write, path::Path};
+
+use serde::Serialize;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    confluence_paginator::ConfluencePaginator,
+    console::{print_info, print_warning},
+    error::{ApiResponse, Result},
+    external_links::RewrittenLink,
+    link_generator::LinkGenerator,
+    markdown_page::MarkdownPage,
+    responses::PageWithLinks,
+    sync_stats::SyncStats,
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct ReportPage {
+    pub source: String,
+    pub title: String,
+    pub id: Option<String>,
+    pub url: Option<String>,
+    /// The tinyui link, which keeps working when the page is retitled or moved.
+    pub short_url: Option<String>,
+}
+
+/// What a sync published, written with `--report` (JSON) and `--links` (markdown).
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct SyncReport {
+    pub space: String,
+    pub pages: Vec<ReportPage>,
+    /// External links that were published with another URL, see `external_links`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rewritten_links: Vec<RewrittenLink>,
+    /// The summary printed at the end of the sync.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<SyncStats>,
+}
+
+/// The most pages the Confluence API returns at once.
+const PAGES_PER_REQUEST: usize = 250;
+
+/// The short links of the pages by their id, fetched for many pages at once.
+fn fetch_short_urls(
+    confluence_client: &ConfluenceClient,
+    ids: &[&str],
+) -> Result<HashMap<String, String>> {
+    let mut short_urls = HashMap::default();
+    for batch in ids.chunks(PAGES_PER_REQUEST) {
+        let response = confluence_client.get_pages_by_id(batch)?.api_result()?;
+        for page in ConfluencePaginator::<PageWithLinks>::new(confluence_client).start(response)? {
+            let page = page?;
+            short_urls.insert(
+                page.id,
+                format!(
+                    "https://{}/wiki{}",
+                    confluence_client.hostname, page.links.tinyui
+                ),
+            );
+        }
+    }
+    Ok(short_urls)
+}
+
+impl SyncReport {
+    /// Collects the published pages (folders have no short link and are left out), fetching their
+    /// short links.
+    pub fn collect(
+        space_key: &str,
+        markdown_pages: &[MarkdownPage],
+        link_generator: &LinkGenerator,
+        confluence_client: &ConfluenceClient,
+    ) -> Self {
+        let published: Vec<(&MarkdownPage, Option<String>)> = markdown_pages
+            .iter()
+            .filter(|markdown_page| !markdown_page.is_folder())
+            .map(|markdown_page| {
+                let id = link_generator.get_page_id(Path::new(&markdown_page.source));
+                (markdown_page, id)
+            })
+            .collect();
+        let ids: Vec<&str> = published
+            .iter()
+            .filter_map(|(_, id)| id.as_deref())
+            .collect();
+        let short_urls = fetch_short_urls(confluence_client, &ids).unwrap_or_else(|err| {
+            print_warning(&format!("failed to fetch the short links: {:#}", err));
+            HashMap::default()
+        });
+        let pages = published
+            .into_iter()
+            .map(|(markdown_page, id)| {
+                let source = Path::new(&markdown_page.source);
+                let short_url = id.as_ref().and_then(|id| short_urls.get(id).cloned());
+                ReportPage {
+                    source: markdown_page.source.replace('\\', "/"),
+                    title: markdown_page.title.clone(),
+                    url: link_generator.get_file_url(source),
+                    id,
+                    short_url,
+                }
+            })
+            .collect();
+        SyncReport {
+            space: String::from(space_key),
+            pages,
+            rewritten_links: link_generator.rewritten_links(),
+            stats: None,
+        }
+    }
+
+    fn links_manifest(&self) -> String {
+        let mut manifest = format!(
+            "# {} Page Links\n\n| Page | Link | Source |\n| --- | --- | --- |\n",
+            self.space
+        );
+        for page in &self.pages {
+            let link = page.short_url.as_ref().or(page.url.as_ref());
+            manifest += &format!(
+                "| {} | {} | `{}` |\n",
+                page.title.replace('|', "\\|"),
+                link.map(String::as_str).unwrap_or("-"),
+                page.source
+            );
+        }
+        manifest
+    }
+}
+
+/// Writes the report of a space, or a list of them when several spaces were synced.
+pub fn write_reports_json(reports: &[SyncReport], path: &Path) -> Result<()> {
+    print_info(&format!("Writing report to {}", path.display()));
+    let json = match reports {
+        [report] => serde_json::to_string_pretty(report)?,
+        reports => serde_json::to_string_pretty(reports)?,
+    };
+    File::create(path)?.write_all(json.as_bytes())?;
+    Ok(())
+}
+
+/// Writes the short links of all synced spaces, a table per space.
+pub fn write_links_manifest(reports: &[SyncReport], path: &Path) -> Result<()> {
+    print_info(&format!("Writing links to {}", path.display()));
+    let manifest = reports
+        .iter()
+        .map(SyncReport::links_manifest)
+        .collect::<Vec<_>>()
+        .join("\n");
+    File::create(path)?.write_all(manifest.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{
+        confluence_page::{ConfluenceNode, ConfluenceNodeType, ConfluencePageData},
+        error::TestResult,
+        responses::{ContentStatus, Version},
+        test_helpers::markdown_page_from_str,
+    };
+
+    use super::*;
+
+    #[test]
+    fn it_collects_short_links() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        // one request for all the pages
+        let mock = server
+            .mock("GET", "/wiki/api/v2/pages")
+            .match_query(mockito::Matcher::UrlEncoded(
+                String::from("id"),
+                String::from("42,43"),
+            ))
+            .with_status(200)
+            .with_body(
+                r#"{"results": [
+                    {"id": "42", "_links": {"tinyui": "/x/KgA"}},
+                    {"id": "43", "_links": {"tinyui": "/x/KwA"}}
+                ], "_links": {}}"#,
+            )
+            .expect(1)
+            .create();
+
+        let pages = vec![
+            markdown_page_from_str("published.md", "# Published\n")?,
+            markdown_page_from_str("new.md", "# New | Draft\n")?,
+            markdown_page_from_str("other.md", "# Other\n")?,
+        ];
+        let mut link_generator = LinkGenerator::default_test();
+        for page in &pages {
+            link_generator.register_markdown_page(page)?;
+        }
+        for (id, title) in [("42", "Published"), ("43", "Other")] {
+            link_generator.register_confluence_node(&ConfluenceNode {
+                id: String::from(id),
+                title: String::from(title),
+                parent_id: None,
+                data: ConfluenceNodeType::Page(ConfluencePageData {
+                    version: Version {
+                        message: String::default(),
+                        number: 1,
+                    },
+                    path: None,
+                    status: ContentStatus::Current,
+                }),
+            });
+        }
+
+        let report = SyncReport::collect("TEST", &pages, &link_generator, &client);
+
+        mock.assert();
+        assert_eq!(
+            report.pages[0].short_url,
+            Some(format!("https://{}/wiki/x/KgA", server.host_with_port()))
+        );
+        assert_eq!(report.pages[1].id, None);
+        assert_eq!(
+            report.links_manifest(),
+            format!(
+                "# TEST Page Links\n\n| Page | Link | Source |\n| --- | --- | --- |\n| Published | https://{}/wiki/x/KgA | `published.md` |\n| New \\| Draft | - | `new.md` |\n| Other | https://{}/wiki/x/KwA | `other.md` |\n",
+                server.host_with_port(),
+                server.host_with_port()
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_combines_the_reports_of_several_spaces() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let path = temp.path().join("report.json");
+        let report = |space: &str| SyncReport {
+            space: String::from(space),
+            ..Default::default()
+        };
+
+        write_reports_json(&[report("TEAM")], &path)?;
+        let single: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+        assert_eq!(single["space"], "TEAM");
+
+        write_reports_json(&[report("TEAM"), report("OPS")], &path)?;
+        let combined: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+        assert_eq!(combined[1]["space"], "OPS");
+
+        Ok(())
+    }
+}
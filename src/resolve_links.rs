@@ -0,0 +1,145 @@
+use std::{collections::BTreeMap, path::Path, sync::Arc};
+
+use crate::{
+    check_environment_vars,
+    confluence_client::ConfluenceClient,
+    console::set_quiet,
+    link_generator::ResolvedLink,
+    markdown_space::MarkdownSpace,
+    mentions::CachedUsers,
+    sync::{check_homepage_exists, load_space, LoadedSpace},
+    Args, Result,
+};
+
+/// The published page of every markdown file of the space in `dir`, keyed by the path of the file
+/// relative to `dir` (with forward slashes). The pages are read from the Confluence at `host` with
+/// the credentials in `API_USER` and `API_TOKEN`, like the command line does, and nothing is
+/// changed.
+pub fn resolve_space_links(host: &str, dir: &Path) -> Result<BTreeMap<String, ResolvedLink>> {
+    check_environment_vars()?;
+    let confluence_client = ConfluenceClient::new(host);
+    let mut markdown_space = MarkdownSpace::from_directory(dir)?;
+    let args = Args {
+        space: vec![dir.to_string_lossy().into_owned()],
+        ..Args::default()
+    };
+    resolved_links(&confluence_client, &mut markdown_space, &args)
+}
+
+fn resolved_links(
+    confluence_client: &ConfluenceClient,
+    markdown_space: &mut MarkdownSpace,
+    args: &Args,
+) -> Result<BTreeMap<String, ResolvedLink>> {
+    let space_key = markdown_space.key.clone();
+    let LoadedSpace {
+        mut space,
+        mut link_generator,
+        ..
+    } = load_space(
+        confluence_client,
+        markdown_space,
+        args,
+        Arc::new(CachedUsers::new(confluence_client.clone())),
+    )?;
+
+    space.read_all_pages(confluence_client)?;
+    check_homepage_exists(&space, &space_key)?;
+    space.link_pages(&mut link_generator)?;
+    Ok(link_generator.resolved_links())
+}
+
+/// Prints a JSON object mapping the source path of every markdown page to the URL of its
+/// published page (or `null` if it hasn't been created yet), for tools that link to the docs.
+pub fn resolve_links(
+    confluence_client: ConfluenceClient,
+    markdown_space: &mut MarkdownSpace,
+    args: Args,
+) -> Result<()> {
+    set_quiet(true);
+    let urls: serde_json::Map<String, serde_json::Value> =
+        resolved_links(&confluence_client, markdown_space, &args)?
+            .into_iter()
+            .map(|(source, link)| (source, link.url.into()))
+            .collect();
+    println!("{}", serde_json::to_string_pretty(&urls)?);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+    use mockito::Matcher;
+    use serde_json::json;
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn it_resolves_the_files_to_their_pages() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("space/index.md").write_str("# Home")?;
+        temp.child("space/guide.md").write_str("# Guide")?;
+        temp.child("space/new.md").write_str("# New")?;
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        server
+            .mock("GET", Matcher::Any)
+            .with_status(200)
+            .with_body(r#"{"results": []}"#)
+            .expect_at_least(0)
+            .create();
+        server
+            .mock("GET", "/wiki/api/v2/spaces")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(r#"{"results": [{"id": "1", "key": "space", "homepageId": "999"}]}"#)
+            .create();
+        server
+            .mock("GET", "/wiki/api/v2/spaces/1/pages")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(
+                json!({"results": [
+                    {"id": "999", "title": "Home", "status": "current", "version": {"number": 2, "message": ""}},
+                    {"id": "1000", "parentId": "999", "title": "Old Guide", "status": "current", "version": {"number": 3, "message": ""}}
+                ]})
+                .to_string(),
+            )
+            .create();
+        // the page of guide.md was retitled in Confluence, the page map still knows it
+        server
+            .mock("GET", "/wiki/api/v2/pages/999/properties")
+            .with_status(200)
+            .with_body(
+                json!({"results": [{
+                    "id": "5",
+                    "key": "marked-space-page-map",
+                    "value": {"guide.md": {"id": "1000"}},
+                    "version": {"number": 1, "message": ""}
+                }]})
+                .to_string(),
+            )
+            .create();
+        let mut markdown_space = MarkdownSpace::from_directory(temp.child("space").path())?;
+
+        let links = resolved_links(&client, &mut markdown_space, &Args::default())?;
+
+        let url = |id: &str| {
+            Some(format!(
+                "https://{}/wiki/spaces/space/pages/{}",
+                server.host_with_port(),
+                id
+            ))
+        };
+        assert_eq!(links["index.md"].url, url("999"));
+        assert_eq!(links["guide.md"].url, url("1000"));
+        assert_eq!(links["guide.md"].title, "Guide");
+        // not created yet
+        assert_eq!(links["new.md"].url, None);
+
+        Ok(())
+    }
+}
@@ -1,8 +1,14 @@
+//! The responses of the Confluence API, as far as marked-space reads them.
+//!
+//! Fields that aren't read are left out, and the ones Confluence may leave out or add values to
+//! get defaults or fallbacks, so responses of newer versions of the API still deserialize (see
+//! [crate::api_version]).
 use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Version {
+    #[serde(default)]
     pub message: String,
     pub number: i32,
 }
@@ -26,7 +32,7 @@ pub enum BodyBulk {
     Empty,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum ContentStatus {
     Current,
@@ -36,6 +42,24 @@ pub enum ContentStatus {
     Trashed,
     Deleted,
     Any,
+    /// Statuses that aren't known yet.
+    #[serde(other)]
+    Unknown,
+}
+
+impl std::fmt::Display for ContentStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ContentStatus::Current => "current",
+            ContentStatus::Draft => "a draft",
+            ContentStatus::Archived => "archived",
+            ContentStatus::Historical => "historical",
+            ContentStatus::Trashed => "trashed",
+            ContentStatus::Deleted => "deleted",
+            ContentStatus::Any => "any",
+            ContentStatus::Unknown => "not current",
+        })
+    }
 }
 
 // TODO: might be a better way to express this...
@@ -69,6 +93,9 @@ pub enum BodySingle {
     AtlasDocFormat(BodyType),
     #[serde(rename = "view")]
     View(BodyType),
+    /// Representations that aren't known yet.
+    #[serde(untagged)]
+    Other(serde_json::Value),
 }
 
 #[derive(Deserialize, Debug)]
@@ -96,6 +123,21 @@ pub struct Links {
     pub next: Option<String>,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PageLinks {
+    /// Short link relative to the wiki base, e.g. `/x/LoB1`.
+    pub tinyui: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PageWithLinks {
+    pub id: String,
+    #[serde(rename = "_links")]
+    pub links: PageLinks,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct MultiEntityResult<T> {
@@ -110,28 +152,36 @@ pub struct MultiEntityResult<T> {
 pub struct Space {
     pub id: String,
     pub key: String,
+    #[serde(default)]
     pub _name: String,
     pub homepage_id: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 #[allow(dead_code)]
 pub struct Attachment {
     pub id: String,
     pub title: String,
+    #[serde(default)]
     pub page_id: String,
+    #[serde(default)]
     pub comment: String,
     pub file_id: String, // File ID of the attachment. This is the ID referenced in atlas_doc_format bodies and is distinct from the attachment ID.
+    /// Where to download the file, relative to the wiki base.
+    #[serde(default)]
+    pub download_link: String,
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 #[allow(dead_code)]
 pub struct Label {
+    #[serde(default)]
     pub prefix: String,
     pub name: String,
     pub id: String,
+    #[serde(default)]
     pub label: String,
 }
 
@@ -153,7 +203,7 @@ pub struct SearchResult {
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct User {
-    #[serde(rename = "type")]
+    #[serde(rename = "type", default)]
     pub _type: String,
     pub account_id: String,
     // pub email: String,
@@ -161,6 +211,12 @@ pub struct User {
     // pub display_name: String,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchStatus {
+    pub watching: bool,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Descendant {
@@ -169,6 +225,7 @@ pub struct Descendant {
     pub title: String,
     #[serde(rename = "type")]
     pub _type: String,
+    #[serde(default)]
     pub parent_id: String,
     // pub depth: i32,
     // pub child_position: i32,
@@ -180,11 +237,98 @@ pub struct Content {
     pub id: String,
     #[serde(rename = "type")]
     pub _type: String,
+    #[serde(default)]
     pub status: String,
     pub title: String,
+    #[serde(default)]
     pub extensions: serde_json::Value,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SpacePermission {
+    pub id: String,
+    pub principal: Principal,
+    pub operation: PermissionOperation,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Principal {
+    #[serde(rename = "type")]
+    pub _type: String, // user, group or role
+    pub id: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PermissionOperation {
+    pub key: String,
+    #[serde(default)]
+    pub target_type: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+#[allow(dead_code)]
+pub struct Group {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SpaceWithDetails {
+    pub name: String,
+    pub description: Option<SpaceDescription>,
+    pub icon: Option<SpaceIcon>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SpaceDescription {
+    pub plain: Option<PlainValue>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PlainValue {
+    pub value: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SpaceIcon {
+    /// Download path of the icon, relative to the host.
+    pub path: String,
+    #[serde(default)]
+    pub is_default: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentAttachment {
+    pub title: String,
+    #[serde(default)]
+    pub metadata: AttachmentMetadata,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AttachmentMetadata {
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentTemplate {
+    pub template_id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+}
+
 #[cfg(test)]
 mod test {
     use serde_json::json;
@@ -224,4 +368,34 @@ mod test {
 
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn it_tolerates_changes_to_the_api() -> crate::error::TestResult {
+        let page = serde_json::from_value::<PageBulkWithoutBody>(json!({
+            "id": "7700526",
+            "title": "A page",
+            "status": "pending-review",
+            "version": {"number": 2},
+            "someNewField": {"nested": true}
+        }))?;
+        assert!(matches!(page.status, ContentStatus::Unknown));
+        assert_eq!(page.version.message, "");
+
+        let page = serde_json::from_value::<PageSingleWithBody>(json!({
+            "id": "7700526",
+            "title": "A page",
+            "version": {"number": 2, "message": ""},
+            "body": {"export_view": {"value": "<p />"}}
+        }))?;
+        assert!(matches!(page.body, BodySingle::Other(_)));
+
+        let descendant = serde_json::from_value::<Descendant>(json!({
+            "id": "1",
+            "title": "Top level",
+            "type": "whiteboard"
+        }))?;
+        assert_eq!(descendant.parent_id, "");
+
+        Ok(())
+    }
 }
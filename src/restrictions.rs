@@ -1,12 +1,20 @@
+use std::collections::HashSet;
+
+use anyhow::Context;
 use serde_json::json;
 
 use crate::{
-    confluence_client::ConfluenceClient, confluence_page::ConfluenceNode, console::print_status,
+    confluence_client::ConfluenceClient,
+    confluence_page::ConfluenceNode,
+    console::print_status,
+    error::{ApiResponse, ConfluenceError},
 };
 
 pub enum RestrictionType<'a> {
-    SingleEditor(&'a serde_json::Value), // only the current user can edit
-    OpenSpace,                           // anyone in the space can edit
+    SingleEditor(&'a serde_json::Value, Vec<String>), // only the current user and page owners can edit
+    EditLock(&'a serde_json::Value), // only the current user can edit, see `edit_lock` in the space config
+    Unlock(&'a serde_json::Value),   // lifts an edit lock, leaves other restrictions alone
+    OpenSpace,                       // anyone in the space can edit
 }
 
 fn restriction_body(editor_list: &serde_json::Value) -> serde_json::Value {
@@ -36,7 +44,7 @@ fn restriction_body(editor_list: &serde_json::Value) -> serde_json::Value {
                         "results": editor_list,
                         "start": 0,
                         "limit": 100,
-                        "size": 1
+                        "size": editor_list.as_array().map(|a| a.len()).unwrap_or_default()
                     },
                     "group": {
                         "results": [],
@@ -60,47 +68,69 @@ pub fn sync_restrictions(
 ) -> anyhow::Result<()> {
     let existing_restrictions = confluence_client
         .get_restrictions_by_operation(&existing_node.id)?
-        .error_for_status()?
+        .api_result()?
         .json::<serde_json::Value>()?;
 
-    let updated = match restriction_type {
-        RestrictionType::SingleEditor(user) => {
-            let update = should_update_restrictions(user, &existing_restrictions)?;
-            if update {
-                let users = json!([user]);
-                let body = restriction_body(&users);
-                print_status(crate::console::Status::Updated, "permissions");
-                Some(confluence_client.set_restrictions(&existing_node.id, body)?)
-            } else {
-                None
-            }
+    let updated = match desired_editors(restriction_type, &existing_restrictions)? {
+        Some(editors) if should_update_restrictions(&editors, &existing_restrictions)? => {
+            let users = json!(editors);
+            let body = restriction_body(&users);
+            print_status(crate::console::Status::Updated, "permissions");
+            Some(confluence_client.set_restrictions(&existing_node.id, body)?)
         }
-
-        RestrictionType::OpenSpace => None,
+        _ => None,
     };
     if let Some(response) = updated {
         if !response.status().is_success() {
-            println!("{}", &response.text()?);
-            return Err(anyhow::anyhow!("Not able to update restrictions"));
+            return Err(ConfluenceError::failed_request(response))
+                .context("Not able to update restrictions");
         }
     }
     Ok(())
 }
 
+/// The users that should be able to edit the page, `None` to leave the restrictions as they are.
+fn desired_editors(
+    restriction_type: RestrictionType,
+    existing_restrictions: &serde_json::Value,
+) -> Result<Option<Vec<serde_json::Value>>, anyhow::Error> {
+    Ok(match restriction_type {
+        RestrictionType::SingleEditor(user, owner_account_ids) => {
+            let mut editors = vec![user.clone()];
+            editors.extend(
+                owner_account_ids
+                    .iter()
+                    .filter(|account_id| Some(account_id.as_str()) != user["accountId"].as_str())
+                    .map(|account_id| json!({"type": "known", "accountId": account_id})),
+            );
+            Some(editors)
+        }
+        RestrictionType::EditLock(user) => Some(vec![user.clone()]),
+        // only when the current user is the sole editor, which is what the lock left behind
+        RestrictionType::Unlock(user) => {
+            (!should_update_restrictions(std::slice::from_ref(user), existing_restrictions)?)
+                .then(Vec::default)
+        }
+        RestrictionType::OpenSpace => None,
+    })
+}
+
 fn should_update_restrictions(
-    user: &serde_json::Value,
+    editors: &[serde_json::Value],
     existing_restrictions: &serde_json::Value,
 ) -> Result<bool, anyhow::Error> {
     let existing_users_json = existing_restrictions.pointer("/update/restrictions/user/results");
-    let mut update = false;
     if let Some(existing_users) = existing_users_json {
         let a = existing_users
             .as_array()
             .ok_or(anyhow::anyhow!("Missing users array"))?;
-        if a.len() != 1 || a[0]["accountId"].as_str() != user["accountId"].as_str() {
-            update = true;
-        }
-        Ok(update)
+        let existing_account_ids: HashSet<Option<&str>> =
+            a.iter().map(|user| user["accountId"].as_str()).collect();
+        let editor_account_ids: HashSet<Option<&str>> = editors
+            .iter()
+            .map(|user| user["accountId"].as_str())
+            .collect();
+        Ok(a.len() != editors.len() || existing_account_ids != editor_account_ids)
     } else {
         Err(anyhow::anyhow!("Missing results"))
     }
@@ -110,7 +140,10 @@ fn should_update_restrictions(
 mod tests {
     use serde_json::json;
 
-    use crate::{error::TestResult, restrictions::should_update_restrictions};
+    use crate::{
+        error::TestResult,
+        restrictions::{desired_editors, should_update_restrictions, RestrictionType},
+    };
 
     fn by_operation_body() -> serde_json::Value {
         json!({
@@ -165,7 +198,7 @@ mod tests {
         });
         let mut current_restrictions = by_operation_body();
         current_restrictions["update"]["restrictions"]["user"]["results"] = json!([other_user]);
-        assert!(should_update_restrictions(&user, &current_restrictions)?);
+        assert!(should_update_restrictions(&[user], &current_restrictions)?);
         Ok(())
     }
 
@@ -176,7 +209,7 @@ mod tests {
         });
         let mut current_restrictions = by_operation_body();
         current_restrictions["update"]["restrictions"]["user"]["results"] = json!([user]);
-        assert!(!should_update_restrictions(&user, &current_restrictions)?);
+        assert!(!should_update_restrictions(&[user], &current_restrictions)?);
         Ok(())
     }
 
@@ -191,7 +224,7 @@ mod tests {
         let mut current_restrictions = by_operation_body();
         current_restrictions["update"]["restrictions"]["user"]["results"] =
             json!([user, other_user]);
-        assert!(should_update_restrictions(&user, &current_restrictions)?);
+        assert!(should_update_restrictions(&[user], &current_restrictions)?);
         Ok(())
     }
 
@@ -199,4 +232,51 @@ mod tests {
     fn it_does_nothing_in_openspace_mode() {
         // assume that permissions are managed by the user in openspace mode
     }
+
+    #[test]
+    fn it_keeps_owners_as_editors() -> TestResult {
+        let user = json!({
+            "accountId": "foobarbaz",
+        });
+        let owner = json!({
+            "accountId": "barry",
+        });
+        let mut current_restrictions = by_operation_body();
+        current_restrictions["update"]["restrictions"]["user"]["results"] = json!([owner, user]);
+        assert!(!should_update_restrictions(
+            &[user.clone(), owner.clone()],
+            &current_restrictions
+        )?);
+        assert!(should_update_restrictions(&[user], &current_restrictions)?);
+        Ok(())
+    }
+
+    #[test]
+    fn it_locks_and_unlocks_edits_for_the_current_user_only() -> TestResult {
+        let user = json!({
+            "accountId": "foobarbaz",
+        });
+        let other_user = json!({
+            "accountId": "barry",
+        });
+        let mut current_restrictions = by_operation_body();
+        current_restrictions["update"]["restrictions"]["user"]["results"] =
+            json!([user, other_user]);
+
+        assert_eq!(
+            desired_editors(RestrictionType::EditLock(&user), &current_restrictions)?,
+            Some(vec![user.clone()])
+        );
+        // someone else restricted the page, the lock didn't
+        assert_eq!(
+            desired_editors(RestrictionType::Unlock(&user), &current_restrictions)?,
+            None
+        );
+        current_restrictions["update"]["restrictions"]["user"]["results"] = json!([user]);
+        assert_eq!(
+            desired_editors(RestrictionType::Unlock(&user), &current_restrictions)?,
+            Some(Vec::default())
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,305 @@
+//! Rolling back the pages a sync changed, with `rollback --to <sync-id>`. The audit log has the
+//! version every page was at before each update of a run, so the pages are restored to that
+//! version with Confluence's version restore, which adds it again as the newest version.
+//!
+//! Only the content and titles of pages are rolled back. Pages the sync created have no earlier
+//! version and are only listed. Everything else the sync did (attachments, labels, properties,
+//! moves, archiving, restrictions, watchers, comments, space settings and templates) stays as it
+//! is, and is counted by operation so it can be undone by hand. Pages that were changed again
+//! after the sync are skipped unless the rollback is forced, so it doesn't undo later edits.
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    confluence_page::ConfluencePageData,
+    console::{print_info, print_warning, Status},
+    error::{ApiResponse, ConfluenceError, Result},
+    responses::PageSingleWithoutBody,
+    sync_operation::SyncOperation,
+};
+
+/// The fields of an audit log entry a rollback needs.
+#[derive(Deserialize, Debug)]
+struct LoggedChange {
+    #[serde(default)]
+    run: String,
+    operation: String,
+    page_id: Option<String>,
+    name: Option<String>,
+    version_before: Option<u64>,
+    version_after: Option<u64>,
+    status: u16,
+}
+
+impl LoggedChange {
+    fn succeeded(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct PageRollback {
+    pub page_id: String,
+    pub title: Option<String>,
+    /// The version before the first change of the sync.
+    pub restore: u64,
+    /// The version after the last change of the sync.
+    pub synced: u64,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RollbackPlan {
+    pub pages: Vec<PageRollback>,
+    /// The ids and titles of the pages the sync created.
+    pub created: Vec<(String, Option<String>)>,
+    /// How often the sync did each operation that isn't rolled back, by the name in the log.
+    pub left: BTreeMap<String, usize>,
+}
+
+/// Works out from the audit log which pages the run changed, and the versions to restore them to.
+pub fn plan(log: &str, run: &str) -> Result<RollbackPlan> {
+    let mut changes = vec![];
+    for (number, line) in log.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let change: LoggedChange = serde_json::from_str(line)
+            .with_context(|| format!("Line {} of the audit log isn't an entry", number + 1))?;
+        if change.run == run && change.succeeded() {
+            changes.push(change);
+        }
+    }
+    if changes.is_empty() {
+        return Err(anyhow!(
+            "The audit log has no changes of sync {}, the sync id is printed when a sync starts",
+            run
+        ));
+    }
+
+    let mut created = BTreeMap::new();
+    let mut pages: BTreeMap<String, PageRollback> = BTreeMap::new();
+    let mut left = BTreeMap::new();
+    for change in changes {
+        let Some(page_id) = change.page_id else {
+            *left.entry(change.operation).or_default() += 1;
+            continue;
+        };
+        match change.operation.as_str() {
+            "create_page" => {
+                created.insert(page_id, change.name);
+            }
+            "update_page" | "update_page_title" => {
+                let (Some(before), Some(after)) = (change.version_before, change.version_after)
+                else {
+                    continue;
+                };
+                let page = pages.entry(page_id.clone()).or_insert(PageRollback {
+                    page_id,
+                    title: None,
+                    restore: before,
+                    synced: after,
+                });
+                page.restore = page.restore.min(before);
+                page.synced = page.synced.max(after);
+                page.title = change.name.or(page.title.take());
+            }
+            _ => *left.entry(change.operation).or_default() += 1,
+        }
+    }
+    // the earlier versions of pages the sync created are its own placeholders
+    pages.retain(|page_id, _| !created.contains_key(page_id));
+    Ok(RollbackPlan {
+        pages: pages.into_values().collect(),
+        created: created.into_iter().collect(),
+        left,
+    })
+}
+
+/// Restores the pages changed by sync `run` to the versions they had before it.
+pub fn rollback(
+    confluence_client: ConfluenceClient,
+    audit_log: &Path,
+    run: &str,
+    force: bool,
+) -> Result<()> {
+    let log = fs::read_to_string(audit_log)
+        .with_context(|| format!("Failed to read audit log {}", audit_log.display()))?;
+    let plan = plan(&log, run)?;
+    let message = format!(
+        "{} rollback={}",
+        ConfluencePageData::version_message_prefix(),
+        run
+    );
+
+    let mut failed = 0;
+    for page in &plan.pages {
+        let desc = format!(
+            "[{}] \"{}\" to version {}",
+            page.page_id,
+            page.title.as_deref().unwrap_or_default(),
+            page.restore
+        );
+        let current: PageSingleWithoutBody = confluence_client
+            .get_page(&page.page_id)?
+            .api_result()?
+            .json()?;
+        let current_version = current.version.number as u64;
+        if current_version != page.synced && !force {
+            print_warning(&format!(
+                "[{}] \"{}\" was changed after the sync (version {}, the sync left version {}), \
+                 not rolling it back. Pass --force to roll it back anyway",
+                page.page_id, current.title, current_version, page.synced
+            ));
+            failed += 1;
+            continue;
+        }
+        let op = SyncOperation::start(desc, true);
+        let response =
+            confluence_client.restore_page_version(&page.page_id, page.restore, &message)?;
+        if response.status().is_success() {
+            op.end(Status::Restored);
+        } else {
+            op.end(Status::Error);
+            print_warning(&format!("{:#}", ConfluenceError::failed_request(response)));
+            failed += 1;
+        }
+    }
+    for (page_id, title) in &plan.created {
+        print_info(&format!(
+            "[{}] \"{}\" was created by the sync, archive it by hand if it should go",
+            page_id,
+            title.as_deref().unwrap_or_default()
+        ));
+    }
+    if !plan.left.is_empty() {
+        print_info(&format!(
+            "The sync also did what a rollback doesn't undo, check it by hand: {}",
+            plan.left
+                .iter()
+                .map(|(operation, count)| format!("{} x{}", operation, count))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} of {} page(s) weren't rolled back",
+            failed,
+            plan.pages.len()
+        ));
+    }
+    print_info(&format!(
+        "Rolled back {} page(s) of sync {}; revert the markdown too, or the next sync publishes it \
+         again",
+        plan.pages.len(),
+        run
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Matcher;
+    use serde_json::json;
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    fn entry(run: &str, operation: &str, page_id: &str, versions: (u64, u64)) -> String {
+        json!({
+            "time": "2026-10-15T09:00:00.000Z",
+            "run": run,
+            "user": "ci@example.com",
+            "operation": operation,
+            "page_id": page_id,
+            "name": format!("Page {}", page_id),
+            "version_before": versions.0,
+            "version_after": versions.1,
+            "status": 200,
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn it_plans_the_versions_to_restore() -> Result<()> {
+        let log = [
+            entry("A", "update_page", "42", (6, 7)),
+            entry("B", "update_page", "42", (7, 8)),
+            entry("B", "update_page_title", "42", (8, 9)),
+            entry("B", "create_page", "43", (0, 1)),
+            entry("B", "update_page", "43", (1, 2)),
+            entry("B", "update_attachment", "44", (1, 2)),
+            json!({"run": "B", "operation": "update_page", "page_id": "45", "status": 409})
+                .to_string(),
+        ]
+        .join("\n");
+
+        assert_eq!(
+            plan(&log, "B")?,
+            RollbackPlan {
+                pages: vec![PageRollback {
+                    page_id: String::from("42"),
+                    title: Some(String::from("Page 42")),
+                    restore: 7,
+                    synced: 9,
+                }],
+                created: vec![(String::from("43"), Some(String::from("Page 43")))],
+                left: BTreeMap::from([(String::from("update_attachment"), 1)]),
+            }
+        );
+        assert!(plan(&log, "C").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn it_restores_the_pages_the_sync_left() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let path = temp.path().join("audit.jsonl");
+        fs::write(
+            &path,
+            [
+                entry("B", "update_page", "42", (7, 8)),
+                entry("B", "update_page", "43", (3, 4)),
+            ]
+            .join("\n"),
+        )?;
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        server
+            .mock("GET", "/wiki/api/v2/pages/42")
+            .with_status(200)
+            .with_body(r#"{"id": "42", "title": "Page 42", "version": {"number": 8}}"#)
+            .create();
+        server
+            .mock("GET", "/wiki/api/v2/pages/43")
+            .with_status(200)
+            .with_body(r#"{"id": "43", "title": "Page 43", "version": {"number": 5}}"#)
+            .create();
+        let restore = server
+            .mock("POST", "/wiki/rest/api/content/42/version")
+            .match_body(Matcher::PartialJson(json!({
+                "operationKey": "restore",
+                "params": {"versionNumber": 7, "message": "updated by markedspace: rollback=B"}
+            })))
+            .with_status(200)
+            .create();
+        let edited = server
+            .mock("POST", "/wiki/rest/api/content/43/version")
+            .with_status(200)
+            .create();
+
+        // page 43 was edited after the sync
+        let err = rollback(client.clone(), &path, "B", false).unwrap_err();
+        assert_eq!(err.to_string(), "1 of 2 page(s) weren't rolled back");
+        restore.assert();
+        assert!(!edited.matched());
+
+        rollback(client, &path, "B", true)?;
+        edited.assert();
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use std::{fs::File, io::Write, path::Path};
+
+use serde::Serialize;
+
+use crate::{
+    console::print_info,
+    error::Result,
+    heading_anchors::{heading_anchor, HeadingAnchors},
+    link_generator::LinkGenerator,
+    markdown_page::MarkdownPage,
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct SearchHeading {
+    pub text: String,
+    /// Anchor to link to the heading, appended to the page URL after a `#`.
+    pub anchor: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct SearchRecord {
+    pub source: String,
+    pub title: String,
+    pub url: Option<String>,
+    pub labels: Vec<String>,
+    pub headings: Vec<SearchHeading>,
+    /// The text of the page without markup, paragraphs separated by blank lines.
+    pub content: String,
+}
+
+/// A manifest of the content of every page, written with `--search-index` in a shape that search
+/// engines like Algolia or Typesense can import as records.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct SearchIndex {
+    pub pages: Vec<SearchRecord>,
+}
+
+impl SearchIndex {
+    pub fn collect(
+        markdown_pages: &[MarkdownPage],
+        link_generator: &LinkGenerator,
+    ) -> Result<Self> {
+        let pages = markdown_pages
+            .iter()
+            .filter(|markdown_page| !markdown_page.is_folder())
+            .map(|markdown_page| {
+                let text = markdown_page.text()?;
+                let mut anchors = HeadingAnchors::default();
+                let headings = text
+                    .headings
+                    .into_iter()
+                    .map(|text| {
+                        let anchor = anchors.add(&text).map(|collision| collision.anchor);
+                        SearchHeading {
+                            anchor: anchor.unwrap_or_else(|| heading_anchor(&text)),
+                            text,
+                        }
+                    })
+                    .collect();
+                Ok(SearchRecord {
+                    source: markdown_page.source.replace('\\', "/"),
+                    title: markdown_page.title.clone(),
+                    url: link_generator.get_file_url(Path::new(&markdown_page.source)),
+                    labels: markdown_page.front_matter.labels.clone(),
+                    headings,
+                    content: text.blocks.join("\n\n"),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(SearchIndex { pages })
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        print_info(&format!("Writing search index to {}", path.display()));
+        File::create(path)?.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{error::TestResult, test_helpers::markdown_page_from_str};
+
+    use super::*;
+
+    #[test]
+    fn it_collects_search_records() -> TestResult {
+        let pages = vec![markdown_page_from_str(
+            "index.md",
+            "---\nlabels: [onboarding]\n---\n# Getting Started\n\nInstall the *tools*.\n\n## Setup\n\n```\nmake setup\n```\n\n## Setup\n",
+        )?];
+
+        let index = SearchIndex::collect(&pages, &LinkGenerator::default_test())?;
+
+        assert_eq!(
+            index.pages,
+            vec![SearchRecord {
+                source: String::from("index.md"),
+                title: String::from("Getting Started"),
+                url: Some(String::from(
+                    "https://example.atlassian.net/wiki/spaces/TEST/pages/999"
+                )),
+                labels: vec![String::from("onboarding")],
+                headings: vec![
+                    SearchHeading {
+                        text: String::from("Setup"),
+                        anchor: String::from("setup"),
+                    },
+                    SearchHeading {
+                        text: String::from("Setup"),
+                        anchor: String::from("setup-1"),
+                    },
+                ],
+                content: String::from("Install the tools.\n\nmake setup\n"),
+            }]
+        );
+
+        Ok(())
+    }
+}
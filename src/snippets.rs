@@ -0,0 +1,85 @@
+//! Markdown files with `snippet: true`, which are never published as pages but included into
+//! other pages.
+//!
+//! A snippet is included by its title (the first heading, which isn't included) with
+//! `{% include "snippets/<title>" %}`, so moving or renaming the file doesn't break the pages that
+//! include it. Including it by its path, like any other markdown file, works too. Either way the
+//! front matter and the title are left out, and the snippet is rendered with tera like the page.
+use std::{fs::File, io, path::Path};
+
+use anyhow::Context;
+
+use crate::{
+    error::{ConfluenceError, Result},
+    frontmatter::FrontMatter,
+    markdown_page::remove_prefix,
+    page_split::heading_text,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Snippet {
+    /// Relative to the space directory.
+    pub source: String,
+    pub title: String,
+    /// The markdown after the title.
+    pub body: String,
+}
+
+impl Snippet {
+    pub fn load(space_dir: &Path, path: &Path) -> Result<Self> {
+        let source = remove_prefix(space_dir, path)?;
+        let mut reader = io::BufReader::new(File::open(path)?);
+        let (_fm, content) =
+            FrontMatter::from_reader(&mut reader).with_context(|| source.clone())?;
+        Self::from_content(source, &content)
+    }
+
+    fn from_content(source: String, content: &str) -> Result<Self> {
+        let mut lines = content.split_inclusive('\n');
+        let title = lines
+            .by_ref()
+            .find(|line| !line.trim().is_empty())
+            .and_then(|line| heading_text(line, 1))
+            .map(String::from)
+            .ok_or_else(|| {
+                ConfluenceError::generic_error(format!(
+                    "Snippet {} should start with its title as a level 1 heading",
+                    source
+                ))
+            })?;
+        let body = lines
+            .collect::<String>()
+            .trim_start_matches('\n')
+            .to_string();
+        Ok(Snippet {
+            source,
+            title,
+            body,
+        })
+    }
+
+    /// The name to include the snippet with.
+    pub fn template_name(&self) -> String {
+        format!("snippets/{}", self.title)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_leaves_out_the_title() -> crate::error::TestResult {
+        let snippet = Snippet::from_content(
+            String::from("shared/support.md"),
+            "\n# Support Contacts\n\nAsk in #help.\n",
+        )?;
+
+        assert_eq!(snippet.title, "Support Contacts");
+        assert_eq!(snippet.body, "Ask in #help.\n");
+        assert_eq!(snippet.template_name(), "snippets/Support Contacts");
+        assert!(Snippet::from_content(String::from("untitled.md"), "Text\n").is_err());
+
+        Ok(())
+    }
+}
@@ -10,7 +10,7 @@ use crate::link_generator::LinkGenerator;
 use crate::markdown_page::MarkdownPage;
 use crate::responses::Descendant;
 
-use crate::error::Result;
+use crate::error::{ApiResponse, Result};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Sort {
@@ -20,14 +20,13 @@ pub enum Sort {
 
 impl Sort {
     pub fn from_str(sort_string: Option<&str>) -> Result<Sort> {
-        if sort_string.is_none() {
-            Ok(Sort::Unsorted)
-        } else {
-            let s = sort_string.unwrap();
+        if let Some(s) = sort_string {
             match s.to_ascii_lowercase().as_str() {
                 "inc" => Ok(Sort::Incrementing),
                 _ => Err(anyhow::anyhow!("invalid value")),
             }
+        } else {
+            Ok(Sort::Unsorted)
         }
     }
 }
@@ -39,7 +38,7 @@ trait MoveContent {
 impl MoveContent for ConfluenceClient {
     fn move_content(&mut self, content_id: &str, operation: &str, target: &str) -> Result<()> {
         self.move_page_relative(content_id, operation, target)?
-            .error_for_status()?;
+            .api_result()?;
         Ok(())
     }
 }
@@ -138,8 +137,7 @@ pub fn sync_sort(
 
         let mut iter = ConfluencePaginator::<Descendant>::new(confluence_client);
 
-        let all_descendants_data: Vec<Descendant> =
-            iter.start(response)?.filter_map(|d| d.ok()).collect();
+        let all_descendants_data: Vec<Descendant> = iter.start(response)?.collect::<Result<_>>()?;
 
         sort_descendants(&all_descendants_data, confluence_client)?;
     }
@@ -166,11 +164,11 @@ mod test {
 
     use super::{sort_descendants, sync_sort, MoveContent};
 
-    fn register_mark_and_conf_page<'a>(
+    fn register_mark_and_conf_page(
         page_id: &str,
         link_generator: &mut LinkGenerator,
-        markdown_page: crate::markdown_page::MarkdownPage<'a>,
-    ) -> Result<crate::markdown_page::MarkdownPage<'a>, anyhow::Error> {
+        markdown_page: crate::markdown_page::MarkdownPage,
+    ) -> Result<crate::markdown_page::MarkdownPage, anyhow::Error> {
         link_generator.register_markdown_page(&markdown_page)?;
         link_generator.register_confluence_node(&ConfluenceNode {
             id: page_id.into(),
@@ -0,0 +1,485 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Context};
+use saphyr::Yaml;
+
+use crate::{
+    archive::OrphanPolicy,
+    attachments::AttachmentNames,
+    checksum::ChecksumAlgorithm,
+    edit_link::EditLink,
+    error::Result,
+    external_links::ExternalLinks,
+    link_definitions::LinkDefinitions,
+    link_generator::LinkText,
+    lint_rules::{parse_positive_number, LintRules},
+    page_emojis::EmojiTitle,
+    page_limits::PageLimits,
+    page_styles::StyleTemplates,
+    page_types::{parse_page_types, PageType},
+    placeholder_pages::RenderErrors,
+    secrets::SecretRules,
+    space_details::SpaceDetails,
+    space_permissions::{parse_permissions, Role},
+    template_renderer::TemplateExclusions,
+    title_policy::TitlePolicy,
+    warnings::{parse_disabled_warnings, WarningCode},
+};
+
+/// Space wide configuration, read from `_space.yml` in the root of the space directory.
+pub static SPACE_CONFIG_FILENAME: &str = "_space.yml";
+
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct SpaceConfig {
+    /// Shortcode name to storage format (tera) template.
+    pub macros: BTreeMap<String, String>,
+    /// Post a comment describing the change whenever the content of a page is updated.
+    pub change_comments: bool,
+    /// Store where every page came from in a content property, see the `verify` subcommand.
+    pub provenance: bool,
+    pub homepage: HomepageConfig,
+    pub titles: TitlePolicy,
+    /// Add `-1`, `-2`, ... anchors to repeated headings instead of warning about them.
+    pub disambiguate_headings: bool,
+    pub link_text: LinkText,
+    pub styles: StyleTemplates,
+    pub external_links: ExternalLinks,
+    pub lint: LintRules,
+    /// Page type name (the `type` front matter key) to its requirements.
+    pub types: BTreeMap<String, PageType>,
+    /// Group name to the role it should have in the space, see the `permissions` subcommand.
+    pub permissions: BTreeMap<String, Role>,
+    pub space: SpaceDetails,
+    pub orphans: OrphanPolicy,
+    /// Algorithm for the checksums of pages and attachments.
+    pub checksum: ChecksumAlgorithm,
+    pub attachment_names: AttachmentNames,
+    /// Warnings that are never reported, the `warnings.disable` key.
+    pub disabled_warnings: BTreeSet<WarningCode>,
+    pub emoji_title: EmojiTitle,
+    pub template_exclude: TemplateExclusions,
+    /// Render tera in fenced code blocks, which are published as they are by default.
+    pub template_code_blocks: bool,
+    /// Pages with this label in Confluence are not updated, the `freeze_label` key.
+    pub freeze_label: Option<String>,
+    /// Label added to every synced page, so only the pages with it are fetched from Confluence.
+    pub managed_label: Option<String>,
+    pub secrets: SecretRules,
+    /// File with the terms that must not be published, relative to the space directory.
+    pub policy: Option<PathBuf>,
+    pub limits: PageLimits,
+    /// Archive the pages of files with `publish: false` instead of leaving them as they are.
+    pub archive_unpublished: bool,
+    /// `true` lets only the user running the sync edit the pages, `false` lifts that lock again
+    /// and without the key restrictions are left as they are.
+    pub edit_lock: Option<bool>,
+    /// Link at the bottom of every page to its file in the git forge.
+    pub edit_link: Option<EditLink>,
+    pub render_errors: RenderErrors,
+    /// How many levels of pages there may be under the homepage, deeper pages are flattened.
+    pub max_depth: Option<usize>,
+    /// Reference definitions every page can use, from `_definitions.md` rather than this file.
+    pub link_definitions: Arc<LinkDefinitions>,
+    pub unknown_keys: Vec<String>,
+}
+
+/// Which page `index.md` maps to, and whether marked-space may change it.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct HomepageConfig {
+    /// Page to use instead of the space homepage.
+    pub id: Option<String>,
+    /// Don't modify the homepage, sync `index.md` as a child page of it instead.
+    pub locked: bool,
+    /// The title of the page of `index.md` under a locked homepage, which can't have the title of
+    /// the homepage.
+    pub title: Option<String>,
+}
+
+impl HomepageConfig {
+    fn from_yaml(yaml: &Yaml) -> Result<Self> {
+        match yaml {
+            Yaml::BadValue | Yaml::Null => Ok(HomepageConfig::default()),
+            Yaml::Hash(_) => {
+                let id = match &yaml["id"] {
+                    Yaml::BadValue | Yaml::Null => None,
+                    Yaml::String(id) => Some(id.clone()),
+                    Yaml::Integer(id) => Some(id.to_string()),
+                    _ => {
+                        return Err(anyhow!(
+                            "Failed to parse \"homepage.id\" key (should be a page id)"
+                        ))
+                    }
+                };
+                let locked = parse_bool(&yaml["locked"], "homepage.locked")?;
+                let title = match &yaml["title"] {
+                    Yaml::BadValue | Yaml::Null => None,
+                    Yaml::String(title) => Some(title.clone()),
+                    _ => {
+                        return Err(anyhow!(
+                            "Failed to parse \"homepage.title\" key (should be a string)"
+                        ))
+                    }
+                };
+                Ok(HomepageConfig { id, locked, title })
+            }
+            _ => Err(anyhow!(
+                "Failed to parse \"homepage\" key (should be a map with id, locked and/or title)"
+            )),
+        }
+    }
+}
+
+impl SpaceConfig {
+    pub fn from_directory(dir: &Path) -> Result<Self> {
+        let config_path = dir.join(SPACE_CONFIG_FILENAME);
+        if !config_path.exists() {
+            return Ok(SpaceConfig::default());
+        }
+        let content = fs::read_to_string(&config_path)?;
+        Self::from_yaml_str(&content).with_context(|| SPACE_CONFIG_FILENAME)
+    }
+
+    pub fn from_yaml_str(s: &str) -> Result<Self> {
+        let docs = Yaml::load_from_str(s).context("Failed to parse space config as YAML")?;
+        if docs.is_empty() {
+            return Ok(SpaceConfig::default());
+        }
+        let yaml = &docs[0];
+        let hash = yaml
+            .as_hash()
+            .ok_or(anyhow!("Expected YAML hash map for space config"))?;
+
+        static VALID_TOP_LEVEL_KEYS: [&str; 30] = [
+            "macros",
+            "change_comments",
+            "homepage",
+            "titles",
+            "disambiguate_headings",
+            "link_text",
+            "external_links",
+            "styles",
+            "lint",
+            "types",
+            "permissions",
+            "space",
+            "orphans",
+            "checksum",
+            "attachment_names",
+            "warnings",
+            "emoji_title",
+            "template_exclude",
+            "template_code_blocks",
+            "freeze_label",
+            "managed_label",
+            "secrets",
+            "policy",
+            "limits",
+            "archive_unpublished",
+            "provenance",
+            "edit_lock",
+            "edit_link",
+            "render_errors",
+            "max_depth",
+        ];
+        let string_keys: HashSet<&str> = hash.keys().filter_map(|key| key.as_str()).collect();
+        let mut unknown_keys: Vec<String> = string_keys
+            .difference(&HashSet::from(VALID_TOP_LEVEL_KEYS))
+            .map(|s| s.to_string())
+            .collect();
+        unknown_keys.sort();
+
+        let macros = parse_string_map(&yaml["macros"], "macros")?;
+        let change_comments = parse_bool(&yaml["change_comments"], "change_comments")?;
+        let homepage = HomepageConfig::from_yaml(&yaml["homepage"])?;
+        let titles = TitlePolicy::from_yaml(&yaml["titles"])?;
+        let disambiguate_headings =
+            parse_bool(&yaml["disambiguate_headings"], "disambiguate_headings")?;
+        let link_text = LinkText::from_str(yaml["link_text"].as_str())?;
+        let external_links = ExternalLinks::from_yaml(&yaml["external_links"])?;
+        let styles = StyleTemplates::from_yaml(&yaml["styles"])?;
+        let lint = LintRules::from_yaml(&yaml["lint"])?;
+        let types = parse_page_types(&yaml["types"])?;
+        let permissions = parse_permissions(&yaml["permissions"])?;
+        let space = SpaceDetails::from_yaml(&yaml["space"])?;
+        let orphans = OrphanPolicy::from_yaml(&yaml["orphans"])?;
+        let checksum = ChecksumAlgorithm::from_str(yaml["checksum"].as_str())?;
+        let attachment_names = AttachmentNames::from_str(yaml["attachment_names"].as_str())?;
+        let disabled_warnings = parse_disabled_warnings(&yaml["warnings"])?;
+        let emoji_title = EmojiTitle::from_str(yaml["emoji_title"].as_str())?;
+        let template_exclude = TemplateExclusions::from_yaml(&yaml["template_exclude"])?;
+        let template_code_blocks =
+            parse_bool(&yaml["template_code_blocks"], "template_code_blocks")?;
+        let freeze_label = parse_label(&yaml["freeze_label"], "freeze_label")?;
+        let managed_label = parse_label(&yaml["managed_label"], "managed_label")?;
+        let secrets = SecretRules::from_yaml(&yaml["secrets"])?;
+        let policy = match &yaml["policy"] {
+            Yaml::BadValue | Yaml::Null => None,
+            Yaml::String(path) => Some(PathBuf::from(path)),
+            _ => {
+                return Err(anyhow!(
+                    "Failed to parse \"policy\" key (should be the path of a policy file)"
+                ))
+            }
+        };
+
+        let limits = PageLimits::from_yaml(&yaml["limits"])?;
+        let archive_unpublished = parse_bool(&yaml["archive_unpublished"], "archive_unpublished")?;
+        let provenance = parse_bool(&yaml["provenance"], "provenance")?;
+        let edit_link = EditLink::from_yaml(&yaml["edit_link"])?;
+        let render_errors = RenderErrors::from_str(yaml["render_errors"].as_str())?;
+        let max_depth = parse_positive_number(&yaml["max_depth"], "max_depth")?;
+        let edit_lock = match &yaml["edit_lock"] {
+            Yaml::BadValue | Yaml::Null => None,
+            edit_lock => Some(parse_bool(edit_lock, "edit_lock")?),
+        };
+
+        Ok(SpaceConfig {
+            macros,
+            change_comments,
+            provenance,
+            homepage,
+            titles,
+            disambiguate_headings,
+            link_text,
+            external_links,
+            styles,
+            lint,
+            types,
+            permissions,
+            space,
+            orphans,
+            checksum,
+            attachment_names,
+            disabled_warnings,
+            emoji_title,
+            template_exclude,
+            template_code_blocks,
+            freeze_label,
+            managed_label,
+            secrets,
+            policy,
+            limits,
+            archive_unpublished,
+            edit_lock,
+            edit_link,
+            render_errors,
+            max_depth,
+            link_definitions: Arc::default(),
+            unknown_keys,
+        })
+    }
+}
+
+pub(crate) fn parse_bool(yaml: &Yaml, key: &str) -> Result<bool> {
+    yaml.borrowed_or(&Yaml::Boolean(false))
+        .as_bool()
+        .ok_or(anyhow!(
+            "Failed to parse \"{}\" key (should be true/false)",
+            key
+        ))
+}
+
+fn parse_label(yaml: &Yaml, key: &str) -> Result<Option<String>> {
+    match yaml {
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        Yaml::String(label) if !label.trim().is_empty() => Ok(Some(label.trim().to_string())),
+        _ => Err(anyhow!(
+            "Failed to parse \"{}\" key (should be a label)",
+            key
+        )),
+    }
+}
+
+pub(crate) fn parse_string_map(yaml: &Yaml, key: &str) -> Result<BTreeMap<String, String>> {
+    match yaml {
+        Yaml::BadValue | Yaml::Null => Ok(BTreeMap::default()),
+        Yaml::Hash(hash) => hash
+            .iter()
+            .map(|(k, v)| match (k.as_str(), v.as_str()) {
+                (Some(k), Some(v)) => Ok((String::from(k), String::from(v))),
+                _ => Err(anyhow!(
+                    "Failed to parse \"{}\" key (should be a map of strings)",
+                    key
+                )),
+            })
+            .collect(),
+        _ => Err(anyhow!(
+            "Failed to parse \"{}\" key (should be a map of strings)",
+            key
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn it_defaults_when_there_is_no_config_file() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let config = SpaceConfig::from_directory(temp.path())?;
+
+        assert_eq!(config, SpaceConfig::default());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_macros() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child(SPACE_CONFIG_FILENAME)
+            .write_str("macros:\n  status: \"<b>{{ colour }}</b>\"\n")?;
+
+        let config = SpaceConfig::from_directory(temp.path())?;
+
+        assert_eq!(
+            config.macros.get("status"),
+            Some(&String::from("<b>{{ colour }}</b>"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_change_comments() -> TestResult {
+        assert!(!SpaceConfig::from_yaml_str("macros: {}\n")?.change_comments);
+        assert!(SpaceConfig::from_yaml_str("change_comments: true\n")?.change_comments);
+        assert!(SpaceConfig::from_yaml_str("change_comments: yes\n").is_err());
+        assert!(SpaceConfig::from_yaml_str("provenance: true\n")?.provenance);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_the_homepage() -> TestResult {
+        let config = SpaceConfig::from_yaml_str(
+            "homepage:\n  id: 123456\n  locked: true\n  title: Team Handbook\n",
+        )?;
+
+        assert_eq!(
+            config.homepage,
+            HomepageConfig {
+                id: Some(String::from("123456")),
+                locked: true,
+                title: Some(String::from("Team Handbook")),
+            }
+        );
+        assert_eq!(
+            SpaceConfig::from_yaml_str("homepage: 123456\n")
+                .unwrap_err()
+                .to_string(),
+            "Failed to parse \"homepage\" key (should be a map with id, locked and/or title)"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_lint_rules() -> TestResult {
+        let config = SpaceConfig::from_yaml_str(
+            "lint:\n  max_heading_depth: 3\n  required_sections:\n    runbook: [Rollback]\n",
+        )?;
+
+        assert_eq!(config.lint.max_heading_depth, Some(3));
+        assert!(!config.lint.no_skipped_heading_levels);
+        assert_eq!(
+            config.lint.required_sections.get("runbook"),
+            Some(&vec![String::from("Rollback")])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_page_types() -> TestResult {
+        let config = SpaceConfig::from_yaml_str("types:\n  runbook:\n    template: runbook.md\n")?;
+
+        assert_eq!(
+            config.types.get("runbook").unwrap().template,
+            Some(String::from("runbook.md"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_permissions() -> TestResult {
+        let config = SpaceConfig::from_yaml_str("permissions:\n  docs-team: editor\n")?;
+
+        assert_eq!(config.permissions.get("docs-team"), Some(&Role::Editor));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_the_checksum_algorithm() -> TestResult {
+        assert_eq!(
+            SpaceConfig::from_yaml_str("macros: {}\n")?.checksum,
+            ChecksumAlgorithm::Sha256
+        );
+        assert_eq!(
+            SpaceConfig::from_yaml_str("checksum: xxh64\n")?.checksum,
+            ChecksumAlgorithm::Xxh64
+        );
+        assert!(SpaceConfig::from_yaml_str("checksum: md5\n").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_the_freeze_label() -> TestResult {
+        assert_eq!(
+            SpaceConfig::from_yaml_str("macros: {}\n")?.freeze_label,
+            None
+        );
+        assert_eq!(
+            SpaceConfig::from_yaml_str("freeze_label: docs-frozen\n")?.freeze_label,
+            Some(String::from("docs-frozen"))
+        );
+        assert!(SpaceConfig::from_yaml_str("freeze_label: [a, b]\n").is_err());
+        assert_eq!(
+            SpaceConfig::from_yaml_str("managed_label: marked-space\n")?.managed_label,
+            Some(String::from("marked-space"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_archive_unpublished() -> TestResult {
+        assert!(!SpaceConfig::from_yaml_str("macros: {}\n")?.archive_unpublished);
+        assert!(SpaceConfig::from_yaml_str("archive_unpublished: true\n")?.archive_unpublished);
+        assert!(SpaceConfig::from_yaml_str("archive_unpublished: sometimes\n").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_unknown_keys() -> TestResult {
+        let config = SpaceConfig::from_yaml_str("macros: {}\nmacro: {}\n")?;
+
+        assert_eq!(config.unknown_keys, vec!["macro"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_errors_if_macros_are_not_strings() {
+        let result = SpaceConfig::from_yaml_str("macros:\n  status:\n    - foo\n");
+
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Failed to parse \"macros\" key (should be a map of strings)"
+        );
+    }
+}
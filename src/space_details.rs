@@ -0,0 +1,306 @@
+//! The name, description and icon of the space, configured with the `space` key in `_space.yml`
+//! and reconciled on every sync.
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::blocking::multipart::Part;
+use saphyr::Yaml;
+use serde_json::json;
+
+use crate::{
+    checksum::sha256_digest,
+    confluence_client::ConfluenceClient,
+    confluence_paginator::ConfluencePaginator,
+    console::{print_warning, Status},
+    error::{ConfluenceError, Result},
+    responses::{ContentAttachment, SpaceIcon, SpaceWithDetails},
+    sync_operation::SyncOperation,
+};
+
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct SpaceDetails {
+    pub name: Option<String>,
+    /// Plain text description, shown in the space directory.
+    pub description: Option<String>,
+    /// Image file relative to the space directory.
+    pub icon: Option<PathBuf>,
+}
+
+fn parse_string(yaml: &Yaml, key: &str) -> Result<Option<String>> {
+    match yaml {
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        Yaml::String(s) => Ok(Some(s.trim().to_string())),
+        _ => Err(anyhow!(
+            "Failed to parse \"space.{}\" key (should be a string)",
+            key
+        )),
+    }
+}
+
+impl SpaceDetails {
+    pub fn from_yaml(yaml: &Yaml) -> Result<Self> {
+        match yaml {
+            Yaml::BadValue | Yaml::Null => Ok(SpaceDetails::default()),
+            Yaml::Hash(_) => Ok(SpaceDetails {
+                name: parse_string(&yaml["name"], "name")?,
+                description: parse_string(&yaml["description"], "description")?,
+                icon: parse_string(&yaml["icon"], "icon")?.map(PathBuf::from),
+            }),
+            _ => Err(anyhow!(
+                "Failed to parse \"space\" key (should be a map with name, description and/or icon)"
+            )),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self == &SpaceDetails::default()
+    }
+}
+
+pub fn sync_space_details(
+    confluence_client: &ConfluenceClient,
+    space_key: &str,
+    space_dir: &Path,
+    details: &SpaceDetails,
+) -> Result<()> {
+    if details.is_empty() {
+        return Ok(());
+    }
+    let response = confluence_client.get_space_details(space_key)?;
+    if !response.status().is_success() {
+        return Err(ConfluenceError::failed_request(response));
+    }
+    let existing: SpaceWithDetails = response.json()?;
+    let existing_description = existing
+        .description
+        .as_ref()
+        .and_then(|description| description.plain.as_ref())
+        .map(|plain| plain.value.trim());
+
+    let op = SyncOperation::start(format!("[{}] space details", space_key), false);
+    let name_changed = details
+        .name
+        .as_ref()
+        .is_some_and(|name| *name != existing.name);
+    let description_changed = details
+        .description
+        .as_ref()
+        .is_some_and(|description| Some(description.as_str()) != existing_description);
+    if name_changed || description_changed {
+        let mut body = json!({
+            "name": details.name.as_ref().unwrap_or(&existing.name),
+        });
+        if let Some(description) = &details.description {
+            body["description"] = json!({
+                "plain": {
+                    "value": description,
+                    "representation": "plain",
+                }
+            });
+        }
+        let response = confluence_client.update_space(space_key, body)?;
+        if !response.status().is_success() {
+            return Err(ConfluenceError::failed_request(response));
+        }
+        op.end(Status::Updated);
+    } else {
+        op.end(Status::Skipped);
+    }
+
+    if let Some(icon) = &details.icon {
+        sync_space_icon(
+            confluence_client,
+            space_key,
+            &space_dir.join(icon),
+            existing.icon.as_ref(),
+        )?;
+    }
+    Ok(())
+}
+
+/// The space icon is an attachment of the (hidden) space description, which only exists once the
+/// space has a custom icon. Returns the id of the description and the name of the attachment.
+fn icon_attachment(icon: &SpaceIcon) -> Option<(String, String)> {
+    static ATTACHMENT_PATH: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"/download/attachments/(\d+)/([^?]+)").unwrap());
+    let captures = ATTACHMENT_PATH.captures(&icon.path)?;
+    Some((captures[1].to_string(), captures[2].to_string()))
+}
+
+fn sync_space_icon(
+    confluence_client: &ConfluenceClient,
+    space_key: &str,
+    path: &Path,
+    existing_icon: Option<&SpaceIcon>,
+) -> Result<()> {
+    if !path.exists() {
+        return Err(anyhow!("Space icon {} does not exist", path.display()));
+    }
+    let Some((content_id, filename)) = existing_icon
+        .filter(|icon| !icon.is_default)
+        .and_then(icon_attachment)
+    else {
+        print_warning(&format!(
+            "space {} still has the default icon, upload any icon in the space settings once so marked-space can replace it",
+            space_key
+        ));
+        return Ok(());
+    };
+
+    let op = SyncOperation::start(format!("[{}] space icon", path.display()), false);
+    let hash = sha256_digest(File::open(path)?)?;
+    let existing: Vec<ContentAttachment> =
+        ConfluencePaginator::<ContentAttachment>::new(confluence_client)
+            .start(confluence_client.get_content_attachments(&content_id, &filename)?)?
+            .collect::<Result<Vec<ContentAttachment>>>()?;
+    if existing.iter().any(|attachment| {
+        attachment.title == filename
+            && attachment.metadata.comment.as_deref() == Some(&format!("hash:{}", hash))
+    }) {
+        op.end(Status::Skipped);
+        return Ok(());
+    }
+
+    let response = confluence_client.create_or_update_attachment(
+        &content_id,
+        Part::file(path)?.file_name(filename),
+        &hash,
+    )?;
+    if !response.status().is_success() {
+        return Err(ConfluenceError::failed_request(response));
+    }
+    op.end(Status::Updated);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+    use mockito::Matcher;
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    fn details_from_str(s: &str) -> Result<SpaceDetails> {
+        SpaceDetails::from_yaml(&Yaml::load_from_str(s)?[0])
+    }
+
+    #[test]
+    fn it_parses_details() -> TestResult {
+        assert_eq!(
+            details_from_str("name: Team\ndescription: |\n  All about the team\nicon: logo.png")?,
+            SpaceDetails {
+                name: Some(String::from("Team")),
+                description: Some(String::from("All about the team")),
+                icon: Some(PathBuf::from("logo.png")),
+            }
+        );
+        assert_eq!(
+            details_from_str("name: [Team]").unwrap_err().to_string(),
+            "Failed to parse \"space.name\" key (should be a string)"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_updates_changed_details() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        server
+            .mock("GET", "/wiki/rest/api/space/TEST")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(
+                r#"{"key": "TEST", "name": "Team", "description": {"plain": {"value": "Old", "representation": "plain"}}}"#,
+            )
+            .create();
+        let update = server
+            .mock("PUT", "/wiki/rest/api/space/TEST")
+            .match_body(Matcher::Json(json!({
+                "name": "Team",
+                "description": {"plain": {"value": "New", "representation": "plain"}}
+            })))
+            .with_status(200)
+            .create();
+
+        let details = SpaceDetails {
+            name: Some(String::from("Team")),
+            description: Some(String::from("New")),
+            icon: None,
+        };
+        sync_space_details(&client, "TEST", Path::new("."), &details)?;
+
+        update.assert();
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_skips_unchanged_details() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        server
+            .mock("GET", "/wiki/rest/api/space/TEST")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(r#"{"key": "TEST", "name": "Team"}"#)
+            .create();
+        let update = server
+            .mock("PUT", "/wiki/rest/api/space/TEST")
+            .expect(0)
+            .create();
+
+        let details = SpaceDetails {
+            name: Some(String::from("Team")),
+            ..Default::default()
+        };
+        sync_space_details(&client, "TEST", Path::new("."), &details)?;
+
+        update.assert();
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_replaces_a_changed_icon() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("logo.png").write_str("not really a png")?;
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        server
+            .mock("GET", "/wiki/rest/api/space/TEST")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(
+                r#"{"key": "TEST", "name": "Team", "icon": {"path": "/wiki/download/attachments/65538/TEST?version=1", "isDefault": false}}"#,
+            )
+            .create();
+        server
+            .mock("GET", "/wiki/rest/api/content/65538/child/attachment")
+            .match_query(Matcher::UrlEncoded("filename".into(), "TEST".into()))
+            .with_status(200)
+            .with_body(r#"{"results": [{"title": "TEST", "metadata": {"comment": "hash:OLD"}}]}"#)
+            .create();
+        let upload = server
+            .mock("PUT", "/wiki/rest/api/content/65538/child/attachment")
+            .with_status(200)
+            .create();
+
+        let details = SpaceDetails {
+            icon: Some(PathBuf::from("logo.png")),
+            ..Default::default()
+        };
+        sync_space_details(&client, "TEST", temp.path(), &details)?;
+
+        upload.assert();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,456 @@
+//! Space permissions declared with the `permissions` key in `_space.yml`, as a map of group names
+//! to roles. The `permissions` subcommand reports where the permissions of the space differ from
+//! it, and changes them to match with `--apply`.
+//!
+//! Only the groups listed in `_space.yml` are managed, permissions of users and other groups are
+//! left alone.
+use std::{collections::BTreeMap, fmt::Display};
+
+use anyhow::anyhow;
+use saphyr::Yaml;
+use serde_json::json;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    confluence_paginator::ConfluencePaginator,
+    confluence_space::ConfluenceSpace,
+    console::{print_info, print_status, print_warning, Status},
+    error::{ConfluenceError, Result},
+    markdown_space::MarkdownSpace,
+    responses::{Group, SpacePermission},
+};
+
+const VIEWER: &[(&str, &str)] = &[("read", "space")];
+const COMMENTER: &[(&str, &str)] = &[("create", "comment")];
+const EDITOR: &[(&str, &str)] = &[
+    ("create", "page"),
+    ("create", "blogpost"),
+    ("create", "attachment"),
+    ("delete", "attachment"),
+    ("archive", "page"),
+];
+const ADMIN: &[(&str, &str)] = &[
+    ("delete", "page"),
+    ("delete", "blogpost"),
+    ("delete", "comment"),
+    ("export", "space"),
+    ("restrict_content", "space"),
+    ("administer", "space"),
+];
+
+/// Each role includes the operations of the roles before it.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Role {
+    Viewer,
+    Commenter,
+    Editor,
+    Admin,
+}
+
+impl Role {
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "viewer" => Some(Role::Viewer),
+            "commenter" => Some(Role::Commenter),
+            "editor" => Some(Role::Editor),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn operations(&self) -> Vec<Operation> {
+        [
+            (Role::Viewer, VIEWER),
+            (Role::Commenter, COMMENTER),
+            (Role::Editor, EDITOR),
+            (Role::Admin, ADMIN),
+        ]
+        .iter()
+        .filter(|(role, _)| role <= self)
+        .flat_map(|(_, operations)| operations.iter())
+        .map(|(key, target)| Operation::new(key, target))
+        .collect()
+    }
+}
+
+/// A space permission, like `create page`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct Operation {
+    pub key: String,
+    pub target: String,
+}
+
+impl Operation {
+    pub fn new(key: &str, target: &str) -> Self {
+        Operation {
+            key: String::from(key),
+            target: String::from(target),
+        }
+    }
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.key, self.target)
+    }
+}
+
+pub fn parse_permissions(yaml: &Yaml) -> Result<BTreeMap<String, Role>> {
+    let error = || {
+        anyhow!(
+            "Failed to parse \"permissions\" key (should be a map of group names to viewer, commenter, editor or admin)"
+        )
+    };
+    match yaml {
+        Yaml::BadValue | Yaml::Null => Ok(BTreeMap::default()),
+        Yaml::Hash(hash) => hash
+            .iter()
+            .map(|(group, role)| {
+                let group = group.as_str().ok_or_else(error)?;
+                let role = role.as_str().and_then(Role::from_str).ok_or_else(error)?;
+                Ok((String::from(group), role))
+            })
+            .collect(),
+        _ => Err(error()),
+    }
+}
+
+/// A difference between the permissions of a group in the space and its role in `_space.yml`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PermissionChange {
+    Grant {
+        group: String,
+        operation: Operation,
+    },
+    Revoke {
+        group: String,
+        operation: Operation,
+        permission_id: String,
+    },
+}
+
+impl Display for PermissionChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PermissionChange::Grant { group, operation } => {
+                write!(f, "{} is missing \"{}\"", group, operation)
+            }
+            PermissionChange::Revoke {
+                group, operation, ..
+            } => write!(f, "{} has extra \"{}\"", group, operation),
+        }
+    }
+}
+
+/// Compares the permissions (id and operation) each configured group has with the operations of
+/// its role. Read access is granted first, as Confluence doesn't allow any other permission
+/// without it.
+pub fn plan_changes(
+    desired: &BTreeMap<String, Role>,
+    current: &BTreeMap<String, Vec<(String, Operation)>>,
+) -> Vec<PermissionChange> {
+    let mut grants = Vec::<PermissionChange>::default();
+    let mut revokes = Vec::<PermissionChange>::default();
+    for (group, role) in desired {
+        let existing = current.get(group).map(Vec::as_slice).unwrap_or_default();
+        let operations = role.operations();
+        grants.extend(
+            operations
+                .iter()
+                .filter(|operation| !existing.iter().any(|(_, o)| o == *operation))
+                .map(|operation| PermissionChange::Grant {
+                    group: group.clone(),
+                    operation: operation.clone(),
+                }),
+        );
+        revokes.extend(
+            existing
+                .iter()
+                .filter(|(_, operation)| !operations.contains(operation))
+                .map(|(permission_id, operation)| PermissionChange::Revoke {
+                    group: group.clone(),
+                    operation: operation.clone(),
+                    permission_id: permission_id.clone(),
+                }),
+        );
+    }
+    grants.extend(revokes);
+    grants
+}
+
+fn get_group(confluence_client: &ConfluenceClient, name: &str) -> Result<Group> {
+    let response = confluence_client.get_group_by_name(name)?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(anyhow!("No such group: {}", name));
+    }
+    if !response.status().is_success() {
+        return Err(ConfluenceError::failed_request(response));
+    }
+    Ok(response.json()?)
+}
+
+/// Reports (and with `apply` makes) the changes needed to give the configured groups the
+/// permissions of their role, returning the number of changes.
+pub fn sync_permissions(
+    confluence_client: &ConfluenceClient,
+    space_key: &str,
+    space_id: &str,
+    desired: &BTreeMap<String, Role>,
+    apply: bool,
+) -> Result<usize> {
+    let permissions = ConfluencePaginator::<SpacePermission>::new(confluence_client)
+        .start(confluence_client.get_space_permissions(space_id)?)?
+        .collect::<Result<Vec<SpacePermission>>>()?;
+
+    let mut group_ids = BTreeMap::<String, String>::default();
+    let mut current = BTreeMap::<String, Vec<(String, Operation)>>::default();
+    for name in desired.keys() {
+        let group = get_group(confluence_client, name)?;
+        let existing = permissions
+            .iter()
+            .filter(|permission| {
+                permission.principal._type == "group" && permission.principal.id == group.id
+            })
+            .map(|permission| {
+                (
+                    permission.id.clone(),
+                    Operation::new(&permission.operation.key, &permission.operation.target_type),
+                )
+            })
+            .collect();
+        current.insert(name.clone(), existing);
+        group_ids.insert(name.clone(), group.id);
+    }
+
+    let changes = plan_changes(desired, &current);
+    for change in &changes {
+        if !apply {
+            print_warning(&change.to_string());
+            continue;
+        }
+        let response = match change {
+            PermissionChange::Grant { group, operation } => confluence_client
+                .add_space_permission(
+                    space_key,
+                    json!({
+                        "subject": {
+                            "type": "group",
+                            "identifier": group_ids[group],
+                        },
+                        "operation": {
+                            "key": operation.key,
+                            "target": operation.target,
+                        },
+                    }),
+                )?,
+            PermissionChange::Revoke { permission_id, .. } => {
+                confluence_client.remove_space_permission(space_key, permission_id)?
+            }
+        };
+        if !response.status().is_success() {
+            return Err(ConfluenceError::failed_request(response));
+        }
+        match change {
+            PermissionChange::Grant { group, operation } => {
+                print_status(Status::Created, &format!("{} for {}", operation, group))
+            }
+            PermissionChange::Revoke {
+                group, operation, ..
+            } => print_status(Status::Deleted, &format!("{} for {}", operation, group)),
+        }
+    }
+    Ok(changes.len())
+}
+
+/// The `permissions` subcommand, which fails when there is drift that isn't applied so it can
+/// run as a scheduled check.
+pub fn permissions(
+    confluence_client: ConfluenceClient,
+    markdown_space: &MarkdownSpace,
+    apply: bool,
+) -> Result<()> {
+    let desired = &markdown_space.config.permissions;
+    if desired.is_empty() {
+        return Err(anyhow!("No permissions configured in _space.yml"));
+    }
+    let space = ConfluenceSpace::get(&confluence_client, &markdown_space.key)?;
+    let changes = sync_permissions(
+        &confluence_client,
+        &markdown_space.key,
+        &space.id,
+        desired,
+        apply,
+    )?;
+    if changes == 0 {
+        print_info("Space permissions match _space.yml");
+    } else if !apply {
+        return Err(anyhow!(
+            "{} permission(s) differ from _space.yml, run with --apply to change them",
+            changes
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Matcher;
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    fn permissions_from_str(s: &str) -> Result<BTreeMap<String, Role>> {
+        parse_permissions(&Yaml::load_from_str(s)?[0])
+    }
+
+    #[test]
+    fn it_parses_permissions() -> TestResult {
+        let permissions = permissions_from_str("docs-team: editor\nconfluence-users: viewer")?;
+
+        assert_eq!(permissions.get("docs-team"), Some(&Role::Editor));
+        assert_eq!(permissions.get("confluence-users"), Some(&Role::Viewer));
+        assert_eq!(
+            permissions_from_str("docs-team: owner")
+                .unwrap_err()
+                .to_string(),
+            "Failed to parse \"permissions\" key (should be a map of group names to viewer, commenter, editor or admin)"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_includes_the_operations_of_lower_roles() {
+        assert_eq!(
+            Role::Commenter.operations(),
+            vec![
+                Operation::new("read", "space"),
+                Operation::new("create", "comment")
+            ]
+        );
+        assert!(Role::Admin
+            .operations()
+            .contains(&Operation::new("create", "page")));
+    }
+
+    #[test]
+    fn it_plans_changes() {
+        let desired = BTreeMap::from([
+            (String::from("docs-team"), Role::Commenter),
+            (String::from("readers"), Role::Viewer),
+        ]);
+        let current = BTreeMap::from([
+            (
+                String::from("docs-team"),
+                vec![
+                    (String::from("1"), Operation::new("read", "space")),
+                    (String::from("2"), Operation::new("create", "page")),
+                ],
+            ),
+            (
+                String::from("readers"),
+                vec![(String::from("3"), Operation::new("read", "space"))],
+            ),
+        ]);
+
+        assert_eq!(
+            plan_changes(&desired, &current),
+            vec![
+                PermissionChange::Grant {
+                    group: String::from("docs-team"),
+                    operation: Operation::new("create", "comment")
+                },
+                PermissionChange::Revoke {
+                    group: String::from("docs-team"),
+                    operation: Operation::new("create", "page"),
+                    permission_id: String::from("2")
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_leaves_matching_groups_alone() {
+        let desired = BTreeMap::from([(String::from("docs-team"), Role::Viewer)]);
+        let current = BTreeMap::from([(
+            String::from("docs-team"),
+            vec![(String::from("1"), Operation::new("read", "space"))],
+        )]);
+
+        assert!(plan_changes(&desired, &current).is_empty());
+    }
+
+    #[test]
+    fn it_applies_changes() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        server
+            .mock("GET", "/wiki/api/v2/spaces/98306/permissions")
+            .with_status(200)
+            .with_body(
+                r#"{"results": [
+                    {"id": "1", "principal": {"type": "group", "id": "g-1"}, "operation": {"key": "read", "targetType": "space"}},
+                    {"id": "2", "principal": {"type": "group", "id": "g-1"}, "operation": {"key": "administer", "targetType": "space"}},
+                    {"id": "3", "principal": {"type": "user", "id": "u-1"}, "operation": {"key": "administer", "targetType": "space"}}
+                ], "_links": {}}"#,
+            )
+            .create();
+        server
+            .mock("GET", "/wiki/rest/api/group/by-name")
+            .match_query(Matcher::UrlEncoded("name".into(), "docs-team".into()))
+            .with_status(200)
+            .with_body(r#"{"id": "g-1", "name": "docs-team"}"#)
+            .create();
+        let grant = server
+            .mock("POST", "/wiki/rest/api/space/TEST/permission")
+            .match_body(Matcher::PartialJson(json!({
+                "subject": {"type": "group", "identifier": "g-1"},
+                "operation": {"key": "create", "target": "comment"}
+            })))
+            .with_status(200)
+            .create();
+        let revoke = server
+            .mock("DELETE", "/wiki/rest/api/space/TEST/permission/2")
+            .with_status(204)
+            .create();
+
+        let desired = BTreeMap::from([(String::from("docs-team"), Role::Commenter)]);
+        let changes = sync_permissions(&client, "TEST", "98306", &desired, true)?;
+
+        assert_eq!(changes, 2);
+        grant.assert();
+        revoke.assert();
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_only_reports_changes_without_apply() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        server
+            .mock("GET", "/wiki/api/v2/spaces/98306/permissions")
+            .with_status(200)
+            .with_body(r#"{"results": [], "_links": {}}"#)
+            .create();
+        server
+            .mock("GET", "/wiki/rest/api/group/by-name")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(r#"{"id": "g-1", "name": "docs-team"}"#)
+            .create();
+        let grant = server
+            .mock("POST", "/wiki/rest/api/space/TEST/permission")
+            .expect(0)
+            .create();
+
+        let desired = BTreeMap::from([(String::from("docs-team"), Role::Viewer)]);
+        let changes = sync_permissions(&client, "TEST", "98306", &desired, false)?;
+
+        assert_eq!(changes, 1);
+        grant.assert();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+//! The `status` subcommand, which lists how the markdown files differ from their pages in
+//! Confluence, like `git status`, without changing anything.
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    confluence_space::ConfluenceSpace,
+    console::set_quiet,
+    link_generator::LinkGenerator,
+    markdown_page::MarkdownPage,
+    markdown_space::MarkdownSpace,
+    mentions::CachedUsers,
+    page_tree::{local_status, NodeStatus},
+    publish_schedule::PublishSchedule,
+    responses::ContentStatus,
+    sync::{check_homepage_exists, load_space, LoadedSpace},
+    Args, Result,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct StatusEntry {
+    pub status: NodeStatus,
+    /// The markdown file, or for orphans the file the page was synced from.
+    pub source: String,
+    pub title: String,
+    /// When a scheduled page is published.
+    pub publish_after: Option<DateTime<Utc>>,
+}
+
+pub fn collect_status(
+    markdown_pages: &[MarkdownPage],
+    link_generator: &LinkGenerator,
+    space: &ConfluenceSpace,
+    schedule: &PublishSchedule,
+) -> Result<Vec<StatusEntry>> {
+    let mut entries = markdown_pages
+        .iter()
+        .map(|markdown_page| {
+            let (_id, status) = local_status(markdown_page, link_generator, space, schedule)?;
+            Ok(StatusEntry {
+                status,
+                source: markdown_page.source.replace('\\', "/"),
+                title: markdown_page.title.clone(),
+                publish_after: schedule.publish_after(&markdown_page.source),
+            })
+        })
+        .collect::<Result<Vec<StatusEntry>>>()?;
+    entries.extend(space.nodes().iter().filter_map(|node| {
+        let page_data = node.page_data()?;
+        if matches!(page_data.status, ContentStatus::Archived)
+            || !link_generator.is_orphaned(node, page_data)
+        {
+            return None;
+        }
+        Some(StatusEntry {
+            status: NodeStatus::Orphan,
+            source: page_data
+                .path
+                .as_ref()
+                .map(|path| path.to_string_lossy().replace('\\', "/"))
+                .unwrap_or_default(),
+            title: node.title.clone(),
+            publish_after: None,
+        })
+    }));
+    entries.sort_by(|a, b| a.source.cmp(&b.source));
+    Ok(entries)
+}
+
+pub fn render_status(entries: &[StatusEntry]) -> String {
+    static SECTIONS: [(NodeStatus, &str); 9] = [
+        (NodeStatus::New, "New pages"),
+        (NodeStatus::Changed, "Modified"),
+        (
+            NodeStatus::RemotelyModified,
+            "Changed in Confluence since the last sync (will be overwritten)",
+        ),
+        (
+            NodeStatus::Adopted,
+            "Never synced, matched by title (will be taken over)",
+        ),
+        (NodeStatus::Moved, "Moved"),
+        (NodeStatus::Restored, "Restored from the archive"),
+        (NodeStatus::Orphan, "Orphaned (will be archived)"),
+        (
+            NodeStatus::Scheduled,
+            "Scheduled (held back until their time)",
+        ),
+        (NodeStatus::Unpublished, "Not published (publish: false)"),
+    ];
+    let mut output = String::default();
+    for (status, heading) in SECTIONS {
+        let section: Vec<&StatusEntry> = entries.iter().filter(|e| e.status == status).collect();
+        if section.is_empty() {
+            continue;
+        }
+        output.push_str(&format!("{}:\n", heading));
+        for entry in section {
+            output.push_str(&format!("  {} \"{}\"", entry.source, entry.title));
+            if let Some(publish_after) = entry.publish_after {
+                output.push_str(&format!(
+                    " until {}",
+                    publish_after.format("%Y-%m-%d %H:%M UTC")
+                ));
+            }
+            output.push('\n');
+        }
+        output.push('\n');
+    }
+    let unchanged = entries
+        .iter()
+        .filter(|entry| entry.status == NodeStatus::UpToDate)
+        .count();
+    if unchanged == entries.len() {
+        output.push_str(&format!("All {} page(s) are up to date\n", unchanged));
+    } else {
+        output.push_str(&format!("{} page(s) unchanged\n", unchanged));
+    }
+    output
+}
+
+pub fn status(
+    confluence_client: ConfluenceClient,
+    markdown_space: &mut MarkdownSpace,
+    args: Args,
+) -> Result<()> {
+    set_quiet(true);
+    let space_key = markdown_space.key.clone();
+    let LoadedSpace {
+        markdown_pages,
+        mut space,
+        mut link_generator,
+        schedule,
+        ..
+    } = load_space(
+        &confluence_client,
+        markdown_space,
+        &args,
+        Arc::new(CachedUsers::new(confluence_client.clone())),
+    )?;
+
+    space.read_all_pages(&confluence_client)?;
+    check_homepage_exists(&space, &space_key)?;
+    space.link_pages(&mut link_generator)?;
+
+    print!(
+        "{}",
+        render_status(&collect_status(
+            &markdown_pages,
+            &link_generator,
+            &space,
+            &schedule
+        )?)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{
+        confluence_page::{ConfluenceNode, ConfluenceNodeType, ConfluencePageData},
+        error::TestResult,
+        page_map::PageMapEntry,
+        responses::Version,
+        test_helpers::markdown_page_from_str,
+    };
+
+    use super::*;
+
+    fn page(id: &str, title: &str, source: &str, checksum: &str) -> ConfluenceNode {
+        ConfluenceNode {
+            id: String::from(id),
+            title: String::from(title),
+            parent_id: Some(String::from("999")),
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: Version {
+                    message: format!(
+                        "updated by markedspace: source={}; checksum={}",
+                        source, checksum
+                    ),
+                    number: 1,
+                },
+                path: Some(PathBuf::from(source)),
+                status: ContentStatus::Current,
+            }),
+        }
+    }
+
+    #[test]
+    fn it_lists_pages_by_status() -> TestResult {
+        let pages = vec![
+            markdown_page_from_str("new.md", "# New\n")?,
+            markdown_page_from_str("same.md", "# Same\n")?,
+            markdown_page_from_str("edited.md", "# Edited\n")?,
+            markdown_page_from_str("adopted.md", "# Adopted\n")?,
+            markdown_page_from_str(
+                "launch.md",
+                "---\npublish_after: 2099-01-01T09:00Z\n---\n# Launch\n",
+            )?,
+        ];
+        let schedule = PublishSchedule::new(&pages, Utc::now());
+        let mut link_generator = LinkGenerator::default_test();
+        for page in &pages {
+            link_generator.register_markdown_page(page)?;
+        }
+        let same = pages[1].render(&link_generator)?;
+        let mut nodes = vec![
+            page("1", "Same", "same.md", &same.checksum),
+            page("2", "Edited", "edited.md", "OLD"),
+            page("3", "Gone", "gone.md", "OLD"),
+            page("4", "Adopted", "adopted.md", "OLD"),
+        ];
+        // both were edited by hand, but only the first one was synced before
+        for index in [1, 3] {
+            if let ConfluenceNodeType::Page(page_data) = &mut nodes[index].data {
+                page_data.version.message = String::from("fixed a typo");
+            }
+        }
+        let mut space = ConfluenceSpace::new_test("1", "999", nodes);
+        space.page_map.pages.insert(
+            String::from("edited.md"),
+            PageMapEntry {
+                id: String::from("2"),
+                checksum: Some(String::from("OLD")),
+                failed: false,
+            },
+        );
+        for node in space.nodes() {
+            link_generator.register_confluence_node(node);
+        }
+
+        let entries = collect_status(&pages, &link_generator, &space, &schedule)?;
+
+        assert_eq!(
+            render_status(&entries),
+            "New pages:\n  new.md \"New\"\n\nChanged in Confluence since the last sync (will be overwritten):\n  edited.md \"Edited\"\n\nNever synced, matched by title (will be taken over):\n  adopted.md \"Adopted\"\n\nOrphaned (will be archived):\n  gone.md \"Gone\"\n\nScheduled (held back until their time):\n  launch.md \"Launch\" until 2099-01-01 09:00 UTC\n\n1 page(s) unchanged\n"
+        );
+
+        Ok(())
+    }
+}
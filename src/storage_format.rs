@@ -0,0 +1,203 @@
+//! Normalizing storage format, so that pages are compared on their content rather than on how the
+//! markup happens to be written.
+//!
+//! Attributes are put in alphabetical order, empty elements are written as self-closing tags and
+//! whitespace is collapsed. Whitespace between tags is dropped next to block-level elements, where it
+//! doesn't show, and kept between inline ones, e.g. the space in `<strong>a</strong> <em>b</em>`.
+//! Confluence adds ids to macros and elements when it stores a page, those attributes are dropped so
+//! a body read back from Confluence normalizes to the same markup as the rendered page. CDATA
+//! sections (code blocks) are left as they are.
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+/// Elements whitespace next to doesn't show on the page.
+static BLOCK_ELEMENTS: [&str; 28] = [
+    "p",
+    "div",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "ul",
+    "ol",
+    "li",
+    "table",
+    "colgroup",
+    "col",
+    "thead",
+    "tbody",
+    "tfoot",
+    "tr",
+    "th",
+    "td",
+    "blockquote",
+    "pre",
+    "hr",
+    "br",
+    "details",
+    "summary",
+    "figure",
+    "figcaption",
+];
+
+/// Confluence elements that are part of the text, every other `ac:` and `ri:` element only holds
+/// the parameters and bodies of these.
+static INLINE_CONFLUENCE_ELEMENTS: [&str; 5] = [
+    "ac:link",
+    "ac:image",
+    "ac:emoticon",
+    "ac:inline-comment-marker",
+    "ac:structured-macro",
+];
+
+/// Whether whitespace before or after the tag doesn't show on the page.
+fn is_block_tag(tag: &str) -> bool {
+    let name = tag
+        .trim_start_matches(['<', '/'])
+        .split(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    if name.starts_with("ac:") || name.starts_with("ri:") {
+        !INLINE_CONFLUENCE_ELEMENTS.contains(&name.as_str())
+    } else {
+        BLOCK_ELEMENTS.contains(&name.as_str())
+    }
+}
+
+/// Attributes Confluence adds to the storage format of a page when it's saved.
+static VOLATILE_ATTRIBUTES: [&str; 4] = [
+    "ac:macro-id",
+    "ac:local-id",
+    "local-id",
+    "ac:schema-version",
+];
+
+fn normalize_markup(markup: &str) -> String {
+    static TAG: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r#"<([A-Za-z][\w:.-]*)((?:\s+[\w:.-]+="[^"]*")*)\s*(/?)>"#).unwrap()
+    });
+    static ATTRIBUTE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"([\w:.-]+)="[^"]*""#).unwrap());
+    static EMPTY_ELEMENT: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r#"<([A-Za-z][\w:.-]*)([^<>]*?)\s*></([A-Za-z][\w:.-]*)>"#).unwrap()
+    });
+    static BETWEEN_TAGS: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"(<[^<>]*>)\s+(<[^<>]*>)").unwrap());
+    static WHITESPACE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\s+").unwrap());
+
+    let markup = TAG.replace_all(markup, |captures: &Captures| {
+        let mut attributes: Vec<&str> = ATTRIBUTE
+            .captures_iter(&captures[2])
+            .filter(|attribute| !VOLATILE_ATTRIBUTES.contains(&&attribute[1]))
+            .filter_map(|attribute| attribute.get(0).map(|m| m.as_str()))
+            .collect();
+        attributes.sort();
+        let attributes: String = attributes
+            .iter()
+            .map(|attribute| format!(" {}", attribute))
+            .collect();
+        if captures[3].is_empty() {
+            format!("<{}{}>", &captures[1], attributes)
+        } else {
+            format!("<{}{} />", &captures[1], attributes)
+        }
+    });
+    let markup = EMPTY_ELEMENT.replace_all(&markup, |captures: &Captures| {
+        if captures[1] == captures[3] {
+            format!("<{}{} />", &captures[1], &captures[2])
+        } else {
+            String::from(&captures[0])
+        }
+    });
+    let markup = WHITESPACE.replace_all(&markup, " ");
+    // the closing tag of a match is the opening tag of the next whitespace, so they're taken one
+    // at a time
+    let mut normalized = String::with_capacity(markup.len());
+    let mut last = 0;
+    while let Some(tags) = BETWEEN_TAGS.captures_at(&markup, last) {
+        let (before, after) = (tags.get(1).unwrap(), tags.get(2).unwrap());
+        normalized.push_str(&markup[last..before.end()]);
+        if !is_block_tag(before.as_str()) && !is_block_tag(after.as_str()) {
+            normalized.push(' ');
+        }
+        last = after.start();
+    }
+    normalized.push_str(&markup[last..]);
+    normalized
+}
+
+pub fn normalize(storage: &str) -> String {
+    static CDATA: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)<!\[CDATA\[.*?\]\]>").unwrap());
+
+    let mut normalized = String::with_capacity(storage.len());
+    let mut last = 0;
+    for cdata in CDATA.find_iter(storage) {
+        normalized.push_str(&normalize_markup(&storage[last..cdata.start()]));
+        normalized.push_str(cdata.as_str());
+        last = cdata.end();
+    }
+    normalized.push_str(&normalize_markup(&storage[last..]));
+    normalized.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_orders_attributes() {
+        assert_eq!(
+            normalize(r#"<a title="t" href="x">link</a>"#),
+            normalize(r#"<a href="x" title="t">link</a>"#)
+        );
+    }
+
+    #[test]
+    fn it_normalizes_empty_elements_and_whitespace() {
+        assert_eq!(
+            normalize("<p>one\n  two</p>\n<br/>\n<td></td>"),
+            "<p>one two</p><br /><td />"
+        );
+    }
+
+    #[test]
+    fn it_keeps_whitespace_between_inline_elements() {
+        assert_eq!(
+            normalize("<p>\n  <strong>a</strong> <em>b</em>\n</p>\n<ul> <li>c</li> </ul>"),
+            "<p><strong>a</strong> <em>b</em></p><ul><li>c</li></ul>"
+        );
+        assert_eq!(
+            normalize("<a href=\"x\" title=\"a b\">a</a>\n<ac:emoticon ac:name=\"tick\" />"),
+            "<a href=\"x\" title=\"a b\">a</a> <ac:emoticon ac:name=\"tick\" />"
+        );
+        assert_ne!(
+            normalize("<p><strong>a</strong> <em>b</em></p>"),
+            normalize("<p><strong>a</strong><em>b</em></p>")
+        );
+    }
+
+    #[test]
+    fn it_ignores_what_confluence_adds() {
+        let rendered = r#"<ac:structured-macro ac:name="toc"></ac:structured-macro><p>Hello</p>"#;
+        let stored = r#"<ac:structured-macro ac:name="toc" ac:schema-version="1" data-layout="default" ac:macro-id="8f1b"/><p local-id="a1">Hello</p>"#;
+
+        assert_eq!(
+            normalize(stored),
+            r#"<ac:structured-macro ac:name="toc" data-layout="default" /><p>Hello</p>"#
+        );
+        assert_eq!(
+            normalize(rendered),
+            r#"<ac:structured-macro ac:name="toc" /><p>Hello</p>"#
+        );
+    }
+
+    #[test]
+    fn it_leaves_code_alone() {
+        let code =
+            "<ac:plain-text-body><![CDATA[fn main() {\n    <a  b=\"c\">\n}]]></ac:plain-text-body>";
+
+        assert_eq!(normalize(code), code);
+    }
+}
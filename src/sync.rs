@@ -1,60 +1,120 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
     fs::{create_dir_all, File},
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use anyhow::Ok;
+use chrono::Utc;
 use serde_json::json;
 
 use crate::{
     attachments::sync_page_attachments,
+    cancellation::{self, print_remaining},
+    change_comments::post_change_comment,
+    conflicts::{check_conflict, is_temporary_rename},
     confluence_client::ConfluenceClient,
-    confluence_page::ConfluenceNode,
+    confluence_page::{ConfluenceNode, ConfluencePageData},
+    confluence_paginator::ConfluencePaginator,
     confluence_space::ConfluenceSpace,
-    console::{print_error, print_info, print_status, Status},
-    error::ConfluenceError,
+    confluence_templates::sync_confluence_templates,
+    console::{print_error, print_info, print_status, print_warning, Status},
+    content_policy::check_content_policy,
+    dry_run::print_dry_run,
+    error::{ApiResponse, ConfluenceError},
+    excerpts::CachedExcerpts,
+    external_links::print_rewritten_links,
     folders::sync_folder,
     link_generator::LinkGenerator,
-    markdown_page::{MarkdownPage, RenderedPage},
+    markdown_page::{remove_prefix, MarkdownPage, RenderedPage},
     markdown_space::MarkdownSpace,
+    mentions::CachedUsers,
+    page_ids,
+    page_map::PageMapEntry,
+    page_moves::plan_moves,
     page_properties::sync_page_properties,
-    responses::{self, MultiEntityResult},
+    page_titles::{execute_renames, plan_temporary_renames},
+    parallel::{map, render_batch_size},
+    parse_cache::ParseCache,
+    placeholder_pages::RenderErrors,
+    provenance::Provenance,
+    publish_schedule::PublishSchedule,
+    render_profile::RenderProfile,
+    report::{write_links_manifest, write_reports_json, SyncReport},
+    responses::{self, BodySingle, MultiEntityResult, PageSingleWithBody},
     restrictions::{sync_restrictions, RestrictionType},
+    search_index::SearchIndex,
+    secrets::check_for_secrets,
     sort::sync_sort,
+    space_config::SpaceConfig,
+    space_details::sync_space_details,
+    space_status::collect_status,
+    storage_format::normalize,
+    sync_lock::SyncLock,
     sync_operation::SyncOperation,
+    sync_stats::{self, PageState},
     template_renderer::TemplateRenderer,
+    warnings::{report, Warning},
+    watch::affected_pages,
+    watchers::{resolve_account_ids, sync_page_watchers},
     Args, Result,
 };
 
-// Returns the ID of the page that the content was synced to.
+/// What syncing the content of an existing page did.
+#[derive(Debug, PartialEq, Eq)]
+enum PageContent {
+    /// Updated or left as it was, and whether the content of a previously synced page changed.
+    Synced { content_changed: bool },
+    /// Never synced, and already the same as the rendered page. It's left as it is, without the
+    /// version message of a sync, so it's recorded without a checksum: a checksum would make it
+    /// look edited in Confluence since the last sync.
+    Adopted,
+}
+
 fn sync_page_content(
     confluence_client: &ConfluenceClient,
     space: &ConfluenceSpace,
     rendered_page: RenderedPage,
     existing_node: &ConfluenceNode,
-) -> Result<()> {
+    compare_remote: bool,
+) -> Result<PageContent> {
     let page_data = existing_node.page_data().unwrap();
     let op = SyncOperation::start(
         format!("[{}] \"{}\"", rendered_page.source, rendered_page.title),
         true,
     );
 
-    let parent_id = if rendered_page.is_home_page() {
-        None
-    } else if let Some(parent) = rendered_page.parent.clone() {
-        Some(parent)
-    } else {
-        Some(space.homepage_id.clone())
-    };
+    let parent_id = rendered_page.parent.clone();
 
     let id = existing_node.id.clone();
     let version_message = rendered_page.version_message();
     if page_up_to_date(existing_node, &rendered_page, &parent_id, &version_message) {
         op.end(Status::Skipped);
-        return Ok(());
+        return Ok(PageContent::Synced {
+            content_changed: false,
+        });
+    }
+    let stored_checksum = ConfluencePageData::extract_checksum(&page_data.version);
+    // pages that weren't synced before (or with --compare-remote) are compared on their body, so
+    // adopting a page that already has the same content doesn't rewrite it
+    if (compare_remote || stored_checksum.is_none())
+        && parent_id == existing_node.parent_id
+        && existing_node.title == rendered_page.title
+        && remote_body_matches(confluence_client, &id, &rendered_page.content)
+            .inspect_err(|_| op.end(Status::Error))?
+    {
+        op.end(Status::Skipped);
+        return Ok(match stored_checksum {
+            Some(_) => PageContent::Synced {
+                content_changed: false,
+            },
+            None => PageContent::Adopted,
+        });
     }
+    let content_changed =
+        stored_checksum.is_some_and(|checksum| !rendered_page.has_checksum(&checksum));
 
     let update_payload = json!({
         "id": id.clone(),
@@ -78,10 +138,26 @@ fn sync_page_content(
         Err(ConfluenceError::failed_request(resp))
     } else {
         op.end(Status::Updated);
-        Ok(())
+        Ok(PageContent::Synced { content_changed })
     }
 }
 
+/// Whether the storage format of the page in Confluence normalizes to the same as `content`.
+fn remote_body_matches(
+    confluence_client: &ConfluenceClient,
+    page_id: &str,
+    content: &str,
+) -> Result<bool> {
+    let page: PageSingleWithBody = confluence_client
+        .get_page_with_body(page_id)?
+        .api_result()?
+        .json()?;
+    Ok(match page.body {
+        BodySingle::Storage(body) => normalize(&body.value) == normalize(content),
+        _ => false,
+    })
+}
+
 fn page_up_to_date(
     existing_node: &ConfluenceNode,
     page: &RenderedPage,
@@ -90,61 +166,616 @@ fn page_up_to_date(
 ) -> bool {
     parent_id == &existing_node.parent_id
         && existing_node.title == page.title
-        && version_message == &existing_node.page_data().unwrap().version.message
+        && (version_message == &existing_node.page_data().unwrap().version.message
+            || page.is_version_message(&existing_node.page_data().unwrap().version.message))
 }
 
-pub fn sync_space<'a>(
-    mut confluence_client: ConfluenceClient,
-    markdown_space: &'a mut MarkdownSpace<'a>,
-    args: Args,
-) -> Result<()> {
+/// The parsed pages of a space, registered with a link generator for the Confluence space they are
+/// synced to.
+pub(crate) struct LoadedSpace {
+    pub markdown_pages: Vec<MarkdownPage>,
+    /// Pages from `_confluence_templates`, published as space templates.
+    pub confluence_templates: Vec<MarkdownPage>,
+    pub space: ConfluenceSpace,
+    pub link_generator: LinkGenerator,
+    pub users: Arc<CachedUsers>,
+    /// Pages that can't be published yet.
+    pub schedule: PublishSchedule,
+    /// Warnings about the space rather than one of its pages, e.g. a mention of an unknown user.
+    pub warnings: Vec<Warning>,
+}
+
+/// Parses the markdown space and prepares linking it to the Confluence space, without reading the
+/// pages of the Confluence space yet.
+pub(crate) fn load_space(
+    confluence_client: &ConfluenceClient,
+    markdown_space: &mut MarkdownSpace,
+    args: &Args,
+    users: Arc<CachedUsers>,
+) -> Result<LoadedSpace> {
     let space_key = markdown_space.key.clone();
-    let space_dir = markdown_space.dir.clone();
+    parse_space(
+        markdown_space,
+        args,
+        users,
+        CachedExcerpts::new(confluence_client.clone(), args.fetch_excerpts),
+        confluence_client,
+        || ConfluenceSpace::get(confluence_client, &space_key),
+        None,
+    )
+}
 
-    let mut template_renderer = TemplateRenderer::new(markdown_space, &confluence_client)?;
-    let markdown_pages = markdown_space.parse(&mut template_renderer)?;
+/// Like [`load_space`], without sending a single request: the pages get made-up ids, and users and
+/// excerpts aren't looked up.
+pub(crate) fn load_space_offline(
+    confluence_client: &ConfluenceClient,
+    markdown_space: &mut MarkdownSpace,
+    args: &Args,
+) -> Result<LoadedSpace> {
+    load_space_offline_with_errors(confluence_client, markdown_space, args, None)
+}
 
-    let mut space = ConfluenceSpace::get(&confluence_client, &space_key)?;
+/// Like [`load_space_offline`]. With `errors`, the pages that fail to parse or register are left
+/// out and their errors added to it, instead of failing on them, and `--strict` is left to the
+/// caller.
+pub(crate) fn load_space_offline_with_errors(
+    confluence_client: &ConfluenceClient,
+    markdown_space: &mut MarkdownSpace,
+    args: &Args,
+    errors: Option<&mut Vec<anyhow::Error>>,
+) -> Result<LoadedSpace> {
+    let mut loaded_space = parse_space(
+        markdown_space,
+        args,
+        Arc::new(CachedUsers::offline(confluence_client.clone())),
+        CachedExcerpts::new(confluence_client.clone(), false),
+        confluence_client,
+        || Ok(ConfluenceSpace::offline()),
+        errors,
+    )?;
+    loaded_space.link_generator.register_offline_ids();
+    Ok(loaded_space)
+}
+
+/// Parses the markdown space, and registers its pages with a link generator for the Confluence
+/// space `get_space` returns once they parsed. Errors of pages are collected in `errors` when
+/// it's given.
+fn parse_space(
+    markdown_space: &mut MarkdownSpace,
+    args: &Args,
+    users: Arc<CachedUsers>,
+    excerpts: CachedExcerpts,
+    confluence_client: &ConfluenceClient,
+    get_space: impl FnOnce() -> Result<ConfluenceSpace>,
+    mut errors: Option<&mut Vec<anyhow::Error>>,
+) -> Result<LoadedSpace> {
+    let space_key = markdown_space.key.clone();
+    markdown_space.config.emoji_title = markdown_space
+        .config
+        .emoji_title
+        .resolve(confluence_client.is_cloud());
+    let space_config = markdown_space.config.clone();
+
+    let markdown_space = &*markdown_space;
+    let mut template_renderer = TemplateRenderer::new(markdown_space, &users, excerpts)?;
+    let parse_cache = match &args.parse_cache {
+        Some(dir) => Some(ParseCache::open(dir, markdown_space)?),
+        None => None,
+    };
+    let markdown_pages = match errors.as_deref_mut() {
+        Some(errors) => {
+            markdown_space.parse_collecting(&template_renderer, parse_cache.as_ref(), errors)?
+        }
+        None => markdown_space.parse(&template_renderer, parse_cache.as_ref())?,
+    };
+    if let Some(parse_cache) = &parse_cache {
+        let (hits, misses) = parse_cache.stats();
+        print_info(&format!(
+            "{} of {} file(s) read from the parse cache",
+            hits,
+            hits + misses
+        ));
+    }
+    let mut markdown_pages = markdown_pages;
+    if let (true, Some(title)) = (space_config.homepage.locked, &space_config.homepage.title) {
+        for markdown_page in markdown_pages.iter_mut() {
+            if markdown_page.source == "index.md" {
+                markdown_page.title = title.clone();
+            }
+        }
+    }
+    let schedule = PublishSchedule::new(&markdown_pages, Utc::now());
+    // pages that aren't registered are orphans, which archives what was published of them
+    let markdown_pages: Vec<MarkdownPage> = if space_config.archive_unpublished {
+        markdown_pages
+            .into_iter()
+            .filter(|page| schedule.is_published(&page.source))
+            .collect()
+    } else {
+        markdown_pages
+    };
+    let confluence_templates = markdown_space.parse_confluence_templates(&mut template_renderer)?;
+    let mut warnings = markdown_space.warnings.clone();
+    warnings.extend(template_renderer.warnings.iter().cloned());
+    for warning in users.take_warnings() {
+        report(warning, &space_config.disabled_warnings, &mut warnings);
+    }
+    if args.strict && errors.is_none() {
+        let warnings: usize = markdown_pages
+            .iter()
+            .chain(confluence_templates.iter())
+            .map(|page| page.warnings.len())
+            .sum::<usize>()
+            + warnings.len();
+        if warnings > 0 {
+            return Err(anyhow::anyhow!(
+                "{} warning(s) found and --strict is set",
+                warnings
+            ));
+        }
+    }
+
+    let mut space = get_space()?;
+    if let Some(homepage_id) = &space_config.homepage.id {
+        space.homepage_id = homepage_id.clone();
+    }
+    space.managed_label = space_config.managed_label.clone();
+    space.page_ids = page_ids::read(&markdown_space.dir)?;
+    space.scope = args.only.clone();
     let mut link_generator =
         LinkGenerator::new(&confluence_client.hostname, &space_key, &space.homepage_id);
+    link_generator.set_title_policy(&space_config.titles);
+    link_generator.set_link_text(space_config.link_text);
+    link_generator.set_strict(args.strict);
+    link_generator.set_disabled_warnings(&space_config.disabled_warnings);
+    link_generator.set_max_depth(space_config.max_depth);
+    link_generator.set_external_links(
+        &space_config
+            .external_links
+            .for_profile(args.profile.as_deref())?,
+    );
+    if space_config.homepage.locked {
+        print_info("Homepage is locked, index.md will be synced as a child page");
+        link_generator.lock_homepage();
+    }
 
     for markdown_page in &markdown_pages {
-        link_generator.register_markdown_page(markdown_page)?;
+        match (
+            link_generator.register_markdown_page(markdown_page),
+            &mut errors,
+        ) {
+            (Err(err), Some(errors)) => errors.push(err),
+            (result, _) => result?,
+        }
+    }
+    for path in &markdown_space.snippets {
+        link_generator.register_snippet_file(Path::new(&remove_prefix(&markdown_space.dir, path)?));
+    }
+    for source in schedule.sources() {
+        link_generator.register_scheduled_file(source);
+    }
+
+    Ok(LoadedSpace {
+        markdown_pages,
+        confluence_templates,
+        space,
+        link_generator,
+        users,
+        schedule,
+        warnings,
+    })
+}
+
+/// What a sync collected for `--report`, `--links` and `--search-index`, written once every space
+/// is synced.
+#[derive(Debug, Default)]
+pub struct SyncOutputs {
+    pub reports: Vec<SyncReport>,
+    pub search_index: Option<SearchIndex>,
+    /// Stopped with Ctrl-C before every page was synced.
+    pub cancelled: bool,
+    /// Pages that failed to render or sync, the others were synced anyway.
+    pub failed: Vec<String>,
+}
+
+impl SyncOutputs {
+    pub fn merge(outputs: Vec<SyncOutputs>) -> SyncOutputs {
+        outputs
+            .into_iter()
+            .fold(SyncOutputs::default(), |mut merged, outputs| {
+                merged.reports.extend(outputs.reports);
+                merged.cancelled |= outputs.cancelled;
+                merged.failed.extend(outputs.failed);
+                if let Some(search_index) = outputs.search_index {
+                    merged
+                        .search_index
+                        .get_or_insert_with(SearchIndex::default)
+                        .pages
+                        .extend(search_index.pages);
+                }
+                merged
+            })
+    }
+
+    /// Writes the outputs, also when the sync was cancelled or pages failed, and fails afterwards
+    /// in those cases.
+    pub fn write(&self, args: &Args) -> Result<()> {
+        if let Some(path) = &args.report {
+            write_reports_json(&self.reports, path)?;
+        }
+        if let Some(path) = &args.links {
+            write_links_manifest(&self.reports, path)?;
+        }
+        if let (Some(path), Some(search_index)) = (&args.search_index, &self.search_index) {
+            search_index.write(path)?;
+        }
+        if self.cancelled {
+            return Err(ConfluenceError::generic_error(
+                "Cancelled, the remaining pages will be synced by the next run",
+            ));
+        }
+        if !self.failed.is_empty() {
+            return Err(ConfluenceError::generic_error(format!(
+                "{} page(s) failed: {}. Run again with --retry-failed to sync only those",
+                self.failed.len(),
+                self.failed.join(", ")
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Whether `--skip` names the file, with either kind of slash and an optional `./`.
+fn is_skipped(skip: &[String], source: &str) -> bool {
+    let source = source.replace('\\', "/");
+    skip.iter()
+        .any(|path| path.replace('\\', "/").trim_start_matches("./") == source)
+}
+
+/// Whether the file is one of the files or directories `--only` names, or a section of one. An
+/// empty list is the whole space.
+pub(crate) fn is_in_scope(only: &[String], source: &str) -> bool {
+    let source = source.replace('\\', "/");
+    only.is_empty()
+        || only.iter().any(|path| {
+            let path = path.replace('\\', "/");
+            let path = path.trim_start_matches("./").trim_end_matches('/');
+            path.is_empty()
+                || path == "."
+                || source == path
+                || source.starts_with(&format!("{}/", path))
+                || source.starts_with(&format!("{}#", path))
+        })
+}
+
+/// Fails when a path of `--only` has no pages, which is most likely a typo.
+fn check_scope(only: &[String], markdown_pages: &[MarkdownPage]) -> Result<()> {
+    for path in only {
+        let path = std::slice::from_ref(path);
+        if !markdown_pages
+            .iter()
+            .any(|markdown_page| is_in_scope(path, &markdown_page.source))
+        {
+            return Err(ConfluenceError::generic_error(format!(
+                "--only {}: no pages in the space",
+                path[0]
+            )));
+        }
+    }
+    Ok(())
+}
+
+pub fn sync_space(
+    mut confluence_client: ConfluenceClient,
+    markdown_space: &mut MarkdownSpace,
+    args: Args,
+    users: Arc<CachedUsers>,
+) -> Result<SyncOutputs> {
+    let space_key = markdown_space.key.clone();
+    let space_dir = markdown_space.dir.clone();
+    let space_config = markdown_space.config.clone();
+    let provenance = space_config
+        .provenance
+        .then(|| Provenance::detect(&space_dir));
+
+    sync_stats::begin("load");
+    let LoadedSpace {
+        markdown_pages,
+        confluence_templates,
+        mut space,
+        mut link_generator,
+        users,
+        schedule,
+        ..
+    } = load_space(&confluence_client, markdown_space, &args, users)?;
+    check_scope(&args.only, &markdown_pages)?;
+    let mut outputs = SyncOutputs::default();
+    let render_profile = RenderProfile::default();
+    let (unpublished_pages, markdown_pages): (Vec<_>, Vec<_>) = markdown_pages
+        .into_iter()
+        .partition(|page| !schedule.is_published(&page.source));
+    let published_pages: Vec<&MarkdownPage> = markdown_pages
+        .iter()
+        .chain(confluence_templates.iter())
+        .collect();
+    check_for_secrets(
+        &published_pages,
+        &space_dir,
+        &space_config.secrets,
+        &args.allow_secret,
+    )?;
+    if let Some(policy) = &space_config.policy {
+        check_content_policy(&published_pages, &space_dir, policy)?;
     }
 
     if args.single_editor {
         print_info("Using single editor restrictions")
     }
 
-    if !args.check {
+    if !args.check && !args.dry_run {
+        let (scheduled_pages, markdown_pages): (Vec<_>, Vec<_>) = markdown_pages
+            .into_iter()
+            .partition(|page| schedule.publish_after(&page.source).is_some());
+        sync_stats::start_phase("prepare");
         print_info(&format!(
             "Synchronizing space {} on {}...",
             space_key, confluence_client.hostname
         ));
-        let current_user: serde_json::Value = confluence_client
-            .current_user()?
-            .error_for_status()?
-            .json()?;
+        let mut lock =
+            SyncLock::acquire(&confluence_client, &space.homepage_id, args.force_unlock)?;
+        let current_user: serde_json::Value =
+            confluence_client.current_user()?.api_result()?.json()?;
 
+        sync_space_details(
+            &confluence_client,
+            &space_key,
+            &space_dir,
+            &space_config.space,
+        )?;
         space.read_all_pages(&confluence_client)?;
-        space.link_pages(&mut link_generator);
-        space.archive_orphans(&link_generator, &space_dir, &confluence_client)?;
+        check_homepage_exists(&space, &space_key)?;
+        cancellation::check()?;
+        space.link_pages(&mut link_generator)?;
+        // the sync records the pages of this run in the page map it read with the pages
+        let mut page_map = std::mem::take(&mut space.page_map);
+        link_generator.check_locked_homepage()?;
+        space.archive_orphans(
+            &link_generator,
+            &space_dir,
+            &confluence_client,
+            &space_config.orphans,
+        )?;
         space.restore_archived_pages(&link_generator, &confluence_client)?;
-        space.create_initial_nodes(&mut link_generator, &confluence_client)?;
-        for markdown_page in markdown_pages.iter() {
-            if markdown_page.is_folder() {
-                sync_folder(markdown_page, &link_generator, &space, &confluence_client)?;
-            } else {
-                sync_page(
-                    markdown_page,
-                    &mut link_generator,
-                    &args,
-                    &space,
-                    &confluence_client,
-                    &current_user,
-                )?;
+        link_generator.register_inactive_pages(space.nodes());
+        let previous_pages = page_map.pages.clone();
+        let previous_entry = |markdown_page: &MarkdownPage| {
+            previous_pages.get(&markdown_page.source.replace('\\', "/"))
+        };
+        let affected = args
+            .changed
+            .as_ref()
+            .and_then(|changed| affected_pages(&markdown_pages, changed));
+        let is_selected = |markdown_page: &MarkdownPage| {
+            !is_skipped(&args.skip, &markdown_page.source)
+                && is_in_scope(&args.only, &markdown_page.source)
+                && affected
+                    .as_ref()
+                    .is_none_or(|affected| affected.contains(&markdown_page.source))
+                && (!args.retry_failed
+                    || previous_entry(markdown_page).is_some_and(|entry| entry.failed))
+        };
+        // the pages this sync skips aren't created either, not even blank
+        space.create_initial_nodes(&mut link_generator, &confluence_client, |file| {
+            markdown_pages
+                .iter()
+                .find(|markdown_page| markdown_page.source.replace('\\', "/") == file)
+                .is_none_or(is_selected)
+        })?;
+        plan_moves(
+            space.nodes(),
+            &desired_parents(&markdown_pages, &link_generator, &args.only),
+        )?
+        .execute(&confluence_client, &mut space)?;
+        let renames = plan_temporary_renames(
+            space.nodes(),
+            &desired_titles(&markdown_pages, &link_generator, &args.only),
+        );
+        execute_renames(&confluence_client, &mut space, &renames)?;
+        let frozen_pages = match &space_config.freeze_label {
+            Some(freeze_label) => labelled_pages(&confluence_client, &space_key, freeze_label)?,
+            None => HashSet::default(),
+        };
+        let context = PageSyncContext {
+            args: &args,
+            space: &space,
+            current_user: &current_user,
+            users: &users,
+            frozen_pages: &frozen_pages,
+            space_dir: &space_dir,
+            space_config: &space_config,
+            provenance: provenance.as_ref(),
+            render_profile: &render_profile,
+        };
+        page_map.clear();
+        for markdown_page in unpublished_pages.iter() {
+            if !markdown_page.is_folder() {
+                markdown_page.render(&link_generator)?;
+            }
+            sync_stats::record_page(PageState::Unpublished);
+            print_status(
+                Status::Skipped,
+                &format!(
+                    "[{}] \"{}\" is not published",
+                    markdown_page.source, markdown_page.title
+                ),
+            );
+            if let Some(id) = link_generator.get_file_id(Path::new(&markdown_page.source)) {
+                page_map.insert(&markdown_page.source, &id, None);
+            }
+        }
+        for markdown_page in scheduled_pages.iter() {
+            if !markdown_page.is_folder() {
+                markdown_page.render(&link_generator)?;
+            }
+            let publish_after = schedule.publish_after(&markdown_page.source).unwrap();
+            sync_stats::record_page(PageState::Scheduled);
+            print_status(
+                Status::Scheduled,
+                &format!(
+                    "[{}] \"{}\" until {}",
+                    markdown_page.source,
+                    markdown_page.title,
+                    publish_after.format("%Y-%m-%d %H:%M UTC")
+                ),
+            );
+            if let Some(id) = link_generator.get_file_id(Path::new(&markdown_page.source)) {
+                page_map.insert(&markdown_page.source, &id, None);
+            }
+        }
+        // every page has an id by now, which is all rendering needs from Confluence
+        sync_stats::start_phase("sync");
+        let mut synced = markdown_pages.len();
+        // the pages are rendered a batch at a time, so the storage format of a large space isn't
+        // all in memory at once and the first pages are synced without waiting for the last ones
+        let batch_size = render_batch_size();
+        let mut rendered_pages = Vec::default().into_iter();
+        for (index, markdown_page) in markdown_pages.iter().enumerate() {
+            if cancellation::is_cancelled() {
+                synced = index;
+                break;
             }
-            sync_sort(markdown_page, &link_generator, &mut confluence_client)?;
+            lock.refresh()?;
+            if index % batch_size == 0 {
+                let batch = &markdown_pages[index..markdown_pages.len().min(index + batch_size)];
+                rendered_pages = map(batch, |markdown_page| {
+                    (is_selected(markdown_page) && !markdown_page.is_folder())
+                        .then(|| markdown_page.render(&link_generator))
+                        .transpose()
+                })
+                .into_iter();
+            }
+            let rendered_page = rendered_pages
+                .next()
+                .expect("every page of the batch is rendered");
+            let id = link_generator.get_file_id(Path::new(&markdown_page.source));
+            if !is_selected(markdown_page) {
+                sync_stats::record_page(PageState::Skipped);
+                if is_skipped(&args.skip, &markdown_page.source) {
+                    print_status(
+                        Status::Skipped,
+                        &format!(
+                            "[{}] \"{}\" with --skip",
+                            markdown_page.source, markdown_page.title
+                        ),
+                    );
+                }
+                if let Some(id) = id {
+                    page_map.keep(&markdown_page.source, &id, previous_entry(markdown_page));
+                }
+                continue;
+            }
+            // a placeholder keeps the children and links of a page that doesn't render in place
+            let mut render_error = None;
+            let rendered_page = match rendered_page {
+                Err(err)
+                    if space_config.render_errors == RenderErrors::Placeholder && !args.strict =>
+                {
+                    let placeholder = markdown_page.render_placeholder(
+                        &link_generator,
+                        &err,
+                        space_config.edit_link.as_ref(),
+                    );
+                    render_error = Some(err.context("Published a placeholder instead"));
+                    placeholder.map(Some)
+                }
+                rendered_page => rendered_page,
+            };
+            let synced_page = rendered_page
+                .and_then(|rendered_page| match rendered_page {
+                    None => {
+                        sync_folder(markdown_page, &link_generator, &space, &confluence_client)?;
+                        Ok(None)
+                    }
+                    Some(rendered_page) => sync_page(
+                        markdown_page,
+                        rendered_page,
+                        &mut link_generator,
+                        &confluence_client,
+                        &context,
+                        previous_entry(markdown_page),
+                    ),
+                })
+                .and_then(|checksum| {
+                    sync_sort(markdown_page, &link_generator, &mut confluence_client)?;
+                    Ok(checksum)
+                });
+            let synced_page = match render_error {
+                Some(err) => synced_page.and(Err(err)),
+                None => synced_page,
+            };
+            // a broken page doesn't stop the others, it's recorded to be retried
+            let id = id.or_else(|| link_generator.get_file_id(Path::new(&markdown_page.source)));
+            match synced_page {
+                Err(err) => {
+                    print_error(&format!("[{}] {:#}", markdown_page.source, err));
+                    sync_stats::record_page(PageState::Failed);
+                    if let Some(id) = id {
+                        page_map.mark_failed(
+                            &markdown_page.source,
+                            &id,
+                            previous_entry(markdown_page),
+                        );
+                    }
+                    outputs.failed.push(markdown_page.source.clone());
+                }
+                checksum => {
+                    if let Some(id) = id {
+                        page_map.insert(&markdown_page.source, &id, checksum?);
+                    }
+                }
+            }
+        }
+        let (markdown_pages, remaining_pages) = markdown_pages.split_at(synced);
+        for markdown_page in remaining_pages {
+            // without a checksum, so they're synced by the next run
+            if let Some(id) = link_generator.get_file_id(Path::new(&markdown_page.source)) {
+                let failed = previous_entry(markdown_page).filter(|entry| entry.failed);
+                page_map.keep(&markdown_page.source, &id, failed);
+            }
+        }
+        sync_stats::start_phase("finish");
+        page_map.save(&confluence_client, &space.homepage_id)?;
+        print_rewritten_links(&link_generator.rewritten_links());
+        if !remaining_pages.is_empty() {
+            print_remaining(markdown_pages.len(), remaining_pages);
+            outputs.cancelled = true;
+        } else {
+            // also without templates, to delete the ones whose files are gone
+            sync_confluence_templates(
+                &confluence_client,
+                &space_key,
+                &confluence_templates,
+                &link_generator,
+            )?;
+        }
+
+        if args.report.is_some() || args.links.is_some() {
+            outputs.reports.push(SyncReport::collect(
+                &space_key,
+                markdown_pages,
+                &link_generator,
+                &confluence_client,
+            ));
+        }
+        if args.search_index.is_some() {
+            outputs.search_index = Some(SearchIndex::collect(markdown_pages, &link_generator)?);
+        }
+        let stats = sync_stats::take();
+        for line in stats.render() {
+            print_info(&line);
+        }
+        for report in outputs.reports.iter_mut() {
+            report.stats = Some(stats.clone());
         }
     } else {
         print_info(&format!(
@@ -152,28 +783,152 @@ pub fn sync_space<'a>(
             space_key, confluence_client.hostname
         ));
         space.read_all_pages(&confluence_client)?;
-        space.link_pages(&mut link_generator);
-        for markdown_page in markdown_pages.iter() {
-            let rendered_page = markdown_page.render(&link_generator)?;
+        check_homepage_exists(&space, &space_key)?;
+        space.link_pages(&mut link_generator)?;
+        link_generator.check_locked_homepage()?;
+        link_generator.register_inactive_pages(space.nodes());
+        plan_moves(
+            space.nodes(),
+            &desired_parents(&markdown_pages, &link_generator, &args.only),
+        )?
+        .print(&space.homepage_id);
+        let rendered_pages = map(&markdown_pages, |markdown_page| {
+            is_in_scope(&args.only, &markdown_page.source)
+                .then(|| markdown_page.render(&link_generator))
+                .transpose()
+        });
+        let mut dry_run_pages = Vec::default();
+        for (markdown_page, rendered_page) in markdown_pages.iter().zip(rendered_pages) {
+            let Some(rendered_page) = rendered_page? else {
+                continue;
+            };
+            render_profile.record(&rendered_page);
+            space_config.limits.check(markdown_page, &rendered_page)?;
             if let Some(ref d) = args.output {
                 output_content(d, &rendered_page)?;
             }
+            if args.dry_run {
+                dry_run_pages.push(rendered_page);
+            }
+        }
+        for rendered_page in map(&unpublished_pages, |markdown_page| {
+            markdown_page.render(&link_generator)
+        }) {
+            rendered_page?;
+        }
+        for rendered_page in map(&confluence_templates, |template| {
+            template.render(&link_generator)
+        }) {
+            rendered_page?;
+        }
+        print_rewritten_links(&link_generator.rewritten_links());
+        if args.search_index.is_some() {
+            outputs.search_index = Some(SearchIndex::collect(&markdown_pages, &link_generator)?);
+        }
+        if args.dry_run {
+            print_dry_run(
+                &dry_run_pages,
+                &markdown_pages,
+                &collect_status(&markdown_pages, &link_generator, &space, &schedule)?,
+                &link_generator,
+                &confluence_client,
+                args.dry_run_html.as_deref(),
+            )?;
         }
         print_info("Check complete");
     }
+    if args.profile_render {
+        render_profile.print();
+    }
+
+    Ok(outputs)
+}
+
+/// The title each existing page should have, by page id. Pages outside of `--only` keep theirs.
+fn desired_titles(
+    markdown_pages: &[MarkdownPage],
+    link_generator: &LinkGenerator,
+    only: &[String],
+) -> BTreeMap<String, String> {
+    markdown_pages
+        .iter()
+        .filter(|markdown_page| !markdown_page.is_folder())
+        .filter(|markdown_page| is_in_scope(only, &markdown_page.source))
+        .filter_map(|markdown_page| {
+            Some((
+                link_generator.get_file_id(&PathBuf::from(&markdown_page.source))?,
+                markdown_page.title.clone(),
+            ))
+        })
+        .collect()
+}
+
+/// The parent each existing page should have, by page id. Pages outside of `--only` stay where
+/// they are.
+fn desired_parents(
+    markdown_pages: &[MarkdownPage],
+    link_generator: &LinkGenerator,
+    only: &[String],
+) -> BTreeMap<String, String> {
+    markdown_pages
+        .iter()
+        .filter(|markdown_page| is_in_scope(only, &markdown_page.source))
+        .filter_map(|markdown_page| {
+            let source = PathBuf::from(&markdown_page.source);
+            Some((
+                link_generator.get_file_id(&source)?,
+                link_generator.get_parent_id(&source)?,
+            ))
+        })
+        .collect()
+}
 
+pub(crate) fn check_homepage_exists(space: &ConfluenceSpace, space_key: &str) -> Result<()> {
+    if space.get_existing_node(&space.homepage_id).is_none() {
+        return Err(ConfluenceError::generic_error(format!(
+            "Homepage {} is not a page in space {}",
+            space.homepage_id, space_key
+        )));
+    }
     Ok(())
 }
 
+/// Everything needed to sync a single page that stays the same for the whole space.
+struct PageSyncContext<'a> {
+    args: &'a Args,
+    space: &'a ConfluenceSpace,
+    current_user: &'a serde_json::Value,
+    users: &'a CachedUsers,
+    /// The ids of the pages with the `freeze_label`, searched for once.
+    frozen_pages: &'a HashSet<String>,
+    space_dir: &'a Path,
+    space_config: &'a SpaceConfig,
+    /// Only when `provenance` is enabled.
+    provenance: Option<&'a Provenance>,
+    render_profile: &'a RenderProfile,
+}
+
 fn sync_page(
     markdown_page: &MarkdownPage,
+    rendered_page: RenderedPage,
     link_generator: &mut LinkGenerator,
-    args: &Args,
-    space: &ConfluenceSpace,
     confluence_client: &ConfluenceClient,
-    current_user: &tera::Value,
-) -> Result<()> {
-    let rendered_page = markdown_page.render(link_generator)?;
+    context: &PageSyncContext,
+    previous: Option<&PageMapEntry>,
+) -> Result<Option<String>> {
+    let PageSyncContext {
+        args,
+        space,
+        current_user,
+        users,
+        frozen_pages,
+        space_dir,
+        space_config,
+        provenance,
+        render_profile,
+    } = context;
+    render_profile.record(&rendered_page);
+    space_config.limits.check(markdown_page, &rendered_page)?;
     if let Some(ref d) = args.output {
         output_content(d, &rendered_page)?;
     }
@@ -186,32 +941,117 @@ fn sync_page(
     if existing_page.page_data().is_none() {
         return Err(anyhow::anyhow!("{} is not a page and cannot be converted (at this time). You'll need to delete it manually before marked-space can create it as a page", existing_page.title));
     }
-    sync_page_content(confluence_client, space, rendered_page, &existing_page)?;
+    if let Some(freeze_label) = &space_config.freeze_label {
+        if frozen_pages.contains(&existing_page.id) {
+            print_warning(&format!(
+                "[{}] \"{}\" has the {} label, not updating it",
+                rendered_page.source, existing_page.title, freeze_label
+            ));
+            sync_stats::record_page(PageState::Frozen);
+            return Ok(None);
+        }
+    }
+    if !is_temporary_rename(confluence_client, &existing_page)? {
+        check_conflict(
+            args.on_conflict,
+            link_generator.warnings(),
+            existing_page.page_data().unwrap(),
+            previous,
+            &rendered_page.source,
+            &existing_page.title,
+        )?;
+    }
+    let checksum = rendered_page.checksum.clone();
+    let page_content = sync_page_content(
+        confluence_client,
+        space,
+        rendered_page,
+        &existing_page,
+        args.compare_remote,
+    )?;
+    let content_changed = page_content
+        == PageContent::Synced {
+            content_changed: true,
+        };
+    sync_stats::record_synced_page(&existing_page.id, content_changed);
+    if content_changed && space_config.change_comments {
+        // the page is synced, a comment that fails doesn't make it fail
+        if let Err(err) = post_change_comment(
+            confluence_client,
+            &existing_page.id,
+            space_dir,
+            markdown_page.source_file(),
+        ) {
+            print_warning(&format!(
+                "[{}] failed to post the change comment: {:#}",
+                markdown_page.source, err
+            ));
+        }
+    }
     sync_page_attachments(
         confluence_client,
         &existing_page.id,
         &markdown_page.source,
         &markdown_page.attachments,
         link_generator,
+        markdown_page.checksum,
     )?;
-    sync_page_labels(
-        confluence_client,
-        &existing_page.id,
-        &markdown_page.front_matter.labels,
-    )?;
+    let labels: Vec<String> = markdown_page
+        .front_matter
+        .labels
+        .iter()
+        .chain(&space_config.managed_label)
+        .cloned()
+        .collect();
+    sync_page_labels(confluence_client, &existing_page.id, &labels)?;
     sync_page_properties(
         confluence_client,
         markdown_page,
         &existing_page.id,
         link_generator,
+        provenance
+            .map(|provenance| provenance.for_page(&markdown_page.source, &checksum))
+            .as_ref(),
     )?;
-    let restrictions_type = if args.single_editor {
-        RestrictionType::SingleEditor(current_user)
-    } else {
-        RestrictionType::OpenSpace
+    sync_page_watchers(
+        confluence_client,
+        users,
+        &existing_page.id,
+        &markdown_page.front_matter,
+    )?;
+    let restrictions_type = match (space_config.edit_lock, args.single_editor) {
+        (Some(true), _) => RestrictionType::EditLock(current_user),
+        (_, true) => RestrictionType::SingleEditor(
+            current_user,
+            resolve_account_ids(users, &markdown_page.front_matter.owners)?,
+        ),
+        (Some(false), false) => RestrictionType::Unlock(current_user),
+        (None, false) => RestrictionType::OpenSpace,
     };
+    for warning in users.take_warnings() {
+        link_generator.warnings().report(warning)?;
+    }
     sync_restrictions(restrictions_type, confluence_client, &existing_page)?;
-    Ok(())
+    Ok(match page_content {
+        PageContent::Synced { .. } => Some(checksum),
+        PageContent::Adopted => None,
+    })
+}
+
+/// Whether the page has `label` in Confluence, e.g. the freeze label an admin added.
+/// The ids of the pages of the space with a label.
+fn labelled_pages(
+    confluence_client: &ConfluenceClient,
+    space_key: &str,
+    label: &str,
+) -> Result<HashSet<String>> {
+    let response = confluence_client
+        .get_labelled_pages(space_key, label)?
+        .api_result()?;
+    ConfluencePaginator::<responses::Content>::new(confluence_client)
+        .start(response)?
+        .map(|page| page.map(|page| page.id))
+        .collect()
 }
 
 fn sync_page_labels(
@@ -231,12 +1071,12 @@ fn sync_page_labels(
     let result = if !labels.is_empty() {
         confluence_client
             .set_page_labels(page_id, body)?
-            .error_for_status()?
+            .api_result()?
             .json::<MultiEntityResult<responses::Label>>()?
     } else {
         confluence_client
             .get_page_labels(page_id)?
-            .error_for_status()?
+            .api_result()?
             .json::<MultiEntityResult<responses::Label>>()?
     };
 
@@ -247,7 +1087,7 @@ fn sync_page_labels(
         .map(|label| {
             confluence_client
                 .remove_label(page_id, label)?
-                .error_for_status()?;
+                .api_result()?;
 
             Ok(label.name.clone())
         })
@@ -286,6 +1126,7 @@ mod tests {
     use crate::{
         confluence_page::{ConfluenceNode, ConfluenceNodeType, ConfluencePageData},
         markdown_page::MarkdownPage,
+        render_profile::PhaseTimings,
         template_renderer::TemplateRenderer,
     };
 
@@ -294,7 +1135,6 @@ mod tests {
     use super::*;
 
     use assert_fs::fixture::{FileWriteStr, PathChild};
-    use comrak::{nodes::AstNode, Arena};
     use responses::ContentStatus;
 
     type TestResult = std::result::Result<(), anyhow::Error>;
@@ -305,14 +1145,13 @@ mod tests {
         markdown_page_path: &Path,
         link_generator: &mut LinkGenerator,
     ) -> Result<RenderedPage> {
-        // The returned nodes are created in the supplied Arena, and are bound by its lifetime.
         let mut template_renderer = TemplateRenderer::default()?;
-        let arena = Arena::<AstNode>::new();
         let markdown_page = MarkdownPage::from_file(
             &markdown_space.dir,
             markdown_page_path,
-            &arena,
             &mut template_renderer,
+            &Arc::new(markdown_space.config.clone()),
+            &markdown_space.defaults,
         )?;
         link_generator.register_markdown_page(&markdown_page)?;
         link_generator.register_confluence_node(&ConfluenceNode {
@@ -432,7 +1271,12 @@ mod tests {
 
         let confluence_client = ConfluenceClient::new("host.example.com");
         let mut space = MarkdownSpace::from_directory(temp.child("test").path())?;
-        let sync_result = sync_space(confluence_client, &mut space, Args::default());
+        let sync_result = sync_space(
+            confluence_client.clone(),
+            &mut space,
+            Args::default(),
+            Arc::new(CachedUsers::new(confluence_client)),
+        );
 
         assert!(sync_result.is_err());
 
@@ -471,6 +1315,8 @@ mod tests {
             source: String::default(),
             parent: None,
             checksum: String::default(),
+            legacy_checksum: None,
+            timings: PhaseTimings::default(),
         };
 
         assert!(!page_up_to_date(
@@ -482,4 +1328,292 @@ mod tests {
 
         Ok(())
     }
+
+    fn adopted_page(server: &mut mockito::Server, remote_body: &str) -> ConfluenceNode {
+        server
+            .mock("GET", "/wiki/api/v2/pages/7")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "body-format".into(),
+                "storage".into(),
+            ))
+            .with_status(200)
+            .with_body(
+                serde_json::json!({
+                    "id": "7",
+                    "title": "Adopted",
+                    "version": {"number": 4, "message": ""},
+                    "body": {"storage": {"representation": "storage", "value": remote_body}}
+                })
+                .to_string(),
+            )
+            .create();
+        ConfluenceNode {
+            id: String::from("7"),
+            title: String::from("Adopted"),
+            parent_id: Some(String::from("999")),
+            data: ConfluenceNodeType::Page(ConfluencePageData {
+                version: Version {
+                    message: String::default(),
+                    number: 4,
+                },
+                path: None,
+                status: ContentStatus::Current,
+            }),
+        }
+    }
+
+    fn adopted_rendered_page() -> RenderedPage {
+        RenderedPage {
+            title: String::from("Adopted"),
+            content: String::from("<p>Hello <a href=\"x\" title=\"t\">there</a></p>"),
+            source: String::from("adopted.md"),
+            parent: Some(String::from("999")),
+            checksum: String::from("ABC"),
+            legacy_checksum: None,
+            timings: PhaseTimings::default(),
+        }
+    }
+
+    #[test]
+    fn it_does_not_rewrite_adopted_pages_with_the_same_body() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let node = adopted_page(
+            &mut server,
+            "<p local-id=\"1\">Hello\n<a title=\"t\" href=\"x\">there</a></p>",
+        );
+        let update = server
+            .mock("PUT", "/wiki/api/v2/pages/7")
+            .expect(0)
+            .create();
+        let space = ConfluenceSpace::new_test("1", "999", vec![node.clone()]);
+
+        assert_eq!(
+            sync_page_content(&client, &space, adopted_rendered_page(), &node, false)?,
+            PageContent::Adopted
+        );
+        update.assert();
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rewrites_adopted_pages_with_a_different_body() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let node = adopted_page(&mut server, "<p>Edited by hand</p>");
+        let update = server
+            .mock("PUT", "/wiki/api/v2/pages/7")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "version": {"number": 5, "message": "updated by markedspace: source=adopted.md; checksum=ABC"}
+            })))
+            .with_status(200)
+            .create();
+        let space = ConfluenceSpace::new_test("1", "999", vec![node.clone()]);
+
+        sync_page_content(&client, &space, adopted_rendered_page(), &node, false)?;
+        update.assert();
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_fails_when_the_body_of_an_adopted_page_cannot_be_fetched() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        server
+            .mock("GET", "/wiki/api/v2/pages/7")
+            .match_query(mockito::Matcher::Any)
+            .with_status(500)
+            .create();
+        let node = adopted_page(&mut server, "<p>Edited by hand</p>");
+        let update = server
+            .mock("PUT", "/wiki/api/v2/pages/7")
+            .expect(0)
+            .create();
+        let space = ConfluenceSpace::new_test("1", "999", vec![node.clone()]);
+
+        assert!(sync_page_content(&client, &space, adopted_rendered_page(), &node, false).is_err());
+        update.assert();
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_finds_the_pages_with_the_freeze_label() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let search = server
+            .mock("GET", "/wiki/rest/api/content/search")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "cql".into(),
+                "type = page and space = \"TEST\" and label = \"docs-frozen\"".into(),
+            ))
+            .with_status(200)
+            .with_body(
+                serde_json::json!({
+                    "results": [
+                        {"id": "7", "type": "page", "status": "current", "title": "Runbook"},
+                        {"id": "9", "type": "page", "status": "current", "title": "Release"}
+                    ],
+                    "_links": {}
+                })
+                .to_string(),
+            )
+            .expect(1)
+            .create();
+
+        assert_eq!(
+            labelled_pages(&client, "TEST", "docs-frozen")?,
+            HashSet::from([String::from("7"), String::from("9")])
+        );
+        search.assert();
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_creates_no_pages_for_files_outside_of_only() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("space/index.md").write_str("# Home")?;
+        temp.child("space/in-scope.md").write_str("# In scope")?;
+        temp.child("space/out-of-scope.md")
+            .write_str("# Out of scope")?;
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        // what the sync reads and writes besides the pages isn't what this is about
+        for method in ["GET", "POST", "PUT"] {
+            server
+                .mock(method, mockito::Matcher::Any)
+                .with_status(200)
+                .with_body(r#"{"results": []}"#)
+                .expect_at_least(0)
+                .create();
+        }
+        server
+            .mock("GET", "/wiki/api/v2/spaces")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(r#"{"results": [{"id": "1", "key": "space", "homepageId": "999"}]}"#)
+            .expect_at_least(0)
+            .create();
+        server
+            .mock("GET", "/wiki/api/v2/spaces/1/pages")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(
+                serde_json::json!({"results": [
+                    {"id": "999", "title": "Home", "status": "current", "version": {"number": 2, "message": ""}}
+                ]})
+                .to_string(),
+            )
+            .expect_at_least(0)
+            .create();
+        let in_scope = server
+            .mock("POST", "/wiki/api/v2/pages")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "title": "In scope"
+            })))
+            .with_status(200)
+            .with_body(
+                serde_json::json!({
+                    "id": "1000", "parentId": "999", "title": "In scope", "status": "current",
+                    "version": {"number": 1, "message": ""}
+                })
+                .to_string(),
+            )
+            .expect(1)
+            .create();
+        let out_of_scope = server
+            .mock("POST", "/wiki/api/v2/pages")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "title": "Out of scope"
+            })))
+            .expect(0)
+            .create();
+        let mut markdown_space = MarkdownSpace::from_directory(temp.child("space").path())?;
+        let args = Args {
+            only: vec![String::from("in-scope.md")],
+            ..Default::default()
+        };
+
+        sync_space(
+            client.clone(),
+            &mut markdown_space,
+            args,
+            Arc::new(CachedUsers::new(client)),
+        )?;
+
+        in_scope.assert();
+        out_of_scope.assert();
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_the_report_of_a_cancelled_sync() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let report = temp.child("report.json");
+        let outputs = SyncOutputs {
+            cancelled: true,
+            ..Default::default()
+        };
+        let args = Args {
+            report: Some(report.path().to_path_buf()),
+            ..Default::default()
+        };
+
+        let result = outputs.write(&args);
+
+        assert!(result.is_err());
+        assert!(report.path().exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_the_report_and_lists_the_pages_that_failed() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        let report = temp.child("report.json");
+        let outputs = SyncOutputs::merge(vec![
+            SyncOutputs {
+                failed: vec![String::from("broken.md")],
+                ..Default::default()
+            },
+            SyncOutputs::default(),
+        ]);
+        let args = Args {
+            report: Some(report.path().to_path_buf()),
+            ..Default::default()
+        };
+
+        let error = outputs.write(&args).unwrap_err().to_string();
+
+        assert!(error.contains("broken.md"), "{}", error);
+        assert!(error.contains("--retry-failed"), "{}", error);
+        assert!(report.path().exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_matches_skipped_paths() {
+        let skip = vec![String::from("./runbooks\\restore.md")];
+
+        assert!(is_skipped(&skip, "runbooks/restore.md"));
+        assert!(!is_skipped(&skip, "runbooks/backup.md"));
+    }
+
+    #[test]
+    fn it_matches_paths_in_scope() {
+        let only = vec![String::from("./docs/team-a/"), String::from("faq.md")];
+
+        assert!(is_in_scope(&only, "docs/team-a/index.md"));
+        assert!(is_in_scope(&only, "docs\\team-a\\runbooks\\restore.md"));
+        assert!(is_in_scope(&only, "faq.md#Billing"));
+        assert!(!is_in_scope(&only, "docs/team-ab/index.md"));
+        assert!(!is_in_scope(&only, "docs/index.md"));
+        assert!(is_in_scope(&[], "docs/index.md"));
+        assert!(is_in_scope(&[String::from(".")], "docs/index.md"));
+    }
 }
@@ -0,0 +1,394 @@
+//! A lock on the space for the duration of a sync, kept in a content property of the homepage, so
+//! two syncs (e.g. the CI jobs of two merges) can't interleave their updates and see each other's
+//! pages as orphans.
+//!
+//! Confluence only creates a property once and rejects an update with an outdated version number,
+//! so of two syncs taking the lock at the same time one fails. A lock is released when the sync
+//! ends, also when it fails; a sync that was killed leaves its lock behind until it expires, or
+//! until a sync is run with `--force-unlock`. A sync that takes longer than half the time a lock
+//! holds extends it, so a long sync doesn't lose its lock halfway through.
+use std::{env, process};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    console::{print_status, print_warning, Status},
+    error::{ApiResponse, ConfluenceError, Result},
+    provenance::Provenance,
+    responses::{ContentProperty, MultiEntityResult},
+};
+
+pub static SYNC_LOCK_PROP: &str = "marked-space-sync-lock";
+
+/// How long a lock holds when its sync doesn't release it.
+const LOCK_HOURS: i64 = 2;
+
+/// Who holds the lock, as stored in the property.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct LockHolder {
+    /// The CI run, or the user and machine, that syncs the space.
+    pub owner: String,
+    /// Tells the holder's own lock from a lock that was taken over.
+    pub token: String,
+    pub acquired: String,
+    pub expires: String,
+}
+
+impl LockHolder {
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        DateTime::parse_from_rfc3339(&self.expires).map_or(true, |expires| expires < now)
+    }
+}
+
+/// The CI run the sync is part of, or else the user and machine it runs on.
+fn owner() -> String {
+    Provenance::detect_with(|name| env::var(name).ok(), |_| None)
+        .run
+        .unwrap_or_else(|| {
+            format!(
+                "{} on {}",
+                env::var("API_USER").unwrap_or(String::from("unknown user")),
+                env::var("HOSTNAME")
+                    .or(env::var("COMPUTERNAME"))
+                    .unwrap_or(String::from("unknown host"))
+            )
+        })
+}
+
+fn lock_property(
+    confluence_client: &ConfluenceClient,
+    homepage_id: &str,
+) -> Result<Option<ContentProperty>> {
+    let properties: MultiEntityResult<ContentProperty> = confluence_client
+        .get_properties(homepage_id)?
+        .api_result()?
+        .json()?;
+    Ok(properties
+        .results
+        .into_iter()
+        .find(|property| property.key == SYNC_LOCK_PROP))
+}
+
+/// The lock of a running sync, released when it's dropped.
+pub struct SyncLock {
+    confluence_client: ConfluenceClient,
+    homepage_id: String,
+    token: String,
+    expires: DateTime<Utc>,
+}
+
+impl SyncLock {
+    pub fn acquire(
+        confluence_client: &ConfluenceClient,
+        homepage_id: &str,
+        force_unlock: bool,
+    ) -> Result<SyncLock> {
+        Self::acquire_as(
+            confluence_client,
+            homepage_id,
+            &owner(),
+            force_unlock,
+            Utc::now(),
+        )
+    }
+
+    fn acquire_as(
+        confluence_client: &ConfluenceClient,
+        homepage_id: &str,
+        owner: &str,
+        force_unlock: bool,
+        now: DateTime<Utc>,
+    ) -> Result<SyncLock> {
+        let holder = LockHolder {
+            owner: String::from(owner),
+            token: format!(
+                "{}-{}",
+                process::id(),
+                now.timestamp_nanos_opt().unwrap_or_default()
+            ),
+            acquired: now.to_rfc3339(),
+            expires: (now + TimeDelta::hours(LOCK_HOURS)).to_rfc3339(),
+        };
+        let response = match lock_property(confluence_client, homepage_id)? {
+            None => confluence_client
+                .create_property(homepage_id, json!({"key": SYNC_LOCK_PROP, "value": holder}))?,
+            Some(property) => {
+                let previous = serde_json::from_value::<LockHolder>(property.value.clone()).ok();
+                match &previous {
+                    Some(previous) if !force_unlock && !previous.is_expired(now) => {
+                        return Err(ConfluenceError::generic_error(format!(
+                            "The space is being synced by {} since {}, the lock expires at {}. \
+                             Pass --force-unlock if that sync isn't running anymore",
+                            previous.owner, previous.acquired, previous.expires
+                        )));
+                    }
+                    Some(previous) => print_warning(&format!(
+                        "Taking over the lock of {} since {}",
+                        previous.owner, previous.acquired
+                    )),
+                    None => (),
+                }
+                confluence_client.set_property(
+                    homepage_id,
+                    &property.id,
+                    json!({
+                        "key": SYNC_LOCK_PROP,
+                        "value": holder,
+                        "version": {
+                            "message": property.version.message,
+                            "number": property.version.number + 1,
+                        }
+                    }),
+                )?
+            }
+        };
+        if !response.status().is_success() {
+            return Err(ConfluenceError::failed_request(response)
+                .context("Another sync took the lock of the space at the same time"));
+        }
+        Ok(SyncLock {
+            confluence_client: confluence_client.clone(),
+            homepage_id: String::from(homepage_id),
+            token: holder.token,
+            expires: now + TimeDelta::hours(LOCK_HOURS),
+        })
+    }
+
+    /// Extends the lock once half of the time it holds has passed, failing when another sync took
+    /// it over in the meantime.
+    pub fn refresh(&mut self) -> Result<()> {
+        self.refresh_at(Utc::now())
+    }
+
+    fn refresh_at(&mut self, now: DateTime<Utc>) -> Result<()> {
+        if self.expires - now > TimeDelta::hours(LOCK_HOURS) / 2 {
+            return Ok(());
+        }
+        let property = lock_property(&self.confluence_client, &self.homepage_id)?;
+        let holder = property
+            .as_ref()
+            .and_then(|property| serde_json::from_value::<LockHolder>(property.value.clone()).ok());
+        let (Some(property), Some(holder)) = (property, holder) else {
+            return Err(ConfluenceError::generic_error(
+                "The lock of the space was removed during the sync",
+            ));
+        };
+        if holder.token != self.token {
+            return Err(ConfluenceError::generic_error(format!(
+                "The lock of the space was taken over by {} during the sync",
+                holder.owner
+            )));
+        }
+        let expires = now + TimeDelta::hours(LOCK_HOURS);
+        let response = self.confluence_client.set_property(
+            &self.homepage_id,
+            &property.id,
+            json!({
+                "key": SYNC_LOCK_PROP,
+                "value": LockHolder {
+                    expires: expires.to_rfc3339(),
+                    ..holder
+                },
+                "version": {
+                    "message": property.version.message,
+                    "number": property.version.number + 1,
+                }
+            }),
+        )?;
+        if !response.status().is_success() {
+            return Err(ConfluenceError::failed_request(response)
+                .context("Failed to extend the lock of the space"));
+        }
+        self.expires = expires;
+        Ok(())
+    }
+
+    fn release(&self) -> Result<()> {
+        let Some(property) = lock_property(&self.confluence_client, &self.homepage_id)? else {
+            return Ok(());
+        };
+        match serde_json::from_value::<LockHolder>(property.value.clone()) {
+            Ok(holder) if holder.token == self.token => {
+                let response = self
+                    .confluence_client
+                    .delete_property(&self.homepage_id, &property.id)?;
+                if !response.status().is_success() {
+                    return Err(ConfluenceError::failed_request(response));
+                }
+                print_status(Status::Deleted, &format!("property {}", SYNC_LOCK_PROP));
+            }
+            Ok(holder) => print_warning(&format!(
+                "The lock of the space was taken over by {} during the sync",
+                holder.owner
+            )),
+            Err(_) => (),
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SyncLock {
+    fn drop(&mut self) {
+        if let Err(err) = self.release() {
+            print_warning(&format!(
+                "Couldn't release the lock of the space, it expires within {} hours: {:#}",
+                LOCK_HOURS, err
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Matcher;
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    fn properties_with_lock(owner: &str, token: &str, expires: &str) -> String {
+        json!({"results": [
+            {"id": "7", "key": SYNC_LOCK_PROP, "value": {
+                "owner": owner,
+                "token": token,
+                "acquired": "2026-10-15T08:00:00+00:00",
+                "expires": expires,
+            }, "version": {"number": 2, "message": ""}}
+        ]})
+        .to_string()
+    }
+
+    #[test]
+    fn it_takes_and_releases_the_lock() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let now = DateTime::parse_from_rfc3339("2026-10-15T09:00:00Z")?.to_utc();
+        let unlocked = server
+            .mock("GET", "/wiki/api/v2/pages/999/properties")
+            .with_status(200)
+            .with_body(json!({"results": []}).to_string())
+            .create();
+        let create = server
+            .mock("POST", "/wiki/api/v2/pages/999/properties")
+            .match_body(Matcher::PartialJson(json!({
+                "key": SYNC_LOCK_PROP,
+                "value": {"owner": "ci", "expires": "2026-10-15T11:00:00+00:00"}
+            })))
+            .with_status(200)
+            .create();
+
+        let lock = SyncLock::acquire_as(&client, "999", "ci", false, now)?;
+        create.assert();
+        unlocked.remove();
+
+        server
+            .mock("GET", "/wiki/api/v2/pages/999/properties")
+            .with_status(200)
+            .with_body(properties_with_lock(
+                "ci",
+                &lock.token,
+                "2026-10-15T11:00:00+00:00",
+            ))
+            .create();
+        let delete = server
+            .mock("DELETE", "/wiki/api/v2/pages/999/properties/7")
+            .with_status(204)
+            .create();
+        drop(lock);
+        delete.assert();
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_refuses_a_lock_that_is_held() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let now = DateTime::parse_from_rfc3339("2026-10-15T09:00:00Z")?.to_utc();
+        server
+            .mock("GET", "/wiki/api/v2/pages/999/properties")
+            .with_status(200)
+            .with_body(properties_with_lock(
+                "https://ci/run/1",
+                "1-1",
+                "2026-10-15T10:00:00+00:00",
+            ))
+            .create();
+        let take_over = server
+            .mock("PUT", "/wiki/api/v2/pages/999/properties/7")
+            .match_body(Matcher::PartialJson(json!({
+                "value": {"owner": "https://ci/run/2"},
+                "version": {"number": 3}
+            })))
+            .with_status(200)
+            .create();
+
+        let err = SyncLock::acquire_as(&client, "999", "https://ci/run/2", false, now)
+            .err()
+            .unwrap();
+        assert!(err
+            .to_string()
+            .starts_with("The space is being synced by https://ci/run/1 since"));
+        assert!(!take_over.matched());
+
+        // forced, or once the lock expired, it's taken over
+        SyncLock::acquire_as(&client, "999", "https://ci/run/2", true, now)?;
+        let later = now + TimeDelta::hours(2);
+        SyncLock::acquire_as(&client, "999", "https://ci/run/2", false, later)?;
+        take_over.expect(2).assert();
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_extends_the_lock_during_a_long_sync() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let now = DateTime::parse_from_rfc3339("2026-10-15T09:00:00Z")?.to_utc();
+        let mut lock = SyncLock {
+            confluence_client: client,
+            homepage_id: String::from("999"),
+            token: String::from("1-1"),
+            expires: now + TimeDelta::hours(LOCK_HOURS),
+        };
+        let read = server
+            .mock("GET", "/wiki/api/v2/pages/999/properties")
+            .with_status(200)
+            .with_body(properties_with_lock(
+                "ci",
+                "1-1",
+                "2026-10-15T11:00:00+00:00",
+            ))
+            .expect(1)
+            .create();
+        let extend = server
+            .mock("PUT", "/wiki/api/v2/pages/999/properties/7")
+            .match_body(Matcher::PartialJson(json!({
+                "value": {"owner": "ci", "token": "1-1", "expires": "2026-10-15T12:00:00+00:00"},
+                "version": {"number": 3}
+            })))
+            .with_status(200)
+            .expect(1)
+            .create();
+
+        // not before half of the time has passed
+        lock.refresh_at(now + TimeDelta::minutes(30))?;
+        lock.refresh_at(now + TimeDelta::hours(1))?;
+        read.assert();
+        extend.assert();
+        assert_eq!(lock.expires, now + TimeDelta::hours(3));
+
+        // the lock of another sync isn't extended
+        lock.token = String::from("2-2");
+        let err = lock.refresh_at(now + TimeDelta::hours(2)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The lock of the space was taken over by ci during the sync"
+        );
+
+        Ok(())
+    }
+}
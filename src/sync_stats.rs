@@ -0,0 +1,247 @@
+//! The summary at the end of a sync: what happened to the pages and attachments, how much went to
+//! and came from Confluence and how long each phase took. Counted per thread like the console
+//! prefix, so spaces that sync at the same time each get their own.
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    time::Instant,
+};
+
+use serde::Serialize;
+
+use crate::{console::Status, page_limits::format_size};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageState {
+    Created,
+    Updated,
+    Unchanged,
+    /// Left alone because of the freeze label.
+    Frozen,
+    Scheduled,
+    Unpublished,
+    /// Left alone with `--skip`, or because `--retry-failed` only syncs failed pages.
+    Skipped,
+    Failed,
+}
+
+impl PageState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PageState::Created => "created",
+            PageState::Updated => "updated",
+            PageState::Unchanged => "unchanged",
+            PageState::Frozen => "frozen",
+            PageState::Scheduled => "scheduled",
+            PageState::Unpublished => "unpublished",
+            PageState::Skipped => "skipped",
+            PageState::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Phase {
+    pub name: String,
+    pub millis: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct SyncStats {
+    /// Pages and folders by what happened to them.
+    pub pages: BTreeMap<PageState, usize>,
+    pub attachments_uploaded: usize,
+    pub attachments_skipped: usize,
+    pub attachments_deleted: usize,
+    /// Request bodies and uploaded attachments.
+    pub bytes_sent: u64,
+    /// Response bodies whose length is known up front.
+    pub bytes_received: u64,
+    pub api_calls: usize,
+    pub warnings: usize,
+    /// In the order they ran.
+    pub phases: Vec<Phase>,
+    /// Pages created by this run, which are updated right after but count as created.
+    #[serde(skip)]
+    created: BTreeSet<String>,
+    #[serde(skip)]
+    current_phase: Option<(String, Instant)>,
+}
+
+thread_local! {
+    static STATS: RefCell<SyncStats> = RefCell::new(SyncStats::default());
+}
+
+fn update(f: impl FnOnce(&mut SyncStats)) {
+    STATS.with(|stats| f(&mut stats.borrow_mut()));
+}
+
+/// Starts counting for a sync, with `name` as the first phase.
+pub fn begin(name: &str) {
+    update(|stats| *stats = SyncStats::default());
+    start_phase(name);
+}
+
+/// Ends the current phase and starts the next one.
+pub fn start_phase(name: &str) {
+    update(|stats| {
+        stats.end_phase();
+        stats.current_phase = Some((String::from(name), Instant::now()));
+    });
+}
+
+/// The counts so far, starting over for the thread.
+pub fn take() -> SyncStats {
+    STATS.with(|stats| {
+        let mut stats = stats.take();
+        stats.end_phase();
+        stats
+    })
+}
+
+/// Adds the counts of another thread, like the ones that render pages.
+pub fn merge(other: SyncStats) {
+    update(|stats| {
+        for (state, count) in other.pages {
+            *stats.pages.entry(state).or_default() += count;
+        }
+        stats.attachments_uploaded += other.attachments_uploaded;
+        stats.attachments_skipped += other.attachments_skipped;
+        stats.attachments_deleted += other.attachments_deleted;
+        stats.bytes_sent += other.bytes_sent;
+        stats.bytes_received += other.bytes_received;
+        stats.api_calls += other.api_calls;
+        stats.warnings += other.warnings;
+    });
+}
+
+pub fn record_page(state: PageState) {
+    update(|stats| *stats.pages.entry(state).or_default() += 1);
+}
+
+pub fn record_created_page(id: &str) {
+    update(|stats| {
+        stats.created.insert(String::from(id));
+        *stats.pages.entry(PageState::Created).or_default() += 1;
+    });
+}
+
+/// A page whose content was synced, unless it was created by this run.
+pub fn record_synced_page(id: &str, changed: bool) {
+    update(|stats| {
+        if !stats.created.contains(id) {
+            let state = match changed {
+                true => PageState::Updated,
+                false => PageState::Unchanged,
+            };
+            *stats.pages.entry(state).or_default() += 1;
+        }
+    });
+}
+
+pub fn record_attachment(status: &Status, bytes: usize) {
+    update(|stats| {
+        match status {
+            Status::Skipped => stats.attachments_skipped += 1,
+            Status::Deleted => stats.attachments_deleted += 1,
+            _ => stats.attachments_uploaded += 1,
+        }
+        stats.bytes_sent += bytes as u64;
+    });
+}
+
+/// A request to Confluence with a body of `bytes`.
+pub fn record_request(bytes: usize) {
+    update(|stats| {
+        stats.api_calls += 1;
+        stats.bytes_sent += bytes as u64;
+    });
+}
+
+/// A response from Confluence with a body of `bytes`, once decompressed.
+pub fn record_response(bytes: usize) {
+    update(|stats| stats.bytes_received += bytes as u64);
+}
+
+pub fn record_warning() {
+    update(|stats| stats.warnings += 1);
+}
+
+impl SyncStats {
+    fn end_phase(&mut self) {
+        if let Some((name, started)) = self.current_phase.take() {
+            self.phases.push(Phase {
+                name,
+                millis: started.elapsed().as_millis() as u64,
+            });
+        }
+    }
+
+    pub fn render(&self) -> Vec<String> {
+        let pages: Vec<String> = self
+            .pages
+            .iter()
+            .map(|(state, count)| format!("{} {}", count, state.as_str()))
+            .collect();
+        let phases: Vec<String> = self
+            .phases
+            .iter()
+            .map(|phase| format!("{} {:.1}s", phase.name, phase.millis as f64 / 1000.0))
+            .collect();
+        vec![
+            format!(
+                "Pages: {}",
+                match pages.is_empty() {
+                    true => String::from("none"),
+                    false => pages.join(", "),
+                }
+            ),
+            format!(
+                "Attachments: {} uploaded, {} skipped, {} deleted",
+                self.attachments_uploaded, self.attachments_skipped, self.attachments_deleted
+            ),
+            format!(
+                "{} API calls, {} sent, {} received, {} warnings",
+                self.api_calls,
+                format_size(self.bytes_sent),
+                format_size(self.bytes_received),
+                self.warnings
+            ),
+            format!("Phases: {}", phases.join(", ")),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_counts_what_the_sync_did() {
+        begin("load");
+        record_created_page("1");
+        record_synced_page("1", true);
+        record_synced_page("2", true);
+        record_synced_page("3", false);
+        record_page(PageState::Scheduled);
+        record_attachment(&Status::Updated, 2048);
+        record_attachment(&Status::Skipped, 0);
+        record_request(100);
+        record_response(1000);
+        record_warning();
+        let mut stats = take();
+        stats.phases[0].millis = 1300;
+
+        assert_eq!(
+            stats.render(),
+            vec![
+                "Pages: 1 created, 1 updated, 1 unchanged, 1 scheduled",
+                "Attachments: 1 uploaded, 1 skipped, 0 deleted",
+                "1 API calls, 2.1KB sent, 1000 bytes received, 1 warnings",
+                "Phases: load 1.3s",
+            ]
+        );
+        assert_eq!(take().pages, BTreeMap::default());
+    }
+}
@@ -0,0 +1,232 @@
+//! The `team(file=...)` shortcode, which renders a YAML file with the members of a team as a
+//! table with their profile pictures, so team pages don't have to be maintained by hand.
+//!
+//! ```yaml
+//! members:
+//!   - name: Jane Doe
+//!     role: Engineering Manager
+//!     user: jane.doe@example.com
+//!   - name: John Doe
+//!     role: Engineer
+//!     user: 557058:048d7c01-8b68-440d-964f-07ce58d92aeb
+//!     avatar: https://example.com/john.png
+//! ```
+//!
+//! `user` is an account id or a name to look up (the `name` when it's left out), people that
+//! can't be found are listed without a mention. `avatar` replaces the Confluence profile picture.
+//! The file is relative to the space directory.
+use std::{collections::HashMap, fs, path::PathBuf, sync::Arc};
+
+use anyhow::{anyhow, Context};
+use saphyr::Yaml;
+use tera::escape_html;
+
+use crate::{error::Result, mentions::CachedUsers, watchers::resolve_account_ids};
+
+#[derive(Debug, PartialEq, Eq)]
+struct Member {
+    name: String,
+    role: Option<String>,
+    user: String,
+    avatar: Option<String>,
+}
+
+fn parse_members(content: &str) -> Result<Vec<Member>> {
+    let docs = Yaml::load_from_str(content).context("Failed to parse team as YAML")?;
+    let members = docs
+        .first()
+        .and_then(|yaml| yaml["members"].as_vec())
+        .ok_or(anyhow!("Expected a \"members\" list"))?;
+    members
+        .iter()
+        .map(|member| {
+            let name = member["name"]
+                .as_str()
+                .ok_or(anyhow!("Every member needs a \"name\""))?;
+            let optional = |key: &str| member[key].as_str().map(String::from);
+            Ok(Member {
+                name: String::from(name),
+                role: optional("role"),
+                user: optional("user").unwrap_or(String::from(name)),
+                avatar: optional("avatar"),
+            })
+        })
+        .collect()
+}
+
+/// The User Profile Picture macro.
+pub fn profile_picture(account_id: &str) -> String {
+    format!(
+        "<ac:structured-macro ac:name=\"profile-picture\" ac:schema-version=\"1\"><ac:parameter ac:name=\"User\"><ri:user ri:account-id=\"{}\"/></ac:parameter></ac:structured-macro>",
+        escape_html(account_id)
+    )
+}
+
+pub struct TeamTable {
+    users: Arc<CachedUsers>,
+    space_dir: PathBuf,
+}
+
+impl TeamTable {
+    pub fn new(users: Arc<CachedUsers>, space_dir: PathBuf) -> TeamTable {
+        TeamTable { users, space_dir }
+    }
+
+    fn row(&self, member: &Member) -> Result<String> {
+        let account_id = resolve_account_ids(&self.users, std::slice::from_ref(&member.user))?
+            .into_iter()
+            .next();
+        let avatar = match (&member.avatar, &account_id) {
+            (Some(url), _) => format!(
+                "<ac:image ac:height=\"48\"><ri:url ri:value=\"{}\"/></ac:image>",
+                escape_html(url)
+            ),
+            (None, Some(account_id)) => profile_picture(account_id),
+            (None, None) => String::default(),
+        };
+        let name = match &account_id {
+            Some(account_id) => format!(
+                "<ac:link><ri:user ri:account-id=\"{}\"/></ac:link>",
+                escape_html(account_id)
+            ),
+            None => escape_html(&member.name),
+        };
+        Ok(format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            avatar,
+            name,
+            escape_html(member.role.as_deref().unwrap_or_default())
+        ))
+    }
+
+    fn table(&self, file: &str) -> Result<String> {
+        let path = self.space_dir.join(file);
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read team file {}", file))?;
+        let members = parse_members(&content).with_context(|| format!("Team file {}", file))?;
+        let rows = members
+            .iter()
+            .map(|member| self.row(member))
+            .collect::<Result<Vec<_>>>()?;
+        // on one line, as a blank line would end the HTML block in the markdown
+        Ok(format!(
+            "<table><tbody><tr><th></th><th>Name</th><th>Role</th></tr>{}</tbody></table>",
+            rows.join("")
+        ))
+    }
+}
+
+impl tera::Function for TeamTable {
+    fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+        let file = match args.get("file") {
+            Some(tera::Value::String(file)) => file,
+            _ => return Err(tera::Error::msg("Missing 'file'")),
+        };
+        self.table(file)
+            .map(tera::Value::from)
+            .map_err(|err| tera::Error::msg(format!("{:#}", err)))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// The `avatar(email=...)` shortcode, the profile picture of someone looked up by their email (or
+/// name, or account id) through the same cache as mentions.
+pub struct Avatar {
+    users: Arc<CachedUsers>,
+}
+
+impl Avatar {
+    pub fn new(users: Arc<CachedUsers>) -> Avatar {
+        Avatar { users }
+    }
+}
+
+impl tera::Function for Avatar {
+    fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+        let email = match args.get("email") {
+            Some(tera::Value::String(email)) => email,
+            _ => return Err(tera::Error::msg("Missing 'email'")),
+        };
+        let account_id = resolve_account_ids(&self.users, std::slice::from_ref(email))
+            .map_err(|err| tera::Error::msg(err.to_string()))?
+            .into_iter()
+            .next();
+        // people that can't be found are already warned about, leave their email on the page
+        Ok(tera::Value::from(match account_id {
+            Some(account_id) => profile_picture(&account_id),
+            None => escape_html(email),
+        }))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+    use tera::Function;
+
+    use crate::{confluence_client::ConfluenceClient, error::TestResult};
+
+    use super::*;
+
+    static TEAM: &str = r#"
+members:
+  - name: Jane Doe
+    role: Engineering Manager
+    user: 557058:048d7c01-8b68-440d-964f-07ce58d92aeb
+  - name: John <Doe>
+    user: 5b10ac8d82e05b22cc7d4ef5
+    avatar: https://example.com/john.png
+"#;
+
+    #[test]
+    fn it_reads_members() -> TestResult {
+        let members = parse_members(TEAM)?;
+
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[0].role.as_deref(), Some("Engineering Manager"));
+        assert_eq!(
+            members[1].avatar.as_deref(),
+            Some("https://example.com/john.png")
+        );
+        assert!(parse_members("members:\n  - role: Engineer\n").is_err());
+        assert!(parse_members("- name: Jane Doe\n").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_renders_a_table() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("teams/platform.yml").write_str(TEAM)?;
+        let team_table = TeamTable::new(
+            Arc::new(CachedUsers::new(ConfluenceClient::new("host.example.com"))),
+            temp.path().to_path_buf(),
+        );
+
+        let table = team_table
+            .call(&HashMap::from([(
+                String::from("file"),
+                tera::Value::from("teams/platform.yml"),
+            )]))?
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        assert!(table.contains(&profile_picture(
+            "557058:048d7c01-8b68-440d-964f-07ce58d92aeb"
+        )));
+        assert!(table.contains("<td>Engineering Manager</td>"));
+        assert!(table.contains("<ac:image ac:height=\"48\"><ri:url ri:value=\"https:"));
+        assert!(!table.contains("John <Doe>"));
+        assert!(!table.contains('\n'));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+//! Tera errors that point at the expression in the page that caused them.
+//!
+//! Tera reports parse errors with a line and column in the template it was given, which is the
+//! page with the import lines in front of it. Render errors (an unknown function or variable)
+//! don't have a position at all, so the first expression mentioning the name is used.
+use comrak::nodes::Sourcepos;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::diagnostics::Diagnostic;
+
+static ONE_OFF_TEMPLATE: &str = "__tera_one_off";
+
+/// The messages of the error and the errors that caused it.
+fn messages(error: &tera::Error) -> Vec<String> {
+    let mut messages = vec![error.to_string()];
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        messages.push(cause.to_string());
+        source = cause.source();
+    }
+    messages
+}
+
+/// The span of the tag (`{{ ... }}` or `{% ... %}`) around `column` (starting at 1) in `line`, as
+/// start and end columns.
+fn tag_span(line: &str, column: usize) -> (usize, usize) {
+    let chars: Vec<char> = line.chars().collect();
+    if chars.is_empty() {
+        return (1, 1);
+    }
+    let at = column.saturating_sub(1).min(chars.len() - 1);
+    let start = (0..=at)
+        .rev()
+        .find(|i| chars[*i] == '{' && matches!(chars.get(i + 1), Some('{') | Some('%')))
+        .unwrap_or(at);
+    let end = (at..chars.len() - 1)
+        .find(|i| matches!(chars[*i], '}' | '%') && chars[i + 1] == '}')
+        .map(|i| i + 1)
+        .unwrap_or(chars.len() - 1);
+    (start + 1, end + 1)
+}
+
+/// Line (starting at 1) and start and end columns of the first tag in `content` that mentions
+/// `name`.
+fn find_tag(content: &str, name: &str) -> Option<(usize, usize, usize)> {
+    static TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{\{.*?\}\}|\{%.*?%\}").unwrap());
+    let name = Regex::new(&format!(r"\b{}\b", regex::escape(name))).ok()?;
+    content.lines().enumerate().find_map(|(index, line)| {
+        let tag = TAG
+            .find_iter(line)
+            .find(|tag| name.is_match(tag.as_str()))?;
+        let start = line[..tag.start()].chars().count() + 1;
+        let end = start + tag.as_str().chars().count() - 1;
+        Some((index + 1, start, end))
+    })
+}
+
+/// A diagnostic for `error`, raised while rendering `content` (the markdown after the front
+/// matter, which starts at line `body_line + 1` of the file) with `prefix_lines` import lines in
+/// front of it.
+pub fn template_diagnostic(
+    error: &tera::Error,
+    content: &str,
+    prefix_lines: usize,
+    body_line: usize,
+) -> Diagnostic {
+    static PARSE_POSITION: Lazy<Regex> = Lazy::new(|| Regex::new(r"-->\s*(\d+):(\d+)").unwrap());
+    static EXPECTED: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^\s*=\s*(.+)$").unwrap());
+    static NAME: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"(?:Function|Filter|Test|Macro) '([^']+)'|Variable `([^`]+)`").unwrap()
+    });
+
+    let messages = messages(error);
+    let causes = &messages[1..];
+    let located = |message: String, line: usize, start: usize, end: usize| {
+        Diagnostic::at(
+            message,
+            Sourcepos::from((line, start, line, end)),
+            content,
+            body_line,
+            "in this expression",
+        )
+    };
+
+    if let Some(position) = causes
+        .iter()
+        .find_map(|cause| PARSE_POSITION.captures(cause))
+    {
+        let expected = causes
+            .iter()
+            .find_map(|cause| EXPECTED.captures(cause))
+            .map(|captures| captures[1].to_string())
+            .unwrap_or_default();
+        let message = format!("Failed to parse template: {}", expected);
+        let line: usize = position[1].parse().unwrap_or_default();
+        let column: usize = position[2].parse().unwrap_or_default();
+        return match line.checked_sub(prefix_lines).filter(|line| *line > 0) {
+            Some(line) => {
+                let text = content.lines().nth(line - 1).unwrap_or_default();
+                let (start, end) = tag_span(text, column);
+                located(message, line, start, end)
+            }
+            None => Diagnostic::new(message),
+        };
+    }
+
+    let message = if causes.is_empty() {
+        messages[0].clone()
+    } else {
+        causes.join(": ")
+    }
+    .replace(&format!(" while rendering '{}'", ONE_OFF_TEMPLATE), "");
+    let tag = NAME
+        .captures(&message)
+        .and_then(|captures| captures.get(1).or(captures.get(2)))
+        .and_then(|name| find_tag(content, name.as_str()));
+    match tag {
+        Some((line, start, end)) => located(message, line, start, end),
+        None => Diagnostic::new(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tera::{Context, Tera};
+
+    use super::*;
+
+    fn render_error(template: &str) -> tera::Error {
+        Tera::default()
+            .render_str(template, &Context::new())
+            .unwrap_err()
+    }
+
+    #[test]
+    fn it_points_at_parse_errors() {
+        let content = "# Title\n\nSee {{ foo( }} here\n";
+        let error = render_error(&format!("{{% set a = 1 %}}\n{}", content));
+
+        assert_eq!(
+            template_diagnostic(&error, content, 1, 2).render("page.md"),
+            "Failed to parse template: expected an identifier (must start with a-z)
+ --> page.md:5:5
+  |
+5 | See {{ foo( }} here
+  |     ^^^^^^^^^^ in this expression"
+        );
+    }
+
+    #[test]
+    fn it_finds_unknown_names() {
+        let content = "# Title\n\n{{ filename }} and {{ nope(a=1) }}\n";
+        let error = render_error(content);
+
+        assert_eq!(
+            template_diagnostic(&error, content, 0, 0).render("page.md"),
+            "Variable `filename` not found in context
+ --> page.md:3:1
+  |
+3 | {{ filename }} and {{ nope(a=1) }}
+  | ^^^^^^^^^^^^^^ in this expression"
+        );
+
+        let content = "{{ nope(a=1) }}";
+        let error = render_error(content);
+        assert_eq!(
+            template_diagnostic(&error, content, 0, 0).message,
+            "Function 'nope' not found"
+        );
+        assert!(template_diagnostic(&error, content, 0, 0)
+            .location
+            .is_some());
+    }
+}
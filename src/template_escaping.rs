@@ -0,0 +1,104 @@
+//! Keeping tera away from fenced code blocks.
+//!
+//! Code blocks often show other templating languages (Helm charts, Ansible, Jinja, ...) whose
+//! `{{ }}` and `{% %}` tera would try to render. Before a page is rendered, every fenced code
+//! block is wrapped in `{% raw %}`, so it's published as it is written. Code blocks inside a
+//! `{% raw %}` block of the page itself are left alone.
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+static RAW: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{%-?\s*raw\s*-?%\}").unwrap());
+static END_RAW: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{%-?\s*endraw\s*-?%\}").unwrap());
+
+/// The fence that opens a code block on this line, e.g. "```" or "~~~~".
+pub(crate) fn opening_fence(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start();
+    let fence_char = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let length = trimmed.chars().take_while(|c| *c == fence_char).count();
+    if length < 3 {
+        return None;
+    }
+    // backticks can't appear in the info string of a backtick fence
+    if fence_char == '`' && trimmed[length..].contains('`') {
+        return None;
+    }
+    Some(&trimmed[..length])
+}
+
+pub(crate) fn closes(line: &str, fence: &str) -> bool {
+    let trimmed = line.trim();
+    let fence_char = fence.chars().next().unwrap_or('`');
+    trimmed.len() >= fence.len() && trimmed.chars().all(|c| c == fence_char)
+}
+
+/// Wraps the fenced code blocks of `content` in `{% raw %}` ... `{% endraw %}`.
+pub fn escape_code_blocks(content: &str) -> String {
+    let mut escaped = String::with_capacity(content.len());
+    let mut fence: Option<&str> = None;
+    let mut in_raw = false;
+    for line in content.split_inclusive('\n') {
+        match fence {
+            Some(open) => {
+                if closes(line, open) {
+                    fence = None;
+                    let end = line.trim_end_matches(['\n', '\r']);
+                    escaped.push_str(end);
+                    escaped.push_str("{% endraw %}");
+                    escaped.push_str(&line[end.len()..]);
+                    continue;
+                }
+            }
+            None if in_raw => {
+                if END_RAW.is_match(line) {
+                    in_raw = false;
+                }
+            }
+            None => {
+                if let Some(open) = opening_fence(line) {
+                    fence = Some(open);
+                    escaped.push_str("{% raw %}");
+                } else if RAW.is_match(line) && !END_RAW.is_match(line) {
+                    in_raw = true;
+                }
+            }
+        }
+        escaped.push_str(line);
+    }
+    if fence.is_some() {
+        // an unclosed code block runs until the end of the page
+        escaped.push_str("{% endraw %}");
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_wraps_fenced_code_blocks() {
+        assert_eq!(
+            escape_code_blocks("Text {{ name }}\n\n```yaml\nimage: {{ .Values.image }}\n```\n"),
+            "Text {{ name }}\n\n{% raw %}```yaml\nimage: {{ .Values.image }}\n```{% endraw %}\n"
+        );
+        assert_eq!(
+            escape_code_blocks("~~~~\n```\n{% if %}\n~~~~"),
+            "{% raw %}~~~~\n```\n{% if %}\n~~~~{% endraw %}"
+        );
+    }
+
+    #[test]
+    fn it_leaves_raw_blocks_and_inline_code_alone() {
+        let content = "{% raw %}\n```\n{{ x }}\n```\n{% endraw %}\n``{{ y }}``\n";
+
+        assert_eq!(escape_code_blocks(content), content);
+    }
+
+    #[test]
+    fn it_closes_unterminated_code_blocks() {
+        assert_eq!(
+            escape_code_blocks("```\n{{ x }}\n"),
+            "{% raw %}```\n{{ x }}\n{% endraw %}"
+        );
+    }
+}
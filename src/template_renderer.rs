@@ -1,20 +1,100 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
 
-use anyhow::bail;
+use anyhow::{anyhow, bail};
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use saphyr::Yaml;
 use tera::{self, Tera, Value};
+use walkdir::WalkDir;
 
 use crate::builtins::add_builtins;
-use crate::confluence_client::ConfluenceClient;
-use crate::error::Result;
+use crate::error::{ConfluenceError, Result};
+use crate::excerpts::CachedExcerpts;
 use crate::frontmatter::FrontMatter;
 use crate::imports::generate_import_lines;
+use crate::macro_registry::MacroRegistry;
+use crate::markdown_page::remove_prefix;
 use crate::markdown_space::MarkdownSpace;
-use crate::mentions::CachedMentions;
+use crate::mentions::{CachedMentions, CachedUsers};
+use crate::path_variables::PathVariables;
+use crate::snippets::Snippet;
+use crate::team_pages::{Avatar, TeamTable};
+use crate::template_errors::template_diagnostic;
+use crate::template_escaping::escape_code_blocks;
+use crate::warnings::{report, Warning};
 
+#[derive(Clone)]
 pub struct TemplateRenderer {
     tera: Tera,
     space_key: String,
+    /// Page type to the `_tera` template its pages are rendered into.
+    type_templates: BTreeMap<String, String>,
+    excluded: TemplateExclusions,
+    /// Render tera in fenced code blocks too, instead of publishing them as they are.
+    render_code_blocks: bool,
+    /// Warnings about the shortcodes of the space.
+    pub warnings: Vec<Warning>,
+}
+
+/// Files that aren't rendered with tera, the `template_exclude` key of `_space.yml`: a list of
+/// globs relative to the space directory, like `guides/templating.md` or `helm/**`.
+#[derive(Debug, Clone)]
+pub struct TemplateExclusions {
+    patterns: Vec<String>,
+    globs: GlobSet,
+}
+
+impl Default for TemplateExclusions {
+    fn default() -> Self {
+        TemplateExclusions {
+            patterns: Vec::default(),
+            globs: GlobSet::empty(),
+        }
+    }
+}
+
+impl PartialEq for TemplateExclusions {
+    fn eq(&self, other: &Self) -> bool {
+        self.patterns == other.patterns
+    }
+}
+
+impl Eq for TemplateExclusions {}
+
+impl TemplateExclusions {
+    pub fn from_yaml(yaml: &Yaml) -> Result<Self> {
+        let error =
+            || anyhow!("Failed to parse \"template_exclude\" key (should be a list of file globs)");
+        let patterns = match yaml {
+            Yaml::BadValue | Yaml::Null => return Ok(TemplateExclusions::default()),
+            Yaml::Array(patterns) => patterns
+                .iter()
+                .map(|pattern| pattern.as_str().map(String::from).ok_or_else(error))
+                .collect::<Result<Vec<String>>>()?,
+            _ => return Err(error()),
+        };
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns.iter() {
+            builder.add(Glob::new(pattern).map_err(|e| {
+                anyhow!(
+                    "Failed to parse \"template_exclude\" glob \"{}\": {}",
+                    pattern,
+                    e
+                )
+            })?);
+        }
+        Ok(TemplateExclusions {
+            patterns,
+            globs: builder.build()?,
+        })
+    }
+
+    pub fn is_excluded(&self, source: &str) -> bool {
+        self.globs.is_match(source)
+    }
 }
 
 fn make_metadata_lookup(metadata: Yaml) -> impl tera::Function {
@@ -39,36 +119,129 @@ fn make_metadata_lookup(metadata: Yaml) -> impl tera::Function {
 
 // Required method
 impl TemplateRenderer {
-    pub fn new(space: &MarkdownSpace, client: &ConfluenceClient) -> Result<TemplateRenderer> {
+    pub fn new(
+        space: &MarkdownSpace,
+        users: &Arc<CachedUsers>,
+        excerpts: CachedExcerpts,
+    ) -> Result<TemplateRenderer> {
         let space_key = space.key.clone();
-        let mut tera = Tera::new(space.dir.join("**/*.md").into_os_string().to_str().unwrap())?;
+        let mut tera = Tera::default();
+        tera.add_template_files(Self::template_files(space)?)?;
+        let mut snippet_titles = BTreeMap::<String, String>::default();
+        for path in &space.snippets {
+            let snippet = Snippet::load(&space.dir, path)?;
+            if let Some(other) =
+                snippet_titles.insert(snippet.title.clone(), snippet.source.clone())
+            {
+                bail!(
+                    "Snippets {} and {} have the same title \"{}\"",
+                    other,
+                    snippet.source,
+                    snippet.title
+                );
+            }
+            tera.add_raw_templates([
+                (snippet.source.as_str(), snippet.body.as_str()),
+                (snippet.template_name().as_str(), snippet.body.as_str()),
+            ])?;
+        }
+
+        let mut registry = MacroRegistry::default();
+        add_builtins(&mut tera, &mut registry)?;
+        registry.register("mention", CachedMentions::new(users.clone()));
+        registry.register("confluence_excerpt", excerpts);
+        registry.register("team", TeamTable::new(users.clone(), space.dir.clone()));
+        registry.register("avatar", Avatar::new(users.clone()));
+        registry.register_templates(&space.config.macros)?;
+        let mut warnings = Vec::default();
+        for warning in std::mem::take(&mut registry.warnings) {
+            report(warning, &space.config.disabled_warnings, &mut warnings);
+        }
+        registry.install(&mut tera);
 
-        add_builtins(&mut tera)?;
-        tera.register_function("mention", CachedMentions::new(client.clone()));
+        let type_templates = space
+            .config
+            .types
+            .iter()
+            .filter_map(|(name, page_type)| Some((name.clone(), page_type.template.clone()?)))
+            .collect();
 
-        Ok(TemplateRenderer { tera, space_key })
+        Ok(TemplateRenderer {
+            tera,
+            space_key,
+            type_templates,
+            excluded: space.config.template_exclude.clone(),
+            render_code_blocks: space.config.template_code_blocks,
+            warnings,
+        })
+    }
+
+    /// Every markdown file in the space, which pages can import or include, except those that
+    /// opted out of templating: they may well not be valid templates.
+    fn template_files(space: &MarkdownSpace) -> Result<Vec<(PathBuf, Option<String>)>> {
+        let mut files = Vec::default();
+        for entry in WalkDir::new(&space.dir).sort_by_file_name() {
+            let entry = entry?;
+            if entry
+                .path()
+                .extension()
+                .is_none_or(|extension| extension != "md")
+            {
+                continue;
+            }
+            let name = remove_prefix(&space.dir, entry.path())?;
+            if space.config.template_exclude.is_excluded(&name) {
+                continue;
+            }
+            let (fm, _content) =
+                FrontMatter::from_reader(&mut io::BufReader::new(File::open(entry.path())?))
+                    .map_err(|e| anyhow!("{}: {:#}", name, e))?;
+            if fm.template {
+                files.push((entry.into_path(), Some(name)));
+            }
+        }
+        Ok(files)
     }
 
     #[cfg(test)]
     pub fn default() -> Result<TemplateRenderer> {
         let mut tera = Tera::default();
         let space_key = String::from("SPACE");
-        add_builtins(&mut tera)?;
+        let mut registry = MacroRegistry::default();
+        add_builtins(&mut tera, &mut registry)?;
+        registry.install(&mut tera);
 
-        Ok(TemplateRenderer { tera, space_key })
+        Ok(TemplateRenderer {
+            tera,
+            space_key,
+            type_templates: BTreeMap::default(),
+            excluded: TemplateExclusions::default(),
+            render_code_blocks: false,
+            warnings: Vec::default(),
+        })
     }
 
     #[cfg(test)]
-    pub fn default_with_client(client: &ConfluenceClient) -> Result<TemplateRenderer> {
-        use crate::mentions::CachedMentions;
-
+    pub fn default_with_client(
+        client: &crate::confluence_client::ConfluenceClient,
+    ) -> Result<TemplateRenderer> {
         let mut tera = Tera::default();
         let space_key = String::from("SPACE");
-        add_builtins(&mut tera)?;
+        let mut registry = MacroRegistry::default();
+        add_builtins(&mut tera, &mut registry)?;
+        let users = Arc::new(CachedUsers::new(client.clone()));
+        registry.register("mention", CachedMentions::new(users.clone()));
+        registry.register("avatar", Avatar::new(users));
+        registry.install(&mut tera);
 
-        tera.register_function("mention", CachedMentions::new(client.clone()));
-
-        Ok(TemplateRenderer { tera, space_key })
+        Ok(TemplateRenderer {
+            tera,
+            space_key,
+            type_templates: BTreeMap::default(),
+            excluded: TemplateExclusions::default(),
+            render_code_blocks: false,
+            warnings: Vec::default(),
+        })
     }
 
     pub fn render_template_str(
@@ -79,7 +252,11 @@ impl TemplateRenderer {
     ) -> Result<String> {
         let mut context = tera::Context::new();
         context.insert("filename", &source);
+        context.insert("path", &PathVariables::from_source(source));
         context.insert("default_space_key", &self.space_key);
+        let mut page = tera::Map::new();
+        page.insert(String::from("type"), Value::from(fm.page_type.clone()));
+        context.insert("page", &page);
         self.tera
             .register_function("metadata", make_metadata_lookup(fm.metadata.clone()));
 
@@ -96,9 +273,42 @@ impl TemplateRenderer {
             }
         }
 
-        let import_text = generate_import_lines(fm) + content;
+        let body = if fm.template && !self.excluded.is_excluded(source) {
+            let import_lines = generate_import_lines(fm);
+            let import_text = if self.render_code_blocks {
+                import_lines.clone() + content
+            } else {
+                import_lines.clone() + &escape_code_blocks(content)
+            };
+            self.tera.render_str(&import_text, &context).map_err(|e| {
+                ConfluenceError::template_error(
+                    source,
+                    template_diagnostic(&e, content, import_lines.lines().count(), fm.body_line),
+                )
+            })?
+        } else {
+            String::from(content)
+        };
+
+        let type_template = fm
+            .page_type
+            .as_ref()
+            .and_then(|page_type| Some((page_type, self.type_templates.get(page_type)?)));
+        if let Some((page_type, template)) = type_template {
+            let template_name = String::from("_tera/") + template;
+            if !self.tera.get_template_names().any(|x| *x == template_name) {
+                bail!(
+                    "Template '{}' for page type '{}' does not exist under the _tera directory",
+                    template,
+                    page_type
+                );
+            }
+            page.insert(String::from("body"), Value::from(body));
+            context.insert("page", &page);
+            return Ok(self.tera.render(&template_name, &context)?);
+        }
 
-        Ok(self.tera.render_str(&import_text, &context)?)
+        Ok(body)
     }
 
     #[cfg(test)]
@@ -113,11 +323,27 @@ impl TemplateRenderer {
 
 #[cfg(test)]
 mod test {
+    use assert_fs::fixture::{FileWriteStr, PathChild};
     use saphyr::Yaml;
 
-    use crate::{error::TestResult, frontmatter::FrontMatter};
+    use crate::{
+        confluence_client::ConfluenceClient, error::TestResult, frontmatter::FrontMatter,
+        markdown_space::MarkdownSpace,
+    };
 
-    use super::TemplateRenderer;
+    use super::{Arc, CachedExcerpts, CachedUsers, TemplateRenderer};
+
+    #[test]
+    fn it_renders_path_variables() -> TestResult {
+        let mut template_renderer = TemplateRenderer::default()?;
+        let result = template_renderer.render_template_str(
+            "runbooks/db/restore.md",
+            "{{ path.top_directory }} {{ path.directory }} {{ path.depth }} {{ path.stem }}",
+            &FrontMatter::default(),
+        )?;
+        assert_eq!(result, "runbooks db 2 restore");
+        Ok(())
+    }
 
     #[test]
     fn it_puts_original_filename_in_message() -> TestResult {
@@ -130,7 +356,11 @@ mod test {
         assert!(result.is_err());
         assert_eq!(
             format!("{:#}", result.unwrap_err()),
-            "Failed to render '__tera_one_off': Function 'func_does_not_exist' not found"
+            "Function 'func_does_not_exist' not found
+ --> test.md:1:1
+  |
+1 | {{ func_does_not_exist() }}
+  | ^^^^^^^^^^^^^^^^^^^^^^^^^^^ in this expression"
         );
         Ok(())
     }
@@ -166,4 +396,183 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn it_renders_macros_from_space_config() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("test/_space.yml").write_str(
+            "macros:\n  jira: '<ac:structured-macro ac:name=\"jira\"><ac:parameter ac:name=\"key\">{{ key }}</ac:parameter></ac:structured-macro>'\n",
+        )?;
+        temp.child("test/index.md").write_str("# Home")?;
+        let space = MarkdownSpace::from_directory(temp.child("test").path())?;
+
+        let mut template_renderer = TemplateRenderer::new(
+            &space,
+            &Arc::new(CachedUsers::new(ConfluenceClient::new("host.example.com"))),
+            CachedExcerpts::disabled(),
+        )?;
+        let result = template_renderer.render_template_str(
+            "index.md",
+            "{{ jira(key=\"ABC-1\") }}",
+            &FrontMatter::default(),
+        )?;
+
+        assert_eq!(
+            result,
+            r#"<ac:structured-macro ac:name="jira"><ac:parameter ac:name="key">ABC-1</ac:parameter></ac:structured-macro>"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_includes_snippets() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("test/index.md").write_str("# Home")?;
+        temp.child("test/shared/support.md").write_str(
+            "---\nsnippet: true\n---\n# Support Contacts\n\nAsk in {{ channel | default(value=\"#help\") }}.\n",
+        )?;
+        let space = MarkdownSpace::from_directory(temp.child("test").path())?;
+        assert_eq!(
+            space.snippets,
+            vec![temp.child("test/shared/support.md").to_path_buf()]
+        );
+
+        let mut template_renderer = TemplateRenderer::new(
+            &space,
+            &Arc::new(CachedUsers::new(ConfluenceClient::new("host.example.com"))),
+            CachedExcerpts::disabled(),
+        )?;
+        let result = template_renderer.render_template_str(
+            "index.md",
+            "{% include \"snippets/Support Contacts\" %}\n{% include \"shared/support.md\" %}",
+            &FrontMatter::default(),
+        )?;
+
+        assert_eq!(result, "Ask in #help.\n\nAsk in #help.\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_renders_typed_pages_into_their_template() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("test/_space.yml")
+            .write_str("types:\n  runbook:\n    template: runbook.md\n")?;
+        temp.child("test/_tera/runbook.md")
+            .write_str("{{ page.body }}\n## Escalation ({{ page.type }})\n")?;
+        temp.child("test/index.md").write_str("# Home")?;
+        let space = MarkdownSpace::from_directory(temp.child("test").path())?;
+
+        let mut template_renderer = TemplateRenderer::new(
+            &space,
+            &Arc::new(CachedUsers::new(ConfluenceClient::new("host.example.com"))),
+            CachedExcerpts::disabled(),
+        )?;
+        let fm = FrontMatter {
+            page_type: Some(String::from("runbook")),
+            ..Default::default()
+        };
+        let result =
+            template_renderer.render_template_str("db.md", "# Restart the database\n", &fm)?;
+
+        assert_eq!(
+            result,
+            "# Restart the database\n\n## Escalation (runbook)\n"
+        );
+
+        let result = template_renderer.render_template_str(
+            "other.md",
+            "type: {{ page.type }}",
+            &FrontMatter::default(),
+        )?;
+        assert_eq!(result, "type: ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_leaves_pages_that_opt_out_alone() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("test/_space.yml")
+            .write_str("template_exclude: [\"helm/**\"]\n")?;
+        temp.child("test/index.md").write_str("# Home")?;
+        temp.child("test/templating.md")
+            .write_str("---\ntemplate: false\n---\n# Templating\n\nUse {{ metadata(path=\n")?;
+        temp.child("test/helm/values.md")
+            .write_str("# Values\n\n{% if %}\n")?;
+        let space = MarkdownSpace::from_directory(temp.child("test").path())?;
+
+        let mut template_renderer = TemplateRenderer::new(
+            &space,
+            &Arc::new(CachedUsers::new(ConfluenceClient::new("host.example.com"))),
+            CachedExcerpts::disabled(),
+        )?;
+        let (fm, content) =
+            FrontMatter::from_str("---\ntemplate: false\n---\nUse {{ metadata(path=\n")?;
+        assert_eq!(
+            template_renderer.render_template_str("templating.md", &content, &fm)?,
+            "Use {{ metadata(path=\n"
+        );
+        assert_eq!(
+            template_renderer.render_template_str(
+                "helm/values.md",
+                "{% if %}\n",
+                &FrontMatter::default()
+            )?,
+            "{% if %}\n"
+        );
+        assert!(template_renderer
+            .render_template_str("index.md", "{% if %}\n", &FrontMatter::default())
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_publishes_code_blocks_as_they_are() -> TestResult {
+        let mut template_renderer = TemplateRenderer::default()?;
+        let result = template_renderer.render_template_str(
+            "helm.md",
+            "{{ filename }}\n\n```yaml\nimage: {{ .Values.image }}\n```\n\n{% raw %}{{ kept }}{% endraw %}\n",
+            &FrontMatter::default(),
+        )?;
+
+        assert_eq!(
+            result,
+            "helm.md\n\n```yaml\nimage: {{ .Values.image }}\n```\n\n{{ kept }}\n"
+        );
+
+        template_renderer.render_code_blocks = true;
+        let result = template_renderer.render_template_str(
+            "page.md",
+            "```\n{{ filename }}\n```\n",
+            &FrontMatter::default(),
+        )?;
+        assert_eq!(result, "```\npage.md\n```\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_points_at_the_line_in_the_file() -> TestResult {
+        let mut template_renderer = TemplateRenderer::default()?;
+        let (fm, content) = FrontMatter::from_str(
+            "---\nimports: []\n---\n# Title\n\nOwned by {{ metadata(path=\"owner\" }}\n",
+        )?;
+        let error = template_renderer
+            .render_template_str("team/page.md", &content, &fm)
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Failed to parse template: expected `or`, `and`, `not`, `<=`, `>=`, `<`, `>`, `==`, `!=`, or a filter
+ --> team/page.md:6:10
+  |
+6 | Owned by {{ metadata(path=\"owner\" }}
+  |          ^^^^^^^^^^^^^^^^^^^^^^^^^^^ in this expression"
+        );
+
+        Ok(())
+    }
 }
@@ -0,0 +1,250 @@
+//! The `test-templates` subcommand: renders every page of the space without Confluence, to catch
+//! template and parse errors in seconds, and checks the assertions in `_template_tests.yml`:
+//!
+//! ```yaml
+//! - page: guides/setup.md
+//!   title: Setup
+//!   contains:
+//!     - '<ac:structured-macro ac:name="info"'
+//!   not_contains:
+//!     - '{{'
+//! ```
+//!
+//! `contains` and `not_contains` are matched against the storage format of the rendered page.
+//! Pages get made-up ids, the homepage 0, so links to them point at made-up URLs, and mentions
+//! keep the name they were given.
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::anyhow;
+use saphyr::Yaml;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    console::{print_error, print_info, print_status, Status},
+    frontmatter::parse_string_list,
+    markdown_page::{MarkdownPage, RenderedPage},
+    markdown_space::MarkdownSpace,
+    parallel::map,
+    render_profile::RenderProfile,
+    sync::{load_space_offline, LoadedSpace},
+    Args, Result,
+};
+
+pub static TEMPLATE_TESTS_FILENAME: &str = "_template_tests.yml";
+
+/// Assertions about one rendered page.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TemplateTest {
+    /// The markdown file, relative to the space directory.
+    pub page: String,
+    pub title: Option<String>,
+    pub contains: Vec<String>,
+    pub not_contains: Vec<String>,
+}
+
+impl TemplateTest {
+    pub fn parse_all(content: &str) -> Result<Vec<TemplateTest>> {
+        let docs = Yaml::load_from_str(content)?;
+        let tests = match docs.first() {
+            None | Some(Yaml::Null) => return Ok(Vec::default()),
+            Some(Yaml::Array(tests)) => tests,
+            Some(_) => {
+                return Err(anyhow!(
+                    "{} should be a list of tests with a page key",
+                    TEMPLATE_TESTS_FILENAME
+                ))
+            }
+        };
+        tests
+            .iter()
+            .enumerate()
+            .map(|(index, yaml)| {
+                let page = yaml["page"].as_str().ok_or(anyhow!(
+                    "Test {} in {} has no page key",
+                    index + 1,
+                    TEMPLATE_TESTS_FILENAME
+                ))?;
+                let title = match &yaml["title"] {
+                    Yaml::BadValue | Yaml::Null => None,
+                    Yaml::String(title) => Some(title.clone()),
+                    _ => {
+                        return Err(anyhow!(
+                            "Failed to parse \"title\" key of the {} test (should be a string)",
+                            page
+                        ))
+                    }
+                };
+                Ok(TemplateTest {
+                    page: page.replace('\\', "/"),
+                    title,
+                    contains: parse_strings(&yaml["contains"], "contains")?,
+                    not_contains: parse_strings(&yaml["not_contains"], "not_contains")?,
+                })
+            })
+            .collect()
+    }
+
+    /// What doesn't hold for the page, empty when the test passes.
+    pub fn failures(&self, rendered_pages: &BTreeMap<String, RenderedPage>) -> Vec<String> {
+        let Some(rendered_page) = rendered_pages.get(&self.page) else {
+            return vec![String::from("no such page was rendered")];
+        };
+        let mut failures = Vec::default();
+        if let Some(title) = self
+            .title
+            .as_ref()
+            .filter(|title| **title != rendered_page.title)
+        {
+            failures.push(format!(
+                "title is \"{}\", expected \"{}\"",
+                rendered_page.title, title
+            ));
+        }
+        for expected in &self.contains {
+            if !rendered_page.content.contains(expected.as_str()) {
+                failures.push(format!("doesn't contain {:?}", expected));
+            }
+        }
+        for unexpected in &self.not_contains {
+            if rendered_page.content.contains(unexpected.as_str()) {
+                failures.push(format!("contains {:?}", unexpected));
+            }
+        }
+        failures
+    }
+}
+
+/// A single string or a list of them.
+fn parse_strings(yaml: &Yaml, key: &str) -> Result<Vec<String>> {
+    match yaml {
+        Yaml::String(value) => Ok(vec![value.clone()]),
+        _ => parse_string_list(yaml, key),
+    }
+}
+
+/// Renders every page and template, returning the rendered pages by source and the pages that
+/// failed to render with their error.
+pub(crate) fn render_all(
+    loaded_space: &LoadedSpace,
+) -> (BTreeMap<String, RenderedPage>, Vec<String>) {
+    let pages: Vec<&MarkdownPage> = loaded_space
+        .markdown_pages
+        .iter()
+        .chain(loaded_space.confluence_templates.iter())
+        .filter(|page| !page.is_folder())
+        .collect();
+    let mut rendered_pages = BTreeMap::default();
+    let mut errors = Vec::default();
+    let results = map(&pages, |markdown_page| {
+        markdown_page.render(&loaded_space.link_generator)
+    });
+    for (markdown_page, result) in pages.into_iter().zip(results) {
+        match result {
+            Ok(rendered_page) => {
+                rendered_pages.insert(markdown_page.source.replace('\\', "/"), rendered_page);
+            }
+            Err(err) => errors.push(format!("[{}] {:#}", markdown_page.source, err)),
+        }
+    }
+    (rendered_pages, errors)
+}
+
+pub fn test_templates(
+    confluence_client: ConfluenceClient,
+    markdown_space: &mut MarkdownSpace,
+    args: Args,
+) -> Result<()> {
+    let tests_path = Path::new(&markdown_space.dir).join(TEMPLATE_TESTS_FILENAME);
+    let tests = if tests_path.exists() {
+        TemplateTest::parse_all(&fs::read_to_string(&tests_path)?)?
+    } else {
+        Vec::default()
+    };
+    let loaded_space = load_space_offline(&confluence_client, markdown_space, &args)?;
+
+    let (rendered_pages, render_errors) = render_all(&loaded_space);
+    if args.profile_render {
+        let render_profile = RenderProfile::default();
+        for rendered_page in rendered_pages.values() {
+            render_profile.record(rendered_page);
+        }
+        render_profile.print();
+    }
+    for error in &render_errors {
+        print_status(Status::Error, error);
+    }
+    let mut failed_tests = 0;
+    for test in &tests {
+        let failures = test.failures(&rendered_pages);
+        if !failures.is_empty() {
+            failed_tests += 1;
+        }
+        for failure in failures {
+            print_error(&format!("[{}] {}", test.page, failure));
+        }
+    }
+    print_info(&format!(
+        "Rendered {} page(s), {} failed to render, {} of {} test(s) failed",
+        rendered_pages.len(),
+        render_errors.len(),
+        failed_tests,
+        tests.len()
+    ));
+    if render_errors.is_empty() && failed_tests == 0 {
+        Ok(())
+    } else {
+        Err(anyhow!("Template tests failed"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::fixture::{FileWriteStr, PathChild};
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn it_renders_pages_offline_and_checks_assertions() -> TestResult {
+        let temp = assert_fs::TempDir::new()?;
+        temp.child("test/index.md")
+            .write_str("# Home\n\nSee [the guide](guide.md).\n")?;
+        temp.child("test/guide.md").write_str(
+            "---\nlabels: [guide]\n---\n# Guide\n\nHello {{ mention(public_name=\"Jane Doe\") }}\n",
+        )?;
+        let tests = TemplateTest::parse_all(
+            "- page: index.md\n  title: Home\n  contains: 'href=\"https://example.atlassian.net/wiki/spaces/test/pages/1\"'\n- page: guide.md\n  contains: [Jane Doe]\n  not_contains: ['{{']\n- page: guide.md\n  title: Setup\n- page: missing.md\n",
+        )?;
+
+        let mut markdown_space = MarkdownSpace::from_directory(temp.child("test").path())?;
+        let client = ConfluenceClient::new("example.atlassian.net");
+        let loaded_space = load_space_offline(&client, &mut markdown_space, &Args::default())?;
+        let (rendered_pages, render_errors) = render_all(&loaded_space);
+
+        assert_eq!(render_errors, Vec::<String>::default());
+        assert_eq!(
+            tests
+                .iter()
+                .map(|test| test.failures(&rendered_pages))
+                .collect::<Vec<_>>(),
+            vec![
+                vec![],
+                vec![],
+                vec![String::from("title is \"Guide\", expected \"Setup\"")],
+                vec![String::from("no such page was rendered")],
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_requires_a_page_for_every_test() {
+        let error = TemplateTest::parse_all("- contains: Home\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Test 1 in _template_tests.yml has no page key"
+        );
+    }
+}
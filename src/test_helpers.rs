@@ -1,22 +1,34 @@
-use comrak::{nodes::AstNode, Arena};
-
 use crate::markdown_page::MarkdownPage;
 
 #[cfg(test)]
-pub fn markdown_page_from_str<'a>(
+pub fn markdown_page_from_str(filename: &str, content: &str) -> crate::error::Result<MarkdownPage> {
+    use std::path::PathBuf;
+
+    use crate::template_renderer::TemplateRenderer;
+
+    MarkdownPage::from_str(
+        &PathBuf::from(filename),
+        content,
+        filename.to_string(),
+        &mut TemplateRenderer::default()?,
+    )
+}
+
+#[cfg(test)]
+pub fn markdown_page_from_str_with_config(
     filename: &str,
     content: &str,
-    arena: &'a Arena<AstNode<'a>>,
-) -> crate::error::Result<MarkdownPage<'a>> {
+    space_config: &crate::space_config::SpaceConfig,
+) -> crate::error::Result<MarkdownPage> {
     use std::path::PathBuf;
 
     use crate::template_renderer::TemplateRenderer;
 
-    MarkdownPage::from_str(
+    MarkdownPage::from_str_with_config(
         &PathBuf::from(filename),
         content,
-        arena,
         filename.to_string(),
         &mut TemplateRenderer::default()?,
+        space_config,
     )
 }
@@ -0,0 +1,232 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use saphyr::Yaml;
+
+use crate::{
+    error::Result,
+    space_config::parse_bool,
+    warnings::{Warning, WarningCode},
+};
+
+/// Confluence rejects titles longer than this.
+static CONFLUENCE_MAX_TITLE_LENGTH: usize = 255;
+
+/// How page titles are cleaned up before they are compared with or sent to Confluence.
+///
+/// The same normalization is applied to local titles (from the first heading) and remote titles,
+/// so stray whitespace or typographic quotes don't stop a page from being matched.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TitlePolicy {
+    pub trim: bool,
+    pub collapse_whitespace: bool,
+    /// Replace typographic (curly) quotes with plain ASCII quotes.
+    pub straighten_quotes: bool,
+    pub max_length: usize,
+    /// Take the title from the first level 1 heading, or the file name without one, and demote
+    /// other level 1 headings instead of failing on pages that don't start with one.
+    pub lenient: bool,
+    /// Make the title of pages without a level 1 heading from their file name, in title case.
+    pub from_filename: bool,
+}
+
+impl Default for TitlePolicy {
+    fn default() -> Self {
+        TitlePolicy {
+            trim: true,
+            collapse_whitespace: true,
+            straighten_quotes: false,
+            max_length: CONFLUENCE_MAX_TITLE_LENGTH,
+            lenient: false,
+            from_filename: false,
+        }
+    }
+}
+
+impl TitlePolicy {
+    pub fn from_yaml(yaml: &Yaml) -> Result<Self> {
+        let default = TitlePolicy::default();
+        match yaml {
+            Yaml::BadValue | Yaml::Null => Ok(default),
+            Yaml::Hash(_) => {
+                let parse_or = |key: &str, default: bool| -> Result<bool> {
+                    match &yaml[key] {
+                        Yaml::BadValue | Yaml::Null => Ok(default),
+                        value => parse_bool(value, &format!("titles.{}", key)),
+                    }
+                };
+                let max_length = match &yaml["max_length"] {
+                    Yaml::BadValue | Yaml::Null => default.max_length,
+                    Yaml::Integer(n) if *n > 0 => *n as usize,
+                    _ => {
+                        return Err(anyhow!(
+                        "Failed to parse \"titles.max_length\" key (should be a positive number)"
+                    ))
+                    }
+                };
+                Ok(TitlePolicy {
+                    trim: parse_or("trim", default.trim)?,
+                    collapse_whitespace: parse_or(
+                        "collapse_whitespace",
+                        default.collapse_whitespace,
+                    )?,
+                    straighten_quotes: parse_or("straighten_quotes", default.straighten_quotes)?,
+                    max_length,
+                    lenient: parse_or("lenient", default.lenient)?,
+                    from_filename: parse_or("from_filename", default.from_filename)?,
+                })
+            }
+            _ => Err(anyhow!("Failed to parse \"titles\" key (should be a map)")),
+        }
+    }
+
+    pub fn normalize(&self, title: &str) -> String {
+        let mut normalized = String::from(title);
+        if self.straighten_quotes {
+            normalized = normalized
+                .chars()
+                .map(|c| match c {
+                    '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' => '\'',
+                    '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' => '"',
+                    c => c,
+                })
+                .collect();
+        }
+        if self.collapse_whitespace {
+            normalized = normalized
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" ");
+            if !self.trim {
+                // split_whitespace drops the ends, put a single space back
+                if title.starts_with(char::is_whitespace) {
+                    normalized.insert(0, ' ');
+                }
+                if title.ends_with(char::is_whitespace) {
+                    normalized.push(' ');
+                }
+            }
+        } else if self.trim {
+            normalized = String::from(normalized.trim());
+        }
+        if normalized.chars().count() > self.max_length {
+            normalized = normalized.chars().take(self.max_length).collect();
+            if self.trim {
+                normalized = String::from(normalized.trim_end());
+            }
+        }
+        normalized
+    }
+
+    /// The title for the file at `source` (relative to the space directory): the words of its
+    /// name, split on dashes and underscores, capitalized. `index.md` is named after its directory.
+    pub fn title_from_filename(source: &str) -> String {
+        let file = Path::new(source.split('#').next().unwrap_or(source));
+        let name = match file.file_stem().and_then(|stem| stem.to_str()) {
+            Some("index") => file
+                .parent()
+                .and_then(Path::file_name)
+                .and_then(|name| name.to_str())
+                .unwrap_or("index"),
+            Some(stem) => stem,
+            None => "",
+        };
+        name.split(['-', '_', ' '])
+            .filter(|word| !word.is_empty())
+            .map(|word| {
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(first) => first.to_uppercase().chain(chars).collect(),
+                    None => String::default(),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    pub fn warning(&self, title: &str) -> Option<Warning> {
+        let length = title.trim().chars().count();
+        if length > self.max_length {
+            Some(Warning::new(
+                WarningCode::LongTitle,
+                format!(
+                    "title is {} characters long and will be shortened to {}",
+                    length, self.max_length
+                ),
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::TestResult;
+
+    use super::*;
+
+    fn policy_from_str(s: &str) -> Result<TitlePolicy> {
+        TitlePolicy::from_yaml(&Yaml::load_from_str(s)?[0])
+    }
+
+    #[test]
+    fn it_trims_and_collapses_whitespace_by_default() {
+        let policy = TitlePolicy::default();
+
+        assert_eq!(policy.normalize("  My   Page\tTitle "), "My Page Title");
+        assert_eq!(
+            policy.normalize("\u{201C}Quoted\u{201D}"),
+            "\u{201C}Quoted\u{201D}"
+        );
+    }
+
+    #[test]
+    fn it_straightens_quotes() -> TestResult {
+        let policy = policy_from_str("straighten_quotes: true")?;
+
+        assert_eq!(
+            policy.normalize("\u{201C}Don\u{2019}t panic\u{201D}"),
+            "\"Don't panic\""
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_leave_titles_as_is() -> TestResult {
+        let policy = policy_from_str("trim: false\ncollapse_whitespace: false")?;
+
+        assert_eq!(policy.normalize(" As  is "), " As  is ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_shortens_long_titles_with_a_warning() -> TestResult {
+        let policy = policy_from_str("max_length: 10")?;
+
+        assert_eq!(policy.normalize("A very long title"), "A very lon");
+        assert_eq!(policy.normalize("Exactly 10"), "Exactly 10");
+        assert_eq!(
+            policy.warning("A very long title"),
+            Some(Warning::new(
+                WarningCode::LongTitle,
+                "title is 17 characters long and will be shortened to 10"
+            ))
+        );
+        assert_eq!(policy.warning("Short"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_errors_on_invalid_max_length() {
+        let result = policy_from_str("max_length: none");
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Failed to parse \"titles.max_length\" key (should be a positive number)"
+        );
+    }
+}
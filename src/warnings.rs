@@ -0,0 +1,303 @@
+//! Warnings about pages, each with a stable code.
+//!
+//! The codes don't change between versions, so they can be turned off for the whole space with
+//! the `warnings.disable` key of `_space.yml`, or for a single page with a comment anywhere in the
+//! markdown:
+//!
+//! ```markdown
+//! <!-- marked-space: disable MS003 MS008 -->
+//! ```
+use std::{collections::BTreeSet, fmt::Display};
+
+use anyhow::anyhow;
+use comrak::nodes::{AstNode, NodeValue};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use saphyr::Yaml;
+use serde::{Deserialize, Serialize};
+
+use crate::{console::print_warning, error::Result};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Serialize, Deserialize)]
+pub enum WarningCode {
+    UnknownFrontMatterKey,
+    DuplicateHeading,
+    MissingAltText,
+    UnknownImageVariant,
+    LongTitle,
+    UnknownPageType,
+    MissingRequiredField,
+    LongPage,
+    DeepHeading,
+    SkippedHeadingLevel,
+    MissingRequiredSection,
+    NearDuplicate,
+    OversizedPage,
+    BrokenAnchor,
+    UndefinedReference,
+    UnusedReference,
+    LenientHeadings,
+    InactiveLink,
+    SnippetLink,
+    UnresolvedLink,
+    MissingIndex,
+    UnknownSpaceConfigKey,
+    UnknownEmoji,
+    UnknownUser,
+    MacroOverride,
+    StalePageId,
+    OverwrittenEdit,
+}
+
+impl WarningCode {
+    pub const ALL: [WarningCode; 27] = [
+        WarningCode::UnknownFrontMatterKey,
+        WarningCode::DuplicateHeading,
+        WarningCode::MissingAltText,
+        WarningCode::UnknownImageVariant,
+        WarningCode::LongTitle,
+        WarningCode::UnknownPageType,
+        WarningCode::MissingRequiredField,
+        WarningCode::LongPage,
+        WarningCode::DeepHeading,
+        WarningCode::SkippedHeadingLevel,
+        WarningCode::MissingRequiredSection,
+        WarningCode::NearDuplicate,
+        WarningCode::OversizedPage,
+        WarningCode::BrokenAnchor,
+        WarningCode::UndefinedReference,
+        WarningCode::UnusedReference,
+        WarningCode::LenientHeadings,
+        WarningCode::InactiveLink,
+        WarningCode::SnippetLink,
+        WarningCode::UnresolvedLink,
+        WarningCode::MissingIndex,
+        WarningCode::UnknownSpaceConfigKey,
+        WarningCode::UnknownEmoji,
+        WarningCode::UnknownUser,
+        WarningCode::MacroOverride,
+        WarningCode::StalePageId,
+        WarningCode::OverwrittenEdit,
+    ];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            WarningCode::UnknownFrontMatterKey => "MS001",
+            WarningCode::DuplicateHeading => "MS002",
+            WarningCode::MissingAltText => "MS003",
+            WarningCode::UnknownImageVariant => "MS004",
+            WarningCode::LongTitle => "MS005",
+            WarningCode::UnknownPageType => "MS006",
+            WarningCode::MissingRequiredField => "MS007",
+            WarningCode::LongPage => "MS008",
+            WarningCode::DeepHeading => "MS009",
+            WarningCode::SkippedHeadingLevel => "MS010",
+            WarningCode::MissingRequiredSection => "MS011",
+            WarningCode::NearDuplicate => "MS012",
+            WarningCode::OversizedPage => "MS013",
+            WarningCode::BrokenAnchor => "MS014",
+            WarningCode::UndefinedReference => "MS015",
+            WarningCode::UnusedReference => "MS016",
+            WarningCode::LenientHeadings => "MS017",
+            WarningCode::InactiveLink => "MS018",
+            WarningCode::SnippetLink => "MS019",
+            WarningCode::UnresolvedLink => "MS020",
+            WarningCode::MissingIndex => "MS021",
+            WarningCode::UnknownSpaceConfigKey => "MS022",
+            WarningCode::UnknownEmoji => "MS023",
+            WarningCode::UnknownUser => "MS024",
+            WarningCode::MacroOverride => "MS025",
+            WarningCode::StalePageId => "MS026",
+            WarningCode::OverwrittenEdit => "MS027",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<WarningCode> {
+        Self::ALL
+            .into_iter()
+            .find(|warning_code| warning_code.code().eq_ignore_ascii_case(code.trim()))
+    }
+}
+
+impl Display for WarningCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Warning {
+    pub code: WarningCode,
+    pub message: String,
+}
+
+impl Warning {
+    pub fn new(code: WarningCode, message: impl Into<String>) -> Self {
+        Warning {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} [{}]", self.message, self.code)
+    }
+}
+
+/// What happens to the warnings found after the pages are parsed, while the space is linked,
+/// rendered and synced: disabled codes are left out, and with `--strict` a warning is an error.
+#[derive(Debug, Default, Clone)]
+pub struct WarningPolicy {
+    pub disabled: BTreeSet<WarningCode>,
+    pub strict: bool,
+}
+
+impl WarningPolicy {
+    pub fn report(&self, warning: Warning) -> Result<()> {
+        if self.disabled.contains(&warning.code) {
+            Ok(())
+        } else if self.strict {
+            Err(anyhow!("{} and --strict is set", warning))
+        } else {
+            print_warning(&warning.to_string());
+            Ok(())
+        }
+    }
+}
+
+/// Prints the warning and adds it to `warnings`, unless its code is disabled.
+pub fn report(warning: Warning, disabled: &BTreeSet<WarningCode>, warnings: &mut Vec<Warning>) {
+    if !disabled.contains(&warning.code) {
+        print_warning(&warning.to_string());
+        warnings.push(warning);
+    }
+}
+
+/// The codes in the `warnings` key of `_space.yml`.
+pub fn parse_disabled_warnings(yaml: &Yaml) -> Result<BTreeSet<WarningCode>> {
+    let error = || {
+        anyhow!("Failed to parse \"warnings.disable\" key (should be a list of codes like MS001)")
+    };
+    match yaml {
+        Yaml::BadValue | Yaml::Null => Ok(BTreeSet::default()),
+        Yaml::Hash(_) => match &yaml["disable"] {
+            Yaml::BadValue | Yaml::Null => Ok(BTreeSet::default()),
+            Yaml::Array(codes) => codes
+                .iter()
+                .map(|code| match code.as_str() {
+                    Some(code) => WarningCode::from_code(code)
+                        .ok_or_else(|| anyhow!("Unknown warning code \"{}\"", code)),
+                    None => Err(error()),
+                })
+                .collect(),
+            _ => Err(error()),
+        },
+        _ => Err(anyhow!(
+            "Failed to parse \"warnings\" key (should be a map with disable)"
+        )),
+    }
+}
+
+/// The codes disabled with `<!-- marked-space: disable ... -->` comments in a page.
+///
+/// Unknown codes are ignored, a comment is no place for errors.
+pub fn inline_disabled_warnings<'a>(root: &'a AstNode<'a>) -> BTreeSet<WarningCode> {
+    static DISABLE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"<!--\s*marked-space:\s*disable\s+([^>]*?)\s*-->").unwrap());
+
+    let mut disabled = BTreeSet::default();
+    for node in root.descendants() {
+        let html = match &node.data.borrow().value {
+            NodeValue::HtmlBlock(block) => block.literal.clone(),
+            NodeValue::HtmlInline(html) => html.clone(),
+            _ => continue,
+        };
+        for captures in DISABLE.captures_iter(&html) {
+            disabled.extend(
+                captures[1]
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter_map(WarningCode::from_code),
+            );
+        }
+    }
+    disabled
+}
+
+#[cfg(test)]
+mod tests {
+    use comrak::{parse_document, Arena, Options};
+
+    use crate::error::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn it_shows_the_code() {
+        let warning = Warning::new(WarningCode::MissingAltText, "image foo.png has no alt text");
+
+        assert_eq!(warning.to_string(), "image foo.png has no alt text [MS003]");
+        assert_eq!(
+            WarningCode::from_code("ms012"),
+            Some(WarningCode::NearDuplicate)
+        );
+        assert_eq!(WarningCode::from_code("MS999"), None);
+    }
+
+    #[test]
+    fn it_reports_by_policy() {
+        let warning = Warning::new(
+            WarningCode::UnresolvedLink,
+            "file link a.md couldn't be resolved",
+        );
+        let mut policy = WarningPolicy::default();
+        assert!(policy.report(warning.clone()).is_ok());
+
+        policy.strict = true;
+        assert_eq!(
+            policy.report(warning.clone()).unwrap_err().to_string(),
+            "file link a.md couldn't be resolved [MS020] and --strict is set"
+        );
+
+        policy.disabled.insert(WarningCode::UnresolvedLink);
+        assert!(policy.report(warning).is_ok());
+    }
+
+    #[test]
+    fn it_parses_disabled_warnings() -> TestResult {
+        let yaml = &Yaml::load_from_str("disable: [MS001, MS008]")?[0];
+
+        assert_eq!(
+            parse_disabled_warnings(yaml)?,
+            BTreeSet::from([WarningCode::UnknownFrontMatterKey, WarningCode::LongPage])
+        );
+
+        let yaml = &Yaml::load_from_str("disable: [MS999]")?[0];
+        assert_eq!(
+            parse_disabled_warnings(yaml).unwrap_err().to_string(),
+            "Unknown warning code \"MS999\""
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_finds_inline_comments() {
+        let arena = Arena::new();
+        let root = parse_document(
+            &arena,
+            "# Title\n\n<!-- marked-space: disable MS003, MS010 -->\n\nText <!-- marked-space: disable MS002 --> and `<!-- marked-space: disable MS001 -->`\n",
+            &Options::default(),
+        );
+
+        assert_eq!(
+            inline_disabled_warnings(root),
+            BTreeSet::from([
+                WarningCode::DuplicateHeading,
+                WarningCode::MissingAltText,
+                WarningCode::SkippedHeadingLevel
+            ])
+        );
+    }
+}
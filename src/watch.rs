@@ -0,0 +1,285 @@
+//! Syncing a space again whenever its files change, with `--watch`.
+//!
+//! The files of the space are polled for changes. When only pages changed, just those and the pages
+//! linking to them are rendered and synced again; any other change (a new or deleted page, the
+//! space config, templates, snippets or images) syncs the whole space.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use walkdir::WalkDir;
+
+use crate::{
+    cancellation,
+    confluence_client::ConfluenceClient,
+    console::{print_error, print_info},
+    markdown_page::MarkdownPage,
+    markdown_space::MarkdownSpace,
+    mentions::CachedUsers,
+    sync::sync_space,
+    Args, Result,
+};
+
+/// How often the files of the space are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The modification time and size of every file of the space, by path relative to the space.
+pub(crate) type Snapshot = BTreeMap<PathBuf, (SystemTime, u64)>;
+
+/// Reads the modification time and size of the files of the space, leaving out hidden files and
+/// the files the sync writes itself.
+pub(crate) fn snapshot(dir: &Path, excluded: &[PathBuf]) -> Snapshot {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0
+                || !(entry.file_name().to_string_lossy().starts_with('.')
+                    || excluded.iter().any(|path| entry.path().starts_with(path)))
+        })
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            let path = entry.path().strip_prefix(dir).ok()?.to_path_buf();
+            Some((path, (metadata.modified().ok()?, metadata.len())))
+        })
+        .collect()
+}
+
+/// The files that were added, changed or deleted between two snapshots.
+fn changed_files(before: &Snapshot, after: &Snapshot) -> BTreeSet<PathBuf> {
+    let changed = after
+        .iter()
+        .filter(|(path, stamp)| before.get(*path) != Some(stamp))
+        .map(|(path, _)| path.clone());
+    let deleted = before
+        .keys()
+        .filter(|path| !after.contains_key(*path))
+        .cloned();
+    changed.chain(deleted).collect()
+}
+
+/// The pages to sync again when only the markdown files `changed` changed: the pages of the files
+/// (or the sections they were split into, or the pages they're merged into), and the pages linking
+/// to them, whose link text may be the title of a changed page. `None` when a file isn't (part of)
+/// a page, like a snippet, as any page may include it.
+pub fn affected_pages(
+    markdown_pages: &[MarkdownPage],
+    changed: &[String],
+) -> Option<BTreeSet<String>> {
+    let mut affected = BTreeSet::new();
+    for file in changed {
+        let section_prefix = format!("{}#", file);
+        let pages: Vec<&MarkdownPage> = markdown_pages
+            .iter()
+            .filter(|page| {
+                page.source == *file
+                    || page.source.starts_with(&section_prefix)
+                    || page.merged.iter().any(|merged| merged.source == *file)
+            })
+            .collect();
+        if pages.is_empty() {
+            return None;
+        }
+        affected.extend(pages.iter().map(|page| page.source.clone()));
+    }
+    for page in markdown_pages {
+        let links_to_changed = page.local_links.iter().any(|local_link| {
+            let path = local_link.path.to_string_lossy().replace('\\', "/");
+            changed.contains(&path)
+                || changed.contains(&format!("{}/index.md", path.trim_end_matches('/')))
+        });
+        if links_to_changed {
+            affected.insert(page.source.clone());
+        }
+    }
+    Some(affected)
+}
+
+/// Whether the changes can be synced incrementally: all of them are changes to existing pages.
+fn only_pages_changed(changed: &BTreeSet<PathBuf>, before: &Snapshot, after: &Snapshot) -> bool {
+    changed.iter().all(|path| {
+        path.extension() == Some(OsStr::new("md"))
+            && before.contains_key(path)
+            && after.contains_key(path)
+    })
+}
+
+/// Waits until files of the space changed and stopped changing, and returns the new snapshot.
+/// `None` when the watch was stopped with Ctrl-C.
+fn wait_for_changes(dir: &Path, excluded: &[PathBuf], before: &Snapshot) -> Option<Snapshot> {
+    let mut after = before.clone();
+    while after == *before {
+        thread::sleep(POLL_INTERVAL);
+        if cancellation::is_cancelled() {
+            return None;
+        }
+        after = snapshot(dir, excluded);
+    }
+    // editors write files in several steps, so wait for them to finish
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let settled = snapshot(dir, excluded);
+        if settled == after {
+            return Some(after);
+        }
+        after = settled;
+    }
+}
+
+fn sync_once(
+    confluence_client: &ConfluenceClient,
+    dir: &Path,
+    args: &Args,
+    users: &Arc<CachedUsers>,
+) -> Result<()> {
+    let mut markdown_space = MarkdownSpace::from_directory(dir)?;
+    sync_space(
+        confluence_client.clone(),
+        &mut markdown_space,
+        args.clone(),
+        users.clone(),
+    )?
+    .write(args)
+}
+
+/// Syncs the space, and then syncs it again every time its files change, until Ctrl-C.
+pub fn watch(confluence_client: ConfluenceClient, dir: &Path, args: Args) -> Result<()> {
+    let users = Arc::new(CachedUsers::new(confluence_client.clone()));
+    let excluded: Vec<PathBuf> = [
+        args.output.as_ref().map(PathBuf::from),
+        args.report.clone(),
+        args.links.clone(),
+        args.search_index.clone(),
+        args.audit_log.clone(),
+        args.dry_run_html.clone(),
+        args.http_cache.clone(),
+        args.parse_cache.clone(),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    let mut before = snapshot(dir, &excluded);
+    if let Err(err) = sync_once(&confluence_client, dir, &args, &users) {
+        print_error(&format!("{:#}", err));
+    }
+    loop {
+        print_info(&format!(
+            "Watching {} for changes, press Ctrl-C to stop",
+            dir.display()
+        ));
+        let Some(after) = wait_for_changes(dir, &excluded, &before) else {
+            return Ok(());
+        };
+        let changed = changed_files(&before, &after);
+        let mut round_args = args.clone();
+        if only_pages_changed(&changed, &before, &after) {
+            round_args.changed = Some(
+                changed
+                    .iter()
+                    .map(|path| path.to_string_lossy().replace('\\', "/"))
+                    .collect(),
+            );
+        }
+        for path in &changed {
+            print_info(&format!("Changed: {}", path.display()));
+        }
+        before = after;
+        if let Err(err) = sync_once(&confluence_client, dir, &round_args, &users) {
+            print_error(&format!("{:#}", err));
+        }
+        if cancellation::is_cancelled() {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use assert_fs::{
+        prelude::{FileWriteStr, PathChild},
+        TempDir,
+    };
+
+    use super::*;
+
+    type TestResult = std::result::Result<(), Box<dyn std::error::Error>>;
+
+    #[test]
+    fn it_lists_the_changed_files() -> TestResult {
+        let temp = TempDir::new()?;
+        temp.child("index.md").write_str("# Home")?;
+        temp.child("a.md").write_str("# A")?;
+        temp.child(".git/HEAD").write_str("ref")?;
+        temp.child("out/a.html").write_str("<h1>A</h1>")?;
+        let excluded = vec![temp.path().join("out")];
+        let before = snapshot(temp.path(), &excluded);
+        assert_eq!(
+            before.keys().collect::<Vec<_>>(),
+            vec![Path::new("a.md"), Path::new("index.md")]
+        );
+
+        fs::remove_file(temp.path().join("a.md"))?;
+        temp.child("b.md").write_str("# B")?;
+        temp.child("index.md").write_str("# Home again")?;
+        let after = snapshot(temp.path(), &excluded);
+        let changed = changed_files(&before, &after);
+
+        assert_eq!(
+            changed,
+            BTreeSet::from([
+                PathBuf::from("a.md"),
+                PathBuf::from("b.md"),
+                PathBuf::from("index.md")
+            ])
+        );
+        assert!(!only_pages_changed(&changed, &before, &after));
+        assert!(only_pages_changed(
+            &BTreeSet::from([PathBuf::from("index.md")]),
+            &before,
+            &after
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn it_syncs_the_changed_pages_and_the_pages_linking_to_them() -> TestResult {
+        let markdown_space = MarkdownSpace::default("TEST", Path::new("test"));
+        let pages = [
+            ("index.md", "# Home\n\n[Guide](guides/index.md)"),
+            ("guides/index.md", "# Guides\n\n[Setup](setup.md)"),
+            ("guides/setup.md", "# Setup"),
+            ("faq.md", "# FAQ\n\n[Setup](guides/setup.md)"),
+            ("about.md", "# About"),
+        ]
+        .iter()
+        .map(|(source, markdown)| markdown_space.page_from_str(source, markdown))
+        .collect::<Result<Vec<_>>>()?;
+
+        assert_eq!(
+            affected_pages(&pages, &[String::from("guides/setup.md")]),
+            Some(BTreeSet::from([
+                String::from("faq.md"),
+                String::from("guides/index.md"),
+                String::from("guides/setup.md"),
+            ]))
+        );
+        assert_eq!(
+            affected_pages(&pages, &[String::from("guides/index.md")]),
+            Some(BTreeSet::from([
+                String::from("guides/index.md"),
+                String::from("index.md"),
+            ]))
+        );
+        assert_eq!(affected_pages(&pages, &[String::from("_snippet.md")]), None);
+        Ok(())
+    }
+}
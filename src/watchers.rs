@@ -0,0 +1,159 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::{
+    confluence_client::ConfluenceClient,
+    console::{print_status, Status},
+    error::{ApiResponse, Result},
+    frontmatter::FrontMatter,
+    mentions::CachedUsers,
+    responses,
+};
+
+/// Atlassian account IDs are either 24 hex digits or `<number>:<uuid>`.
+static ACCOUNT_ID: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^([0-9a-f]{24}|\d+:[0-9a-f]{8}(-[0-9a-f]{4}){3}-[0-9a-f]{12})$").unwrap()
+});
+
+fn is_account_id(person: &str) -> bool {
+    ACCOUNT_ID.is_match(person)
+}
+
+/// Resolves people listed in front matter to account IDs, skipping (and warning about) people
+/// that are not known to Confluence.
+pub fn resolve_account_ids(users: &CachedUsers, people: &[String]) -> Result<Vec<String>> {
+    let mut account_ids = Vec::<String>::new();
+    for person in people {
+        let account_id = if is_account_id(person) {
+            Some(person.clone())
+        } else {
+            users.account_id(person)?
+        };
+        if let Some(account_id) = account_id {
+            if !account_ids.contains(&account_id) {
+                account_ids.push(account_id);
+            }
+        }
+    }
+    Ok(account_ids)
+}
+
+/// Makes sure everyone listed as a watcher or an owner watches the page. People who chose to watch
+/// the page themselves are left alone.
+pub fn sync_page_watchers(
+    confluence_client: &ConfluenceClient,
+    users: &CachedUsers,
+    page_id: &str,
+    front_matter: &FrontMatter,
+) -> Result<()> {
+    let people: Vec<String> = front_matter
+        .watchers
+        .iter()
+        .chain(front_matter.owners.iter())
+        .cloned()
+        .collect();
+
+    let mut added = Vec::<String>::new();
+    for account_id in resolve_account_ids(users, &people)? {
+        let status = confluence_client
+            .get_content_watch_status(page_id, &account_id)?
+            .api_result()?
+            .json::<responses::WatchStatus>()?;
+        if !status.watching {
+            confluence_client
+                .add_content_watcher(page_id, &account_id)?
+                .api_result()?;
+            added.push(account_id);
+        }
+    }
+
+    if !added.is_empty() {
+        print_status(Status::Created, &format!("watchers: {}", added.join(",")));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Matcher;
+
+    use crate::{
+        confluence_client::ConfluenceClient, error::TestResult, frontmatter::FrontMatter,
+        mentions::CachedUsers,
+    };
+
+    use super::*;
+
+    static ACCOUNT_ID: &str = "557058:048d7c01-8b68-440d-964f-07ce58d92aeb";
+
+    fn mock_watch_status(
+        server: &mut mockito::ServerGuard,
+        account_id: &str,
+        watching: bool,
+    ) -> mockito::Mock {
+        server
+            .mock("GET", "/wiki/rest/api/user/watch/content/42")
+            .match_query(Matcher::UrlEncoded("accountId".into(), account_id.into()))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(format!("{{\"watching\":{}}}", watching))
+            .create()
+    }
+
+    #[test]
+    fn it_recognizes_account_ids() {
+        assert!(is_account_id(ACCOUNT_ID));
+        assert!(is_account_id("5b10ac8d82e05b22cc7d4ef5"));
+        assert!(!is_account_id("John Doe"));
+        assert!(!is_account_id("john.doe@example.com"));
+    }
+
+    #[test]
+    fn it_adds_missing_watchers() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let users = CachedUsers::new(client.clone());
+
+        let status_mock = mock_watch_status(&mut server, ACCOUNT_ID, false);
+        let add_mock = server
+            .mock("POST", "/wiki/rest/api/user/watch/content/42")
+            .match_query(Matcher::UrlEncoded("accountId".into(), ACCOUNT_ID.into()))
+            .with_status(204)
+            .expect(1)
+            .create();
+
+        let (front_matter, _) =
+            FrontMatter::from_str(&format!("---\nwatchers:\n- {}\n---\n# title", ACCOUNT_ID))?;
+        sync_page_watchers(&client, &users, "42", &front_matter)?;
+
+        status_mock.assert();
+        add_mock.assert();
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_does_not_add_existing_watchers_twice() -> TestResult {
+        let mut server = mockito::Server::new();
+        let client = ConfluenceClient::new_insecure(&server.host_with_port());
+        let users = CachedUsers::new(client.clone());
+
+        let status_mock = mock_watch_status(&mut server, ACCOUNT_ID, true);
+        let add_mock = server
+            .mock("POST", "/wiki/rest/api/user/watch/content/42")
+            .expect(0)
+            .create();
+
+        let (front_matter, _) = FrontMatter::from_str(&format!(
+            "---\nwatchers:\n- {0}\nowners:\n- {0}\n---\n# title",
+            ACCOUNT_ID
+        ))?;
+        sync_page_watchers(&client, &users, "42", &front_matter)?;
+
+        status_mock.assert();
+        add_mock.assert();
+
+        Ok(())
+    }
+}